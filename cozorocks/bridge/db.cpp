@@ -86,6 +86,15 @@ shared_ptr<RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 
     db->db_path = string(opts.db_path);
 
+    if (opts.read_only) {
+        DB *ro_db = nullptr;
+        write_status(DB::OpenForReadOnly(options, db->db_path, &ro_db), status);
+        db->ro_db.reset(ro_db);
+        // a database shared with other processes is never destroyed by one of them
+        db->destroy_on_exit = false;
+        return db;
+    }
+
     TransactionDB *txn_db = nullptr;
     write_status(
             TransactionDB::Open(options, TransactionDBOptions(), db->db_path,&txn_db),
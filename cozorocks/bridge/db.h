@@ -42,11 +42,17 @@ struct SstFileWriterBridge {
 
 struct RocksDbBridge {
     unique_ptr<TransactionDB> db;
+    // set instead of db when opened read-only
+    unique_ptr<DB> ro_db;
 
     bool destroy_on_exit;
     string db_path;
 
     inline unique_ptr<SstFileWriterBridge> get_sst_writer(rust::Str path, RocksDbStatus &status) const {
+        if (ro_db != nullptr) {
+            write_status(read_only_status(), status);
+            return nullptr;
+        }
         DB *db_ = get_base_db();
         auto cf = db->DefaultColumnFamily();
         Options options_ = db_->GetOptions(cf);
@@ -58,6 +64,10 @@ struct RocksDbBridge {
     }
 
     inline void ingest_sst(rust::Str path, RocksDbStatus &status) const {
+        if (ro_db != nullptr) {
+            write_status(read_only_status(), status);
+            return;
+        }
         IngestExternalFileOptions ifo;
         DB *db_ = get_base_db();
         string path_(path);
@@ -71,11 +81,18 @@ struct RocksDbBridge {
 
 
     [[nodiscard]] inline unique_ptr<TxBridge> transact() const {
+        if (ro_db != nullptr) {
+            return make_unique<TxBridge>(&*this->ro_db, ro_db->DefaultColumnFamily());
+        }
         auto ret = make_unique<TxBridge>(&*this->db, db->DefaultColumnFamily());
         return ret;
     }
 
     inline void del_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
+        if (ro_db != nullptr) {
+            write_status(read_only_status(), status);
+            return;
+        }
         WriteBatch batch;
         auto cf = db->DefaultColumnFamily();
         auto s = batch.DeleteRange(cf, convert_slice(start), convert_slice(end));
@@ -92,6 +109,10 @@ struct RocksDbBridge {
     }
 
     void compact_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
+        if (ro_db != nullptr) {
+            write_status(read_only_status(), status);
+            return;
+        }
         CompactRangeOptions options;
         auto cf = db->DefaultColumnFamily();
         auto start_s = convert_slice(start);
@@ -100,10 +121,30 @@ struct RocksDbBridge {
         write_status(s, status);
     }
 
+    void flush(RocksDbStatus &status) const {
+        // nothing is ever written to a read-only database
+        if (ro_db != nullptr) {
+            return;
+        }
+        FlushOptions options;
+        options.wait = true;
+        auto s = db->Flush(options, db->DefaultColumnFamily());
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        write_status(db->FlushWAL(true), status);
+    }
+
     DB *get_base_db() const {
         return db->GetBaseDB();
     }
 
+    bool get_int_property(rust::Str name, uint64_t &value) const {
+        DB *db_ = ro_db != nullptr ? ro_db.get() : get_base_db();
+        return db_->GetIntProperty(Slice(name.data(), name.size()), &value);
+    }
+
     ~RocksDbBridge();
 };
 
@@ -9,6 +9,47 @@
 #include "slice.h"
 #include "status.h"
 
+// Finds the first key in lower..upper whose latest write in the batch of the transaction is a
+// put, copying the key and the value put into the arguments
+inline bool batch_next_put(Transaction *tx, RustBytes lower, RustBytes upper,
+                           rust::Vec<uint8_t> &key, rust::Vec<uint8_t> &val, RocksDbStatus &status) {
+    unique_ptr<WBWIIterator> it(tx->GetWriteBatch()->NewIterator());
+    Slice upper_ = convert_slice(upper);
+    it->Seek(convert_slice(lower));
+    while (it->Valid() && it->Entry().key.compare(upper_) < 0) {
+        string current = it->Entry().key.ToString();
+        bool is_put = false;
+        string value;
+        // writes made after a savepoint do not overwrite the entries of the key made before it,
+        // the last entry being the latest
+        while (it->Valid() && it->Entry().key == Slice(current)) {
+            WriteEntry entry = it->Entry();
+            is_put = entry.type == kPutRecord;
+            if (is_put) {
+                value = entry.value.ToString();
+            }
+            it->Next();
+        }
+        if (is_put) {
+            key.clear();
+            key.reserve(current.size());
+            for (char c: current) {
+                key.push_back(static_cast<uint8_t>(c));
+            }
+            val.clear();
+            val.reserve(value.size());
+            for (char c: value) {
+                val.push_back(static_cast<uint8_t>(c));
+            }
+            return true;
+        }
+    }
+    if (!it->status().ok()) {
+        write_status(it->status(), status);
+    }
+    return false;
+}
+
 struct IterBridge {
     DB *db;
     Transaction *tx;
@@ -22,7 +63,12 @@ struct IterBridge {
     explicit IterBridge(Transaction *tx_) : db(nullptr), tx(tx_), iter(nullptr), lower_bound(),
                                                                      upper_bound(),
                                                                      r_opts(new ReadOptions) {
-        r_opts->ignore_range_deletions = true;
+        r_opts->auto_prefix_mode = true;
+    }
+
+    explicit IterBridge(DB *db_) : db(db_), tx(nullptr), iter(nullptr), lower_bound(),
+                                   upper_bound(),
+                                   r_opts(new ReadOptions) {
         r_opts->auto_prefix_mode = true;
     }
 
@@ -133,6 +179,14 @@ struct IterBridge {
     [[nodiscard]] inline RustBytes val() const {
         return convert_slice_back(iter->value());
     }
+
+    inline bool batch_next(RustBytes lower, RustBytes upper, rust::Vec<uint8_t> &key,
+                           rust::Vec<uint8_t> &val, RocksDbStatus &status) const {
+        if (tx == nullptr) {
+            return false;
+        }
+        return batch_next_put(tx, lower, upper, key, val, status);
+    }
 };
 
 #endif //COZOROCKS_ITER_H
@@ -6,6 +6,9 @@
 #include "cozorocks/src/bridge/mod.rs.h"
 
 void TxBridge::start() {
+    if (rdb != nullptr) {
+        return;
+    }
     if (odb != nullptr) {
         Transaction *txn = odb->BeginTransaction(*w_opts, *o_tx_opts);
         tx.reset(txn);
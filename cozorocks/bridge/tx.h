@@ -10,26 +10,70 @@
 #include "status.h"
 #include "iter.h"
 
+inline Status read_only_status() {
+    return Status::NotSupported("the database is opened read-only");
+}
+
+// Replays the writes of a transaction into another batch
+struct BatchReplayer : public WriteBatch::Handler {
+    WriteBatch *target;
+
+    explicit BatchReplayer(WriteBatch *target_) : target(target_) {}
+
+    Status PutCF(uint32_t, const Slice &key, const Slice &value) override {
+        return target->Put(key, value);
+    }
+
+    Status DeleteCF(uint32_t, const Slice &key) override {
+        return target->Delete(key);
+    }
+
+    Status SingleDeleteCF(uint32_t, const Slice &key) override {
+        return target->SingleDelete(key);
+    }
+
+    Status DeleteRangeCF(uint32_t, const Slice &begin, const Slice &end) override {
+        return target->DeleteRange(begin, end);
+    }
+};
+
 struct TxBridge {
     OptimisticTransactionDB *odb;
     TransactionDB *tdb;
+    // set instead of the transaction databases when opened read-only, reads then going to it
+    // directly as nothing can change
+    DB *rdb;
     unique_ptr<Transaction> tx;
     unique_ptr<WriteOptions> w_opts;
     unique_ptr<ReadOptions> r_opts;
     unique_ptr<OptimisticTransactionOptions> o_tx_opts;
     unique_ptr<TransactionOptions> p_tx_opts;
     ColumnFamilyHandle * cf_handle;
+    // ranges deleted when the transaction commits, as transactions cannot hold range deletions
+    vector<pair<string, string>> commit_del_ranges;
 
     explicit TxBridge(TransactionDB *tdb_, ColumnFamilyHandle * cf_handle_) :
             odb(nullptr),
             tdb(tdb_),
+            rdb(nullptr),
             tx(),
             w_opts(new WriteOptions),
             r_opts(new ReadOptions),
             o_tx_opts(nullptr),
             p_tx_opts(new TransactionOptions),
             cf_handle(cf_handle_) {
-        r_opts->ignore_range_deletions = true;
+    }
+
+    explicit TxBridge(DB *rdb_, ColumnFamilyHandle * cf_handle_) :
+            odb(nullptr),
+            tdb(nullptr),
+            rdb(rdb_),
+            tx(),
+            w_opts(new WriteOptions),
+            r_opts(new ReadOptions),
+            o_tx_opts(nullptr),
+            p_tx_opts(nullptr),
+            cf_handle(cf_handle_) {
     }
 
     inline WriteOptions &get_w_opts() {
@@ -49,6 +93,9 @@ struct TxBridge {
     }
 
     inline unique_ptr<IterBridge> iterator() const {
+        if (rdb != nullptr) {
+            return make_unique<IterBridge>(rdb);
+        }
         return make_unique<IterBridge>(&*tx);
     };
 
@@ -65,11 +112,15 @@ struct TxBridge {
     }
 
     inline void clear_snapshot() {
-        tx->ClearSnapshot();
+        if (tx != nullptr) {
+            tx->ClearSnapshot();
+        }
     }
 
     [[nodiscard]] inline DB *get_db() const {
-        if (tdb != nullptr) {
+        if (rdb != nullptr) {
+            return rdb;
+        } else if (tdb != nullptr) {
             return tdb;
         } else {
             return odb;
@@ -81,7 +132,9 @@ struct TxBridge {
     inline unique_ptr<PinnableSlice> get(RustBytes key, bool for_update, RocksDbStatus &status) const {
         Slice key_ = convert_slice(key);
         auto ret = make_unique<PinnableSlice>();
-        if (for_update) {
+        if (rdb != nullptr) {
+            write_status(rdb->Get(*r_opts, cf_handle, key_, &*ret), status);
+        } else if (for_update) {
             auto s = tx->GetForUpdate(*r_opts, cf_handle, key_, &*ret);
             write_status(s, status);
         } else {
@@ -94,7 +147,9 @@ struct TxBridge {
     inline void exists(RustBytes key, bool for_update, RocksDbStatus &status) const {
         Slice key_ = convert_slice(key);
         auto ret = PinnableSlice();
-        if (for_update) {
+        if (rdb != nullptr) {
+            write_status(rdb->Get(*r_opts, cf_handle, key_, &ret), status);
+        } else if (for_update) {
             auto s = tx->GetForUpdate(*r_opts, cf_handle, key_, &ret);
             write_status(s, status);
         } else {
@@ -104,30 +159,133 @@ struct TxBridge {
     }
 
     inline void put(RustBytes key, RustBytes val, RocksDbStatus &status) {
+        if (rdb != nullptr) {
+            write_status(read_only_status(), status);
+            return;
+        }
         write_status(tx->Put(convert_slice(key), convert_slice(val)), status);
     }
 
     inline void del(RustBytes key, RocksDbStatus &status) {
+        if (rdb != nullptr) {
+            write_status(read_only_status(), status);
+            return;
+        }
         write_status(tx->Delete(convert_slice(key)), status);
     }
 
+    // Deletes the keys put within the transaction in lower..upper, which would otherwise be
+    // written back after the range is deleted on commit
+    inline void del_written_range(RustBytes lower, RustBytes upper, RocksDbStatus &status) {
+        if (rdb != nullptr) {
+            write_status(read_only_status(), status);
+            return;
+        }
+        vector<string> written;
+        {
+            unique_ptr<WBWIIterator> it(tx->GetWriteBatch()->NewIterator());
+            Slice upper_ = convert_slice(upper);
+            for (it->Seek(convert_slice(lower));
+                 it->Valid() && it->Entry().key.compare(upper_) < 0;
+                 it->Next()) {
+                if (it->Entry().type == kPutRecord || it->Entry().type == kMergeRecord) {
+                    written.push_back(it->Entry().key.ToString());
+                }
+            }
+            if (!it->status().ok()) {
+                write_status(it->status(), status);
+                return;
+            }
+        }
+        for (auto &key: written) {
+            auto s = tx->Delete(key);
+            if (!s.ok()) {
+                write_status(s, status);
+                return;
+            }
+        }
+    }
+
+    inline bool batch_next(RustBytes lower, RustBytes upper, rust::Vec<uint8_t> &key,
+                           rust::Vec<uint8_t> &val, RocksDbStatus &status) const {
+        if (rdb != nullptr) {
+            return false;
+        }
+        return batch_next_put(&*tx, lower, upper, key, val, status);
+    }
+
+    inline void del_range_on_commit(RustBytes lower, RustBytes upper) {
+        commit_del_ranges.emplace_back(convert_slice_to_string(lower),
+                                       convert_slice_to_string(upper));
+    }
+
+    // as nothing can be written to a read-only database, there is nothing to commit or
+    // roll back either
+
     inline void commit(RocksDbStatus &status) {
-        write_status(tx->Commit(), status);
+        if (rdb != nullptr) {
+            return;
+        }
+        if (commit_del_ranges.empty()) {
+            write_status(tx->Commit(), status);
+            return;
+        }
+        // the ranges are deleted ahead of the writes of the transaction, all in one batch
+        // written atomically, and the transaction is then rolled back to release its locks.
+        // The batch skips conflict detection, which cannot handle range deletions: callers
+        // lock the keys of the ranges beforehand
+        WriteBatch batch;
+        for (auto &range: commit_del_ranges) {
+            auto s = batch.DeleteRange(cf_handle, range.first, range.second);
+            if (!s.ok()) {
+                commit_del_ranges.clear();
+                write_status(s, status);
+                return;
+            }
+        }
+        commit_del_ranges.clear();
+        BatchReplayer replayer(&batch);
+        auto s = tx->GetWriteBatch()->GetWriteBatch()->Iterate(&replayer);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        TransactionDBWriteOptimizations optimizations;
+        optimizations.skip_concurrency_control = true;
+        s = tdb->Write(*w_opts, optimizations, &batch);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        write_status(tx->Rollback(), status);
     }
 
     inline void rollback(RocksDbStatus &status) {
+        if (rdb != nullptr) {
+            return;
+        }
+        commit_del_ranges.clear();
         write_status(tx->Rollback(), status);
     }
 
     inline void rollback_to_savepoint(RocksDbStatus &status) {
+        if (rdb != nullptr) {
+            return;
+        }
         write_status(tx->RollbackToSavePoint(), status);
     }
 
     inline void pop_savepoint(RocksDbStatus &status) {
+        if (rdb != nullptr) {
+            return;
+        }
         write_status(tx->PopSavePoint(), status);
     }
 
     inline void set_savepoint() {
+        if (rdb != nullptr) {
+            return;
+        }
         tx->SetSavePoint();
     }
 };
@@ -33,6 +33,7 @@ impl<'a> Default for DbOpts<'a> {
             use_fixed_prefix_extractor: false,
             fixed_prefix_extractor_len: 0,
             destroy_on_exit: false,
+            read_only: false,
         }
     }
 }
@@ -96,6 +97,12 @@ impl<'a> DbBuilder<'a> {
         self.opts.fixed_prefix_extractor_len = len;
         self
     }
+    /// Open the database without taking its lock, so that several processes can read it.
+    /// Transactions then only read, seeing the data as of the opening, and writing fails.
+    pub fn read_only(mut self, val: bool) -> Self {
+        self.opts.read_only = val;
+        self
+    }
     pub fn build(self) -> Result<RocksDb, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
 
@@ -145,6 +152,23 @@ impl RocksDb {
             Err(status)
         }
     }
+    /// The value of an integer property of the engine, such as `rocksdb.compaction-pending`,
+    /// if it is known.
+    pub fn int_property(&self, name: &str) -> Option<u64> {
+        let mut value = 0;
+        self.inner
+            .get_int_property(name, &mut value)
+            .then_some(value)
+    }
+    pub fn flush(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.flush(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
     pub fn get_sst_writer(&self, path: &str) -> Result<SstWriter, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
         let ret = self.inner.get_sst_writer(path, &mut status);
@@ -138,6 +138,27 @@ impl DbIter {
             }
         }
     }
+    /// As [`Tx::batch_next`](crate::Tx::batch_next), for the transaction iterated over.
+    #[inline]
+    pub fn batch_next(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let mut key = vec![];
+        let mut val = vec![];
+        let found = self
+            .inner
+            .batch_next(lower, upper, &mut key, &mut val, &mut status);
+        if !status.is_ok() {
+            Err(status)
+        } else if found {
+            Ok(Some((key, val)))
+        } else {
+            Ok(None)
+        }
+    }
     #[inline]
     pub fn pair(&self) -> Result<Option<(&[u8], &[u8])>, RocksDbStatus> {
         if self.is_valid() {
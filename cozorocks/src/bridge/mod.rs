@@ -35,6 +35,7 @@ pub(crate) mod ffi {
         pub use_fixed_prefix_extractor: bool,
         pub fixed_prefix_extractor_len: usize,
         pub destroy_on_exit: bool,
+        pub read_only: bool,
     }
 
     #[derive(Clone, Debug, Eq, PartialEq)]
@@ -133,6 +134,8 @@ pub(crate) mod ffi {
             upper: &[u8],
             status: &mut RocksDbStatus,
         );
+        fn flush(self: &RocksDbBridge, status: &mut RocksDbStatus);
+        fn get_int_property(self: &RocksDbBridge, name: &str, value: &mut u64) -> bool;
         fn get_sst_writer(
             self: &RocksDbBridge,
             path: &str,
@@ -176,6 +179,21 @@ pub(crate) mod ffi {
             status: &mut RocksDbStatus,
         );
         fn del(self: Pin<&mut TxBridge>, key: &[u8], status: &mut RocksDbStatus);
+        fn del_written_range(
+            self: Pin<&mut TxBridge>,
+            lower: &[u8],
+            upper: &[u8],
+            status: &mut RocksDbStatus,
+        );
+        fn batch_next(
+            self: &TxBridge,
+            lower: &[u8],
+            upper: &[u8],
+            key: &mut Vec<u8>,
+            val: &mut Vec<u8>,
+            status: &mut RocksDbStatus,
+        ) -> bool;
+        fn del_range_on_commit(self: Pin<&mut TxBridge>, lower: &[u8], upper: &[u8]);
         fn commit(self: Pin<&mut TxBridge>, status: &mut RocksDbStatus);
         fn rollback(self: Pin<&mut TxBridge>, status: &mut RocksDbStatus);
         fn rollback_to_savepoint(self: Pin<&mut TxBridge>, status: &mut RocksDbStatus);
@@ -208,6 +226,14 @@ pub(crate) mod ffi {
         fn status(self: &IterBridge, status: &mut RocksDbStatus);
         fn key(self: &IterBridge) -> &[u8];
         fn val(self: &IterBridge) -> &[u8];
+        fn batch_next(
+            self: &IterBridge,
+            lower: &[u8],
+            upper: &[u8],
+            key: &mut Vec<u8>,
+            val: &mut Vec<u8>,
+            status: &mut RocksDbStatus,
+        ) -> bool;
     }
 }
 
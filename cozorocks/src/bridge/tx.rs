@@ -36,7 +36,11 @@ impl TxBuilder {
     #[inline]
     pub fn start(mut self) -> Tx {
         self.inner.pin_mut().start();
-        Tx { inner: self.inner }
+        Tx {
+            inner: self.inner,
+            del_ranges: vec![],
+            saves: vec![],
+        }
     }
     #[inline]
     pub fn set_snapshot(mut self, val: bool) -> Self {
@@ -76,6 +80,10 @@ impl TxBuilder {
 
 pub struct Tx {
     pub(crate) inner: UniquePtr<TxBridge>,
+    /// Ranges to delete on commit
+    del_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+    /// The number of ranges to delete at each savepoint
+    saves: Vec<usize>,
 }
 
 impl Tx {
@@ -107,6 +115,52 @@ impl Tx {
             Err(status)
         }
     }
+    /// Deletes all keys in the range `lower..upper` when the transaction commits, without
+    /// visiting them. Keys put within the transaction in the range are deleted now, keys put
+    /// in it afterwards are kept. Until the commit, reads of the range do not see the deletion:
+    /// the range should instead be read from the writes of the transaction with
+    /// [`Tx::batch_next`]. Keys put in the range by other transactions committing before this
+    /// one are deleted too, unless the keys are locked beforehand.
+    #[inline]
+    pub fn del_range_on_commit(&mut self, lower: &[u8], upper: &[u8]) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner
+            .pin_mut()
+            .del_written_range(lower, upper, &mut status);
+        if status.is_ok() {
+            self.del_ranges.push((lower.to_vec(), upper.to_vec()));
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// The ranges to be deleted on commit.
+    #[inline]
+    pub fn del_ranges(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.del_ranges
+    }
+    /// The first key in the range `lower..upper` put within the transaction and not deleted
+    /// since, with its value.
+    #[inline]
+    pub fn batch_next(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<Option<(Vec<u8>, Vec<u8>)>, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let mut key = vec![];
+        let mut val = vec![];
+        let found = self
+            .inner
+            .batch_next(lower, upper, &mut key, &mut val, &mut status);
+        if !status.is_ok() {
+            Err(status)
+        } else if found {
+            Ok(Some((key, val)))
+        } else {
+            Ok(None)
+        }
+    }
     #[inline]
     pub fn get(&self, key: &[u8], for_update: bool) -> Result<Option<PinSlice>, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
@@ -130,6 +184,9 @@ impl Tx {
     #[inline]
     pub fn commit(&mut self) -> Result<(), RocksDbStatus> {
         let mut status = RocksDbStatus::default();
+        for (lower, upper) in self.del_ranges.drain(..) {
+            self.inner.pin_mut().del_range_on_commit(&lower, &upper);
+        }
         self.inner.pin_mut().commit(&mut status);
         if status.is_ok() {
             Ok(())
@@ -139,6 +196,8 @@ impl Tx {
     }
     #[inline]
     pub fn rollback(&mut self) -> Result<(), RocksDbStatus> {
+        self.del_ranges.clear();
+        self.saves.clear();
         let mut status = RocksDbStatus::default();
         self.inner.pin_mut().rollback(&mut status);
         if status.is_ok() {
@@ -152,6 +211,9 @@ impl Tx {
         let mut status = RocksDbStatus::default();
         self.inner.pin_mut().rollback_to_savepoint(&mut status);
         if status.is_ok() {
+            if let Some(n) = self.saves.pop() {
+                self.del_ranges.truncate(n);
+            }
             Ok(())
         } else {
             Err(status)
@@ -159,6 +221,7 @@ impl Tx {
     }
     #[inline]
     pub fn save(&mut self) {
+        self.saves.push(self.del_ranges.len());
         self.inner.pin_mut().set_savepoint();
     }
     #[inline]
@@ -166,6 +229,7 @@ impl Tx {
         let mut status = RocksDbStatus::default();
         self.inner.pin_mut().pop_savepoint(&mut status);
         if status.is_ok() {
+            self.saves.pop();
             Ok(())
         } else {
             Err(status)
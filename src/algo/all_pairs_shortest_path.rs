@@ -4,16 +4,18 @@
 
 use std::cmp::Reverse;
 use std::collections::BTreeMap;
+use std::mem::size_of;
 
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, ensure, Diagnostic, Result};
 use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
 use rayon::prelude::*;
 use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
 
 use crate::algo::shortest_path_dijkstra::dijkstra_keep_ties;
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -92,6 +94,12 @@ impl AlgoImpl for BetweennessCentrality {
     ) -> Result<usize> {
         Ok(2)
     }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        let searches = rows.saturating_mul(4 * size_of::<f64>());
+        Some(graph_memory_estimate(rows, undirected).saturating_add(searches))
+    }
 }
 
 pub(crate) struct ClosenessCentrality;
@@ -142,6 +150,139 @@ impl AlgoImpl for ClosenessCentrality {
     ) -> Result<usize> {
         Ok(2)
     }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        let searches = rows.saturating_mul(4 * size_of::<f64>());
+        Some(graph_memory_estimate(rows, undirected).saturating_add(searches))
+    }
+}
+
+pub(crate) struct FloydWarshall;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Graph with {0} nodes is too large for 'FloydWarshall'")]
+#[diagnostic(code(algo::graph_too_large))]
+#[diagnostic(help(
+    "The running time is cubic in the number of nodes; raise the option 'max_nodes' \
+    if this is intended, or use 'ShortestPathDijkstra' instead"
+))]
+struct GraphTooLargeError(usize, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Negative cycle through the node {0:?}")]
+#[diagnostic(code(algo::negative_cycle))]
+struct NegativeCycleError(DataValue, #[label] SourceSpan);
+
+impl AlgoImpl for FloydWarshall {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+        let undirected = algo.bool_option("undirected", Some(false))?;
+        let max_nodes = algo.pos_integer_option("max_nodes", Some(1000))?;
+
+        let (graph, indices, _inv_indices, _) =
+            edges.convert_edge_to_weighted_graph(undirected, true, tx, stores)?;
+
+        let n = graph.len();
+        ensure!(n <= max_nodes, GraphTooLargeError(n, algo.span));
+
+        let (distance, next) = floyd_warshall(&graph, poison)?;
+        for i in 0..n {
+            if distance[i][i] < 0. {
+                bail!(NegativeCycleError(indices[i].clone(), algo.span));
+            }
+        }
+
+        for from in 0..n {
+            for to in 0..n {
+                let cost = distance[from][to];
+                let mut path = vec![];
+                if cost.is_finite() {
+                    let mut current = from;
+                    path.push(indices[current].clone());
+                    while current != to {
+                        current = next[current][to];
+                        path.push(indices[current].clone());
+                    }
+                }
+                out.put(
+                    Tuple(vec![
+                        indices[from].clone(),
+                        indices[to].clone(),
+                        DataValue::from(cost),
+                        DataValue::List(path),
+                    ]),
+                    0,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(4)
+    }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        let max_nodes = algo.pos_integer_option("max_nodes", Some(1000)).ok()?;
+        // larger graphs are refused anyway
+        let n = rows.min(max_nodes);
+        let matrices = n
+            .saturating_mul(n)
+            .saturating_mul(size_of::<f64>() + size_of::<usize>());
+        Some(graph_memory_estimate(rows, undirected).saturating_add(matrices))
+    }
+}
+
+/// Returns the matrix of distances and the matrix of the next hop on a shortest path.
+/// Among paths of equal cost the direct edge, or else the one found first, is kept.
+pub(crate) fn floyd_warshall(
+    edges: &[Vec<(usize, f64)>],
+    poison: Poison,
+) -> Result<(Vec<Vec<f64>>, Vec<Vec<usize>>)> {
+    let n = edges.len();
+    let mut distance = vec![vec![f64::INFINITY; n]; n];
+    let mut next = vec![vec![usize::MAX; n]; n];
+    for (from, tos) in edges.iter().enumerate() {
+        distance[from][from] = 0.;
+        next[from][from] = from;
+        for (to, weight) in tos {
+            if *weight < distance[from][*to] {
+                distance[from][*to] = *weight;
+                next[from][*to] = *to;
+            }
+        }
+    }
+    for k in 0..n {
+        for i in 0..n {
+            let through_k = distance[i][k];
+            if !through_k.is_finite() {
+                continue;
+            }
+            for j in 0..n {
+                let cost = through_k + distance[k][j];
+                if cost < distance[i][j] {
+                    distance[i][j] = cost;
+                    next[i][j] = next[i][k];
+                }
+            }
+        }
+        poison.check()?;
+    }
+    Ok((distance, next))
 }
 
 pub(crate) fn dijkstra_cost_only(
@@ -2,17 +2,20 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+use std::borrow::Cow;
 use std::cmp::Reverse;
 use std::collections::BTreeMap;
 
-use miette::{ensure, Result};
+use miette::{bail, ensure, Diagnostic, Result};
 use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
 use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
 
 use crate::algo::{AlgoImpl, BadExprValueError, NodeNotFoundError};
 use crate::data::expr::Expr;
-use crate::data::program::{MagicAlgoApply, MagicAlgoRuleArg, MagicSymbol};
+use crate::data::functions::op_haversine_deg_input;
+use crate::data::program::{MagicAlgoApply, MagicAlgoRuleArg, MagicSymbol, WrongAlgoOptionError};
 use crate::data::symb::Symbol;
 use crate::data::tuple::Tuple;
 use crate::data::value::DataValue;
@@ -21,6 +24,13 @@ use crate::runtime::db::Poison;
 use crate::runtime::in_mem::InMemRelation;
 use crate::runtime::transact::SessionTx;
 
+/// A* search for the cheapest paths from the starting nodes to the goals.
+///
+/// Takes the relations of edges, nodes, starting nodes and goals, or only edges, starting
+/// nodes and goals, in which case the heuristic is computed from the node keys alone:
+/// an expression refers to the node by the name of the first column of the edges, and
+/// the built-in heuristics read the coordinates from keys that are lists. No node set is
+/// then loaded at all, only the edges out of the nodes visited.
 pub(crate) struct ShortestPathAStar;
 
 impl AlgoImpl for ShortestPathAStar {
@@ -33,15 +43,70 @@ impl AlgoImpl for ShortestPathAStar {
         poison: Poison,
     ) -> Result<()> {
         let edges = algo.relation_with_min_len(0, 3, tx, stores)?;
-        let nodes = algo.relation(1)?;
-        let starting = algo.relation(2)?;
-        let goals = algo.relation(3)?;
-        let mut heuristic = algo.expr_option("heuristic", None)?;
-
-        let mut binding_map = nodes.get_binding_map(0);
-        let goal_binding_map = goals.get_binding_map(nodes.arity(tx, stores)?);
-        binding_map.extend(goal_binding_map);
-        heuristic.fill_binding_indices(&binding_map)?;
+        let (nodes, starting, goals) = if algo.rule_args.len() == 3 {
+            (None, algo.relation(1)?, algo.relation(2)?)
+        } else {
+            (
+                Some(algo.relation(1)?),
+                algo.relation(2)?,
+                algo.relation(3)?,
+            )
+        };
+        let heuristic_expr = algo.expr_option("heuristic", None)?;
+        let heuristic = match heuristic_expr {
+            Expr::Const {
+                val: DataValue::Str(s),
+                span,
+            } => match &s as &str {
+                "haversine" => {
+                    let radius = algo
+                        .expr_option(
+                            "radius",
+                            Some(Expr::Const {
+                                val: DataValue::from(6371.),
+                                span,
+                            }),
+                        )?
+                        .eval_to_const()?;
+                    let radius = match radius.get_float() {
+                        Some(f) if f > 0. => f,
+                        _ => bail!(WrongAlgoOptionError {
+                            name: "radius".to_string(),
+                            span,
+                            algo_name: algo.algo.name.to_string(),
+                            help: "a positive number is required".to_string(),
+                        }),
+                    };
+                    Heuristic::Haversine { radius, span }
+                }
+                "euclidean" => Heuristic::Euclidean { span },
+                _ => bail!(WrongAlgoOptionError {
+                    name: "heuristic".to_string(),
+                    span,
+                    algo_name: algo.algo.name.to_string(),
+                    help: "the built-in heuristics are 'haversine' and 'euclidean'".to_string(),
+                }),
+            },
+            mut expr => {
+                let mut binding_map = match nodes {
+                    Some(nodes) => nodes.get_binding_map(0),
+                    None => edges
+                        .get_binding_map(0)
+                        .into_iter()
+                        .filter(|(_, idx)| *idx == 0)
+                        .collect(),
+                };
+                let node_arity = match nodes {
+                    Some(nodes) => nodes.arity(tx, stores)?,
+                    None => 1,
+                };
+                let goal_binding_map = goals.get_binding_map(node_arity);
+                binding_map.extend(goal_binding_map);
+                expr.fill_binding_indices(&binding_map)?;
+                Heuristic::Expr(expr)
+            }
+        };
+        let validate = algo.bool_option("validate_heuristic", Some(false))?;
         for start in starting.iter(tx, stores)? {
             let start = start?;
             for goal in goals.iter(tx, stores)? {
@@ -52,6 +117,7 @@ impl AlgoImpl for ShortestPathAStar {
                     edges,
                     nodes,
                     &heuristic,
+                    validate,
                     tx,
                     stores,
                     poison.clone(),
@@ -81,12 +147,121 @@ impl AlgoImpl for ShortestPathAStar {
     }
 }
 
+/// The estimated cost from a node to the goal.
+///
+/// Besides arbitrary expressions, the common cases are built in: `haversine` takes the
+/// second and third columns of the nodes and goals as latitude and longitude in degrees,
+/// and `euclidean` takes all columns after the first as coordinates. Nodes and goals
+/// consisting only of a key that is a list take the elements of the list as the columns.
+enum Heuristic {
+    Expr(Expr),
+    Haversine { radius: f64, span: SourceSpan },
+    Euclidean { span: SourceSpan },
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The heuristic of A* is inconsistent at the edge from {0:?} to {1:?}")]
+#[diagnostic(code(algo::inconsistent_heuristic))]
+#[diagnostic(help(
+    "The estimated cost dropped by more than the cost of the edge, \
+    so the path found may not be the shortest"
+))]
+struct InconsistentHeuristicError(DataValue, DataValue, #[label] SourceSpan);
+
+impl Heuristic {
+    fn span(&self) -> SourceSpan {
+        match self {
+            Heuristic::Expr(expr) => expr.span(),
+            Heuristic::Haversine { span, .. } | Heuristic::Euclidean { span } => *span,
+        }
+    }
+    fn coordinates(&self, tuple: &Tuple) -> Result<Vec<f64>> {
+        tuple.0[1..]
+            .iter()
+            .map(|v| {
+                v.get_float().ok_or_else(|| {
+                    BadExprValueError(
+                        v.clone(),
+                        self.span(),
+                        "coordinates must be numbers".to_string(),
+                    )
+                    .into()
+                })
+            })
+            .collect()
+    }
+    fn eval(&self, node: &Tuple, goal: &Tuple) -> Result<f64> {
+        let (node, goal) = match self {
+            Heuristic::Expr(_) => (Cow::Borrowed(node), Cow::Borrowed(goal)),
+            _ => (unpack_key(node), unpack_key(goal)),
+        };
+        let (node, goal) = (node.as_ref(), goal.as_ref());
+        Ok(match self {
+            Heuristic::Expr(expr) => {
+                let mut v = node.0.clone();
+                v.extend_from_slice(&goal.0);
+                let cost_val = expr.eval(&Tuple(v))?;
+                cost_val.get_float().ok_or_else(|| {
+                    BadExprValueError(cost_val, expr.span(), "a number is required".to_string())
+                })?
+            }
+            Heuristic::Haversine { radius, span } => {
+                ensure!(
+                    node.0.len() >= 3 && goal.0.len() >= 3,
+                    BadExprValueError(
+                        node.0[0].clone(),
+                        *span,
+                        "nodes and goals must have latitude and longitude".to_string(),
+                    )
+                );
+                let angle = op_haversine_deg_input(&[
+                    node.0[1].clone(),
+                    node.0[2].clone(),
+                    goal.0[1].clone(),
+                    goal.0[2].clone(),
+                ])?;
+                angle.get_float().unwrap_or(f64::NAN) * radius
+            }
+            Heuristic::Euclidean { span } => {
+                let from = self.coordinates(node)?;
+                let to = self.coordinates(goal)?;
+                ensure!(
+                    !from.is_empty() && from.len() == to.len(),
+                    BadExprValueError(
+                        node.0[0].clone(),
+                        *span,
+                        "nodes and goals must have the same number of coordinates".to_string(),
+                    )
+                );
+                from.iter()
+                    .zip(to.iter())
+                    .map(|(a, b)| (a - b) * (a - b))
+                    .sum::<f64>()
+                    .sqrt()
+            }
+        })
+    }
+}
+
+/// The tuple with the elements of the key appended, if it consists only of a key that is a list.
+fn unpack_key(tuple: &Tuple) -> Cow<'_, Tuple> {
+    match tuple.0.as_slice() {
+        [key @ DataValue::List(elems)] => {
+            let mut unpacked = vec![key.clone()];
+            unpacked.extend_from_slice(elems);
+            Cow::Owned(Tuple(unpacked))
+        }
+        _ => Cow::Borrowed(tuple),
+    }
+}
+
 fn astar(
     starting: &Tuple,
     goal: &Tuple,
     edges: &MagicAlgoRuleArg,
-    nodes: &MagicAlgoRuleArg,
-    heuristic: &Expr,
+    nodes: Option<&MagicAlgoRuleArg>,
+    heuristic: &Heuristic,
+    validate: bool,
     tx: &SessionTx,
     stores: &BTreeMap<MagicSymbol, InMemRelation>,
     poison: Poison,
@@ -94,17 +269,7 @@ fn astar(
     let start_node = &starting.0[0];
     let goal_node = &goal.0[0];
     let eval_heuristic = |node: &Tuple| -> Result<f64> {
-        let mut v = node.0.clone();
-        v.extend_from_slice(&goal.0);
-        let t = Tuple(v);
-        let cost_val = heuristic.eval(&t)?;
-        let cost = cost_val.get_float().ok_or_else(|| {
-            BadExprValueError(
-                cost_val,
-                heuristic.span(),
-                "a number is required".to_string(),
-            )
-        })?;
+        let cost = heuristic.eval(node, goal)?;
         ensure!(
             !cost.is_nan(),
             BadExprValueError(
@@ -115,6 +280,29 @@ fn astar(
         );
         Ok(cost)
     };
+    // estimates are only kept when validating: a consistent heuristic never drops
+    // by more than the cost of an edge, which guarantees that the result is optimal
+    let mut estimates: BTreeMap<DataValue, f64> = Default::default();
+    if validate {
+        let (start_tuple, goal_tuple) = match nodes {
+            Some(_) => (starting.clone(), goal.clone()),
+            None => (
+                Tuple(vec![start_node.clone()]),
+                Tuple(vec![goal_node.clone()]),
+            ),
+        };
+        let start_estimate = eval_heuristic(&start_tuple)?;
+        estimates.insert(start_node.clone(), start_estimate);
+        let goal_estimate = eval_heuristic(&goal_tuple)?;
+        ensure!(
+            goal_estimate.abs() < 1e-9,
+            BadExprValueError(
+                DataValue::from(goal_estimate),
+                heuristic.span(),
+                "the estimated cost from the goal to itself must be zero".to_string(),
+            )
+        );
+    }
     let mut back_trace: BTreeMap<DataValue, DataValue> = Default::default();
     let mut g_score: BTreeMap<DataValue, f64> = BTreeMap::from([(start_node.clone(), 0.)]);
     let mut open_set: PriorityQueue<DataValue, (Reverse<OrderedFloat<f64>>, usize)> =
@@ -161,15 +349,30 @@ fn astar(
                 back_trace.insert(edge_dst.clone(), node.clone());
                 g_score.insert(edge_dst.clone(), tentative_cost_to_dst);
 
-                let edge_dst_tuple = nodes
-                    .prefix_iter(edge_dst, tx, stores)?
-                    .next()
-                    .ok_or_else(|| NodeNotFoundError {
-                        missing: edge_dst.clone(),
-                        span: nodes.span(),
-                    })??;
+                let edge_dst_tuple = match nodes {
+                    Some(nodes) => nodes
+                        .prefix_iter(edge_dst, tx, stores)?
+                        .next()
+                        .ok_or_else(|| NodeNotFoundError {
+                            missing: edge_dst.clone(),
+                            span: nodes.span(),
+                        })??,
+                    None => Tuple(vec![edge_dst.clone()]),
+                };
 
                 let heuristic_cost = eval_heuristic(&edge_dst_tuple)?;
+                if validate {
+                    let src_estimate = estimates.get(&node).cloned().unwrap_or(0.);
+                    ensure!(
+                        src_estimate <= edge_cost + heuristic_cost + 1e-9,
+                        InconsistentHeuristicError(
+                            node.clone(),
+                            edge_dst.clone(),
+                            heuristic.span()
+                        )
+                    );
+                    estimates.insert(edge_dst.clone(), heuristic_cost);
+                }
                 sub_priority += 1;
                 open_set.push_increase(
                     edge_dst.clone(),
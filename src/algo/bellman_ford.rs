@@ -0,0 +1,204 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::algo::{graph_memory_estimate, AlgoImpl};
+use crate::data::expr::Expr;
+use crate::data::program::{MagicAlgoApply, MagicSymbol, WrongAlgoOptionError};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+pub(crate) struct ShortestPathBellmanFord;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Negative cycle reachable from the starting node {0:?}")]
+#[diagnostic(code(algo::negative_cycle))]
+#[diagnostic(help(
+    "Shortest paths are undefined when a negative cycle can be traversed; \
+    set the option 'negative_cycle' to 'flag' to report the affected nodes instead"
+))]
+struct NegativeCycleError(DataValue, #[label] SourceSpan);
+
+fn flag_negative_cycles(options: &BTreeMap<SmartString<LazyCompact>, Expr>) -> bool {
+    matches!(
+        options.get("negative_cycle"),
+        Some(Expr::Const { val: DataValue::Str(s), .. }) if s == "flag"
+    )
+}
+
+impl AlgoImpl for ShortestPathBellmanFord {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+        let starting = algo.relation(1)?;
+        let termination = algo.relation(2);
+        let undirected = algo.bool_option("undirected", Some(false))?;
+        let flag = match &algo.string_option("negative_cycle", Some("error"))? as &str {
+            "error" => false,
+            "flag" => true,
+            _ => bail!(WrongAlgoOptionError {
+                name: "negative_cycle".to_string(),
+                span: algo.span,
+                algo_name: algo.algo.name.to_string(),
+                help: "either 'error' or 'flag' is required".to_string(),
+            }),
+        };
+
+        let (graph, indices, inv_indices, _) =
+            edges.convert_edge_to_weighted_graph(undirected, true, tx, stores)?;
+
+        let mut starting_nodes = BTreeSet::new();
+        for tuple in starting.iter(tx, stores)? {
+            let tuple = tuple?;
+            if let Some(idx) = inv_indices.get(&tuple.0[0]) {
+                starting_nodes.insert(*idx);
+            }
+        }
+        let termination_nodes = match termination {
+            Err(_) => None,
+            Ok(t) => {
+                let mut tn = BTreeSet::new();
+                for tuple in t.iter(tx, stores)? {
+                    let tuple = tuple?;
+                    if let Some(idx) = inv_indices.get(&tuple.0[0]) {
+                        tn.insert(*idx);
+                    }
+                }
+                Some(tn)
+            }
+        };
+
+        for start in starting_nodes {
+            let (distance, back_pointers, in_cycle) = bellman_ford(&graph, start, poison.clone())?;
+            if !flag && in_cycle.iter().any(|b| *b) {
+                bail!(NegativeCycleError(indices[start].clone(), algo.span))
+            }
+            let targets: Box<dyn Iterator<Item = usize>> = match &termination_nodes {
+                None => Box::new(0..graph.len()),
+                Some(tn) => Box::new(tn.iter().cloned()),
+            };
+            for target in targets {
+                let (cost, path) = if in_cycle[target] {
+                    (f64::NEG_INFINITY, vec![])
+                } else if !distance[target].is_finite() {
+                    (distance[target], vec![])
+                } else {
+                    let mut path = vec![];
+                    let mut current = target;
+                    while current != start {
+                        path.push(current);
+                        current = back_pointers[current];
+                    }
+                    path.push(start);
+                    path.reverse();
+                    (distance[target], path)
+                };
+                let mut t = vec![
+                    indices[start].clone(),
+                    indices[target].clone(),
+                    DataValue::from(cost),
+                    DataValue::List(path.into_iter().map(|u| indices[u].clone()).collect_vec()),
+                ];
+                if flag {
+                    t.push(DataValue::Bool(in_cycle[target]));
+                }
+                out.put(Tuple(t), 0)
+            }
+        }
+
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(if flag_negative_cycles(options) { 5 } else { 4 })
+    }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        Some(graph_memory_estimate(rows, undirected))
+    }
+}
+
+/// Returns the distances from `start`, the back pointers of the shortest paths, and
+/// whether each node can be reached through a negative cycle (its distance is then
+/// unbounded).
+pub(crate) fn bellman_ford(
+    edges: &[Vec<(usize, f64)>],
+    start: usize,
+    poison: Poison,
+) -> Result<(Vec<f64>, Vec<usize>, Vec<bool>)> {
+    let n = edges.len();
+    let mut distance = vec![f64::INFINITY; n];
+    let mut back_pointers = vec![usize::MAX; n];
+    distance[start] = 0.;
+
+    for _ in 1..n {
+        let mut changed = false;
+        for (node, nxts) in edges.iter().enumerate() {
+            if !distance[node].is_finite() {
+                continue;
+            }
+            for (nxt, weight) in nxts {
+                let nxt_cost = distance[node] + *weight;
+                if nxt_cost < distance[*nxt] {
+                    distance[*nxt] = nxt_cost;
+                    back_pointers[*nxt] = node;
+                    changed = true;
+                }
+            }
+        }
+        poison.check()?;
+        if !changed {
+            break;
+        }
+    }
+
+    // nodes that can still be relaxed lie on or behind a negative cycle,
+    // and so does everything reachable from them
+    let mut in_cycle = vec![false; n];
+    let mut stack = vec![];
+    for (node, nxts) in edges.iter().enumerate() {
+        if !distance[node].is_finite() {
+            continue;
+        }
+        for (nxt, weight) in nxts {
+            if distance[node] + *weight < distance[*nxt] && !in_cycle[*nxt] {
+                in_cycle[*nxt] = true;
+                stack.push(*nxt);
+            }
+        }
+    }
+    while let Some(node) = stack.pop() {
+        for (nxt, _) in &edges[node] {
+            if !in_cycle[*nxt] {
+                in_cycle[*nxt] = true;
+                stack.push(*nxt);
+            }
+        }
+    }
+
+    Ok((distance, back_pointers, in_cycle))
+}
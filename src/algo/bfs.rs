@@ -4,7 +4,7 @@
 
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
-use miette::{Result};
+use miette::Result;
 use smartstring::{LazyCompact, SmartString};
 
 use crate::algo::{AlgoImpl, NodeNotFoundError};
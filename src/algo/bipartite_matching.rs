@@ -0,0 +1,161 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+
+use itertools::Itertools;
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::algo::{AlgoImpl, NotAnEdgeError};
+use crate::data::expr::Expr;
+use crate::data::program::{MagicAlgoApply, MagicSymbol};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+/// A maximum matching of a bipartite graph, by the Hopcroft-Karp algorithm.
+/// The sources of the edges form one side of the graph and the targets the other,
+/// and each matched pair is produced as a row.
+pub(crate) struct MaximumMatching;
+
+impl AlgoImpl for MaximumMatching {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+
+        let mut graph: Vec<Vec<usize>> = vec![];
+        let mut left_indices: Vec<DataValue> = vec![];
+        let mut inv_left: BTreeMap<DataValue, usize> = Default::default();
+        let mut right_indices: Vec<DataValue> = vec![];
+        let mut inv_right: BTreeMap<DataValue, usize> = Default::default();
+        for tuple in edges.iter(tx, stores)? {
+            let mut tuple = tuple?.0.into_iter();
+            let from = tuple.next().ok_or_else(|| NotAnEdgeError(edges.span()))?;
+            let to = tuple.next().ok_or_else(|| NotAnEdgeError(edges.span()))?;
+            let from_idx = *inv_left.entry(from.clone()).or_insert_with(|| {
+                left_indices.push(from);
+                graph.push(vec![]);
+                graph.len() - 1
+            });
+            let to_idx = *inv_right.entry(to.clone()).or_insert_with(|| {
+                right_indices.push(to);
+                right_indices.len() - 1
+            });
+            graph[from_idx].push(to_idx);
+        }
+
+        let matched = hopcroft_karp(&graph, right_indices.len(), poison)?;
+        for (left, right) in matched.into_iter().enumerate() {
+            if let Some(right) = right {
+                out.put(
+                    Tuple(vec![
+                        left_indices[left].clone(),
+                        right_indices[right].clone(),
+                    ]),
+                    0,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
+
+const UNREACHED: usize = usize::MAX;
+
+/// Returns the node on the right each node on the left is matched to.
+fn hopcroft_karp(
+    graph: &[Vec<usize>],
+    n_right: usize,
+    poison: Poison,
+) -> Result<Vec<Option<usize>>> {
+    let n_left = graph.len();
+    let mut match_left: Vec<Option<usize>> = vec![None; n_left];
+    let mut match_right: Vec<Option<usize>> = vec![None; n_right];
+    let mut dist = vec![UNREACHED; n_left];
+    loop {
+        // layer the free nodes on the left and the nodes reachable from them
+        // through alternating paths, stopping at the first layer reaching a free node
+        let mut queue = VecDeque::new();
+        for (u, m) in match_left.iter().enumerate() {
+            if m.is_none() {
+                dist[u] = 0;
+                queue.push_back(u);
+            } else {
+                dist[u] = UNREACHED;
+            }
+        }
+        let mut found = false;
+        while let Some(u) = queue.pop_front() {
+            for &v in &graph[u] {
+                match match_right[v] {
+                    None => found = true,
+                    Some(w) => {
+                        if dist[w] == UNREACHED {
+                            dist[w] = dist[u] + 1;
+                            if !found {
+                                queue.push_back(w);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if !found {
+            return Ok(match_left);
+        }
+
+        // augment along vertex-disjoint shortest paths found by depth-first search
+        let mut next_edge = vec![0; n_left];
+        let free = (0..n_left)
+            .filter(|u| match_left[*u].is_none())
+            .collect_vec();
+        for root in free {
+            let mut stack = vec![root];
+            while let Some(&u) = stack.last() {
+                if next_edge[u] == graph[u].len() {
+                    dist[u] = UNREACHED;
+                    stack.pop();
+                    if let Some(&parent) = stack.last() {
+                        next_edge[parent] += 1;
+                    }
+                    continue;
+                }
+                let v = graph[u][next_edge[u]];
+                match match_right[v] {
+                    None => {
+                        for &x in &stack {
+                            let y = graph[x][next_edge[x]];
+                            match_left[x] = Some(y);
+                            match_right[y] = Some(x);
+                        }
+                        break;
+                    }
+                    Some(w) if dist[w] == dist[u] + 1 => stack.push(w),
+                    Some(_) => next_edge[u] += 1,
+                }
+            }
+            poison.check()?;
+        }
+    }
+}
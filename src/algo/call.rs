@@ -0,0 +1,379 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Applying fixed rules to stored relations from Rust with [`Db::run_algo`](crate::Db::run_algo),
+//! without writing and parsing scripts. [`AlgoCall`] applies any fixed rule and returns the
+//! rows as JSON; the builders it makes for common algorithms set their options with typed
+//! methods and convert the rows to Rust types.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use itertools::Itertools;
+use miette::{Diagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::algo::custom::CustomAlgos;
+use crate::algo::AlgoHandle;
+use crate::data::expr::Expr;
+use crate::data::json::JsonValue;
+use crate::data::program::{
+    AlgoApply, AlgoRuleArg, InputInlineRulesOrAlgo, InputProgram, QueryOutOptions,
+};
+use crate::data::symb::{quote_ident, Symbol, PROG_ENTRY};
+use crate::data::value::DataValue;
+
+/// The application of a fixed rule, builtin or custom, to stored relations, as in
+/// `?[] <~ Name(*input[], option: value)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlgoCall {
+    name: String,
+    /// The positional inputs, `None` for those not given yet
+    inputs: Vec<Option<String>>,
+    options: BTreeMap<String, JsonValue>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Input {1} of '{0}' is not given")]
+#[diagnostic(code(algo::call_input_missing))]
+struct AlgoCallInputMissing(String, usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unexpected row returned by '{0}': {1:?}")]
+#[diagnostic(code(algo::call_unexpected_row))]
+struct AlgoCallUnexpectedRow(&'static str, Vec<JsonValue>);
+
+impl AlgoCall {
+    /// Applies the fixed rule `name`, to which inputs and options are then added.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            inputs: vec![],
+            options: Default::default(),
+        }
+    }
+    /// Adds the stored relation `relation` as the next input.
+    pub fn input(mut self, relation: impl Into<String>) -> Self {
+        self.inputs.push(Some(relation.into()));
+        self
+    }
+    /// Sets the option `name`, which must be a constant.
+    pub fn option(mut self, name: impl Into<String>, value: impl Into<JsonValue>) -> Self {
+        self.options.insert(name.into(), value.into());
+        self
+    }
+    fn with_input(mut self, idx: usize, relation: impl Into<String>) -> Self {
+        if self.inputs.len() <= idx {
+            self.inputs.resize(idx + 1, None);
+        }
+        self.inputs[idx] = Some(relation.into());
+        self
+    }
+    /// `PageRank`, giving the score of each node.
+    pub fn pagerank() -> PageRankCall {
+        PageRankCall(Self::new("PageRank"))
+    }
+    /// `ConnectedComponents`, giving the index of the component of each node.
+    pub fn connected_components() -> ConnectedComponentsCall {
+        ConnectedComponentsCall(Self::new("ConnectedComponents"))
+    }
+    /// `CommunityDetectionLouvain`, giving the communities of each node from the outermost
+    /// to the innermost.
+    pub fn louvain() -> LouvainCall {
+        LouvainCall(Self::new("CommunityDetectionLouvain"))
+    }
+    /// `ShortestPathDijkstra`, giving the shortest paths from the starting nodes.
+    pub fn shortest_path_dijkstra() -> ShortestPathDijkstraCall {
+        ShortestPathDijkstraCall(Self::new("ShortestPathDijkstra"))
+    }
+    /// The program applying the fixed rule as the entry of a query, which may be one of the
+    /// custom algorithms of the database.
+    pub(crate) fn to_program(&self, custom_algos: &CustomAlgos) -> Result<InputProgram> {
+        let algo = AlgoHandle::resolve(&self.name, Default::default(), custom_algos);
+        let algo_impl = algo.get_impl()?;
+        let rule_args = self
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, input)| match input {
+                Some(name) => Ok(AlgoRuleArg::Stored {
+                    name: Symbol::new(name.as_str(), Default::default()),
+                    bindings: vec![],
+                    valid_at: None,
+                    span: Default::default(),
+                }),
+                None => Err(AlgoCallInputMissing(self.name.clone(), i).into()),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut options = self
+            .options
+            .iter()
+            .map(|(name, val)| {
+                (
+                    SmartString::from(name.as_str()),
+                    Expr::Const {
+                        val: DataValue::from(val),
+                        span: Default::default(),
+                    },
+                )
+            })
+            .collect();
+        algo_impl.process_options(&mut options, Default::default())?;
+        let arity = algo_impl.arity(&options, &[], Default::default())?;
+        let apply = AlgoApply {
+            algo,
+            rule_args,
+            options,
+            head: vec![],
+            arity,
+            span: Default::default(),
+            algo_impl,
+        };
+        Ok(InputProgram {
+            prog: BTreeMap::from([(
+                Symbol::new(PROG_ENTRY, Default::default()),
+                InputInlineRulesOrAlgo::Algo { algo: apply },
+            )]),
+            out_opts: QueryOutOptions::default(),
+            deprecations: vec![],
+        })
+    }
+}
+
+impl Display for AlgoCall {
+    /// The call written as a script.
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let inputs = self.inputs.iter().map(|input| match input {
+            Some(name) => format!("*{}[]", quote_ident(name)),
+            None => "_[]".to_string(),
+        });
+        let options = self
+            .options
+            .iter()
+            .map(|(name, val)| format!("{}: {}", name, val));
+        write!(
+            f,
+            "?[] <~ {}({})",
+            self.name,
+            inputs.chain(options).join(", ")
+        )
+    }
+}
+
+/// A fixed rule application run by [`Db::run_algo`](crate::Db::run_algo), with the type its
+/// rows are converted to.
+pub trait TypedAlgoCall {
+    /// What the rows are converted to.
+    type Output;
+    /// The application itself.
+    fn into_call(self) -> AlgoCall;
+    /// Converts the rows returned.
+    fn convert(rows: Vec<Vec<JsonValue>>) -> Result<Self::Output>;
+}
+
+impl TypedAlgoCall for AlgoCall {
+    type Output = Vec<Vec<JsonValue>>;
+
+    fn into_call(self) -> AlgoCall {
+        self
+    }
+    fn convert(rows: Vec<Vec<JsonValue>>) -> Result<Self::Output> {
+        Ok(rows)
+    }
+}
+
+/// Converts each row with `f`, failing on the first row it cannot convert.
+fn convert_rows<T>(
+    algo: &'static str,
+    rows: Vec<Vec<JsonValue>>,
+    f: impl Fn(&[JsonValue]) -> Option<T>,
+) -> Result<Vec<T>> {
+    rows.into_iter()
+        .map(|row| match f(&row) {
+            Some(converted) => Ok(converted),
+            None => Err(AlgoCallUnexpectedRow(algo, row).into()),
+        })
+        .collect()
+}
+
+/// Builder of a `PageRank` call made by [`AlgoCall::pagerank`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageRankCall(AlgoCall);
+
+impl PageRankCall {
+    /// The stored relation of the edges, whose first two columns are the nodes they connect.
+    pub fn edges(self, relation: impl Into<String>) -> Self {
+        Self(self.0.with_input(0, relation))
+    }
+    /// Whether the edges go both ways. Defaults to `false`.
+    pub fn undirected(self, undirected: bool) -> Self {
+        Self(self.0.option("undirected", undirected))
+    }
+    /// The probability of following an edge rather than jumping to a random node,
+    /// `theta` in scripts. Defaults to 0.8.
+    pub fn damping(self, damping: f64) -> Self {
+        Self(self.0.option("theta", damping))
+    }
+    /// Iteration stops once no score changes by more than this. Defaults to 0.05.
+    pub fn epsilon(self, epsilon: f64) -> Self {
+        Self(self.0.option("epsilon", epsilon))
+    }
+    /// The maximum number of iterations. Defaults to 20.
+    pub fn iterations(self, iterations: usize) -> Self {
+        Self(self.0.option("iterations", iterations))
+    }
+}
+
+impl TypedAlgoCall for PageRankCall {
+    /// The nodes with their scores.
+    type Output = Vec<(JsonValue, f64)>;
+
+    fn into_call(self) -> AlgoCall {
+        self.0
+    }
+    fn convert(rows: Vec<Vec<JsonValue>>) -> Result<Self::Output> {
+        convert_rows("PageRank", rows, |row| {
+            Some((row.first()?.clone(), row.get(1)?.as_f64()?))
+        })
+    }
+}
+
+/// Builder of a `ConnectedComponents` call made by [`AlgoCall::connected_components`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConnectedComponentsCall(AlgoCall);
+
+impl ConnectedComponentsCall {
+    /// The stored relation of the edges, whose first two columns are the nodes they connect.
+    pub fn edges(self, relation: impl Into<String>) -> Self {
+        Self(self.0.with_input(0, relation))
+    }
+    /// The stored relation of all the nodes, so that those without edges get components too.
+    pub fn nodes(self, relation: impl Into<String>) -> Self {
+        Self(self.0.with_input(1, relation))
+    }
+}
+
+impl TypedAlgoCall for ConnectedComponentsCall {
+    /// The nodes with the indices of their components.
+    type Output = Vec<(JsonValue, i64)>;
+
+    fn into_call(self) -> AlgoCall {
+        self.0
+    }
+    fn convert(rows: Vec<Vec<JsonValue>>) -> Result<Self::Output> {
+        convert_rows("ConnectedComponents", rows, |row| {
+            Some((row.first()?.clone(), row.get(1)?.as_i64()?))
+        })
+    }
+}
+
+/// Builder of a `CommunityDetectionLouvain` call made by [`AlgoCall::louvain`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct LouvainCall(AlgoCall);
+
+impl LouvainCall {
+    /// The stored relation of the edges, whose first two columns are the nodes they connect,
+    /// and whose third column, if any, is their weight.
+    pub fn edges(self, relation: impl Into<String>) -> Self {
+        Self(self.0.with_input(0, relation))
+    }
+    /// Whether the edges go both ways. Defaults to `false`.
+    pub fn undirected(self, undirected: bool) -> Self {
+        Self(self.0.option("undirected", undirected))
+    }
+    /// The maximum number of iterations at each level. Defaults to 10.
+    pub fn max_iter(self, max_iter: usize) -> Self {
+        Self(self.0.option("max_iter", max_iter))
+    }
+    /// The smallest gain in modularity for moving a node. Defaults to 0.0001.
+    pub fn delta(self, delta: f64) -> Self {
+        Self(self.0.option("delta", delta))
+    }
+    /// The number of levels of communities kept, from the outermost.
+    pub fn keep_depth(self, depth: usize) -> Self {
+        Self(self.0.option("keep_depth", depth))
+    }
+}
+
+impl TypedAlgoCall for LouvainCall {
+    /// The nodes with their communities, from the outermost to the innermost.
+    type Output = Vec<(JsonValue, Vec<i64>)>;
+
+    fn into_call(self) -> AlgoCall {
+        self.0
+    }
+    fn convert(rows: Vec<Vec<JsonValue>>) -> Result<Self::Output> {
+        convert_rows("CommunityDetectionLouvain", rows, |row| {
+            let labels = row
+                .first()?
+                .as_array()?
+                .iter()
+                .map(|label| label.as_i64())
+                .collect::<Option<Vec<_>>>()?;
+            Some((row.get(1)?.clone(), labels))
+        })
+    }
+}
+
+/// Builder of a `ShortestPathDijkstra` call made by [`AlgoCall::shortest_path_dijkstra`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShortestPathDijkstraCall(AlgoCall);
+
+/// A shortest path returned by [`ShortestPathDijkstraCall`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ShortestPath {
+    /// The node the path starts from
+    pub start: JsonValue,
+    /// The node the path ends at
+    pub goal: JsonValue,
+    /// The total cost of the edges along the path
+    pub cost: f64,
+    /// The nodes along the path, from `start` to `goal`
+    pub path: Vec<JsonValue>,
+}
+
+impl ShortestPathDijkstraCall {
+    /// The stored relation of the edges, whose first two columns are the nodes they connect,
+    /// and whose third column, if any, is their cost.
+    pub fn edges(self, relation: impl Into<String>) -> Self {
+        Self(self.0.with_input(0, relation))
+    }
+    /// The stored relation whose first column holds the nodes the paths start from.
+    pub fn starting(self, relation: impl Into<String>) -> Self {
+        Self(self.0.with_input(1, relation))
+    }
+    /// The stored relation whose first column holds the nodes the paths go to.
+    /// Without it, paths to all reachable nodes are returned.
+    pub fn goals(self, relation: impl Into<String>) -> Self {
+        Self(self.0.with_input(2, relation))
+    }
+    /// Whether the edges go both ways. Defaults to `false`.
+    pub fn undirected(self, undirected: bool) -> Self {
+        Self(self.0.option("undirected", undirected))
+    }
+    /// Whether all the shortest paths are returned when several have the same cost,
+    /// instead of only one. Defaults to `false`.
+    pub fn keep_ties(self, keep_ties: bool) -> Self {
+        Self(self.0.option("keep_ties", keep_ties))
+    }
+}
+
+impl TypedAlgoCall for ShortestPathDijkstraCall {
+    type Output = Vec<ShortestPath>;
+
+    fn into_call(self) -> AlgoCall {
+        self.0
+    }
+    fn convert(rows: Vec<Vec<JsonValue>>) -> Result<Self::Output> {
+        convert_rows("ShortestPathDijkstra", rows, |row| {
+            Some(ShortestPath {
+                start: row.first()?.clone(),
+                goal: row.get(1)?.clone(),
+                cost: row.get(2)?.as_f64()?,
+                path: row.get(3)?.as_array()?.clone(),
+            })
+        })
+    }
+}
@@ -0,0 +1,158 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Result};
+use rand::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::algo::{AlgoImpl, BadExprValueError};
+use crate::data::expr::Expr;
+use crate::data::program::{MagicAlgoApply, MagicSymbol, WrongAlgoOptionError};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+pub(crate) struct CascadeSimulation;
+
+impl AlgoImpl for CascadeSimulation {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+        let seeds = algo.relation(1)?;
+        let undirected = algo.bool_option("undirected", Some(false))?;
+        let rounds = algo.pos_integer_option("rounds", Some(usize::MAX))?;
+        let linear_threshold = match &algo.string_option("model", Some("independent_cascade"))?
+            as &str
+        {
+            "independent_cascade" => false,
+            "linear_threshold" => true,
+            _ => bail!(WrongAlgoOptionError {
+                name: "model".to_string(),
+                span: algo.span,
+                algo_name: algo.algo.name.to_string(),
+                help: "either 'independent_cascade' or 'linear_threshold' is required".to_string(),
+            }),
+        };
+        let probability = if algo.options.contains_key("probability") {
+            Some(algo.unit_interval_option("probability", None)?)
+        } else {
+            None
+        };
+        let threshold = if algo.options.contains_key("threshold") {
+            Some(algo.unit_interval_option("threshold", None)?)
+        } else {
+            None
+        };
+        let mut rng = if algo.options.contains_key("seed") {
+            StdRng::seed_from_u64(algo.non_neg_integer_option("seed", None)? as u64)
+        } else {
+            StdRng::from_entropy()
+        };
+
+        let (graph, indices, inv_indices, _) =
+            edges.convert_edge_to_weighted_graph(undirected, false, tx, stores)?;
+        if probability.is_none() && !linear_threshold {
+            for (_, weight) in graph.iter().flatten() {
+                ensure!(
+                    *weight <= 1.,
+                    BadExprValueError(
+                        DataValue::from(*weight),
+                        edges.span(),
+                        "edge weights are activation probabilities and cannot exceed 1".to_string(),
+                    )
+                );
+            }
+        }
+
+        let mut activated_at: Vec<Option<usize>> = vec![None; graph.len()];
+        let mut frontier = vec![];
+        for tuple in seeds.iter(tx, stores)? {
+            let tuple = tuple?;
+            if let Some(idx) = inv_indices.get(&tuple.0[0]) {
+                if activated_at[*idx].is_none() {
+                    activated_at[*idx] = Some(0);
+                    frontier.push(*idx);
+                }
+            }
+        }
+
+        let thresholds: Vec<f64> = if linear_threshold {
+            (0..graph.len())
+                .map(|_| threshold.unwrap_or_else(|| rng.gen::<f64>()))
+                .collect()
+        } else {
+            vec![]
+        };
+        let mut influence = vec![0.; graph.len()];
+
+        let mut round = 0;
+        while !frontier.is_empty() && round < rounds {
+            round += 1;
+            let mut next_frontier = vec![];
+            if linear_threshold {
+                let mut touched = vec![];
+                for node in &frontier {
+                    for (nxt, weight) in &graph[*node] {
+                        if activated_at[*nxt].is_none() {
+                            influence[*nxt] += probability.unwrap_or(*weight);
+                            touched.push(*nxt);
+                        }
+                    }
+                }
+                for node in touched {
+                    if activated_at[node].is_none() && influence[node] >= thresholds[node] {
+                        activated_at[node] = Some(round);
+                        next_frontier.push(node);
+                    }
+                }
+            } else {
+                // every newly activated node has a single chance to activate each neighbour
+                for node in &frontier {
+                    for (nxt, weight) in &graph[*node] {
+                        if activated_at[*nxt].is_none()
+                            && rng.gen::<f64>() < probability.unwrap_or(*weight)
+                        {
+                            activated_at[*nxt] = Some(round);
+                            next_frontier.push(*nxt);
+                        }
+                    }
+                }
+            }
+            frontier = next_frontier;
+            poison.check()?;
+        }
+
+        for (idx, activated) in activated_at.into_iter().enumerate() {
+            if let Some(round) = activated {
+                out.put(
+                    Tuple(vec![indices[idx].clone(), DataValue::from(round as i64)]),
+                    0,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+}
@@ -0,0 +1,174 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Checkpoints of the state of long-running iterative algorithms, written to files in the
+//! temporary directory every `checkpoint_every` iterations, so that a run that was cancelled
+//! or crashed can be resumed with the option `resume: true`. A checkpoint is only used by a
+//! run of the same algorithm with the same options on the same graph, and is removed once
+//! the algorithm completes.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+use miette::{IntoDiagnostic, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::data::program::MagicAlgoApply;
+
+/// Name of the directory under the temporary directory holding the checkpoints.
+const CHECKPOINT_DIR: &str = "cozo-checkpoints";
+
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct Saved<T> {
+    /// Number of iterations done when the state was saved
+    done: usize,
+    state: T,
+}
+
+/// Where and how often an algorithm application saves its state.
+#[derive(Default)]
+pub(crate) struct Checkpoint {
+    /// The file of the checkpoint, `None` if neither saving nor resuming is asked for
+    path: Option<PathBuf>,
+    every: Option<usize>,
+    resume: bool,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint options of the application. The checkpoint is identified by the
+    /// name and other options of the algorithm, and by what `input` feeds into the hasher,
+    /// which should cover the graph and any other input. `input` is only called when
+    /// checkpoints are asked for.
+    pub(crate) fn new(
+        algo: &MagicAlgoApply,
+        input: impl FnOnce(&mut DefaultHasher),
+    ) -> Result<Self> {
+        let every = if algo.options.contains_key("checkpoint_every") {
+            Some(algo.pos_integer_option("checkpoint_every", None)?)
+        } else {
+            None
+        };
+        let resume = algo.bool_option("resume", Some(false))?;
+        if every.is_none() && !resume {
+            return Ok(Self {
+                path: None,
+                every,
+                resume,
+            });
+        }
+
+        let mut hasher = DefaultHasher::new();
+        algo.algo.name.name.hash(&mut hasher);
+        for (name, expr) in &algo.options {
+            if name == "checkpoint_every" || name == "resume" {
+                continue;
+            }
+            name.hash(&mut hasher);
+            expr.clone().eval_to_const().ok().hash(&mut hasher);
+        }
+        input(&mut hasher);
+        let mut path = std::env::temp_dir();
+        path.push(CHECKPOINT_DIR);
+        path.push(format!(
+            "{}-{:016x}.json",
+            algo.algo.name.name,
+            hasher.finish()
+        ));
+        Ok(Self {
+            path: Some(path),
+            every,
+            resume,
+        })
+    }
+    /// The number of iterations done and the state saved by a previous run, if resuming
+    /// and such a run left a checkpoint.
+    pub(crate) fn load<T: DeserializeOwned>(&self) -> Result<Option<(usize, T)>> {
+        let path = match &self.path {
+            Some(path) if self.resume => path,
+            _ => return Ok(None),
+        };
+        match fs::read(path) {
+            Ok(content) => {
+                let saved: Saved<T> = serde_json::from_slice(&content).into_diagnostic()?;
+                Ok(Some((saved.done, saved.state)))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+    /// Saves the state after `done` iterations if it is time for a checkpoint. The file is
+    /// replaced in one go, so that a crash while saving keeps the previous checkpoint.
+    // `usize::is_multiple_of` would raise the minimum supported Rust version
+    #[allow(clippy::manual_is_multiple_of)]
+    pub(crate) fn save<T: Serialize>(&self, done: usize, state: &T) -> Result<()> {
+        let (path, every) = match (&self.path, self.every) {
+            (Some(path), Some(every)) => (path, every),
+            _ => return Ok(()),
+        };
+        if done % every != 0 {
+            return Ok(());
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).into_diagnostic()?;
+        }
+        let content = serde_json::to_vec(&Saved { done, state }).into_diagnostic()?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content).into_diagnostic()?;
+        fs::rename(&tmp, path).into_diagnostic()?;
+        Ok(())
+    }
+    /// Removes the checkpoint once the algorithm has completed.
+    pub(crate) fn finish(&self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Feeds a weighted graph into a hasher, for identifying checkpoints.
+pub(crate) fn hash_weighted_graph<H: Hasher>(graph: &[Vec<(usize, f64)>], hasher: &mut H) {
+    for edges in graph {
+        edges.len().hash(hasher);
+        for (to, weight) in edges {
+            (to, weight.to_bits()).hash(hasher);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algo::checkpoint::Checkpoint;
+
+    #[test]
+    fn save_and_resume() {
+        let mut path = std::env::temp_dir();
+        path.push(super::CHECKPOINT_DIR);
+        path.push("_test_checkpoint.json");
+        let checkpoint = Checkpoint {
+            path: Some(path.clone()),
+            every: Some(2),
+            resume: true,
+        };
+        checkpoint.finish();
+        assert!(checkpoint.load::<Vec<f64>>().unwrap().is_none());
+
+        checkpoint.save(1, &vec![1., 2.]).unwrap();
+        assert!(checkpoint.load::<Vec<f64>>().unwrap().is_none());
+        checkpoint.save(2, &vec![3., 4.]).unwrap();
+        assert_eq!(
+            checkpoint.load::<Vec<f64>>().unwrap(),
+            Some((2, vec![3., 4.]))
+        );
+
+        let fresh = Checkpoint {
+            resume: false,
+            ..checkpoint
+        };
+        assert!(fresh.load::<Vec<f64>>().unwrap().is_none());
+        fresh.finish();
+        assert!(!path.exists());
+    }
+}
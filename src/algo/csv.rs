@@ -5,11 +5,11 @@
 use std::collections::BTreeMap;
 
 use csv::StringRecord;
-use miette::{bail, ensure, IntoDiagnostic, Result};
+use miette::{bail, ensure, miette, IntoDiagnostic, Result};
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::{AlgoImpl, CannotDetermineArity};
 use crate::algo::jlines::get_file_content_from_url;
+use crate::algo::{AlgoImpl, CannotDetermineArity};
 use crate::data::expr::Expr;
 use crate::data::functions::{op_to_float, op_to_uuid};
 use crate::data::program::{
@@ -102,46 +102,7 @@ impl AlgoImpl for CsvReader {
                             )
                         }
                     }
-                    Some(s) => {
-                        let dv = DataValue::Str(SmartString::from(s));
-                        match &typ.coltype {
-                            ColType::Any | ColType::String => out_tuple.0.push(dv),
-                            ColType::Uuid => out_tuple.0.push(match op_to_uuid(&[dv]) {
-                                Ok(uuid) => uuid,
-                                Err(err) => {
-                                    if typ.nullable {
-                                        DataValue::Null
-                                    } else {
-                                        bail!(err)
-                                    }
-                                }
-                            }),
-                            ColType::Float => out_tuple.0.push(match op_to_float(&[dv]) {
-                                Ok(data) => data,
-                                Err(err) => {
-                                    if typ.nullable {
-                                        DataValue::Null
-                                    } else {
-                                        bail!(err)
-                                    }
-                                }
-                            }),
-                            ColType::Int => {
-                                let f = op_to_float(&[dv]).unwrap_or(DataValue::Null);
-                                match f.get_int() {
-                                    None => {
-                                        if typ.nullable {
-                                            out_tuple.0.push(DataValue::Null)
-                                        } else {
-                                            bail!("cannot convert {} to type {}", s, typ)
-                                        }
-                                    }
-                                    Some(i) => out_tuple.0.push(DataValue::from(i)),
-                                };
-                            }
-                            _ => bail!("cannot convert {} to type {}", s, typ),
-                        }
-                    }
+                    Some(s) => out_tuple.0.push(csv_field_to_value(s, typ)?),
                 }
             }
             out.put(out_tuple, 0);
@@ -209,3 +170,33 @@ impl AlgoImpl for CsvReader {
         ))
     }
 }
+
+/// Converts a field read from CSV to a value of the type `typ`. Fields that cannot be
+/// converted are null if the type is nullable.
+pub(crate) fn csv_field_to_value(s: &str, typ: &NullableColType) -> Result<DataValue> {
+    let dv = DataValue::Str(SmartString::from(s));
+    let converted = match &typ.coltype {
+        ColType::Any | ColType::String => Ok(dv),
+        ColType::Uuid => op_to_uuid(&[dv]),
+        ColType::Float => op_to_float(&[dv]),
+        ColType::BigInt | ColType::Decimal | ColType::Timestamp | ColType::Json => typ.coerce(dv),
+        ColType::Int => {
+            let f = op_to_float(&[dv]).unwrap_or(DataValue::Null);
+            match f.get_int() {
+                None => Err(miette!("cannot convert {} to type {}", s, typ)),
+                Some(i) => Ok(DataValue::from(i)),
+            }
+        }
+        ColType::Bool => match s.trim().to_ascii_lowercase().as_str() {
+            "true" | "t" | "yes" | "y" | "1" => Ok(DataValue::Bool(true)),
+            "false" | "f" | "no" | "n" | "0" => Ok(DataValue::Bool(false)),
+            _ => Err(miette!("cannot convert {} to type {}", s, typ)),
+        },
+        _ => bail!("cannot convert {} to type {}", s, typ),
+    };
+    match converted {
+        Ok(data) => Ok(data),
+        Err(_) if typ.nullable => Ok(DataValue::Null),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,180 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use miette::{ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::algo::{AlgoImpl, BUILTIN_ALGOS};
+use crate::data::expr::Expr;
+use crate::data::json::JsonValue;
+use crate::data::program::{MagicAlgoApply, MagicSymbol};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+/// A fixed rule implemented outside of the crate, registered with
+/// [`DbOptions::custom_algos`](crate::DbOptions::custom_algos) and applied in CozoScript
+/// like the builtin ones, as in `?[a, b] <~ MyAlgo(edges[], option: 1)`.
+///
+/// Options are passed as JSON values and must therefore be constants.
+pub trait CustomAlgo: Send + Sync {
+    /// The number of columns of the output, given the options and the names in the rule head.
+    fn arity(&self, options: &BTreeMap<String, JsonValue>, rule_head: &[String]) -> Result<usize>;
+    /// Computes the output rows from the input relations, putting them into `out`.
+    fn run(&self, input: &CustomAlgoInput<'_>, out: &mut CustomAlgoOutput<'_>) -> Result<()>;
+}
+
+impl Debug for dyn CustomAlgo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CustomAlgo")
+    }
+}
+
+/// The custom algorithms of a database, keyed by the name used to apply them.
+pub(crate) type CustomAlgos = BTreeMap<String, Arc<dyn CustomAlgo>>;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Algorithm '{0}' is already defined")]
+#[diagnostic(code(algo::name_conflict))]
+#[diagnostic(help("Custom algorithms cannot shadow builtin ones"))]
+struct AlgoNameConflict(String);
+
+/// Checks the custom algorithms given when opening a database.
+pub(crate) fn check_custom_algos(algos: &CustomAlgos) -> Result<()> {
+    for name in algos.keys() {
+        let is_builtin = BUILTIN_ALGOS
+            .iter()
+            .any(|a| a.names.contains(&name.as_str()));
+        ensure!(!is_builtin, AlgoNameConflict(name.to_string()));
+    }
+    Ok(())
+}
+
+/// The input of a custom algorithm: its options, and the relations it is applied to,
+/// in the order they appear in the application.
+pub struct CustomAlgoInput<'a> {
+    tx: &'a SessionTx,
+    algo: &'a MagicAlgoApply,
+    stores: &'a BTreeMap<MagicSymbol, InMemRelation>,
+    options: BTreeMap<String, JsonValue>,
+    poison: Poison,
+}
+
+impl CustomAlgoInput<'_> {
+    /// The options given in the application.
+    pub fn options(&self) -> &BTreeMap<String, JsonValue> {
+        &self.options
+    }
+    /// The number of input relations.
+    pub fn relations_count(&self) -> usize {
+        self.algo.rule_args.len()
+    }
+    /// The number of columns of the input relation at `idx`.
+    pub fn arity(&self, idx: usize) -> Result<usize> {
+        self.algo.relation(idx)?.arity(self.tx, self.stores)
+    }
+    /// The rows of the input relation at `idx`, read as the iterator is advanced.
+    pub fn rows(&self, idx: usize) -> Result<impl Iterator<Item = Result<Vec<JsonValue>>> + '_> {
+        let rows = self.algo.relation(idx)?.iter(self.tx, self.stores)?;
+        Ok(rows.map(|tuple| Ok(tuple?.0.into_iter().map(JsonValue::from).collect())))
+    }
+    /// Fails if the query has been killed or has timed out. Long computations should
+    /// call it from time to time.
+    pub fn check_killed(&self) -> Result<()> {
+        self.poison.check()
+    }
+}
+
+/// Where a custom algorithm puts its output rows.
+pub struct CustomAlgoOutput<'a> {
+    algo: &'a MagicAlgoApply,
+    out: &'a InMemRelation,
+}
+
+impl CustomAlgoOutput<'_> {
+    /// Adds a row to the output, which must have as many columns as the arity.
+    pub fn put(&mut self, row: Vec<JsonValue>) -> Result<()> {
+        ensure!(
+            row.len() == self.algo.arity,
+            CustomAlgoBadRow(
+                self.algo.algo.name.to_string(),
+                row.len(),
+                self.algo.arity,
+                self.algo.span
+            )
+        );
+        self.out
+            .put(Tuple(row.iter().map(DataValue::from).collect()), 0);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Option '{0}' of a custom algorithm must be a constant")]
+#[diagnostic(code(algo::custom_option_not_constant))]
+struct CustomOptionNotConstant(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Custom algorithm '{0}' returned a row of {1} columns, but its arity is {2}")]
+#[diagnostic(code(algo::custom_bad_row))]
+struct CustomAlgoBadRow(String, usize, usize, #[label] SourceSpan);
+
+fn options_to_json(
+    options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+) -> Result<BTreeMap<String, JsonValue>> {
+    options
+        .iter()
+        .map(|(k, v)| -> Result<(String, JsonValue)> {
+            let val = v
+                .clone()
+                .eval_to_const()
+                .map_err(|_| CustomOptionNotConstant(k.to_string(), v.span()))?;
+            Ok((k.to_string(), JsonValue::from(val)))
+        })
+        .collect()
+}
+
+pub(crate) struct CustomAlgoImpl(pub(crate) Arc<dyn CustomAlgo>);
+
+impl AlgoImpl for CustomAlgoImpl {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let input = CustomAlgoInput {
+            tx,
+            algo,
+            stores,
+            options: options_to_json(&algo.options)?,
+            poison,
+        };
+        self.0.run(&input, &mut CustomAlgoOutput { algo, out })
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        let head = rule_head
+            .iter()
+            .map(|s| s.name.to_string())
+            .collect::<Vec<_>>();
+        self.0.arity(&options_to_json(options)?, &head)
+    }
+}
@@ -0,0 +1,129 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::Result;
+use rand::prelude::*;
+use rayon::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::algo::all_pairs_shortest_path::dijkstra_cost_only;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
+use crate::data::expr::Expr;
+use crate::data::program::{MagicAlgoApply, MagicSymbol};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+/// The eccentricity of each node, its greatest distance to a node reachable from it, or with
+/// the option `summary`, a single row with the diameter and the radius of the graph.
+///
+/// With the option `samples`, distances are only computed to that many randomly chosen nodes,
+/// giving lower bounds of the eccentricities, for graphs too large for the exact computation.
+pub(crate) struct Eccentricity;
+
+impl AlgoImpl for Eccentricity {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+        let undirected = algo.bool_option("undirected", Some(false))?;
+        let summary = algo.bool_option("summary", Some(false))?;
+        let samples = if algo.options.contains_key("samples") {
+            Some(algo.pos_integer_option("samples", None)?)
+        } else {
+            None
+        };
+
+        let (graph, indices, _inv_indices, _) =
+            edges.convert_edge_to_weighted_graph(undirected, false, tx, stores)?;
+
+        let n = graph.len();
+        if n == 0 {
+            return Ok(());
+        }
+        let eccentricities = match samples {
+            Some(k) if k < n => {
+                let mut rng = if algo.options.contains_key("seed") {
+                    StdRng::seed_from_u64(algo.non_neg_integer_option("seed", None)? as u64)
+                } else {
+                    StdRng::from_entropy()
+                };
+                let targets = (0..n).choose_multiple(&mut rng, k);
+                // distances to a target are distances from it in the reversed graph
+                let mut reversed = vec![vec![]; n];
+                for (from, tos) in graph.iter().enumerate() {
+                    for (to, weight) in tos {
+                        reversed[*to].push((from, *weight));
+                    }
+                }
+                let to_targets: Vec<_> = targets
+                    .into_par_iter()
+                    .map(|target| dijkstra_cost_only(&reversed, target, poison.clone()))
+                    .collect::<Result<_>>()?;
+                (0..n)
+                    .map(|node| {
+                        to_targets
+                            .iter()
+                            .map(|distances| distances[node])
+                            .filter(|d| d.is_finite())
+                            .fold(0., f64::max)
+                    })
+                    .collect()
+            }
+            _ => (0..n)
+                .into_par_iter()
+                .map(|start| -> Result<f64> {
+                    let distances = dijkstra_cost_only(&graph, start, poison.clone())?;
+                    Ok(distances
+                        .into_iter()
+                        .filter(|d| d.is_finite())
+                        .fold(0., f64::max))
+                })
+                .collect::<Result<Vec<_>>>()?,
+        };
+
+        if summary {
+            let diameter = eccentricities.iter().cloned().fold(0., f64::max);
+            let radius = eccentricities.iter().cloned().fold(f64::INFINITY, f64::min);
+            out.put(
+                Tuple(vec![DataValue::from(diameter), DataValue::from(radius)]),
+                0,
+            );
+        } else {
+            for (idx, eccentricity) in eccentricities.into_iter().enumerate() {
+                out.put(
+                    Tuple(vec![indices[idx].clone(), DataValue::from(eccentricity)]),
+                    0,
+                );
+                poison.check()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        Some(graph_memory_estimate(rows, undirected))
+    }
+}
@@ -0,0 +1,145 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use miette::Result;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::algo::{AlgoImpl, NotAnEdgeError};
+use crate::data::expr::Expr;
+use crate::data::program::{MagicAlgoApply, MagicAlgoRuleArg, MagicSymbol};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+pub(crate) struct GraphDiff;
+
+type EdgeWeights = BTreeMap<(DataValue, DataValue), DataValue>;
+
+/// Collects the edges with their weights, which are null for edge relations of arity two.
+/// Of parallel edges, the one with the smallest weight is kept.
+fn collect_edges(
+    rel: &MagicAlgoRuleArg,
+    tx: &SessionTx,
+    stores: &BTreeMap<MagicSymbol, InMemRelation>,
+) -> Result<EdgeWeights> {
+    let mut ret = EdgeWeights::new();
+    for tuple in rel.iter(tx, stores)? {
+        let mut tuple = tuple?.0.into_iter();
+        let from = tuple.next().ok_or_else(|| NotAnEdgeError(rel.span()))?;
+        let to = tuple.next().ok_or_else(|| NotAnEdgeError(rel.span()))?;
+        let weight = tuple.next().unwrap_or(DataValue::Null);
+        let entry = ret.entry((from, to)).or_insert_with(|| weight.clone());
+        if weight < *entry {
+            *entry = weight;
+        }
+    }
+    Ok(ret)
+}
+
+fn nodes_of(edges: &EdgeWeights) -> BTreeSet<&DataValue> {
+    edges.keys().flat_map(|(from, to)| [from, to]).collect()
+}
+
+impl AlgoImpl for GraphDiff {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let old_edges = collect_edges(algo.relation(0)?, tx, stores)?;
+        let new_edges = collect_edges(algo.relation(1)?, tx, stores)?;
+        poison.check()?;
+
+        let change = |kind: &str, from: &DataValue, to: &DataValue, old, new| {
+            Tuple(vec![
+                DataValue::Str(SmartString::from(kind)),
+                from.clone(),
+                to.clone(),
+                old,
+                new,
+            ])
+        };
+
+        let old_nodes = nodes_of(&old_edges);
+        let new_nodes = nodes_of(&new_edges);
+        for node in old_nodes.difference(&new_nodes) {
+            out.put(
+                change(
+                    "node_removed",
+                    node,
+                    &DataValue::Null,
+                    DataValue::Null,
+                    DataValue::Null,
+                ),
+                0,
+            );
+        }
+        for node in new_nodes.difference(&old_nodes) {
+            out.put(
+                change(
+                    "node_added",
+                    node,
+                    &DataValue::Null,
+                    DataValue::Null,
+                    DataValue::Null,
+                ),
+                0,
+            );
+        }
+
+        for ((from, to), old_weight) in &old_edges {
+            match new_edges.get(&(from.clone(), to.clone())) {
+                None => out.put(
+                    change(
+                        "edge_removed",
+                        from,
+                        to,
+                        old_weight.clone(),
+                        DataValue::Null,
+                    ),
+                    0,
+                ),
+                Some(new_weight) if new_weight != old_weight => out.put(
+                    change(
+                        "weight_changed",
+                        from,
+                        to,
+                        old_weight.clone(),
+                        new_weight.clone(),
+                    ),
+                    0,
+                ),
+                _ => {}
+            }
+        }
+        for ((from, to), new_weight) in &new_edges {
+            if !old_edges.contains_key(&(from.clone(), to.clone())) {
+                out.put(
+                    change("edge_added", from, to, DataValue::Null, new_weight.clone()),
+                    0,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(5)
+    }
+}
@@ -0,0 +1,470 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::algo::jlines::get_file_stream_from_url;
+use crate::algo::{AlgoImpl, CannotDetermineArity};
+use crate::data::expr::Expr;
+use crate::data::program::{MagicAlgoApply, MagicSymbol, WrongAlgoOptionError};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+pub(crate) struct GraphReader;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum GraphFormat {
+    EdgeList,
+    GraphMl,
+    MatrixMarket,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum IdType {
+    Any,
+    Int,
+    String,
+}
+
+/// Where in the input a problem was found: text formats are read by lines, XML by bytes.
+#[derive(Debug, Copy, Clone)]
+enum Position {
+    Line(usize),
+    Byte(usize),
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Position::Line(n) => write!(f, "line {}", n),
+            Position::Byte(n) => write!(f, "byte {}", n),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Malformed {0} input at {1}: {2}")]
+#[diagnostic(code(algo::graph_reader_malformed))]
+struct MalformedGraphInput(&'static str, Position, String, #[label] SourceSpan);
+
+impl AlgoImpl for GraphReader {
+    fn run(
+        &mut self,
+        _tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        _stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let url = algo.string_option("url", None)?;
+        let format = match &algo.string_option("format", Some("edge_list"))? as &str {
+            "edge_list" => GraphFormat::EdgeList,
+            "graphml" => GraphFormat::GraphMl,
+            "matrix_market" => GraphFormat::MatrixMarket,
+            _ => bail!(WrongAlgoOptionError {
+                name: "format".to_string(),
+                span: algo.span,
+                algo_name: "GraphReader".to_string(),
+                help: "'format' must be one of 'edge_list', 'graphml' or 'matrix_market'"
+                    .to_string()
+            }),
+        };
+        let emit_nodes = match &algo.string_option("output", Some("edges"))? as &str {
+            "edges" => false,
+            "nodes" => true,
+            _ => bail!(WrongAlgoOptionError {
+                name: "output".to_string(),
+                span: algo.span,
+                algo_name: "GraphReader".to_string(),
+                help: "'output' must be either 'edges' or 'nodes'".to_string()
+            }),
+        };
+        let weighted = algo.bool_option("weighted", Some(false))?;
+        let default_id_type = if format == GraphFormat::MatrixMarket {
+            "Int"
+        } else {
+            "String"
+        };
+        let id_type = match &algo.string_option("id_type", Some(default_id_type))? as &str {
+            "Any" => IdType::Any,
+            "Int" => IdType::Int,
+            "String" => IdType::String,
+            _ => bail!(WrongAlgoOptionError {
+                name: "id_type".to_string(),
+                span: algo.span,
+                algo_name: "GraphReader".to_string(),
+                help: "'id_type' must be one of 'Any', 'Int' or 'String'".to_string()
+            }),
+        };
+        let delimiter = match algo.options.get("delimiter") {
+            None => None,
+            Some(_) => {
+                let d = algo.string_option("delimiter", None)?;
+                if d.chars().count() != 1 {
+                    bail!(WrongAlgoOptionError {
+                        name: "delimiter".to_string(),
+                        span: algo.span,
+                        algo_name: "GraphReader".to_string(),
+                        help: "'delimiter' must be a single character".to_string()
+                    })
+                }
+                d.chars().next()
+            }
+        };
+        let weight_key = algo.string_option("weight_key", Some("weight"))?;
+
+        let span = algo.span;
+        let to_id = |s: &str, at: Position| -> Result<DataValue> {
+            Ok(match id_type {
+                IdType::String => DataValue::Str(SmartString::from(s)),
+                IdType::Int => match s.parse::<i64>() {
+                    Ok(i) => DataValue::from(i),
+                    Err(_) => bail!(MalformedGraphInput(
+                        "graph",
+                        at,
+                        format!("node id '{}' is not an integer", s),
+                        span
+                    )),
+                },
+                IdType::Any => match s.parse::<i64>() {
+                    Ok(i) => DataValue::from(i),
+                    Err(_) => DataValue::Str(SmartString::from(s)),
+                },
+            })
+        };
+        let emit = |fr: DataValue, to: DataValue, weight: f64| {
+            if emit_nodes {
+                out.put(Tuple(vec![fr]), 0);
+                out.put(Tuple(vec![to]), 0);
+            } else if weighted {
+                out.put(Tuple(vec![fr, to, DataValue::from(weight)]), 0);
+            } else {
+                out.put(Tuple(vec![fr, to]), 0);
+            }
+        };
+
+        match format {
+            GraphFormat::EdgeList => {
+                for_each_line(&url, |at, line| {
+                    poison.check()?;
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') || line.starts_with('%') {
+                        return Ok(());
+                    }
+                    let fields: Vec<&str> = match delimiter {
+                        None => line.split_whitespace().collect(),
+                        Some(d) => line.split(d).map(|s| s.trim()).collect(),
+                    };
+                    if fields.len() < 2 {
+                        bail!(MalformedGraphInput(
+                            "edge list",
+                            at,
+                            "expected at least two fields".to_string(),
+                            span
+                        ))
+                    }
+                    let weight = match fields.get(2) {
+                        None => 1.,
+                        Some(w) => w.parse::<f64>().map_err(|_| {
+                            MalformedGraphInput(
+                                "edge list",
+                                at,
+                                format!("weight '{}' is not a number", w),
+                                span,
+                            )
+                        })?,
+                    };
+                    emit(to_id(fields[0], at)?, to_id(fields[1], at)?, weight);
+                    Ok(())
+                })?;
+            }
+            GraphFormat::MatrixMarket => {
+                let mut symmetric = false;
+                let mut seen_size_line = false;
+                for_each_line(&url, |at, line| {
+                    poison.check()?;
+                    let line = line.trim();
+                    if let Some(header) = line.strip_prefix("%%MatrixMarket") {
+                        let header = header.to_lowercase();
+                        if !header.contains("coordinate") {
+                            bail!(MalformedGraphInput(
+                                "MatrixMarket",
+                                at,
+                                "only the coordinate format is supported".to_string(),
+                                span
+                            ))
+                        }
+                        symmetric = header.contains("symmetric");
+                        return Ok(());
+                    }
+                    if line.is_empty() || line.starts_with('%') {
+                        return Ok(());
+                    }
+                    let fields: Vec<&str> = line.split_whitespace().collect();
+                    if !seen_size_line {
+                        seen_size_line = true;
+                        if emit_nodes {
+                            let n_rows = fields.first().and_then(|s| s.parse::<i64>().ok());
+                            let n_cols = fields.get(1).and_then(|s| s.parse::<i64>().ok());
+                            match (n_rows, n_cols) {
+                                (Some(r), Some(c)) => {
+                                    for i in 1..=r.max(c) {
+                                        out.put(Tuple(vec![to_id(&i.to_string(), at)?]), 0);
+                                    }
+                                }
+                                _ => bail!(MalformedGraphInput(
+                                    "MatrixMarket",
+                                    at,
+                                    "bad size line".to_string(),
+                                    span
+                                )),
+                            }
+                        }
+                        return Ok(());
+                    }
+                    if emit_nodes {
+                        return Ok(());
+                    }
+                    if fields.len() < 2 {
+                        bail!(MalformedGraphInput(
+                            "MatrixMarket",
+                            at,
+                            "expected at least two fields".to_string(),
+                            span
+                        ))
+                    }
+                    let weight = match fields.get(2) {
+                        None => 1.,
+                        Some(w) => w.parse::<f64>().map_err(|_| {
+                            MalformedGraphInput(
+                                "MatrixMarket",
+                                at,
+                                format!("value '{}' is not a number", w),
+                                span,
+                            )
+                        })?,
+                    };
+                    let fr = to_id(fields[0], at)?;
+                    let to = to_id(fields[1], at)?;
+                    if symmetric && fr != to {
+                        emit(to.clone(), fr.clone(), weight);
+                    }
+                    emit(fr, to, weight);
+                    Ok(())
+                })?;
+            }
+            GraphFormat::GraphMl => {
+                read_graphml(
+                    open_url(&url)?,
+                    &weight_key,
+                    span,
+                    &poison,
+                    |id, at| {
+                        if emit_nodes {
+                            out.put(Tuple(vec![to_id(id, at)?]), 0);
+                        }
+                        Ok(())
+                    },
+                    |fr, to, weight, at| {
+                        emit(to_id(fr, at)?, to_id(to, at)?, weight);
+                        Ok(())
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        let emit_nodes = match options.get("output") {
+            None => false,
+            Some(Expr::Const {
+                val: DataValue::Str(s),
+                ..
+            }) if s == "edges" => false,
+            Some(Expr::Const {
+                val: DataValue::Str(s),
+                ..
+            }) if s == "nodes" => true,
+            _ => bail!(CannotDetermineArity(
+                "GraphReader".to_string(),
+                "invalid option 'output' given, expect 'edges' or 'nodes'".to_string(),
+                span
+            )),
+        };
+        if emit_nodes {
+            return Ok(1);
+        }
+        Ok(match options.get("weighted") {
+            None => 2,
+            Some(Expr::Const {
+                val: DataValue::Bool(true),
+                ..
+            }) => 3,
+            Some(Expr::Const {
+                val: DataValue::Bool(false),
+                ..
+            }) => 2,
+            _ => bail!(CannotDetermineArity(
+                "GraphReader".to_string(),
+                "invalid option 'weighted' given, expect a boolean".to_string(),
+                span
+            )),
+        })
+    }
+}
+
+/// Opens the resource for reading, streaming from disk for `file://` URLs and from the
+/// network otherwise.
+fn open_url(url: &str) -> Result<Box<dyn BufRead>> {
+    Ok(match url.strip_prefix("file://") {
+        Some(file_path) => Box::new(io::BufReader::new(File::open(file_path).into_diagnostic()?)),
+        None => Box::new(io::BufReader::new(get_file_stream_from_url(url)?)),
+    })
+}
+
+/// Calls `f` with each line of the resource.
+fn for_each_line(url: &str, mut f: impl FnMut(Position, &str) -> Result<()>) -> Result<()> {
+    for (i, line) in open_url(url)?.lines().enumerate() {
+        let line = line.into_diagnostic()?;
+        f(Position::Line(i + 1), &line)?;
+    }
+    Ok(())
+}
+
+/// Calls `on_node` with the id of each node of a GraphML document and `on_edge` with the
+/// source, target and weight of each edge, the weight being the data of the edge under the key
+/// named `weight_key`, or 1 if it has none.
+fn read_graphml(
+    input: impl BufRead,
+    weight_key: &str,
+    span: SourceSpan,
+    poison: &Poison,
+    mut on_node: impl FnMut(&str, Position) -> Result<()>,
+    mut on_edge: impl FnMut(&str, &str, f64, Position) -> Result<()>,
+) -> Result<()> {
+    let mut reader = Reader::from_reader(input);
+    let mut buf = vec![];
+    let mut weight_key_ids = BTreeSet::new();
+    let mut cur_edge: Option<(String, String, f64)> = None;
+    let mut weight_text: Option<String> = None;
+    loop {
+        poison.check()?;
+        buf.clear();
+        let at = Position::Byte(reader.buffer_position());
+        let malformed = |msg: String| MalformedGraphInput("GraphML", at, msg, span);
+        let attr = |tag: &BytesStart<'_>, name: &[u8]| {
+            attr_of(tag, name).map_err(|e| malformed(e.to_string()))
+        };
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| malformed(e.to_string()))?;
+        match event {
+            Event::Start(ref tag) | Event::Empty(ref tag) => {
+                let self_closing = matches!(event, Event::Empty(_));
+                match tag.local_name().as_ref() {
+                    b"key" => {
+                        let for_edges =
+                            matches!(attr(tag, b"for")?.as_deref(), Some("edge" | "all"));
+                        if for_edges && attr(tag, b"attr.name")?.as_deref() == Some(weight_key) {
+                            if let Some(id) = attr(tag, b"id")? {
+                                weight_key_ids.insert(id);
+                            }
+                        }
+                    }
+                    b"node" => {
+                        if let Some(id) = attr(tag, b"id")? {
+                            on_node(&id, at)?;
+                        }
+                    }
+                    b"edge" => {
+                        let (fr, to) = match (attr(tag, b"source")?, attr(tag, b"target")?) {
+                            (Some(fr), Some(to)) => (fr, to),
+                            _ => bail!(malformed("edge without source or target".to_string())),
+                        };
+                        if self_closing {
+                            on_edge(&fr, &to, 1., at)?;
+                        } else {
+                            cur_edge = Some((fr, to, 1.));
+                        }
+                    }
+                    b"data" if cur_edge.is_some() && !self_closing => {
+                        if let Some(key) = attr(tag, b"key")? {
+                            if weight_key_ids.contains(&key) {
+                                weight_text = Some(String::new());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::Text(text) => {
+                if let Some(captured) = &mut weight_text {
+                    captured.push_str(&text.unescape().map_err(|e| malformed(e.to_string()))?);
+                }
+            }
+            Event::CData(text) => {
+                if let Some(captured) = &mut weight_text {
+                    captured.push_str(&String::from_utf8_lossy(&text));
+                }
+            }
+            Event::End(tag) => match tag.local_name().as_ref() {
+                b"edge" => {
+                    if let Some((fr, to, weight)) = cur_edge.take() {
+                        on_edge(&fr, &to, weight, at)?;
+                    }
+                }
+                b"data" => {
+                    if let Some(text) = weight_text.take() {
+                        let text = text.trim();
+                        let weight = text
+                            .parse::<f64>()
+                            .map_err(|_| malformed(format!("weight '{}' is not a number", text)))?;
+                        if let Some((_, _, w)) = &mut cur_edge {
+                            *w = weight;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The unescaped value of the attribute `name` of `tag`, if present.
+fn attr_of(
+    tag: &BytesStart<'_>,
+    name: &[u8],
+) -> std::result::Result<Option<String>, quick_xml::Error> {
+    for attr in tag.attributes() {
+        let attr = attr?;
+        if attr.key.as_ref() == name {
+            return Ok(Some(attr.unescape_value()?.into_owned()));
+        }
+    }
+    Ok(None)
+}
@@ -4,13 +4,13 @@
 
 use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::BufRead;
+use std::io::{BufRead, Read};
 use std::{fs, io};
 
 use itertools::Itertools;
 use log::error;
 use miette::{bail, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
-use minreq::Response;
+use minreq::{Response, ResponseLazy};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
@@ -180,3 +180,42 @@ pub(crate) fn get_file_content_from_url(url: &str) -> Result<Response> {
         })
         .wrap_err_with(|| format!("when requesting URL {}", url))
 }
+
+/// The body of the response to a GET request of `url`, read as it arrives.
+pub(crate) fn get_file_stream_from_url(url: &str) -> Result<impl Read> {
+    let response = minreq::get(url as &str)
+        .send_lazy()
+        .map_err(|e| {
+            error!("{:?}", e);
+            miette!(e)
+        })
+        .wrap_err_with(|| format!("when requesting URL {}", url))?;
+    if response.status_code >= 400 {
+        bail!(
+            "request for URL {} failed with status {} {}",
+            url,
+            response.status_code,
+            response.reason_phrase
+        )
+    }
+    Ok(ResponseBody(response))
+}
+
+struct ResponseBody(ResponseLazy);
+
+impl Read for ResponseBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.0.next() {
+                None => break,
+                Some(Ok((byte, _))) => {
+                    buf[n] = byte;
+                    n += 1;
+                }
+                Some(Err(e)) => return Err(io::Error::other(e.to_string())),
+            }
+        }
+        Ok(n)
+    }
+}
@@ -11,7 +11,7 @@ use ordered_float::OrderedFloat;
 use priority_queue::PriorityQueue;
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -62,6 +62,10 @@ impl AlgoImpl for MinimumSpanningForestKruskal {
     ) -> Result<usize> {
         Ok(3)
     }
+
+    fn memory_estimate(&self, _algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        Some(graph_memory_estimate(rows, true))
+    }
 }
 
 fn kruskal(edges: &[Vec<(usize, f64)>], poison: Poison) -> Result<Vec<(usize, usize, f64)>> {
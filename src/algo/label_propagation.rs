@@ -9,7 +9,7 @@ use miette::Result;
 use rand::prelude::*;
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -52,6 +52,11 @@ impl AlgoImpl for LabelPropagation {
     ) -> Result<usize> {
         Ok(2)
     }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        Some(graph_memory_estimate(rows, undirected))
+    }
 }
 
 fn label_propagation(
@@ -3,13 +3,15 @@
  */
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::hash::Hash;
 
 use itertools::Itertools;
 use log::debug;
 use miette::Result;
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::AlgoImpl;
+use crate::algo::checkpoint::{hash_weighted_graph, Checkpoint};
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -39,6 +41,10 @@ impl AlgoImpl for CommunityDetectionLouvain {
 
         let (graph, indices, _inv_indices, _) =
             edges.convert_edge_to_weighted_graph(undirected, false, tx, stores)?;
+        let checkpoint = Checkpoint::new(algo, |hasher| {
+            indices.hash(hasher);
+            hash_weighted_graph(&graph, hasher);
+        })?;
         let graph = graph
             .into_iter()
             .map(|edges| -> BTreeMap<usize, f64> {
@@ -49,7 +55,7 @@ impl AlgoImpl for CommunityDetectionLouvain {
                 m
             })
             .collect_vec();
-        let result = louvain(&graph, delta, max_iter, poison)?;
+        let result = louvain(&graph, delta, max_iter, &checkpoint, poison)?;
         for (idx, node) in indices.into_iter().enumerate() {
             let mut labels = vec![];
             let mut cur_idx = idx;
@@ -64,6 +70,7 @@ impl AlgoImpl for CommunityDetectionLouvain {
             }
             out.put(Tuple(vec![DataValue::List(labels), node]), 0);
         }
+        checkpoint.finish();
 
         Ok(())
     }
@@ -76,17 +83,33 @@ impl AlgoImpl for CommunityDetectionLouvain {
     ) -> Result<usize> {
         Ok(2)
     }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        // the graph is converted to maps of weights, then aggregated at each level
+        Some(graph_memory_estimate(rows, undirected).saturating_mul(3))
+    }
 }
 
+/// Returns the community of each node at each level of the hierarchy. A checkpoint holds
+/// the levels found so far and the graph of the communities of the last one.
 fn louvain(
     graph: &[BTreeMap<usize, f64>],
     delta: f64,
     max_iter: usize,
+    checkpoint: &Checkpoint,
     poison: Poison,
 ) -> Result<Vec<Vec<usize>>> {
-    let mut current = graph;
-    let mut collected = vec![];
-    while current.len() > 2 {
+    let (mut levels, mut aggregated) =
+        match checkpoint.load::<(Vec<Vec<usize>>, Vec<BTreeMap<usize, f64>>)>()? {
+            Some((_, (levels, aggregated))) => (levels, Some(aggregated)),
+            None => (vec![], None),
+        };
+    loop {
+        let current = aggregated.as_deref().unwrap_or(graph);
+        if current.len() <= 2 {
+            break;
+        }
         let (node2comm, new_graph) = louvain_step(current, delta, max_iter, poison.clone())?;
         debug!(
             "before size: {}, after size: {}",
@@ -96,10 +119,11 @@ fn louvain(
         if new_graph.len() == current.len() {
             break;
         }
-        collected.push((node2comm, new_graph));
-        current = &collected.last().unwrap().1;
+        levels.push(node2comm);
+        checkpoint.save(levels.len(), &(&levels, &new_graph))?;
+        aggregated = Some(new_graph);
     }
-    Ok(collected.into_iter().map(|(a, _)| a).collect_vec())
+    Ok(levels)
 }
 
 fn calculate_delta(
@@ -256,6 +280,7 @@ fn louvain_step(
 mod tests {
     use itertools::Itertools;
 
+    use crate::algo::checkpoint::Checkpoint;
     use crate::algo::louvain::louvain;
     use crate::runtime::db::Poison;
 
@@ -283,6 +308,6 @@ mod tests {
             .into_iter()
             .map(|edges| edges.into_iter().map(|n| (n, 1.)).collect())
             .collect_vec();
-        louvain(&graph, 0., 100, Poison::default()).unwrap();
+        louvain(&graph, 0., 100, &Checkpoint::default(), Poison::default()).unwrap();
     }
 }
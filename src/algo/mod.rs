@@ -3,30 +3,44 @@
  */
 
 use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::sync::Arc;
 
+use either::{Left, Right};
 use miette::{bail, ensure, Diagnostic, Result};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::algo::all_pairs_shortest_path::{BetweennessCentrality, ClosenessCentrality};
+use crate::algo::all_pairs_shortest_path::{
+    BetweennessCentrality, ClosenessCentrality, FloydWarshall,
+};
 use crate::algo::astar::ShortestPathAStar;
+use crate::algo::bellman_ford::ShortestPathBellmanFord;
 use crate::algo::bfs::Bfs;
+use crate::algo::bipartite_matching::MaximumMatching;
+use crate::algo::cascade::CascadeSimulation;
 use crate::algo::constant::Constant;
 use crate::algo::csv::CsvReader;
+use crate::algo::custom::{CustomAlgo, CustomAlgoImpl, CustomAlgos};
 use crate::algo::degree_centrality::DegreeCentrality;
 use crate::algo::dfs::Dfs;
+use crate::algo::eccentricity::Eccentricity;
+use crate::algo::graph_diff::GraphDiff;
+use crate::algo::graph_reader::GraphReader;
 use crate::algo::jlines::JsonReader;
 use crate::algo::kruskal::MinimumSpanningForestKruskal;
 use crate::algo::label_propagation::LabelPropagation;
 use crate::algo::louvain::CommunityDetectionLouvain;
-use crate::algo::pagerank::PageRank;
+use crate::algo::node2vec::Node2Vec;
+use crate::algo::pagerank::{IncrementalPageRank, PageRank, PersonalizedPageRank};
 use crate::algo::prim::MinimumSpanningTreePrim;
 use crate::algo::random_walk::RandomWalk;
 use crate::algo::reorder_sort::ReorderSort;
 use crate::algo::shortest_path_dijkstra::ShortestPathDijkstra;
 use crate::algo::strongly_connected_components::StronglyConnectedComponent;
 use crate::algo::top_sort::TopSort;
-use crate::algo::triangles::ClusteringCoefficients;
+use crate::algo::triangles::{ClusteringCoefficients, TriangleCount};
+use crate::algo::vector_search::VectorSearch;
 use crate::algo::yen::KShortestPathYen;
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicAlgoRuleArg, MagicSymbol};
@@ -40,15 +54,25 @@ use crate::runtime::transact::SessionTx;
 
 pub(crate) mod all_pairs_shortest_path;
 pub(crate) mod astar;
+pub(crate) mod bellman_ford;
 pub(crate) mod bfs;
+pub(crate) mod bipartite_matching;
+pub(crate) mod call;
+pub(crate) mod cascade;
+pub(crate) mod checkpoint;
 pub(crate) mod constant;
 pub(crate) mod csv;
+pub(crate) mod custom;
 pub(crate) mod degree_centrality;
 pub(crate) mod dfs;
+pub(crate) mod eccentricity;
+pub(crate) mod graph_diff;
+pub(crate) mod graph_reader;
 pub(crate) mod jlines;
 pub(crate) mod kruskal;
 pub(crate) mod label_propagation;
 pub(crate) mod louvain;
+pub(crate) mod node2vec;
 pub(crate) mod pagerank;
 pub(crate) mod prim;
 pub(crate) mod random_walk;
@@ -57,9 +81,10 @@ pub(crate) mod shortest_path_dijkstra;
 pub(crate) mod strongly_connected_components;
 pub(crate) mod top_sort;
 pub(crate) mod triangles;
+pub(crate) mod vector_search;
 pub(crate) mod yen;
 
-pub(crate) trait AlgoImpl {
+pub(crate) trait AlgoImpl: Send {
     fn run(
         &mut self,
         tx: &SessionTx,
@@ -81,6 +106,87 @@ pub(crate) trait AlgoImpl {
     ) -> Result<()> {
         Ok(())
     }
+    /// The memory in bytes the algorithm is expected to need when its first input relation
+    /// has `rows` rows, if it can be estimated. Checked against the memory budget of the
+    /// query before the algorithm starts.
+    fn memory_estimate(&self, _algo: &MagicAlgoApply, _rows: usize) -> Option<usize> {
+        None
+    }
+}
+
+/// Estimated bytes taken by each node of a graph converted from edges: its key in the list
+/// of nodes and in the map from keys to positions, and its list of neighbours.
+const GRAPH_NODE_BYTES: usize =
+    2 * size_of::<DataValue>() + 2 * size_of::<usize>() + size_of::<Vec<(usize, f64)>>();
+
+/// Estimated bytes taken by the graph converted from `edges` edges. The number of nodes is
+/// not known before the conversion and is taken to be the number of edges, as in sparse graphs.
+pub(crate) fn graph_memory_estimate(edges: usize, undirected: bool) -> usize {
+    let stored = if undirected {
+        edges.saturating_mul(2)
+    } else {
+        edges
+    };
+    edges
+        .saturating_mul(GRAPH_NODE_BYTES)
+        .saturating_add(stored.saturating_mul(size_of::<(usize, f64)>()))
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error(
+    "Running '{name}' on {rows} input rows is estimated to need {needed} bytes of memory, \
+    more than the budget of {budget} bytes"
+)]
+#[diagnostic(code(algo::memory_budget_exceeded))]
+#[diagnostic(help(
+    "Restrict the input of the algorithm, \
+    or raise the budget for the query with ':algo_memory_budget'"
+))]
+struct MemoryBudgetExceeded {
+    name: String,
+    rows: usize,
+    needed: usize,
+    budget: usize,
+    #[label]
+    span: SourceSpan,
+}
+
+impl MagicAlgoApply {
+    /// Refuses to run the algorithm if its estimated memory use is over `budget`. The size of
+    /// the first input relation is counted for rules, and taken from the collected statistics
+    /// for stored relations, which are not checked if they have none.
+    pub(crate) fn check_memory_budget(
+        &self,
+        algo_impl: &dyn AlgoImpl,
+        budget: usize,
+        tx: &SessionTx,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+    ) -> Result<()> {
+        let rows = match self.rule_args.first() {
+            None => return Ok(()),
+            Some(MagicAlgoRuleArg::InMem { name, .. }) => {
+                stores.get(name).map(|store| store.num_tuples())
+            }
+            Some(MagicAlgoRuleArg::Stored { name, .. }) => {
+                tx.get_statistics(name)?.map(|stats| stats.rows)
+            }
+        };
+        if let Some(rows) = rows {
+            if let Some(needed) = algo_impl.memory_estimate(self, rows) {
+                ensure!(
+                    needed <= budget,
+                    MemoryBudgetExceeded {
+                        name: self.algo.name.to_string(),
+                        rows,
+                        needed,
+                        budget,
+                        span: self.span,
+                    }
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Error, Diagnostic)]
@@ -95,46 +201,277 @@ pub(crate) struct CannotDetermineArity(
 #[derive(Clone, Debug)]
 pub(crate) struct AlgoHandle {
     pub(crate) name: Symbol,
+    /// The custom algorithm of the database going by the name, if it is not a builtin
+    pub(crate) custom: Option<Arc<dyn CustomAlgo>>,
 }
 
 impl AlgoHandle {
     pub(crate) fn new(name: &str, span: SourceSpan) -> Self {
         AlgoHandle {
             name: Symbol::new(name, span),
+            custom: None,
+        }
+    }
+
+    /// The handle for `name`, looked up among the custom algorithms of the database
+    /// unless it is a builtin.
+    pub(crate) fn resolve(name: &str, span: SourceSpan, custom_algos: &CustomAlgos) -> Self {
+        AlgoHandle {
+            name: Symbol::new(name, span),
+            custom: custom_algos.get(name).cloned(),
         }
     }
 
     pub(crate) fn get_impl(&self) -> Result<Box<dyn AlgoImpl>> {
-        Ok(match &self.name.name as &str {
-            "ClusteringCoefficients" => Box::new(ClusteringCoefficients),
-            "DegreeCentrality" => Box::new(DegreeCentrality),
-            "ClosenessCentrality" => Box::new(ClosenessCentrality),
-            "BetweennessCentrality" => Box::new(BetweennessCentrality),
-            "DepthFirstSearch" | "DFS" => Box::new(Dfs),
-            "BreadthFirstSearch" | "BFS" => Box::new(Bfs),
-            "ShortestPathDijkstra" => Box::new(ShortestPathDijkstra),
-            "ShortestPathAStar" => Box::new(ShortestPathAStar),
-            "KShortestPathYen" => Box::new(KShortestPathYen),
-            "MinimumSpanningTreePrim" => Box::new(MinimumSpanningTreePrim),
-            "MinimumSpanningForestKruskal" => Box::new(MinimumSpanningForestKruskal),
-            "TopSort" => Box::new(TopSort),
-            "ConnectedComponents" => Box::new(StronglyConnectedComponent::new(false)),
-            "StronglyConnectedComponents" | "SCC" => {
-                Box::new(StronglyConnectedComponent::new(true))
-            }
-            "PageRank" => Box::new(PageRank),
-            "CommunityDetectionLouvain" => Box::new(CommunityDetectionLouvain),
-            "LabelPropagation" => Box::new(LabelPropagation),
-            "RandomWalk" => Box::new(RandomWalk),
-            "ReorderSort" => Box::new(ReorderSort),
-            "JsonReader" => Box::new(JsonReader),
-            "CsvReader" => Box::new(CsvReader),
-            "Constant" => Box::new(Constant),
-            name => bail!(AlgoNotFoundError(name.to_string(), self.name.span)),
-        })
+        let name = &self.name.name as &str;
+        if let Some(builtin) = BUILTIN_ALGOS.iter().find(|a| a.names.contains(&name)) {
+            return Ok((builtin.make)());
+        }
+        match &self.custom {
+            Some(custom) => Ok(Box::new(CustomAlgoImpl(custom.clone()))),
+            None => bail!(AlgoNotFoundError(name.to_string(), self.name.span)),
+        }
     }
 }
 
+/// A builtin fixed rule, with the names it can be applied by and the options it reads.
+pub(crate) struct BuiltinAlgo {
+    pub(crate) names: &'static [&'static str],
+    pub(crate) options: &'static [&'static str],
+    pub(crate) make: fn() -> Box<dyn AlgoImpl>,
+}
+
+impl BuiltinAlgo {
+    /// The arity when applied with no options, if it does not depend on them.
+    pub(crate) fn default_arity(&self) -> Option<usize> {
+        (self.make)()
+            .arity(&Default::default(), &[], SourceSpan(0, 0))
+            .ok()
+    }
+}
+
+pub(crate) const BUILTIN_ALGOS: &[BuiltinAlgo] = &[
+    BuiltinAlgo {
+        names: &["ClusteringCoefficients"],
+        options: &[],
+        make: || Box::new(ClusteringCoefficients),
+    },
+    BuiltinAlgo {
+        names: &["TriangleCount"],
+        options: &[],
+        make: || Box::new(TriangleCount),
+    },
+    BuiltinAlgo {
+        names: &["DegreeCentrality"],
+        options: &[],
+        make: || Box::new(DegreeCentrality),
+    },
+    BuiltinAlgo {
+        names: &["ClosenessCentrality"],
+        options: &["undirected"],
+        make: || Box::new(ClosenessCentrality),
+    },
+    BuiltinAlgo {
+        names: &["BetweennessCentrality"],
+        options: &["undirected"],
+        make: || Box::new(BetweennessCentrality),
+    },
+    BuiltinAlgo {
+        names: &["Eccentricity"],
+        options: &["undirected", "summary", "samples", "seed"],
+        make: || Box::new(Eccentricity),
+    },
+    BuiltinAlgo {
+        names: &["MaximumMatching"],
+        options: &[],
+        make: || Box::new(MaximumMatching),
+    },
+    BuiltinAlgo {
+        names: &["DepthFirstSearch", "DFS"],
+        options: &["condition", "limit"],
+        make: || Box::new(Dfs),
+    },
+    BuiltinAlgo {
+        names: &["BreadthFirstSearch", "BFS"],
+        options: &["condition", "limit"],
+        make: || Box::new(Bfs),
+    },
+    BuiltinAlgo {
+        names: &["ShortestPathDijkstra"],
+        options: &["undirected", "keep_ties"],
+        make: || Box::new(ShortestPathDijkstra),
+    },
+    BuiltinAlgo {
+        names: &["ShortestPathAStar"],
+        options: &["heuristic", "radius", "validate_heuristic"],
+        make: || Box::new(ShortestPathAStar),
+    },
+    BuiltinAlgo {
+        names: &["ShortestPathBellmanFord", "BellmanFord"],
+        options: &["undirected", "negative_cycle"],
+        make: || Box::new(ShortestPathBellmanFord),
+    },
+    BuiltinAlgo {
+        names: &["FloydWarshall"],
+        options: &["undirected", "max_nodes"],
+        make: || Box::new(FloydWarshall),
+    },
+    BuiltinAlgo {
+        names: &["KShortestPathYen"],
+        options: &["k", "undirected", "tolerance", "max_cost"],
+        make: || Box::new(KShortestPathYen),
+    },
+    BuiltinAlgo {
+        names: &["MinimumSpanningTreePrim"],
+        options: &[],
+        make: || Box::new(MinimumSpanningTreePrim),
+    },
+    BuiltinAlgo {
+        names: &["MinimumSpanningForestKruskal"],
+        options: &[],
+        make: || Box::new(MinimumSpanningForestKruskal),
+    },
+    BuiltinAlgo {
+        names: &["TopSort"],
+        options: &[],
+        make: || Box::new(TopSort),
+    },
+    BuiltinAlgo {
+        names: &["ConnectedComponents"],
+        options: &[],
+        make: || Box::new(StronglyConnectedComponent::new(false)),
+    },
+    BuiltinAlgo {
+        names: &["StronglyConnectedComponents", "SCC"],
+        options: &[],
+        make: || Box::new(StronglyConnectedComponent::new(true)),
+    },
+    BuiltinAlgo {
+        names: &["PageRank"],
+        options: &[
+            "undirected",
+            "theta",
+            "epsilon",
+            "iterations",
+            "checkpoint_every",
+            "resume",
+        ],
+        make: || Box::new(PageRank),
+    },
+    BuiltinAlgo {
+        names: &["PersonalizedPageRank"],
+        options: &[
+            "undirected",
+            "theta",
+            "epsilon",
+            "iterations",
+            "checkpoint_every",
+            "resume",
+        ],
+        make: || Box::new(PersonalizedPageRank),
+    },
+    BuiltinAlgo {
+        names: &["IncrementalPageRank"],
+        options: &["undirected", "theta", "epsilon", "iterations"],
+        make: || Box::new(IncrementalPageRank),
+    },
+    BuiltinAlgo {
+        names: &["CommunityDetectionLouvain"],
+        options: &[
+            "undirected",
+            "max_iter",
+            "delta",
+            "keep_depth",
+            "checkpoint_every",
+            "resume",
+        ],
+        make: || Box::new(CommunityDetectionLouvain),
+    },
+    BuiltinAlgo {
+        names: &["LabelPropagation"],
+        options: &["undirected", "max_iter"],
+        make: || Box::new(LabelPropagation),
+    },
+    BuiltinAlgo {
+        names: &["RandomWalk"],
+        options: &["steps", "weight", "iterations", "restart", "seed"],
+        make: || Box::new(RandomWalk),
+    },
+    BuiltinAlgo {
+        names: &["Node2Vec"],
+        options: &[
+            "walk_length",
+            "walks_per_node",
+            "p",
+            "q",
+            "undirected",
+            "seed",
+        ],
+        make: || Box::new(Node2Vec),
+    },
+    BuiltinAlgo {
+        names: &["CascadeSimulation"],
+        options: &[
+            "model",
+            "probability",
+            "threshold",
+            "rounds",
+            "seed",
+            "undirected",
+        ],
+        make: || Box::new(CascadeSimulation),
+    },
+    BuiltinAlgo {
+        names: &["ReorderSort"],
+        options: &["out", "sort_by", "descending", "break_ties", "skip", "take"],
+        make: || Box::new(ReorderSort),
+    },
+    BuiltinAlgo {
+        names: &["JsonReader"],
+        options: &[
+            "url",
+            "fields",
+            "json_lines",
+            "null_if_absent",
+            "prepend_index",
+        ],
+        make: || Box::new(JsonReader),
+    },
+    BuiltinAlgo {
+        names: &["CsvReader"],
+        options: &["url", "types", "delimiter", "prepend_index", "has_headers"],
+        make: || Box::new(CsvReader),
+    },
+    BuiltinAlgo {
+        names: &["GraphReader"],
+        options: &[
+            "url",
+            "format",
+            "output",
+            "weighted",
+            "id_type",
+            "delimiter",
+            "weight_key",
+        ],
+        make: || Box::new(GraphReader),
+    },
+    BuiltinAlgo {
+        names: &["GraphDiff"],
+        options: &[],
+        make: || Box::new(GraphDiff),
+    },
+    BuiltinAlgo {
+        names: &["VectorSearch"],
+        options: &["query", "k", "ef"],
+        make: || Box::new(VectorSearch),
+    },
+    BuiltinAlgo {
+        names: &["Constant"],
+        options: &["data"],
+        make: || Box::new(Constant),
+    },
+];
+
 #[derive(Error, Diagnostic, Debug)]
 #[error("The relation cannot be interpreted as an edge")]
 #[diagnostic(code(algo::not_an_edge))]
@@ -156,14 +493,6 @@ struct BadEdgeWeightError(DataValue, #[label] SourceSpan);
 #[diagnostic(code(algo::rule_not_found))]
 struct RuleNotFoundError(String, #[label] SourceSpan);
 
-#[derive(Error, Diagnostic, Debug)]
-#[error("Invalid reverse scanning of triples")]
-#[diagnostic(code(algo::invalid_reverse_triple_scan))]
-#[diagnostic(help(
-    "Inverse scanning of triples requires the type to be 'ref', or the value be indexed"
-))]
-struct InvalidInverseTripleUse(String, #[label] SourceSpan);
-
 #[derive(Error, Diagnostic, Debug)]
 #[error("Required node with key {missing:?} not found")]
 #[diagnostic(code(algo::node_with_key_not_found))]
@@ -330,10 +659,13 @@ impl MagicAlgoRuleArg {
                 let t = Tuple(vec![prefix.clone()]);
                 Box::new(store.scan_prefix(&t))
             }
-            MagicAlgoRuleArg::Stored { name, .. } => {
+            MagicAlgoRuleArg::Stored { name, valid_at, .. } => {
                 let relation = tx.get_relation(name, false)?;
                 let t = Tuple(vec![prefix.clone()]);
-                Box::new(relation.scan_prefix(tx, &t))
+                Box::new(match valid_at {
+                    None => Left(relation.scan_prefix(tx, &t)),
+                    Some(valid_at) => Right(relation.scan_prefix_as_of(tx, &t, *valid_at)),
+                })
             }
         })
     }
@@ -367,9 +699,12 @@ impl MagicAlgoRuleArg {
                 })?;
                 Box::new(store.scan_all())
             }
-            MagicAlgoRuleArg::Stored { name, .. } => {
+            MagicAlgoRuleArg::Stored { name, valid_at, .. } => {
                 let relation = tx.get_relation(name, false)?;
-                Box::new(relation.scan_all(tx))
+                Box::new(match valid_at {
+                    None => Left(relation.scan_all(tx)),
+                    Some(valid_at) => Right(relation.scan_all_as_of(tx, *valid_at)),
+                })
             }
         })
     }
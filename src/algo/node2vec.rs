@@ -0,0 +1,131 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use miette::{ensure, Result};
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use rayon::prelude::*;
+use smartstring::{LazyCompact, SmartString};
+
+use crate::algo::{graph_memory_estimate, AlgoImpl};
+use crate::data::expr::Expr;
+use crate::data::program::{MagicAlgoApply, MagicSymbol, WrongAlgoOptionError};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+/// Biased random walks in the manner of node2vec, as a corpus for training node embeddings.
+/// Every node starts `walks_per_node` walks, and each step from a node reached from `prev`
+/// goes back to `prev` with weight `1/p`, to a neighbour of `prev` with weight 1, and further
+/// away with weight `1/q`, multiplied by the weight of the edge. The walks are produced as
+/// rows of the walk id, the position in the walk and the node.
+pub(crate) struct Node2Vec;
+
+impl AlgoImpl for Node2Vec {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+        let undirected = algo.bool_option("undirected", Some(false))?;
+        let walk_length = algo.pos_integer_option("walk_length", Some(80))?;
+        let walks_per_node = algo.pos_integer_option("walks_per_node", Some(10))?;
+        let p = positive_float_option(algo, "p")?;
+        let q = positive_float_option(algo, "q")?;
+        let seed = if algo.options.contains_key("seed") {
+            algo.non_neg_integer_option("seed", None)? as u64
+        } else {
+            thread_rng().gen()
+        };
+
+        let (graph, indices, _, _) =
+            edges.convert_edge_to_weighted_graph(undirected, false, tx, stores)?;
+        let neighbours: Vec<BTreeSet<usize>> = graph
+            .iter()
+            .map(|edges| edges.iter().map(|(to, _)| *to).collect())
+            .collect();
+        let n_nodes = graph.len();
+
+        let walks: Vec<Vec<usize>> = (0..n_nodes * walks_per_node)
+            .into_par_iter()
+            .map(|walk_id| -> Result<Vec<usize>> {
+                let mut rng = StdRng::seed_from_u64(seed.wrapping_add(walk_id as u64));
+                let mut walk = vec![walk_id % n_nodes];
+                while walk.len() < walk_length {
+                    let cur = walk[walk.len() - 1];
+                    let prev = walk.len().checked_sub(2).map(|i| walk[i]);
+                    let weights = graph[cur].iter().map(|(to, weight)| match prev {
+                        None => *weight,
+                        Some(prev) if prev == *to => weight / p,
+                        Some(prev) if neighbours[prev].contains(to) => *weight,
+                        Some(_) => weight / q,
+                    });
+                    // a walk stops at nodes without edges, or with edges of zero weight only
+                    match WeightedIndex::new(weights) {
+                        Ok(dist) => walk.push(graph[cur][dist.sample(&mut rng)].0),
+                        Err(_) => break,
+                    }
+                }
+                poison.check()?;
+                Ok(walk)
+            })
+            .collect::<Result<_>>()?;
+
+        for (walk_id, walk) in walks.into_iter().enumerate() {
+            for (pos, node) in walk.into_iter().enumerate() {
+                out.put(
+                    Tuple(vec![
+                        DataValue::from(walk_id as i64),
+                        DataValue::from(pos as i64),
+                        indices[node].clone(),
+                    ]),
+                    0,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        Some(graph_memory_estimate(rows, undirected))
+    }
+}
+
+fn positive_float_option(algo: &MagicAlgoApply, name: &str) -> Result<f64> {
+    let f = algo.non_neg_float_option(name, Some(1.))?;
+    ensure!(
+        f > 0.,
+        WrongAlgoOptionError {
+            name: name.to_string(),
+            span: algo
+                .options
+                .get(name)
+                .map(|v| v.span())
+                .unwrap_or(algo.span),
+            algo_name: algo.algo.name.to_string(),
+            help: "a positive number is required".to_string(),
+        }
+    );
+    Ok(f)
+}
@@ -2,15 +2,20 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::Hash;
 use std::mem;
 
 use approx::AbsDiffEq;
-use miette::Result;
+use itertools::Itertools;
+use miette::{Diagnostic, Result};
 use nalgebra::{Dynamic, OMatrix, U1};
+use rayon::prelude::*;
 use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
 
-use crate::algo::AlgoImpl;
+use crate::algo::checkpoint::{hash_weighted_graph, Checkpoint};
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -38,13 +43,18 @@ impl AlgoImpl for PageRank {
         let epsilon = algo.unit_interval_option("epsilon", Some(0.05))? as f32;
         let iterations = algo.pos_integer_option("iterations", Some(20))?;
         let (graph, indices, _) = edges.convert_edge_to_graph(undirected, tx, stores)?;
-        let res = pagerank(&graph, theta, epsilon, iterations, poison)?;
+        let checkpoint = Checkpoint::new(algo, |hasher| {
+            indices.hash(hasher);
+            graph.hash(hasher);
+        })?;
+        let res = pagerank(&graph, theta, epsilon, iterations, &checkpoint, poison)?;
         for (idx, score) in res.iter().enumerate() {
             out.put(
                 Tuple(vec![indices[idx].clone(), DataValue::from(*score as f64)]),
                 0,
             );
         }
+        checkpoint.finish();
         Ok(())
     }
 
@@ -56,6 +66,18 @@ impl AlgoImpl for PageRank {
     ) -> Result<usize> {
         Ok(2)
     }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        // the distinct targets of the nodes are kept besides the graph, and three vectors
+        // of scores
+        let scores = rows.saturating_mul(3 * mem::size_of::<f32>());
+        Some(
+            graph_memory_estimate(rows, undirected)
+                .saturating_mul(2)
+                .saturating_add(scores),
+        )
+    }
 }
 
 fn pagerank(
@@ -63,31 +85,41 @@ fn pagerank(
     theta: f32,
     epsilon: f32,
     iterations: usize,
+    checkpoint: &Checkpoint,
     poison: Poison,
 ) -> Result<OMatrix<f32, Dynamic, U1>> {
-    let init_val = (1. - theta) / edges.len() as f32;
-    let mut g_mat = OMatrix::<f32, Dynamic, Dynamic>::repeat(edges.len(), edges.len(), init_val);
     let n = edges.len();
-    let empty_score = theta / n as f32;
-    for (node, to_nodes) in edges.iter().enumerate() {
-        let l = to_nodes.len();
-        if l == 0 {
-            for to_node in 0..n {
-                g_mat[(node, to_node)] = empty_score;
-            }
-        } else {
-            let score = theta / n as f32;
-            for to_node in to_nodes {
-                g_mat[(node, *to_node)] = score;
-            }
+    let init_val = (1. - theta) / n as f32;
+    let score = theta / n as f32;
+    // The transition matrix holds `score` at the edges and throughout the rows of nodes
+    // without edges, and `init_val` everywhere else. It is dense, so it is applied to the
+    // scores without being built, from the distinct targets of each node.
+    let targets = edges
+        .iter()
+        .map(|to_nodes| to_nodes.iter().copied().sorted().dedup().collect_vec())
+        .collect_vec();
+    let (done, mut pi_vec) = match checkpoint.load::<Vec<f32>>()? {
+        Some((done, saved)) if saved.len() == n => {
+            (done, OMatrix::<f32, Dynamic, U1>::from_vec(saved))
         }
-    }
-    let mut pi_vec = OMatrix::<f32, Dynamic, U1>::repeat(edges.len(), 1.);
+        _ => (0, OMatrix::<f32, Dynamic, U1>::repeat(edges.len(), 1.)),
+    };
     let scale_target = (n as f32).sqrt();
     let mut last_pi_vec = pi_vec.clone();
-    for _ in 0..iterations {
+    for i in done..iterations {
         mem::swap(&mut pi_vec, &mut last_pi_vec);
-        pi_vec = g_mat.tr_mul(&last_pi_vec);
+        let base: f32 = targets
+            .iter()
+            .zip(last_pi_vec.iter())
+            .map(|(to_nodes, v)| if to_nodes.is_empty() { score } else { init_val } * v)
+            .sum();
+        let mut next = vec![base; n];
+        for (to_nodes, v) in targets.iter().zip(last_pi_vec.iter()) {
+            for to_node in to_nodes {
+                next[*to_node] += (score - init_val) * v;
+            }
+        }
+        pi_vec = OMatrix::<f32, Dynamic, U1>::from_vec(next);
         pi_vec.normalize_mut();
         let f = pi_vec.norm() / scale_target;
         pi_vec.unscale_mut(f);
@@ -96,6 +128,296 @@ fn pagerank(
             break;
         }
         poison.check()?;
+        checkpoint.save(i + 1, &pi_vec.as_slice())?;
     }
     Ok(pi_vec)
 }
+
+pub(crate) struct PersonalizedPageRank;
+
+impl AlgoImpl for PersonalizedPageRank {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+        let sources = algo.relation(1)?;
+        let undirected = algo.bool_option("undirected", Some(false))?;
+        let theta = algo.unit_interval_option("theta", Some(0.8))?;
+        let epsilon = algo.unit_interval_option("epsilon", Some(0.0001))?;
+        let iterations = algo.pos_integer_option("iterations", Some(20))?;
+        let (graph, indices, inv_indices, _) =
+            edges.convert_edge_to_weighted_graph(undirected, false, tx, stores)?;
+
+        let mut personalization = vec![0.; graph.len()];
+        let mut n_sources = 0;
+        for tuple in sources.iter(tx, stores)? {
+            let tuple = tuple?;
+            if let Some(idx) = inv_indices.get(&tuple.0[0]) {
+                if personalization[*idx] == 0. {
+                    personalization[*idx] = 1.;
+                    n_sources += 1;
+                }
+            }
+        }
+        if n_sources == 0 {
+            return Ok(());
+        }
+        for p in personalization.iter_mut() {
+            *p /= n_sources as f64;
+        }
+
+        let checkpoint = Checkpoint::new(algo, |hasher| {
+            indices.hash(hasher);
+            hash_weighted_graph(&graph, hasher);
+            for p in &personalization {
+                p.to_bits().hash(hasher);
+            }
+        })?;
+        let res = personalized_pagerank(
+            &graph,
+            &personalization,
+            theta,
+            epsilon,
+            iterations,
+            &checkpoint,
+            poison,
+        )?;
+        for (idx, score) in res.into_iter().enumerate() {
+            out.put(Tuple(vec![indices[idx].clone(), DataValue::from(score)]), 0);
+        }
+        checkpoint.finish();
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        // the incoming edges are kept besides the graph, and three vectors of scores
+        let scores = rows.saturating_mul(3 * mem::size_of::<f64>());
+        Some(
+            graph_memory_estimate(rows, undirected)
+                .saturating_mul(2)
+                .saturating_add(scores),
+        )
+    }
+}
+
+/// Power iteration where the random surfer teleports, and leaves nodes without outgoing
+/// edges, to the source nodes according to `personalization`, which sums to one.
+/// Iteration stops when the scores change by less than `epsilon` in total.
+fn personalized_pagerank(
+    edges: &[Vec<(usize, f64)>],
+    personalization: &[f64],
+    theta: f64,
+    epsilon: f64,
+    iterations: usize,
+    checkpoint: &Checkpoint,
+    poison: Poison,
+) -> Result<Vec<f64>> {
+    let n = edges.len();
+    let mut incoming: Vec<Vec<(usize, f64)>> = vec![vec![]; n];
+    let mut dangling = vec![];
+    for (from, to_nodes) in edges.iter().enumerate() {
+        let total: f64 = to_nodes.iter().map(|(_, w)| *w).sum();
+        if total > 0. {
+            for (to, w) in to_nodes {
+                incoming[*to].push((from, *w / total));
+            }
+        } else {
+            dangling.push(from);
+        }
+    }
+
+    let (done, mut scores) = match checkpoint.load::<Vec<f64>>()? {
+        Some((done, saved)) if saved.len() == n => (done, saved),
+        _ => (0, personalization.to_vec()),
+    };
+    for i in done..iterations {
+        let dangling_mass: f64 = dangling.iter().map(|node| scores[*node]).sum();
+        let next: Vec<f64> = (0..n)
+            .into_par_iter()
+            .map(|node| {
+                let propagated: f64 = incoming[node]
+                    .iter()
+                    .map(|(from, p)| scores[*from] * p)
+                    .sum();
+                (1. - theta) * personalization[node]
+                    + theta * (propagated + dangling_mass * personalization[node])
+            })
+            .collect();
+        let delta: f64 = next
+            .iter()
+            .zip(scores.iter())
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        scores = next;
+        if delta < epsilon {
+            break;
+        }
+        poison.check()?;
+        checkpoint.save(i + 1, &scores)?;
+    }
+    Ok(scores)
+}
+
+/// PageRank kept up to date as the graph changes. Starting from the scores of a previous run,
+/// only the scores of the nodes touched by the edges added or removed since, and of the nodes
+/// downstream of them, are recomputed, and only for as long as they keep changing by more
+/// than `epsilon`. Without previous scores, every node is recomputed, which is a full run.
+/// Scores solve the linear system `x = (1 - theta) + theta * P^T x`, where nodes without
+/// outgoing edges pass on no score, so that each node only depends on its incoming edges.
+/// They are proportional to the usual scores where such nodes teleport uniformly.
+pub(crate) struct IncrementalPageRank;
+
+impl AlgoImpl for IncrementalPageRank {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The previous score {0:?} is not a number")]
+        #[diagnostic(code(algo::bad_previous_score))]
+        struct BadPreviousScore(DataValue, #[label] SourceSpan);
+
+        let edges = algo.relation(0)?;
+        let previous = algo.relation_with_min_len(1, 2, tx, stores)?;
+        let undirected = algo.bool_option("undirected", Some(false))?;
+        let theta = algo.unit_interval_option("theta", Some(0.8))?;
+        let epsilon = algo.unit_interval_option("epsilon", Some(0.0001))?;
+        let iterations = algo.pos_integer_option("iterations", Some(20))?;
+        let (graph, indices, inv_indices) = edges.convert_edge_to_graph(undirected, tx, stores)?;
+
+        let mut scores = vec![None; graph.len()];
+        for tuple in previous.iter(tx, stores)? {
+            let tuple = tuple?;
+            if let Some(idx) = inv_indices.get(&tuple.0[0]) {
+                let score = tuple.0[1]
+                    .get_float()
+                    .ok_or_else(|| BadPreviousScore(tuple.0[1].clone(), previous.span()))?;
+                scores[*idx] = Some(score);
+            }
+        }
+        // changes start from new nodes and the endpoints of the edges in the relations after
+        // the first two, and reach the targets of their outgoing edges at once, as the share
+        // of score each target gets depends on the number of outgoing edges
+        let mut touched = scores
+            .iter()
+            .positions(|score| score.is_none())
+            .collect_vec();
+        for rel in algo.rule_args.iter().skip(2) {
+            for tuple in rel.iter(tx, stores)? {
+                let tuple = tuple?;
+                for node in tuple.0.iter().take(2) {
+                    if let Some(idx) = inv_indices.get(node) {
+                        touched.push(*idx);
+                    }
+                }
+            }
+        }
+        let frontier = touched
+            .iter()
+            .flat_map(|node| std::iter::once(node).chain(graph[*node].iter()))
+            .copied()
+            .collect();
+        let mut scores = scores
+            .into_iter()
+            .map(|score| score.unwrap_or(1.))
+            .collect_vec();
+        incremental_pagerank(
+            &graph,
+            &mut scores,
+            frontier,
+            theta,
+            epsilon,
+            iterations,
+            poison,
+        )?;
+        for (idx, score) in scores.into_iter().enumerate() {
+            out.put(Tuple(vec![indices[idx].clone(), DataValue::from(score)]), 0);
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(2)
+    }
+
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        // the incoming edges are kept besides the graph
+        let scores = rows.saturating_mul(2 * mem::size_of::<f64>());
+        Some(
+            graph_memory_estimate(rows, undirected)
+                .saturating_mul(2)
+                .saturating_add(scores),
+        )
+    }
+}
+
+/// Recomputes the scores of the nodes in `frontier`, then those of the targets of the nodes
+/// whose scores changed by more than `epsilon`, and so on, for at most `iterations` rounds.
+fn incremental_pagerank(
+    edges: &[Vec<usize>],
+    scores: &mut [f64],
+    mut frontier: BTreeSet<usize>,
+    theta: f64,
+    epsilon: f64,
+    iterations: usize,
+    poison: Poison,
+) -> Result<()> {
+    let n = edges.len();
+    let mut incoming: Vec<Vec<usize>> = vec![vec![]; n];
+    for (from, to_nodes) in edges.iter().enumerate() {
+        for to in to_nodes {
+            incoming[*to].push(from);
+        }
+    }
+    for _ in 0..iterations {
+        if frontier.is_empty() {
+            break;
+        }
+        let updated: Vec<(usize, f64)> = frontier
+            .par_iter()
+            .map(|node| {
+                let propagated: f64 = incoming[*node]
+                    .iter()
+                    .map(|from| scores[*from] / edges[*from].len() as f64)
+                    .sum();
+                (*node, (1. - theta) + theta * propagated)
+            })
+            .collect();
+        let mut next = BTreeSet::new();
+        for (node, score) in updated {
+            let delta = score - scores[node];
+            scores[node] = score;
+            if delta.abs() > epsilon {
+                next.extend(edges[node].iter().copied());
+            }
+        }
+        frontier = next;
+        poison.check()?;
+    }
+    Ok(())
+}
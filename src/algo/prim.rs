@@ -12,7 +12,7 @@ use priority_queue::PriorityQueue;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -84,6 +84,10 @@ impl AlgoImpl for MinimumSpanningTreePrim {
     ) -> Result<usize> {
         Ok(3)
     }
+
+    fn memory_estimate(&self, _algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        Some(graph_memory_estimate(rows, true))
+    }
 }
 
 fn prim(
@@ -37,6 +37,7 @@ impl AlgoImpl for RandomWalk {
         let starting = algo.relation(2)?;
         let iterations = algo.pos_integer_option("iterations", Some(1))?;
         let steps = algo.pos_integer_option("steps", None)?;
+        let restart = algo.unit_interval_option("restart", Some(0.))?;
 
         let mut maybe_weight = algo.expr_option("weight", None).ok();
         if let Some(weight) = &mut maybe_weight {
@@ -48,7 +49,11 @@ impl AlgoImpl for RandomWalk {
         }
 
         let mut counter = 0i64;
-        let mut rng = thread_rng();
+        let mut rng = if algo.options.contains_key("seed") {
+            StdRng::seed_from_u64(algo.non_neg_integer_option("seed", None)? as u64)
+        } else {
+            StdRng::from_entropy()
+        };
         for start_node in starting.iter(tx, stores)? {
             let start_node = start_node?;
             let start_node_key = &start_node.0[0];
@@ -64,6 +69,11 @@ impl AlgoImpl for RandomWalk {
                 let mut current_tuple = starting_tuple.clone();
                 let mut path = vec![start_node_key.clone()];
                 for _ in 0..steps {
+                    if restart > 0. && rng.gen::<f64>() < restart {
+                        current_tuple = starting_tuple.clone();
+                        path.push(start_node_key.clone());
+                        continue;
+                    }
                     let cur_node_key = &current_tuple.0[0];
                     let candidate_steps: Vec<_> =
                         edges.prefix_iter(cur_node_key, tx, stores)?.try_collect()?;
@@ -99,8 +109,11 @@ impl AlgoImpl for RandomWalk {
                                 })
                             })
                             .try_collect()?;
-                        let dist = WeightedIndex::new(&weights).unwrap();
-                        &candidate_steps[dist.sample(&mut rng)]
+                        // a walk cannot continue if all edges have zero weight
+                        match WeightedIndex::new(&weights) {
+                            Ok(dist) => &candidate_steps[dist.sample(&mut rng)],
+                            Err(_) => break,
+                        }
                     } else {
                         candidate_steps.choose(&mut rng).unwrap()
                     };
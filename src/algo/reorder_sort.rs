@@ -91,8 +91,7 @@ impl AlgoImpl for ReorderSort {
         let mut count = 0usize;
         let mut rank = 0usize;
         let mut last = &DataValue::Bot;
-        let skip = skip as usize;
-        let take_plus_skip = (take as usize).saturating_add(skip);
+        let take_plus_skip = take.saturating_add(skip);
         for val in &buffer {
             let sorter = val.last().unwrap();
 
@@ -2,7 +2,7 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::cmp::{Ordering, Reverse};
+use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet};
 use std::iter;
 
@@ -14,7 +14,7 @@ use rayon::prelude::*;
 use smallvec::{smallvec, SmallVec};
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -68,76 +68,49 @@ impl AlgoImpl for ShortestPathDijkstra {
             }
         };
 
-        if starting_nodes.len() <= 1 {
-            for start in starting_nodes {
-                let res = if let Some(tn) = &termination_nodes {
-                    if tn.len() == 1 {
-                        let single = Some(*tn.iter().next().unwrap());
-                        if keep_ties {
-                            dijkstra_keep_ties(&graph, start, &single, &(), &(), poison.clone())?
-                        } else {
-                            dijkstra(&graph, start, &single, &(), &())
-                        }
-                    } else if keep_ties {
+        // A single search is run from each starting node, settling nodes until all the
+        // termination nodes are reached, so no work is repeated per pair of nodes.
+        let search = |start: usize| -> Result<Vec<(usize, f64, Vec<usize>)>> {
+            Ok(match &termination_nodes {
+                None => dijkstra(&graph, start, &(), &(), &()),
+                Some(tn) if tn.len() == 1 => {
+                    let single = Some(*tn.iter().next().unwrap());
+                    if keep_ties {
+                        dijkstra_keep_ties(&graph, start, &single, &(), &(), poison.clone())?
+                    } else {
+                        dijkstra(&graph, start, &single, &(), &())
+                    }
+                }
+                Some(tn) => {
+                    if keep_ties {
                         dijkstra_keep_ties(&graph, start, tn, &(), &(), poison.clone())?
                     } else {
                         dijkstra(&graph, start, tn, &(), &())
                     }
-                } else {
-                    dijkstra(&graph, start, &(), &(), &())
-                };
-                for (target, cost, path) in res {
-                    let t = vec![
-                        indices[start].clone(),
-                        indices[target].clone(),
-                        DataValue::from(cost),
-                        DataValue::List(path.into_iter().map(|u| indices[u].clone()).collect_vec()),
-                    ];
-                    out.put(Tuple(t), 0)
                 }
-            }
+            })
+        };
+
+        let all_res: Vec<_> = if starting_nodes.len() <= 1 {
+            starting_nodes
+                .into_iter()
+                .map(|start| -> Result<_> { Ok((start, search(start)?)) })
+                .collect::<Result<_>>()?
         } else {
-            let all_res: Vec<_> = starting_nodes
+            starting_nodes
                 .into_par_iter()
-                .map(|start| -> Result<(usize, Vec<(usize, f64, Vec<usize>)>)> {
-                    Ok((
-                        start,
-                        if let Some(tn) = &termination_nodes {
-                            if tn.len() == 1 {
-                                let single = Some(*tn.iter().next().unwrap());
-                                if keep_ties {
-                                    dijkstra_keep_ties(
-                                        &graph,
-                                        start,
-                                        &single,
-                                        &(),
-                                        &(),
-                                        poison.clone(),
-                                    )?
-                                } else {
-                                    dijkstra(&graph, start, &single, &(), &())
-                                }
-                            } else if keep_ties {
-                                dijkstra_keep_ties(&graph, start, tn, &(), &(), poison.clone())?
-                            } else {
-                                dijkstra(&graph, start, tn, &(), &())
-                            }
-                        } else {
-                            dijkstra(&graph, start, &(), &(), &())
-                        },
-                    ))
-                })
-                .collect::<Result<_>>()?;
-            for (start, res) in all_res {
-                for (target, cost, path) in res {
-                    let t = vec![
-                        indices[start].clone(),
-                        indices[target].clone(),
-                        DataValue::from(cost),
-                        DataValue::List(path.into_iter().map(|u| indices[u].clone()).collect_vec()),
-                    ];
-                    out.put(Tuple(t), 0)
-                }
+                .map(|start| -> Result<_> { Ok((start, search(start)?)) })
+                .collect::<Result<_>>()?
+        };
+        for (start, res) in all_res {
+            for (target, cost, path) in res {
+                let t = vec![
+                    indices[start].clone(),
+                    indices[target].clone(),
+                    DataValue::from(cost),
+                    DataValue::List(path.into_iter().map(|u| indices[u].clone()).collect_vec()),
+                ];
+                out.put(Tuple(t), 0)
             }
         }
 
@@ -152,31 +125,13 @@ impl AlgoImpl for ShortestPathDijkstra {
     ) -> Result<usize> {
         Ok(4)
     }
-}
-
-#[derive(PartialEq)]
-struct HeapState {
-    cost: f64,
-    node: usize,
-}
-
-impl PartialOrd for HeapState {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
 
-impl Ord for HeapState {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.cost
-            .total_cmp(&other.cost)
-            .reverse()
-            .then_with(|| self.node.cmp(&other.node))
+    fn memory_estimate(&self, algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        let undirected = algo.bool_option("undirected", Some(false)).unwrap_or(false);
+        Some(graph_memory_estimate(rows, undirected))
     }
 }
 
-impl Eq for HeapState {}
-
 pub(crate) trait ForbiddenEdge {
     fn is_forbidden(&self, src: usize, dst: usize) -> bool;
 }
@@ -282,6 +237,13 @@ pub(crate) fn dijkstra<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal + Clone>(
             continue;
         }
 
+        // the path to a settled node is final, so the search stops as soon as the
+        // last goal is settled, without expanding it
+        goals_remaining.visit(node);
+        if goals_remaining.is_exhausted() {
+            break;
+        }
+
         for (nxt_node, path_weight) in &edges[node] {
             if forbidden_nodes.is_forbidden(*nxt_node) {
                 continue;
@@ -296,11 +258,6 @@ pub(crate) fn dijkstra<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal + Clone>(
                 back_pointers[*nxt_node] = node;
             }
         }
-
-        goals_remaining.visit(node);
-        if goals_remaining.is_exhausted() {
-            break;
-        }
     }
 
     let ret = goals
@@ -340,12 +297,24 @@ pub(crate) fn dijkstra_keep_ties<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal +
     distance[start] = 0.;
     pq.push(start, Reverse(OrderedFloat(0.)));
     let mut goals_remaining = goals.clone();
+    let mut goals_cost = None;
 
     while let Some((node, Reverse(OrderedFloat(cost)))) = pq.pop() {
         if cost > distance[node] {
             continue;
         }
 
+        // nodes at the cost of the last goal settled may still lead to the goals by
+        // zero-weight edges, giving more paths of the same cost, so the search only
+        // stops once the frontier is costlier
+        if matches!(goals_cost, Some(goals_cost) if cost > goals_cost) {
+            break;
+        }
+        goals_remaining.visit(node);
+        if goals_cost.is_none() && goals_remaining.is_exhausted() {
+            goals_cost = Some(cost);
+        }
+
         for (nxt_node, path_weight) in &edges[node] {
             if forbidden_nodes.is_forbidden(*nxt_node) {
                 continue;
@@ -360,16 +329,12 @@ pub(crate) fn dijkstra_keep_ties<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal +
                 back_pointers[*nxt_node].clear();
                 back_pointers[*nxt_node].push(node);
             } else if nxt_cost == distance[*nxt_node] {
-                pq.push_increase(*nxt_node, Reverse(OrderedFloat(nxt_cost)));
+                // the node is either queued at this cost already or settled, and must not
+                // be expanded twice
                 back_pointers[*nxt_node].push(node);
             }
             poison.check()?;
         }
-
-        goals_remaining.visit(node);
-        if goals_remaining.is_exhausted() {
-            break;
-        }
     }
 
     let ret = goals
@@ -395,6 +360,10 @@ pub(crate) fn dijkstra_keep_ties<FE: ForbiddenEdge, FN: ForbiddenNode, G: Goal +
                         let last = chain.last().unwrap();
                         let prevs = &back_pointers[*last];
                         for nxt in prevs {
+                            // cycles of zero-weight edges are not followed
+                            if chain.contains(nxt) {
+                                continue;
+                            }
                             let mut ret = chain.to_vec();
                             ret.push(*nxt);
                             if *nxt == start {
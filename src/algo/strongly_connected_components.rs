@@ -9,7 +9,7 @@ use itertools::Itertools;
 use miette::Result;
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -79,6 +79,10 @@ impl AlgoImpl for StronglyConnectedComponent {
     ) -> Result<usize> {
         Ok(2)
     }
+
+    fn memory_estimate(&self, _algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        Some(graph_memory_estimate(rows, !self.strong))
+    }
 }
 
 pub(crate) struct TarjanScc<'a> {
@@ -114,7 +118,7 @@ impl<'a> TarjanScc<'a> {
             low_map.entry(grp).or_default().push(idx);
         }
 
-        Ok(low_map.into_iter().map(|(_, vs)| vs).collect_vec())
+        Ok(low_map.into_values().collect_vec())
     }
     fn dfs(&mut self, at: usize) {
         self.stack.push(at);
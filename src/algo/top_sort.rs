@@ -7,7 +7,7 @@ use std::collections::BTreeMap;
 use miette::Result;
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -52,6 +52,10 @@ impl AlgoImpl for TopSort {
     ) -> Result<usize> {
         Ok(2)
     }
+
+    fn memory_estimate(&self, _algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        Some(graph_memory_estimate(rows, false))
+    }
 }
 
 pub(crate) fn kahn(graph: &[Vec<usize>], poison: Poison) -> Result<Vec<usize>> {
@@ -70,8 +74,7 @@ pub(crate) fn kahn(graph: &[Vec<usize>], poison: Poison) -> Result<Vec<usize>> {
         }
     }
 
-    while !pending.is_empty() {
-        let removed = pending.pop().unwrap();
+    while let Some(removed) = pending.pop() {
         sorted.push(removed);
         if let Some(edges) = graph.get(removed) {
             for nxt in edges {
@@ -8,7 +8,7 @@ use miette::Result;
 use rayon::prelude::*;
 use smartstring::{LazyCompact, SmartString};
 
-use crate::algo::AlgoImpl;
+use crate::algo::{graph_memory_estimate, AlgoImpl};
 use crate::data::expr::Expr;
 use crate::data::program::{MagicAlgoApply, MagicSymbol};
 use crate::data::symb::Symbol;
@@ -58,6 +58,70 @@ impl AlgoImpl for ClusteringCoefficients {
     ) -> Result<usize> {
         Ok(4)
     }
+
+    fn memory_estimate(&self, _algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        Some(graph_memory_estimate(rows, true))
+    }
+}
+
+/// The number of triangles in the graph, with its global clustering coefficient
+/// (transitivity: the fraction of connected triples that are closed) and the average of
+/// the local clustering coefficients of its nodes, produced as a single row.
+pub(crate) struct TriangleCount;
+
+impl AlgoImpl for TriangleCount {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let edges = algo.relation(0)?;
+        let (graph, _, _) = edges.convert_edge_to_graph(true, tx, stores)?;
+        let graph: Vec<BTreeSet<usize>> =
+            graph.into_iter().map(|e| e.into_iter().collect()).collect();
+        let coefficients = clustering_coefficients(&graph, poison)?;
+        // each triangle is counted once at each of its corners
+        let n_triangles = coefficients.iter().map(|(_, n, _)| n).sum::<usize>() / 3;
+        let n_triples: usize = coefficients
+            .iter()
+            .map(|(_, _, degree)| degree * degree.saturating_sub(1) / 2)
+            .sum();
+        let transitivity = if n_triples == 0 {
+            0.
+        } else {
+            3. * n_triangles as f64 / n_triples as f64
+        };
+        let average = if coefficients.is_empty() {
+            0.
+        } else {
+            coefficients.iter().map(|(cc, _, _)| cc).sum::<f64>() / coefficients.len() as f64
+        };
+        out.put(
+            Tuple(vec![
+                DataValue::from(n_triangles as i64),
+                DataValue::from(transitivity),
+                DataValue::from(average),
+            ]),
+            0,
+        );
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        _rule_head: &[Symbol],
+        _span: SourceSpan,
+    ) -> Result<usize> {
+        Ok(3)
+    }
+
+    fn memory_estimate(&self, _algo: &MagicAlgoApply, rows: usize) -> Option<usize> {
+        Some(graph_memory_estimate(rows, true))
+    }
 }
 
 fn clustering_coefficients(
@@ -0,0 +1,111 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::algo::{AlgoImpl, CannotDetermineArity};
+use crate::data::expr::Expr;
+use crate::data::functions::get_vector;
+use crate::data::program::{MagicAlgoApply, MagicAlgoRuleArg, MagicSymbol};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::Poison;
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::transact::SessionTx;
+
+/// Approximate nearest neighbours of a vector, using the vector index of a stored relation.
+/// Produces the keys of the nearest rows followed by their distances to the query.
+pub(crate) struct VectorSearch;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The input to 'VectorSearch' must be a stored relation with a vector index")]
+#[diagnostic(code(algo::no_vector_index))]
+#[diagnostic(help("Create the index with '::relation vector_index <relation> on <column>'"))]
+struct NoVectorIndex(#[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'VectorSearch' cannot search past versions of a relation")]
+#[diagnostic(code(algo::vector_search_as_of))]
+#[diagnostic(help("The vector index only covers the current rows"))]
+struct VectorSearchAsOf(#[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query vector has dimension {0}, but the index is over vectors of dimension {1}")]
+#[diagnostic(code(algo::vector_dim_mismatch))]
+struct QueryDimMismatch(usize, usize, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'VectorSearch' over '{0}' produces {1} columns, but the rule head has {2}")]
+#[diagnostic(code(algo::vector_search_arity))]
+#[diagnostic(help("The output consists of the keys of the relation followed by the distance"))]
+struct VectorSearchArityMismatch(String, usize, usize, #[label] SourceSpan);
+
+impl AlgoImpl for VectorSearch {
+    fn run(
+        &mut self,
+        tx: &SessionTx,
+        algo: &MagicAlgoApply,
+        _stores: &BTreeMap<MagicSymbol, InMemRelation>,
+        out: &InMemRelation,
+        poison: Poison,
+    ) -> Result<()> {
+        let rel = algo.relation(0)?;
+        let handle = match rel {
+            MagicAlgoRuleArg::Stored { name, valid_at, .. } => {
+                ensure!(valid_at.is_none(), VectorSearchAsOf(rel.span()));
+                tx.get_relation(name, false)?
+            }
+            MagicAlgoRuleArg::InMem { .. } => bail!(NoVectorIndex(rel.span())),
+        };
+        let index = match &handle.vector_index {
+            Some(index) => index,
+            None => bail!(NoVectorIndex(rel.span())),
+        };
+        let n_keys = handle.metadata.keys.len();
+        ensure!(
+            algo.arity == n_keys + 1,
+            VectorSearchArityMismatch(handle.name.to_string(), n_keys + 1, algo.arity, algo.span)
+        );
+
+        let query = algo.expr_option("query", None)?;
+        let query_span = query.span();
+        let q = get_vector(&query.eval_to_const()?, "VectorSearch")?;
+        ensure!(
+            q.len() == index.dim,
+            QueryDimMismatch(q.len(), index.dim, query_span)
+        );
+        let k = algo.pos_integer_option("k", Some(10))?;
+        let ef = algo.pos_integer_option("ef", Some(50))?;
+
+        for (dist, mut keys) in tx.hnsw_search(&handle, index, &q, k, ef)? {
+            keys.push(DataValue::from(dist));
+            out.put(Tuple(keys), 0);
+            poison.check()?;
+        }
+        Ok(())
+    }
+
+    fn arity(
+        &self,
+        _options: &BTreeMap<SmartString<LazyCompact>, Expr>,
+        rule_head: &[Symbol],
+        span: SourceSpan,
+    ) -> Result<usize> {
+        ensure!(
+            !rule_head.is_empty(),
+            CannotDetermineArity(
+                "VectorSearch".to_string(),
+                "the rule head is empty".to_string(),
+                span
+            )
+        );
+        Ok(rule_head.len())
+    }
+}
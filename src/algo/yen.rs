@@ -2,11 +2,15 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
 use miette::Result;
+use ordered_float::OrderedFloat;
+use priority_queue::PriorityQueue;
 use rayon::prelude::*;
+use rayon::ThreadPool;
 use smartstring::{LazyCompact, SmartString};
 
 use crate::algo::shortest_path_dijkstra::dijkstra;
@@ -36,10 +40,30 @@ impl AlgoImpl for KShortestPathYen {
         let starting = algo.relation(1)?;
         let termination = algo.relation(2)?;
         let undirected = algo.bool_option("undirected", Some(false))?;
-        let k = algo.pos_integer_option("k", None)?;
+        let tolerance = if algo.options.contains_key("tolerance") {
+            Some(algo.non_neg_float_option("tolerance", None)?)
+        } else {
+            None
+        };
+        let max_cost = if algo.options.contains_key("max_cost") {
+            Some(algo.non_neg_float_option("max_cost", None)?)
+        } else {
+            None
+        };
+        // with a cost cutoff, the number of paths need not be limited
+        let k = if tolerance.is_some() || max_cost.is_some() {
+            algo.pos_integer_option("k", Some(usize::MAX))?
+        } else {
+            algo.pos_integer_option("k", None)?
+        };
+        let cutoff = Cutoff {
+            tolerance,
+            max_cost,
+        };
 
         let (graph, indices, inv_indices, _) =
             edges.convert_edge_to_weighted_graph(undirected, false, tx, stores)?;
+        let pool = tx.eval.algo_pool.as_deref();
 
         let mut starting_nodes = BTreeSet::new();
         for tuple in starting.iter(tx, stores)? {
@@ -61,7 +85,7 @@ impl AlgoImpl for KShortestPathYen {
             for start in starting_nodes {
                 for goal in &termination_nodes {
                     for (cost, path) in
-                        k_shortest_path_yen(k as usize, &graph, start, *goal, poison.clone())?
+                        k_shortest_path_yen(k, &cutoff, &graph, start, *goal, pool, poison.clone())?
                     {
                         let t = vec![
                             indices[start].clone(),
@@ -76,20 +100,30 @@ impl AlgoImpl for KShortestPathYen {
                 }
             }
         } else {
-            let res_all: Vec<_> = starting_nodes
-                .iter()
-                .flat_map(|start| termination_nodes.iter().map(|goal| (*start, *goal)))
-                .par_bridge()
-                .map(
-                    |(start, goal)| -> Result<(usize, usize, Vec<(f64, Vec<usize>)>)> {
-                        Ok((
-                            start,
-                            goal,
-                            k_shortest_path_yen(k as usize, &graph, start, goal, poison.clone())?,
-                        ))
-                    },
-                )
-                .collect::<Result<_>>()?;
+            let res_all: Vec<_> = on_pool(pool, || {
+                starting_nodes
+                    .iter()
+                    .flat_map(|start| termination_nodes.iter().map(|goal| (*start, *goal)))
+                    .par_bridge()
+                    .map(
+                        |(start, goal)| -> Result<(usize, usize, Vec<(f64, Vec<usize>)>)> {
+                            Ok((
+                                start,
+                                goal,
+                                k_shortest_path_yen(
+                                    k,
+                                    &cutoff,
+                                    &graph,
+                                    start,
+                                    goal,
+                                    pool,
+                                    poison.clone(),
+                                )?,
+                            ))
+                        },
+                    )
+                    .collect::<Result<_>>()
+            })?;
             for (start, goal, res) in res_all {
                 for (cost, path) in res {
                     let t = vec![
@@ -115,80 +149,194 @@ impl AlgoImpl for KShortestPathYen {
     }
 }
 
+/// Stops the search for further paths once their cost exceeds the cost of the shortest
+/// path by the relative `tolerance`, or exceeds `max_cost`.
+struct Cutoff {
+    tolerance: Option<f64>,
+    max_cost: Option<f64>,
+}
+
+impl Cutoff {
+    fn limit(&self, best_cost: f64) -> f64 {
+        let relative = match self.tolerance {
+            Some(t) => best_cost * (1. + t),
+            None => f64::INFINITY,
+        };
+        relative.min(self.max_cost.unwrap_or(f64::INFINITY))
+    }
+}
+
+/// Runs `op` on the algorithm thread pool of the database if it has one, so that the parallel
+/// iterators within it do not use whichever pool happens to be current.
+fn on_pool<R: Send>(pool: Option<&ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// The tree of the shortest paths from all nodes to a goal, computed once by a search
+/// backwards from the goal and shared by all the spur searches.
+struct GoalTree {
+    /// The cost of the shortest path from each node to the goal
+    cost: Vec<f64>,
+    /// The node following each node on its shortest path to the goal
+    next: Vec<usize>,
+}
+
+impl GoalTree {
+    fn new(edges: &[Vec<(usize, f64)>], goal: usize) -> Self {
+        let mut reversed = vec![vec![]; edges.len()];
+        for (fr, outgoing) in edges.iter().enumerate() {
+            for (to, weight) in outgoing {
+                reversed[*to].push((fr, *weight));
+            }
+        }
+        let mut cost = vec![f64::INFINITY; edges.len()];
+        let mut next = vec![usize::MAX; edges.len()];
+        let mut pq = PriorityQueue::new();
+        cost[goal] = 0.;
+        pq.push(goal, Reverse(OrderedFloat(0.)));
+        while let Some((node, Reverse(OrderedFloat(node_cost)))) = pq.pop() {
+            for (prev, weight) in &reversed[node] {
+                let prev_cost = node_cost + *weight;
+                if prev_cost < cost[*prev] {
+                    cost[*prev] = prev_cost;
+                    next[*prev] = node;
+                    pq.push_increase(*prev, Reverse(OrderedFloat(prev_cost)));
+                }
+            }
+        }
+        Self { cost, next }
+    }
+
+    /// The shortest path from `node` to the goal, if it uses none of the forbidden edges and
+    /// nodes: it is then also the shortest of the paths avoiding them.
+    fn path_avoiding(
+        &self,
+        node: usize,
+        forbidden_edges: &BTreeSet<(usize, usize)>,
+        forbidden_nodes: &BTreeSet<usize>,
+    ) -> Option<(f64, Vec<usize>)> {
+        let mut path = vec![node];
+        let mut current = node;
+        while self.next[current] != usize::MAX {
+            let nxt = self.next[current];
+            if forbidden_nodes.contains(&nxt) || forbidden_edges.contains(&(current, nxt)) {
+                return None;
+            }
+            path.push(nxt);
+            current = nxt;
+        }
+        Some((self.cost[node], path))
+    }
+}
+
+/// Yen's algorithm with Lawler's modification: spur nodes before the point where a path
+/// deviated from its parent have already been explored with the same root path and are
+/// skipped. The spur paths of one iteration are computed in parallel, and a spur search is
+/// only run when the shortest path from the spur node to the goal is blocked by the root path
+/// or by the paths already found.
 fn k_shortest_path_yen(
     k: usize,
+    cutoff: &Cutoff,
     edges: &[Vec<(usize, f64)>],
     start: usize,
     goal: usize,
+    pool: Option<&ThreadPool>,
     poison: Poison,
 ) -> Result<Vec<(f64, Vec<usize>)>> {
-    let mut k_shortest: Vec<(f64, Vec<usize>)> = Vec::with_capacity(k);
-    let mut candidates: Vec<(f64, Vec<usize>)> = vec![];
-
-    match dijkstra(edges, start, &Some(goal), &(), &())
-        .into_iter()
-        .next()
-    {
-        None => return Ok(k_shortest),
-        Some((_, cost, path)) => k_shortest.push((cost, path)),
+    let mut k_shortest: Vec<(f64, Vec<usize>)> = vec![];
+    // each path is kept together with the index at which it deviates from its parent
+    let mut deviations: Vec<usize> = vec![];
+    let mut candidates: Vec<(f64, Vec<usize>, usize)> = vec![];
+
+    let tree = GoalTree::new(edges, goal);
+    let cost = tree.cost[start];
+    if !cost.is_finite() || cost > cutoff.limit(cost) {
+        return Ok(k_shortest);
     }
+    let (_, path) = tree
+        .path_avoiding(start, &Default::default(), &Default::default())
+        .unwrap();
+    k_shortest.push((cost, path));
+    deviations.push(0);
 
     for _ in 1..k {
         let (_, prev_path) = k_shortest.last().unwrap();
-        for i in 0..prev_path.len() - 1 {
-            let spur_node = prev_path[i];
-            let root_path = &prev_path[0..i + 1];
-            let mut forbidden_edges = BTreeSet::new();
-            for (_, p) in &k_shortest {
-                if p.len() < root_path.len() + 1 {
-                    continue;
-                }
-                let p_prefix = &p[0..i + 1];
-                if p_prefix == root_path {
-                    forbidden_edges.insert((p[i], p[i + 1]));
-                }
-            }
-            let mut forbidden_nodes = BTreeSet::new();
-            for node in &prev_path[0..i] {
-                forbidden_nodes.insert(*node);
-            }
-            if let Some((_, spur_cost, spur_path)) = dijkstra(
-                edges,
-                spur_node,
-                &Some(goal),
-                &forbidden_edges,
-                &forbidden_nodes,
-            )
-            .into_iter()
-            .next()
-            {
-                let mut total_cost = spur_cost;
-                for i in 0..root_path.len() - 1 {
-                    let s = root_path[i];
-                    let d = root_path[i + 1];
-                    let eds = &edges[s];
-                    for (e, c) in eds {
-                        if *e == d {
-                            total_cost += *c;
-                            break;
+        let deviation = *deviations.last().unwrap();
+        let spur_search = || {
+            (deviation..prev_path.len() - 1)
+                .into_par_iter()
+                .map(|i| -> Result<Option<(f64, Vec<usize>, usize)>> {
+                    let spur_node = prev_path[i];
+                    let root_path = &prev_path[0..i + 1];
+                    let mut forbidden_edges = BTreeSet::new();
+                    for (_, p) in &k_shortest {
+                        if p.len() < root_path.len() + 1 {
+                            continue;
+                        }
+                        let p_prefix = &p[0..i + 1];
+                        if p_prefix == root_path {
+                            forbidden_edges.insert((p[i], p[i + 1]));
                         }
                     }
-                }
-                let mut total_path = root_path.to_vec();
-                total_path.pop();
-                total_path.extend(spur_path);
-                if candidates.iter().all(|(_, v)| *v != total_path) {
-                    candidates.push((total_cost, total_path));
-                }
-                poison.check()?;
+                    let mut forbidden_nodes = BTreeSet::new();
+                    for node in &prev_path[0..i] {
+                        forbidden_nodes.insert(*node);
+                    }
+                    poison.check()?;
+                    if !tree.cost[spur_node].is_finite() {
+                        return Ok(None);
+                    }
+                    let (spur_cost, spur_path) =
+                        match tree.path_avoiding(spur_node, &forbidden_edges, &forbidden_nodes) {
+                            Some(found) => found,
+                            None => match dijkstra(
+                                edges,
+                                spur_node,
+                                &Some(goal),
+                                &forbidden_edges,
+                                &forbidden_nodes,
+                            )
+                            .into_iter()
+                            .next()
+                            {
+                                Some((_, cost, path)) if cost.is_finite() => (cost, path),
+                                _ => return Ok(None),
+                            },
+                        };
+                    let mut total_cost = spur_cost;
+                    for (s, d) in root_path.iter().tuple_windows() {
+                        total_cost += edges[*s]
+                            .iter()
+                            .filter(|(e, _)| e == d)
+                            .map(|(_, c)| *c)
+                            .fold(f64::INFINITY, f64::min);
+                    }
+                    let mut total_path = root_path.to_vec();
+                    total_path.pop();
+                    total_path.extend(spur_path);
+                    Ok(Some((total_cost, total_path, i)))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let spurs = on_pool(pool, spur_search)?;
+        for (total_cost, total_path, i) in spurs.into_iter().flatten() {
+            if candidates.iter().all(|(_, v, _)| *v != total_path) {
+                candidates.push((total_cost, total_path, i));
             }
         }
         if candidates.is_empty() {
             break;
         }
-        candidates.sort_by(|(a_cost, _), (b_cost, _)| b_cost.total_cmp(a_cost));
-        let shortest = candidates.pop().unwrap();
-        k_shortest.push(shortest);
+        candidates.sort_by(|(a_cost, _, _), (b_cost, _, _)| b_cost.total_cmp(a_cost));
+        let (cost, path, i) = candidates.pop().unwrap();
+        if cost > cutoff.limit(k_shortest[0].0) {
+            break;
+        }
+        k_shortest.push((cost, path));
+        deviations.push(i);
     }
     Ok(k_shortest)
 }
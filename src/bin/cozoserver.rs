@@ -4,6 +4,7 @@
 
 use std::fmt::Debug;
 use std::fs;
+use std::io::Read;
 use std::net::Ipv6Addr;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -14,7 +15,7 @@ use log::{error, info};
 use rand::Rng;
 use rouille::{router, try_or_400, Request, Response};
 
-use cozo::Db;
+use cozo::{Db, DbOptions, RemoteDb};
 
 #[derive(Parser, Debug)]
 #[clap(version, about, long_about = None)]
@@ -30,6 +31,19 @@ struct Args {
     /// Port to use
     #[clap(short, long, default_value_t = 9070)]
     port: u16,
+
+    /// Remote database whose relations queries can read as `*NAME::relation`, given as NAME=URL
+    #[clap(long = "remote", value_parser = parse_remote)]
+    remotes: Vec<(String, String)>,
+}
+
+fn parse_remote(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, url)) if !name.is_empty() && !url.is_empty() => {
+            Ok((name.to_string(), url.to_string()))
+        }
+        _ => Err(format!("expected NAME=URL, got '{}'", s)),
+    }
 }
 
 fn main() {
@@ -39,7 +53,15 @@ fn main() {
         eprintln!("{}", SECURITY_WARNING);
     }
 
-    let db = Db::new(args.path.as_str()).unwrap();
+    let options = DbOptions {
+        remotes: args
+            .remotes
+            .iter()
+            .map(|(name, url)| (name.clone(), RemoteDb::new(url)))
+            .collect(),
+        ..Default::default()
+    };
+    let db = Db::new_with_options(args.path.as_str(), options).unwrap();
 
     let mut path_buf = PathBuf::from(&args.path);
     path_buf.push("auth.txt");
@@ -97,7 +119,9 @@ fn main() {
                     }
 
                     let payload: QueryPayload = try_or_400!(rouille::input::json_input(request));
-                    let result = db.run_script_fold_err(&payload.script, &payload.params);
+                    let label = request.header("x-cozo-label");
+                    let result =
+                        db.run_script_fold_err_labelled(&payload.script, &payload.params, label);
                     let response = Response::json(&result);
                     if let Some(serde_json::Value::Bool(true)) = result.get("ok") {
                         response
@@ -118,6 +142,44 @@ fn main() {
                     //     _ => Response::json(&result).with_status_code(400)
                     // }
                 },
+                (POST) (/sync) => {
+                    if !request.remote_addr().ip().is_loopback() {
+                        match request.header("x-cozo-auth") {
+                            None => return Response::text("Unauthorized").with_status_code(401),
+                            Some(code) => {
+                                if auth_guard != code {
+                                    return Response::text("Unauthorized").with_status_code(401);
+                                }
+                            }
+                        }
+                    }
+
+                    let mut payload = vec![];
+                    match request.data() {
+                        None => return Response::text("Missing request body").with_status_code(400),
+                        Some(mut body) => {
+                            try_or_400!(body.read_to_end(&mut payload));
+                        }
+                    }
+                    match db.handle_sync_request(&payload) {
+                        Ok(response) => Response::from_data("application/octet-stream", response),
+                        Err(err) => Response::text(format!("{:?}", err)).with_status_code(400),
+                    }
+                },
+                (GET) (/metrics) => {
+                    if !request.remote_addr().ip().is_loopback() {
+                        match request.header("x-cozo-auth") {
+                            None => return Response::text("Unauthorized").with_status_code(401),
+                            Some(code) => {
+                                if auth_guard != code {
+                                    return Response::text("Unauthorized").with_status_code(401);
+                                }
+                            }
+                        }
+                    }
+
+                    Response::text(db.export_metrics())
+                },
                 (GET) (/) => {
                     Response::html(HTML_CONTENT)
                 },
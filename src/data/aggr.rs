@@ -4,17 +4,29 @@
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
-
-use miette::{bail, ensure, miette, Result};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use bigdecimal::BigDecimal;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result};
+use num_bigint::BigInt;
 use rand::prelude::*;
+use thiserror::Error;
 
-use crate::data::value::DataValue;
+use crate::data::json::JsonValue;
+use crate::data::value::{decimal_to_f64, DataValue, Num};
 
 pub(crate) struct Aggregation {
     pub(crate) name: &'static str,
     pub(crate) is_meet: bool,
     pub(crate) meet_op: Option<Box<dyn MeetAggrObj>>,
     pub(crate) normal_op: Option<Box<dyn NormalAggrObj>>,
+    pub(crate) user: Option<UserAggregation>,
 }
 
 impl Clone for Aggregation {
@@ -24,6 +36,7 @@ impl Clone for Aggregation {
             is_meet: self.is_meet,
             meet_op: None,
             normal_op: None,
+            user: self.user.clone(),
         }
     }
 }
@@ -56,6 +69,7 @@ macro_rules! define_aggr {
             is_meet: $is_meet,
             meet_op: None,
             normal_op: None,
+            user: None,
         };
     };
 }
@@ -390,6 +404,59 @@ impl NormalAggrObj for AggrChoiceRand {
     }
 }
 
+define_aggr!(AGGR_SAMPLE, false);
+
+/// Keeps a uniform random sample of at most `n` values by reservoir sampling.
+pub(crate) struct AggrSample {
+    size: usize,
+    seen: usize,
+    rng: StdRng,
+    reservoir: Vec<DataValue>,
+}
+
+impl AggrSample {
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let size = match args.first().map(|v| v.get_int()) {
+            Some(Some(i)) if i > 0 => i as usize,
+            _ => bail!("'sample' requires a positive integer as the sample size"),
+        };
+        let rng = match args.get(1) {
+            None | Some(DataValue::Null) => StdRng::from_entropy(),
+            Some(v) => {
+                let seed = v
+                    .get_int()
+                    .ok_or_else(|| miette!("the seed of 'sample' must be an integer"))?;
+                StdRng::seed_from_u64(seed as u64)
+            }
+        };
+        Ok(Self {
+            size,
+            seen: 0,
+            rng,
+            reservoir: vec![],
+        })
+    }
+}
+
+impl NormalAggrObj for AggrSample {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.seen += 1;
+        if self.reservoir.len() < self.size {
+            self.reservoir.push(value.clone());
+        } else {
+            let idx = self.rng.gen_range(0..self.seen);
+            if idx < self.size {
+                self.reservoir[idx] = value.clone();
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::List(self.reservoir.clone()))
+    }
+}
+
 define_aggr!(AGGR_COUNT, false);
 
 #[derive(Default)]
@@ -408,34 +475,66 @@ impl NormalAggrObj for AggrCount {
     }
 }
 
+/// Count, mean and sums of the powers of the deviations from the mean of numbers, updated one
+/// number at a time (Welford's algorithm, extended to the third and fourth powers), which
+/// does not lose precision to cancellation the way sums of powers of the numbers do.
+#[derive(Default)]
+struct Moments {
+    count: u64,
+    mean: f64,
+    m2: f64,
+    m3: f64,
+    m4: f64,
+}
+
+impl Moments {
+    fn add(&mut self, name: &str, value: &DataValue) -> Result<()> {
+        let x = match value {
+            DataValue::Num(n) => n.get_float(),
+            v => bail!("cannot compute '{}': encountered value {:?}", name, v),
+        };
+        let prev = self.count as f64;
+        self.count += 1;
+        let n = self.count as f64;
+        let delta = x - self.mean;
+        let delta_n = delta / n;
+        let delta_n2 = delta_n * delta_n;
+        let term = delta * delta_n * prev;
+        self.mean += delta_n;
+        self.m4 += term * delta_n2 * (n * n - 3. * n + 3.) + 6. * delta_n2 * self.m2
+            - 4. * delta_n * self.m3;
+        self.m3 += term * delta_n * (n - 2.) - 3. * delta_n * self.m2;
+        self.m2 += term;
+        Ok(())
+    }
+    /// The sample variance.
+    fn variance(&self) -> f64 {
+        self.m2 / (self.count as f64 - 1.)
+    }
+    /// The sample skewness, not corrected for bias.
+    fn skewness(&self) -> f64 {
+        (self.count as f64).sqrt() * self.m3 / self.m2.powf(1.5)
+    }
+    /// The excess kurtosis of the sample, not corrected for bias.
+    fn kurtosis(&self) -> f64 {
+        self.count as f64 * self.m4 / (self.m2 * self.m2) - 3.
+    }
+}
+
 define_aggr!(AGGR_VARIANCE, false);
 
 #[derive(Default)]
 pub(crate) struct AggrVariance {
-    count: i64,
-    sum: f64,
-    sum_sq: f64,
+    moments: Moments,
 }
 
 impl NormalAggrObj for AggrVariance {
     fn set(&mut self, value: &DataValue) -> Result<()> {
-        match value {
-            DataValue::Num(n) => {
-                let f = n.get_float();
-                self.sum += f;
-                self.sum_sq += f * f;
-                self.count += 1;
-            }
-            v => bail!("cannot compute 'variance': encountered value {:?}", v),
-        }
-        Ok(())
+        self.moments.add("variance", value)
     }
 
     fn get(&self) -> Result<DataValue> {
-        let ct = self.count as f64;
-        Ok(DataValue::from(
-            (self.sum_sq - self.sum * self.sum / ct) / (ct - 1.),
-        ))
+        Ok(DataValue::from(self.moments.variance()))
     }
 }
 
@@ -443,29 +542,95 @@ define_aggr!(AGGR_STD_DEV, false);
 
 #[derive(Default)]
 pub(crate) struct AggrStdDev {
-    count: i64,
-    sum: f64,
-    sum_sq: f64,
+    moments: Moments,
 }
 
 impl NormalAggrObj for AggrStdDev {
     fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("std_dev", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.moments.variance().sqrt()))
+    }
+}
+
+define_aggr!(AGGR_SKEWNESS, false);
+
+#[derive(Default)]
+pub(crate) struct AggrSkewness {
+    moments: Moments,
+}
+
+impl NormalAggrObj for AggrSkewness {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("skewness", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.moments.skewness()))
+    }
+}
+
+define_aggr!(AGGR_KURTOSIS, false);
+
+#[derive(Default)]
+pub(crate) struct AggrKurtosis {
+    moments: Moments,
+}
+
+impl NormalAggrObj for AggrKurtosis {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.moments.add("kurtosis", value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.moments.kurtosis()))
+    }
+}
+
+/// Running sum that stays exact when big integers or decimals are involved.
+#[derive(Default)]
+struct NumAccum {
+    ints: i128,
+    floats: f64,
+    exact: Option<BigDecimal>,
+    has_float: bool,
+    has_decimal: bool,
+}
+
+impl NumAccum {
+    fn add(&mut self, value: &DataValue) -> bool {
         match value {
-            DataValue::Num(n) => {
-                let f = n.get_float();
-                self.sum += f;
-                self.sum_sq += f * f;
-                self.count += 1;
+            DataValue::Num(Num::Int(i)) => self.ints += *i as i128,
+            DataValue::Num(Num::Float(f)) => {
+                self.floats += f;
+                self.has_float = true;
+            }
+            DataValue::BigInt(_) | DataValue::Decimal(_) => {
+                self.has_decimal |= matches!(value, DataValue::Decimal(_));
+                let v = value.get_exact().unwrap();
+                self.exact = Some(match self.exact.take() {
+                    None => v,
+                    Some(acc) => acc + v,
+                });
             }
-            v => bail!("cannot compute 'std_dev': encountered value {:?}", v),
+            _ => return false,
         }
-        Ok(())
+        true
     }
-
-    fn get(&self) -> Result<DataValue> {
-        let ct = self.count as f64;
-        let var = (self.sum_sq - self.sum * self.sum / ct) / (ct - 1.);
-        Ok(DataValue::from(var.sqrt()))
+    /// The exact total if only integers, big integers and decimals were seen and at least
+    /// one of them was big.
+    fn exact_total(&self) -> Option<BigDecimal> {
+        if self.has_float {
+            return None;
+        }
+        let exact = self.exact.as_ref()?;
+        Some(exact.clone() + BigDecimal::new(BigInt::from(self.ints), 0))
+    }
+    fn float_total(&self) -> f64 {
+        let exact = self.exact.as_ref().map(decimal_to_f64).unwrap_or(0.);
+        self.ints as f64 + self.floats + exact
     }
 }
 
@@ -474,23 +639,27 @@ define_aggr!(AGGR_MEAN, false);
 #[derive(Default)]
 pub(crate) struct AggrMean {
     count: i64,
-    sum: f64,
+    sum: NumAccum,
 }
 
 impl NormalAggrObj for AggrMean {
     fn set(&mut self, value: &DataValue) -> Result<()> {
-        match value {
-            DataValue::Num(n) => {
-                self.sum += n.get_float();
-                self.count += 1;
-            }
-            v => bail!("cannot compute 'mean': encountered value {:?}", v),
-        }
+        ensure!(
+            self.sum.add(value),
+            "cannot compute 'mean': encountered value {:?}",
+            value
+        );
+        self.count += 1;
         Ok(())
     }
 
     fn get(&self) -> Result<DataValue> {
-        Ok(DataValue::from(self.sum / (self.count as f64)))
+        Ok(match self.sum.exact_total() {
+            Some(total) if self.count > 0 => {
+                DataValue::Decimal(total / BigDecimal::from(self.count))
+            }
+            _ => DataValue::from(self.sum.float_total() / (self.count as f64)),
+        })
     }
 }
 
@@ -498,22 +667,25 @@ define_aggr!(AGGR_SUM, false);
 
 #[derive(Default)]
 pub(crate) struct AggrSum {
-    sum: f64,
+    sum: NumAccum,
 }
 
 impl NormalAggrObj for AggrSum {
     fn set(&mut self, value: &DataValue) -> Result<()> {
-        match value {
-            DataValue::Num(n) => {
-                self.sum += n.get_float();
-            }
-            v => bail!("cannot compute 'sum': encountered value {:?}", v),
-        }
+        ensure!(
+            self.sum.add(value),
+            "cannot compute 'sum': encountered value {:?}",
+            value
+        );
         Ok(())
     }
 
     fn get(&self) -> Result<DataValue> {
-        Ok(DataValue::from(self.sum))
+        Ok(match self.sum.exact_total() {
+            Some(total) if self.sum.has_decimal => DataValue::Decimal(total),
+            Some(total) => DataValue::BigInt(total.with_scale(0).as_bigint_and_exponent().0),
+            None => DataValue::from(self.sum.float_total()),
+        })
     }
 }
 
@@ -1044,40 +1216,513 @@ impl NormalAggrObj for AggrBitXor {
     }
 }
 
-pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
-    Some(match name {
-        "and" => &AGGR_AND,
-        "or" => &AGGR_OR,
-        "unique" => &AGGR_UNIQUE,
-        "group_count" => &AGGR_GROUP_COUNT,
-        "union" => &AGGR_UNION,
-        "intersection" => &AGGR_INTERSECTION,
-        "count" => &AGGR_COUNT,
-        "count_unique" => &AGGR_COUNT_UNIQUE,
-        "variance" => &AGGR_VARIANCE,
-        "std_dev" => &AGGR_STD_DEV,
-        "sum" => &AGGR_SUM,
-        "product" => &AGGR_PRODUCT,
-        "min" => &AGGR_MIN,
-        "max" => &AGGR_MAX,
-        "mean" => &AGGR_MEAN,
-        "choice" => &AGGR_CHOICE,
-        "choice_last" => &AGGR_CHOICE_LAST,
-        "collect" => &AGGR_COLLECT,
-        "shortest" => &AGGR_SHORTEST,
-        "min_cost" => &AGGR_MIN_COST,
-        "coalesce" => &AGGR_COALESCE,
-        "bit_and" => &AGGR_BIT_AND,
-        "bit_or" => &AGGR_BIT_OR,
-        "bit_xor" => &AGGR_BIT_XOR,
-        "latest_by" => &AGGR_LATEST_BY,
-        "choice_rand" => &AGGR_CHOICE_RAND,
-        _ => return None,
+define_aggr!(AGGR_HISTOGRAM, false);
+
+/// Counts values into the buckets `[e0, e1), [e1, e2), ..., [en-1, en]` delimited by
+/// the given edges; values outside of the edges are not counted.
+pub(crate) struct AggrHistogram {
+    edges: Vec<f64>,
+    counts: Vec<i64>,
+}
+
+impl AggrHistogram {
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let edges: Vec<f64> = match args.first() {
+            Some(DataValue::List(l)) => l
+                .iter()
+                .map(|v| {
+                    v.get_float()
+                        .filter(|f| f.is_finite())
+                        .ok_or_else(|| miette!("bucket edges of 'histogram' must be numbers"))
+                })
+                .collect::<Result<_>>()?,
+            _ => bail!("'histogram' requires a list of bucket edges as argument"),
+        };
+        ensure!(
+            edges.len() >= 2,
+            "'histogram' requires at least two bucket edges"
+        );
+        ensure!(
+            edges.windows(2).all(|w| w[0] < w[1]),
+            "bucket edges of 'histogram' must be strictly increasing"
+        );
+        let counts = vec![0; edges.len() - 1];
+        Ok(Self { edges, counts })
+    }
+}
+
+impl NormalAggrObj for AggrHistogram {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let f = value
+            .get_float()
+            .ok_or_else(|| miette!("'histogram' requires numbers"))?;
+        let last = self.edges.len() - 1;
+        if f.is_nan() || f < self.edges[0] || f > self.edges[last] {
+            return Ok(());
+        }
+        let idx = self.edges.partition_point(|e| *e <= f).min(last);
+        self.counts[idx - 1] += 1;
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::List(
+            self.counts.iter().map(|c| DataValue::from(*c)).collect(),
+        ))
+    }
+}
+
+define_aggr!(AGGR_AUTO_HISTOGRAM, false);
+
+/// Counts values into a fixed number of equal-width buckets without knowing the range
+/// in advance: whenever a value falls outside of the current range, the width of the
+/// buckets is doubled and neighbouring buckets are merged. The result is a list of
+/// `[lower, upper, count]` triples.
+pub(crate) struct AggrAutoHistogram {
+    n_buckets: usize,
+    first: Option<(f64, i64)>,
+    lower: f64,
+    width: f64,
+    counts: Vec<i64>,
+}
+
+impl AggrAutoHistogram {
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let n_buckets = match args.first() {
+            None => 10,
+            Some(v) => match v.get_int() {
+                Some(i) if i > 0 => i as usize,
+                _ => bail!("the argument to 'auto_histogram' must be a positive integer"),
+            },
+        };
+        Ok(Self {
+            n_buckets,
+            first: None,
+            lower: 0.,
+            width: 0.,
+            counts: vec![],
+        })
+    }
+    fn upper(&self) -> f64 {
+        self.lower + self.width * (self.n_buckets as f64)
+    }
+    fn widen_upwards(&mut self) {
+        let mut counts = vec![0; self.n_buckets];
+        for (i, c) in self.counts.iter().enumerate() {
+            counts[i / 2] += c;
+        }
+        self.counts = counts;
+        self.width *= 2.;
+    }
+    fn widen_downwards(&mut self) {
+        let mut counts = vec![0; self.n_buckets];
+        for (i, c) in self.counts.iter().enumerate() {
+            counts[(i + self.n_buckets) / 2] += c;
+        }
+        self.counts = counts;
+        self.lower -= self.width * (self.n_buckets as f64);
+        self.width *= 2.;
+    }
+}
+
+impl NormalAggrObj for AggrAutoHistogram {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let f = value
+            .get_float()
+            .filter(|f| f.is_finite())
+            .ok_or_else(|| miette!("'auto_histogram' requires finite numbers"))?;
+        if self.counts.is_empty() {
+            match &mut self.first {
+                None => {
+                    self.first = Some((f, 1));
+                    return Ok(());
+                }
+                Some((v, n)) if *v == f => {
+                    *n += 1;
+                    return Ok(());
+                }
+                Some((v, n)) => {
+                    // the range of the first two distinct values decides the initial width
+                    self.lower = v.min(f);
+                    self.width = (v.max(f) - self.lower) / (self.n_buckets as f64);
+                    self.counts = vec![0; self.n_buckets];
+                    let idx = if *v < f { 0 } else { self.n_buckets - 1 };
+                    self.counts[idx] += *n;
+                }
+            }
+        }
+        while f < self.lower {
+            self.widen_downwards();
+        }
+        while f > self.upper() {
+            self.widen_upwards();
+        }
+        let idx = (((f - self.lower) / self.width) as usize).min(self.n_buckets - 1);
+        self.counts[idx] += 1;
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        if self.counts.is_empty() {
+            return Ok(DataValue::List(match self.first {
+                None => vec![],
+                Some((v, n)) => vec![DataValue::List(vec![
+                    DataValue::from(v),
+                    DataValue::from(v),
+                    DataValue::from(n),
+                ])],
+            }));
+        }
+        Ok(DataValue::List(
+            self.counts
+                .iter()
+                .enumerate()
+                .map(|(i, c)| {
+                    DataValue::List(vec![
+                        DataValue::from(self.lower + self.width * (i as f64)),
+                        DataValue::from(self.lower + self.width * ((i + 1) as f64)),
+                        DataValue::from(*c),
+                    ])
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// Number of values of a group held in memory by the aggregations keeping all of them,
+/// beyond which the values are spilled to temporary files.
+const AGGR_SPILL_THRESHOLD: usize = 1 << 16;
+
+static SPILL_FILE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// All the values of a group, read back in sorted order. Up to `AGGR_SPILL_THRESHOLD`
+/// values are kept in memory; whenever that many have accumulated, they are sorted and
+/// written to a temporary file as a run, and the runs are merged when read back.
+#[derive(Default)]
+struct SortedValues {
+    count: usize,
+    buffer: Vec<DataValue>,
+    runs: Vec<(PathBuf, usize)>,
+}
+
+impl SortedValues {
+    fn push(&mut self, value: &DataValue) -> Result<()> {
+        self.count += 1;
+        self.buffer.push(value.clone());
+        if self.buffer.len() >= AGGR_SPILL_THRESHOLD {
+            self.spill()?;
+        }
+        Ok(())
+    }
+    fn spill(&mut self) -> Result<()> {
+        self.buffer.sort();
+        let path = std::env::temp_dir().join(format!(
+            "cozo-aggr-{}-{}.run",
+            std::process::id(),
+            SPILL_FILE_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut writer = BufWriter::new(File::create(&path).into_diagnostic()?);
+        for value in &self.buffer {
+            rmp_serde::encode::write(&mut writer, value).into_diagnostic()?;
+        }
+        writer.flush().into_diagnostic()?;
+        self.runs.push((path, self.buffer.len()));
+        self.buffer.clear();
+        Ok(())
+    }
+    fn sorted(&self) -> Result<impl Iterator<Item = Result<DataValue>>> {
+        let mut buffer = self.buffer.clone();
+        buffer.sort();
+        let mut streams: Vec<Box<dyn Iterator<Item = Result<DataValue>>>> =
+            vec![Box::new(buffer.into_iter().map(Ok))];
+        for (path, len) in &self.runs {
+            let mut reader = BufReader::new(File::open(path).into_diagnostic()?);
+            streams.push(Box::new((0..*len).map(move |_| {
+                rmp_serde::decode::from_read(&mut reader).into_diagnostic()
+            })));
+        }
+        Ok(streams.into_iter().kmerge_by(|a, b| match (a, b) {
+            (Ok(a), Ok(b)) => a < b,
+            _ => true,
+        }))
+    }
+}
+
+impl Drop for SortedValues {
+    fn drop(&mut self) {
+        for (path, _) in &self.runs {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+define_aggr!(AGGR_MEDIAN, false);
+define_aggr!(AGGR_PERCENTILE, false);
+
+/// The value below which the given fraction of the values lie, interpolating linearly
+/// between the two closest values.
+pub(crate) struct AggrPercentile {
+    name: &'static str,
+    fraction: f64,
+    values: SortedValues,
+}
+
+impl AggrPercentile {
+    fn median() -> Self {
+        Self {
+            name: "median",
+            fraction: 0.5,
+            values: Default::default(),
+        }
+    }
+    fn new(args: &[DataValue]) -> Result<Self> {
+        let percent = match args.first().and_then(|v| v.get_float()) {
+            Some(p) if (0. ..=100.).contains(&p) => p,
+            _ => bail!("'percentile' requires a number between 0 and 100 as argument"),
+        };
+        Ok(Self {
+            name: "percentile",
+            fraction: percent / 100.,
+            values: Default::default(),
+        })
+    }
+}
+
+impl NormalAggrObj for AggrPercentile {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        match value {
+            DataValue::Num(_) => self.values.push(value),
+            v => bail!("cannot compute '{}': encountered value {:?}", self.name, v),
+        }
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        if self.values.count == 0 {
+            return Ok(DataValue::Null);
+        }
+        let rank = (self.values.count - 1) as f64 * self.fraction;
+        let lower = rank.floor();
+        let mut closest = self.values.sorted()?.skip(lower as usize).take(2);
+        let below = closest.next().unwrap()?.get_float().unwrap();
+        Ok(DataValue::from(match closest.next() {
+            Some(above) if rank > lower => {
+                below + (rank - lower) * (above?.get_float().unwrap() - below)
+            }
+            _ => below,
+        }))
+    }
+}
+
+define_aggr!(AGGR_MODE, false);
+
+/// The most frequent value, the least of them if several are equally frequent.
+#[derive(Default)]
+pub(crate) struct AggrMode {
+    values: SortedValues,
+}
+
+impl NormalAggrObj for AggrMode {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.values.push(value)
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let mut found = DataValue::Null;
+        let mut found_count = 0;
+        let mut current: Option<(DataValue, usize)> = None;
+        for value in self.values.sorted()? {
+            let value = value?;
+            match &mut current {
+                Some((v, n)) if *v == value => *n += 1,
+                _ => {
+                    if let Some((v, n)) = current.take() {
+                        if n > found_count {
+                            found = v;
+                            found_count = n;
+                        }
+                    }
+                    current = Some((value, 1));
+                }
+            }
+        }
+        if let Some((v, n)) = current {
+            if n > found_count {
+                found = v;
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// The state of a user-defined aggregation over one group of rows.
+pub trait UserNormalAggregation {
+    /// Called with each value of the group in turn.
+    fn set(&mut self, value: &JsonValue) -> Result<()>;
+    /// Returns the aggregated value of the values set so far.
+    fn get(&self) -> Result<JsonValue>;
+}
+
+/// A user-defined aggregation, made available to queries by [`register_aggregation`].
+#[derive(Clone)]
+pub enum UserAggregation {
+    /// An ordinary aggregation. The function creates the state for a group from the
+    /// extra arguments given to the aggregation in the rule head, as in `agg(x, 1, 2)`.
+    Normal(Arc<dyn Fn(&[JsonValue]) -> Result<Box<dyn UserNormalAggregation>> + Send + Sync>),
+    /// A meet aggregation, which can be used in recursive rules. The function merges the
+    /// second value into the first and returns whether the first changed. It must be
+    /// idempotent, commutative and associative, or recursive queries may not terminate.
+    Meet(Arc<dyn Fn(&mut JsonValue, &JsonValue) -> Result<bool> + Send + Sync>),
+}
+
+lazy_static! {
+    static ref USER_AGGRS: RwLock<BTreeMap<String, (&'static str, UserAggregation)>> =
+        Default::default();
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Aggregation '{0}' is already defined")]
+#[diagnostic(code(aggr::name_conflict))]
+struct AggrNameConflict(String);
+
+/// Registers an aggregation implemented in Rust under `name`, to be used in rule heads
+/// like the builtin ones. Registered aggregations are visible to all databases of the
+/// process and cannot shadow builtin or previously registered ones.
+pub fn register_aggregation(name: &str, aggr: UserAggregation) -> Result<()> {
+    let mut registry = USER_AGGRS.write().unwrap();
+    ensure!(
+        parse_aggr(name).is_none() && !registry.contains_key(name),
+        AggrNameConflict(name.to_string())
+    );
+    let static_name: &'static str = Box::leak(name.to_string().into_boxed_str());
+    registry.insert(name.to_string(), (static_name, aggr));
+    Ok(())
+}
+
+/// The names of the registered aggregations, each with whether it is a meet aggregation.
+pub(crate) fn list_user_aggrs() -> Vec<(String, bool)> {
+    USER_AGGRS
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(name, (_, aggr))| (name.clone(), matches!(aggr, UserAggregation::Meet(_))))
+        .collect()
+}
+
+pub(crate) fn parse_user_aggr(name: &str) -> Option<Aggregation> {
+    let registry = USER_AGGRS.read().unwrap();
+    let (name, aggr) = registry.get(name)?;
+    Some(Aggregation {
+        name,
+        is_meet: matches!(aggr, UserAggregation::Meet(_)),
+        meet_op: None,
+        normal_op: None,
+        user: Some(aggr.clone()),
     })
 }
 
+struct UserNormalAggr(Box<dyn UserNormalAggregation>);
+
+impl NormalAggrObj for UserNormalAggr {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        self.0.set(&JsonValue::from(value.clone()))
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(DataValue::from(self.0.get()?))
+    }
+}
+
+/// A user-defined meet aggregation used in a rule that is not a meet rule, folding the
+/// values of the group with the merge function.
+struct UserMeetAsNormalAggr {
+    merge: Arc<dyn Fn(&mut JsonValue, &JsonValue) -> Result<bool> + Send + Sync>,
+    accum: Option<JsonValue>,
+}
+
+impl NormalAggrObj for UserMeetAsNormalAggr {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let value = JsonValue::from(value.clone());
+        match &mut self.accum {
+            None => self.accum = Some(value),
+            Some(accum) => {
+                (self.merge)(accum, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        Ok(match &self.accum {
+            None => DataValue::Null,
+            Some(accum) => DataValue::from(accum),
+        })
+    }
+}
+
+struct UserMeetAggr(Arc<dyn Fn(&mut JsonValue, &JsonValue) -> Result<bool> + Send + Sync>);
+
+impl MeetAggrObj for UserMeetAggr {
+    fn update(&self, left: &mut DataValue, right: &DataValue) -> Result<bool> {
+        let mut merged = JsonValue::from(left.clone());
+        let changed = (self.0)(&mut merged, &JsonValue::from(right.clone()))?;
+        if changed {
+            *left = DataValue::from(merged);
+        }
+        Ok(changed)
+    }
+}
+
+/// All the builtin aggregations, keyed by the names used in rule heads.
+pub(crate) const AGGRS: &[(&str, &Aggregation)] = &[
+    ("and", &AGGR_AND),
+    ("or", &AGGR_OR),
+    ("unique", &AGGR_UNIQUE),
+    ("group_count", &AGGR_GROUP_COUNT),
+    ("union", &AGGR_UNION),
+    ("intersection", &AGGR_INTERSECTION),
+    ("count", &AGGR_COUNT),
+    ("count_unique", &AGGR_COUNT_UNIQUE),
+    ("variance", &AGGR_VARIANCE),
+    ("std_dev", &AGGR_STD_DEV),
+    ("skewness", &AGGR_SKEWNESS),
+    ("kurtosis", &AGGR_KURTOSIS),
+    ("sum", &AGGR_SUM),
+    ("product", &AGGR_PRODUCT),
+    ("min", &AGGR_MIN),
+    ("max", &AGGR_MAX),
+    ("mean", &AGGR_MEAN),
+    ("choice", &AGGR_CHOICE),
+    ("choice_last", &AGGR_CHOICE_LAST),
+    ("collect", &AGGR_COLLECT),
+    ("shortest", &AGGR_SHORTEST),
+    ("min_cost", &AGGR_MIN_COST),
+    ("coalesce", &AGGR_COALESCE),
+    ("bit_and", &AGGR_BIT_AND),
+    ("bit_or", &AGGR_BIT_OR),
+    ("bit_xor", &AGGR_BIT_XOR),
+    ("latest_by", &AGGR_LATEST_BY),
+    ("choice_rand", &AGGR_CHOICE_RAND),
+    ("histogram", &AGGR_HISTOGRAM),
+    ("auto_histogram", &AGGR_AUTO_HISTOGRAM),
+    ("sample", &AGGR_SAMPLE),
+    ("median", &AGGR_MEDIAN),
+    ("percentile", &AGGR_PERCENTILE),
+    ("mode", &AGGR_MODE),
+];
+
+pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
+    AGGRS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, aggr)| *aggr)
+}
+
 impl Aggregation {
     pub(crate) fn meet_init(&mut self, _args: &[DataValue]) -> Result<()> {
+        if let Some(UserAggregation::Meet(merge)) = &self.user {
+            self.meet_op.replace(Box::new(UserMeetAggr(merge.clone())));
+            return Ok(());
+        }
         self.meet_op.replace(match self.name {
             name if name == AGGR_AND.name => Box::new(MeetAggrAnd),
             name if name == AGGR_OR.name => Box::new(MeetAggrOr),
@@ -1097,6 +1742,22 @@ impl Aggregation {
         Ok(())
     }
     pub(crate) fn normal_init(&mut self, args: &[DataValue]) -> Result<()> {
+        match &self.user {
+            None => {}
+            Some(UserAggregation::Normal(init)) => {
+                let args: Vec<_> = args.iter().map(|v| JsonValue::from(v.clone())).collect();
+                self.normal_op
+                    .replace(Box::new(UserNormalAggr(init(&args)?)));
+                return Ok(());
+            }
+            Some(UserAggregation::Meet(merge)) => {
+                self.normal_op.replace(Box::new(UserMeetAsNormalAggr {
+                    merge: merge.clone(),
+                    accum: None,
+                }));
+                return Ok(());
+            }
+        }
         self.normal_op.replace(match self.name {
             name if name == AGGR_AND.name => Box::new(AggrAnd::default()),
             name if name == AGGR_OR.name => Box::new(AggrOr::default()),
@@ -1110,6 +1771,8 @@ impl Aggregation {
             name if name == AGGR_MEAN.name => Box::new(AggrMean::default()),
             name if name == AGGR_VARIANCE.name => Box::new(AggrVariance::default()),
             name if name == AGGR_STD_DEV.name => Box::new(AggrStdDev::default()),
+            name if name == AGGR_SKEWNESS.name => Box::new(AggrSkewness::default()),
+            name if name == AGGR_KURTOSIS.name => Box::new(AggrKurtosis::default()),
             name if name == AGGR_CHOICE.name => Box::new(AggrChoice::default()),
             name if name == AGGR_CHOICE_LAST.name => Box::new(AggrChoiceLast::default()),
             name if name == AGGR_BIT_AND.name => Box::new(AggrBitAnd::default()),
@@ -1123,6 +1786,12 @@ impl Aggregation {
             name if name == AGGR_LATEST_BY.name => Box::new(AggrLatestBy::default()),
             name if name == AGGR_COALESCE.name => Box::new(AggrCoalesce::default()),
             name if name == AGGR_CHOICE_RAND.name => Box::new(AggrChoiceRand::default()),
+            name if name == AGGR_HISTOGRAM.name => Box::new(AggrHistogram::new(args)?),
+            name if name == AGGR_AUTO_HISTOGRAM.name => Box::new(AggrAutoHistogram::new(args)?),
+            name if name == AGGR_SAMPLE.name => Box::new(AggrSample::new(args)?),
+            name if name == AGGR_MEDIAN.name => Box::new(AggrPercentile::median()),
+            name if name == AGGR_PERCENTILE.name => Box::new(AggrPercentile::new(args)?),
+            name if name == AGGR_MODE.name => Box::new(AggrMode::default()),
             name if name == AGGR_COLLECT.name => Box::new({
                 if args.is_empty() {
                     AggrCollect::default()
@@ -96,12 +96,6 @@ impl Display for Expr {
 #[diagnostic(code(eval::predicate_not_bool))]
 struct PredicateTypeError(#[label] SourceSpan, DataValue);
 
-#[derive(Debug, Error, Diagnostic)]
-#[error("Cannot build entity ID from {0:?}")]
-#[diagnostic(code(parser::bad_eid))]
-#[diagnostic(help("Entity ID should be an integer satisfying certain constraints"))]
-struct BadEntityId(DataValue, #[label] SourceSpan);
-
 #[derive(Error, Diagnostic, Debug)]
 #[error("Evaluation of expression failed")]
 #[diagnostic(code(eval::throw))]
@@ -158,6 +152,28 @@ impl Expr {
             v => vec![v.clone()],
         }
     }
+    pub(crate) fn replace_ops(&mut self, f: &impl Fn(&'static Op) -> &'static Op) {
+        match self {
+            Expr::Binding { .. } | Expr::Const { .. } => {}
+            Expr::Apply { op, args, .. } => {
+                *op = f(op);
+                for arg in args.iter_mut() {
+                    arg.replace_ops(f);
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.replace_ops(f);
+                    val.replace_ops(f);
+                }
+            }
+            Expr::Try { clauses, .. } => {
+                for clause in clauses {
+                    clause.replace_ops(f);
+                }
+            }
+        }
+    }
     pub(crate) fn fill_binding_indices(
         &mut self,
         binding_map: &BTreeMap<Symbol, usize>,
@@ -235,45 +251,209 @@ impl Expr {
         self.partial_eval()?;
         match self {
             Expr::Const { val, .. } => Ok(val),
+            // folding skips non-deterministic and failing applications,
+            // evaluating here surfaces their values and errors
+            e if e.bindings().is_empty() => e.eval(&Tuple(vec![])),
             _ => bail!(NotConstError),
         }
     }
+    /// Folds constant sub-expressions and simplifies boolean logic in place.
+    /// Applications that fail to evaluate are left alone so that the error
+    /// is only raised if the expression is actually reached at runtime.
     pub(crate) fn partial_eval(&mut self) -> Result<()> {
-        if let Expr::Apply { args, span, .. } = self {
-            let span = *span;
-            let mut all_evaluated = true;
-            for arg in args.iter_mut() {
-                arg.partial_eval()?;
-                all_evaluated = all_evaluated && matches!(arg, Expr::Const { .. });
-            }
-            if all_evaluated {
-                let result = self.eval(&Tuple(vec![]))?;
-                mem::swap(self, &mut Expr::Const { val: result, span });
-            }
-            // nested not's can accumulate during conversion to normal form
-            if let Expr::Apply {
-                op: op1,
-                args: arg1,
-                ..
-            } = self
-            {
-                if op1.name == OP_NEGATE.name {
-                    if let Some(Expr::Apply {
-                        op: op2,
-                        args: arg2,
-                        ..
-                    }) = arg1.first()
-                    {
-                        if op2.name == OP_NEGATE.name {
-                            let mut new_self = arg2[0].clone();
-                            mem::swap(self, &mut new_self);
+        match self {
+            Expr::Apply { args, span, op } => {
+                let span = *span;
+                let mut all_evaluated = true;
+                for arg in args.iter_mut() {
+                    arg.partial_eval()?;
+                    all_evaluated = all_evaluated && matches!(arg, Expr::Const { .. });
+                }
+                if all_evaluated && op.is_deterministic() {
+                    if let Ok(result) = self.eval(&Tuple(vec![])) {
+                        *self = Expr::Const { val: result, span };
+                        return Ok(());
+                    }
+                }
+                self.simplify_bool_logic();
+                // nested not's can accumulate during conversion to normal form
+                if let Expr::Apply {
+                    op: op1,
+                    args: arg1,
+                    ..
+                } = self
+                {
+                    if op1.name == OP_NEGATE.name {
+                        if let Some(Expr::Apply {
+                            op: op2,
+                            args: arg2,
+                            ..
+                        }) = arg1.first()
+                        {
+                            if op2.name == OP_NEGATE.name {
+                                let mut new_self = arg2[0].clone();
+                                mem::swap(self, &mut new_self);
+                            }
                         }
                     }
                 }
             }
+            Expr::Cond { clauses, span } => {
+                let span = *span;
+                let mut kept = vec![];
+                for (mut cond, mut val) in mem::take(clauses) {
+                    cond.partial_eval()?;
+                    val.partial_eval()?;
+                    match cond.const_truth() {
+                        Some(false) => {}
+                        Some(true) => {
+                            kept.push((cond, val));
+                            break;
+                        }
+                        None => kept.push((cond, val)),
+                    }
+                }
+                match kept.first().map(|(cond, _)| cond.const_truth()) {
+                    None => {
+                        *self = Expr::Const {
+                            val: DataValue::Null,
+                            span,
+                        }
+                    }
+                    Some(Some(true)) => *self = kept.swap_remove(0).1,
+                    Some(_) => *clauses = kept,
+                }
+            }
+            Expr::Try { clauses, .. } => {
+                for clause in clauses.iter_mut() {
+                    clause.partial_eval()?;
+                }
+            }
+            Expr::Binding { .. } | Expr::Const { .. } => {}
         }
         Ok(())
     }
+    fn simplify_bool_logic(&mut self) {
+        if let Expr::Apply { op, args, span } = self {
+            let (absorbing, identity) = if op.name == OP_AND.name {
+                (false, true)
+            } else if op.name == OP_OR.name {
+                (true, false)
+            } else {
+                return;
+            };
+            let span = *span;
+            if args.iter().any(|arg| arg.const_truth() == Some(absorbing)) {
+                *self = Expr::Const {
+                    val: DataValue::Bool(absorbing),
+                    span,
+                };
+                return;
+            }
+            let mut remaining = args
+                .iter()
+                .filter(|arg| arg.const_truth() != Some(identity))
+                .cloned()
+                .collect_vec();
+            match remaining.len() {
+                0 => {
+                    *self = Expr::Const {
+                        val: DataValue::Bool(identity),
+                        span,
+                    }
+                }
+                1 => *self = remaining.pop().unwrap(),
+                n if n < args.len() => *args = remaining.into_boxed_slice(),
+                _ => {}
+            }
+        }
+    }
+    /// Whether evaluating this expression always yields the same result for the same bindings.
+    pub(crate) fn is_deterministic(&self) -> bool {
+        match self {
+            Expr::Binding { .. } | Expr::Const { .. } => true,
+            Expr::Apply { op, args, .. } => {
+                op.is_deterministic() && args.iter().all(|arg| arg.is_deterministic())
+            }
+            Expr::Cond { clauses, .. } => clauses
+                .iter()
+                .all(|(cond, val)| cond.is_deterministic() && val.is_deterministic()),
+            Expr::Try { clauses, .. } => clauses.iter().all(|clause| clause.is_deterministic()),
+        }
+    }
+    /// Like the `Display` form, but with every constant rendered as `_`,
+    /// so that expressions differing only in literal values compare equal.
+    pub(crate) fn shape(&self) -> String {
+        match self {
+            Expr::Binding { var, .. } => var.name.to_string(),
+            Expr::Const { .. } => "_".to_string(),
+            Expr::Apply { op, args, .. } => format!(
+                "{}({})",
+                op.name.strip_prefix("OP_").unwrap().to_lowercase(),
+                args.iter().map(|arg| arg.shape()).join(", ")
+            ),
+            Expr::Cond { clauses, .. } => format!(
+                "cond({})",
+                clauses
+                    .iter()
+                    .map(|(cond, val)| format!("{}, {}", cond.shape(), val.shape()))
+                    .join(", ")
+            ),
+            Expr::Try { clauses, .. } => format!(
+                "try({})",
+                clauses.iter().map(|clause| clause.shape()).join(", ")
+            ),
+        }
+    }
+    /// Estimated cost of evaluating the expression once.
+    pub(crate) fn eval_cost(&self) -> u32 {
+        match self {
+            Expr::Binding { .. } | Expr::Const { .. } => 0,
+            Expr::Apply { op, args, .. } => {
+                op.eval_cost() + args.iter().map(|arg| arg.eval_cost()).sum::<u32>()
+            }
+            Expr::Cond { clauses, .. } => clauses
+                .iter()
+                .map(|(cond, val)| cond.eval_cost() + val.eval_cost())
+                .sum(),
+            Expr::Try { clauses, .. } => clauses.iter().map(|clause| clause.eval_cost()).sum(),
+        }
+    }
+    /// Whether evaluating the expression may fail, for example by dividing by zero.
+    pub(crate) fn may_fail(&self) -> bool {
+        match self {
+            Expr::Binding { .. } | Expr::Const { .. } => false,
+            Expr::Apply { op, args, .. } => !op.is_total() || args.iter().any(|arg| arg.may_fail()),
+            Expr::Cond { .. } | Expr::Try { .. } => true,
+        }
+    }
+    /// Smaller values for predicates expected to filter out more rows.
+    pub(crate) fn selectivity_rank(&self) -> u32 {
+        match self {
+            Expr::Apply { op, .. } => {
+                if op.name == OP_EQ.name || op.name == OP_IS_IN.name {
+                    0
+                } else if [OP_GT.name, OP_GE.name, OP_LT.name, OP_LE.name].contains(&op.name) {
+                    1
+                } else if op.name == OP_NEQ.name {
+                    3
+                } else {
+                    2
+                }
+            }
+            _ => 2,
+        }
+    }
+    /// Whether the expression is the constant `true` (`Some(true)`) or `false` (`Some(false)`).
+    pub(crate) fn const_truth(&self) -> Option<bool> {
+        match self {
+            Expr::Const {
+                val: DataValue::Bool(b),
+                ..
+            } => Some(*b),
+            _ => None,
+        }
+    }
     pub(crate) fn bindings(&self) -> BTreeSet<Symbol> {
         let mut ret = BTreeSet::new();
         self.collect_bindings(&mut ret);
@@ -580,130 +760,222 @@ impl PartialEq for Op {
 
 impl Eq for Op {}
 
+impl Op {
+    /// Whether applications of this op to constant arguments may be folded at compile time.
+    pub(crate) fn is_deterministic(&self) -> bool {
+        !(self.name.starts_with("OP_RAND_") || self.name == OP_NOW.name)
+    }
+    /// Whether an application of this op never fails, whatever its arguments.
+    pub(crate) fn is_total(&self) -> bool {
+        let name = self.name;
+        (name.starts_with("OP_IS_") && name != OP_IS_IN.name)
+            || name == OP_EQ.name
+            || name == OP_NEQ.name
+    }
+    /// Rough relative cost of one application, used to order predicates.
+    pub(crate) fn eval_cost(&self) -> u32 {
+        let name = self.name;
+        if name.starts_with("OP_IS_") && name != OP_IS_IN.name {
+            0
+        } else if name.starts_with("OP_REGEX") {
+            50
+        } else if name.starts_with("OP_STR")
+            || name.starts_with("OP_TRIM")
+            || name.ends_with("CASE")
+            || name.ends_with("_WITH")
+            || name.ends_with("BASE64")
+            || name.ends_with("TIMESTAMP")
+            || name == OP_CONCAT.name
+            || name == OP_UNICODE_NORMALIZE.name
+            || name == OP_CHARS.name
+            || name == OP_FROM_SUBSTRINGS.name
+        {
+            10
+        } else if name == OP_IS_IN.name
+            || name == OP_CONTAINS.name
+            || name == OP_TO_SET.name
+            || name == OP_SORTED.name
+            || name == OP_UNION.name
+            || name == OP_DIFFERENCE.name
+            || name == OP_INTERSECTION.name
+            || name.starts_with("OP_CHUNKS")
+            || name == OP_WINDOWS.name
+            || name.starts_with("OP_HAVERSINE")
+        {
+            5
+        } else {
+            1
+        }
+    }
+}
+
 impl Debug for Op {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.name)
     }
 }
 
+/// All the functions available in expressions, keyed by the names they are called by.
+pub(crate) const OPS: &[(&str, &Op)] = &[
+    ("list", &OP_LIST),
+    ("add", &OP_ADD),
+    ("sub", &OP_SUB),
+    ("mul", &OP_MUL),
+    ("div", &OP_DIV),
+    ("checked_add", &OP_CHECKED_ADD),
+    ("checked_sub", &OP_CHECKED_SUB),
+    ("checked_mul", &OP_CHECKED_MUL),
+    ("saturating_add", &OP_SATURATING_ADD),
+    ("saturating_sub", &OP_SATURATING_SUB),
+    ("saturating_mul", &OP_SATURATING_MUL),
+    ("div_safe", &OP_DIV_SAFE),
+    ("minus", &OP_MINUS),
+    ("abs", &OP_ABS),
+    ("signum", &OP_SIGNUM),
+    ("floor", &OP_FLOOR),
+    ("ceil", &OP_CEIL),
+    ("round", &OP_ROUND),
+    ("mod", &OP_MOD),
+    ("mod_safe", &OP_MOD_SAFE),
+    ("max", &OP_MAX),
+    ("min", &OP_MIN),
+    ("coalesce", &OP_COALESCE),
+    ("greatest", &OP_GREATEST),
+    ("least", &OP_LEAST),
+    ("pow", &OP_POW),
+    ("exp", &OP_EXP),
+    ("exp2", &OP_EXP2),
+    ("ln", &OP_LN),
+    ("log2", &OP_LOG2),
+    ("log10", &OP_LOG10),
+    ("sin", &OP_SIN),
+    ("cos", &OP_COS),
+    ("tan", &OP_TAN),
+    ("asin", &OP_ASIN),
+    ("acos", &OP_ACOS),
+    ("atan", &OP_ATAN),
+    ("atan2", &OP_ATAN2),
+    ("sinh", &OP_SINH),
+    ("cosh", &OP_COSH),
+    ("tanh", &OP_TANH),
+    ("asinh", &OP_ASINH),
+    ("acosh", &OP_ACOSH),
+    ("atanh", &OP_ATANH),
+    ("eq", &OP_EQ),
+    ("neq", &OP_NEQ),
+    ("gt", &OP_GT),
+    ("ge", &OP_GE),
+    ("lt", &OP_LT),
+    ("le", &OP_LE),
+    ("or", &OP_OR),
+    ("and", &OP_AND),
+    ("negate", &OP_NEGATE),
+    ("bit_and", &OP_BIT_AND),
+    ("bit_or", &OP_BIT_OR),
+    ("bit_not", &OP_BIT_NOT),
+    ("bit_xor", &OP_BIT_XOR),
+    ("pack_bits", &OP_PACK_BITS),
+    ("unpack_bits", &OP_UNPACK_BITS),
+    ("concat", &OP_CONCAT),
+    ("str_includes", &OP_STR_INCLUDES),
+    ("lowercase", &OP_LOWERCASE),
+    ("uppercase", &OP_UPPERCASE),
+    ("trim", &OP_TRIM),
+    ("trim_start", &OP_TRIM_START),
+    ("trim_end", &OP_TRIM_END),
+    ("starts_with", &OP_STARTS_WITH),
+    ("ends_with", &OP_ENDS_WITH),
+    ("is_null", &OP_IS_NULL),
+    ("is_int", &OP_IS_INT),
+    ("is_float", &OP_IS_FLOAT),
+    ("is_num", &OP_IS_NUM),
+    ("is_bigint", &OP_IS_BIGINT),
+    ("is_decimal", &OP_IS_DECIMAL),
+    ("is_timestamp", &OP_IS_TIMESTAMP),
+    ("is_json", &OP_IS_JSON),
+    ("is_string", &OP_IS_STRING),
+    ("is_list", &OP_IS_LIST),
+    ("is_set", &OP_IS_SET),
+    ("is_vec", &OP_IS_VEC),
+    ("is_bytes", &OP_IS_BYTES),
+    ("is_in", &OP_IS_IN),
+    ("contains", &OP_CONTAINS),
+    ("is_finite", &OP_IS_FINITE),
+    ("is_infinite", &OP_IS_INFINITE),
+    ("is_nan", &OP_IS_NAN),
+    ("is_uuid", &OP_IS_UUID),
+    ("length", &OP_LENGTH),
+    ("sorted", &OP_SORTED),
+    ("reverse", &OP_REVERSE),
+    ("append", &OP_APPEND),
+    ("prepend", &OP_PREPEND),
+    ("unicode_normalize", &OP_UNICODE_NORMALIZE),
+    ("haversine", &OP_HAVERSINE),
+    ("haversine_deg_input", &OP_HAVERSINE_DEG_INPUT),
+    ("deg_to_rad", &OP_DEG_TO_RAD),
+    ("rad_to_deg", &OP_RAD_TO_DEG),
+    ("get", &OP_GET),
+    ("maybe_get", &OP_MAYBE_GET),
+    ("chars", &OP_CHARS),
+    ("from_substrings", &OP_FROM_SUBSTRINGS),
+    ("slice", &OP_SLICE),
+    ("regex_matches", &OP_REGEX_MATCHES),
+    ("regex_replace", &OP_REGEX_REPLACE),
+    ("regex_replace_all", &OP_REGEX_REPLACE_ALL),
+    ("regex_extract", &OP_REGEX_EXTRACT),
+    ("regex_extract_first", &OP_REGEX_EXTRACT_FIRST),
+    ("encode_base64", &OP_ENCODE_BASE64),
+    ("decode_base64", &OP_DECODE_BASE64),
+    ("bytes_slice", &OP_BYTES_SLICE),
+    ("encode_hex", &OP_ENCODE_HEX),
+    ("decode_hex", &OP_DECODE_HEX),
+    ("sha256", &OP_SHA256),
+    ("blake3", &OP_BLAKE3),
+    ("tuple_hash", &OP_TUPLE_HASH),
+    ("first", &OP_FIRST),
+    ("last", &OP_LAST),
+    ("chunks", &OP_CHUNKS),
+    ("chunks_exact", &OP_CHUNKS_EXACT),
+    ("windows", &OP_WINDOWS),
+    ("to_float", &OP_TO_FLOAT),
+    ("to_bigint", &OP_TO_BIGINT),
+    ("to_decimal", &OP_TO_DECIMAL),
+    ("parse_decimal", &OP_PARSE_DECIMAL),
+    ("round_decimal", &OP_ROUND_DECIMAL),
+    ("format_decimal", &OP_FORMAT_DECIMAL),
+    ("to_string", &OP_TO_STRING),
+    ("rand_float", &OP_RAND_FLOAT),
+    ("rand_bernoulli", &OP_RAND_BERNOULLI),
+    ("rand_int", &OP_RAND_INT),
+    ("rand_choose", &OP_RAND_CHOOSE),
+    ("assert", &OP_ASSERT),
+    ("to_set", &OP_TO_SET),
+    ("union", &OP_UNION),
+    ("intersection", &OP_INTERSECTION),
+    ("difference", &OP_DIFFERENCE),
+    ("vec", &OP_VEC),
+    ("l2_dist", &OP_L2_DIST),
+    ("cos_dist", &OP_COS_DIST),
+    ("ip_dist", &OP_IP_DIST),
+    ("to_uuid", &OP_TO_UUID),
+    ("to_bool", &OP_TO_BOOL),
+    ("rand_uuid_v1", &OP_RAND_UUID_V1),
+    ("rand_uuid_v4", &OP_RAND_UUID_V4),
+    ("uuid_timestamp", &OP_UUID_TIMESTAMP),
+    ("now", &OP_NOW),
+    ("format_timestamp", &OP_FORMAT_TIMESTAMP),
+    ("parse_timestamp", &OP_PARSE_TIMESTAMP),
+    ("to_timestamp", &OP_TO_TIMESTAMP),
+    ("parse_json", &OP_PARSE_JSON),
+    ("to_json", &OP_TO_JSON),
+    ("json_get", &OP_JSON_GET),
+    ("json_set", &OP_JSON_SET),
+    ("json_remove", &OP_JSON_REMOVE),
+    ("json_merge", &OP_JSON_MERGE),
+];
+
 pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
-    Some(match name {
-        "list" => &OP_LIST,
-        "add" => &OP_ADD,
-        "sub" => &OP_SUB,
-        "mul" => &OP_MUL,
-        "div" => &OP_DIV,
-        "minus" => &OP_MINUS,
-        "abs" => &OP_ABS,
-        "signum" => &OP_SIGNUM,
-        "floor" => &OP_FLOOR,
-        "ceil" => &OP_CEIL,
-        "round" => &OP_ROUND,
-        "mod" => &OP_MOD,
-        "max" => &OP_MAX,
-        "min" => &OP_MIN,
-        "pow" => &OP_POW,
-        "exp" => &OP_EXP,
-        "exp2" => &OP_EXP2,
-        "ln" => &OP_LN,
-        "log2" => &OP_LOG2,
-        "log10" => &OP_LOG10,
-        "sin" => &OP_SIN,
-        "cos" => &OP_COS,
-        "tan" => &OP_TAN,
-        "asin" => &OP_ASIN,
-        "acos" => &OP_ACOS,
-        "atan" => &OP_ATAN,
-        "atan2" => &OP_ATAN2,
-        "sinh" => &OP_SINH,
-        "cosh" => &OP_COSH,
-        "tanh" => &OP_TANH,
-        "asinh" => &OP_ASINH,
-        "acosh" => &OP_ACOSH,
-        "atanh" => &OP_ATANH,
-        "eq" => &OP_EQ,
-        "neq" => &OP_NEQ,
-        "gt" => &OP_GT,
-        "ge" => &OP_GE,
-        "lt" => &OP_LT,
-        "le" => &OP_LE,
-        "or" => &OP_OR,
-        "and" => &OP_AND,
-        "negate" => &OP_NEGATE,
-        "bit_and" => &OP_BIT_AND,
-        "bit_or" => &OP_BIT_OR,
-        "bit_not" => &OP_BIT_NOT,
-        "bit_xor" => &OP_BIT_XOR,
-        "pack_bits" => &OP_PACK_BITS,
-        "unpack_bits" => &OP_UNPACK_BITS,
-        "concat" => &OP_CONCAT,
-        "str_includes" => &OP_STR_INCLUDES,
-        "lowercase" => &OP_LOWERCASE,
-        "uppercase" => &OP_UPPERCASE,
-        "trim" => &OP_TRIM,
-        "trim_start" => &OP_TRIM_START,
-        "trim_end" => &OP_TRIM_END,
-        "starts_with" => &OP_STARTS_WITH,
-        "ends_with" => &OP_ENDS_WITH,
-        "is_null" => &OP_IS_NULL,
-        "is_int" => &OP_IS_INT,
-        "is_float" => &OP_IS_FLOAT,
-        "is_num" => &OP_IS_NUM,
-        "is_string" => &OP_IS_STRING,
-        "is_list" => &OP_IS_LIST,
-        "is_bytes" => &OP_IS_BYTES,
-        "is_in" => &OP_IS_IN,
-        "is_finite" => &OP_IS_FINITE,
-        "is_infinite" => &OP_IS_INFINITE,
-        "is_nan" => &OP_IS_NAN,
-        "is_uuid" => &OP_IS_UUID,
-        "length" => &OP_LENGTH,
-        "sorted" => &OP_SORTED,
-        "reverse" => &OP_REVERSE,
-        "append" => &OP_APPEND,
-        "prepend" => &OP_PREPEND,
-        "unicode_normalize" => &OP_UNICODE_NORMALIZE,
-        "haversine" => &OP_HAVERSINE,
-        "haversine_deg_input" => &OP_HAVERSINE_DEG_INPUT,
-        "deg_to_rad" => &OP_DEG_TO_RAD,
-        "rad_to_deg" => &OP_RAD_TO_DEG,
-        "get" => &OP_GET,
-        "maybe_get" => &OP_MAYBE_GET,
-        "chars" => &OP_CHARS,
-        "from_substrings" => &OP_FROM_SUBSTRINGS,
-        "slice" => &OP_SLICE,
-        "regex_matches" => &OP_REGEX_MATCHES,
-        "regex_replace" => &OP_REGEX_REPLACE,
-        "regex_replace_all" => &OP_REGEX_REPLACE_ALL,
-        "regex_extract" => &OP_REGEX_EXTRACT,
-        "regex_extract_first" => &OP_REGEX_EXTRACT_FIRST,
-        "encode_base64" => &OP_ENCODE_BASE64,
-        "decode_base64" => &OP_DECODE_BASE64,
-        "first" => &OP_FIRST,
-        "last" => &OP_LAST,
-        "chunks" => &OP_CHUNKS,
-        "chunks_exact" => &OP_CHUNKS_EXACT,
-        "windows" => &OP_WINDOWS,
-        "to_float" => &OP_TO_FLOAT,
-        "to_string" => &OP_TO_STRING,
-        "rand_float" => &OP_RAND_FLOAT,
-        "rand_bernoulli" => &OP_RAND_BERNOULLI,
-        "rand_int" => &OP_RAND_INT,
-        "rand_choose" => &OP_RAND_CHOOSE,
-        "assert" => &OP_ASSERT,
-        "union" => &OP_UNION,
-        "intersection" => &OP_INTERSECTION,
-        "difference" => &OP_DIFFERENCE,
-        "to_uuid" => &OP_TO_UUID,
-        "to_bool" => &OP_TO_BOOL,
-        "rand_uuid_v1" => &OP_RAND_UUID_V1,
-        "rand_uuid_v4" => &OP_RAND_UUID_V4,
-        "uuid_timestamp" => &OP_UUID_TIMESTAMP,
-        "now" => &OP_NOW,
-        "format_timestamp" => &OP_FORMAT_TIMESTAMP,
-        "parse_timestamp" => &OP_PARSE_TIMESTAMP,
-        _ => return None,
-    })
+    OPS.iter().find(|(n, _)| *n == name).map(|(_, op)| *op)
 }
 
 impl Op {
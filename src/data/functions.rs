@@ -2,23 +2,32 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::collections::BTreeSet;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Write};
 use std::ops::{Div, Rem};
 use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use chrono::{DateTime, TimeZone, Utc};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
 use itertools::Itertools;
 use miette::{bail, ensure, miette, Result};
-use num_traits::FloatConst;
+use num_bigint::BigInt;
+use num_traits::{FloatConst, FromPrimitive, Signed, ToPrimitive, Zero};
 use rand::prelude::*;
-use smartstring::SmartString;
+use sha2::{Digest, Sha256};
+use smartstring::{LazyCompact, SmartString};
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
 
 use crate::data::expr::Op;
 use crate::data::json::JsonValue;
-use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper};
+use crate::data::memcmp::MemCmpEncoder;
+use crate::data::value::{
+    timestamp_to_datetime, DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Vector,
+};
 
 macro_rules! define_op {
     ($name:ident, $min_arity:expr, $vararg:expr) => {
@@ -37,12 +46,18 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
         (a, b),
         (Null, Null)
             | (Bool(_), Bool(_))
-            | (Num(_), Num(_))
+            | (
+                Num(_) | DataValue::BigInt(_) | Decimal(_),
+                Num(_) | DataValue::BigInt(_) | Decimal(_)
+            )
             | (Str(_), Str(_))
             | (Bytes(_), Bytes(_))
             | (Regex(_), Regex(_))
             | (List(_), List(_))
             | (Set(_), Set(_))
+            | (DataValue::Vec(_), DataValue::Vec(_))
+            | (DataValue::Timestamp(_), DataValue::Timestamp(_))
+            | (DataValue::Json(_), DataValue::Json(_))
             | (Guard, Guard)
             | (Bot, Bot)
     ) {
@@ -55,6 +70,56 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
     Ok(())
 }
 
+/// Compares numbers when at least one side is a big integer or a decimal: exactly if neither
+/// side is a float, otherwise by their float approximations.
+fn cmp_exact_nums(a: &DataValue, b: &DataValue) -> Option<Ordering> {
+    if !matches!(a, DataValue::BigInt(_) | DataValue::Decimal(_))
+        && !matches!(b, DataValue::BigInt(_) | DataValue::Decimal(_))
+    {
+        return None;
+    }
+    match (a.get_exact(), b.get_exact()) {
+        (Some(l), Some(r)) => Some(l.cmp(&r)),
+        _ => a.get_float()?.partial_cmp(&b.get_float()?),
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum ExactKind {
+    BigInt,
+    Decimal,
+}
+
+/// Arithmetic is done exactly if big integers or decimals are involved, and no floats.
+fn exact_kind(args: &[DataValue]) -> Option<ExactKind> {
+    let mut kind = None;
+    for arg in args {
+        match arg {
+            DataValue::Num(Num::Float(_)) => return None,
+            DataValue::Decimal(_) => kind = Some(ExactKind::Decimal),
+            DataValue::BigInt(_) if kind.is_none() => kind = Some(ExactKind::BigInt),
+            _ => {}
+        }
+    }
+    kind
+}
+
+fn exact_args(args: &[DataValue], what: &str) -> Result<Vec<BigDecimal>> {
+    args.iter()
+        .map(|arg| {
+            arg.get_exact()
+                .ok_or_else(|| miette!("{} requires numbers", what))
+        })
+        .collect()
+}
+
+fn exact_result(kind: ExactKind, val: BigDecimal) -> DataValue {
+    match kind {
+        ExactKind::BigInt => DataValue::BigInt(val.with_scale(0).as_bigint_and_exponent().0),
+        ExactKind::Decimal => DataValue::Decimal(val),
+    }
+}
+
 define_op!(OP_LIST, 0, true);
 pub(crate) fn op_list(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(args.to_vec()))
@@ -62,6 +127,9 @@ pub(crate) fn op_list(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_EQ, 2, false);
 pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(ord) = cmp_exact_nums(&args[0], &args[1]) {
+        return Ok(DataValue::Bool(ord == Ordering::Equal));
+    }
     Ok(DataValue::Bool(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 == *f,
@@ -77,14 +145,29 @@ pub(crate) fn op_is_uuid(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_IS_IN, 2, false);
 pub(crate) fn op_is_in(args: &[DataValue]) -> Result<DataValue> {
     let left = &args[0];
+    if let DataValue::Set(s) = &args[1] {
+        return Ok(DataValue::Bool(s.contains(left)));
+    }
     let right = args[1]
         .get_list()
-        .ok_or_else(|| miette!("right hand side of 'is_in' must be a list"))?;
+        .ok_or_else(|| miette!("right hand side of 'is_in' must be a list or a set"))?;
     Ok(DataValue::Bool(right.contains(left)))
 }
 
+define_op!(OP_CONTAINS, 2, false);
+pub(crate) fn op_contains(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Set(s) => Ok(DataValue::Bool(s.contains(&args[1]))),
+        DataValue::List(l) => Ok(DataValue::Bool(l.contains(&args[1]))),
+        _ => bail!("'contains' requires a list or a set"),
+    }
+}
+
 define_op!(OP_NEQ, 2, false);
 pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(ord) = cmp_exact_nums(&args[0], &args[1]) {
+        return Ok(DataValue::Bool(ord != Ordering::Equal));
+    }
     Ok(DataValue::Bool(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 != *f,
@@ -95,9 +178,12 @@ pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_GT, 2, false);
 pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(ord) = cmp_exact_nums(&args[0], &args[1]) {
+        return Ok(DataValue::Bool(ord == Ordering::Greater));
+    }
     Ok(DataValue::Bool(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l as f64 > *r as f64,
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r as f64,
+        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l > *r as f64,
+        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r,
         (a, b) => a > b,
     }))
 }
@@ -105,9 +191,12 @@ pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_GE, 2, false);
 pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(ord) = cmp_exact_nums(&args[0], &args[1]) {
+        return Ok(DataValue::Bool(ord != Ordering::Less));
+    }
     Ok(DataValue::Bool(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l as f64 >= *r as f64,
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r as f64,
+        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l >= *r as f64,
+        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r,
         (a, b) => a >= b,
     }))
 }
@@ -115,9 +204,12 @@ pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_LT, 2, false);
 pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(ord) = cmp_exact_nums(&args[0], &args[1]) {
+        return Ok(DataValue::Bool(ord == Ordering::Less));
+    }
     Ok(DataValue::Bool(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => (*l as f64) < (*r as f64),
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < (*r as f64),
+        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l < (*r as f64),
+        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < *r,
         (a, b) => a < b,
     }))
 }
@@ -125,31 +217,94 @@ pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
 define_op!(OP_LE, 2, false);
 pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
     ensure_same_value_type(&args[0], &args[1])?;
+    if let Some(ord) = cmp_exact_nums(&args[0], &args[1]) {
+        return Ok(DataValue::Bool(ord != Ordering::Greater));
+    }
     Ok(DataValue::Bool(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => (*l as f64) <= (*r as f64),
-        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= (*r as f64),
+        (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l <= (*r as f64),
+        (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= *r,
         (a, b) => a <= b,
     }))
 }
 
-define_op!(OP_ADD, 0, true);
-pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
+/// The timestamp `secs` seconds after the one given in microseconds since the epoch.
+fn shift_timestamp(micros: i64, secs: f64) -> Result<DataValue> {
+    let delta = (secs * 1e6).round();
+    ensure!(
+        delta.is_finite() && delta.abs() < i64::MAX as f64,
+        "timestamp out of range"
+    );
+    micros
+        .checked_add(delta as i64)
+        .map(DataValue::Timestamp)
+        .ok_or_else(|| miette!("timestamp out of range"))
+}
+
+fn sum_nums(
+    args: &[DataValue],
+    add: impl Fn(i64, i64) -> Option<i64>,
+) -> Result<Option<DataValue>> {
+    // a timestamp plus numbers of seconds is a timestamp
+    if args
+        .iter()
+        .any(|arg| matches!(arg, DataValue::Timestamp(_)))
+    {
+        let mut timestamp = None;
+        let mut secs = 0.;
+        for arg in args {
+            match arg {
+                DataValue::Timestamp(t) => {
+                    ensure!(timestamp.is_none(), "timestamps cannot be added together");
+                    timestamp = Some(*t);
+                }
+                v => {
+                    secs += v
+                        .get_float()
+                        .ok_or_else(|| miette!("addition requires numbers"))?
+                }
+            }
+        }
+        return shift_timestamp(timestamp.unwrap(), secs).map(Some);
+    }
+    if let Some(kind) = exact_kind(args) {
+        let sum = exact_args(args, "addition")?.into_iter().sum();
+        return Ok(Some(exact_result(kind, sum)));
+    }
     let mut i_accum = 0i64;
     let mut f_accum = 0.0f64;
     for arg in args {
         match arg {
-            DataValue::Num(Num::Int(i)) => i_accum += i,
+            DataValue::Num(Num::Int(i)) => match add(i_accum, *i) {
+                Some(sum) => i_accum = sum,
+                None => return Ok(None),
+            },
             DataValue::Num(Num::Float(f)) => f_accum += f,
+            v @ (DataValue::BigInt(_) | DataValue::Decimal(_)) => f_accum += v.get_float().unwrap(),
             _ => bail!("addition requires numbers"),
         }
     }
     if f_accum == 0.0f64 {
-        Ok(DataValue::Num(Num::Int(i_accum)))
+        Ok(Some(DataValue::Num(Num::Int(i_accum))))
     } else {
-        Ok(DataValue::Num(Num::Float(i_accum as f64 + f_accum)))
+        Ok(Some(DataValue::Num(Num::Float(i_accum as f64 + f_accum))))
     }
 }
 
+define_op!(OP_ADD, 0, true);
+pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
+    sum_nums(args, i64::checked_add)?.ok_or_else(|| miette!("integer overflow in addition"))
+}
+
+define_op!(OP_CHECKED_ADD, 0, true);
+pub(crate) fn op_checked_add(args: &[DataValue]) -> Result<DataValue> {
+    Ok(sum_nums(args, i64::checked_add)?.unwrap_or(DataValue::Null))
+}
+
+define_op!(OP_SATURATING_ADD, 0, true);
+pub(crate) fn op_saturating_add(args: &[DataValue]) -> Result<DataValue> {
+    Ok(sum_nums(args, |a, b| Some(a.saturating_add(b)))?.unwrap())
+}
+
 define_op!(OP_MAX, 1, true);
 pub(crate) fn op_max(args: &[DataValue]) -> Result<DataValue> {
     let res = args
@@ -180,12 +335,62 @@ pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
-define_op!(OP_SUB, 2, false);
-pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
-    Ok(match (&args[0], &args[1]) {
-        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Int(*a - *b))
+define_op!(OP_COALESCE, 0, true);
+pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
+    Ok(args
+        .iter()
+        .find(|v| **v != DataValue::Null)
+        .cloned()
+        .unwrap_or(DataValue::Null))
+}
+
+define_op!(OP_GREATEST, 1, true);
+pub(crate) fn op_greatest(args: &[DataValue]) -> Result<DataValue> {
+    // nulls are skipped, values of different types are compared as keys are
+    Ok(args
+        .iter()
+        .filter(|v| **v != DataValue::Null)
+        .max()
+        .cloned()
+        .unwrap_or(DataValue::Null))
+}
+
+define_op!(OP_LEAST, 1, true);
+pub(crate) fn op_least(args: &[DataValue]) -> Result<DataValue> {
+    Ok(args
+        .iter()
+        .filter(|v| **v != DataValue::Null)
+        .min()
+        .cloned()
+        .unwrap_or(DataValue::Null))
+}
+
+fn sub_nums(
+    args: &[DataValue],
+    sub: impl Fn(i64, i64) -> Option<i64>,
+) -> Result<Option<DataValue>> {
+    match (&args[0], &args[1]) {
+        // the interval between two timestamps is in seconds
+        (DataValue::Timestamp(a), DataValue::Timestamp(b)) => {
+            return Ok(a.checked_sub(*b).map(|d| DataValue::from(d as f64 / 1e6)))
         }
+        (DataValue::Timestamp(a), b) => {
+            let secs = b.get_float().ok_or_else(|| {
+                miette!("only numbers of seconds can be subtracted from timestamps")
+            })?;
+            return shift_timestamp(*a, -secs).map(Some);
+        }
+        _ => {}
+    }
+    if let Some(kind) = exact_kind(args) {
+        let [a, b]: [BigDecimal; 2] = exact_args(args, "subtraction")?.try_into().unwrap();
+        return Ok(Some(exact_result(kind, a - b)));
+    }
+    Ok(Some(match (&args[0], &args[1]) {
+        (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => match sub(*a, *b) {
+            Some(diff) => DataValue::Num(Num::Int(diff)),
+            None => return Ok(None),
+        },
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Float(b))) => {
             DataValue::Num(Num::Float(*a - *b))
         }
@@ -195,30 +400,80 @@ pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float(a - (*b as f64)))
         }
-        _ => bail!("subtraction requires numbers"),
-    })
+        (a, b) => match (a.get_float(), b.get_float()) {
+            (Some(a), Some(b)) => DataValue::Num(Num::Float(a - b)),
+            _ => bail!("subtraction requires numbers"),
+        },
+    }))
 }
 
-define_op!(OP_MUL, 0, true);
-pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
+define_op!(OP_SUB, 2, false);
+pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
+    sub_nums(args, i64::checked_sub)?.ok_or_else(|| miette!("integer overflow in subtraction"))
+}
+
+define_op!(OP_CHECKED_SUB, 2, false);
+pub(crate) fn op_checked_sub(args: &[DataValue]) -> Result<DataValue> {
+    Ok(sub_nums(args, i64::checked_sub)?.unwrap_or(DataValue::Null))
+}
+
+define_op!(OP_SATURATING_SUB, 2, false);
+pub(crate) fn op_saturating_sub(args: &[DataValue]) -> Result<DataValue> {
+    Ok(sub_nums(args, |a, b| Some(a.saturating_sub(b)))?.unwrap())
+}
+
+fn mul_nums(
+    args: &[DataValue],
+    mul: impl Fn(i64, i64) -> Option<i64>,
+) -> Result<Option<DataValue>> {
+    if let Some(kind) = exact_kind(args) {
+        let prod = exact_args(args, "multiplication")?
+            .into_iter()
+            .fold(BigDecimal::from(1), |acc, x| acc * x);
+        return Ok(Some(exact_result(kind, prod)));
+    }
     let mut i_accum = 1i64;
     let mut f_accum = 1.0f64;
     for arg in args {
         match arg {
-            DataValue::Num(Num::Int(i)) => i_accum *= i,
+            DataValue::Num(Num::Int(i)) => match mul(i_accum, *i) {
+                Some(prod) => i_accum = prod,
+                None => return Ok(None),
+            },
             DataValue::Num(Num::Float(f)) => f_accum *= f,
+            v @ (DataValue::BigInt(_) | DataValue::Decimal(_)) => f_accum *= v.get_float().unwrap(),
             _ => bail!("multiplication requires numbers"),
         }
     }
     if f_accum == 1.0f64 {
-        Ok(DataValue::Num(Num::Int(i_accum)))
+        Ok(Some(DataValue::Num(Num::Int(i_accum))))
     } else {
-        Ok(DataValue::Num(Num::Float(i_accum as f64 * f_accum)))
+        Ok(Some(DataValue::Num(Num::Float(i_accum as f64 * f_accum))))
     }
 }
 
+define_op!(OP_MUL, 0, true);
+pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
+    mul_nums(args, i64::checked_mul)?.ok_or_else(|| miette!("integer overflow in multiplication"))
+}
+
+define_op!(OP_CHECKED_MUL, 0, true);
+pub(crate) fn op_checked_mul(args: &[DataValue]) -> Result<DataValue> {
+    Ok(mul_nums(args, i64::checked_mul)?.unwrap_or(DataValue::Null))
+}
+
+define_op!(OP_SATURATING_MUL, 0, true);
+pub(crate) fn op_saturating_mul(args: &[DataValue]) -> Result<DataValue> {
+    Ok(mul_nums(args, |a, b| Some(a.saturating_mul(b)))?.unwrap())
+}
+
 define_op!(OP_DIV, 2, false);
 pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
+    if exact_kind(args).is_some() {
+        let [a, b]: [BigDecimal; 2] = exact_args(args, "division")?.try_into().unwrap();
+        ensure!(!b.is_zero(), "division by zero");
+        return Ok(DataValue::Decimal(a / b));
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float((*a as f64) / (*b as f64)))
@@ -232,15 +487,33 @@ pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float(a / (*b as f64)))
         }
-        _ => bail!("division requires numbers"),
+        (a, b) => match (a.get_float(), b.get_float()) {
+            (Some(a), Some(b)) => DataValue::Num(Num::Float(a / b)),
+            _ => bail!("division requires numbers"),
+        },
     })
 }
 
+define_op!(OP_DIV_SAFE, 2, true);
+pub(crate) fn op_div_safe(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 3,
+        "'div_safe' takes a numerator, a denominator and an optional default"
+    );
+    if args[1].is_zero_num() {
+        Ok(args.get(2).cloned().unwrap_or(DataValue::Null))
+    } else {
+        op_div(&args[..2])
+    }
+}
+
 define_op!(OP_MINUS, 1, false);
 pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(-(*i))),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(-(*f))),
+        DataValue::BigInt(i) => DataValue::BigInt(-i.clone()),
+        DataValue::Decimal(d) => DataValue::Decimal(-d.clone()),
         _ => bail!("minus can only be applied to numbers"),
     })
 }
@@ -250,6 +523,8 @@ pub(crate) fn op_abs(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(i.abs())),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.abs())),
+        DataValue::BigInt(i) => DataValue::BigInt(i.abs()),
+        DataValue::Decimal(d) => DataValue::Decimal(d.abs()),
         _ => bail!("'abs' requires numbers"),
     })
 }
@@ -269,6 +544,8 @@ pub(crate) fn op_signum(args: &[DataValue]) -> Result<DataValue> {
                 DataValue::from(f64::NAN)
             }
         }
+        DataValue::BigInt(i) => DataValue::from(i.signum().to_i64().unwrap_or(0)),
+        DataValue::Decimal(d) => DataValue::from(d.signum().to_i64().unwrap_or(0)),
         _ => bail!("'signum' requires numbers"),
     })
 }
@@ -278,6 +555,15 @@ pub(crate) fn op_floor(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.floor())),
+        DataValue::BigInt(i) => DataValue::BigInt(i.clone()),
+        DataValue::Decimal(d) => {
+            let truncated = d.with_scale(0);
+            DataValue::Decimal(if truncated > *d {
+                truncated - BigDecimal::from(1)
+            } else {
+                truncated
+            })
+        }
         _ => bail!("'floor' requires numbers"),
     })
 }
@@ -287,6 +573,15 @@ pub(crate) fn op_ceil(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.ceil())),
+        DataValue::BigInt(i) => DataValue::BigInt(i.clone()),
+        DataValue::Decimal(d) => {
+            let truncated = d.with_scale(0);
+            DataValue::Decimal(if truncated < *d {
+                truncated + BigDecimal::from(1)
+            } else {
+                truncated
+            })
+        }
         _ => bail!("'ceil' requires numbers"),
     })
 }
@@ -296,6 +591,8 @@ pub(crate) fn op_round(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(*i)),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.round())),
+        DataValue::BigInt(i) => DataValue::BigInt(i.clone()),
+        DataValue::Decimal(d) => DataValue::Decimal(d.round(0)),
         _ => bail!("'round' requires numbers"),
     })
 }
@@ -503,9 +800,15 @@ pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MOD, 2, false);
 pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
+    if let Some(kind) = exact_kind(args) {
+        let [a, b]: [BigDecimal; 2] = exact_args(args, "'mod'")?.try_into().unwrap();
+        ensure!(!b.is_zero(), "'mod' by zero");
+        return Ok(exact_result(kind, a % b));
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
-            DataValue::Num(Num::Int(a.rem(b)))
+            ensure!(*b != 0, "'mod' by zero");
+            DataValue::Num(Num::Int(a.wrapping_rem(*b)))
         }
         (DataValue::Num(Num::Float(a)), DataValue::Num(Num::Float(b))) => {
             DataValue::Num(Num::Float(a.rem(*b)))
@@ -520,6 +823,19 @@ pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_MOD_SAFE, 2, true);
+pub(crate) fn op_mod_safe(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 3,
+        "'mod_safe' takes a dividend, a divisor and an optional default"
+    );
+    if args[1].is_zero_num() {
+        Ok(args.get(2).cloned().unwrap_or(DataValue::Null))
+    } else {
+        op_mod(&args[..2])
+    }
+}
+
 define_op!(OP_AND, 0, true);
 pub(crate) fn op_and(args: &[DataValue]) -> Result<DataValue> {
     for arg in args {
@@ -787,15 +1103,36 @@ pub(crate) fn op_ends_with(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Bool(a.ends_with(b as &str)))
 }
 
+const REGEX_CACHE_CAPACITY: usize = 1024;
+
+thread_local! {
+    /// Compiled patterns, so that patterns only known at evaluation time, such as those
+    /// read from rows, are compiled once per thread instead of once per row.
+    static REGEX_CACHE: RefCell<BTreeMap<SmartString<LazyCompact>, regex::Regex>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+fn compile_regex(pattern: &SmartString<LazyCompact>) -> Result<regex::Regex> {
+    REGEX_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if let Some(compiled) = cache.get(pattern) {
+            return Ok(compiled.clone());
+        }
+        let compiled = regex::Regex::new(pattern)
+            .map_err(|err| miette!("The string cannot be interpreted as regex: {}", err))?;
+        if cache.len() >= REGEX_CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(pattern.clone(), compiled.clone());
+        Ok(compiled)
+    })
+}
+
 define_op!(OP_REGEX, 1, false);
 pub(crate) fn op_regex(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         r @ DataValue::Regex(_) => r.clone(),
-        DataValue::Str(s) => {
-            DataValue::Regex(RegexWrapper(regex::Regex::new(s).map_err(|err| {
-                miette!("The string cannot be interpreted as regex: {}", err)
-            })?))
-        }
+        DataValue::Str(s) => DataValue::Regex(RegexWrapper(compile_regex(s)?)),
         _ => bail!("'regex' requires strings"),
     })
 }
@@ -880,14 +1217,34 @@ define_op!(OP_IS_NUM, 1, false);
 pub(crate) fn op_is_num(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Bool(matches!(
         args[0],
-        DataValue::Num(Num::Int(_)) | DataValue::Num(Num::Float(_))
+        DataValue::Num(_) | DataValue::BigInt(_) | DataValue::Decimal(_)
     )))
 }
 
+define_op!(OP_IS_BIGINT, 1, false);
+pub(crate) fn op_is_bigint(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Bool(matches!(args[0], DataValue::BigInt(_))))
+}
+
+define_op!(OP_IS_TIMESTAMP, 1, false);
+pub(crate) fn op_is_timestamp(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Bool(matches!(args[0], DataValue::Timestamp(_))))
+}
+
+define_op!(OP_IS_JSON, 1, false);
+pub(crate) fn op_is_json(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Bool(matches!(args[0], DataValue::Json(_))))
+}
+
+define_op!(OP_IS_DECIMAL, 1, false);
+pub(crate) fn op_is_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Bool(matches!(args[0], DataValue::Decimal(_))))
+}
+
 define_op!(OP_IS_FINITE, 1, false);
 pub(crate) fn op_is_finite(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Bool(match &args[0] {
-        DataValue::Num(Num::Int(_)) => true,
+        DataValue::Num(Num::Int(_)) | DataValue::BigInt(_) | DataValue::Decimal(_) => true,
         DataValue::Num(Num::Float(f)) => f.is_finite(),
         _ => false,
     }))
@@ -914,6 +1271,11 @@ pub(crate) fn op_is_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Bool(matches!(args[0], DataValue::Str(_))))
 }
 
+define_op!(OP_IS_SET, 1, false);
+pub(crate) fn op_is_set(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Bool(matches!(args[0], DataValue::Set(_))))
+}
+
 define_op!(OP_IS_LIST, 1, false);
 pub(crate) fn op_is_list(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Bool(matches!(
@@ -968,6 +1330,7 @@ pub(crate) fn op_length(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => l.len() as i64,
         DataValue::Str(s) => s.chars().count() as i64,
         DataValue::Bytes(b) => b.len() as i64,
+        DataValue::Vec(v) => v.0.len() as i64,
         _ => bail!("'length' requires lists"),
     }))
 }
@@ -1244,6 +1607,115 @@ pub(crate) fn op_decode_base64(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_BYTES_SLICE, 3, false);
+pub(crate) fn op_bytes_slice(args: &[DataValue]) -> Result<DataValue> {
+    let b = match &args[0] {
+        DataValue::Bytes(b) => b,
+        _ => bail!("first argument to 'bytes_slice' must be bytes"),
+    };
+    let bound = |arg: &DataValue, pos: &str| -> Result<usize> {
+        let mut i = arg
+            .get_int()
+            .ok_or_else(|| miette!("{} argument to 'bytes_slice' must be an integer", pos))?;
+        if i < 0 {
+            i += b.len() as i64;
+        }
+        ensure!(
+            i >= 0 && i as usize <= b.len(),
+            "index {} out of bound for 'bytes_slice'",
+            i
+        );
+        Ok(i as usize)
+    };
+    let start = bound(&args[1], "second")?;
+    let end = bound(&args[2], "third")?;
+    ensure!(
+        start <= end,
+        "start {} of 'bytes_slice' is after end {}",
+        start,
+        end
+    );
+    Ok(DataValue::Bytes(b[start..end].to_vec()))
+}
+
+define_op!(OP_ENCODE_HEX, 1, false);
+pub(crate) fn op_encode_hex(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Bytes(b) => {
+            let mut s = SmartString::new();
+            for byte in b {
+                write!(s, "{:02x}", byte).unwrap();
+            }
+            Ok(DataValue::Str(s))
+        }
+        _ => bail!("'encode_hex' requires bytes"),
+    }
+}
+
+define_op!(OP_DECODE_HEX, 1, false);
+pub(crate) fn op_decode_hex(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Str(s) => {
+            ensure!(s.len() % 2 == 0, "Data is not properly encoded");
+            let b = (0..s.len())
+                .step_by(2)
+                .map(|i| {
+                    s.get(i..i + 2)
+                        .and_then(|d| u8::from_str_radix(d, 16).ok())
+                        .ok_or_else(|| miette!("Data is not properly encoded"))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(DataValue::Bytes(b))
+        }
+        _ => bail!("'decode_hex' requires strings"),
+    }
+}
+
+/// The bytes hashed by [op_sha256] and [op_blake3]: bytes as they are and strings as UTF-8.
+fn hash_input<'a>(arg: &'a DataValue, name: &str) -> Result<&'a [u8]> {
+    match arg {
+        DataValue::Bytes(b) => Ok(b),
+        DataValue::Str(s) => Ok(s.as_bytes()),
+        _ => bail!("'{}' requires bytes or strings", name),
+    }
+}
+
+define_op!(OP_SHA256, 1, false);
+pub(crate) fn op_sha256(args: &[DataValue]) -> Result<DataValue> {
+    let digest = Sha256::digest(hash_input(&args[0], "sha256")?);
+    Ok(DataValue::Bytes(digest.to_vec()))
+}
+
+define_op!(OP_BLAKE3, 1, false);
+pub(crate) fn op_blake3(args: &[DataValue]) -> Result<DataValue> {
+    let digest = blake3::hash(hash_input(&args[0], "blake3")?);
+    Ok(DataValue::Bytes(digest.as_bytes().to_vec()))
+}
+
+/// Offset basis of the 64-bit FNV-1a hash, the initial state for [tuple_hash].
+pub(crate) const TUPLE_HASH_INIT: u64 = 0xcbf29ce484222325;
+
+/// Continues a stable 64-bit FNV-1a hash over the memcmp encoding of `values`.
+/// The encoding is self-delimiting, so hashing rows one after another is the same
+/// as hashing their concatenation.
+pub(crate) fn tuple_hash(mut hash: u64, values: &[DataValue]) -> u64 {
+    let mut encoded = vec![];
+    for val in values {
+        encoded.encode_datavalue(val);
+    }
+    for b in encoded {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+define_op!(OP_TUPLE_HASH, 0, true);
+pub(crate) fn op_tuple_hash(args: &[DataValue]) -> Result<DataValue> {
+    let hash = tuple_hash(TUPLE_HASH_INIT, args);
+    Ok(DataValue::Str(SmartString::from(format!("{:016x}", hash))))
+}
+
 define_op!(OP_TO_BOOL, 1, false);
 pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Bool(match &args[0] {
@@ -1256,8 +1728,13 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::Regex(r) => !r.0.as_str().is_empty(),
         DataValue::List(l) => !l.is_empty(),
         DataValue::Set(s) => !s.is_empty(),
+        DataValue::Vec(v) => !v.0.is_empty(),
         DataValue::Guard => false,
         DataValue::Bot => false,
+        DataValue::BigInt(i) => !i.is_zero(),
+        DataValue::Decimal(d) => !d.is_zero(),
+        DataValue::Timestamp(t) => *t != 0,
+        DataValue::Json(j) => !j.0.is_null(),
     }))
 }
 
@@ -1265,6 +1742,8 @@ define_op!(OP_TO_FLOAT, 1, false);
 pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(n) => n.get_float().into(),
+        v @ (DataValue::BigInt(_) | DataValue::Decimal(_)) => v.get_float().unwrap().into(),
+        DataValue::Timestamp(t) => (*t as f64 / 1e6).into(),
         DataValue::Str(t) => match t as &str {
             "PI" => f64::PI().into(),
             "E" => f64::E().into(),
@@ -1279,6 +1758,199 @@ pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_TO_BIGINT, 1, false);
+pub(crate) fn op_to_bigint(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::BigInt(match &args[0] {
+        DataValue::Num(Num::Int(i)) => BigInt::from(*i),
+        DataValue::Num(Num::Float(f)) => {
+            ensure!(f.is_finite(), "'to_bigint' cannot convert {}", f);
+            BigInt::from_f64(f.trunc()).unwrap()
+        }
+        DataValue::BigInt(i) => i.clone(),
+        DataValue::Decimal(d) => d.with_scale(0).as_bigint_and_exponent().0,
+        DataValue::Str(s) => BigInt::from_str(s.trim())
+            .map_err(|_| miette!("The string cannot be interpreted as an integer"))?,
+        v => bail!("'to_bigint' does not recognize {:?}", v),
+    }))
+}
+
+define_op!(OP_TO_DECIMAL, 1, false);
+pub(crate) fn op_to_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Decimal(match &args[0] {
+        DataValue::Num(Num::Float(f)) => {
+            ensure!(f.is_finite(), "'to_decimal' cannot convert {}", f);
+            // the shortest representation that round-trips, not the binary expansion
+            BigDecimal::from_str(&f.to_string()).unwrap()
+        }
+        DataValue::Str(s) => BigDecimal::from_str(s.trim())
+            .map_err(|_| miette!("The string cannot be interpreted as a decimal"))?,
+        v => v
+            .get_exact()
+            .ok_or_else(|| miette!("'to_decimal' does not recognize {:?}", v))?,
+    }))
+}
+
+define_op!(OP_PARSE_DECIMAL, 1, false);
+pub(crate) fn op_parse_decimal(args: &[DataValue]) -> Result<DataValue> {
+    let s = args[0]
+        .get_string()
+        .ok_or_else(|| miette!("'parse_decimal' requires strings"))?
+        .trim();
+    // accounting notation: (12.50) is negative
+    let (negative, s) = match s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+        Some(inner) => (true, inner.trim()),
+        None => (false, s),
+    };
+    // the decimal point is always '.', while ',', '_' and spaces may group digits
+    let cleaned: String = s
+        .chars()
+        .filter(|c| !matches!(c, ',' | '_' | ' '))
+        .collect();
+    let d = BigDecimal::from_str(&cleaned)
+        .map_err(|_| miette!("The string '{}' cannot be interpreted as a decimal", s))?;
+    ensure!(
+        !negative || !cleaned.starts_with('-'),
+        "The string '{}' cannot be interpreted as a decimal",
+        s
+    );
+    Ok(DataValue::Decimal(if negative { -d } else { d }))
+}
+
+fn get_decimal_places(v: &DataValue, what: &str) -> Result<i64> {
+    match v.get_int() {
+        Some(i) if (0..=1000).contains(&i) => Ok(i),
+        _ => bail!(
+            "'{}' requires the number of decimal places to be a small non-negative integer",
+            what
+        ),
+    }
+}
+
+fn get_exact_for_rounding(v: &DataValue, what: &str) -> Result<BigDecimal> {
+    match v {
+        DataValue::Num(Num::Float(_)) => {
+            Ok(op_to_decimal(std::slice::from_ref(v))?.get_exact().unwrap())
+        }
+        v => v
+            .get_exact()
+            .ok_or_else(|| miette!("'{}' requires numbers", what)),
+    }
+}
+
+/// Rounds to the given number of places after the decimal point.
+///
+/// The modes are those of accounting practice: `half_even` (banker's rounding),
+/// `half_up` and `half_down` (ties away from and towards zero), `up` and `down`
+/// (away from and towards zero), `ceiling` and `floor`.
+fn round_decimal(d: &BigDecimal, places: i64, mode: &str) -> Result<BigDecimal> {
+    ensure!(
+        matches!(
+            mode,
+            "half_even" | "half_up" | "half_down" | "up" | "down" | "ceiling" | "floor"
+        ),
+        "unknown rounding mode '{}'",
+        mode
+    );
+    let (mantissa, scale) = d.as_bigint_and_exponent();
+    if scale <= places {
+        return Ok(d.with_scale(places));
+    }
+    let divisor = num_traits::pow(BigInt::from(10), (scale - places) as usize);
+    let quotient = &mantissa / &divisor;
+    let remainder = &mantissa % &divisor;
+    let twice_rem = remainder.abs() * 2;
+    let away = match mode {
+        "half_even" => {
+            twice_rem > divisor
+                || (twice_rem == divisor && !(&quotient % BigInt::from(2)).is_zero())
+        }
+        "half_up" => twice_rem >= divisor,
+        "half_down" => twice_rem > divisor,
+        "up" => !remainder.is_zero(),
+        "down" => false,
+        "ceiling" => remainder.is_positive(),
+        _ => remainder.is_negative(),
+    };
+    let quotient = if !away {
+        quotient
+    } else if mantissa.is_negative() {
+        quotient - 1
+    } else {
+        quotient + 1
+    };
+    Ok(BigDecimal::new(quotient, places))
+}
+
+define_op!(OP_ROUND_DECIMAL, 2, true);
+pub(crate) fn op_round_decimal(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 3,
+        "'round_decimal' takes a number, the decimal places and an optional rounding mode"
+    );
+    let d = get_exact_for_rounding(&args[0], "round_decimal")?;
+    let places = get_decimal_places(&args[1], "round_decimal")?;
+    let mode = match args.get(2) {
+        None | Some(DataValue::Null) => "half_even",
+        Some(m) => m
+            .get_string()
+            .ok_or_else(|| miette!("'round_decimal' requires the rounding mode to be a string"))?,
+    };
+    Ok(DataValue::Decimal(round_decimal(&d, places, mode)?))
+}
+
+define_op!(OP_FORMAT_DECIMAL, 2, true);
+pub(crate) fn op_format_decimal(args: &[DataValue]) -> Result<DataValue> {
+    ensure!(
+        args.len() <= 4,
+        "'format_decimal' takes a number, the decimal places, an optional rounding mode \
+        and an optional group separator"
+    );
+    let d = get_exact_for_rounding(&args[0], "format_decimal")?;
+    let places = get_decimal_places(&args[1], "format_decimal")?;
+    let mode = match args.get(2) {
+        None | Some(DataValue::Null) => "half_even",
+        Some(m) => m
+            .get_string()
+            .ok_or_else(|| miette!("'format_decimal' requires the rounding mode to be a string"))?,
+    };
+    let separator = match args.get(3) {
+        None | Some(DataValue::Null) => "",
+        Some(sep) => sep.get_string().ok_or_else(|| {
+            miette!("'format_decimal' requires the group separator to be a string")
+        })?,
+    };
+    let rounded = round_decimal(&d, places, mode)?;
+    // the sign is dropped when everything was rounded away
+    let formatted = if rounded.is_zero() {
+        rounded.abs().to_string()
+    } else {
+        rounded.to_string()
+    };
+    if separator.is_empty() {
+        return Ok(DataValue::Str(SmartString::from(formatted)));
+    }
+    let (sign, unsigned) = match formatted.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", formatted.as_str()),
+    };
+    let (int_part, frac_part) = match unsigned.split_once('.') {
+        Some((i, f)) => (i, Some(f)),
+        None => (unsigned, None),
+    };
+    let mut grouped = String::from(sign);
+    for (i, c) in int_part.chars().enumerate() {
+        if i > 0 && (int_part.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(c);
+    }
+    if let Some(frac) = frac_part {
+        grouped.push('.');
+        grouped.push_str(frac);
+    }
+    Ok(DataValue::Str(SmartString::from(grouped)))
+}
+
 define_op!(OP_TO_STRING, 1, false);
 pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
@@ -1349,6 +2021,24 @@ pub(crate) fn op_assert(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+/// Set operations return sets if their first argument is one, and sorted lists otherwise.
+fn set_op_result(first: &DataValue, result: BTreeSet<DataValue>) -> DataValue {
+    if let DataValue::Set(_) = first {
+        DataValue::Set(result)
+    } else {
+        DataValue::List(result.into_iter().collect())
+    }
+}
+
+define_op!(OP_TO_SET, 1, false);
+pub(crate) fn op_to_set(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        DataValue::List(l) => DataValue::Set(l.iter().cloned().collect()),
+        s @ DataValue::Set(_) => s.clone(),
+        _ => bail!("'to_set' requires a list or a set"),
+    })
+}
+
 define_op!(OP_UNION, 1, true);
 pub(crate) fn op_union(args: &[DataValue]) -> Result<DataValue> {
     let mut ret = BTreeSet::new();
@@ -1364,10 +2054,10 @@ pub(crate) fn op_union(args: &[DataValue]) -> Result<DataValue> {
                     ret.insert(el.clone());
                 }
             }
-            _ => bail!("'union' requires lists"),
+            _ => bail!("'union' requires lists or sets"),
         }
     }
-    Ok(DataValue::List(ret.into_iter().collect()))
+    Ok(set_op_result(&args[0], ret))
 }
 
 define_op!(OP_DIFFERENCE, 2, true);
@@ -1375,7 +2065,7 @@ pub(crate) fn op_difference(args: &[DataValue]) -> Result<DataValue> {
     let mut start: BTreeSet<_> = match &args[0] {
         DataValue::List(l) => l.iter().cloned().collect(),
         DataValue::Set(s) => s.iter().cloned().collect(),
-        _ => bail!("'difference' requires lists"),
+        _ => bail!("'difference' requires lists or sets"),
     };
     for arg in &args[1..] {
         match arg {
@@ -1389,10 +2079,10 @@ pub(crate) fn op_difference(args: &[DataValue]) -> Result<DataValue> {
                     start.remove(el);
                 }
             }
-            _ => bail!("'difference' requires lists"),
+            _ => bail!("'difference' requires lists or sets"),
         }
     }
-    Ok(DataValue::List(start.into_iter().collect()))
+    Ok(set_op_result(&args[0], start))
 }
 
 define_op!(OP_INTERSECTION, 1, true);
@@ -1400,7 +2090,7 @@ pub(crate) fn op_intersection(args: &[DataValue]) -> Result<DataValue> {
     let mut start: BTreeSet<_> = match &args[0] {
         DataValue::List(l) => l.iter().cloned().collect(),
         DataValue::Set(s) => s.iter().cloned().collect(),
-        _ => bail!("'intersection' requires lists"),
+        _ => bail!("'intersection' requires lists or sets"),
     };
     for arg in &args[1..] {
         match arg {
@@ -1409,10 +2099,98 @@ pub(crate) fn op_intersection(args: &[DataValue]) -> Result<DataValue> {
                 start = start.intersection(&other).cloned().collect();
             }
             DataValue::Set(s) => start = start.intersection(s).cloned().collect(),
-            _ => bail!("'intersection' requires lists"),
+            _ => bail!("'intersection' requires lists or sets"),
         }
     }
-    Ok(DataValue::List(start.into_iter().collect()))
+    Ok(set_op_result(&args[0], start))
+}
+
+define_op!(OP_VEC, 1, false);
+pub(crate) fn op_vec(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Vec(Vector(get_vector(&args[0], "vec")?)))
+}
+
+define_op!(OP_IS_VEC, 1, false);
+pub(crate) fn op_is_vec(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Bool(matches!(args[0], DataValue::Vec(_))))
+}
+
+/// Reads a vector, or a list of numbers, as 32-bit floats.
+pub(crate) fn get_vector(arg: &DataValue, op_name: &str) -> Result<Vec<f32>> {
+    match arg {
+        DataValue::Vec(v) => Ok(v.0.clone()),
+        DataValue::List(l) => l
+            .iter()
+            .map(|el| el.get_float().map(|f| f as f32))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| miette!("'{}' requires vectors or lists of numbers", op_name)),
+        _ => bail!("'{}' requires vectors or lists of numbers", op_name),
+    }
+}
+
+fn get_vector_pair(args: &[DataValue], op_name: &str) -> Result<(Vec<f32>, Vec<f32>)> {
+    let a = get_vector(&args[0], op_name)?;
+    let b = get_vector(&args[1], op_name)?;
+    ensure!(
+        a.len() == b.len(),
+        "'{}' requires vectors of the same dimension, got {} and {}",
+        op_name,
+        a.len(),
+        b.len()
+    );
+    Ok((a, b))
+}
+
+/// Squared Euclidean distance.
+pub(crate) fn l2_dist(a: &[f32], b: &[f32]) -> f64 {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| {
+            let d = (*x as f64) - (*y as f64);
+            d * d
+        })
+        .sum()
+}
+
+/// One minus the cosine of the angle between the vectors.
+pub(crate) fn cos_dist(a: &[f32], b: &[f32]) -> f64 {
+    let mut dot = 0.;
+    let mut a_norm = 0.;
+    let mut b_norm = 0.;
+    for (x, y) in a.iter().zip(b) {
+        let (x, y) = (*x as f64, *y as f64);
+        dot += x * y;
+        a_norm += x * x;
+        b_norm += y * y;
+    }
+    1. - dot / (a_norm.sqrt() * b_norm.sqrt())
+}
+
+/// One minus the inner product, for normalized vectors.
+pub(crate) fn ip_dist(a: &[f32], b: &[f32]) -> f64 {
+    1. - a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| (*x as f64) * (*y as f64))
+        .sum::<f64>()
+}
+
+define_op!(OP_L2_DIST, 2, false);
+pub(crate) fn op_l2_dist(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = get_vector_pair(args, "l2_dist")?;
+    Ok(DataValue::from(l2_dist(&a, &b)))
+}
+
+define_op!(OP_COS_DIST, 2, false);
+pub(crate) fn op_cos_dist(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = get_vector_pair(args, "cos_dist")?;
+    Ok(DataValue::from(cos_dist(&a, &b)))
+}
+
+define_op!(OP_IP_DIST, 2, false);
+pub(crate) fn op_ip_dist(args: &[DataValue]) -> Result<DataValue> {
+    let (a, b) = get_vector_pair(args, "ip_dist")?;
+    Ok(DataValue::from(ip_dist(&a, &b)))
 }
 
 define_op!(OP_TO_UUID, 1, false);
@@ -1429,47 +2207,303 @@ pub(crate) fn op_to_uuid(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_NOW, 0, false);
 pub(crate) fn op_now(_args: &[DataValue]) -> Result<DataValue> {
+    // seconds since the epoch, so that the arithmetic and comparisons of existing scripts
+    // keep working: 'to_timestamp(now())' is the timestamp
     let now = SystemTime::now();
     Ok(DataValue::from(
         now.duration_since(UNIX_EPOCH).unwrap().as_secs_f64(),
     ))
 }
 
+/// The microseconds since the epoch of a timestamp, or of a number of seconds since the epoch.
+fn get_timestamp_micros(v: &DataValue, name: &str) -> Result<i64> {
+    match v {
+        DataValue::Timestamp(t) => Ok(*t),
+        v => match v.get_float().and_then(DataValue::timestamp_from_secs) {
+            Some(DataValue::Timestamp(t)) => Ok(t),
+            _ => bail!("'{}' expects a timestamp or a number of seconds", name),
+        },
+    }
+}
+
+fn format_datetime<Tz: TimeZone>(dt: DateTime<Tz>, format: Option<&str>) -> Result<String>
+where
+    Tz::Offset: Display,
+{
+    match format {
+        None => Ok(dt.to_rfc3339()),
+        Some(format) => {
+            let mut formatted = String::new();
+            write!(formatted, "{}", dt.format(format))
+                .map_err(|_| miette!("bad format for 'format_timestamp': {}", format))?;
+            Ok(formatted)
+        }
+    }
+}
+
 define_op!(OP_FORMAT_TIMESTAMP, 1, true);
 pub(crate) fn op_format_timestamp(args: &[DataValue]) -> Result<DataValue> {
-    let f = args[0]
-        .get_float()
-        .ok_or_else(|| miette!("'format_timestamp' expects a number"))?;
-    let millis = (f * 1000.) as i64;
-    let dt = Utc.timestamp_millis(millis);
-    match args.get(1) {
+    let micros = get_timestamp_micros(&args[0], "format_timestamp")?;
+    let dt = timestamp_to_datetime(micros).ok_or_else(|| miette!("timestamp out of range"))?;
+    let format = args
+        .get(2)
+        .map(|v| {
+            v.get_string()
+                .ok_or_else(|| miette!("'format_timestamp' format specification requires a string"))
+        })
+        .transpose()?;
+    let s = match args.get(1) {
+        None | Some(DataValue::Null) => format_datetime(dt, format)?,
         Some(tz_v) => {
             let tz_s = tz_v.get_string().ok_or_else(|| {
                 miette!("'format_timestamp' timezone specification requires a string")
             })?;
             let tz = chrono_tz::Tz::from_str(tz_s)
                 .map_err(|_| miette!("bad timezone specification: {}", tz_s))?;
-            let dt_tz = dt.with_timezone(&tz);
-            let s = SmartString::from(dt_tz.to_rfc3339());
-            Ok(DataValue::Str(s))
+            format_datetime(dt.with_timezone(&tz), format)?
         }
-        None => {
-            let s = SmartString::from(dt.to_rfc3339());
-            Ok(DataValue::Str(s))
-        }
-    }
+    };
+    Ok(DataValue::Str(SmartString::from(s)))
 }
 
-define_op!(OP_PARSE_TIMESTAMP, 1, false);
+define_op!(OP_PARSE_TIMESTAMP, 1, true);
 pub(crate) fn op_parse_timestamp(args: &[DataValue]) -> Result<DataValue> {
     let s = args[0]
         .get_string()
         .ok_or_else(|| miette!("'parse_timestamp' expects a string"))?;
-    let dt = DateTime::parse_from_rfc3339(s).map_err(|_| miette!("bad datetime: {}", s))?;
-    let st: SystemTime = dt.into();
-    Ok(DataValue::from(
-        st.duration_since(UNIX_EPOCH).unwrap().as_secs_f64(),
-    ))
+    let dt = match args.get(1) {
+        None => DateTime::parse_from_rfc3339(s)
+            .map_err(|_| miette!("bad datetime: {}", s))?
+            .with_timezone(&Utc),
+        Some(format_v) => {
+            let format = format_v.get_string().ok_or_else(|| {
+                miette!("'parse_timestamp' format specification requires a string")
+            })?;
+            // the format may leave out the offset, and even the time, which default to UTC
+            // and midnight
+            if let Ok(dt) = DateTime::parse_from_str(s, format) {
+                dt.with_timezone(&Utc)
+            } else if let Ok(dt) = NaiveDateTime::parse_from_str(s, format) {
+                Utc.from_utc_datetime(&dt)
+            } else {
+                let date = NaiveDate::parse_from_str(s, format)
+                    .map_err(|_| miette!("datetime {} does not match format {}", s, format))?;
+                Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+            }
+        }
+    };
+    let micros = dt
+        .timestamp()
+        .checked_mul(1_000_000)
+        .and_then(|t| t.checked_add(dt.timestamp_subsec_micros() as i64))
+        .ok_or_else(|| miette!("timestamp out of range"))?;
+    Ok(DataValue::Timestamp(micros))
+}
+
+define_op!(OP_TO_TIMESTAMP, 1, false);
+pub(crate) fn op_to_timestamp(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Str(_) => op_parse_timestamp(args),
+        v => get_timestamp_micros(v, "to_timestamp").map(DataValue::Timestamp),
+    }
+}
+
+/// The JSON form of a value: JSON values are taken as they are, other values are converted
+/// the same way as in query results.
+fn to_json_value(v: &DataValue) -> JsonValue {
+    match v {
+        DataValue::Json(j) => j.0.clone(),
+        v => JsonValue::from(v.clone()),
+    }
+}
+
+/// The value of a part of a JSON value: scalars become the corresponding values, whereas
+/// arrays and objects stay JSON values.
+fn from_json_value(v: &JsonValue) -> DataValue {
+    match v {
+        JsonValue::Array(_) | JsonValue::Object(_) => DataValue::Json(JsonData(v.clone())),
+        v => DataValue::from(v),
+    }
+}
+
+/// A path into a JSON value, either a list of object keys and array indices, or a string of
+/// them separated by dots. Negative indices count from the end of arrays.
+fn json_path(path: &DataValue, name: &str) -> Result<Vec<DataValue>> {
+    Ok(match path {
+        DataValue::Str(s) if s.is_empty() => vec![],
+        DataValue::Str(s) => s
+            .split('.')
+            .map(|seg| DataValue::Str(SmartString::from(seg)))
+            .collect(),
+        DataValue::List(l) => {
+            for seg in l {
+                ensure!(
+                    matches!(seg, DataValue::Str(_) | DataValue::Num(Num::Int(_))),
+                    "path segments of '{}' must be strings or integers, got {:?}",
+                    name,
+                    seg
+                );
+            }
+            l.clone()
+        }
+        v => bail!(
+            "'{}' expects a path given as a string or a list, got {:?}",
+            name,
+            v
+        ),
+    })
+}
+
+fn json_key(seg: &DataValue) -> String {
+    match seg {
+        DataValue::Str(s) => s.to_string(),
+        v => v.to_string(),
+    }
+}
+
+fn json_index(seg: &DataValue, len: usize) -> Option<usize> {
+    let i = match seg {
+        DataValue::Num(Num::Int(i)) => *i,
+        DataValue::Str(s) => s.parse::<i64>().ok()?,
+        _ => return None,
+    };
+    let i = if i < 0 { len as i64 + i } else { i };
+    if i < 0 {
+        None
+    } else {
+        Some(i as usize)
+    }
+}
+
+fn json_lookup<'a>(mut v: &'a JsonValue, path: &[DataValue]) -> Option<&'a JsonValue> {
+    for seg in path {
+        v = match v {
+            JsonValue::Object(o) => o.get(&json_key(seg))?,
+            JsonValue::Array(a) => a.get(json_index(seg, a.len())?)?,
+            _ => return None,
+        };
+    }
+    Some(v)
+}
+
+define_op!(OP_PARSE_JSON, 1, false);
+pub(crate) fn op_parse_json(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Str(s) => {
+            let j = serde_json::from_str(s).map_err(|e| miette!("invalid JSON: {}", e))?;
+            Ok(DataValue::Json(JsonData(j)))
+        }
+        v => bail!("'parse_json' expects a string, got {:?}", v),
+    }
+}
+
+define_op!(OP_TO_JSON, 1, false);
+pub(crate) fn op_to_json(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::Json(JsonData(to_json_value(&args[0]))))
+}
+
+define_op!(OP_JSON_GET, 2, false);
+pub(crate) fn op_json_get(args: &[DataValue]) -> Result<DataValue> {
+    let path = json_path(&args[1], "json_get")?;
+    let j = to_json_value(&args[0]);
+    Ok(json_lookup(&j, &path).map_or(DataValue::Null, from_json_value))
+}
+
+define_op!(OP_JSON_SET, 3, false);
+pub(crate) fn op_json_set(args: &[DataValue]) -> Result<DataValue> {
+    let path = json_path(&args[1], "json_set")?;
+    let mut j = to_json_value(&args[0]);
+    let mut target = &mut j;
+    for seg in &path {
+        if target.is_null() {
+            *target = JsonValue::Object(Default::default());
+        }
+        target = match target {
+            JsonValue::Object(o) => o.entry(json_key(seg)).or_insert(JsonValue::Null),
+            JsonValue::Array(a) => {
+                let len = a.len();
+                match json_index(seg, len) {
+                    Some(i) if i < len => &mut a[i],
+                    Some(i) if i == len => {
+                        a.push(JsonValue::Null);
+                        &mut a[i]
+                    }
+                    _ => bail!(
+                        "'json_set' cannot index an array of length {} with {:?}",
+                        len,
+                        seg
+                    ),
+                }
+            }
+            v => bail!("'json_set' cannot set {:?} inside {}", seg, v),
+        };
+    }
+    *target = to_json_value(&args[2]);
+    Ok(DataValue::Json(JsonData(j)))
+}
+
+define_op!(OP_JSON_REMOVE, 2, false);
+pub(crate) fn op_json_remove(args: &[DataValue]) -> Result<DataValue> {
+    let path = json_path(&args[1], "json_remove")?;
+    let mut j = to_json_value(&args[0]);
+    match path.split_last() {
+        None => j = JsonValue::Null,
+        Some((last, parents)) => {
+            let mut target = Some(&mut j);
+            for seg in parents {
+                target = match target {
+                    Some(JsonValue::Object(o)) => o.get_mut(&json_key(seg)),
+                    Some(JsonValue::Array(a)) => {
+                        let len = a.len();
+                        json_index(seg, len).and_then(move |i| a.get_mut(i))
+                    }
+                    _ => None,
+                };
+            }
+            match target {
+                Some(JsonValue::Object(o)) => {
+                    o.remove(&json_key(last));
+                }
+                Some(JsonValue::Array(a)) => {
+                    if let Some(i) = json_index(last, a.len()).filter(|i| *i < a.len()) {
+                        a.remove(i);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(DataValue::Json(JsonData(j)))
+}
+
+/// Applies `patch` to `target` as a JSON merge patch (RFC 7386): objects are merged key by
+/// key, null removes keys, and anything else replaces the target.
+fn json_merge_patch(target: &mut JsonValue, patch: &JsonValue) {
+    match patch {
+        JsonValue::Object(p) => {
+            if !target.is_object() {
+                *target = JsonValue::Object(Default::default());
+            }
+            let t = target.as_object_mut().unwrap();
+            for (k, v) in p {
+                if v.is_null() {
+                    t.remove(k);
+                } else {
+                    json_merge_patch(t.entry(k.clone()).or_insert(JsonValue::Null), v);
+                }
+            }
+        }
+        p => *target = p.clone(),
+    }
+}
+
+define_op!(OP_JSON_MERGE, 2, true);
+pub(crate) fn op_json_merge(args: &[DataValue]) -> Result<DataValue> {
+    let mut j = to_json_value(&args[0]);
+    for patch in &args[1..] {
+        json_merge_patch(&mut j, &to_json_value(patch));
+    }
+    Ok(DataValue::Json(JsonData(j)))
 }
 
 define_op!(OP_RAND_UUID_V1, 0, false);
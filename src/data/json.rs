@@ -6,7 +6,7 @@ use serde_json::json;
 pub(crate) use serde_json::Value as JsonValue;
 use smartstring::SmartString;
 
-use crate::data::value::{DataValue, Num};
+use crate::data::value::{format_timestamp, DataValue, Num};
 
 impl From<JsonValue> for DataValue {
     fn from(v: JsonValue) -> Self {
@@ -25,7 +25,9 @@ impl From<JsonValue> for DataValue {
             JsonValue::Object(d) => DataValue::List(
                 d.into_iter()
                     .map(|(k, v)| {
-                        DataValue::List([DataValue::Str(SmartString::from(k)), DataValue::from(v)].into())
+                        DataValue::List(
+                            [DataValue::Str(SmartString::from(k)), DataValue::from(v)].into(),
+                        )
                     })
                     .collect(),
             ),
@@ -89,12 +91,18 @@ impl From<DataValue> for JsonValue {
             DataValue::Set(l) => {
                 JsonValue::Array(l.iter().map(|v| JsonValue::from(v.clone())).collect())
             }
+            DataValue::Vec(v) => JsonValue::Array(v.0.iter().map(|f| json!(f)).collect()),
             DataValue::Regex(r) => {
                 json!(r.0.as_str())
             }
             DataValue::Uuid(u) => {
                 json!(u.0)
             }
+            // rendered as strings so that no precision is lost in transit
+            DataValue::BigInt(i) => JsonValue::String(i.to_string()),
+            DataValue::Decimal(d) => JsonValue::String(d.to_string()),
+            DataValue::Timestamp(t) => JsonValue::String(format_timestamp(t)),
+            DataValue::Json(j) => j.0,
         }
     }
 }
@@ -6,10 +6,15 @@ use std::collections::BTreeSet;
 use std::io::Write;
 use std::str::FromStr;
 
+use bigdecimal::BigDecimal;
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use num_bigint::{BigInt, Sign};
+use num_traits::ToPrimitive;
 use regex::Regex;
 
-use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper};
+use crate::data::value::{
+    decimal_floor_f64, DataValue, JsonData, Num, RegexWrapper, UuidWrapper, Vector,
+};
 
 const INIT_TAG: u8 = 0x00;
 const NULL_TAG: u8 = 0x01;
@@ -22,14 +27,28 @@ const UUID_TAG: u8 = 0x08;
 const REGEX_TAG: u8 = 0x09;
 const LIST_TAG: u8 = 0x0A;
 const SET_TAG: u8 = 0x0B;
+const VEC_TAG: u8 = 0x0C;
+const TIMESTAMP_TAG: u8 = 0x0D;
+const JSON_TAG: u8 = 0x0E;
 const GUARD_TAG: u8 = 0xFE;
 const BOT_TAG: u8 = 0xFF;
 
+/// Numbers of exact kinds strictly between two consecutive floats, followed by their exact
+/// value and then their kind.
+const IS_ABOVE_FLOAT: u8 = 0b00010100;
 const IS_FLOAT: u8 = 0b00010000;
+const IS_DECIMAL: u8 = 0b00001100;
+const IS_BIGINT: u8 = 0b00001000;
 const IS_APPROX_INT: u8 = 0b00000100;
 const IS_EXACT_INT: u8 = 0b00000000;
 const EXACT_INT_BOUND: i64 = 0x20_0000_0000_0000;
 
+const EXACT_NEG: u8 = 0x01;
+const EXACT_ZERO: u8 = 0x02;
+const EXACT_POS: u8 = 0x03;
+const EXACT_POS_END: u8 = 0x00;
+const EXACT_NEG_END: u8 = 0xFF;
+
 pub(crate) trait MemCmpEncoder: Write {
     fn encode_datavalue(&mut self, v: &DataValue) {
         match v {
@@ -75,25 +94,127 @@ pub(crate) trait MemCmpEncoder: Write {
                 }
                 self.write_u8(INIT_TAG).unwrap()
             }
+            DataValue::Vec(v) => {
+                self.write_u8(VEC_TAG).unwrap();
+                for el in &v.0 {
+                    self.write_u8(NULL_TAG).unwrap();
+                    self.write_u32::<BigEndian>(order_encode_f32(*el)).unwrap();
+                }
+                self.write_u8(INIT_TAG).unwrap()
+            }
+            DataValue::Timestamp(t) => {
+                self.write_u8(TIMESTAMP_TAG).unwrap();
+                self.write_u64::<BigEndian>(order_encode_i64(*t)).unwrap();
+            }
+            DataValue::Json(j) => {
+                self.write_u8(JSON_TAG).unwrap();
+                self.encode_bytes(j.0.to_string().as_bytes());
+            }
             DataValue::Guard => self.write_u8(GUARD_TAG).unwrap(),
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
+            DataValue::BigInt(i) => {
+                self.write_u8(NUM_TAG).unwrap();
+                let digits = i.magnitude().to_string();
+                let exponent = digits.len() as i64;
+                let (floor, is_exact) = decimal_floor_f64(&BigDecimal::new(i.clone(), 0));
+                self.write_u64::<BigEndian>(order_encode_f64(floor))
+                    .unwrap();
+                self.encode_exact_kind(
+                    is_exact,
+                    IS_BIGINT,
+                    i.sign() == Sign::Minus,
+                    &digits,
+                    exponent,
+                );
+            }
+            DataValue::Decimal(d) => {
+                self.write_u8(NUM_TAG).unwrap();
+                let (mantissa, scale) = d.as_bigint_and_exponent();
+                let digits = mantissa.magnitude().to_string();
+                let exponent = digits.len() as i64 - scale;
+                let (floor, is_exact) = decimal_floor_f64(d);
+                self.write_u64::<BigEndian>(order_encode_f64(floor))
+                    .unwrap();
+                self.encode_exact_kind(
+                    is_exact,
+                    IS_DECIMAL,
+                    mantissa.sign() == Sign::Minus,
+                    &digits,
+                    exponent,
+                );
+            }
+        }
+    }
+    /// Encodes what follows the float of a number of an exact kind: if the number equals the
+    /// float, its kind and then its exact value, which sort before a float equal to it;
+    /// otherwise its exact value and then its kind, which sort after such a float.
+    fn encode_exact_kind(
+        &mut self,
+        equals_float: bool,
+        kind: u8,
+        negative: bool,
+        digits: &str,
+        exponent: i64,
+    ) {
+        if equals_float {
+            self.write_u8(kind).unwrap();
+            self.encode_exact(negative, digits, exponent);
+        } else {
+            self.write_u8(IS_ABOVE_FLOAT).unwrap();
+            self.encode_exact(negative, digits, exponent);
+            self.write_u8(kind).unwrap();
+        }
+    }
+    /// Encodes the exact value `0.{digits} * 10^exponent` so that the bytes sort in the order
+    /// of the values: sign first, then the exponent, then the digits.
+    fn encode_exact(&mut self, negative: bool, digits: &str, exponent: i64) {
+        let digits = digits.trim_end_matches('0');
+        if digits.is_empty() {
+            self.write_u8(EXACT_ZERO).unwrap();
+        } else if negative {
+            self.write_u8(EXACT_NEG).unwrap();
+            self.write_u64::<BigEndian>(!order_encode_i64(exponent))
+                .unwrap();
+            for d in digits.bytes() {
+                self.write_u8(10 - (d - b'0')).unwrap();
+            }
+            self.write_u8(EXACT_NEG_END).unwrap();
+        } else {
+            self.write_u8(EXACT_POS).unwrap();
+            self.write_u64::<BigEndian>(order_encode_i64(exponent))
+                .unwrap();
+            for d in digits.bytes() {
+                self.write_u8(d - b'0' + 1).unwrap();
+            }
+            self.write_u8(EXACT_POS_END).unwrap();
         }
     }
     fn encode_num(&mut self, v: Num) {
-        let f = v.get_float();
-        let u = order_encode_f64(f);
-        self.write_u64::<BigEndian>(u).unwrap();
         match v {
+            Num::Int(i) if i > -EXACT_INT_BOUND && i < EXACT_INT_BOUND => {
+                self.write_u64::<BigEndian>(order_encode_f64(i as f64))
+                    .unwrap();
+                self.write_u8(IS_EXACT_INT).unwrap();
+            }
             Num::Int(i) => {
-                if i > -EXACT_INT_BOUND && i < EXACT_INT_BOUND {
-                    self.write_u8(IS_EXACT_INT).unwrap();
+                // the float of integers too large to be exact is rounded down, as for the
+                // other exact kinds
+                let (floor, is_exact) = decimal_floor_f64(&BigDecimal::from(i));
+                self.write_u64::<BigEndian>(order_encode_f64(floor))
+                    .unwrap();
+                if is_exact {
+                    self.write_u8(IS_APPROX_INT).unwrap();
+                    self.write_u64::<BigEndian>(order_encode_i64(i)).unwrap();
                 } else {
+                    self.write_u8(IS_ABOVE_FLOAT).unwrap();
+                    let digits = i.unsigned_abs().to_string();
+                    let exponent = digits.len() as i64;
+                    self.encode_exact(i < 0, &digits, exponent);
                     self.write_u8(IS_APPROX_INT).unwrap();
-                    let en = order_encode_i64(i);
-                    self.write_u64::<BigEndian>(en).unwrap();
                 }
             }
-            Num::Float(_) => {
+            Num::Float(f) => {
+                self.write_u64::<BigEndian>(order_encode_f64(f)).unwrap();
                 self.write_u8(IS_FLOAT).unwrap();
             }
         }
@@ -165,6 +286,26 @@ fn order_encode_f64(v: f64) -> u64 {
     }
 }
 
+const SIGN_MARK_32: u32 = 1 << 31;
+
+fn order_encode_f32(v: f32) -> u32 {
+    let u = v.to_bits();
+    if v.is_sign_positive() {
+        u | SIGN_MARK_32
+    } else {
+        !u
+    }
+}
+
+fn order_decode_f32(u: u32) -> f32 {
+    let u = if u & SIGN_MARK_32 > 0 {
+        u & (!SIGN_MARK_32)
+    } else {
+        !u
+    };
+    f32::from_bits(u)
+}
+
 fn order_decode_f64(u: u64) -> f64 {
     let u = if u & SIGN_MARK > 0 {
         u & (!SIGN_MARK)
@@ -187,6 +328,11 @@ impl Num {
         match *tag {
             IS_FLOAT => (Num::Float(f), remaining),
             IS_EXACT_INT => (Num::Int(f as i64), remaining),
+            IS_ABOVE_FLOAT => {
+                let (mantissa, exp, remaining) = decode_exact(remaining);
+                let i = mantissa * num_traits::pow(BigInt::from(10u32), exp as usize);
+                (Num::Int(i.to_i64().unwrap()), &remaining[1..])
+            }
             IS_APPROX_INT => {
                 let (int_part, remaining) = remaining.split_at(8);
                 let iu = BigEndian::read_u64(int_part);
@@ -209,6 +355,40 @@ impl Num {
     }
 }
 
+/// Decodes what [MemCmpEncoder::encode_exact] wrote, as `mantissa * 10^exponent`.
+fn decode_exact(bs: &[u8]) -> (BigInt, i64, &[u8]) {
+    let (tag, remaining) = bs.split_first().unwrap();
+    if *tag == EXACT_ZERO {
+        return (BigInt::from(0), 0, remaining);
+    }
+    let negative = *tag == EXACT_NEG;
+    let (exp_part, remaining) = remaining.split_at(8);
+    let exp_u = BigEndian::read_u64(exp_part);
+    let exponent = order_decode_i64(if negative { !exp_u } else { exp_u });
+    let end_tag = if negative {
+        EXACT_NEG_END
+    } else {
+        EXACT_POS_END
+    };
+    let n_digits = remaining.iter().position(|b| *b == end_tag).unwrap();
+    let digits: String = remaining[..n_digits]
+        .iter()
+        .map(|b| {
+            let d = if negative { 10 - *b } else { *b - 1 };
+            (b'0' + d) as char
+        })
+        .collect();
+    let mut mantissa = BigInt::from_str(&digits).unwrap();
+    if negative {
+        mantissa = -mantissa;
+    }
+    (
+        mantissa,
+        exponent - n_digits as i64,
+        &remaining[n_digits + 1..],
+    )
+}
+
 impl DataValue {
     pub(crate) fn decode_from_key(bs: &[u8]) -> (Self, &[u8]) {
         let (tag, remaining) = bs.split_first().unwrap();
@@ -216,10 +396,43 @@ impl DataValue {
             NULL_TAG => (DataValue::Null, remaining),
             FALSE_TAG => (DataValue::Bool(false), remaining),
             TRUE_TAG => (DataValue::Bool(true), remaining),
-            NUM_TAG => {
-                let (n, remaining) = Num::decode_from_key(remaining);
-                (DataValue::Num(n), remaining)
-            }
+            NUM_TAG => match remaining[8] {
+                IS_BIGINT => {
+                    let (mantissa, exp, remaining) = decode_exact(&remaining[9..]);
+                    let ten = BigInt::from(10u32);
+                    let i = mantissa * num_traits::pow(ten, exp as usize);
+                    (DataValue::BigInt(i), remaining)
+                }
+                IS_DECIMAL => {
+                    let (mantissa, exp, remaining) = decode_exact(&remaining[9..]);
+                    (
+                        DataValue::Decimal(BigDecimal::new(mantissa, -exp)),
+                        remaining,
+                    )
+                }
+                IS_ABOVE_FLOAT => {
+                    let (mantissa, exp, rest) = decode_exact(&remaining[9..]);
+                    match rest[0] {
+                        IS_BIGINT => {
+                            let ten = BigInt::from(10u32);
+                            let i = mantissa * num_traits::pow(ten, exp as usize);
+                            (DataValue::BigInt(i), &rest[1..])
+                        }
+                        IS_DECIMAL => (
+                            DataValue::Decimal(BigDecimal::new(mantissa, -exp)),
+                            &rest[1..],
+                        ),
+                        _ => {
+                            let (n, remaining) = Num::decode_from_key(remaining);
+                            (DataValue::Num(n), remaining)
+                        }
+                    }
+                }
+                _ => {
+                    let (n, remaining) = Num::decode_from_key(remaining);
+                    (DataValue::Num(n), remaining)
+                }
+            },
             STR_TAG => {
                 let (bytes, remaining) = decode_bytes(remaining);
                 let s = unsafe { String::from_utf8_unchecked(bytes) };
@@ -267,6 +480,25 @@ impl DataValue {
                 }
                 (DataValue::Set(collected), &remaining[1..])
             }
+            VEC_TAG => {
+                let mut collected = vec![];
+                let mut remaining = remaining;
+                while remaining[0] != INIT_TAG {
+                    collected.push(order_decode_f32(BigEndian::read_u32(&remaining[1..5])));
+                    remaining = &remaining[5..];
+                }
+                (DataValue::Vec(Vector(collected)), &remaining[1..])
+            }
+            TIMESTAMP_TAG => {
+                let (t, remaining) = remaining.split_at(8);
+                let t = order_decode_i64(BigEndian::read_u64(t));
+                (DataValue::Timestamp(t), remaining)
+            }
+            JSON_TAG => {
+                let (bytes, remaining) = decode_bytes(remaining);
+                let j = serde_json::from_slice(&bytes).unwrap();
+                (DataValue::Json(JsonData(j)), remaining)
+            }
             GUARD_TAG => (DataValue::Guard, remaining),
             BOT_TAG => (DataValue::Bot, remaining),
             _ => unreachable!("{:?}", bs),
@@ -278,11 +510,20 @@ impl<T: Write> MemCmpEncoder for T {}
 
 #[cfg(test)]
 mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+    use byteorder::{BigEndian, WriteBytesExt};
+    use num_bigint::BigInt;
     use smartstring::SmartString;
     use uuid::Uuid;
 
-    use crate::data::memcmp::{decode_bytes, MemCmpEncoder};
-    use crate::data::value::{DataValue, Num, UuidWrapper};
+    use crate::data::memcmp::{
+        decode_bytes, order_encode_f64, order_encode_i64, MemCmpEncoder, IS_APPROX_INT, NUM_TAG,
+    };
+    use crate::data::tuple::Tuple;
+    use crate::data::value::{DataValue, JsonData, Num, UuidWrapper, Vector};
+    use crate::runtime::relation::RelationId;
 
     #[test]
     fn encode_decode_num() {
@@ -321,6 +562,110 @@ mod tests {
         assert_eq!(collected, collected_copy);
     }
 
+    #[test]
+    fn encode_decode_exact_nums() {
+        let mut vals = vec![
+            DataValue::from(-3),
+            DataValue::from(0),
+            DataValue::from(2),
+            DataValue::from(2.5),
+            DataValue::from(i64::MAX),
+        ];
+        for s in [
+            "-100000000000000000000000",
+            "-12",
+            "-1",
+            "0",
+            "2",
+            "99999999999999999999",
+            "100000000000000000000000",
+        ] {
+            vals.push(DataValue::BigInt(BigInt::from_str(s).unwrap()));
+        }
+        for s in [
+            "-12.5", "-12.45", "-0.001", "0.00", "0.001", "0.0011", "2.5", "2.50001", "1E+30",
+        ] {
+            vals.push(DataValue::Decimal(BigDecimal::from_str(s).unwrap()));
+        }
+        // numbers of all kinds around the points where floats stop being exact
+        for i in [
+            1,
+            (1 << 53) - 1,
+            1 << 53,
+            (1 << 53) + 1,
+            (1 << 53) + 3,
+            i64::MAX,
+        ] {
+            vals.push(DataValue::from(i));
+            vals.push(DataValue::from(-i));
+            vals.push(DataValue::from(i as f64));
+            vals.push(DataValue::from(-i as f64));
+            vals.push(DataValue::BigInt(BigInt::from(i) + 1));
+            vals.push(DataValue::BigInt(-BigInt::from(i) - 2));
+        }
+        for s in [
+            "9007199254740992",
+            "9007199254740992.5",
+            "9007199254740993",
+            "-9007199254740993.5",
+            "0.99999999999999999999",
+            "1.00000000000000000001",
+            "1E-400",
+            "-1E-400",
+            "1E+400",
+            "-1E+400",
+        ] {
+            vals.push(DataValue::Decimal(BigDecimal::from_str(s).unwrap()));
+        }
+        vals.push(DataValue::from(-0.));
+        vals.push(DataValue::from(f64::MAX));
+        vals.push(DataValue::from(f64::INFINITY));
+        vals.push(DataValue::from(f64::NEG_INFINITY));
+        let mut encoded = vec![];
+        for v in &vals {
+            let mut encoder = vec![];
+            encoder.encode_datavalue(v);
+            let (decoded, remaining) = DataValue::decode_from_key(&encoder);
+            assert!(remaining.is_empty());
+            assert_eq!(&decoded, v);
+            encoded.push((encoder, v.clone()));
+        }
+        let mut by_value = encoded.clone();
+        by_value.sort_by(|a, b| a.1.cmp(&b.1));
+        encoded.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(encoded, by_value);
+    }
+
+    #[test]
+    fn encode_decode_vectors() {
+        let vals = [
+            vec![],
+            vec![f32::NEG_INFINITY],
+            vec![-1.5, 2.],
+            vec![-0.],
+            vec![0.],
+            vec![0., -1.],
+            vec![0., 0.],
+            vec![0., 0., 0.],
+            vec![3.25],
+            vec![f32::INFINITY],
+        ];
+        let mut encoded = vec![];
+        for v in vals {
+            let v = DataValue::Vec(Vector(v));
+            let mut encoder = vec![];
+            encoder.encode_datavalue(&v);
+            let (decoded, remaining) = DataValue::decode_from_key(&encoder);
+            assert!(remaining.is_empty());
+            assert_eq!(decoded, v);
+            encoded.push((encoder, v));
+        }
+        let mut by_value = encoded.clone();
+        by_value.sort_by(|a, b| a.1.cmp(&b.1));
+        encoded.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(encoded, by_value);
+    }
+
     #[test]
     fn test_encode_decode_uuid() {
         let uuid = DataValue::Uuid(UuidWrapper(
@@ -396,6 +741,9 @@ mod tests {
             DataValue::from(i64::MIN + 2),
             DataValue::from(f64::INFINITY),
             DataValue::from(f64::NEG_INFINITY),
+            DataValue::BigInt(BigInt::from_str("-123456789012345678901234567890").unwrap()),
+            DataValue::Decimal(BigDecimal::from_str("-0.0012").unwrap()),
+            DataValue::Decimal(BigDecimal::from_str("1500.25").unwrap()),
             DataValue::List(vec![]),
         ];
         dv.push(DataValue::List(dv.clone()));
@@ -407,4 +755,122 @@ mod tests {
         assert!(remaining.is_empty());
         assert_eq!(decoded, v);
     }
+
+    #[test]
+    fn bool_keys_take_one_byte() {
+        let mut encoded = vec![];
+        for v in [
+            DataValue::Null,
+            DataValue::Bool(false),
+            DataValue::Bool(true),
+            DataValue::from(0),
+        ] {
+            let mut one = vec![];
+            one.encode_datavalue(&v);
+            encoded.push(one);
+        }
+        assert_eq!(encoded[1].len(), 1);
+        assert_eq!(encoded[2].len(), 1);
+        // null sorts before false, which sorts before true and then numbers
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (bytes, expected) in encoded[1..3].iter().zip([false, true]) {
+            let (decoded, remaining) = DataValue::decode_from_key(bytes);
+            assert!(remaining.is_empty());
+            assert_eq!(decoded, DataValue::Bool(expected));
+        }
+    }
+
+    #[test]
+    fn encode_decode_timestamps() {
+        let vals = vec![
+            DataValue::from(1e18),
+            DataValue::Str(SmartString::from("")),
+            DataValue::Timestamp(i64::MIN),
+            DataValue::Timestamp(-1),
+            DataValue::Timestamp(0),
+            DataValue::Timestamp(1_700_000_000_000_000),
+            DataValue::Timestamp(i64::MAX),
+        ];
+        let mut encoded = vec![];
+        for v in &vals {
+            let mut encoder = vec![];
+            encoder.encode_datavalue(v);
+            let (decoded, remaining) = DataValue::decode_from_key(&encoder);
+            assert!(remaining.is_empty());
+            assert_eq!(&decoded, v);
+            encoded.push((encoder, v.clone()));
+        }
+        let mut by_value = encoded.clone();
+        by_value.sort_by(|a, b| a.1.cmp(&b.1));
+        encoded.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(encoded, by_value);
+    }
+
+    #[test]
+    fn upgrade_storage_version_1_keys() {
+        // storage version 1 encoded all integers of magnitude 2^53 or more after their
+        // nearest float, followed by the integer itself
+        let old_key = |i: i64| {
+            let mut key = RelationId::new(1).raw_encode().to_vec();
+            key.write_u8(NUM_TAG).unwrap();
+            key.write_u64::<BigEndian>(order_encode_f64(i as f64))
+                .unwrap();
+            key.write_u8(IS_APPROX_INT).unwrap();
+            key.write_u64::<BigEndian>(order_encode_i64(i)).unwrap();
+            key.encode_datavalue(&DataValue::Str(SmartString::from("rest")));
+            key
+        };
+        for i in [
+            (1 << 53) + 1,
+            (1 << 53) + 3,
+            -(1 << 53) - 1,
+            i64::MAX,
+            i64::MIN + 1,
+        ] {
+            let key = old_key(i);
+            let tuple = Tuple(vec![
+                DataValue::from(i),
+                DataValue::Str(SmartString::from("rest")),
+            ]);
+            assert_eq!(Tuple::decode_from_key(&key), tuple);
+            assert_eq!(
+                Tuple::upgrade_key(&key),
+                Some(tuple.encode_as_key(RelationId::new(1)))
+            );
+        }
+        // integers equal to a float were encoded as they are now
+        for i in [1 << 53, 1 << 60, i64::MIN] {
+            assert_eq!(Tuple::upgrade_key(&old_key(i)), None);
+        }
+        let mut small = RelationId::new(1).raw_encode().to_vec();
+        small.encode_datavalue(&DataValue::from(42));
+        assert_eq!(Tuple::upgrade_key(&small), None);
+    }
+
+    #[test]
+    fn encode_decode_json() {
+        let vals = vec![
+            DataValue::Timestamp(i64::MAX),
+            DataValue::Json(JsonData(serde_json::json!(null))),
+            DataValue::Json(JsonData(serde_json::json!([1, "a"]))),
+            DataValue::Json(JsonData(
+                serde_json::json!({"a": {"b": [1, 2.5]}, "c": "x"}),
+            )),
+            DataValue::Json(JsonData(serde_json::json!({"b": 1}))),
+            DataValue::Guard,
+        ];
+        let mut encoded = vec![];
+        for v in &vals {
+            let mut encoder = vec![];
+            encoder.encode_datavalue(v);
+            let (decoded, remaining) = DataValue::decode_from_key(&encoder);
+            assert!(remaining.is_empty());
+            assert_eq!(&decoded, v);
+            encoded.push((encoder, v.clone()));
+        }
+        let mut by_value = encoded.clone();
+        by_value.sort_by(|a, b| a.1.cmp(&b.1));
+        encoded.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(encoded, by_value);
+    }
 }
@@ -13,11 +13,16 @@ use thiserror::Error;
 
 use crate::algo::{AlgoHandle, AlgoImpl};
 use crate::data::aggr::Aggregation;
-use crate::data::expr::Expr;
+use crate::data::expr::{Expr, Op};
+use crate::data::functions::{
+    OP_ADD, OP_CHECKED_ADD, OP_CHECKED_MUL, OP_CHECKED_SUB, OP_MUL, OP_SATURATING_ADD,
+    OP_SATURATING_MUL, OP_SATURATING_SUB, OP_SUB,
+};
 use crate::data::relation::StoredRelationMetadata;
-use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::symb::{quote_ident, Symbol, PROG_ENTRY};
 use crate::data::value::DataValue;
-use crate::parse::SourceSpan;
+use crate::parse::{Deprecation, SourceSpan};
+use crate::query::logical::prune_const_predicates;
 use crate::runtime::in_mem::InMemRelation;
 use crate::runtime::relation::InputRelationHandle;
 use crate::runtime::transact::SessionTx;
@@ -37,6 +42,17 @@ pub(crate) struct QueryOutOptions {
     pub(crate) sorters: Vec<(Symbol, SortDir)>,
     pub(crate) store_relation: Option<(InputRelationHandle, RelationOp)>,
     pub(crate) assertion: Option<QueryAssertion>,
+    pub(crate) null_on_error: bool,
+    pub(crate) overflow: OverflowPolicy,
+    pub(crate) max_rows_scanned: Option<usize>,
+    pub(crate) max_intermediate_rows: Option<usize>,
+    /// Bytes of memory algorithms are estimated to need above which they are not run
+    pub(crate) algo_memory_budget: Option<usize>,
+    pub(crate) strict: bool,
+    /// Whether to report timings and counts of the evaluation of each rule
+    pub(crate) profile: bool,
+    /// Whether stored relations with soft deletion also produce their deleted rows
+    pub(crate) include_deleted: bool,
 }
 
 impl Debug for QueryOutOptions {
@@ -56,6 +72,32 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {};", l)?;
         }
+        if let Some(l) = self.max_rows_scanned {
+            writeln!(f, ":max_rows_scanned {};", l)?;
+        }
+        if let Some(l) = self.max_intermediate_rows {
+            writeln!(f, ":max_intermediate_rows {};", l)?;
+        }
+        if let Some(l) = self.algo_memory_budget {
+            writeln!(f, ":algo_memory_budget {};", l)?;
+        }
+        if self.strict {
+            writeln!(f, ":strict;")?;
+        }
+        if self.profile {
+            writeln!(f, ":profile;")?;
+        }
+        if self.include_deleted {
+            writeln!(f, ":include_deleted true;")?;
+        }
+        if self.null_on_error {
+            writeln!(f, ":on_error null;")?;
+        }
+        match self.overflow {
+            OverflowPolicy::Fail => {}
+            OverflowPolicy::Null => writeln!(f, ":overflow null;")?,
+            OverflowPolicy::Saturate => writeln!(f, ":overflow saturate;")?,
+        }
         for (symb, dir) in &self.sorters {
             write!(f, ":order ")?;
             if *dir == SortDir::Dsc {
@@ -94,7 +136,7 @@ impl Display for QueryOutOptions {
                     write!(f, ":ensure_not ")?;
                 }
             }
-            write!(f, "{} {{", name)?;
+            write!(f, "{} {{", quote_ident(name))?;
             let mut is_first = true;
             for (col, bind) in keys.iter().zip(key_bindings) {
                 if is_first {
@@ -102,7 +144,7 @@ impl Display for QueryOutOptions {
                 } else {
                     write!(f, ", ")?;
                 }
-                write!(f, "{}: {}", col.name, col.typing)?;
+                write!(f, "{}: {}", quote_ident(&col.name), col.typing)?;
                 if let Some(gen) = &col.default_gen {
                     write!(f, " default {}", gen)?;
                 } else {
@@ -117,7 +159,7 @@ impl Display for QueryOutOptions {
                 } else {
                     write!(f, ", ")?;
                 }
-                write!(f, "{}: {}", col.name, col.typing)?;
+                write!(f, "{}: {}", quote_ident(&col.name), col.typing)?;
                 if let Some(gen) = &col.default_gen {
                     write!(f, " default {}", gen)?;
                 } else {
@@ -152,6 +194,29 @@ impl QueryOutOptions {
     }
 }
 
+/// What integer arithmetic does when the result does not fit in 64 bits
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub(crate) enum OverflowPolicy {
+    #[default]
+    Fail,
+    Null,
+    Saturate,
+}
+
+impl OverflowPolicy {
+    fn replace_op(self, op: &'static Op) -> &'static Op {
+        match (self, op.name) {
+            (OverflowPolicy::Null, n) if n == OP_ADD.name => &OP_CHECKED_ADD,
+            (OverflowPolicy::Null, n) if n == OP_SUB.name => &OP_CHECKED_SUB,
+            (OverflowPolicy::Null, n) if n == OP_MUL.name => &OP_CHECKED_MUL,
+            (OverflowPolicy::Saturate, n) if n == OP_ADD.name => &OP_SATURATING_ADD,
+            (OverflowPolicy::Saturate, n) if n == OP_SUB.name => &OP_SATURATING_SUB,
+            (OverflowPolicy::Saturate, n) if n == OP_MUL.name => &OP_SATURATING_MUL,
+            _ => op,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum SortDir {
     Asc,
@@ -472,6 +537,41 @@ impl MagicAlgoApply {
             },
         }
     }
+    pub(crate) fn non_neg_float_option(&self, name: &str, default: Option<f64>) -> Result<f64> {
+        match self.options.get(name) {
+            Some(v) => match v.clone().eval_to_const() {
+                Ok(DataValue::Num(n)) => {
+                    let f = n.get_float();
+                    ensure!(
+                        f >= 0.,
+                        WrongAlgoOptionError {
+                            name: name.to_string(),
+                            span: v.span(),
+                            algo_name: self.algo.name.to_string(),
+                            help: "a non-negative number is required".to_string(),
+                        }
+                    );
+                    Ok(f)
+                }
+                _ => Err(WrongAlgoOptionError {
+                    name: name.to_string(),
+                    span: v.span(),
+                    algo_name: self.algo.name.to_string(),
+                    help: "a non-negative number is required".to_string(),
+                }
+                .into()),
+            },
+            None => match default {
+                Some(v) => Ok(v),
+                None => Err(AlgoOptionNotFoundError {
+                    name: name.to_string(),
+                    span: self.span,
+                    algo_name: self.algo.name.to_string(),
+                }
+                .into()),
+            },
+        }
+    }
     pub(crate) fn bool_option(&self, name: &str, default: Option<bool>) -> Result<bool> {
         match self.options.get(name) {
             Some(v) => match v.clone().eval_to_const() {
@@ -507,6 +607,24 @@ impl Debug for MagicAlgoApply {
     }
 }
 
+/// The version of a stored relation retaining history to read, given after `@`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum AsOf {
+    /// The rows as they were at this time, in seconds since the epoch
+    Time(f64),
+    /// The rows as they were once the transaction with this id was committed
+    Tx(u64),
+}
+
+impl Display for AsOf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsOf::Time(t) => write!(f, "{}", t),
+            AsOf::Tx(id) => write!(f, "tx {}", id),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub(crate) enum AlgoRuleArg {
     InMem {
@@ -517,11 +635,13 @@ pub(crate) enum AlgoRuleArg {
     Stored {
         name: Symbol,
         bindings: Vec<Symbol>,
+        valid_at: Option<AsOf>,
         span: SourceSpan,
     },
     NamedStored {
         name: Symbol,
         bindings: BTreeMap<SmartString<LazyCompact>, Symbol>,
+        valid_at: Option<AsOf>,
         span: SourceSpan,
     },
 }
@@ -539,17 +659,33 @@ impl Display for AlgoRuleArg {
                 write!(f, "{}", name)?;
                 f.debug_list().entries(bindings).finish()?;
             }
-            AlgoRuleArg::Stored { name, bindings, .. } => {
-                write!(f, ":{}", name)?;
+            AlgoRuleArg::Stored {
+                name,
+                bindings,
+                valid_at,
+                ..
+            } => {
+                write!(f, ":{}", quote_ident(name))?;
                 f.debug_list().entries(bindings).finish()?;
+                if let Some(t) = valid_at {
+                    write!(f, " @ {}", t)?;
+                }
             }
-            AlgoRuleArg::NamedStored { name, bindings, .. } => {
+            AlgoRuleArg::NamedStored {
+                name,
+                bindings,
+                valid_at,
+                ..
+            } => {
                 write!(f, ":")?;
-                let mut sf = f.debug_struct(name);
+                let mut sf = f.debug_struct(&quote_ident(name));
                 for (k, v) in bindings {
-                    sf.field(k, v);
+                    sf.field(&quote_ident(k), v);
                 }
                 sf.finish()?;
+                if let Some(t) = valid_at {
+                    write!(f, " @ {}", t)?;
+                }
             }
         }
         Ok(())
@@ -566,6 +702,8 @@ pub(crate) enum MagicAlgoRuleArg {
     Stored {
         name: Symbol,
         bindings: Vec<Symbol>,
+        /// Read the rows as they were at this time or transaction, from the retained history
+        valid_at: Option<AsOf>,
         span: SourceSpan,
     },
 }
@@ -599,6 +737,7 @@ impl MagicAlgoRuleArg {
 pub(crate) struct InputProgram {
     pub(crate) prog: BTreeMap<Symbol, InputInlineRulesOrAlgo>,
     pub(crate) out_opts: QueryOutOptions,
+    pub(crate) deprecations: Vec<Deprecation>,
 }
 
 impl Display for InputProgram {
@@ -689,6 +828,51 @@ struct EntryHeadNotExplicitlyDefinedError(#[label] SourceSpan);
 pub(crate) struct NoEntryError;
 
 impl InputProgram {
+    /// The names of the stored relations read by the rules and fixed rules of the program.
+    pub(crate) fn stored_relations_read(&self) -> BTreeSet<SmartString<LazyCompact>> {
+        fn collect(atom: &InputAtom, names: &mut BTreeSet<SmartString<LazyCompact>>) {
+            match atom {
+                InputAtom::Relation { inner } => {
+                    names.insert(inner.name.name.clone());
+                }
+                InputAtom::NamedFieldRelation { inner } => {
+                    names.insert(inner.name.name.clone());
+                }
+                InputAtom::Negation { inner, .. } => collect(inner, names),
+                InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                    for atom in inner {
+                        collect(atom, names);
+                    }
+                }
+                InputAtom::Rule { .. }
+                | InputAtom::Predicate { .. }
+                | InputAtom::Unification { .. } => {}
+            }
+        }
+
+        let mut names = BTreeSet::new();
+        for rules_or_algo in self.prog.values() {
+            match rules_or_algo {
+                InputInlineRulesOrAlgo::Rules { rules } => {
+                    for atom in rules.iter().flat_map(|rule| rule.body.iter()) {
+                        collect(atom, &mut names);
+                    }
+                }
+                InputInlineRulesOrAlgo::Algo { algo } => {
+                    for arg in &algo.rule_args {
+                        match arg {
+                            AlgoRuleArg::Stored { name, .. }
+                            | AlgoRuleArg::NamedStored { name, .. } => {
+                                names.insert(name.name.clone());
+                            }
+                            AlgoRuleArg::InMem { .. } => {}
+                        }
+                    }
+                }
+            }
+        }
+        names
+    }
     pub(crate) fn get_entry_arity(&self) -> Result<usize> {
         if let Some(entry) = self.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
             return match entry {
@@ -720,7 +904,7 @@ impl InputProgram {
                     for (symb, aggr) in head.iter().zip(aggrs.iter()) {
                         if let Some((aggr, _)) = aggr {
                             ret.push(Symbol::new(
-                                &format!(
+                                format!(
                                     "{}({})",
                                     aggr.name
                                         .strip_prefix("AGGR_")
@@ -748,12 +932,38 @@ impl InputProgram {
 
         Err(NoEntryError.into())
     }
+    /// Rewrites integer arithmetic in all rule bodies and algorithm options to follow the
+    /// overflow policy of the query.
+    pub(crate) fn apply_overflow_policy(&mut self) {
+        let policy = self.out_opts.overflow;
+        if policy == OverflowPolicy::Fail {
+            return;
+        }
+        let replace = |op| policy.replace_op(op);
+        for rules_or_algo in self.prog.values_mut() {
+            match rules_or_algo {
+                InputInlineRulesOrAlgo::Rules { rules } => {
+                    for rule in rules {
+                        for atom in rule.body.iter_mut() {
+                            atom.replace_ops(&replace);
+                        }
+                    }
+                }
+                InputInlineRulesOrAlgo::Algo { algo } => {
+                    for expr in algo.options.values_mut() {
+                        expr.replace_ops(&replace);
+                    }
+                }
+            }
+        }
+    }
     pub(crate) fn to_normalized_program(&self, tx: &SessionTx) -> Result<NormalFormProgram> {
         let mut prog: BTreeMap<Symbol, _> = Default::default();
         for (k, rules_or_algo) in &self.prog {
             match rules_or_algo {
                 InputInlineRulesOrAlgo::Rules { rules } => {
                     let mut collected_rules = vec![];
+                    let mut unsatisfiable = None;
                     for rule in rules {
                         let mut counter = -1;
                         let mut gen_symb = |span| {
@@ -782,6 +992,7 @@ impl InputProgram {
                         }
                         for conj in normalized_body.inner {
                             let mut body = conj.0;
+                            let satisfiable = prune_const_predicates(&mut body);
                             for (old_symb, new_symbs) in seen.iter() {
                                 for new_symb in new_symbs.iter() {
                                     body.push(NormalFormAtom::Unification(Unification {
@@ -800,7 +1011,23 @@ impl InputProgram {
                                 aggr: rule.aggr.clone(),
                                 body,
                             };
-                            collected_rules.push(normalized_rule.convert_to_well_ordered_rule()?);
+                            if satisfiable {
+                                let mut rule = normalized_rule
+                                    .eliminate_common_subexpressions()
+                                    .convert_to_well_ordered_rule()?;
+                                if tx.eval.reorder_predicates {
+                                    rule = rule.reorder_predicates_by_cost();
+                                }
+                                collected_rules.push(rule);
+                            } else if unsatisfiable.is_none() {
+                                unsatisfiable = Some(normalized_rule);
+                            }
+                        }
+                    }
+                    // a rule must keep at least one body even if it can never match
+                    if collected_rules.is_empty() {
+                        if let Some(rule) = unsatisfiable {
+                            collected_rules.push(rule.convert_to_well_ordered_rule()?);
                         }
                     }
                     prog.insert(
@@ -1063,6 +1290,36 @@ pub(crate) enum InputAtom {
     },
 }
 
+impl InputAtom {
+    fn replace_ops(&mut self, f: &impl Fn(&'static Op) -> &'static Op) {
+        match self {
+            InputAtom::Rule { inner } => {
+                for arg in inner.args.iter_mut() {
+                    arg.replace_ops(f);
+                }
+            }
+            InputAtom::NamedFieldRelation { inner } => {
+                for arg in inner.args.values_mut() {
+                    arg.replace_ops(f);
+                }
+            }
+            InputAtom::Relation { inner } => {
+                for arg in inner.args.iter_mut() {
+                    arg.replace_ops(f);
+                }
+            }
+            InputAtom::Predicate { inner } => inner.replace_ops(f),
+            InputAtom::Negation { inner, .. } => inner.replace_ops(f),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.replace_ops(f);
+                }
+            }
+            InputAtom::Unification { inner } => inner.expr.replace_ops(f),
+        }
+    }
+}
+
 impl Debug for InputAtom {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self)
@@ -1079,20 +1336,38 @@ impl Display for InputAtom {
                 f.debug_list().entries(args).finish()?;
             }
             InputAtom::NamedFieldRelation {
-                inner: InputNamedFieldRelationApplyAtom { name, args, .. },
+                inner:
+                    InputNamedFieldRelationApplyAtom {
+                        name,
+                        args,
+                        valid_at,
+                        ..
+                    },
             } => {
                 f.write_str(":")?;
-                let mut sf = f.debug_struct(name);
+                let mut sf = f.debug_struct(&quote_ident(name));
                 for (k, v) in args {
-                    sf.field(k, v);
+                    sf.field(&quote_ident(k), v);
                 }
                 sf.finish()?;
+                if let Some(t) = valid_at {
+                    write!(f, " @ {}", t)?;
+                }
             }
             InputAtom::Relation {
-                inner: InputRelationApplyAtom { name, args, .. },
+                inner:
+                    InputRelationApplyAtom {
+                        name,
+                        args,
+                        valid_at,
+                        ..
+                    },
             } => {
-                write!(f, ":{}", name)?;
+                write!(f, ":{}", quote_ident(name))?;
                 f.debug_list().entries(args).finish()?;
+                if let Some(t) = valid_at {
+                    write!(f, " @ {}", t)?;
+                }
             }
             InputAtom::Predicate { inner } => {
                 write!(f, "{}", inner)?;
@@ -1184,6 +1459,7 @@ pub(crate) struct InputRuleApplyAtom {
 pub(crate) struct InputNamedFieldRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: BTreeMap<SmartString<LazyCompact>, Expr>,
+    pub(crate) valid_at: Option<AsOf>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1191,6 +1467,8 @@ pub(crate) struct InputNamedFieldRelationApplyAtom {
 pub(crate) struct InputRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Expr>,
+    /// Time or transaction to read the relation as of, for relations retaining history
+    pub(crate) valid_at: Option<AsOf>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1205,6 +1483,7 @@ pub(crate) struct NormalFormRuleApplyAtom {
 pub(crate) struct NormalFormRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
+    pub(crate) valid_at: Option<AsOf>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1219,6 +1498,7 @@ pub(crate) struct MagicRuleApplyAtom {
 pub(crate) struct MagicRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
+    pub(crate) valid_at: Option<AsOf>,
     pub(crate) span: SourceSpan,
 }
 
@@ -10,7 +10,10 @@ use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::expr::Expr;
-use crate::data::value::{DataValue, UuidWrapper};
+use crate::data::functions::{
+    op_parse_json, op_to_bigint, op_to_decimal, op_to_json, op_to_timestamp,
+};
+use crate::data::value::{DataValue, UuidWrapper, Vector};
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
 pub(crate) struct NullableColType {
@@ -28,6 +31,10 @@ impl Display for NullableColType {
             ColType::String => f.write_str("String")?,
             ColType::Bytes => f.write_str("Bytes")?,
             ColType::Uuid => f.write_str("Uuid")?,
+            ColType::BigInt => f.write_str("BigInt")?,
+            ColType::Decimal => f.write_str("Decimal")?,
+            ColType::Timestamp => f.write_str("Timestamp")?,
+            ColType::Json => f.write_str("Json")?,
             ColType::List { eltype, len } => {
                 f.write_str("[")?;
                 write!(f, "{}", eltype)?;
@@ -36,6 +43,12 @@ impl Display for NullableColType {
                 }
                 f.write_str("]")?;
             }
+            ColType::Set { eltype } => {
+                write!(f, "Set<{}>", eltype)?;
+            }
+            ColType::Vec { dim } => {
+                write!(f, "<F32;{}>", dim)?;
+            }
             ColType::Tuple(t) => {
                 f.write_str("(")?;
                 let l = t.len();
@@ -69,6 +82,51 @@ pub(crate) enum ColType {
         len: Option<usize>,
     },
     Tuple(Vec<NullableColType>),
+    BigInt,
+    Decimal,
+    Timestamp,
+    Json,
+    Set {
+        eltype: Box<NullableColType>,
+    },
+    Vec {
+        dim: usize,
+    },
+}
+
+impl ColType {
+    /// Whether values of type `source` may be coerced into this type. Used for static
+    /// checks, so anything that may succeed is accepted.
+    pub(crate) fn accepts(&self, source: &ColType) -> bool {
+        match (self, source) {
+            (ColType::Any, _) | (_, ColType::Any) => true,
+            (
+                ColType::Int | ColType::Float | ColType::BigInt | ColType::Decimal,
+                ColType::Int | ColType::Float | ColType::BigInt | ColType::Decimal,
+            ) => true,
+            (
+                ColType::Bytes
+                | ColType::Uuid
+                | ColType::BigInt
+                | ColType::Decimal
+                | ColType::Timestamp,
+                ColType::String,
+            ) => true,
+            (ColType::Timestamp, ColType::Int | ColType::Float) => true,
+            (ColType::Bool, ColType::Int | ColType::Float | ColType::String) => true,
+            (ColType::Json, _) => true,
+            (
+                ColType::List { eltype, .. } | ColType::Set { eltype },
+                ColType::List { eltype: source, .. } | ColType::Set { eltype: source },
+            ) => eltype.coltype.accepts(&source.coltype),
+            (
+                ColType::List { .. } | ColType::Tuple(_),
+                ColType::List { .. } | ColType::Tuple(_),
+            ) => true,
+            (ColType::Vec { .. }, ColType::List { .. }) => true,
+            (a, b) => a == b,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
@@ -162,7 +220,21 @@ impl NullableColType {
 
         Ok(match &self.coltype {
             ColType::Any => data,
-            ColType::Bool => DataValue::Bool(data.get_bool().ok_or_else(make_err)?),
+            ColType::Bool => match &data {
+                DataValue::Bool(_) => data,
+                // sources without booleans often hold them as 0 and 1 or as text
+                DataValue::Num(_) => match data.get_int() {
+                    Some(0) => DataValue::Bool(false),
+                    Some(1) => DataValue::Bool(true),
+                    _ => bail!(make_err()),
+                },
+                DataValue::Str(s) => match s.trim().to_ascii_lowercase().as_str() {
+                    "true" => DataValue::Bool(true),
+                    "false" => DataValue::Bool(false),
+                    _ => bail!(make_err()),
+                },
+                _ => bail!(make_err()),
+            },
             ColType::Int => DataValue::from(data.get_int().ok_or_else(make_err)?),
             ColType::Float => DataValue::from(data.get_float().ok_or_else(make_err)?),
             ColType::String => {
@@ -185,6 +257,24 @@ impl NullableColType {
                 _ => bail!(make_err()),
             },
             ColType::Uuid => DataValue::Uuid(UuidWrapper(data.get_uuid().ok_or_else(make_err)?)),
+            ColType::BigInt => match data {
+                DataValue::Str(_) => {
+                    op_to_bigint(std::slice::from_ref(&data)).map_err(|_| make_err())?
+                }
+                _ => DataValue::BigInt(data.get_bigint().ok_or_else(make_err)?),
+            },
+            ColType::Decimal => {
+                op_to_decimal(std::slice::from_ref(&data)).map_err(|_| make_err())?
+            }
+            ColType::Timestamp => {
+                op_to_timestamp(std::slice::from_ref(&data)).map_err(|_| make_err())?
+            }
+            ColType::Json => match data {
+                DataValue::Str(_) => {
+                    op_parse_json(std::slice::from_ref(&data)).map_err(|_| make_err())?
+                }
+                _ => op_to_json(&[data])?,
+            },
             ColType::List { eltype, len } => {
                 if let DataValue::List(l) = data {
                     if let Some(expected) = len {
@@ -195,6 +285,31 @@ impl NullableColType {
                     bail!(make_err())
                 }
             }
+            ColType::Set { eltype } => match data {
+                DataValue::List(l) => {
+                    DataValue::Set(l.into_iter().map(|el| eltype.coerce(el)).try_collect()?)
+                }
+                DataValue::Set(s) => {
+                    DataValue::Set(s.into_iter().map(|el| eltype.coerce(el)).try_collect()?)
+                }
+                _ => bail!(make_err()),
+            },
+            ColType::Vec { dim } => match data {
+                DataValue::Vec(v) => {
+                    ensure!(*dim == v.0.len(), BadListLength(self.clone(), v.0.len()));
+                    DataValue::Vec(v)
+                }
+                DataValue::List(ref l) => {
+                    ensure!(*dim == l.len(), BadListLength(self.clone(), l.len()));
+                    let v = l
+                        .iter()
+                        .map(|el| el.get_float().map(|f| f as f32))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(make_err)?;
+                    DataValue::Vec(Vector(v))
+                }
+                _ => bail!(make_err()),
+            },
             ColType::Tuple(typ) => {
                 if let DataValue::List(l) = data {
                     ensure!(typ.len() == l.len(), BadListLength(self.clone(), l.len()));
@@ -2,17 +2,19 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 
 use miette::{bail, Diagnostic, Result};
+use pest::Parser;
 use serde_derive::{Deserialize, Serialize};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use crate::parse::SourceSpan;
+use crate::parse::{CozoScriptParser, Rule, SourceSpan};
 
 #[derive(Clone, Deserialize, Serialize)]
 pub(crate) struct Symbol {
@@ -91,3 +93,18 @@ impl Symbol {
 }
 
 pub(crate) const PROG_ENTRY: &str = "?";
+
+/// Renders a relation or column name so that it parses back to the same name,
+/// wrapping it in backticks if it is not a plain identifier. Plain identifiers are those
+/// the grammar accepts unquoted, following the Unicode XID rules.
+pub(crate) fn quote_ident(name: &str) -> Cow<'_, str> {
+    let is_plain = |seg: &str| match CozoScriptParser::parse(Rule::ident, seg) {
+        Ok(mut pairs) => pairs.next().map(|p| p.as_str().len()) == Some(seg.len()),
+        Err(_) => false,
+    };
+    if name.split('.').count() <= 2 && name.split('.').all(is_plain) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("`{}`", name.replace('`', "``")))
+    }
+}
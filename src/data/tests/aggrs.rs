@@ -2,8 +2,12 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+use std::str::FromStr;
+
 use approx::AbsDiffEq;
+use bigdecimal::BigDecimal;
 use itertools::Itertools;
+use num_bigint::BigInt;
 
 use crate::data::aggr::parse_aggr;
 use crate::data::value::DataValue;
@@ -229,6 +233,81 @@ fn test_collect() {
     );
 }
 
+#[test]
+fn test_histogram() {
+    let mut aggr = parse_aggr("histogram").unwrap().clone();
+    let edges = DataValue::List([0, 10, 20, 30].into_iter().map(DataValue::from).collect());
+    aggr.normal_init(&[edges]).unwrap();
+
+    let mut histogram_aggr = aggr.normal_op.unwrap();
+    for v in [-1., 0., 5., 10., 19.5, 30., 31.] {
+        histogram_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    assert_eq!(
+        histogram_aggr.get().unwrap(),
+        DataValue::List([2, 2, 1].into_iter().map(DataValue::from).collect())
+    );
+
+    let mut aggr = parse_aggr("histogram").unwrap().clone();
+    let edges = DataValue::List([10, 0].into_iter().map(DataValue::from).collect());
+    assert!(aggr.normal_init(&[edges]).is_err());
+
+    let mut aggr = parse_aggr("auto_histogram").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(4)]).unwrap();
+
+    let mut histogram_aggr = aggr.normal_op.unwrap();
+    for v in [0, 4, 1, 2, 7, -8] {
+        histogram_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    let res = histogram_aggr.get().unwrap();
+    let buckets = res.get_list().unwrap();
+    assert_eq!(buckets.len(), 4);
+    let total: i64 = buckets
+        .iter()
+        .map(|b| b.get_list().unwrap()[2].get_int().unwrap())
+        .sum();
+    assert_eq!(total, 6);
+    assert_eq!(buckets[0].get_list().unwrap()[0], DataValue::from(-8.));
+    assert_eq!(buckets[3].get_list().unwrap()[1], DataValue::from(8.));
+}
+
+#[test]
+fn test_sample() {
+    let run = |seed: i64| {
+        let mut aggr = parse_aggr("sample").unwrap().clone();
+        aggr.normal_init(&[DataValue::from(3), DataValue::from(seed)])
+            .unwrap();
+        let mut sample_aggr = aggr.normal_op.unwrap();
+        for i in 0..100 {
+            sample_aggr.set(&DataValue::from(i)).unwrap();
+        }
+        sample_aggr.get().unwrap()
+    };
+    let res = run(42);
+    assert_eq!(res.get_list().unwrap().len(), 3);
+    assert!(res
+        .get_list()
+        .unwrap()
+        .iter()
+        .all(|v| (0..100).contains(&v.get_int().unwrap())));
+    assert_eq!(res, run(42));
+
+    let mut aggr = parse_aggr("sample").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(5)]).unwrap();
+    let mut sample_aggr = aggr.normal_op.unwrap();
+    sample_aggr.set(&DataValue::from(1)).unwrap();
+    sample_aggr.set(&DataValue::from(2)).unwrap();
+    assert_eq!(
+        sample_aggr.get().unwrap(),
+        DataValue::List(vec![DataValue::from(1), DataValue::from(2)])
+    );
+    assert!(parse_aggr("sample")
+        .unwrap()
+        .clone()
+        .normal_init(&[DataValue::from(0)])
+        .is_err());
+}
+
 #[test]
 fn test_count() {
     let mut aggr = parse_aggr("count").unwrap().clone();
@@ -267,6 +346,40 @@ fn test_std_dev() {
     assert!(v.abs_diff_eq(&(0.5_f64).sqrt(), 1e-10));
 }
 
+#[test]
+fn test_variance_large_offset() {
+    let mut aggr = parse_aggr("variance").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut variance_aggr = aggr.normal_op.unwrap();
+    for x in [4., 7., 13., 16.] {
+        variance_aggr.set(&DataValue::from(1e9 + x)).unwrap();
+    }
+    let v = variance_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&30., 1e-6));
+}
+
+#[test]
+fn test_skewness_kurtosis() {
+    let mut aggr = parse_aggr("skewness").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut skewness_aggr = aggr.normal_op.unwrap();
+
+    let mut aggr = parse_aggr("kurtosis").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut kurtosis_aggr = aggr.normal_op.unwrap();
+
+    for x in [1, 2, 3, 10] {
+        skewness_aggr.set(&DataValue::from(x)).unwrap();
+        kurtosis_aggr.set(&DataValue::from(x)).unwrap();
+    }
+    let v = skewness_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&1.0182337649086284, 1e-10));
+    let v = kurtosis_aggr.get().unwrap().get_float().unwrap();
+    assert!(v.abs_diff_eq(&-0.7696, 1e-10));
+    assert!(skewness_aggr.set(&DataValue::Null).is_err());
+}
+
 #[test]
 fn test_mean() {
     let mut aggr = parse_aggr("mean").unwrap().clone();
@@ -295,6 +408,37 @@ fn test_sum() {
     assert_eq!(sum_aggr.get().unwrap(), DataValue::from(15.));
 }
 
+#[test]
+fn test_exact_sum_mean() {
+    let dec = |s: &str| DataValue::Decimal(BigDecimal::from_str(s).unwrap());
+
+    let mut aggr = parse_aggr("sum").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut sum_aggr = aggr.normal_op.unwrap();
+    sum_aggr.set(&dec("0.10")).unwrap();
+    sum_aggr.set(&dec("0.20")).unwrap();
+    sum_aggr.set(&DataValue::from(1)).unwrap();
+    assert_eq!(sum_aggr.get().unwrap(), dec("1.3"));
+
+    let mut aggr = parse_aggr("sum").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut sum_aggr = aggr.normal_op.unwrap();
+    let big = BigInt::from_str("123456789012345678901234567890").unwrap();
+    sum_aggr.set(&DataValue::BigInt(big.clone())).unwrap();
+    sum_aggr.set(&DataValue::from(10)).unwrap();
+    assert_eq!(
+        sum_aggr.get().unwrap(),
+        DataValue::BigInt(big + BigInt::from(10))
+    );
+
+    let mut aggr = parse_aggr("mean").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut mean_aggr = aggr.normal_op.unwrap();
+    mean_aggr.set(&dec("1.10")).unwrap();
+    mean_aggr.set(&dec("2.20")).unwrap();
+    assert_eq!(mean_aggr.get().unwrap(), dec("1.65"));
+}
+
 #[test]
 fn test_product() {
     let mut aggr = parse_aggr("product").unwrap().clone();
@@ -608,3 +752,54 @@ fn test_bit_xor() {
     bit_xor_aggr.set(&DataValue::Bytes(vec![0b01011])).unwrap();
     assert_eq!(bit_xor_aggr.get().unwrap(), DataValue::Bytes(vec![0b10111]));
 }
+
+#[test]
+fn test_median_percentile() {
+    let mut aggr = parse_aggr("median").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut median_aggr = aggr.normal_op.unwrap();
+    assert_eq!(median_aggr.get().unwrap(), DataValue::Null);
+    for v in [5, 1, 4, 2] {
+        median_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    assert_eq!(median_aggr.get().unwrap(), DataValue::from(3.));
+    median_aggr.set(&DataValue::from(10.5)).unwrap();
+    assert_eq!(median_aggr.get().unwrap(), DataValue::from(4.));
+    assert!(median_aggr.set(&DataValue::Str("a".into())).is_err());
+
+    let mut aggr = parse_aggr("percentile").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(90)]).unwrap();
+    let mut percentile_aggr = aggr.normal_op.unwrap();
+    for v in 1..=11 {
+        percentile_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    assert_eq!(percentile_aggr.get().unwrap(), DataValue::from(10.));
+    assert!(parse_aggr("percentile")
+        .unwrap()
+        .clone()
+        .normal_init(&[DataValue::from(101)])
+        .is_err());
+
+    // enough values to be spilled to temporary files
+    let mut aggr = parse_aggr("percentile").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(25)]).unwrap();
+    let mut percentile_aggr = aggr.normal_op.unwrap();
+    for v in (0..200001).rev() {
+        percentile_aggr.set(&DataValue::from(v)).unwrap();
+    }
+    assert_eq!(percentile_aggr.get().unwrap(), DataValue::from(50000.));
+}
+
+#[test]
+fn test_mode() {
+    let mut aggr = parse_aggr("mode").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+    let mut mode_aggr = aggr.normal_op.unwrap();
+    assert_eq!(mode_aggr.get().unwrap(), DataValue::Null);
+    for v in ["b", "a", "c", "b", "a", "b"] {
+        mode_aggr.set(&DataValue::Str(v.into())).unwrap();
+    }
+    assert_eq!(mode_aggr.get().unwrap(), DataValue::Str("b".into()));
+    mode_aggr.set(&DataValue::Str("a".into())).unwrap();
+    assert_eq!(mode_aggr.get().unwrap(), DataValue::Str("a".into()));
+}
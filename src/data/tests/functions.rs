@@ -2,7 +2,11 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+use std::str::FromStr;
+
 use approx::AbsDiffEq;
+use bigdecimal::BigDecimal;
+use num_bigint::BigInt;
 use num_traits::FloatConst;
 use regex::Regex;
 use smartstring::SmartString;
@@ -312,6 +316,188 @@ fn test_max_min() {
     assert!(op_max(&[DataValue::Bool(true)]).is_err());
 }
 
+#[test]
+fn test_coalesce_greatest_least() {
+    assert_eq!(op_coalesce(&[]).unwrap(), DataValue::Null);
+    assert_eq!(
+        op_coalesce(&[DataValue::Null, DataValue::from(1), DataValue::from(2)]).unwrap(),
+        DataValue::from(1)
+    );
+    assert_eq!(
+        op_greatest(&[DataValue::Null, DataValue::from(1), DataValue::from(2.5)]).unwrap(),
+        DataValue::from(2.5)
+    );
+    assert_eq!(
+        op_greatest(&[DataValue::from(3), DataValue::Str(SmartString::from("a"))]).unwrap(),
+        DataValue::Str(SmartString::from("a"))
+    );
+    assert_eq!(
+        op_least(&[DataValue::from(3), DataValue::Null, DataValue::Bool(true)]).unwrap(),
+        DataValue::Bool(true)
+    );
+    assert_eq!(op_least(&[DataValue::Null]).unwrap(), DataValue::Null);
+}
+
+#[test]
+fn test_safe_and_checked_arithmetic() {
+    assert_eq!(
+        op_div_safe(&[DataValue::from(1), DataValue::from(0)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_div_safe(&[DataValue::from(1), DataValue::from(0.), DataValue::from(-1)]).unwrap(),
+        DataValue::from(-1)
+    );
+    assert_eq!(
+        op_div_safe(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
+        DataValue::from(0.5)
+    );
+    assert_eq!(
+        op_mod_safe(&[DataValue::from(7), DataValue::from(0), DataValue::from(0)]).unwrap(),
+        DataValue::from(0)
+    );
+    assert_eq!(
+        op_mod_safe(&[DataValue::from(7), DataValue::from(3)]).unwrap(),
+        DataValue::from(1)
+    );
+    assert!(op_mod(&[DataValue::from(7), DataValue::from(0)]).is_err());
+
+    let max = DataValue::from(i64::MAX);
+    let min = DataValue::from(i64::MIN);
+    assert!(op_add(&[max.clone(), DataValue::from(1)]).is_err());
+    assert!(op_sub(&[min.clone(), DataValue::from(1)]).is_err());
+    assert!(op_mul(&[max.clone(), DataValue::from(2)]).is_err());
+    assert_eq!(
+        op_checked_add(&[max.clone(), DataValue::from(1)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_checked_sub(&[DataValue::from(3), DataValue::from(1)]).unwrap(),
+        DataValue::from(2)
+    );
+    assert_eq!(
+        op_checked_mul(&[max.clone(), DataValue::from(2)]).unwrap(),
+        DataValue::Null
+    );
+    assert_eq!(
+        op_saturating_add(&[max.clone(), DataValue::from(1)]).unwrap(),
+        max
+    );
+    assert_eq!(
+        op_saturating_sub(&[min.clone(), DataValue::from(1)]).unwrap(),
+        min
+    );
+    assert_eq!(
+        op_saturating_mul(&[min.clone(), DataValue::from(2)]).unwrap(),
+        min
+    );
+}
+
+#[test]
+fn test_bigint_decimal() {
+    let dec = |s: &str| DataValue::Decimal(BigDecimal::from_str(s).unwrap());
+    let big = |s: &str| DataValue::BigInt(BigInt::from_str(s).unwrap());
+
+    assert_eq!(op_add(&[dec("0.1"), dec("0.2")]).unwrap(), dec("0.3"));
+    assert_eq!(
+        op_mul(&[big("9223372036854775807"), DataValue::from(2)]).unwrap(),
+        big("18446744073709551614")
+    );
+    assert_eq!(
+        op_sub(&[DataValue::from(1), dec("0.01")]).unwrap(),
+        dec("0.99")
+    );
+    assert_eq!(
+        op_div(&[big("1"), DataValue::from(4)]).unwrap(),
+        dec("0.25")
+    );
+    assert!(op_div(&[dec("1"), dec("0")]).is_err());
+    assert_eq!(op_mod(&[big("10"), DataValue::from(3)]).unwrap(), big("1"));
+    assert_eq!(
+        op_add(&[dec("0.5"), DataValue::from(0.25)]).unwrap(),
+        DataValue::from(0.75)
+    );
+    assert_eq!(
+        op_eq(&[dec("2.00"), DataValue::from(2)]).unwrap(),
+        DataValue::Bool(true)
+    );
+    assert_eq!(
+        op_lt(&[big("100000000000000000000"), DataValue::from(i64::MAX)]).unwrap(),
+        DataValue::Bool(false)
+    );
+    assert_eq!(op_floor(&[dec("-1.5")]).unwrap(), dec("-2"));
+    assert_eq!(op_ceil(&[dec("1.2")]).unwrap(), dec("2"));
+    assert_eq!(op_minus(&[big("5")]).unwrap(), big("-5"));
+
+    assert_eq!(
+        op_to_bigint(&[DataValue::Str(SmartString::from("12345678901234567890"))]).unwrap(),
+        big("12345678901234567890")
+    );
+    assert_eq!(op_to_bigint(&[dec("-3.7")]).unwrap(), big("-3"));
+    assert_eq!(op_to_decimal(&[DataValue::from(0.1)]).unwrap(), dec("0.1"));
+    assert_eq!(
+        op_to_decimal(&[DataValue::Str(SmartString::from("12.50"))]).unwrap(),
+        dec("12.5")
+    );
+    assert_eq!(op_to_float(&[dec("0.5")]).unwrap(), DataValue::from(0.5));
+    assert!(op_to_decimal(&[DataValue::from(f64::NAN)]).is_err());
+}
+
+#[test]
+fn test_decimal_formatting() {
+    let dec = |s: &str| DataValue::Decimal(BigDecimal::from_str(s).unwrap());
+    let s = |s: &str| DataValue::Str(SmartString::from(s));
+
+    assert_eq!(
+        op_round_decimal(&[dec("2.345"), DataValue::from(2)]).unwrap(),
+        dec("2.34")
+    );
+    assert_eq!(
+        op_round_decimal(&[dec("2.355"), DataValue::from(2)]).unwrap(),
+        dec("2.36")
+    );
+    assert_eq!(
+        op_round_decimal(&[dec("-2.345"), DataValue::from(2), s("half_up")]).unwrap(),
+        dec("-2.35")
+    );
+    assert_eq!(
+        op_round_decimal(&[dec("-2.341"), DataValue::from(2), s("floor")]).unwrap(),
+        dec("-2.35")
+    );
+    assert_eq!(
+        op_round_decimal(&[dec("2.341"), DataValue::from(2), s("ceiling")]).unwrap(),
+        dec("2.35")
+    );
+    assert!(op_round_decimal(&[dec("2.341"), DataValue::from(2), s("sideways")]).is_err());
+    assert_eq!(
+        op_format_decimal(&[
+            DataValue::from(1234567.891),
+            DataValue::from(2),
+            s("half_even"),
+            s(",")
+        ])
+        .unwrap(),
+        s("1,234,567.89")
+    );
+    assert_eq!(
+        op_format_decimal(&[dec("-0.001"), DataValue::from(2)]).unwrap(),
+        s("0.00")
+    );
+    assert_eq!(
+        op_format_decimal(&[DataValue::from(5), DataValue::from(1)]).unwrap(),
+        s("5.0")
+    );
+    assert_eq!(
+        op_parse_decimal(&[s("(1,234.50)")]).unwrap(),
+        dec("-1234.5")
+    );
+    assert_eq!(
+        op_parse_decimal(&[s(" 1 000_000 ")]).unwrap(),
+        dec("1000000")
+    );
+    assert!(op_parse_decimal(&[s("1,2a")]).is_err());
+}
+
 #[test]
 fn test_minus() {
     assert_eq!(
@@ -785,6 +971,14 @@ fn test_regex() {
         .unwrap(),
         DataValue::Null
     );
+    for _ in 0..2 {
+        let compiled = op_regex(&[DataValue::Str("c.e".into())]).unwrap();
+        assert_eq!(
+            op_regex_matches(&[DataValue::Str("abcdef".into()), compiled]).unwrap(),
+            DataValue::Bool(true)
+        );
+        assert!(op_regex(&[DataValue::Str("(c.e".into())]).is_err());
+    }
 }
 
 #[test]
@@ -1205,6 +1399,69 @@ fn test_encode_decode() {
     )
 }
 
+#[test]
+fn test_bytes() {
+    let b = DataValue::Bytes([0, 1, 171, 255].into());
+    assert_eq!(
+        op_encode_hex(std::slice::from_ref(&b)).unwrap(),
+        DataValue::Str("0001abff".into())
+    );
+    assert_eq!(
+        op_decode_hex(&[DataValue::Str("0001ABff".into())]).unwrap(),
+        b
+    );
+    assert!(op_decode_hex(&[DataValue::Str("abc".into())]).is_err());
+    assert!(op_decode_hex(&[DataValue::Str("zz".into())]).is_err());
+
+    assert_eq!(
+        op_bytes_slice(&[b.clone(), DataValue::from(1), DataValue::from(-1)]).unwrap(),
+        DataValue::Bytes([1, 171].into())
+    );
+    assert_eq!(
+        op_bytes_slice(&[b.clone(), DataValue::from(2), DataValue::from(4)]).unwrap(),
+        DataValue::Bytes([171, 255].into())
+    );
+    assert!(op_bytes_slice(&[b.clone(), DataValue::from(3), DataValue::from(1)]).is_err());
+    assert!(op_bytes_slice(&[b, DataValue::from(0), DataValue::from(5)]).is_err());
+
+    assert_eq!(
+        op_encode_hex(&[op_sha256(&[DataValue::Str("abc".into())]).unwrap()]).unwrap(),
+        DataValue::Str("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad".into())
+    );
+    assert_eq!(
+        op_sha256(&[DataValue::Str("abc".into())]).unwrap(),
+        op_sha256(&[DataValue::Bytes(b"abc".to_vec())]).unwrap()
+    );
+    assert_eq!(
+        op_encode_hex(&[op_blake3(&[DataValue::Str("".into())]).unwrap()]).unwrap(),
+        DataValue::Str("af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262".into())
+    );
+}
+
+#[test]
+fn test_tuple_hash() {
+    let a = op_tuple_hash(&[DataValue::from(1), DataValue::Str("a".into())]).unwrap();
+    let b = op_tuple_hash(&[DataValue::from(1), DataValue::Str("a".into())]).unwrap();
+    let c = op_tuple_hash(&[DataValue::Str("a".into()), DataValue::from(1)]).unwrap();
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+    assert_eq!(
+        op_tuple_hash(&[DataValue::List(vec![DataValue::from(1)])]).unwrap(),
+        op_tuple_hash(&[DataValue::List(vec![DataValue::from(1)])]).unwrap()
+    );
+    assert_ne!(
+        op_tuple_hash(&[DataValue::Str("ab".into()), DataValue::Str("c".into())]).unwrap(),
+        op_tuple_hash(&[DataValue::Str("a".into()), DataValue::Str("bc".into())]).unwrap()
+    );
+    assert_eq!(
+        tuple_hash(
+            tuple_hash(TUPLE_HASH_INIT, &[DataValue::from(1)]),
+            &[DataValue::from(2)]
+        ),
+        tuple_hash(TUPLE_HASH_INIT, &[DataValue::from(1), DataValue::from(2)])
+    );
+}
+
 #[test]
 fn test_to_string() {
     assert_eq!(
@@ -1316,6 +1573,30 @@ fn test_set_ops() {
         .unwrap(),
         DataValue::List([1, 6].into_iter().map(DataValue::from).collect())
     );
+    let set = op_to_set(&[DataValue::List(
+        [3, 1, 3].into_iter().map(DataValue::from).collect(),
+    )])
+    .unwrap();
+    assert_eq!(
+        set,
+        DataValue::Set([1, 3].into_iter().map(DataValue::from).collect())
+    );
+    assert_eq!(
+        op_union(&[
+            set.clone(),
+            DataValue::List([2].into_iter().map(DataValue::from).collect())
+        ])
+        .unwrap(),
+        DataValue::Set([1, 2, 3].into_iter().map(DataValue::from).collect())
+    );
+    assert_eq!(
+        op_contains(&[set.clone(), DataValue::from(3)]).unwrap(),
+        DataValue::Bool(true)
+    );
+    assert_eq!(
+        op_is_in(&[DataValue::from(2), set]).unwrap(),
+        DataValue::Bool(false)
+    );
 }
 
 #[test]
@@ -1335,8 +1616,64 @@ fn test_uuid() {
 fn test_now() {
     let now = op_now(&[]).unwrap();
     assert!(matches!(now, DataValue::Num(_)));
-    let s = op_format_timestamp(&[now]).unwrap();
-    let _dt = op_parse_timestamp(&[s]).unwrap();
+    assert!(op_sub(&[now.clone(), DataValue::from(3600)])
+        .unwrap()
+        .get_float()
+        .is_some());
+    let now = op_to_timestamp(&[now]).unwrap();
+    assert!(matches!(now, DataValue::Timestamp(_)));
+    let s = op_format_timestamp(std::slice::from_ref(&now)).unwrap();
+    assert_eq!(op_parse_timestamp(&[s]).unwrap(), now);
+}
+
+#[test]
+fn test_timestamps() {
+    let t = op_parse_timestamp(&[DataValue::Str("2024-03-01T13:00:00.5+01:00".into())]).unwrap();
+    assert_eq!(t, DataValue::Timestamp(1_709_294_400_500_000));
+    assert_eq!(
+        op_format_timestamp(std::slice::from_ref(&t)).unwrap(),
+        DataValue::Str("2024-03-01T12:00:00.500+00:00".into())
+    );
+    assert_eq!(
+        op_format_timestamp(&[
+            t.clone(),
+            DataValue::Str("Asia/Tokyo".into()),
+            DataValue::Str("%Y-%m-%d %H:%M".into())
+        ])
+        .unwrap(),
+        DataValue::Str("2024-03-01 21:00".into())
+    );
+    assert_eq!(
+        op_parse_timestamp(&[
+            DataValue::Str("2024-03-01".into()),
+            DataValue::Str("%Y-%m-%d".into())
+        ])
+        .unwrap(),
+        DataValue::Timestamp(1_709_251_200_000_000)
+    );
+    assert!(op_parse_timestamp(&[DataValue::Str("yesterday".into())]).is_err());
+    assert_eq!(
+        op_to_timestamp(&[DataValue::from(1709294400.5)]).unwrap(),
+        t
+    );
+    assert_eq!(
+        op_to_float(std::slice::from_ref(&t)).unwrap(),
+        DataValue::from(1709294400.5)
+    );
+
+    let later = op_add(&[t.clone(), DataValue::from(90)]).unwrap();
+    assert_eq!(later, DataValue::Timestamp(1_709_294_490_500_000));
+    assert_eq!(op_add(&[DataValue::from(90), t.clone()]).unwrap(), later);
+    assert_eq!(op_sub(&[later.clone(), DataValue::from(90)]).unwrap(), t);
+    assert_eq!(
+        op_sub(&[later.clone(), t.clone()]).unwrap(),
+        DataValue::from(90.)
+    );
+    assert!(op_add(&[t.clone(), later.clone()]).is_err());
+    assert!(op_sub(&[DataValue::from(1), t.clone()]).is_err());
+    assert_eq!(op_lt(&[t.clone(), later]).unwrap(), DataValue::Bool(true));
+    assert!(op_lt(&[t.clone(), DataValue::from(1)]).is_err());
+    assert_eq!(op_is_timestamp(&[t]).unwrap(), DataValue::Bool(true));
 }
 
 #[test]
@@ -1382,3 +1719,89 @@ fn test_to_bool() {
         DataValue::Bool(true)
     );
 }
+
+#[test]
+fn test_json() {
+    let j = op_parse_json(&[DataValue::Str(
+        r#"{"a": {"b": [1, 2.5, "x"]}, "c": null}"#.into(),
+    )])
+    .unwrap();
+    assert!(op_is_json(std::slice::from_ref(&j))
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(op_parse_json(&[DataValue::Str("{".into())]).is_err());
+
+    let get = |path: DataValue| op_json_get(&[j.clone(), path]).unwrap();
+    assert_eq!(get(DataValue::Str("a.b.0".into())), DataValue::from(1));
+    assert_eq!(get(DataValue::Str("a.b.1".into())), DataValue::from(2.5));
+    assert_eq!(
+        get(DataValue::List(vec![
+            DataValue::Str("a".into()),
+            DataValue::Str("b".into()),
+            DataValue::from(-1)
+        ])),
+        DataValue::Str("x".into())
+    );
+    assert_eq!(get(DataValue::Str("a.z".into())), DataValue::Null);
+    assert_eq!(get(DataValue::Str("c".into())), DataValue::Null);
+    assert_eq!(
+        op_to_string(&[get(DataValue::Str("a".into()))]).unwrap(),
+        DataValue::Str(r#"{"b":[1,2.5,"x"]}"#.into())
+    );
+
+    let set = op_json_set(&[
+        j.clone(),
+        DataValue::Str("a.d.e".into()),
+        DataValue::List(vec![DataValue::from(1)]),
+    ])
+    .unwrap();
+    assert_eq!(
+        op_to_string(std::slice::from_ref(&set)).unwrap(),
+        DataValue::Str(r#"{"a":{"b":[1,2.5,"x"],"d":{"e":[1]}},"c":null}"#.into())
+    );
+    let appended = op_json_set(&[
+        j.clone(),
+        DataValue::Str("a.b.3".into()),
+        DataValue::Bool(true),
+    ])
+    .unwrap();
+    assert_eq!(
+        op_json_get(&[appended, DataValue::Str("a.b.3".into())]).unwrap(),
+        DataValue::Bool(true)
+    );
+    assert!(op_json_set(&[j.clone(), DataValue::Str("a.b.9".into()), DataValue::Null]).is_err());
+
+    let removed = op_json_remove(&[set, DataValue::Str("a.b".into())]).unwrap();
+    assert_eq!(
+        op_to_string(&[removed]).unwrap(),
+        DataValue::Str(r#"{"a":{"d":{"e":[1]}},"c":null}"#.into())
+    );
+
+    let merged = op_json_merge(&[
+        j,
+        op_parse_json(&[DataValue::Str(
+            r#"{"a": {"b": null, "f": 1}, "c": 2}"#.into(),
+        )])
+        .unwrap(),
+    ])
+    .unwrap();
+    assert_eq!(
+        op_to_string(&[merged]).unwrap(),
+        DataValue::Str(r#"{"a":{"f":1},"c":2}"#.into())
+    );
+
+    // values compare by their text, with the keys of objects sorted
+    assert_eq!(
+        op_parse_json(&[DataValue::Str(r#"{"y": 1, "x": 2}"#.into())]).unwrap(),
+        op_parse_json(&[DataValue::Str(r#"{"x": 2, "y": 1}"#.into())]).unwrap()
+    );
+    assert_eq!(
+        op_to_json(&[DataValue::List(vec![
+            DataValue::from(1),
+            DataValue::Str("a".into())
+        ])])
+        .unwrap(),
+        op_parse_json(&[DataValue::Str(r#"[1, "a"]"#.into())]).unwrap()
+    );
+}
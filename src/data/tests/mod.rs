@@ -2,5 +2,5 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+mod aggrs;
 mod functions;
-mod aggrs;
\ No newline at end of file
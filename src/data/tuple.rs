@@ -44,5 +44,18 @@ impl Tuple {
         }
         Tuple(ret)
     }
+    /// The key re-encoded as the current storage version encodes it, if it was written
+    /// differently by an older one.
+    pub(crate) fn upgrade_key(key: &[u8]) -> Option<Vec<u8>> {
+        let mut upgraded = key[..ENCODED_KEY_MIN_LEN].to_vec();
+        for val in Self::decode_from_key(key).0.iter() {
+            upgraded.encode_datavalue(val);
+        }
+        if upgraded == key {
+            None
+        } else {
+            Some(upgraded)
+        }
+    }
 }
-pub(crate) const ENCODED_KEY_MIN_LEN: usize = 8;
\ No newline at end of file
+pub(crate) const ENCODED_KEY_MIN_LEN: usize = 8;
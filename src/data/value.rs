@@ -7,12 +7,18 @@ use std::collections::BTreeSet;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, SecondsFormat, TimeZone, Utc};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
 use ordered_float::OrderedFloat;
 use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize};
 use smartstring::{LazyCompact, SmartString};
 use uuid::Uuid;
 
+use crate::data::json::JsonValue;
+
 #[derive(Clone, Hash, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
 pub(crate) struct UuidWrapper(pub(crate) Uuid);
 
@@ -76,13 +82,81 @@ impl Ord for RegexWrapper {
 
 impl PartialOrd for RegexWrapper {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.0.as_str().partial_cmp(other.0.as_str())
+        Some(self.cmp(other))
+    }
+}
+
+/// A JSON value. Values are compared and hashed by their text, in which the keys of objects
+/// are sorted, which is also how they are laid out in the key encoding.
+#[derive(Clone, serde_derive::Deserialize, serde_derive::Serialize)]
+#[serde(transparent)]
+pub(crate) struct JsonData(pub(crate) JsonValue);
+
+impl Hash for JsonData {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_string().hash(state)
+    }
+}
+
+impl PartialEq for JsonData {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for JsonData {}
+
+impl Ord for JsonData {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.to_string().cmp(&other.0.to_string())
+    }
+}
+
+impl PartialOrd for JsonData {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A dense vector of 32-bit floats, compared element by element in the total order of floats.
+#[derive(Clone, serde_derive::Deserialize, serde_derive::Serialize)]
+pub(crate) struct Vector(pub(crate) Vec<f32>);
+
+impl Hash for Vector {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for f in &self.0 {
+            f.to_bits().hash(state)
+        }
+    }
+}
+
+impl PartialEq for Vector {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Vector {}
+
+impl Ord for Vector {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for (l, r) in self.0.iter().zip(other.0.iter()) {
+            match l.total_cmp(r) {
+                Ordering::Equal => continue,
+                ord => return ord,
+            }
+        }
+        self.0.len().cmp(&other.0.len())
+    }
+}
+
+impl PartialOrd for Vector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-#[derive(
-    Clone, PartialEq, Eq, PartialOrd, Ord, serde_derive::Deserialize, serde_derive::Serialize, Hash,
-)]
+#[derive(Clone, PartialEq, Eq, serde_derive::Deserialize, serde_derive::Serialize, Hash)]
 pub(crate) enum DataValue {
     Null,
     Bool(bool),
@@ -94,8 +168,14 @@ pub(crate) enum DataValue {
     Regex(RegexWrapper),
     List(Vec<DataValue>),
     Set(BTreeSet<DataValue>),
+    Vec(Vector),
     Guard,
     Bot,
+    BigInt(BigInt),
+    Decimal(BigDecimal),
+    /// Microseconds since the epoch, in UTC
+    Timestamp(i64),
+    Json(JsonData),
 }
 
 impl From<i64> for DataValue {
@@ -193,21 +273,20 @@ impl PartialOrd for Num {
 impl Ord for Num {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
+            // an integer whose approximation equals a float is compared with it exactly, as
+            // the float is then integral and within the range of `i128`, and comes first if
+            // they are equal
             (Num::Int(i), Num::Float(r)) => {
                 let l = *i as f64;
-                match l.total_cmp(r) {
-                    Ordering::Less => Ordering::Less,
-                    Ordering::Equal => Ordering::Less,
-                    Ordering::Greater => Ordering::Greater,
-                }
+                l.total_cmp(r)
+                    .then_with(|| (*i as i128).cmp(&(*r as i128)))
+                    .then(Ordering::Less)
             }
             (Num::Float(l), Num::Int(i)) => {
                 let r = *i as f64;
-                match l.total_cmp(&r) {
-                    Ordering::Less => Ordering::Less,
-                    Ordering::Equal => Ordering::Greater,
-                    Ordering::Greater => Ordering::Greater,
-                }
+                l.total_cmp(&r)
+                    .then_with(|| (*l as i128).cmp(&(*i as i128)))
+                    .then(Ordering::Greater)
             }
             (Num::Int(l), Num::Int(r)) => l.cmp(r),
             (Num::Float(l), Num::Float(r)) => l.total_cmp(r),
@@ -215,6 +294,164 @@ impl Ord for Num {
     }
 }
 
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DataValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DataValue::Bool(l), DataValue::Bool(r)) => l.cmp(r),
+            (DataValue::Num(l), DataValue::Num(r)) => l.cmp(r),
+            (DataValue::BigInt(l), DataValue::BigInt(r)) => l.cmp(r),
+            (DataValue::Decimal(l), DataValue::Decimal(r)) => l.cmp(r),
+            (DataValue::Str(l), DataValue::Str(r)) => l.cmp(r),
+            (DataValue::Bytes(l), DataValue::Bytes(r)) => l.cmp(r),
+            (DataValue::Uuid(l), DataValue::Uuid(r)) => l.cmp(r),
+            (DataValue::Regex(l), DataValue::Regex(r)) => l.cmp(r),
+            (DataValue::List(l), DataValue::List(r)) => l.cmp(r),
+            (DataValue::Set(l), DataValue::Set(r)) => l.cmp(r),
+            (DataValue::Vec(l), DataValue::Vec(r)) => l.cmp(r),
+            (DataValue::Timestamp(l), DataValue::Timestamp(r)) => l.cmp(r),
+            (DataValue::Json(l), DataValue::Json(r)) => l.cmp(r),
+            (l, r) => match (l.num_sort_key(), r.num_sort_key()) {
+                // numbers of different kinds are ordered by their approximate value first,
+                // then by their exact value, and last by their kind, which is the order of
+                // the key encoding
+                (Some((lf, lk)), Some((rf, rk))) => lf
+                    .total_cmp(&rf)
+                    .then_with(|| l.exact_cmp(r))
+                    .then(lk.cmp(&rk)),
+                _ => l.type_rank().cmp(&r.type_rank()),
+            },
+        }
+    }
+}
+
+impl DataValue {
+    fn type_rank(&self) -> u8 {
+        match self {
+            DataValue::Null => 0,
+            DataValue::Bool(_) => 1,
+            DataValue::Num(_) | DataValue::BigInt(_) | DataValue::Decimal(_) => 2,
+            DataValue::Str(_) => 3,
+            DataValue::Bytes(_) => 4,
+            DataValue::Uuid(_) => 5,
+            DataValue::Regex(_) => 6,
+            DataValue::List(_) => 7,
+            DataValue::Set(_) => 8,
+            DataValue::Vec(_) => 9,
+            DataValue::Timestamp(_) => 10,
+            DataValue::Json(_) => 11,
+            DataValue::Guard => 12,
+            DataValue::Bot => 13,
+        }
+    }
+    /// Compares numbers whose approximations are equal by their exact values.
+    fn exact_cmp(&self, other: &Self) -> Ordering {
+        let exact = |v: &DataValue| match v {
+            DataValue::Num(Num::Float(f)) => float_to_decimal(*f),
+            v => v.get_exact(),
+        };
+        match (exact(self), exact(other)) {
+            (Some(l), Some(r)) => l.cmp(&r),
+            // only infinite floats have no exact value, and they are only approximations of
+            // numbers out of the range of floats
+            (None, Some(_)) => self.get_float().unwrap().total_cmp(&0.),
+            (Some(_), None) => 0f64.total_cmp(&other.get_float().unwrap()),
+            (None, None) => Ordering::Equal,
+        }
+    }
+    fn num_sort_key(&self) -> Option<(f64, u8)> {
+        match self {
+            DataValue::Num(Num::Int(i)) => Some((*i as f64, 0)),
+            DataValue::BigInt(i) => Some((bigint_to_f64(i), 1)),
+            DataValue::Decimal(d) => Some((decimal_to_f64(d), 2)),
+            DataValue::Num(Num::Float(f)) => Some((*f, 3)),
+            _ => None,
+        }
+    }
+}
+
+/// Formats a timestamp given in microseconds since the epoch as RFC 3339, in UTC and with as
+/// many digits of the fraction of a second as needed.
+pub(crate) fn format_timestamp(micros: i64) -> String {
+    timestamp_to_datetime(micros)
+        .map(|dt| dt.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        .unwrap_or_else(|| micros.to_string())
+}
+
+/// The date and time of a timestamp given in microseconds since the epoch, if representable.
+pub(crate) fn timestamp_to_datetime(micros: i64) -> Option<DateTime<Utc>> {
+    let secs = micros.div_euclid(1_000_000);
+    let nanos = micros.rem_euclid(1_000_000) as u32 * 1000;
+    Utc.timestamp_opt(secs, nanos).single()
+}
+
+/// Correctly rounded conversion, so that the order of approximations never contradicts
+/// the order of the exact values.
+pub(crate) fn bigint_to_f64(i: &BigInt) -> f64 {
+    i.to_string().parse().unwrap_or(f64::NAN)
+}
+
+/// See [bigint_to_f64].
+pub(crate) fn decimal_to_f64(d: &BigDecimal) -> f64 {
+    d.to_string().parse().unwrap_or(f64::NAN)
+}
+
+/// The exact value of a float, unless it is infinite or NaN.
+pub(crate) fn float_to_decimal(f: f64) -> Option<BigDecimal> {
+    if !f.is_finite() {
+        return None;
+    }
+    let (mantissa, exponent, sign) = num_traits::Float::integer_decode(f);
+    let mantissa = if sign < 0 {
+        -BigInt::from(mantissa)
+    } else {
+        BigInt::from(mantissa)
+    };
+    Some(if exponent >= 0 {
+        BigDecimal::new(mantissa << exponent as usize, 0)
+    } else {
+        // m / 2^k is m * 5^k / 10^k
+        let scale = -exponent as i64;
+        BigDecimal::new(
+            mantissa * num_traits::pow(BigInt::from(5), scale as usize),
+            scale,
+        )
+    })
+}
+
+/// The greatest float not above the exact value `d`, and whether it is equal to `d`.
+///
+/// Numbers of exact kinds are laid out in keys after this float rather than their nearest
+/// one, so that all the numbers after a float and before the next one are above it.
+pub(crate) fn decimal_floor_f64(d: &BigDecimal) -> (f64, bool) {
+    let nearest = decimal_to_f64(d);
+    match float_to_decimal(nearest) {
+        None if nearest > 0. => (f64::MAX, false),
+        None => (f64::NEG_INFINITY, false),
+        Some(approx) => match approx.cmp(d) {
+            Ordering::Greater => (next_float_down(nearest), false),
+            Ordering::Equal => (nearest, true),
+            Ordering::Less => (nearest, false),
+        },
+    }
+}
+
+/// The greatest float below the finite float `f`.
+fn next_float_down(f: f64) -> f64 {
+    if f == 0. {
+        -f64::from_bits(1)
+    } else if f > 0. {
+        f64::from_bits(f.to_bits() - 1)
+    } else {
+        f64::from_bits(f.to_bits() + 1)
+    }
+}
+
 impl Debug for DataValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self)
@@ -241,15 +478,33 @@ impl Display for DataValue {
             }
             DataValue::List(ls) => f.debug_list().entries(ls).finish(),
             DataValue::Set(s) => f.debug_list().entries(s).finish(),
+            DataValue::Vec(v) => {
+                write!(f, "vec(")?;
+                f.debug_list().entries(&v.0).finish()?;
+                write!(f, ")")
+            }
             DataValue::Guard => {
                 write!(f, "null")
             }
             DataValue::Bot => write!(f, "null"),
+            DataValue::BigInt(i) => write!(f, "to_bigint({:?})", i.to_string()),
+            DataValue::Decimal(d) => write!(f, "to_decimal({:?})", d.to_string()),
+            DataValue::Timestamp(t) => write!(f, "to_timestamp({:?})", format_timestamp(*t)),
+            DataValue::Json(j) => write!(f, "parse_json({:?})", j.0.to_string()),
         }
     }
 }
 
 impl DataValue {
+    /// The timestamp `secs` seconds after the epoch, to the nearest microsecond.
+    pub(crate) fn timestamp_from_secs(secs: f64) -> Option<Self> {
+        let micros = (secs * 1e6).round();
+        if micros.is_finite() && micros.abs() < i64::MAX as f64 {
+            Some(DataValue::Timestamp(micros as i64))
+        } else {
+            None
+        }
+    }
     pub(crate) fn get_list(&self) -> Option<&[DataValue]> {
         match self {
             DataValue::List(l) => Some(l),
@@ -265,23 +520,52 @@ impl DataValue {
     pub(crate) fn get_int(&self) -> Option<i64> {
         match self {
             DataValue::Num(n) => n.get_int(),
+            DataValue::BigInt(i) => i.to_i64(),
+            DataValue::Decimal(d) if d.is_integer() => d.to_i64(),
             _ => None,
         }
     }
     pub(crate) fn get_non_neg_int(&self) -> Option<u64> {
+        self.get_int()
+            .and_then(|i| if i < 0 { None } else { Some(i as u64) })
+    }
+    pub(crate) fn get_float(&self) -> Option<f64> {
         match self {
-            DataValue::Num(n) => n
-                .get_int()
-                .and_then(|i| if i < 0 { None } else { Some(i as u64) }),
+            DataValue::Num(n) => Some(n.get_float()),
+            DataValue::BigInt(i) => Some(bigint_to_f64(i)),
+            DataValue::Decimal(d) => Some(decimal_to_f64(d)),
             _ => None,
         }
     }
-    pub(crate) fn get_float(&self) -> Option<f64> {
+    /// The exact value of integers, big integers and decimals.
+    pub(crate) fn get_exact(&self) -> Option<BigDecimal> {
         match self {
-            DataValue::Num(n) => Some(n.get_float()),
+            DataValue::Num(Num::Int(i)) => Some(BigDecimal::from(*i)),
+            DataValue::BigInt(i) => Some(BigDecimal::new(i.clone(), 0)),
+            DataValue::Decimal(d) => Some(d.clone()),
             _ => None,
         }
     }
+    pub(crate) fn get_bigint(&self) -> Option<BigInt> {
+        match self {
+            DataValue::Num(Num::Int(i)) => Some(BigInt::from(*i)),
+            DataValue::Num(Num::Float(f)) if f.trunc() == *f => BigInt::from_f64(*f),
+            DataValue::BigInt(i) => Some(i.clone()),
+            DataValue::Decimal(d) if d.is_integer() => {
+                Some(d.with_scale(0).as_bigint_and_exponent().0)
+            }
+            _ => None,
+        }
+    }
+    pub(crate) fn is_zero_num(&self) -> bool {
+        match self {
+            DataValue::Num(Num::Int(i)) => *i == 0,
+            DataValue::Num(Num::Float(f)) => *f == 0.,
+            DataValue::BigInt(i) => i.is_zero(),
+            DataValue::Decimal(d) => d.is_zero(),
+            _ => false,
+        }
+    }
     pub(crate) fn get_bool(&self) -> Option<bool> {
         match self {
             DataValue::Bool(b) => Some(*b),
@@ -304,9 +588,13 @@ pub(crate) const LARGEST_UTF_CHAR: char = '\u{10ffff}';
 
 #[cfg(test)]
 mod tests {
+    use std::cmp::Ordering;
     use std::collections::{BTreeMap, HashMap};
     use std::mem::size_of;
+    use std::str::FromStr;
 
+    use bigdecimal::BigDecimal;
+    use num_bigint::BigInt;
     use smartstring::SmartString;
 
     use crate::data::symb::Symbol;
@@ -337,12 +625,38 @@ mod tests {
         dbg!(s);
     }
 
+    #[test]
+    fn exact_order_of_numbers_sharing_a_float() {
+        let int = |i: i64| DataValue::from(i);
+        let big = |s: &str| DataValue::BigInt(BigInt::from_str(s).unwrap());
+        let dec = |s: &str| DataValue::Decimal(BigDecimal::from_str(s).unwrap());
+        // all of these are approximated by 2^53 as floats
+        let ordered = [
+            dec("9007199254740991.5"),
+            int(1 << 53),
+            big("9007199254740992"),
+            dec("9007199254740992"),
+            DataValue::from(9007199254740992.),
+            dec("9007199254740992.5"),
+            int((1 << 53) + 1),
+            big("9007199254740993"),
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(pair[0].cmp(&pair[1]), Ordering::Less, "{:?}", pair);
+            assert_eq!(pair[1].cmp(&pair[0]), Ordering::Greater, "{:?}", pair);
+        }
+        assert!(int(i64::MIN) > big("-9223372036854775809"));
+        assert!(int(i64::MAX) < big("9223372036854775808"));
+        assert!(dec("-1E+400") > DataValue::from(f64::NEG_INFINITY));
+        assert!(dec("1E+400") < DataValue::from(f64::INFINITY));
+    }
+
     #[test]
     fn display_datavalues() {
         println!("{}", DataValue::Null);
         println!("{}", DataValue::Bool(true));
         println!("{}", DataValue::from(-1));
-        println!("{}", DataValue::from(-1121212121.331212121));
+        println!("{}", DataValue::from(-1_121_212_121.331_212));
         println!("{}", DataValue::from(f64::NAN));
         println!("{}", DataValue::from(f64::NEG_INFINITY));
         println!(
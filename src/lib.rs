@@ -18,11 +18,31 @@
 
 pub use miette::Error;
 
+pub use algo::call::{
+    AlgoCall, ConnectedComponentsCall, LouvainCall, PageRankCall, ShortestPath,
+    ShortestPathDijkstraCall, TypedAlgoCall,
+};
+pub use algo::custom::{CustomAlgo, CustomAlgoInput, CustomAlgoOutput};
+pub use data::aggr::{register_aggregation, UserAggregation, UserNormalAggregation};
+pub use parse::{quote_identifier, quote_string};
+pub use query::builder::{QueryAtom, QueryBuilder, QueryExpr, QueryRule};
+pub use runtime::access::{AccessPolicy, RelationAccess};
+pub use runtime::cdc::{RelationChanges, RowChange};
+pub use runtime::continuous::QueryDiff;
+pub use runtime::csv_import::CsvImportOptions;
 pub use runtime::db::Db;
+pub use runtime::db::DbOptions;
+pub use runtime::db::MultiTransaction;
+pub use runtime::db::QueryCursor;
+pub use runtime::export::ExportFormat;
+pub use runtime::federation::RemoteDb;
+pub use runtime::namespace::NamespaceQuota;
+pub use runtime::sync::SyncReport;
 
 pub(crate) mod algo;
 pub(crate) mod data;
 pub(crate) mod parse;
 pub(crate) mod query;
 pub(crate) mod runtime;
+pub mod storage;
 pub(crate) mod utils;
@@ -13,8 +13,8 @@ use thiserror::Error;
 
 use crate::data::expr::{get_op, Expr};
 use crate::data::functions::{
-    OP_ADD, OP_AND, OP_CONCAT, OP_DIV, OP_EQ, OP_GE, OP_GT, OP_LE, OP_LIST, OP_LT, OP_MINUS,
-    OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_OR, OP_POW, OP_SUB,
+    OP_ADD, OP_AND, OP_CONCAT, OP_DIV, OP_EQ, OP_GE, OP_GT, OP_IS_NULL, OP_LE, OP_LIST, OP_LT,
+    OP_MINUS, OP_MOD, OP_MUL, OP_NEGATE, OP_NEQ, OP_OR, OP_POW, OP_REGEX_MATCHES, OP_SUB,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
@@ -27,12 +27,16 @@ lazy_static! {
         PrattParser::new()
             .op(Op::infix(Rule::op_or, Left))
             .op(Op::infix(Rule::op_and, Left))
+            .op(Op::postfix(Rule::is_null) | Op::postfix(Rule::is_not_null))
             .op(Op::infix(Rule::op_gt, Left)
                 | Op::infix(Rule::op_lt, Left)
                 | Op::infix(Rule::op_ge, Left)
                 | Op::infix(Rule::op_le, Left))
             .op(Op::infix(Rule::op_mod, Left))
-            .op(Op::infix(Rule::op_eq, Left) | Op::infix(Rule::op_ne, Left))
+            .op(Op::infix(Rule::op_eq, Left)
+                | Op::infix(Rule::op_ne, Left)
+                | Op::infix(Rule::op_null_safe_eq, Left)
+                | Op::infix(Rule::op_regex_match, Left))
             .op(Op::infix(Rule::op_add, Left)
                 | Op::infix(Rule::op_sub, Left)
                 | Op::infix(Rule::op_concat, Left))
@@ -74,11 +78,29 @@ pub(crate) fn build_expr(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue
                 _ => unreachable!(),
             })
         })
+        .map_postfix(|lhs, op| {
+            let lhs = lhs?;
+            let span = lhs.span().merge(op.extract_span());
+            let is_null = Expr::Apply {
+                op: &OP_IS_NULL,
+                args: [lhs].into(),
+                span,
+            };
+            Ok(match op.as_rule() {
+                Rule::is_null => is_null,
+                Rule::is_not_null => Expr::Apply {
+                    op: &OP_NEGATE,
+                    args: [is_null].into(),
+                    span,
+                },
+                _ => unreachable!(),
+            })
+        })
         .parse(pair.into_inner())
 }
 
 fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Result<Expr> {
-    let args = vec![lhs?, rhs?];
+    let mut args = vec![lhs?, rhs?];
     let op = match op.as_rule() {
         Rule::op_add => &OP_ADD,
         Rule::op_sub => &OP_SUB,
@@ -86,8 +108,11 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
         Rule::op_div => &OP_DIV,
         Rule::op_mod => &OP_MOD,
         Rule::op_pow => &OP_POW,
-        Rule::op_eq => &OP_EQ,
+        // nulls compare equal to each other and unequal to anything else,
+        // which is exactly the behaviour of `==`
+        Rule::op_eq | Rule::op_null_safe_eq => &OP_EQ,
         Rule::op_ne => &OP_NEQ,
+        Rule::op_regex_match => &OP_REGEX_MATCHES,
         Rule::op_gt => &OP_GT,
         Rule::op_ge => &OP_GE,
         Rule::op_lt => &OP_LT,
@@ -97,6 +122,7 @@ fn build_expr_infix(lhs: Result<Expr>, op: Pair<'_>, rhs: Result<Expr>) -> Resul
         Rule::op_and => &OP_AND,
         _ => unreachable!(),
     };
+    op.post_process_args(&mut args);
     let start = args[0].span().0;
     let end = args[1].span().0 + args[1].span().1;
     let length = end - start;
@@ -396,6 +422,6 @@ fn parse_s_quoted_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
 
 fn parse_raw_string(pair: Pair<'_>) -> Result<SmartString<LazyCompact>> {
     Ok(SmartString::from(
-        pair.into_inner().into_iter().next().unwrap().as_str(),
+        pair.into_inner().next().unwrap().as_str(),
     ))
 }
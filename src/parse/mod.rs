@@ -4,14 +4,18 @@
 
 use std::cmp::{max, min};
 use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
 
 use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
 use pest::error::InputLocation;
 use pest::Parser;
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::algo::custom::CustomAlgos;
 use crate::data::program::InputProgram;
 use crate::data::relation::NullableColType;
+use crate::data::symb::quote_ident;
 use crate::data::value::DataValue;
 use crate::parse::query::parse_query;
 use crate::parse::schema::parse_nullable_type;
@@ -89,6 +93,56 @@ pub(crate) struct ParseError {
     pub(crate) span: SourceSpan,
 }
 
+/// Strips the backtick quoting from a (possibly compound) relation or column name.
+/// Within quotes, a doubled backtick stands for a literal one.
+pub(crate) fn unquote_ident(src: &str) -> SmartString<LazyCompact> {
+    if !src.contains('`') {
+        return SmartString::from(src);
+    }
+    let mut ret = SmartString::new();
+    let mut quoted = false;
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '`' {
+            if quoted && chars.peek() == Some(&'`') {
+                chars.next();
+                ret.push('`');
+            } else {
+                quoted = !quoted;
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Quotes `name` so that it can be pasted into a script as a relation or column name,
+/// standing for exactly that name whatever characters it contains.
+pub fn quote_identifier(name: &str) -> String {
+    quote_ident(name).into_owned()
+}
+
+/// Writes `s` as a string literal that can be pasted into a script, with quotes,
+/// backslashes and control characters escaped.
+pub fn quote_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if c.is_control() => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
 pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     let parsed = CozoScriptParser::parse(Rule::col_type_with_term, src)
         .into_diagnostic()?
@@ -97,9 +151,81 @@ pub(crate) fn parse_type(src: &str) -> Result<NullableColType> {
     parse_nullable_type(parsed.into_inner().next().unwrap())
 }
 
+/// The newest script version, declared with `%version N` at the start of a script.
+/// Scripts without the pragma are taken to be of the oldest version, so that
+/// they keep working as syntax is retired.
+pub(crate) const CURRENT_SCRIPT_VERSION: u32 = 2;
+pub(crate) const OLDEST_SCRIPT_VERSION: u32 = 1;
+
+/// A construct that is still accepted from older scripts, with a warning,
+/// but rejected from scripts declaring version `removed_in` or later.
+#[derive(Debug, Clone)]
+pub(crate) struct Deprecation {
+    pub(crate) removed_in: u32,
+    pub(crate) message: &'static str,
+    pub(crate) span: SourceSpan,
+}
+
+impl Display for Deprecation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}; this is rejected from script version {} on",
+            self.message, self.removed_in
+        )
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("{0}, which is rejected from script version {1} on")]
+#[diagnostic(code(parser::removed_syntax))]
+struct RemovedSyntax(&'static str, u32, #[label] SourceSpan);
+
+fn parse_version(src: &mut Pairs<'_>) -> Result<u32> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Unsupported script version {0}")]
+    #[diagnostic(code(parser::bad_script_version))]
+    #[diagnostic(help("Supported versions are {1} to {2}"))]
+    struct BadScriptVersion(String, u32, u32, #[label] SourceSpan);
+
+    match src.peek() {
+        Some(p) if p.as_rule() == Rule::version_pragma => {
+            src.next();
+            let v = p.into_inner().next().unwrap();
+            let version = v.as_str().replace('_', "").parse::<u32>().ok();
+            match version {
+                Some(version)
+                    if (OLDEST_SCRIPT_VERSION..=CURRENT_SCRIPT_VERSION).contains(&version) =>
+                {
+                    Ok(version)
+                }
+                _ => bail!(BadScriptVersion(
+                    v.as_str().to_string(),
+                    OLDEST_SCRIPT_VERSION,
+                    CURRENT_SCRIPT_VERSION,
+                    v.extract_span()
+                )),
+            }
+        }
+        _ => Ok(OLDEST_SCRIPT_VERSION),
+    }
+}
+
+/// Rejects the deprecated constructs that are no longer accepted by the declared version.
+fn check_deprecations(prog: &InputProgram, version: u32) -> Result<()> {
+    for d in &prog.deprecations {
+        ensure!(
+            version < d.removed_in,
+            RemovedSyntax(d.message, d.removed_in, d.span)
+        );
+    }
+    Ok(())
+}
+
 pub(crate) fn parse_script(
     src: &str,
     param_pool: &BTreeMap<String, DataValue>,
+    custom_algos: &CustomAlgos,
 ) -> Result<CozoScript> {
     let parsed = CozoScriptParser::parse(Rule::script, src)
         .map_err(|err| {
@@ -111,21 +237,27 @@ pub(crate) fn parse_script(
         })?
         .next()
         .unwrap();
-    Ok(match parsed.as_rule() {
+    let script_rule = parsed.as_rule();
+    let mut pairs = parsed.into_inner();
+    let version = parse_version(&mut pairs)?;
+    Ok(match script_rule {
         Rule::query_script => {
-            let q = parse_query(parsed.into_inner(), param_pool)?;
+            let q = parse_query(pairs, param_pool, custom_algos)?;
+            check_deprecations(&q, version)?;
             CozoScript::Multi(vec![q])
         }
         Rule::multi_script => {
             let mut qs = vec![];
-            for pair in parsed.into_inner() {
+            for pair in pairs {
                 if pair.as_rule() != Rule::EOI {
-                    qs.push(parse_query(pair.into_inner(), param_pool)?);
+                    let q = parse_query(pair.into_inner(), param_pool, custom_algos)?;
+                    check_deprecations(&q, version)?;
+                    qs.push(q);
                 }
             }
             CozoScript::Multi(qs)
         }
-        Rule::sys_script => CozoScript::Sys(parse_sys(parsed.into_inner(), param_pool)?),
+        Rule::sys_script => CozoScript::Sys(parse_sys(pairs, param_pool, custom_algos)?),
         _ => unreachable!(),
     })
 }
@@ -2,6 +2,7 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+use std::borrow::Cow;
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::error::Error;
@@ -14,20 +15,22 @@ use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::algo::constant::Constant;
+use crate::algo::custom::CustomAlgos;
 use crate::algo::AlgoHandle;
-use crate::data::aggr::{parse_aggr, Aggregation};
+use crate::data::aggr::{parse_aggr, parse_user_aggr, Aggregation};
 use crate::data::expr::Expr;
+use crate::data::functions::OP_LIST;
 use crate::data::program::{
-    AlgoApply, AlgoRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrAlgo,
+    AlgoApply, AlgoRuleArg, AsOf, InputAtom, InputInlineRule, InputInlineRulesOrAlgo,
     InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
-    QueryAssertion, QueryOutOptions, RelationOp, SortDir, Unification,
+    OverflowPolicy, QueryAssertion, QueryOutOptions, RelationOp, SortDir, Unification,
 };
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::value::DataValue;
 use crate::parse::expr::build_expr;
 use crate::parse::schema::parse_schema;
-use crate::parse::{ExtractSpan, Pair, Pairs, Rule, SourceSpan};
+use crate::parse::{unquote_ident, Deprecation, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
 use crate::runtime::relation::InputRelationHandle;
 
 #[derive(Error, Diagnostic, Debug)]
@@ -45,6 +48,11 @@ struct OptionNotNonNegIntError(&'static str, #[label] SourceSpan);
 #[diagnostic(code(parser::option_not_pos))]
 struct OptionNotPosIntError(&'static str, #[label] SourceSpan);
 
+#[derive(Error, Diagnostic, Debug)]
+#[error("Query option {0} requires a boolean")]
+#[diagnostic(code(parser::option_not_bool))]
+struct OptionNotBoolError(&'static str, #[label] SourceSpan);
+
 #[derive(Debug)]
 struct MultipleRuleDefinitionError(String, Vec<SourceSpan>);
 
@@ -84,12 +92,56 @@ fn merge_spans(symbs: &[Symbol]) -> SourceSpan {
     fst
 }
 
+/// Evaluates the `const name = expr` definitions of a script in order, making each
+/// available to later constants and to the whole script as the parameter `$name`.
+fn collect_script_consts<'a>(
+    src: Pairs<'_>,
+    param_pool: &'a BTreeMap<String, DataValue>,
+) -> Result<Cow<'a, BTreeMap<String, DataValue>>> {
+    #[derive(Error, Diagnostic, Debug)]
+    #[error("Script constant {0} is not constant")]
+    #[diagnostic(code(parser::script_const_not_constant))]
+    struct ScriptConstNotConstantError(String, #[label] SourceSpan, #[related] [Report; 1]);
+
+    #[derive(Error, Diagnostic, Debug)]
+    #[error("Script constant {0} is already defined")]
+    #[diagnostic(code(parser::script_const_redefined))]
+    #[diagnostic(help("Script constants share the namespace of parameters"))]
+    struct ScriptConstRedefinedError(String, #[label] SourceSpan);
+
+    let mut pool = Cow::Borrowed(param_pool);
+    for pair in src {
+        if pair.as_rule() != Rule::script_const {
+            continue;
+        }
+        let mut inner = pair.into_inner();
+        inner.next().unwrap();
+        let name_p = inner.next().unwrap();
+        let name = name_p.as_str().to_string();
+        ensure!(
+            !pool.contains_key(&name),
+            ScriptConstRedefinedError(name, name_p.extract_span())
+        );
+        let expr_p = inner.next().unwrap();
+        let span = expr_p.extract_span();
+        let val = build_expr(expr_p, &pool)?
+            .eval_to_const()
+            .map_err(|err| ScriptConstNotConstantError(name.clone(), span, [err]))?;
+        pool.to_mut().insert(name, val);
+    }
+    Ok(pool)
+}
+
 pub(crate) fn parse_query(
     src: Pairs<'_>,
     param_pool: &BTreeMap<String, DataValue>,
+    custom_algos: &CustomAlgos,
 ) -> Result<InputProgram> {
+    let consts = collect_script_consts(src.clone(), param_pool)?;
+    let param_pool: &BTreeMap<String, DataValue> = &consts;
     let mut progs: BTreeMap<Symbol, InputInlineRulesOrAlgo> = Default::default();
     let mut out_opts: QueryOutOptions = Default::default();
+    let mut deprecations = vec![];
     let mut stored_relation = None;
 
     for pair in src {
@@ -139,7 +191,7 @@ pub(crate) fn parse_query(
             }
             Rule::algo_rule => {
                 let rule_span = pair.extract_span();
-                let (name, apply) = parse_algo_rule(pair, param_pool)?;
+                let (name, apply) = parse_algo_rule(pair, param_pool, custom_algos)?;
 
                 match progs.entry(name) {
                     Entry::Vacant(e) => {
@@ -188,12 +240,15 @@ pub(crate) fn parse_query(
                     ensure!(a.is_none(), AggrInConstRuleError(v.span));
                 }
 
-                let data = build_expr(src.next().unwrap(), param_pool)?;
+                let data_p = src.next().unwrap();
+                let data = if data_p.as_rule() == Rule::named_rows {
+                    build_named_rows(data_p, &head, param_pool)?
+                } else {
+                    build_expr(data_p, param_pool)?
+                };
                 let mut options = BTreeMap::new();
                 options.insert(SmartString::from("data"), data);
-                let handle = AlgoHandle {
-                    name: Symbol::new("Constant", span),
-                };
+                let handle = AlgoHandle::new("Constant", span);
                 let algo_impl = handle.get_impl()?;
                 algo_impl.process_options(&mut options, span)?;
                 let arity = algo_impl.arity(&options, &head, span)?;
@@ -261,6 +316,13 @@ pub(crate) fn parse_query(
                 out_opts.offset = Some(offset as usize);
             }
             Rule::sort_option => {
+                if pair.as_str().starts_with(":order") {
+                    deprecations.push(Deprecation {
+                        removed_in: 2,
+                        message: "`:order` is deprecated, use `:sort`",
+                        span: pair.extract_span(),
+                    });
+                }
                 for part in pair.into_inner() {
                     let mut var = "";
                     let mut dir = SortDir::Asc;
@@ -293,7 +355,7 @@ pub(crate) fn parse_query(
                 };
 
                 let name_p = args.next().unwrap();
-                let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+                let name = Symbol::new(unquote_ident(name_p.as_str()), name_p.extract_span());
                 match args.next() {
                     None => stored_relation = Some(Left((name, span, op))),
                     Some(schema_p) => {
@@ -325,6 +387,64 @@ pub(crate) fn parse_query(
                 );
                 out_opts.assertion = Some(QueryAssertion::AssertSome(pair.extract_span()))
             }
+            Rule::on_error_option => {
+                let behaviour = pair.into_inner().next().unwrap();
+                out_opts.null_on_error = behaviour.as_rule() == Rule::on_error_null;
+            }
+            Rule::max_rows_scanned_option | Rule::max_intermediate_rows_option => {
+                let is_scanned = pair.as_rule() == Rule::max_rows_scanned_option;
+                let name = if is_scanned {
+                    "max_rows_scanned"
+                } else {
+                    "max_intermediate_rows"
+                };
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let max = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError(name, span, [err]))?
+                    .get_non_neg_int()
+                    .ok_or(OptionNotNonNegIntError(name, span))?;
+                ensure!(max > 0, OptionNotPosIntError(name, span));
+                if is_scanned {
+                    out_opts.max_rows_scanned = Some(max as usize);
+                } else {
+                    out_opts.max_intermediate_rows = Some(max as usize);
+                }
+            }
+            Rule::algo_memory_budget_option => {
+                let name = "algo_memory_budget";
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                let budget = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError(name, span, [err]))?
+                    .get_non_neg_int()
+                    .ok_or(OptionNotNonNegIntError(name, span))?;
+                ensure!(budget > 0, OptionNotPosIntError(name, span));
+                out_opts.algo_memory_budget = Some(budget as usize);
+            }
+            Rule::strict_option => out_opts.strict = true,
+            Rule::profile_option => out_opts.profile = true,
+            Rule::include_deleted_option => {
+                let pair = pair.into_inner().next().unwrap();
+                let span = pair.extract_span();
+                out_opts.include_deleted = build_expr(pair, param_pool)?
+                    .eval_to_const()
+                    .map_err(|err| OptionNotConstantError("include_deleted", span, [err]))?
+                    .get_bool()
+                    .ok_or(OptionNotBoolError("include_deleted", span))?;
+            }
+            Rule::overflow_option => {
+                let policy = pair.into_inner().next().unwrap();
+                out_opts.overflow = match policy.as_rule() {
+                    Rule::overflow_fail => OverflowPolicy::Fail,
+                    Rule::overflow_null => OverflowPolicy::Null,
+                    Rule::overflow_saturate => OverflowPolicy::Saturate,
+                    _ => unreachable!(),
+                };
+            }
+            Rule::script_const => {}
             Rule::EOI => break,
             r => unreachable!("{:?}", r),
         }
@@ -333,7 +453,9 @@ pub(crate) fn parse_query(
     let mut prog = InputProgram {
         prog: progs,
         out_opts,
+        deprecations,
     };
+    prog.apply_overflow_policy();
 
     if prog.prog.is_empty() {
         if let Some((
@@ -539,17 +661,19 @@ fn parse_atom(src: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result
         Rule::relation_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name = src.next().unwrap();
+            let name = parse_relation_name(src.next().unwrap(), param_pool)?;
             let args: Vec<_> = src
                 .next()
                 .unwrap()
                 .into_inner()
                 .map(|v| build_expr(v, param_pool))
                 .try_collect()?;
+            let valid_at = parse_validity_clause(src.next(), param_pool)?;
             InputAtom::Relation {
                 inner: InputRelationApplyAtom {
-                    name: Symbol::new(&name.as_str()[1..], name.extract_span()),
+                    name,
                     args,
+                    valid_at,
                     span,
                 },
             }
@@ -557,8 +681,7 @@ fn parse_atom(src: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result
         Rule::relation_named_apply => {
             let span = src.extract_span();
             let mut src = src.into_inner();
-            let name_p = src.next().unwrap();
-            let name = Symbol::new(&name_p.as_str()[1..], name_p.extract_span());
+            let name = parse_relation_name(src.next().unwrap(), param_pool)?;
             let args = src
                 .next()
                 .unwrap()
@@ -566,7 +689,7 @@ fn parse_atom(src: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result
                 .map(|pair| -> Result<(SmartString<LazyCompact>, Expr)> {
                     let mut inner = pair.into_inner();
                     let name_p = inner.next().unwrap();
-                    let name = SmartString::from(name_p.as_str());
+                    let name = unquote_ident(name_p.as_str());
                     let arg = match inner.next() {
                         Some(a) => build_expr(a, param_pool)?,
                         None => Expr::Binding {
@@ -577,11 +700,152 @@ fn parse_atom(src: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result
                     Ok((name, arg))
                 })
                 .try_collect()?;
+            let valid_at = parse_validity_clause(src.next(), param_pool)?;
             InputAtom::NamedFieldRelation {
-                inner: InputNamedFieldRelationApplyAtom { name, args, span },
+                inner: InputNamedFieldRelationApplyAtom {
+                    name,
+                    args,
+                    valid_at,
+                    span,
+                },
             }
         }
-        rule => unreachable!("{:?}", rule),
+        r => unreachable!("{:?}", r),
+    })
+}
+
+/// The stored relation named after `*`, either written out or, as in `*$rel`, held by a
+/// parameter. Names from parameters are never parsed, so they cannot smuggle in script text.
+fn parse_relation_name(pair: Pair<'_>, param_pool: &BTreeMap<String, DataValue>) -> Result<Symbol> {
+    #[derive(Error, Diagnostic, Debug)]
+    #[error("Required parameter {0} not found")]
+    #[diagnostic(code(parser::param_not_found))]
+    struct ParamNotFoundError(String, #[label] SourceSpan);
+
+    #[derive(Error, Diagnostic, Debug)]
+    #[error("Parameter {0} is not a valid relation name: {1}")]
+    #[diagnostic(code(parser::bad_relation_param))]
+    #[diagnostic(help(
+        "Relation names given as parameters must be non-empty strings naming local relations"
+    ))]
+    struct BadRelationParam(String, DataValue, #[label] SourceSpan);
+
+    let span = pair.extract_span();
+    let name = pair.as_str().strip_prefix('*').unwrap();
+    let param = match name.strip_prefix('$') {
+        None => return Ok(Symbol::new(unquote_ident(name), span)),
+        Some(param) => param,
+    };
+    let val = param_pool
+        .get(param)
+        .ok_or_else(|| ParamNotFoundError(param.to_string(), span))?;
+    match val {
+        DataValue::Str(s) if !s.is_empty() && !s.contains("::") => Ok(Symbol::new(s.clone(), span)),
+        v => bail!(BadRelationParam(param.to_string(), v.clone(), span)),
+    }
+}
+
+/// Evaluates the time or the transaction id given after `@` in a stored relation application.
+fn parse_validity_clause(
+    src: Option<Pair<'_>>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<Option<AsOf>> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Bad validity timestamp: {0:?}")]
+    #[diagnostic(code(parser::bad_validity))]
+    #[diagnostic(help(
+        "The timestamp must evaluate to a constant timestamp or number of seconds since the epoch"
+    ))]
+    struct BadValidity(DataValue, #[label] SourceSpan);
+
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Bad transaction id: {0:?}")]
+    #[diagnostic(code(parser::bad_validity_tx))]
+    #[diagnostic(help(
+        "The id must evaluate to a non-negative integer, such as the 'tx_id' returned by a write"
+    ))]
+    struct BadValidityTx(DataValue, #[label] SourceSpan);
+
+    let src = match src {
+        None => return Ok(None),
+        Some(p) => p.into_inner().next().unwrap(),
+    };
+    if src.as_rule() == Rule::validity_tx {
+        let src = src.into_inner().nth(1).unwrap();
+        let span = src.extract_span();
+        let val = build_expr(src, param_pool)?.eval_to_const()?;
+        return match val.get_non_neg_int() {
+            Some(id) => Ok(Some(AsOf::Tx(id))),
+            None => bail!(BadValidityTx(val, span)),
+        };
+    }
+    let span = src.extract_span();
+    let val = build_expr(src, param_pool)?.eval_to_const()?;
+    let secs = match &val {
+        DataValue::Timestamp(t) => Some(*t as f64 / 1e6),
+        v => v.get_float(),
+    };
+    match secs {
+        Some(f) => Ok(Some(AsOf::Time(f))),
+        None => bail!(BadValidity(val, span)),
+    }
+}
+
+/// Turns rows given as `{col: expr, ...}` into positional rows following the rule head.
+/// Columns missing from a row are filled with `null`.
+fn build_named_rows(
+    src: Pair<'_>,
+    head: &[Symbol],
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<Expr> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Column '{0}' does not appear in the head of the constant rule")]
+    #[diagnostic(code(parser::named_row_unknown_column))]
+    struct UnknownColumnInRow(String, #[label] SourceSpan);
+
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Column '{0}' is given more than once in a row")]
+    #[diagnostic(code(parser::named_row_duplicate_column))]
+    struct DuplicateColumnInRow(String, #[label] SourceSpan);
+
+    let span = src.extract_span();
+    let mut rows = vec![];
+    for row_p in src.into_inner() {
+        let row_span = row_p.extract_span();
+        let mut row: Vec<Option<Expr>> = vec![None; head.len()];
+        for pair in row_p.into_inner() {
+            let mut inner = pair.into_inner();
+            let name_p = inner.next().unwrap();
+            let name = unquote_ident(name_p.as_str());
+            let idx = head
+                .iter()
+                .position(|h| h.name == name)
+                .ok_or_else(|| UnknownColumnInRow(name.to_string(), name_p.extract_span()))?;
+            ensure!(
+                row[idx].is_none(),
+                DuplicateColumnInRow(name.to_string(), name_p.extract_span())
+            );
+            row[idx] = Some(build_expr(inner.next().unwrap(), param_pool)?);
+        }
+        let args = row
+            .into_iter()
+            .map(|v| {
+                v.unwrap_or(Expr::Const {
+                    val: DataValue::Null,
+                    span: row_span,
+                })
+            })
+            .collect_vec();
+        rows.push(Expr::Apply {
+            op: &OP_LIST,
+            args: args.into(),
+            span: row_span,
+        });
+    }
+    Ok(Expr::Apply {
+        op: &OP_LIST,
+        args: rows.into(),
+        span,
     })
 }
 
@@ -629,8 +893,11 @@ fn parse_rule_head_arg(
                 Symbol::new(var.as_str(), var.extract_span()),
                 Some((
                     parse_aggr(aggr_name)
-                        .ok_or_else(|| AggrNotFound(aggr_name.to_string(), aggr_p.extract_span()))?
-                        .clone(),
+                        .cloned()
+                        .or_else(|| parse_user_aggr(aggr_name))
+                        .ok_or_else(|| {
+                            AggrNotFound(aggr_name.to_string(), aggr_p.extract_span())
+                        })?,
                     args,
                 )),
             )
@@ -642,6 +909,7 @@ fn parse_rule_head_arg(
 fn parse_algo_rule(
     src: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
+    custom_algos: &CustomAlgos,
 ) -> Result<(Symbol, AlgoApply)> {
     let mut src = src.into_inner();
     let (out_symbol, head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
@@ -683,26 +951,34 @@ fn parse_algo_rule(
                     Rule::algo_relation_rel => {
                         let mut els = inner.into_inner();
                         let name = els.next().unwrap();
-                        let bindings = els
+                        let (bindings, validity): (Vec<_>, Vec<_>) =
+                            els.partition(|p| p.as_rule() != Rule::validity_clause);
+                        let bindings = bindings
+                            .into_iter()
                             .map(|v| Symbol::new(v.as_str(), v.extract_span()))
                             .collect_vec();
+                        let valid_at =
+                            parse_validity_clause(validity.into_iter().next(), param_pool)?;
                         rule_args.push(AlgoRuleArg::Stored {
-                            name: Symbol::new(
-                                name.as_str().strip_prefix('*').unwrap(),
-                                name.extract_span(),
-                            ),
+                            name: parse_relation_name(name, param_pool)?,
                             bindings,
+                            valid_at,
                             span,
                         })
                     }
                     Rule::algo_named_relation_rel => {
                         let mut els = inner.into_inner();
                         let name = els.next().unwrap();
-                        let bindings = els
+                        let (bindings, validity): (Vec<_>, Vec<_>) =
+                            els.partition(|p| p.as_rule() != Rule::validity_clause);
+                        let valid_at =
+                            parse_validity_clause(validity.into_iter().next(), param_pool)?;
+                        let bindings = bindings
+                            .into_iter()
                             .map(|v| {
                                 let mut vs = v.into_inner();
                                 let kp = vs.next().unwrap();
-                                let k = SmartString::from(kp.as_str());
+                                let k = unquote_ident(kp.as_str());
                                 let v = match vs.next() {
                                     Some(vp) => Symbol::new(vp.as_str(), vp.extract_span()),
                                     None => Symbol::new(k.clone(), kp.extract_span()),
@@ -712,11 +988,9 @@ fn parse_algo_rule(
                             .collect();
 
                         rule_args.push(AlgoRuleArg::NamedStored {
-                            name: Symbol::new(
-                                name.as_str().strip_prefix(':').unwrap(),
-                                name.extract_span(),
-                            ),
+                            name: parse_relation_name(name, param_pool)?,
                             bindings,
+                            valid_at,
                             span,
                         })
                     }
@@ -734,7 +1008,7 @@ fn parse_algo_rule(
         }
     }
 
-    let algo = AlgoHandle::new(algo_name, name_pair.extract_span());
+    let algo = AlgoHandle::resolve(algo_name, name_pair.extract_span(), custom_algos);
 
     let algo_impl = algo.get_impl()?;
     algo_impl.process_options(&mut options, args_list_span)?;
@@ -784,9 +1058,7 @@ fn make_empty_const_rule(prog: &mut InputProgram, bindings: &[Symbol]) {
         entry_symbol,
         InputInlineRulesOrAlgo::Algo {
             algo: AlgoApply {
-                algo: AlgoHandle {
-                    name: Symbol::new("Constant", Default::default()),
-                },
+                algo: AlgoHandle::new("Constant", Default::default()),
                 rule_args: vec![],
                 options,
                 head: bindings.to_vec(),
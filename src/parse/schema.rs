@@ -6,14 +6,13 @@ use std::collections::BTreeSet;
 
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result};
-use smartstring::SmartString;
 use thiserror::Error;
 
 use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::parse::expr::build_expr;
-use crate::parse::{ExtractSpan, Pair, Rule, SourceSpan};
+use crate::parse::{unquote_ident, ExtractSpan, Pair, Rule, SourceSpan};
 
 pub(crate) fn parse_schema(
     pair: Pair<'_>,
@@ -74,7 +73,7 @@ pub(crate) fn parse_schema(
 fn parse_col(pair: Pair<'_>) -> Result<(ColumnDef, Symbol)> {
     let mut src = pair.into_inner();
     let name_p = src.next().unwrap();
-    let name = SmartString::from(name_p.as_str());
+    let name = unquote_ident(name_p.as_str());
     let mut typing = NullableColType {
         coltype: ColType::Any,
         nullable: true,
@@ -115,6 +114,10 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         Rule::bool_type => ColType::Bool,
         Rule::int_type => ColType::Int,
         Rule::float_type => ColType::Float,
+        Rule::bigint_type => ColType::BigInt,
+        Rule::decimal_type => ColType::Decimal,
+        Rule::timestamp_type => ColType::Timestamp,
+        Rule::json_type => ColType::Json,
         Rule::string_type => ColType::String,
         Rule::bytes_type => ColType::Bytes,
         Rule::uuid_type => ColType::Uuid,
@@ -143,6 +146,26 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
                 len,
             }
         }
+        Rule::set_type => ColType::Set {
+            eltype: parse_nullable_type(pair.into_inner().next().unwrap())?.into(),
+        },
+        Rule::vec_type => {
+            let dim_p = pair.into_inner().next().unwrap();
+
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Bad dimension of vector type: {0}")]
+            #[diagnostic(code(parser::bad_vec_dim_in_type))]
+            struct BadVecDimSpec(String, #[label] SourceSpan);
+
+            let dim = dim_p
+                .as_str()
+                .replace('_', "")
+                .parse::<usize>()
+                .ok()
+                .filter(|d| *d > 0)
+                .ok_or_else(|| BadVecDimSpec(dim_p.as_str().to_string(), dim_p.extract_span()))?;
+            ColType::Vec { dim }
+        }
         Rule::tuple_type => {
             ColType::Tuple(pair.into_inner().map(parse_nullable_type).try_collect()?)
         }
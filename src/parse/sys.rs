@@ -2,31 +2,85 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
 
 use itertools::Itertools;
-use miette::{Diagnostic, Result};
+use miette::{bail, ensure, Diagnostic, Result};
 use thiserror::Error;
 
+use crate::algo::custom::CustomAlgos;
 use crate::data::program::InputProgram;
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
+use crate::parse::expr::{build_expr, parse_string};
 use crate::parse::query::parse_query;
-use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
+use crate::parse::{unquote_ident, ExtractSpan, Pair, Pairs, Rule, SourceSpan};
+use crate::runtime::csv_import::CsvImportOptions;
+use crate::runtime::graph_view::GraphView;
+use crate::runtime::hnsw::{VectorDistance, VectorIndexConfig};
+use crate::runtime::infer::{ImportFormat, InferredImport, DEFAULT_INFER_SAMPLE};
+use crate::runtime::job::{CronSchedule, Job};
+use crate::runtime::maintain::{MaintainedColumn, MaintainedRelation};
+use crate::runtime::namespace::NamespaceQuota;
 use crate::runtime::relation::AccessLevel;
+use crate::runtime::schedule::ScheduledQuery;
+
+/// A relation with the prefixes of keys from which (inclusive) and up to which (exclusive)
+/// its rows are affected.
+pub(crate) type RelationRange = (Symbol, Option<Vec<DataValue>>, Option<Vec<DataValue>>);
 
 pub(crate) enum SysOp {
     Compact,
     ListRelation(Symbol),
+    RelationStats(Symbol),
+    RelationChecksum(Symbol),
     ListRelations,
+    ListFunctions,
+    ListAlgos,
     ListRunning,
     KillRunning(u64),
     Explain(Box<InputProgram>),
+    Estimate(Box<InputProgram>),
     RemoveRelation(Vec<Symbol>),
     RenameRelation(Vec<(Symbol, Symbol)>),
+    CloneRelation(Symbol, Symbol),
+    DeleteRange(Symbol, Option<Vec<DataValue>>, Option<Vec<DataValue>>),
+    TruncateRelation(Symbol),
+    SetRelationHistory(Symbol, bool),
+    SetRelationLww(Symbol, bool),
+    SetRelationSoftDelete(Symbol, bool),
+    PurgeRelation(Symbol),
+    SetRelationTiering(Symbol, Option<f64>),
+    OffloadRelation(Symbol),
+    ImportRemote(String, Vec<RelationRange>, Option<String>),
+    ImportInferred(Symbol, InferredImport),
+    ImportCsv(Symbol, String, CsvImportOptions),
+    SetVectorIndex(Symbol, Option<VectorIndexConfig>),
     ShowTrigger(Symbol),
     SetTriggers(Symbol, Vec<String>, Vec<String>, Vec<String>),
     SetAccessLevel(Vec<Symbol>, AccessLevel),
+    ListPlans,
+    PinPlan(String),
+    UnpinPlan(String),
+    SetGraphView(Symbol, GraphView),
+    RemoveGraphView(Symbol),
+    ListGraphViews,
+    CreateNamespace(Symbol),
+    RemoveNamespace(Symbol),
+    SetNamespaceQuota(Symbol, NamespaceQuota),
+    ListNamespaces,
+    CreateSchedule(Symbol, ScheduledQuery),
+    RemoveSchedule(Symbol),
+    ListSchedules,
+    CreateJob(Symbol, Job),
+    RemoveJob(Symbol),
+    RunJob(Symbol),
+    ShowJobHistory(Symbol),
+    ListJobs,
+    CreateMaintained(Symbol, MaintainedRelation),
+    RemoveMaintained(Symbol),
+    ListMaintained,
 }
 
 #[derive(Debug, Diagnostic, Error)]
@@ -37,6 +91,7 @@ struct ProcessIdError(String, #[label] SourceSpan);
 pub(crate) fn parse_sys(
     mut src: Pairs<'_>,
     param_pool: &BTreeMap<String, DataValue>,
+    custom_algos: &CustomAlgos,
 ) -> Result<SysOp> {
     let inner = src.next().unwrap();
     Ok(match inner.as_rule() {
@@ -51,37 +106,355 @@ pub(crate) fn parse_sys(
             SysOp::KillRunning(i)
         }
         Rule::explain_op => {
-            let prog = parse_query(inner.into_inner().next().unwrap().into_inner(), param_pool)?;
+            let prog = parse_query(
+                inner.into_inner().next().unwrap().into_inner(),
+                param_pool,
+                custom_algos,
+            )?;
             SysOp::Explain(Box::new(prog))
         }
+        Rule::estimate_op => {
+            let prog = parse_query(
+                inner.into_inner().next().unwrap().into_inner(),
+                param_pool,
+                custom_algos,
+            )?;
+            SysOp::Estimate(Box::new(prog))
+        }
         Rule::list_relations_op => SysOp::ListRelations,
+        Rule::list_functions_op => SysOp::ListFunctions,
+        Rule::list_algos_op => SysOp::ListAlgos,
         Rule::remove_relations_op => {
             let rel = inner
                 .into_inner()
-                .map(|rels_p| Symbol::new(rels_p.as_str(), rels_p.extract_span()))
+                .map(|rels_p| Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span()))
                 .collect_vec();
 
             SysOp::RemoveRelation(rel)
         }
         Rule::list_relation_op => {
             let rels_p = inner.into_inner().next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             SysOp::ListRelation(rel)
         }
+        Rule::relation_stats_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            SysOp::RelationStats(rel)
+        }
+        Rule::relation_checksum_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            SysOp::RelationChecksum(rel)
+        }
         Rule::rename_relations_op => {
             let rename_pairs = inner
                 .into_inner()
                 .map(|pair| {
                     let mut src = pair.into_inner();
                     let rels_p = src.next().unwrap();
-                    let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+                    let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
                     let rels_p = src.next().unwrap();
-                    let new_rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+                    let new_rel =
+                        Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
                     (rel, new_rel)
                 })
                 .collect_vec();
             SysOp::RenameRelation(rename_pairs)
         }
+        Rule::clone_relation_op => {
+            let mut src = inner.into_inner().next().unwrap().into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            let rels_p = src.next().unwrap();
+            let new_rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            SysOp::CloneRelation(rel, new_rel)
+        }
+        Rule::delete_range_op => {
+            let (rel, from, to) = parse_relation_range(inner.into_inner(), param_pool)?;
+            SysOp::DeleteRange(rel, from, to)
+        }
+        Rule::truncate_relation_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            SysOp::TruncateRelation(rel)
+        }
+        Rule::history_relation_op => {
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            let retain = src.next().unwrap().as_rule() == Rule::history_on;
+            SysOp::SetRelationHistory(rel, retain)
+        }
+        Rule::lww_relation_op => {
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            let maintain = src.next().unwrap().as_rule() == Rule::history_on;
+            SysOp::SetRelationLww(rel, maintain)
+        }
+        Rule::soft_delete_relation_op => {
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            let soft = src.next().unwrap().as_rule() == Rule::history_on;
+            SysOp::SetRelationSoftDelete(rel, soft)
+        }
+        Rule::purge_relation_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            SysOp::PurgeRelation(rel)
+        }
+        Rule::tier_relation_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("The age after which rows are offloaded must be a positive number of seconds")]
+            #[diagnostic(code(parser::bad_tiering_age))]
+            struct BadTieringAge(#[label] SourceSpan);
+
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            let switch = src.next().unwrap();
+            let after = match switch.as_rule() {
+                Rule::history_off => None,
+                Rule::tier_after => {
+                    let span = switch.extract_span();
+                    let expr_p = switch.into_inner().next().unwrap();
+                    let val = build_expr(expr_p, param_pool)?.eval_to_const()?;
+                    match val.get_float() {
+                        Some(f) if f > 0. => Some(f),
+                        _ => bail!(BadTieringAge(span)),
+                    }
+                }
+                r => unreachable!("{:?}", r),
+            };
+            SysOp::SetRelationTiering(rel, after)
+        }
+        Rule::import_remote_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Expect a string for the {0} of the remote database")]
+            #[diagnostic(code(parser::bad_import_remote))]
+            struct BadImportRemote(&'static str, #[label] SourceSpan);
+
+            let mut src = inner.into_inner();
+            let url_p = src.next().unwrap();
+            let url_span = url_p.extract_span();
+            let url = match build_expr(url_p, param_pool)?.eval_to_const()? {
+                DataValue::Str(s) => s.to_string(),
+                _ => bail!(BadImportRemote("URL", url_span)),
+            };
+            let mut relations = vec![];
+            let mut auth = None;
+            for p in src {
+                match p.as_rule() {
+                    Rule::import_relation => {
+                        relations.push(parse_relation_range(p.into_inner(), param_pool)?)
+                    }
+                    Rule::import_auth => {
+                        let auth_p = p.into_inner().next().unwrap();
+                        let auth_span = auth_p.extract_span();
+                        auth = match build_expr(auth_p, param_pool)?.eval_to_const()? {
+                            DataValue::Str(s) => Some(s.to_string()),
+                            _ => bail!(BadImportRemote("auth token", auth_span)),
+                        };
+                    }
+                    r => unreachable!("{:?}", r),
+                }
+            }
+            SysOp::ImportRemote(url, relations, auth)
+        }
+        Rule::import_into_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Expect a string for the path or URL of the CSV source to import")]
+            #[diagnostic(code(parser::bad_import_url))]
+            struct BadImportUrl(#[label] SourceSpan);
+
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Bad value for CSV import option '{0}'")]
+            #[diagnostic(code(parser::bad_csv_import_option))]
+            #[diagnostic(help("{1}"))]
+            struct BadCsvImportOption(String, String, #[label] SourceSpan);
+
+            let mut src = inner.into_inner();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            let url_p = src.next().unwrap();
+            let url_span = url_p.extract_span();
+            let url = match build_expr(url_p, param_pool)?.eval_to_const()? {
+                DataValue::Str(s) => s.to_string(),
+                _ => bail!(BadImportUrl(url_span)),
+            };
+            let mut options = CsvImportOptions::default();
+            for opt in src {
+                let span = opt.extract_span();
+                let mut opt_inner = opt.into_inner();
+                let name = opt_inner.next().unwrap().as_str();
+                let val = build_expr(opt_inner.next().unwrap(), param_pool)?.eval_to_const()?;
+                match name {
+                    "delimiter" => {
+                        options.delimiter = match val.get_string().map(|s| s.as_bytes()) {
+                            Some([b]) => *b,
+                            _ => bail!(BadCsvImportOption(
+                                name.to_string(),
+                                "A string of a single byte is required".to_string(),
+                                span
+                            )),
+                        }
+                    }
+                    "headers" => {
+                        options.has_headers = match val.get_bool() {
+                            Some(b) => b,
+                            _ => bail!(BadCsvImportOption(
+                                name.to_string(),
+                                "A boolean is required".to_string(),
+                                span
+                            )),
+                        }
+                    }
+                    "batch_size" => {
+                        options.batch_size = match val.get_non_neg_int() {
+                            Some(n) if n > 0 => n as usize,
+                            _ => bail!(BadCsvImportOption(
+                                name.to_string(),
+                                "A positive integer is required".to_string(),
+                                span
+                            )),
+                        }
+                    }
+                    _ => bail!(BadCsvImportOption(
+                        name.to_string(),
+                        "Valid options are 'delimiter', 'headers' and 'batch_size'".to_string(),
+                        span
+                    )),
+                }
+            }
+            SysOp::ImportCsv(rel, url, options)
+        }
+        Rule::import_infer_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Expect a string for the URL of the source to import")]
+            #[diagnostic(code(parser::bad_import_url))]
+            struct BadImportUrl(#[label] SourceSpan);
+
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("The sample size must be a positive integer")]
+            #[diagnostic(code(parser::bad_import_sample))]
+            struct BadImportSample(#[label] SourceSpan);
+
+            let mut src = inner.into_inner();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
+            let url_p = src.next().unwrap();
+            let url_span = url_p.extract_span();
+            let url = match build_expr(url_p, param_pool)?.eval_to_const()? {
+                DataValue::Str(s) => s.to_string(),
+                _ => bail!(BadImportUrl(url_span)),
+            };
+            let mut import = InferredImport {
+                url,
+                format: None,
+                sample: DEFAULT_INFER_SAMPLE,
+            };
+            for p in src {
+                match p.as_rule() {
+                    Rule::import_format => {
+                        import.format = match p.into_inner().next().unwrap().as_rule() {
+                            Rule::import_csv => Some(ImportFormat::Csv),
+                            Rule::import_jsonl => Some(ImportFormat::JsonLines),
+                            r => unreachable!("{:?}", r),
+                        }
+                    }
+                    Rule::import_sample => {
+                        let sample_p = p.into_inner().next().unwrap();
+                        let span = sample_p.extract_span();
+                        import.sample = match build_expr(sample_p, param_pool)?
+                            .eval_to_const()?
+                            .get_non_neg_int()
+                        {
+                            Some(n) if n > 0 => n as usize,
+                            _ => bail!(BadImportSample(span)),
+                        };
+                    }
+                    r => unreachable!("{:?}", r),
+                }
+            }
+            SysOp::ImportInferred(rel, import)
+        }
+        Rule::offload_relation_op => {
+            let rels_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            SysOp::OffloadRelation(rel)
+        }
+        Rule::vector_index_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Bad value for vector index option '{0}'")]
+            #[diagnostic(code(parser::bad_vector_index_option))]
+            #[diagnostic(help("{1}"))]
+            struct BadVectorIndexOption(String, String, #[label] SourceSpan);
+
+            let mut src = inner.into_inner();
+            let rels_p = src.next().unwrap();
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+            let switch = src.next().unwrap();
+            let config = match switch.as_rule() {
+                Rule::vector_index_off => None,
+                Rule::vector_index_on => {
+                    let mut opts = switch.into_inner();
+                    let col_p = opts.next().unwrap();
+                    let mut config = VectorIndexConfig {
+                        column: Symbol::new(col_p.as_str(), col_p.extract_span()),
+                        m: 16,
+                        ef_construction: 200,
+                        distance: VectorDistance::L2,
+                    };
+                    for opt in opts {
+                        let span = opt.extract_span();
+                        let mut opt_inner = opt.into_inner();
+                        let name = opt_inner.next().unwrap().as_str();
+                        let val =
+                            build_expr(opt_inner.next().unwrap(), param_pool)?.eval_to_const()?;
+                        match name {
+                            "m" | "ef_construction" => {
+                                let n = match val.get_int() {
+                                    Some(i) if i > 1 => i as usize,
+                                    _ => bail!(BadVectorIndexOption(
+                                        name.to_string(),
+                                        "An integer greater than 1 is required".to_string(),
+                                        span
+                                    )),
+                                };
+                                if name == "m" {
+                                    config.m = n;
+                                } else {
+                                    config.ef_construction = n;
+                                }
+                            }
+                            "distance" => {
+                                config.distance = match val.get_string() {
+                                    Some("l2") => VectorDistance::L2,
+                                    Some("cosine") => VectorDistance::Cosine,
+                                    Some("ip") => VectorDistance::InnerProduct,
+                                    _ => bail!(BadVectorIndexOption(
+                                        name.to_string(),
+                                        "Expect one of 'l2', 'cosine' and 'ip'".to_string(),
+                                        span
+                                    )),
+                                }
+                            }
+                            _ => bail!(BadVectorIndexOption(
+                                name.to_string(),
+                                "Valid options are 'm', 'ef_construction' and 'distance'"
+                                    .to_string(),
+                                span
+                            )),
+                        }
+                    }
+                    Some(config)
+                }
+                r => unreachable!("{:?}", r),
+            };
+            SysOp::SetVectorIndex(rel, config)
+        }
         Rule::access_level_op => {
             let mut ps = inner.into_inner();
             let access_level = match ps.next().unwrap().as_str() {
@@ -89,24 +462,313 @@ pub(crate) fn parse_sys(
                 "protected" => AccessLevel::Protected,
                 "read_only" => AccessLevel::ReadOnly,
                 "hidden" => AccessLevel::Hidden,
-                _ => unreachable!()
+                _ => unreachable!(),
             };
             let mut rels = vec![];
             for rel_p in ps {
-                let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                let rel = Symbol::new(unquote_ident(rel_p.as_str()), rel_p.extract_span());
                 rels.push(rel)
             }
             SysOp::SetAccessLevel(rels, access_level)
         }
+        Rule::plan_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::plan_list => SysOp::ListPlans,
+                Rule::plan_pin => {
+                    SysOp::PinPlan(op.into_inner().next().unwrap().as_str().to_string())
+                }
+                Rule::plan_unpin => {
+                    SysOp::UnpinPlan(op.into_inner().next().unwrap().as_str().to_string())
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::schedule_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("The interval of a scheduled query must be a positive number of seconds")]
+            #[diagnostic(code(parser::bad_schedule_interval))]
+            struct BadScheduleInterval(#[label] SourceSpan);
+
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Scheduled queries cannot write to stored relations")]
+            #[diagnostic(code(parser::scheduled_query_write))]
+            #[diagnostic(help(
+                "The result and its changes are written into the relations named after 'into' and 'delta'"
+            ))]
+            struct ScheduledQueryWrite(#[label] SourceSpan);
+
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::schedule_list => SysOp::ListSchedules,
+                Rule::schedule_remove => {
+                    let name_p = op.into_inner().next().unwrap();
+                    SysOp::RemoveSchedule(Symbol::new(name_p.as_str(), name_p.extract_span()))
+                }
+                Rule::schedule_create => {
+                    let mut src = op.into_inner();
+                    let name_p = src.next().unwrap();
+                    let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+                    let every_p = src.next().unwrap();
+                    let span = every_p.extract_span();
+                    let every = match build_expr(every_p, param_pool)?
+                        .eval_to_const()?
+                        .get_float()
+                    {
+                        Some(f) if f > 0. => f,
+                        _ => bail!(BadScheduleInterval(span)),
+                    };
+                    let result_p = src.next().unwrap();
+                    let delta_p = src.next().unwrap();
+                    let script = src.next().unwrap();
+                    let span = script.extract_span();
+                    let script_str = script.as_str();
+                    let script_str = script_str[1..script_str.len() - 1].to_string();
+                    let prog = parse_query(script.into_inner(), &Default::default(), custom_algos)?;
+                    ensure!(
+                        prog.out_opts.store_relation.is_none(),
+                        ScheduledQueryWrite(span)
+                    );
+                    SysOp::CreateSchedule(
+                        name,
+                        ScheduledQuery {
+                            every,
+                            script: script_str,
+                            result: unquote_ident(result_p.as_str()).to_string(),
+                            delta: unquote_ident(delta_p.as_str()).to_string(),
+                            last_run: None,
+                        },
+                    )
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::job_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Bad cron expression: {0}")]
+            #[diagnostic(code(parser::bad_cron_expr))]
+            #[diagnostic(help(
+                "Cron expressions have five fields: minute, hour, day of month, month and day of week"
+            ))]
+            struct BadCronExpr(String, #[label] SourceSpan);
+
+            let op = inner.into_inner().next().unwrap();
+            let symbol = |p: Pair<'_>| Symbol::new(p.as_str(), p.extract_span());
+            match op.as_rule() {
+                Rule::job_list => SysOp::ListJobs,
+                Rule::job_remove => SysOp::RemoveJob(symbol(op.into_inner().next().unwrap())),
+                Rule::job_run => SysOp::RunJob(symbol(op.into_inner().next().unwrap())),
+                Rule::job_history => SysOp::ShowJobHistory(symbol(op.into_inner().next().unwrap())),
+                Rule::job_create => {
+                    let mut src = op.into_inner();
+                    let name = symbol(src.next().unwrap());
+                    let cron_p = src.next().unwrap();
+                    let span = cron_p.extract_span();
+                    let cron = parse_string(cron_p)?.to_string();
+                    if let Err(msg) = CronSchedule::from_str(&cron) {
+                        bail!(BadCronExpr(msg, span))
+                    }
+                    let mut scripts = vec![];
+                    for script in src {
+                        scripts.push(script.as_str().to_string());
+                        parse_query(script.into_inner(), &Default::default(), custom_algos)?;
+                    }
+                    SysOp::CreateJob(
+                        name,
+                        Job {
+                            cron,
+                            script: scripts.join("\n"),
+                            next_run: None,
+                            last_run: None,
+                            failures: 0,
+                            label: None,
+                        },
+                    )
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::maintain_op => {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Duplicate column '{0}' in maintained relation")]
+            #[diagnostic(code(parser::dup_maintained_column))]
+            struct DuplicateMaintainedColumn(String, #[label] SourceSpan);
+
+            let op = inner.into_inner().next().unwrap();
+            let symbol = |p: Pair<'_>| Symbol::new(unquote_ident(p.as_str()), p.extract_span());
+            match op.as_rule() {
+                Rule::maintain_list => SysOp::ListMaintained,
+                Rule::maintain_remove => {
+                    SysOp::RemoveMaintained(symbol(op.into_inner().next().unwrap()))
+                }
+                Rule::maintain_create => {
+                    let mut src = op.into_inner();
+                    let name = symbol(src.next().unwrap());
+                    let base = unquote_ident(src.next().unwrap().as_str()).to_string();
+                    let mut rule = MaintainedRelation {
+                        base,
+                        by: vec![],
+                        columns: vec![],
+                    };
+                    let mut seen = BTreeSet::new();
+                    for p in src {
+                        let span = p.extract_span();
+                        let col_name = match p.as_rule() {
+                            Rule::var => {
+                                rule.by.push(p.as_str().to_string());
+                                p.as_str().to_string()
+                            }
+                            Rule::maintain_column => {
+                                let mut col_src = p.into_inner();
+                                let col_name = col_src.next().unwrap().as_str().to_string();
+                                let aggr_p = col_src.next().unwrap();
+                                let aggr = aggr_p.as_str().to_string();
+                                let arg = aggr_p.into_inner().nth(1).unwrap().as_str().to_string();
+                                rule.columns.push(MaintainedColumn {
+                                    name: col_name.clone(),
+                                    aggr,
+                                    arg,
+                                });
+                                col_name
+                            }
+                            r => unreachable!("{:?}", r),
+                        };
+                        ensure!(
+                            seen.insert(col_name.clone()),
+                            DuplicateMaintainedColumn(col_name, span)
+                        );
+                    }
+                    SysOp::CreateMaintained(name, rule)
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::graph_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::graph_list => SysOp::ListGraphViews,
+                Rule::graph_remove => {
+                    let name_p = op.into_inner().next().unwrap();
+                    SysOp::RemoveGraphView(Symbol::new(name_p.as_str(), name_p.extract_span()))
+                }
+                Rule::graph_view => {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("The parts of a graph view must consist of rules for the entry only")]
+                    #[diagnostic(code(parser::bad_graph_view_part))]
+                    #[diagnostic(help(
+                        "Options and auxiliary rules are not allowed in graph views"
+                    ))]
+                    struct BadGraphViewPart(#[label] SourceSpan);
+
+                    let mut src = op.into_inner();
+                    let name_p = src.next().unwrap();
+                    let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+                    let mut view = GraphView::default();
+                    for part in src {
+                        let mut part_inner = part.into_inner();
+                        let kind = part_inner.next().unwrap();
+                        let script = part_inner.next().unwrap();
+                        let span = script.extract_span();
+                        let script_str = script.as_str();
+                        let script_str = script_str[1..script_str.len() - 1].to_string();
+                        let prog =
+                            parse_query(script.into_inner(), &Default::default(), custom_algos)?;
+                        ensure!(
+                            prog.prog.len() == 1 && prog.out_opts == Default::default(),
+                            BadGraphViewPart(span)
+                        );
+                        prog.get_entry_out_head()?;
+                        match kind.as_rule() {
+                            Rule::graph_nodes => view.nodes = Some(script_str),
+                            Rule::graph_edges => view.edges = Some(script_str),
+                            r => unreachable!("{:?}", r),
+                        }
+                    }
+                    SysOp::SetGraphView(name, view)
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
+        Rule::namespace_op => {
+            let op = inner.into_inner().next().unwrap();
+            match op.as_rule() {
+                Rule::namespace_list => SysOp::ListNamespaces,
+                Rule::namespace_create => {
+                    let name_p = op.into_inner().next().unwrap();
+                    let name = Symbol::new(unquote_ident(name_p.as_str()), name_p.extract_span());
+                    SysOp::CreateNamespace(name)
+                }
+                Rule::namespace_remove => {
+                    let name_p = op.into_inner().next().unwrap();
+                    let name = Symbol::new(unquote_ident(name_p.as_str()), name_p.extract_span());
+                    SysOp::RemoveNamespace(name)
+                }
+                Rule::namespace_quota => {
+                    #[derive(Debug, Error, Diagnostic)]
+                    #[error("Bad value for namespace quota option '{0}'")]
+                    #[diagnostic(code(parser::bad_namespace_quota_option))]
+                    #[diagnostic(help("{1}"))]
+                    struct BadNamespaceQuotaOption(String, String, #[label] SourceSpan);
+
+                    let mut src = op.into_inner();
+                    let name_p = src.next().unwrap();
+                    let name = Symbol::new(unquote_ident(name_p.as_str()), name_p.extract_span());
+                    let mut quota = NamespaceQuota::default();
+                    for opt in src {
+                        let span = opt.extract_span();
+                        let mut opt_inner = opt.into_inner();
+                        let opt_name = opt_inner.next().unwrap().as_str();
+                        let val =
+                            build_expr(opt_inner.next().unwrap(), param_pool)?.eval_to_const()?;
+                        match opt_name {
+                            "max_storage_bytes" | "max_result_rows" => {
+                                let n = match val.get_int() {
+                                    Some(i) if i >= 0 => i,
+                                    _ => bail!(BadNamespaceQuotaOption(
+                                        opt_name.to_string(),
+                                        "A non-negative integer is required".to_string(),
+                                        span
+                                    )),
+                                };
+                                if opt_name == "max_storage_bytes" {
+                                    quota.max_storage_bytes = Some(n as u64);
+                                } else {
+                                    quota.max_result_rows = Some(n as usize);
+                                }
+                            }
+                            "max_query_secs" => {
+                                quota.max_query_secs = match val.get_float() {
+                                    Some(secs) if secs > 0. => Some(secs),
+                                    _ => bail!(BadNamespaceQuotaOption(
+                                        opt_name.to_string(),
+                                        "A positive number is required".to_string(),
+                                        span
+                                    )),
+                                }
+                            }
+                            _ => bail!(BadNamespaceQuotaOption(
+                                opt_name.to_string(),
+                                "Valid options are 'max_storage_bytes', 'max_result_rows' and \
+                                'max_query_secs'"
+                                    .to_string(),
+                                span
+                            )),
+                        }
+                    }
+                    SysOp::SetNamespaceQuota(name, quota)
+                }
+                r => unreachable!("{:?}", r),
+            }
+        }
         Rule::trigger_relation_show_op => {
             let rels_p = inner.into_inner().next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             SysOp::ShowTrigger(rel)
         }
         Rule::trigger_relation_op => {
             let mut src = inner.into_inner();
             let rels_p = src.next().unwrap();
-            let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
+            let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
             let mut puts = vec![];
             let mut rms = vec![];
             let mut replaces = vec![];
@@ -115,7 +777,7 @@ pub(crate) fn parse_sys(
                 let op = clause_inner.next().unwrap();
                 let script = clause_inner.next().unwrap();
                 let script_str = script.as_str();
-                parse_query(script.into_inner(), &Default::default())?;
+                parse_query(script.into_inner(), &Default::default(), custom_algos)?;
                 match op.as_rule() {
                     Rule::trigger_put => puts.push(script_str.to_string()),
                     Rule::trigger_rm => rms.push(script_str.to_string()),
@@ -125,6 +787,31 @@ pub(crate) fn parse_sys(
             }
             SysOp::SetTriggers(rel, puts, rms, replaces)
         }
-        rule => unreachable!("{:?}", rule),
+        r => unreachable!("{:?}", r),
     })
 }
+
+/// Parses a relation name followed by optional `from` and `to` clauses giving key prefixes.
+fn parse_relation_range(
+    mut src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<RelationRange> {
+    let rels_p = src.next().unwrap();
+    let rel = Symbol::new(unquote_ident(rels_p.as_str()), rels_p.extract_span());
+    let mut from = None;
+    let mut to = None;
+    for clause in src {
+        let is_from = clause.as_rule() == Rule::from_clause;
+        let prefix =
+            match build_expr(clause.into_inner().next().unwrap(), param_pool)?.eval_to_const()? {
+                DataValue::List(l) => l,
+                v => vec![v],
+            };
+        if is_from {
+            from = Some(prefix);
+        } else {
+            to = Some(prefix);
+        }
+    }
+    Ok((rel, from, to))
+}
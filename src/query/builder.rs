@@ -0,0 +1,419 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Building queries from Rust with [`QueryBuilder`] and running them with
+//! [`Db::run_built_query`](crate::Db::run_built_query), for query builders and mappers on top
+//! of the crate. The rules are assembled directly into the program the parser would produce
+//! for the equivalent script, so values never pass through script text and need no escaping.
+
+use std::collections::btree_map::Entry;
+use std::collections::BTreeMap;
+
+use miette::{bail, ensure, Diagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::aggr::{parse_aggr, parse_user_aggr};
+use crate::data::expr::{get_op, Expr};
+use crate::data::json::JsonValue;
+use crate::data::program::{
+    InputAtom, InputInlineRule, InputInlineRulesOrAlgo, InputNamedFieldRelationApplyAtom,
+    InputProgram, InputRelationApplyAtom, InputRuleApplyAtom, QueryOutOptions, SortDir,
+    Unification,
+};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::value::DataValue;
+
+/// An expression in a rule body: a variable, a constant or the application of a function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryExpr(ExprKind);
+
+#[derive(Clone, Debug, PartialEq)]
+enum ExprKind {
+    Var(String),
+    Const(JsonValue),
+    Apply(String, Vec<QueryExpr>),
+}
+
+impl QueryExpr {
+    /// The variable `name`.
+    pub fn var(name: impl Into<String>) -> Self {
+        Self(ExprKind::Var(name.into()))
+    }
+    /// The constant `value`.
+    pub fn val(value: impl Into<JsonValue>) -> Self {
+        Self(ExprKind::Const(value.into()))
+    }
+    /// The function `name`, such as `add` or `starts_with`, applied to `args`.
+    pub fn call(name: impl Into<String>, args: impl IntoIterator<Item = QueryExpr>) -> Self {
+        Self(ExprKind::Apply(name.into(), args.into_iter().collect()))
+    }
+    fn to_expr(&self) -> Result<Expr> {
+        Ok(match &self.0 {
+            ExprKind::Var(name) => Expr::Binding {
+                var: var_symbol(name)?,
+                tuple_pos: None,
+            },
+            ExprKind::Const(val) => Expr::Const {
+                val: DataValue::from(val),
+                span: Default::default(),
+            },
+            ExprKind::Apply(name, args) => {
+                #[derive(Error, Diagnostic, Debug)]
+                #[error("Named function '{0}' not found")]
+                #[diagnostic(code(builder::func_not_found))]
+                struct FuncNotFound(String);
+
+                #[derive(Error, Diagnostic, Debug)]
+                #[error("Wrong number of arguments for function '{0}'")]
+                #[diagnostic(code(builder::func_wrong_num_args))]
+                struct WrongNumArgs(String, #[help] String);
+
+                let op = get_op(name).ok_or_else(|| FuncNotFound(name.clone()))?;
+                let mut args = args
+                    .iter()
+                    .map(|arg| arg.to_expr())
+                    .collect::<Result<Vec<_>>>()?;
+                if op.vararg {
+                    ensure!(
+                        op.min_arity <= args.len(),
+                        WrongNumArgs(
+                            name.clone(),
+                            format!("Need at least {} argument(s)", op.min_arity)
+                        )
+                    );
+                } else {
+                    ensure!(
+                        op.min_arity == args.len(),
+                        WrongNumArgs(
+                            name.clone(),
+                            format!("Need exactly {} argument(s)", op.min_arity)
+                        )
+                    );
+                }
+                op.post_process_args(&mut args);
+                Expr::Apply {
+                    op,
+                    args: args.into(),
+                    span: Default::default(),
+                }
+            }
+        })
+    }
+}
+
+/// An atom of a rule body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryAtom(AtomKind);
+
+#[derive(Clone, Debug, PartialEq)]
+enum AtomKind {
+    Rule(String, Vec<QueryExpr>),
+    Stored(String, Vec<QueryExpr>),
+    StoredNamed(String, Vec<(String, QueryExpr)>),
+    Predicate(QueryExpr),
+    Unify(String, QueryExpr, bool),
+    Negation(Box<QueryAtom>),
+    Disjunction(Vec<QueryAtom>),
+}
+
+impl QueryAtom {
+    /// The rule `name` applied to `args`, as in `name[a, b]`.
+    pub fn rule(name: impl Into<String>, args: impl IntoIterator<Item = QueryExpr>) -> Self {
+        Self(AtomKind::Rule(name.into(), args.into_iter().collect()))
+    }
+    /// The stored relation `name` with its columns bound by position, as in `*name[a, b]`.
+    pub fn stored(name: impl Into<String>, args: impl IntoIterator<Item = QueryExpr>) -> Self {
+        Self(AtomKind::Stored(name.into(), args.into_iter().collect()))
+    }
+    /// The stored relation `name` with its columns bound by name, as in `*name{col: a}`.
+    pub fn stored_named<K: Into<String>>(
+        name: impl Into<String>,
+        args: impl IntoIterator<Item = (K, QueryExpr)>,
+    ) -> Self {
+        Self(AtomKind::StoredNamed(
+            name.into(),
+            args.into_iter().map(|(k, v)| (k.into(), v)).collect(),
+        ))
+    }
+    /// A condition the expression must satisfy.
+    pub fn predicate(expr: QueryExpr) -> Self {
+        Self(AtomKind::Predicate(expr))
+    }
+    /// Binds the variable `var` to the value of `expr`, as in `var = expr`.
+    pub fn unify(var: impl Into<String>, expr: QueryExpr) -> Self {
+        Self(AtomKind::Unify(var.into(), expr, false))
+    }
+    /// Binds the variable `var` to each element of the list `expr`, as in `var in expr`.
+    pub fn unify_each(var: impl Into<String>, expr: QueryExpr) -> Self {
+        Self(AtomKind::Unify(var.into(), expr, true))
+    }
+    /// The negation of `atom`, as in `not atom`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(atom: QueryAtom) -> Self {
+        Self(AtomKind::Negation(Box::new(atom)))
+    }
+    /// Any of `atoms`, as in `a or b`.
+    pub fn or(atoms: impl IntoIterator<Item = QueryAtom>) -> Self {
+        Self(AtomKind::Disjunction(atoms.into_iter().collect()))
+    }
+    fn to_atom(&self) -> Result<InputAtom> {
+        let exprs = |args: &[QueryExpr]| -> Result<Vec<Expr>> {
+            args.iter().map(|arg| arg.to_expr()).collect()
+        };
+        Ok(match &self.0 {
+            AtomKind::Rule(name, args) => InputAtom::Rule {
+                inner: InputRuleApplyAtom {
+                    name: rule_symbol(name)?,
+                    args: exprs(args)?,
+                    span: Default::default(),
+                },
+            },
+            AtomKind::Stored(name, args) => InputAtom::Relation {
+                inner: InputRelationApplyAtom {
+                    name: Symbol::new(name.as_str(), Default::default()),
+                    args: exprs(args)?,
+                    valid_at: None,
+                    span: Default::default(),
+                },
+            },
+            AtomKind::StoredNamed(name, args) => InputAtom::NamedFieldRelation {
+                inner: InputNamedFieldRelationApplyAtom {
+                    name: Symbol::new(name.as_str(), Default::default()),
+                    args: args
+                        .iter()
+                        .map(|(col, arg)| Ok((SmartString::from(col.as_str()), arg.to_expr()?)))
+                        .collect::<Result<_>>()?,
+                    valid_at: None,
+                    span: Default::default(),
+                },
+            },
+            AtomKind::Predicate(expr) => InputAtom::Predicate {
+                inner: expr.to_expr()?,
+            },
+            AtomKind::Unify(var, expr, one_many_unif) => InputAtom::Unification {
+                inner: Unification {
+                    binding: var_symbol(var)?,
+                    expr: expr.to_expr()?,
+                    one_many_unif: *one_many_unif,
+                    span: Default::default(),
+                },
+            },
+            AtomKind::Negation(inner) => InputAtom::Negation {
+                inner: Box::new(inner.to_atom()?),
+                span: Default::default(),
+            },
+            AtomKind::Disjunction(inner) => InputAtom::Disjunction {
+                inner: inner
+                    .iter()
+                    .map(|atom| atom.to_atom())
+                    .collect::<Result<_>>()?,
+                span: Default::default(),
+            },
+        })
+    }
+}
+
+/// A rule, made of its head and the atoms of its body, which must all hold.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryRule {
+    name: String,
+    /// The variables of the head, with the aggregations applied to them
+    head: Vec<(String, Option<String>)>,
+    body: Vec<QueryAtom>,
+}
+
+impl QueryRule {
+    /// The rule `name`, to which variables of the head and atoms of the body are then added.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            head: vec![],
+            body: vec![],
+        }
+    }
+    /// The entry rule `?`, whose rows are returned.
+    pub fn entry() -> Self {
+        Self::new(PROG_ENTRY)
+    }
+    /// Adds the variable `var` to the head.
+    pub fn var(mut self, var: impl Into<String>) -> Self {
+        self.head.push((var.into(), None));
+        self
+    }
+    /// Adds the aggregation `aggr`, such as `count` or `max`, of the variable `var` to the head.
+    pub fn aggr(mut self, aggr: impl Into<String>, var: impl Into<String>) -> Self {
+        self.head.push((var.into(), Some(aggr.into())));
+        self
+    }
+    /// Adds `atom` to the body.
+    pub fn atom(mut self, atom: QueryAtom) -> Self {
+        self.body.push(atom);
+        self
+    }
+    fn to_rule(&self) -> Result<InputInlineRule> {
+        #[derive(Error, Diagnostic, Debug)]
+        #[error("Aggregation '{0}' not found")]
+        #[diagnostic(code(builder::aggr_not_found))]
+        struct AggrNotFound(String);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Rule '{0}' has an empty head")]
+        #[diagnostic(code(builder::empty_rule_head))]
+        struct EmptyRuleHead(String);
+
+        ensure!(!self.head.is_empty(), EmptyRuleHead(self.name.clone()));
+        let mut head = vec![];
+        let mut aggr = vec![];
+        for (var, aggr_name) in &self.head {
+            head.push(var_symbol(var)?);
+            aggr.push(match aggr_name {
+                None => None,
+                Some(name) => Some((
+                    parse_aggr(name)
+                        .cloned()
+                        .or_else(|| parse_user_aggr(name))
+                        .ok_or_else(|| AggrNotFound(name.clone()))?,
+                    vec![],
+                )),
+            });
+        }
+        Ok(InputInlineRule {
+            head,
+            aggr,
+            body: self
+                .body
+                .iter()
+                .map(|atom| atom.to_atom())
+                .collect::<Result<_>>()?,
+            span: Default::default(),
+        })
+    }
+}
+
+/// A read-only query made of rules, one of which must be the entry [`QueryRule::entry`],
+/// with the options applied to the rows it returns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct QueryBuilder {
+    rules: Vec<QueryRule>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    /// The variables to sort by, with whether the order is descending
+    order: Vec<(String, bool)>,
+}
+
+impl QueryBuilder {
+    /// An empty query, to which rules are then added.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Adds `rule`. Rules of the same name are alternatives, as when defined several times in
+    /// a script, and must have the same arity and aggregations.
+    pub fn rule(mut self, rule: QueryRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+    /// Returns at most `limit` rows, as `:limit`.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    /// Skips the first `offset` rows, as `:offset`.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+    /// Sorts the rows by the variable `var` of the entry, after those sorted by before.
+    pub fn order_by(mut self, var: impl Into<String>) -> Self {
+        self.order.push((var.into(), false));
+        self
+    }
+    /// Sorts the rows by the variable `var` of the entry in descending order, after those
+    /// sorted by before.
+    pub fn order_by_desc(mut self, var: impl Into<String>) -> Self {
+        self.order.push((var.into(), true));
+        self
+    }
+    /// The program the query is evaluated as.
+    pub(crate) fn to_program(&self) -> Result<InputProgram> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Rule '{0}' has multiple definitions with conflicting heads")]
+        #[diagnostic(code(builder::head_aggr_mismatch))]
+        #[diagnostic(help(
+            "The arity of each rule head must match. In addition, any aggregation \
+            applied must be the same."
+        ))]
+        struct RuleHeadMismatch(String);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Sort key '{0}' not found")]
+        #[diagnostic(code(builder::sort_key_not_found))]
+        struct SortKeyNotFound(String);
+
+        let mut prog: BTreeMap<Symbol, InputInlineRulesOrAlgo> = BTreeMap::new();
+        for rule in &self.rules {
+            let name = rule_symbol(&rule.name)?;
+            let rule = rule.to_rule()?;
+            match prog.entry(name) {
+                Entry::Vacant(e) => {
+                    e.insert(InputInlineRulesOrAlgo::Rules { rules: vec![rule] });
+                }
+                Entry::Occupied(mut e) => {
+                    let name = e.key().name.to_string();
+                    if let InputInlineRulesOrAlgo::Rules { rules } = e.get_mut() {
+                        ensure!(rules[0].aggr == rule.aggr, RuleHeadMismatch(name));
+                        rules.push(rule);
+                    }
+                }
+            }
+        }
+        let mut out_opts = QueryOutOptions {
+            limit: self.limit,
+            offset: self.offset,
+            ..Default::default()
+        };
+        for (var, desc) in &self.order {
+            let dir = if *desc { SortDir::Dsc } else { SortDir::Asc };
+            out_opts.sorters.push((var_symbol(var)?, dir));
+        }
+        let program = InputProgram {
+            prog,
+            out_opts,
+            deprecations: vec![],
+        };
+        let head = program.get_entry_out_head()?;
+        for (sorter, _) in &program.out_opts.sorters {
+            ensure!(head.contains(sorter), SortKeyNotFound(sorter.to_string()));
+        }
+        Ok(program)
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{0}' is not a valid name for a {1}")]
+#[diagnostic(code(builder::bad_name))]
+#[diagnostic(help("Names must start with a letter or '_', followed by letters, digits or '_'"))]
+struct BadName(String, &'static str);
+
+fn is_ident(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => chars.all(|c| c == '_' || c.is_alphanumeric()),
+        _ => false,
+    }
+}
+
+fn var_symbol(name: &str) -> Result<Symbol> {
+    if !is_ident(name) {
+        bail!(BadName(name.to_string(), "variable"))
+    }
+    Ok(Symbol::new(name, Default::default()))
+}
+
+fn rule_symbol(name: &str) -> Result<Symbol> {
+    if name != PROG_ENTRY && !is_ident(name) {
+        bail!(BadName(name.to_string(), "rule"))
+    }
+    Ok(Symbol::new(name, Default::default()))
+}
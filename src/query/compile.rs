@@ -2,16 +2,17 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use itertools::Itertools;
+use log::debug;
 use miette::{bail, ensure, Context, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::program::{
-    MagicAlgoApply, MagicAtom, MagicInlineRule, MagicRulesOrAlgo, MagicSymbol,
+    MagicAlgoApply, MagicAtom, MagicInlineRule, MagicRuleApplyAtom, MagicRulesOrAlgo, MagicSymbol,
     StratifiedMagicProgram,
 };
 use crate::data::symb::Symbol;
@@ -85,6 +86,101 @@ struct RuleNotFound(String, #[label] SourceSpan);
 #[diagnostic(help("Required arity: {1}, number of arguments given: {2}"))]
 struct ArityMismatch(String, usize, usize, #[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation '{0}' does not retain history")]
+#[diagnostic(code(eval::no_history))]
+#[diagnostic(help("Enable it with `::relation history {0} on`"))]
+pub(crate) struct NoHistory(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+/// Finds rulesets that are structurally identical to a ruleset in a stratum evaluated
+/// strictly earlier, mapping each of them to the earlier ruleset whose result can be reused.
+///
+/// Within a single query the stored relations do not change, so two rulesets with the same
+/// normalized bodies over the same inputs necessarily produce the same rows.
+fn memoized_rulesets(prog: &StratifiedMagicProgram) -> BTreeMap<MagicSymbol, MagicSymbol> {
+    let mut memoized = BTreeMap::new();
+    let mut seen: HashMap<String, MagicSymbol> = HashMap::new();
+    for stratum in prog.0.iter().rev() {
+        let mut found = vec![];
+        for (name, ruleset) in &stratum.prog {
+            if let MagicRulesOrAlgo::Rules { rules } = ruleset {
+                if let Some(fingerprint) = ruleset_fingerprint(name, rules, &memoized) {
+                    match seen.get(&fingerprint) {
+                        Some(earlier) => {
+                            memoized.insert(name.clone(), earlier.clone());
+                        }
+                        None => found.push((fingerprint, name.clone())),
+                    }
+                }
+            }
+        }
+        // only rulesets of completed strata can be reused
+        for (fingerprint, name) in found {
+            seen.entry(fingerprint).or_insert(name);
+        }
+    }
+    memoized
+}
+
+/// Renders the rules of a ruleset without spans, with its own name replaced by a placeholder
+/// and references to memoized rulesets replaced by the rulesets they reuse.
+/// Returns `None` if the rules contain non-deterministic expressions.
+fn ruleset_fingerprint(
+    name: &MagicSymbol,
+    rules: &[MagicInlineRule],
+    memoized: &BTreeMap<MagicSymbol, MagicSymbol>,
+) -> Option<String> {
+    let rule_name = |symb: &MagicSymbol| {
+        if symb == name {
+            "self".to_string()
+        } else {
+            format!("{:?}", memoized.get(symb).unwrap_or(symb))
+        }
+    };
+    let args = |args: &[Symbol]| args.iter().map(|arg| &arg.name).join(", ");
+    let mut ret = String::new();
+    for rule in rules {
+        ret += &format!("[{}] {:?} :=", args(&rule.head), rule.aggr);
+        for atom in &rule.body {
+            let rendered = match atom {
+                MagicAtom::Rule(app) => format!("{}[{}]", rule_name(&app.name), args(&app.args)),
+                MagicAtom::NegatedRule(app) => {
+                    format!("not {}[{}]", rule_name(&app.name), args(&app.args))
+                }
+                MagicAtom::Relation(app) => {
+                    format!("*{}[{}] @ {:?}", app.name, args(&app.args), app.valid_at)
+                }
+                MagicAtom::NegatedRelation(app) => {
+                    format!(
+                        "not *{}[{}] @ {:?}",
+                        app.name,
+                        args(&app.args),
+                        app.valid_at
+                    )
+                }
+                MagicAtom::Predicate(expr) => {
+                    if !expr.is_deterministic() {
+                        return None;
+                    }
+                    format!("{}", expr)
+                }
+                MagicAtom::Unification(unif) => {
+                    if !unif.expr.is_deterministic() {
+                        return None;
+                    }
+                    let op = if unif.one_many_unif { "in" } else { "=" };
+                    format!("{} {} {}", unif.binding, op, unif.expr)
+                }
+            };
+            ret += " ";
+            ret += &rendered;
+            ret += ";";
+        }
+        ret += "\n";
+    }
+    Some(ret)
+}
+
 impl SessionTx {
     pub(crate) fn stratified_magic_compile(
         &mut self,
@@ -101,6 +197,8 @@ impl SessionTx {
             }
         }
 
+        let memoized = memoized_rulesets(prog);
+
         let compiled: Vec<_> = prog
             .0
             .iter()
@@ -110,6 +208,34 @@ impl SessionTx {
                     .prog
                     .iter()
                     .map(|(k, body)| -> Result<(MagicSymbol, CompiledRuleSet)> {
+                        if let Some(earlier) = memoized.get(k) {
+                            debug!("rule {} reuses the result of {}", k, earlier);
+                            let span = k.symbol().span;
+                            let arity = stores.get(earlier).unwrap().arity;
+                            let head = (0..arity)
+                                .map(|i| Symbol::new(&format!("**{}", i) as &str, span))
+                                .collect_vec();
+                            let copy = MagicInlineRule {
+                                head: head.clone(),
+                                aggr: vec![None; arity],
+                                body: vec![MagicAtom::Rule(MagicRuleApplyAtom {
+                                    name: earlier.clone(),
+                                    args: head,
+                                    span,
+                                })],
+                            };
+                            let mut relation =
+                                self.compile_magic_rule_body(&copy, k, &stores, &copy.head)?;
+                            relation.fill_binding_indices()?;
+                            return Ok((
+                                k.clone(),
+                                CompiledRuleSet::Rules(vec![CompiledRule {
+                                    aggr: copy.aggr.clone(),
+                                    relation,
+                                    contained_rules: copy.contained_rules(),
+                                }]),
+                            ));
+                        }
                         match body {
                             MagicRulesOrAlgo::Rules { rules: body } => {
                                 let mut collected = Vec::with_capacity(body.len());
@@ -233,7 +359,14 @@ impl SessionTx {
                         }
                     }
 
-                    let right = RelAlgebra::relation(right_vars, store, rel_app.span);
+                    if rel_app.valid_at.is_some() {
+                        ensure!(
+                            store.history.is_some(),
+                            NoHistory(store.name.to_string(), rel_app.span)
+                        );
+                    }
+                    let right =
+                        RelAlgebra::relation(right_vars, store, rel_app.valid_at, rel_app.span);
                     debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                     ret = ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
                 }
@@ -303,7 +436,18 @@ impl SessionTx {
                         }
                     }
 
-                    let right = RelAlgebra::relation(right_vars, store, relation_app.span);
+                    if relation_app.valid_at.is_some() {
+                        ensure!(
+                            store.history.is_some(),
+                            NoHistory(store.name.to_string(), relation_app.span)
+                        );
+                    }
+                    let right = RelAlgebra::relation(
+                        right_vars,
+                        store,
+                        relation_app.valid_at,
+                        relation_app.span,
+                    );
                     debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                     ret = ret.neg_join(
                         right,
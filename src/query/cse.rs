@@ -0,0 +1,144 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeMap;
+
+use crate::data::expr::Expr;
+use crate::data::program::{NormalFormAtom, NormalFormInlineRule, Unification};
+use crate::data::symb::Symbol;
+
+impl NormalFormInlineRule {
+    /// Binds sub-expressions occurring more than once in the predicates and unifications
+    /// of the body to fresh variables, so that each is evaluated only once per binding.
+    ///
+    /// Only expressions whose variables are all bound by positive atoms are considered.
+    /// The new unification is placed right before the first atom using the expression, or
+    /// after the atom binding the last of its variables if that comes later, so that it is
+    /// never evaluated ahead of the predicates guarding it, such as `y != 0` before
+    /// `mod(x, y)`. Branches of `cond` and `try` are evaluated lazily and are left alone.
+    pub(crate) fn eliminate_common_subexpressions(mut self) -> Self {
+        let mut bound_at: BTreeMap<Symbol, usize> = BTreeMap::new();
+        for (i, atom) in self.body.iter().enumerate() {
+            let args = match atom {
+                NormalFormAtom::Rule(r) => &r.args,
+                NormalFormAtom::Relation(r) => &r.args,
+                _ => continue,
+            };
+            for arg in args {
+                bound_at.entry(arg.clone()).or_insert(i);
+            }
+        }
+        let mut counter = 0;
+        loop {
+            let mut candidates: BTreeMap<String, (usize, Expr)> = BTreeMap::new();
+            for atom in &self.body {
+                match atom {
+                    NormalFormAtom::Predicate(p) => collect_candidates(p, &mut candidates),
+                    NormalFormAtom::Unification(u) => collect_candidates(&u.expr, &mut candidates),
+                    _ => {}
+                }
+            }
+            let best = candidates
+                .into_iter()
+                .filter(|(_, (n, expr))| {
+                    *n > 1 && expr.bindings().iter().all(|b| bound_at.contains_key(b))
+                })
+                .max_by_key(|(_, (_, expr))| expr_size(expr));
+            let (key, (_, expr)) = match best {
+                None => break,
+                Some(found) => found,
+            };
+
+            let span = expr.span();
+            let binding = Symbol::new(&format!("**cse{}", counter) as &str, span);
+            counter += 1;
+            let mut first_use = None;
+            for (i, atom) in self.body.iter_mut().enumerate() {
+                let replaced = match atom {
+                    NormalFormAtom::Predicate(p) => replace_occurrences(p, &key, &binding),
+                    NormalFormAtom::Unification(u) => {
+                        replace_occurrences(&mut u.expr, &key, &binding)
+                    }
+                    _ => false,
+                };
+                if replaced && first_use.is_none() {
+                    first_use = Some(i);
+                }
+            }
+            let pos = expr
+                .bindings()
+                .iter()
+                .map(|b| bound_at[b] + 1)
+                .chain(first_use)
+                .max()
+                .unwrap_or(0);
+            for idx in bound_at.values_mut() {
+                if *idx >= pos {
+                    *idx += 1;
+                }
+            }
+            bound_at.insert(binding.clone(), pos);
+            self.body.insert(
+                pos,
+                NormalFormAtom::Unification(Unification {
+                    binding,
+                    expr,
+                    one_many_unif: false,
+                    span,
+                }),
+            );
+        }
+        self
+    }
+}
+
+fn collect_candidates(expr: &Expr, coll: &mut BTreeMap<String, (usize, Expr)>) {
+    if let Expr::Apply { op, args, .. } = expr {
+        if op.is_deterministic() && !expr.bindings().is_empty() {
+            coll.entry(expr.to_string())
+                .or_insert_with(|| (0, expr.clone()))
+                .0 += 1;
+        }
+        for arg in args.iter() {
+            collect_candidates(arg, coll);
+        }
+    }
+}
+
+/// Replaces the occurrences of the expression `key` in `expr` by `binding`, returning
+/// whether there were any.
+fn replace_occurrences(expr: &mut Expr, key: &str, binding: &Symbol) -> bool {
+    if !matches!(expr, Expr::Apply { .. }) {
+        return false;
+    }
+    if expr.to_string() == key {
+        *expr = Expr::Binding {
+            var: binding.clone(),
+            tuple_pos: None,
+        };
+        true
+    } else if let Expr::Apply { args, .. } = expr {
+        let mut replaced = false;
+        for arg in args.iter_mut() {
+            replaced |= replace_occurrences(arg, key, binding);
+        }
+        replaced
+    } else {
+        false
+    }
+}
+
+fn expr_size(expr: &Expr) -> usize {
+    match expr {
+        Expr::Binding { .. } | Expr::Const { .. } => 1,
+        Expr::Apply { args, .. } => 1 + args.iter().map(expr_size).sum::<usize>(),
+        Expr::Cond { clauses, .. } => {
+            1 + clauses
+                .iter()
+                .map(|(cond, val)| expr_size(cond) + expr_size(val))
+                .sum::<usize>()
+        }
+        Expr::Try { clauses, .. } => 1 + clauses.iter().map(expr_size).sum::<usize>(),
+    }
+}
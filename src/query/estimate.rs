@@ -0,0 +1,351 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Estimates of the rows a compiled query produces and the work needed to produce them,
+//! made without evaluating it, for `::estimate`.
+//!
+//! Stored relations are assumed to hold as many rows as their statistics, collected with
+//! `::relation stats`, say. The fraction of rows passing comparisons of columns with constants
+//! and joins on columns is estimated from the statistics of the columns. Without them, joins
+//! on keys of stored relations are assumed to match rows uniformly, and each filter or join
+//! on other columns to keep a fixed fraction of rows.
+//! Recursive rules are estimated from a single step of recursion.
+//!
+//! The same estimates tell joins looking up stored relations by key prefix, and negations of
+//! rules, how many rows they are expected to probe with, so that they can pick a strategy.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::expr::Expr;
+use crate::data::functions::{OP_EQ, OP_GE, OP_GT, OP_LE, OP_LT, OP_NEQ};
+use crate::data::program::{MagicAlgoRuleArg, MagicSymbol};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::relation::{InnerJoin, RelAlgebra};
+use crate::runtime::stats::ColumnStatistics;
+use crate::runtime::transact::SessionTx;
+
+/// Rows assumed for stored relations whose statistics were never collected
+const UNKNOWN_RELATION_ROWS: f64 = 1000.;
+/// Fraction of rows assumed to pass each filter, negation or join on non-key columns
+const SELECTIVITY: f64 = 1. / 3.;
+/// Values assumed to be bound by each multi-unification to a non-constant list
+const MULTI_UNIFY_FANOUT: f64 = 10.;
+
+/// The estimate for a rule of a compiled query.
+pub(crate) struct RuleEstimate {
+    pub(crate) stratum: usize,
+    pub(crate) rule: MagicSymbol,
+    /// Rows the rule is estimated to produce
+    pub(crate) rows: f64,
+    /// Rows the evaluation of the rule is estimated to go through, including those read
+    /// from relations and those of intermediate results
+    pub(crate) cost: f64,
+}
+
+impl SessionTx {
+    /// Estimates every rule of the compiled strata, in the order they are evaluated.
+    /// `num_to_take` is the number of rows of the entry rule that are asked for, if limited.
+    pub(crate) fn estimate_compiled(
+        &self,
+        strata: &[CompiledProgram],
+        num_to_take: Option<usize>,
+    ) -> Result<Vec<RuleEstimate>> {
+        let entry = MagicSymbol::Muggle {
+            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        };
+        let mut rule_rows: BTreeMap<MagicSymbol, f64> = BTreeMap::new();
+        let mut ret = vec![];
+        for (stratum, prog) in strata.iter().enumerate() {
+            // the first pass estimates the rules with their recursive references still
+            // empty, the second pass one step of recursion
+            let mut estimates = BTreeMap::new();
+            for _ in 0..2 {
+                for (name, ruleset) in prog {
+                    let (mut rows, cost) = match ruleset {
+                        CompiledRuleSet::Rules(rules) => {
+                            let mut rows = 0.;
+                            let mut cost = 0.;
+                            for rule in rules {
+                                let (r_rows, r_cost) = self.estimate_ra(
+                                    &rule.relation,
+                                    &rule_rows,
+                                    &mut Default::default(),
+                                )?;
+                                // aggregating every column leaves a single row
+                                let all_aggr =
+                                    !rule.aggr.is_empty() && rule.aggr.iter().all(|a| a.is_some());
+                                rows += if all_aggr { r_rows.min(1.) } else { r_rows };
+                                cost += r_cost;
+                            }
+                            (rows, cost)
+                        }
+                        CompiledRuleSet::Algo(algo) => {
+                            let mut rows = 0.;
+                            for arg in &algo.rule_args {
+                                rows += match arg {
+                                    MagicAlgoRuleArg::InMem { name, .. } => {
+                                        rule_rows.get(name).copied().unwrap_or(0.)
+                                    }
+                                    MagicAlgoRuleArg::Stored { name, .. } => {
+                                        self.estimated_relation_rows(&name.name)?
+                                    }
+                                };
+                            }
+                            (rows, rows)
+                        }
+                    };
+                    if *name == entry {
+                        if let Some(n) = num_to_take {
+                            rows = rows.min(n as f64);
+                        }
+                    }
+                    rule_rows.insert(name.clone(), rows);
+                    estimates.insert(name.clone(), (rows, cost));
+                }
+            }
+            for (rule, (rows, cost)) in estimates {
+                ret.push(RuleEstimate {
+                    stratum,
+                    rule,
+                    rows,
+                    cost,
+                });
+            }
+        }
+        Ok(ret)
+    }
+    /// Sets the number of rows each join of the compiled strata looking up a stored relation,
+    /// and each negation of a rule, is expected to probe with, from the estimates of the rows
+    /// of its left side.
+    pub(crate) fn plan_adaptive_joins(&self, strata: &mut [CompiledProgram]) -> Result<()> {
+        let rule_rows: BTreeMap<MagicSymbol, f64> = self
+            .estimate_compiled(strata, None)?
+            .into_iter()
+            .map(|est| (est.rule, est.rows))
+            .collect();
+        for prog in strata.iter_mut() {
+            for ruleset in prog.values_mut() {
+                if let CompiledRuleSet::Rules(rules) = ruleset {
+                    for rule in rules {
+                        self.plan_joins_in(&mut rule.relation, &rule_rows)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    fn plan_joins_in(
+        &self,
+        rel: &mut RelAlgebra,
+        rule_rows: &BTreeMap<MagicSymbol, f64>,
+    ) -> Result<()> {
+        match rel {
+            RelAlgebra::Fixed(_) | RelAlgebra::InMem(_) | RelAlgebra::Stored(_) => {}
+            RelAlgebra::Join(inner) => {
+                if matches!(inner.right, RelAlgebra::Stored(_)) {
+                    let (rows, _) =
+                        self.estimate_ra(&inner.left, rule_rows, &mut Default::default())?;
+                    inner.expected_probes = Some(rows.ceil() as usize);
+                }
+                self.plan_joins_in(&mut inner.left, rule_rows)?;
+                self.plan_joins_in(&mut inner.right, rule_rows)?;
+            }
+            RelAlgebra::NegJoin(inner) => {
+                if matches!(inner.right, RelAlgebra::InMem(_)) {
+                    let (rows, _) =
+                        self.estimate_ra(&inner.left, rule_rows, &mut Default::default())?;
+                    inner.expected_probes = Some(rows.ceil() as usize);
+                }
+                self.plan_joins_in(&mut inner.left, rule_rows)?;
+                self.plan_joins_in(&mut inner.right, rule_rows)?;
+            }
+            RelAlgebra::Reorder(r) => self.plan_joins_in(&mut r.relation, rule_rows)?,
+            RelAlgebra::Filter(r) => self.plan_joins_in(&mut r.parent, rule_rows)?,
+            RelAlgebra::Unification(r) => self.plan_joins_in(&mut r.parent, rule_rows)?,
+        }
+        Ok(())
+    }
+    fn estimated_relation_rows(&self, name: &str) -> Result<f64> {
+        Ok(match self.get_statistics(name)? {
+            Some(stats) => stats.rows as f64,
+            None => UNKNOWN_RELATION_ROWS,
+        })
+    }
+    /// The rows produced by `rel` and the rows gone through to produce them. The statistics
+    /// of the columns of stored relations read are collected into `columns` by binding.
+    fn estimate_ra(
+        &self,
+        rel: &RelAlgebra,
+        rule_rows: &BTreeMap<MagicSymbol, f64>,
+        columns: &mut BTreeMap<Symbol, ColumnStatistics>,
+    ) -> Result<(f64, f64)> {
+        Ok(match rel {
+            RelAlgebra::Fixed(f) => {
+                let rows = f.data.len() as f64;
+                (rows, rows)
+            }
+            RelAlgebra::InMem(r) => {
+                let scanned = rule_rows.get(&r.storage.rule_name).copied().unwrap_or(0.);
+                let rows = scanned * filters_selectivity(&r.filters, columns);
+                (rows, scanned + rows)
+            }
+            RelAlgebra::Stored(r) => {
+                let scanned = match self.get_statistics(&r.storage.name)? {
+                    Some(stats) => {
+                        columns.extend(r.bindings.iter().cloned().zip(stats.columns));
+                        stats.rows as f64
+                    }
+                    None => UNKNOWN_RELATION_ROWS,
+                };
+                let rows = scanned * filters_selectivity(&r.filters, columns);
+                (rows, scanned + rows)
+            }
+            RelAlgebra::Join(inner) => self.estimate_join(inner, rule_rows, columns)?,
+            RelAlgebra::NegJoin(inner) => {
+                let (l_rows, l_cost) = self.estimate_ra(&inner.left, rule_rows, columns)?;
+                let (_, r_cost) = self.estimate_ra(&inner.right, rule_rows, columns)?;
+                let rows = l_rows * SELECTIVITY;
+                (rows, l_cost + r_cost + rows)
+            }
+            RelAlgebra::Reorder(r) => self.estimate_ra(&r.relation, rule_rows, columns)?,
+            RelAlgebra::Filter(r) => {
+                let (p_rows, p_cost) = self.estimate_ra(&r.parent, rule_rows, columns)?;
+                let rows = p_rows * filters_selectivity(&r.pred, columns);
+                (rows, p_cost + rows)
+            }
+            RelAlgebra::Unification(r) => {
+                let (p_rows, p_cost) = self.estimate_ra(&r.parent, rule_rows, columns)?;
+                let rows = if r.is_multi {
+                    p_rows
+                        * match &r.expr {
+                            Expr::Const {
+                                val: DataValue::List(l),
+                                ..
+                            } => l.len() as f64,
+                            _ => MULTI_UNIFY_FANOUT,
+                        }
+                } else {
+                    p_rows
+                };
+                (rows, p_cost + rows)
+            }
+        })
+    }
+    fn estimate_join(
+        &self,
+        join: &InnerJoin,
+        rule_rows: &BTreeMap<MagicSymbol, f64>,
+        columns: &mut BTreeMap<Symbol, ColumnStatistics>,
+    ) -> Result<(f64, f64)> {
+        let (l_rows, l_cost) = self.estimate_ra(&join.left, rule_rows, columns)?;
+        let (r_rows, r_cost) = self.estimate_ra(&join.right, rule_rows, columns)?;
+        let (_, right_indices) = join.joiner.join_indices(
+            &join.left.bindings_after_eliminate(),
+            &join.right.bindings_after_eliminate(),
+        )?;
+        // the fraction of pairs of rows agreeing on each joined column, if known
+        let factors = join
+            .joiner
+            .left_keys
+            .iter()
+            .zip(join.joiner.right_keys.iter())
+            .map(|(l, r)| join_selectivity(columns.get(l), columns.get(r)))
+            .collect_vec();
+        if let RelAlgebra::Stored(stored) = &join.right {
+            // rows are looked up by the bound prefix of the keys, each value of which is
+            // assumed to match as many rows as any other unless known otherwise
+            let n_keys = stored.storage.metadata.keys.len();
+            let prefix = (0..n_keys)
+                .take_while(|i| right_indices.contains(i))
+                .count();
+            if prefix > 0 {
+                let mut per_lookup = r_rows;
+                let mut others = 1.;
+                for (idx, factor) in right_indices.iter().zip(factors) {
+                    match factor {
+                        Some(f) if *idx < prefix => per_lookup *= f,
+                        None if *idx < prefix => {
+                            per_lookup *= r_rows.max(1.).powf(-1. / n_keys as f64)
+                        }
+                        f => others *= f.unwrap_or(SELECTIVITY),
+                    }
+                }
+                let rows = l_rows * per_lookup * others;
+                return Ok((rows, l_cost + l_rows * per_lookup + rows));
+            }
+        }
+        let selectivity: f64 = factors
+            .into_iter()
+            .map(|f| f.unwrap_or(SELECTIVITY))
+            .product();
+        let rows = l_rows * r_rows * selectivity;
+        Ok((rows, l_cost + r_cost + rows))
+    }
+}
+
+/// The fraction of pairs of rows whose values of the two columns are equal, assuming the
+/// values of the column with fewer distinct values all appear in the other.
+fn join_selectivity(
+    left: Option<&ColumnStatistics>,
+    right: Option<&ColumnStatistics>,
+) -> Option<f64> {
+    let distinct = match (left, right) {
+        (None, None) => return None,
+        (Some(s), None) | (None, Some(s)) => s.distinct,
+        (Some(l), Some(r)) => l.distinct.max(r.distinct),
+    };
+    Some(1. / distinct.max(1) as f64)
+}
+
+/// The fraction of rows passing all of the predicates, estimated from the statistics of the
+/// columns compared to constants, and assumed to be independent.
+fn filters_selectivity(preds: &[Expr], columns: &BTreeMap<Symbol, ColumnStatistics>) -> f64 {
+    preds
+        .iter()
+        .map(|pred| predicate_selectivity(pred, columns).unwrap_or(SELECTIVITY))
+        .product()
+}
+
+fn predicate_selectivity(pred: &Expr, columns: &BTreeMap<Symbol, ColumnStatistics>) -> Option<f64> {
+    let (op, args) = match pred {
+        Expr::Apply { op, args, .. } if args.len() == 2 => (op.name, args),
+        _ => return None,
+    };
+    // comparisons are turned around to have the column on the left
+    let (stats, val, op) = match (&args[0], &args[1]) {
+        (Expr::Binding { var, .. }, Expr::Const { val, .. }) => (columns.get(var)?, val, op),
+        (Expr::Const { val, .. }, Expr::Binding { var, .. }) => {
+            let flipped = match op {
+                n if n == OP_LT.name => OP_GT.name,
+                n if n == OP_LE.name => OP_GE.name,
+                n if n == OP_GT.name => OP_LT.name,
+                n if n == OP_GE.name => OP_LE.name,
+                n => n,
+            };
+            (columns.get(var)?, val, flipped)
+        }
+        _ => return None,
+    };
+    let non_null = 1. - stats.null_fraction;
+    let eq = stats.eq_selectivity(val);
+    let lt = stats.lt_selectivity(val);
+    Some(
+        match op {
+            n if n == OP_EQ.name => eq,
+            n if n == OP_NEQ.name => non_null - eq,
+            n if n == OP_LT.name => lt,
+            n if n == OP_LE.name => lt + eq,
+            n if n == OP_GT.name => non_null - lt - eq,
+            n if n == OP_GE.name => non_null - lt,
+            _ => return None,
+        }
+        .clamp(0., 1.),
+    )
+}
@@ -4,17 +4,122 @@
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::mem;
+use std::time::{Duration, Instant};
 
 use log::{debug, trace};
 use miette::Result;
+use serde_json::json;
+use smartstring::{LazyCompact, SmartString};
 
+use crate::data::json::JsonValue;
 use crate::data::program::{MagicAlgoApply, MagicSymbol, NoEntryError};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::parse::SourceSpan;
 use crate::query::compile::{AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet};
 use crate::runtime::db::Poison;
 use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::metrics::GaugeGuard;
 use crate::runtime::transact::SessionTx;
+use crate::utils::{enter_span, trace_event};
+
+/// The transaction lent to the thread pool running an algorithm.
+///
+/// Everything else the job captures is checked by the compiler to be `Send`; only this
+/// reference is not, as the engine transaction inside [`SessionTx`] is not `Sync`.
+struct PoolTxRef<'a>(&'a SessionTx);
+
+// SAFETY: a `PoolTxRef` is only created in `algo_application_eval` and moved into a job
+// passed to `ThreadPool::install`, which blocks the calling thread until the job returns
+// and so outlives no borrow. `SessionTx` is not `Sync`, so no other thread holds a
+// reference to it, and the blocked caller does not use it until the job is done: the
+// transaction is accessed by one thread at a time. Inside the job the reference is again a
+// plain `&SessionTx`, so it cannot be handed on to the workers of a parallel iterator. The
+// engine transactions have no affinity to the thread that created them (see `StoreTx`).
+unsafe impl Send for PoolTxRef<'_> {}
+
+impl<'a> PoolTxRef<'a> {
+    // Taking `self` makes closures capture the whole wrapper rather than the field.
+    fn get(self) -> &'a SessionTx {
+        self.0
+    }
+}
+
+/// Timings and counts of the evaluation of each stratum and each rule,
+/// collected when a query is run with `:profile`.
+#[derive(Debug, Default)]
+pub(crate) struct QueryProfile {
+    strata: Vec<(Duration, u32)>,
+    rules: Vec<RuleProfile>,
+    pub(crate) join_switches: Vec<JoinSwitch>,
+}
+
+/// A join looking up a stored relation by key prefix that probed far more rows than the
+/// planner expected, at which point it switched to looking the rows up in a hash table,
+/// unless the relation was too large to hold in memory.
+#[derive(Debug)]
+pub(crate) struct JoinSwitch {
+    pub(crate) relation: SmartString<LazyCompact>,
+    pub(crate) expected_probes: usize,
+    pub(crate) probes: usize,
+    pub(crate) switched: bool,
+}
+
+#[derive(Debug)]
+struct RuleProfile {
+    stratum: usize,
+    rule: String,
+    time: Duration,
+    /// Number of iterations in which the rule derived new tuples
+    iterations: u32,
+    tuples: usize,
+}
+
+impl QueryProfile {
+    pub(crate) fn to_json(&self) -> JsonValue {
+        let strata = self
+            .strata
+            .iter()
+            .enumerate()
+            .map(|(idx, (time, iterations))| json!([idx, time.as_secs_f64(), iterations]))
+            .collect::<Vec<_>>();
+        let rules = self
+            .rules
+            .iter()
+            .map(|rule| {
+                json!([
+                    rule.stratum,
+                    rule.rule,
+                    rule.time.as_secs_f64(),
+                    rule.iterations,
+                    rule.tuples
+                ])
+            })
+            .collect::<Vec<_>>();
+        let join_switches = self
+            .join_switches
+            .iter()
+            .map(|switch| {
+                json!([
+                    switch.relation,
+                    switch.expected_probes,
+                    switch.probes,
+                    if switch.switched { "hash" } else { "prefix" }
+                ])
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "strata": {"headers": ["stratum", "time", "iterations"], "rows": strata},
+            "rules": {
+                "headers": ["stratum", "rule", "time", "iterations", "tuples"],
+                "rows": rules
+            },
+            "join_switches": {
+                "headers": ["relation", "expected_probes", "probes", "strategy"],
+                "rows": join_switches
+            },
+        })
+    }
+}
 
 pub(crate) struct QueryLimiter {
     total: Option<usize>,
@@ -47,6 +152,8 @@ impl SessionTx {
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
+        mut profile: Option<&mut QueryProfile>,
+        in_mem: &mut GaugeGuard,
     ) -> Result<(InMemRelation, bool)> {
         let ret_area = stores
             .get(&MagicSymbol::Muggle {
@@ -57,24 +164,49 @@ impl SessionTx {
         let mut early_return = false;
         for (idx, cur_prog) in strata.iter().enumerate() {
             debug!("stratum {}", idx);
-            early_return = self.semi_naive_magic_evaluate(
+            let _span = enter_span!("stratum", idx);
+            let started = Instant::now();
+            let mut rule_profiles = profile.as_ref().map(|_| BTreeMap::new());
+            let (stratum_early_return, epochs) = self.semi_naive_magic_evaluate(
                 cur_prog,
                 stores,
                 total_num_to_take,
                 num_to_skip,
                 poison.clone(),
+                rule_profiles.as_mut(),
+                in_mem,
             )?;
+            early_return = stratum_early_return;
+            trace_event!(early_return, "stratum evaluated");
+            if let (Some(profile), Some(rule_profiles)) = (profile.as_mut(), rule_profiles) {
+                profile.strata.push((started.elapsed(), epochs));
+                for (k, (time, iterations)) in rule_profiles {
+                    profile.rules.push(RuleProfile {
+                        stratum: idx,
+                        rule: k.to_string(),
+                        time,
+                        iterations,
+                        tuples: stores.get(k).unwrap().num_tuples_at_epoch(0),
+                    });
+                }
+            }
         }
         Ok((ret_area, early_return))
     }
-    fn semi_naive_magic_evaluate(
+    /// Evaluates one stratum, returning whether the result limit was hit and the number of
+    /// iterations taken. With `profile`, the time spent on each rule and the number of
+    /// iterations in which it derived new tuples are accumulated into it. The tuples held
+    /// in `stores` are counted into `in_mem` after each iteration.
+    fn semi_naive_magic_evaluate<'a>(
         &self,
-        prog: &CompiledProgram,
+        prog: &'a CompiledProgram,
         stores: &BTreeMap<MagicSymbol, InMemRelation>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
         poison: Poison,
-    ) -> Result<bool> {
+        mut profile: Option<&mut BTreeMap<&'a MagicSymbol, (Duration, u32)>>,
+        in_mem: &mut GaugeGuard,
+    ) -> Result<(bool, u32)> {
         let mut changed: BTreeMap<_, _> = prog.keys().map(|k| (k, false)).collect();
         let mut prev_changed = changed.clone();
         let mut limiter = QueryLimiter {
@@ -84,11 +216,14 @@ impl SessionTx {
         };
 
         let mut used_limiter = false;
+        let mut epochs = 0;
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
+            epochs = epoch + 1;
             if epoch == 0 {
                 for (k, compiled_ruleset) in prog.iter() {
+                    let started = profile.is_some().then(Instant::now);
                     match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => {
                             let aggr_kind = compiled_ruleset.aggr_kind();
@@ -104,8 +239,15 @@ impl SessionTx {
                         }
                         CompiledRuleSet::Algo(algo_apply) => {
                             self.algo_application_eval(k, algo_apply, stores, poison.clone())?;
+                            self.check_result_rows(k, stores.get(k).unwrap())?;
+                            if let Some(profile) = profile.as_mut() {
+                                profile.entry(k).or_default().1 += 1;
+                            }
                         }
                     }
+                    if let (Some(profile), Some(started)) = (profile.as_mut(), started) {
+                        profile.entry(k).or_default().0 += started.elapsed();
+                    }
                 }
             } else {
                 mem::swap(&mut changed, &mut prev_changed);
@@ -114,6 +256,7 @@ impl SessionTx {
                 }
 
                 for (k, compiled_ruleset) in prog.iter() {
+                    let started = profile.is_some().then(Instant::now);
                     match compiled_ruleset {
                         CompiledRuleSet::Rules(ruleset) => {
                             let is_meet_aggr = match compiled_ruleset.aggr_kind() {
@@ -136,13 +279,24 @@ impl SessionTx {
 
                         CompiledRuleSet::Algo(_) => unreachable!(),
                     }
+                    if let (Some(profile), Some(started)) = (profile.as_mut(), started) {
+                        profile.entry(k).or_default().0 += started.elapsed();
+                    }
+                }
+            }
+            if let Some(profile) = profile.as_mut() {
+                for (k, rule_changed) in changed.iter() {
+                    if *rule_changed {
+                        profile.entry(*k).or_default().1 += 1;
+                    }
                 }
             }
+            in_mem.set(stores.values().map(|s| s.num_tuples()).sum::<usize>() as i64);
             if changed.values().all(|rule_changed| !*rule_changed) {
                 break;
             }
         }
-        Ok(used_limiter)
+        Ok((used_limiter, epochs))
     }
     fn algo_application_eval(
         &self,
@@ -152,8 +306,17 @@ impl SessionTx {
         poison: Poison,
     ) -> Result<()> {
         let mut algo_impl = algo_apply.algo.get_impl()?;
+        if let Some(budget) = self.query.algo_memory_budget {
+            algo_apply.check_memory_budget(algo_impl.as_ref(), budget, self, stores)?;
+        }
         let out = stores.get(rule_symb).unwrap();
-        algo_impl.run(self, algo_apply, stores, out, poison)
+        match &self.eval.algo_pool {
+            None => algo_impl.run(self, algo_apply, stores, out, poison),
+            Some(pool) => {
+                let tx = PoolTxRef(self);
+                pool.install(move || algo_impl.run(tx.get(), algo_apply, stores, out, poison))
+            }
+        }
     }
     fn initial_rule_eval(
         &self,
@@ -180,6 +343,7 @@ impl SessionTx {
                     }
                     for item_res in rule.relation.iter(self, Some(0), &use_delta)? {
                         let item = item_res?;
+                        self.count_derived_row()?;
                         trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
                         if is_meet {
                             store.aggr_meet_put(&item, &mut aggr, 0)?;
@@ -194,6 +358,7 @@ impl SessionTx {
                         } else {
                             store.put(item, 0);
                         }
+                        self.check_result_rows(rule_symb, store)?;
                         *changed.get_mut(rule_symb).unwrap() = true;
                         poison.check()?;
                     }
@@ -210,6 +375,7 @@ impl SessionTx {
                         rule.relation.iter(self, Some(0), &use_delta)?.enumerate()
                     {
                         let item = item_res?;
+                        self.count_derived_row()?;
                         trace!("item for {:?}.{}: {:?} at {}", rule_symb, rule_n, item, 0);
                         store_to_use.normal_aggr_put(&item, &rule.aggr, serial);
                         *changed.get_mut(rule_symb).unwrap() = true;
@@ -228,6 +394,7 @@ impl SessionTx {
                 )? {
                     return Ok(true);
                 }
+                self.check_result_rows(rule_symb, store)?;
             }
         }
         Ok(should_check_limit)
@@ -277,11 +444,13 @@ impl SessionTx {
                 let use_delta = BTreeSet::from([delta_store.id]);
                 for item_res in rule.relation.iter(self, Some(epoch), &use_delta)? {
                     let item = item_res?;
+                    self.count_derived_row()?;
                     // improvement: the clauses can actually be evaluated in parallel
                     if is_meet_aggr {
                         let aggr_changed = store.aggr_meet_put(&item, &mut aggr, epoch)?;
                         if aggr_changed {
                             *changed.get_mut(rule_symb).unwrap() = true;
+                            self.check_result_rows(rule_symb, store)?;
                         }
                     } else if store.exists(&item, 0) {
                         trace!(
@@ -302,6 +471,7 @@ impl SessionTx {
                         *changed.get_mut(rule_symb).unwrap() = true;
                         store.put(item.clone(), epoch);
                         store.put_with_skip(item, limiter.should_skip_next());
+                        self.check_result_rows(rule_symb, store)?;
                         if should_check_limit && limiter.incr_and_should_stop() {
                             trace!("early stopping due to result count limit exceeded");
                             return Ok(true);
@@ -5,8 +5,8 @@
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 
-use miette::Result;
 use itertools::Itertools;
+use miette::Result;
 
 use crate::algo::strongly_connected_components::TarjanScc;
 use crate::runtime::db::Poison;
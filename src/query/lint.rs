@@ -0,0 +1,191 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeSet;
+
+use miette::{Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::program::{NormalFormAlgoOrRules, NormalFormAtom, NormalFormProgram};
+use crate::data::symb::Symbol;
+use crate::parse::SourceSpan;
+use crate::runtime::transact::SessionTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("In rule '{rule}', '{left}' and '{right}' share no variables and are joined as a cartesian product")]
+#[diagnostic(code(lint::cross_product))]
+#[diagnostic(help(
+    "This is usually caused by a misspelled variable. \
+    If the product is intended, remove the ':strict' option to run the query anyway"
+))]
+pub(crate) struct CrossProductLint {
+    rule: String,
+    left: String,
+    right: String,
+    #[label]
+    left_span: SourceSpan,
+    #[label]
+    right_span: SourceSpan,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("In rule '{rule}', '{atom}' scans all of its {rows} rows as none of its keys are bound")]
+#[diagnostic(code(lint::full_scan))]
+#[diagnostic(help(
+    "Bind the first key of the relation to a constant or to a variable of an earlier atom, \
+    or query an index of the relation starting with a bound column. \
+    If the scan is intended, remove the ':strict' option to run the query anyway"
+))]
+pub(crate) struct FullScanLint {
+    rule: String,
+    atom: String,
+    rows: usize,
+    #[label]
+    span: SourceSpan,
+}
+
+impl NormalFormProgram {
+    /// Finds rule bodies whose positive atoms fall into groups sharing no variables, which
+    /// are evaluated as cartesian products. Atoms with an argument bound to a constant are
+    /// lookups and are not considered, nor are atoms without arguments.
+    pub(crate) fn cross_product_lints(&self) -> Vec<CrossProductLint> {
+        let mut ret = vec![];
+        for (name, ruleset) in &self.prog {
+            let rules = match ruleset {
+                NormalFormAlgoOrRules::Rules { rules } => rules,
+                NormalFormAlgoOrRules::Algo { .. } => continue,
+            };
+            for rule in rules {
+                let const_bound: BTreeSet<&Symbol> = rule
+                    .body
+                    .iter()
+                    .filter_map(|atom| match atom {
+                        NormalFormAtom::Unification(u) if u.expr.bindings().is_empty() => {
+                            Some(&u.binding)
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                let atoms: Vec<(String, &[Symbol], SourceSpan)> = rule
+                    .body
+                    .iter()
+                    .filter_map(|atom| match atom {
+                        NormalFormAtom::Rule(r) => Some((r.name.to_string(), &r.args[..], r.span)),
+                        NormalFormAtom::Relation(r) => {
+                            Some((format!("*{}", r.name), &r.args[..], r.span))
+                        }
+                        _ => None,
+                    })
+                    .filter(|(_, args, _)| {
+                        !args.is_empty() && args.iter().all(|a| !const_bound.contains(a))
+                    })
+                    .collect();
+                // unifications computing a variable from others join through it
+                let links = rule.body.iter().filter_map(|atom| match atom {
+                    NormalFormAtom::Unification(u) => {
+                        let mut vars = u.expr.bindings();
+                        vars.insert(u.binding.clone());
+                        Some(vars)
+                    }
+                    _ => None,
+                });
+
+                // group by shared variables, each group keeping its first atom if any
+                let mut groups: Vec<(Option<usize>, BTreeSet<Symbol>)> = vec![];
+                let var_sets = atoms
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, (_, args, _))| (Some(idx), args.iter().cloned().collect()))
+                    .chain(links.map(|vars| (None, vars)));
+                for (idx, mut vars) in var_sets {
+                    let mut first = idx;
+                    groups.retain(|(group_first, group_vars)| {
+                        if group_vars.is_disjoint(&vars) {
+                            true
+                        } else {
+                            first = match (first, *group_first) {
+                                (Some(a), Some(b)) => Some(a.min(b)),
+                                (a, b) => a.or(b),
+                            };
+                            vars.extend(group_vars.iter().cloned());
+                            false
+                        }
+                    });
+                    groups.push((first, vars));
+                }
+                let mut groups = groups
+                    .into_iter()
+                    .filter_map(|(first, _)| first)
+                    .collect::<Vec<_>>();
+                if groups.len() > 1 {
+                    groups.sort();
+                    let (left, _, left_span) = &atoms[groups[0]];
+                    let (right, _, right_span) = &atoms[groups[1]];
+                    ret.push(CrossProductLint {
+                        rule: name.to_string(),
+                        left: left.clone(),
+                        right: right.clone(),
+                        left_span: *left_span,
+                        right_span: *right_span,
+                    })
+                }
+            }
+        }
+        ret
+    }
+    /// Finds stored relations read in rule bodies without their first key bound by a constant
+    /// or by an earlier atom, whose rows are all scanned. Only relations with more than
+    /// `threshold` rows as of the last time their statistics were collected are reported.
+    pub(crate) fn full_scan_lints(
+        &self,
+        tx: &SessionTx,
+        threshold: usize,
+    ) -> Result<Vec<FullScanLint>> {
+        let mut ret = vec![];
+        for (name, ruleset) in &self.prog {
+            let rules = match ruleset {
+                NormalFormAlgoOrRules::Rules { rules } => rules,
+                NormalFormAlgoOrRules::Algo { .. } => continue,
+            };
+            for rule in rules {
+                let mut bound: BTreeSet<&Symbol> = rule
+                    .body
+                    .iter()
+                    .filter_map(|atom| match atom {
+                        NormalFormAtom::Unification(u) if u.expr.bindings().is_empty() => {
+                            Some(&u.binding)
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                for atom in &rule.body {
+                    match atom {
+                        NormalFormAtom::Rule(r) => bound.extend(r.args.iter()),
+                        NormalFormAtom::Relation(r) => {
+                            let unbound = r.args.first().is_some_and(|a| !bound.contains(a));
+                            if unbound {
+                                if let Some(stats) = tx.get_statistics(&r.name)? {
+                                    if stats.rows > threshold {
+                                        ret.push(FullScanLint {
+                                            rule: name.to_string(),
+                                            atom: format!("*{}", r.name),
+                                            rows: stats.rows,
+                                            span: r.span,
+                                        })
+                                    }
+                                }
+                            }
+                            bound.extend(r.args.iter());
+                        }
+                        NormalFormAtom::Unification(u) => {
+                            bound.insert(&u.binding);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+}
@@ -53,6 +53,22 @@ impl Disjunction {
 #[derive(Debug)]
 pub(crate) struct Conjunction(pub(crate) Vec<NormalFormAtom>);
 
+/// Removes constantly true predicates from a rule body,
+/// returning `false` if the body contains a constantly false one.
+pub(crate) fn prune_const_predicates(body: &mut Vec<NormalFormAtom>) -> bool {
+    let const_truth = |atom: &NormalFormAtom| match atom {
+        NormalFormAtom::Predicate(p) => p.const_truth(),
+        _ => None,
+    };
+    if body.iter().any(|atom| const_truth(atom) == Some(false)) {
+        return false;
+    }
+    if body.iter().any(|atom| const_truth(atom).is_none()) {
+        body.retain(|atom| const_truth(atom).is_none());
+    }
+    true
+}
+
 impl InputAtom {
     pub(crate) fn negation_normal_form(self) -> Result<Self> {
         Ok(match self {
@@ -131,6 +147,7 @@ impl InputAtom {
         InputNamedFieldRelationApplyAtom {
             name,
             mut args,
+            valid_at,
             span,
         }: InputNamedFieldRelationApplyAtom,
         gen: &mut TempSymbGen,
@@ -166,6 +183,7 @@ impl InputAtom {
         Ok(InputRelationApplyAtom {
             name,
             args: new_args,
+            valid_at,
             span,
         })
     }
@@ -216,7 +234,8 @@ impl InputAtom {
                 }
                 _ => unreachable!(),
             },
-            InputAtom::Unification { inner: u } => {
+            InputAtom::Unification { inner: mut u } => {
+                u.expr.partial_eval()?;
                 Disjunction::singlet(NormalFormAtom::Unification(u))
             }
         })
@@ -325,12 +344,14 @@ impl InputRelationApplyAtom {
             NormalFormAtom::NegatedRelation(NormalFormRelationApplyAtom {
                 name: self.name,
                 args,
+                valid_at: self.valid_at,
                 span: self.span,
             })
         } else {
             NormalFormAtom::Relation(NormalFormRelationApplyAtom {
                 name: self.name,
                 args,
+                valid_at: self.valid_at,
                 span: self.span,
             })
         });
@@ -11,13 +11,14 @@ use smallvec::SmallVec;
 use smartstring::SmartString;
 
 use crate::data::program::{
-    AlgoRuleArg, MagicAlgoApply, MagicAlgoRuleArg, MagicAtom, MagicProgram, MagicRelationApplyAtom,
-    MagicInlineRule, MagicRuleApplyAtom, MagicRulesOrAlgo, MagicSymbol, NormalFormAlgoOrRules,
-    NormalFormAtom, NormalFormProgram, NormalFormInlineRule, StratifiedMagicProgram,
-    StratifiedNormalFormProgram,
+    AlgoRuleArg, MagicAlgoApply, MagicAlgoRuleArg, MagicAtom, MagicInlineRule, MagicProgram,
+    MagicRelationApplyAtom, MagicRuleApplyAtom, MagicRulesOrAlgo, MagicSymbol,
+    NormalFormAlgoOrRules, NormalFormAtom, NormalFormInlineRule, NormalFormProgram,
+    StratifiedMagicProgram, StratifiedNormalFormProgram,
 };
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::parse::SourceSpan;
+use crate::query::compile::NoHistory;
 use crate::query::logical::NamedFieldNotFound;
 use crate::runtime::transact::SessionTx;
 
@@ -257,10 +258,10 @@ impl NormalFormProgram {
                         for atom in rule.body.iter() {
                             match atom {
                                 NormalFormAtom::Rule(r_app)
-                                | NormalFormAtom::NegatedRule(r_app) => {
-                                    if !own_rules.contains(&r_app.name) {
-                                        downstream_rules.insert(r_app.name.clone());
-                                    }
+                                | NormalFormAtom::NegatedRule(r_app)
+                                    if !own_rules.contains(&r_app.name) =>
+                                {
+                                    downstream_rules.insert(r_app.name.clone());
                                 }
                                 _ => {}
                             }
@@ -325,18 +326,36 @@ impl NormalFormProgram {
                                             AlgoRuleArg::Stored {
                                                 name,
                                                 bindings,
+                                                valid_at,
                                                 span,
-                                            } => MagicAlgoRuleArg::Stored {
-                                                name: name.clone(),
-                                                bindings: bindings.clone(),
-                                                span: *span,
-                                            },
+                                            } => {
+                                                if valid_at.is_some() {
+                                                    let relation = tx.get_relation(name, false)?;
+                                                    ensure!(
+                                                        relation.history.is_some(),
+                                                        NoHistory(name.to_string(), *span)
+                                                    );
+                                                }
+                                                MagicAlgoRuleArg::Stored {
+                                                    name: name.clone(),
+                                                    bindings: bindings.clone(),
+                                                    valid_at: *valid_at,
+                                                    span: *span,
+                                                }
+                                            }
                                             AlgoRuleArg::NamedStored {
                                                 name,
                                                 bindings,
+                                                valid_at,
                                                 span,
                                             } => {
                                                 let relation = tx.get_relation(name, false)?;
+                                                if valid_at.is_some() {
+                                                    ensure!(
+                                                        relation.history.is_some(),
+                                                        NoHistory(name.to_string(), *span)
+                                                    );
+                                                }
                                                 let fields: BTreeSet<_> = relation
                                                     .metadata
                                                     .keys
@@ -371,6 +390,7 @@ impl NormalFormProgram {
                                                 MagicAlgoRuleArg::Stored {
                                                     name: name.clone(),
                                                     bindings: new_bindings,
+                                                    valid_at: *valid_at,
                                                     span: *span,
                                                 }
                                             }
@@ -378,7 +398,7 @@ impl NormalFormProgram {
                                     })
                                     .try_collect()?,
                                 options: algo_apply.options.clone(),
-                                arity: algo_apply.arity
+                                arity: algo_apply.arity,
                             },
                         },
                     );
@@ -451,6 +471,7 @@ impl NormalFormAtom {
                 let v = MagicRelationApplyAtom {
                     name: v.name.clone(),
                     args: v.args.clone(),
+                    valid_at: v.valid_at,
                     span: v.span,
                 };
                 for arg in v.args.iter() {
@@ -505,6 +526,7 @@ impl NormalFormAtom {
                 MagicAtom::NegatedRelation(MagicRelationApplyAtom {
                     name: nv.name.clone(),
                     args: nv.args.clone(),
+                    valid_at: nv.valid_at,
                     span: nv.span,
                 })
             }
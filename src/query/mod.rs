@@ -2,13 +2,18 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+pub(crate) mod builder;
 pub(crate) mod compile;
+pub(crate) mod cse;
+pub(crate) mod estimate;
 pub(crate) mod eval;
 pub(crate) mod graph;
+pub(crate) mod lint;
 pub(crate) mod logical;
 pub(crate) mod magic;
-pub(crate) mod stored;
 pub(crate) mod relation;
 pub(crate) mod reorder;
+pub(crate) mod sort;
+pub(crate) mod sql;
+pub(crate) mod stored;
 pub(crate) mod stratify;
-pub(crate) mod sort;
\ No newline at end of file
@@ -2,21 +2,24 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Debug, Formatter};
 use std::iter;
+use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
-use log::{debug, error};
-use miette::{Diagnostic, Result};
+use log::debug;
+use miette::{bail, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::expr::{compute_bounds, Expr};
+use crate::data::program::AsOf;
 use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
+use crate::query::eval::JoinSwitch;
 use crate::runtime::in_mem::{InMemRelation, StoredRelationId};
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::transact::SessionTx;
@@ -25,7 +28,7 @@ use crate::utils::swap_option_result;
 pub(crate) enum RelAlgebra {
     Fixed(InlineFixedRA),
     InMem(InMemRelationRA),
-    Stored(StoredRA),
+    Stored(Box<StoredRA>),
     Join(Box<InnerJoin>),
     NegJoin(Box<NegJoin>),
     Reorder(ReorderRA),
@@ -57,11 +60,6 @@ pub(crate) struct UnificationRA {
     pub(crate) span: SourceSpan,
 }
 
-#[derive(Debug, Error, Diagnostic)]
-#[error("Found value {0:?} while iterating, unacceptable for an Entity ID")]
-#[diagnostic(code(eval::iter_bad_entity_id))]
-struct EntityIdExpected(DataValue, #[label] SourceSpan);
-
 fn eliminate_from_tuple(mut ret: Tuple, eliminate_indices: &BTreeSet<usize>) -> Tuple {
     if !eliminate_indices.is_empty() {
         ret = Tuple(
@@ -118,20 +116,29 @@ impl UnificationRA {
                 .parent
                 .iter(tx, epoch, use_delta)?
                 .map_ok(move |tuple| -> Result<Vec<Tuple>> {
-                    let result_list = self.expr.eval(&tuple)?;
-                    let result_list = result_list.get_list().ok_or_else(|| {
-                        #[derive(Debug, Error, Diagnostic)]
-                        #[error("Invalid spread unification")]
-                        #[diagnostic(code(eval::invalid_spread_unif))]
-                        #[diagnostic(help("Spread unification requires a list at the right"))]
-                        struct BadSpreadUnification(#[label] SourceSpan);
-
-                        BadSpreadUnification(self.span)
-                    })?;
+                    let result_list = tx.eval_expr(&self.expr, &tuple)?;
+                    if result_list == DataValue::Null && tx.query.tolerated_errors.is_some() {
+                        return Ok(vec![]);
+                    }
+                    let result_list: Vec<DataValue> = match result_list {
+                        DataValue::List(l) => l,
+                        DataValue::Set(s) => s.into_iter().collect(),
+                        _ => {
+                            #[derive(Debug, Error, Diagnostic)]
+                            #[error("Invalid spread unification")]
+                            #[diagnostic(code(eval::invalid_spread_unif))]
+                            #[diagnostic(help(
+                                "Spread unification requires a list or a set at the right"
+                            ))]
+                            struct BadSpreadUnification(#[label] SourceSpan);
+
+                            bail!(BadSpreadUnification(self.span))
+                        }
+                    };
                     let mut coll = vec![];
                     for result in result_list {
                         let mut ret = tuple.0.clone();
-                        ret.push(result.clone());
+                        ret.push(result);
                         let ret = Tuple(ret);
                         let ret = eliminate_from_tuple(ret, &eliminate_indices);
                         coll.push(ret);
@@ -146,7 +153,7 @@ impl UnificationRA {
                 self.parent
                     .iter(tx, epoch, use_delta)?
                     .map_ok(move |tuple| -> Result<Tuple> {
-                        let result = self.expr.eval(&tuple)?;
+                        let result = tx.eval_expr(&self.expr, &tuple)?;
                         let mut ret = tuple.0;
                         ret.push(result);
                         let ret = Tuple(ret);
@@ -208,7 +215,7 @@ impl FilteredRA {
                 .filter_map(move |tuple| match tuple {
                     Ok(t) => {
                         for p in self.pred.iter() {
-                            match p.eval_pred(&t) {
+                            match tx.eval_pred(p, &t) {
                                 Ok(false) => return None,
                                 Err(e) => return Some(Err(e)),
                                 Ok(true) => {}
@@ -242,7 +249,7 @@ impl Debug for RelAlgebra {
                 } else if r.data.len() == 1 {
                     f.debug_tuple("Singlet")
                         .field(&bindings)
-                        .field(r.data.get(0).unwrap())
+                        .field(r.data.first().unwrap())
                         .finish()
                 } else {
                     f.debug_tuple("Fixed")
@@ -359,14 +366,16 @@ impl RelAlgebra {
     pub(crate) fn relation(
         bindings: Vec<Symbol>,
         storage: RelationHandle,
+        valid_at: Option<AsOf>,
         span: SourceSpan,
     ) -> Self {
-        Self::Stored(StoredRA {
+        Self::Stored(Box::new(StoredRA {
             bindings,
             storage,
             filters: vec![],
+            valid_at,
             span,
-        })
+        }))
     }
     pub(crate) fn reorder(self, new_order: Vec<Symbol>) -> Self {
         Self::Reorder(ReorderRA {
@@ -416,19 +425,9 @@ impl RelAlgebra {
                     span,
                 })
             }
-            RelAlgebra::Stored(StoredRA {
-                bindings,
-                storage,
-                mut filters,
-                span,
-            }) => {
-                filters.push(filter);
-                RelAlgebra::Stored(StoredRA {
-                    bindings,
-                    storage,
-                    filters,
-                    span,
-                })
+            RelAlgebra::Stored(mut stored) => {
+                stored.filters.push(filter);
+                RelAlgebra::Stored(stored)
             }
             RelAlgebra::Join(inner) => {
                 let filters = filter.to_conjunction();
@@ -445,6 +444,7 @@ impl RelAlgebra {
                     mut right,
                     joiner,
                     to_eliminate,
+                    expected_probes,
                     span,
                 } = *inner;
                 for filter in filters {
@@ -462,6 +462,7 @@ impl RelAlgebra {
                     right,
                     joiner,
                     to_eliminate,
+                    expected_probes,
                     span,
                 }));
                 if !remaining.is_empty() {
@@ -507,6 +508,7 @@ impl RelAlgebra {
                 right_keys,
             },
             to_eliminate: Default::default(),
+            expected_probes: None,
             span,
         }))
     }
@@ -525,6 +527,7 @@ impl RelAlgebra {
                 right_keys,
             },
             to_eliminate: Default::default(),
+            expected_probes: None,
             span,
         }))
     }
@@ -689,13 +692,14 @@ fn invert_option_err<T>(v: Result<Option<T>>) -> Option<Result<T>> {
     }
 }
 
-fn filter_iter(
+fn filter_iter<'a>(
+    tx: &'a SessionTx,
     filters: Vec<Expr>,
-    it: impl Iterator<Item = Result<Tuple>>,
-) -> impl Iterator<Item = Result<Tuple>> {
+    it: impl Iterator<Item = Result<Tuple>> + 'a,
+) -> impl Iterator<Item = Result<Tuple>> + 'a {
     it.filter_map_ok(move |t| -> Option<Result<Tuple>> {
         for p in filters.iter() {
-            match p.eval_pred(&t) {
+            match tx.eval_pred(p, &t) {
                 Ok(false) => return None,
                 Err(e) => {
                     debug!("{:?}", t);
@@ -709,6 +713,11 @@ fn filter_iter(
     .map(flatten_err)
 }
 
+/// The values of the columns of `tuple` at `indices`.
+fn join_key(tuple: &Tuple, indices: &[usize]) -> Box<[DataValue]> {
+    indices.iter().map(|i| tuple.0[*i].clone()).collect()
+}
+
 fn get_eliminate_indices(bindings: &[Symbol], eliminate: &BTreeSet<Symbol>) -> BTreeSet<usize> {
     bindings
         .iter()
@@ -723,15 +732,76 @@ fn get_eliminate_indices(bindings: &[Symbol], eliminate: &BTreeSet<Symbol>) -> B
         .collect::<BTreeSet<_>>()
 }
 
+/// A join probing a stored relation by key prefix switches to a hash table once it has
+/// probed this many times as many rows as expected
+const ADAPTIVE_JOIN_FACTOR: usize = 10;
+/// Joins probing fewer rows than this never switch, whatever was expected
+const ADAPTIVE_JOIN_MIN_PROBES: usize = 1000;
+/// Joins do not switch when the stored relation has more rows than this to hold in memory
+const ADAPTIVE_JOIN_MAX_ROWS: usize = 1_000_000;
+/// A negated rule is only looked up by the keys probed if it has at least this many times
+/// as many rows as the probes expected
+const NEG_LOOKUP_FACTOR: usize = 10;
+
 #[derive(Debug)]
 pub(crate) struct StoredRA {
     pub(crate) bindings: Vec<Symbol>,
     pub(crate) storage: RelationHandle,
     pub(crate) filters: Vec<Expr>,
+    pub(crate) valid_at: Option<AsOf>,
     pub(crate) span: SourceSpan,
 }
 
 impl StoredRA {
+    fn scan_all<'a>(&'a self, tx: &'a SessionTx) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        match self.valid_at {
+            None => Left(self.storage.scan_all(tx)),
+            Some(valid_at) => Right(self.storage.scan_all_as_of(tx, valid_at)),
+        }
+    }
+
+    fn scan_prefix<'a>(
+        &'a self,
+        tx: &'a SessionTx,
+        prefix: &Tuple,
+    ) -> impl Iterator<Item = Result<Tuple>> + 'a {
+        match self.valid_at {
+            None => Left(self.storage.scan_prefix(tx, prefix)),
+            Some(valid_at) => Right(self.storage.scan_prefix_as_of(tx, prefix, valid_at)),
+        }
+    }
+
+    /// The rows passing the filters, by the encoding of their first `n_prefix` keys, or `None`
+    /// if there are too many to hold in memory. Keys are encoded as for the prefix scans the
+    /// table replaces, so that both match the same rows, numbers of different kinds included.
+    fn hash_table(
+        &self,
+        tx: &SessionTx,
+        n_prefix: usize,
+    ) -> Result<Option<HashMap<Vec<u8>, Arc<Vec<Tuple>>>>> {
+        let mut table: HashMap<Vec<u8>, Vec<Tuple>> = HashMap::new();
+        let mut n_rows = 0;
+        'outer: for found in self.scan_all(tx) {
+            let found = found?;
+            for p in self.filters.iter() {
+                if !tx.eval_pred(p, &found)? {
+                    continue 'outer;
+                }
+            }
+            n_rows += 1;
+            if n_rows > ADAPTIVE_JOIN_MAX_ROWS {
+                return Ok(None);
+            }
+            table
+                .entry(Tuple(found.0[..n_prefix].to_vec()).encode_as_key(self.storage.id))
+                .or_default()
+                .push(found);
+        }
+        Ok(Some(
+            table.into_iter().map(|(k, v)| (k, Arc::new(v))).collect(),
+        ))
+    }
+
     fn fill_binding_indices(&mut self) -> Result<()> {
         let bindings: BTreeMap<_, _> = self
             .bindings
@@ -752,6 +822,7 @@ impl StoredRA {
         left_iter: TupleIter<'a>,
         (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
         eliminate_indices: BTreeSet<usize>,
+        expected_probes: Option<usize>,
     ) -> Result<TupleIter<'a>> {
         let mut right_invert_indices = right_join_indices.iter().enumerate().collect_vec();
         right_invert_indices.sort_by_key(|(_, b)| **b);
@@ -760,6 +831,12 @@ impl StoredRA {
             .map(|(a, _)| left_join_indices[a])
             .collect_vec();
 
+        let switch_at = expected_probes.map(|n| {
+            n.saturating_mul(ADAPTIVE_JOIN_FACTOR)
+                .max(ADAPTIVE_JOIN_MIN_PROBES)
+        });
+        let mut probes = 0;
+        let mut table = None;
         let mut skip_range_check = false;
         let it = left_iter
             .map_ok(move |tuple| {
@@ -769,24 +846,48 @@ impl StoredRA {
                         .map(|i| tuple.0[*i].clone())
                         .collect_vec(),
                 );
+                probes += 1;
+                if switch_at == Some(probes) {
+                    let built = self.hash_table(tx, prefix.0.len());
+                    tx.record_join_switch(JoinSwitch {
+                        relation: self.storage.name.clone(),
+                        expected_probes: expected_probes.unwrap_or_default(),
+                        probes,
+                        switched: matches!(built, Ok(Some(_))),
+                    });
+                    match built {
+                        Ok(built) => table = built,
+                        Err(err) => return Right(Right(iter::once(Err(err)))),
+                    }
+                }
+                if let Some(table) = &table {
+                    let found: Arc<Vec<Tuple>> = table
+                        .get(&prefix.encode_as_key(self.storage.id))
+                        .cloned()
+                        .unwrap_or_default();
+                    return Right(Left((0..found.len()).map(move |i| {
+                        let mut ret = tuple.0.clone();
+                        ret.extend(found[i].0.iter().cloned());
+                        Ok(Tuple(ret))
+                    })));
+                }
                 let filters = self.filters.clone();
 
-                if !skip_range_check && !self.filters.is_empty() {
+                // bounded scans are not available over the history of a relation
+                if !skip_range_check && !self.filters.is_empty() && self.valid_at.is_none() {
                     let other_bindings = &self.bindings[right_join_indices.len()..];
-                    let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
-                        Ok(b) => b,
-                        _ => (vec![], vec![]),
-                    };
+                    let (l_bound, u_bound) =
+                        compute_bounds(&self.filters, other_bindings).unwrap_or_default();
                     if !l_bound.iter().all(|v| *v == DataValue::Null)
                         || !u_bound.iter().all(|v| *v == DataValue::Bot)
                     {
-                        return Left(
+                        return Left(Left(
                             self.storage
                                 .scan_bounded_prefix(tx, &prefix, &l_bound, &u_bound)
                                 .map(move |res_found| -> Result<Option<Tuple>> {
                                     let found = res_found?;
                                     for p in filters.iter() {
-                                        if !p.eval_pred(&found)? {
+                                        if !tx.eval_pred(p, &found)? {
                                             return Ok(None);
                                         }
                                     }
@@ -795,17 +896,16 @@ impl StoredRA {
                                     Ok(Some(Tuple(ret)))
                                 })
                                 .filter_map(swap_option_result),
-                        );
+                        ));
                     }
                 }
                 skip_range_check = true;
-                Right(
-                    self.storage
-                        .scan_prefix(tx, &prefix)
+                Left(Right(
+                    self.scan_prefix(tx, &prefix)
                         .map(move |res_found| -> Result<Option<Tuple>> {
                             let found = res_found?;
                             for p in filters.iter() {
-                                if !p.eval_pred(&found)? {
+                                if !tx.eval_pred(p, &found)? {
                                     return Ok(None);
                                 }
                             }
@@ -814,7 +914,7 @@ impl StoredRA {
                             Ok(Some(Tuple(ret)))
                         })
                         .filter_map(swap_option_result),
-                )
+                ))
             })
             .flatten_ok()
             .map(flatten_err);
@@ -854,7 +954,7 @@ impl StoredRA {
                                 .collect_vec(),
                         );
 
-                        'outer: for found in self.storage.scan_prefix(tx, &prefix) {
+                        'outer: for found in self.scan_prefix(tx, &prefix) {
                             let found = found?;
                             for (left_idx, right_idx) in
                                 left_join_indices.iter().zip(right_join_indices.iter())
@@ -891,7 +991,7 @@ impl StoredRA {
         } else {
             let mut right_join_vals = BTreeSet::new();
 
-            for tuple in self.storage.scan_all(tx) {
+            for tuple in self.scan_all(tx) {
                 let tuple = tuple?;
                 let to_join: Box<[DataValue]> = right_join_indices
                     .iter()
@@ -935,12 +1035,12 @@ impl StoredRA {
         }
     }
 
-    fn iter(&self, tx: &SessionTx) -> Result<TupleIter<'_>> {
-        let it = self.storage.scan_all(tx);
+    fn iter<'a>(&'a self, tx: &'a SessionTx) -> Result<TupleIter<'a>> {
+        let it = self.scan_all(tx);
         Ok(if self.filters.is_empty() {
             Box::new(it)
         } else {
-            Box::new(filter_iter(self.filters.clone(), it))
+            Box::new(filter_iter(tx, self.filters.clone(), it))
         })
     }
 }
@@ -975,37 +1075,35 @@ impl InMemRelationRA {
         Ok(())
     }
 
-    fn iter(
-        &self,
+    fn iter<'a>(
+        &'a self,
+        tx: &'a SessionTx,
         epoch: Option<u32>,
         use_delta: &BTreeSet<StoredRelationId>,
-    ) -> Result<TupleIter<'_>> {
+    ) -> Result<TupleIter<'a>> {
         if epoch == Some(0) && use_delta.contains(&self.storage.id) {
             return Ok(Box::new(iter::empty()));
         }
 
         let scan_epoch = match epoch {
             None => 0,
-            Some(ep) => {
-                if use_delta.contains(&self.storage.id) {
-                    ep - 1
-                } else {
-                    0
-                }
-            }
+            Some(ep) if use_delta.contains(&self.storage.id) => ep - 1,
+            Some(_) => 0,
         };
         let it = self.storage.scan_all_for_epoch(scan_epoch);
         Ok(if self.filters.is_empty() {
             Box::new(it)
         } else {
-            Box::new(filter_iter(self.filters.clone(), it))
+            Box::new(filter_iter(tx, self.filters.clone(), it))
         })
     }
     fn neg_join<'a>(
         &'a self,
+        tx: &'a SessionTx,
         left_iter: TupleIter<'a>,
         (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
         eliminate_indices: BTreeSet<usize>,
+        expected_probes: Option<usize>,
     ) -> Result<TupleIter<'a>> {
         debug_assert!(!right_join_indices.is_empty());
         let mut right_invert_indices = right_join_indices.iter().enumerate().collect_vec();
@@ -1063,13 +1161,52 @@ impl InMemRelationRA {
                     .filter_map(invert_option_err),
             ))
         } else {
+            let n_rows = self.storage.num_tuples();
+            let lookup = match (expected_probes, tx.eval.negation_materialize_rows) {
+                (Some(probes), Some(threshold)) => {
+                    n_rows > threshold && probes.saturating_mul(NEG_LOOKUP_FACTOR) <= n_rows
+                }
+                _ => false,
+            };
+            if lookup && !left_to_prefix_indices.is_empty() {
+                return Ok(self.neg_lookup_join(
+                    left_iter,
+                    (left_join_indices, right_join_indices),
+                    left_to_prefix_indices,
+                    eliminate_indices,
+                ));
+            }
+            let mut left_iter = left_iter;
+            // when few probes are expected, they are gathered first so that only the rows
+            // matching them are kept, unless there turn out to be more of them
+            let mut probed = None;
+            if lookup {
+                let cap = expected_probes
+                    .unwrap_or_default()
+                    .saturating_mul(NEG_LOOKUP_FACTOR);
+                let gathered = left_iter
+                    .by_ref()
+                    .take(cap + 1)
+                    .collect::<Result<Vec<_>>>()?;
+                if gathered.len() <= cap {
+                    probed = Some(
+                        gathered
+                            .iter()
+                            .map(|t| join_key(t, &left_join_indices))
+                            .collect::<BTreeSet<_>>(),
+                    );
+                }
+                left_iter = Box::new(gathered.into_iter().map(Ok).chain(left_iter));
+            }
             let mut right_join_vals = BTreeSet::new();
             for tuple in self.storage.scan_all() {
                 let tuple = tuple?;
-                let to_join: Box<[DataValue]> = right_join_indices
-                    .iter()
-                    .map(|i| tuple.0[*i].clone())
-                    .collect();
+                let to_join = join_key(&tuple, &right_join_indices);
+                if let Some(probed) = &probed {
+                    if !probed.contains(&to_join) {
+                        continue;
+                    }
+                }
                 right_join_vals.insert(to_join);
             }
 
@@ -1107,8 +1244,59 @@ impl InMemRelationRA {
             ))
         }
     }
+
+    /// Anti-joins by looking up the rows of the relation matching each key probed by the
+    /// part of the key that is a prefix, remembering the answer for keys probed again.
+    fn neg_lookup_join<'a>(
+        &'a self,
+        left_iter: TupleIter<'a>,
+        (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
+        left_to_prefix_indices: Vec<usize>,
+        eliminate_indices: BTreeSet<usize>,
+    ) -> TupleIter<'a> {
+        let mut probed: HashMap<Box<[DataValue]>, bool> = HashMap::new();
+        Box::new(
+            left_iter
+                .map_ok(move |tuple| -> Result<Option<Tuple>> {
+                    let key = join_key(&tuple, &left_join_indices);
+                    let found = match probed.get(&key) {
+                        Some(found) => *found,
+                        None => {
+                            let prefix = Tuple(
+                                left_to_prefix_indices
+                                    .iter()
+                                    .map(|i| tuple.0[*i].clone())
+                                    .collect_vec(),
+                            );
+                            let mut found = false;
+                            for row in self.storage.scan_prefix(&prefix) {
+                                let row = row?;
+                                if right_join_indices
+                                    .iter()
+                                    .zip(key.iter())
+                                    .all(|(i, v)| row.0[*i] == *v)
+                                {
+                                    found = true;
+                                    break;
+                                }
+                            }
+                            probed.insert(key, found);
+                            found
+                        }
+                    };
+                    Ok(if found {
+                        None
+                    } else {
+                        Some(eliminate_from_tuple(tuple, &eliminate_indices))
+                    })
+                })
+                .map(flatten_err)
+                .filter_map(invert_option_err),
+        )
+    }
     fn prefix_join<'a>(
         &'a self,
+        tx: &'a SessionTx,
         left_iter: TupleIter<'a>,
         (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
         eliminate_indices: BTreeSet<usize>,
@@ -1126,13 +1314,8 @@ impl InMemRelationRA {
             .collect_vec();
         let scan_epoch = match epoch {
             None => 0,
-            Some(ep) => {
-                if use_delta.contains(&self.storage.id) {
-                    ep - 1
-                } else {
-                    0
-                }
-            }
+            Some(ep) if use_delta.contains(&self.storage.id) => ep - 1,
+            Some(_) => 0,
         };
         let mut skip_range_check = false;
         let it = left_iter
@@ -1148,10 +1331,8 @@ impl InMemRelationRA {
 
                 if !skip_range_check && !self.filters.is_empty() {
                     let other_bindings = &self.bindings[right_join_indices.len()..];
-                    let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
-                        Ok(b) => b,
-                        _ => (vec![], vec![]),
-                    };
+                    let (l_bound, u_bound) =
+                        compute_bounds(&self.filters, other_bindings).unwrap_or_default();
                     if !l_bound.iter().all(|v| *v == DataValue::Null)
                         || !u_bound.iter().all(|v| *v == DataValue::Bot)
                     {
@@ -1163,7 +1344,7 @@ impl InMemRelationRA {
                                 .map(move |res_found| -> Result<Option<Tuple>> {
                                     let found = res_found?;
                                     for p in filters.iter() {
-                                        if !p.eval_pred(&found)? {
+                                        if !tx.eval_pred(p, &found)? {
                                             return Ok(None);
                                         }
                                     }
@@ -1182,7 +1363,7 @@ impl InMemRelationRA {
                         .map(move |res_found| -> Result<Option<Tuple>> {
                             let found = res_found?;
                             for p in filters.iter() {
-                                if !p.eval_pred(&found)? {
+                                if !tx.eval_pred(p, &found)? {
                                     return Ok(None);
                                 }
                             }
@@ -1314,7 +1495,7 @@ impl RelAlgebra {
     ) -> Result<TupleIter<'a>> {
         match self {
             RelAlgebra::Fixed(f) => Ok(Box::new(f.data.iter().map(|t| Ok(Tuple(t.clone()))))),
-            RelAlgebra::InMem(r) => r.iter(epoch, use_delta),
+            RelAlgebra::InMem(r) => r.iter(tx, epoch, use_delta),
             RelAlgebra::Stored(v) => v.iter(tx),
             RelAlgebra::Join(j) => j.iter(tx, epoch, use_delta),
             RelAlgebra::Reorder(r) => r.iter(tx, epoch, use_delta),
@@ -1331,6 +1512,10 @@ pub(crate) struct NegJoin {
     pub(crate) right: RelAlgebra,
     pub(crate) joiner: Joiner,
     pub(crate) to_eliminate: BTreeSet<Symbol>,
+    /// The number of rows of the left side the planner expects a negated rule to be probed
+    /// with. When few compared to the rows of the rule, only the rows matching the keys
+    /// probed are looked up, instead of materializing the keys of all of them.
+    pub(crate) expected_probes: Option<usize>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1402,9 +1587,11 @@ impl NegJoin {
                     )
                     .unwrap();
                 r.neg_join(
+                    tx,
                     self.left.iter(tx, epoch, use_delta)?,
                     join_indices,
                     eliminate_indices,
+                    self.expected_probes,
                 )
             }
             RelAlgebra::Stored(v) => {
@@ -1435,6 +1622,10 @@ pub(crate) struct InnerJoin {
     pub(crate) right: RelAlgebra,
     pub(crate) joiner: Joiner,
     pub(crate) to_eliminate: BTreeSet<Symbol>,
+    /// The number of rows of the left side the planner expects a join looking up a stored
+    /// relation by key prefix to probe with. When the left side turns out much larger, the
+    /// join switches to looking the rows up in a hash table. When `None`, it never switches.
+    pub(crate) expected_probes: Option<usize>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1543,6 +1734,7 @@ impl InnerJoin {
                     .unwrap();
                 if join_is_prefix(&join_indices.1) {
                     r.prefix_join(
+                        tx,
                         self.left.iter(tx, epoch, use_delta)?,
                         join_indices,
                         eliminate_indices,
@@ -1567,6 +1759,7 @@ impl InnerJoin {
                         self.left.iter(tx, epoch, use_delta)?,
                         join_indices,
                         eliminate_indices,
+                        self.expected_probes,
                     )
                 } else {
                     self.materialized_join(tx, eliminate_indices, epoch, use_delta)
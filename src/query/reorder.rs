@@ -5,6 +5,7 @@
 use std::collections::BTreeSet;
 use std::mem;
 
+use itertools::Itertools;
 use miette::{bail, Diagnostic, Result};
 use thiserror::Error;
 
@@ -27,6 +28,44 @@ pub(crate) struct UnsafeNegation(#[label] pub(crate) SourceSpan);
 pub(crate) struct UnboundVariable(#[label] pub(crate) SourceSpan);
 
 impl NormalFormInlineRule {
+    /// Sorts each run of consecutive predicates of a well-ordered body by estimated cost
+    /// and selectivity, so that cheap and selective filters reject rows first.
+    /// A predicate that may fail is never moved ahead of a predicate written before it,
+    /// which may be guarding it, as `y != 0` guards `mod(x, y) == 1`. Otherwise predicates
+    /// of equal estimates keep their written order.
+    pub(crate) fn reorder_predicates_by_cost(mut self) -> Self {
+        let mut i = 0;
+        while i < self.body.len() {
+            let start = i;
+            while i < self.body.len() && matches!(self.body[i], NormalFormAtom::Predicate(_)) {
+                i += 1;
+            }
+            if i == start {
+                i += 1;
+                continue;
+            }
+            let mut pending = self
+                .body
+                .drain(start..i)
+                .map(|atom| match atom {
+                    NormalFormAtom::Predicate(p) => p,
+                    _ => unreachable!(),
+                })
+                .collect_vec();
+            let mut ordered = Vec::with_capacity(pending.len());
+            while !pending.is_empty() {
+                let (next, _) = pending
+                    .iter()
+                    .enumerate()
+                    .filter(|(j, p)| *j == 0 || !p.may_fail())
+                    .min_by_key(|(_, p)| (p.eval_cost(), p.selectivity_rank()))
+                    .unwrap();
+                ordered.push(NormalFormAtom::Predicate(pending.remove(next)));
+            }
+            self.body.splice(start..start, ordered);
+        }
+        self
+    }
     pub(crate) fn convert_to_well_ordered_rule(self) -> Result<Self> {
         let mut seen_variables = BTreeSet::default();
         let mut round_1_collected = vec![];
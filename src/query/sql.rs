@@ -0,0 +1,613 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use itertools::Itertools;
+use miette::{bail, ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::expr::Expr;
+use crate::data::program::{
+    InputProgram, NoEntryError, NormalFormAlgoOrRules, NormalFormAtom, NormalFormInlineRule,
+    NormalFormProgram, SortDir,
+};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::value::{DataValue, Num};
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::runtime::transact::SessionTx;
+
+/// SQL dialects the transpiler can target.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum SqlDialect {
+    Postgres,
+    Sqlite,
+    MySql,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown SQL dialect '{0}'")]
+#[diagnostic(code(transpile::unknown_dialect))]
+#[diagnostic(help("Supported dialects are 'postgres', 'sqlite' and 'mysql'"))]
+struct UnknownSqlDialect(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Query cannot be transpiled to SQL: {0}")]
+#[diagnostic(code(transpile::not_supported))]
+#[diagnostic(help(
+    "Only non-recursive queries over stored relations without fixed rules can be transpiled"
+))]
+struct NotTranspilable(String, #[label] SourceSpan);
+
+impl SqlDialect {
+    pub(crate) fn from_name(name: &str) -> Result<Self> {
+        Ok(match name.to_ascii_lowercase().as_str() {
+            "postgres" | "postgresql" => SqlDialect::Postgres,
+            "sqlite" => SqlDialect::Sqlite,
+            "mysql" => SqlDialect::MySql,
+            _ => bail!(UnknownSqlDialect(name.to_string())),
+        })
+    }
+    fn quote_ident(&self, ident: &str) -> String {
+        match self {
+            SqlDialect::Postgres | SqlDialect::Sqlite => {
+                format!("\"{}\"", ident.replace('"', "\"\""))
+            }
+            SqlDialect::MySql => format!("`{}`", ident.replace('`', "``")),
+        }
+    }
+    fn literal(&self, val: &DataValue, span: SourceSpan) -> Result<String> {
+        Ok(match val {
+            DataValue::Null => "NULL".to_string(),
+            DataValue::Bool(b) => match (self, b) {
+                (SqlDialect::Sqlite, true) => "1".to_string(),
+                (SqlDialect::Sqlite, false) => "0".to_string(),
+                (_, true) => "TRUE".to_string(),
+                (_, false) => "FALSE".to_string(),
+            },
+            DataValue::Num(Num::Int(i)) => i.to_string(),
+            DataValue::Num(Num::Float(f)) => {
+                ensure!(
+                    f.is_finite(),
+                    NotTranspilable(format!("non-finite number {}", f), span)
+                );
+                format!("{:?}", f)
+            }
+            DataValue::Str(s) => {
+                let escaped = s.replace('\'', "''");
+                if *self == SqlDialect::MySql {
+                    format!("'{}'", escaped.replace('\\', "\\\\"))
+                } else {
+                    format!("'{}'", escaped)
+                }
+            }
+            v => bail!(NotTranspilable(
+                format!("constant {} has no SQL equivalent", v),
+                span
+            )),
+        })
+    }
+}
+
+fn rule_cte_name(name: &Symbol) -> String {
+    if name.is_prog_entry() {
+        "_cozo_entry".to_string()
+    } else {
+        format!("_cozo_{}", name.name)
+    }
+}
+
+struct RuleBodyCtx {
+    from_items: Vec<String>,
+    conditions: Vec<String>,
+    bindings: BTreeMap<Symbol, String>,
+}
+
+impl SessionTx {
+    /// Transpile a non-recursive program touching only stored relations into a single
+    /// SQL statement: every inline rule becomes a common table expression.
+    pub(crate) fn transpile_to_sql(
+        &self,
+        input_program: &InputProgram,
+        dialect: SqlDialect,
+    ) -> Result<String> {
+        if let Some((meta, _)) = &input_program.out_opts.store_relation {
+            bail!(NotTranspilable(
+                "queries writing to stored relations".to_string(),
+                meta.span
+            ))
+        }
+        let out_head = input_program.get_entry_out_head_or_default()?;
+        let prog = input_program.to_normalized_program(self)?;
+
+        let entry = Symbol::new(PROG_ENTRY, SourceSpan(0, 0));
+        let mut ordered = vec![];
+        let mut visited = BTreeSet::new();
+        let mut visiting = BTreeSet::new();
+        topo_order_rules(&prog, &entry, &mut visiting, &mut visited, &mut ordered)?;
+
+        let mut arities: BTreeMap<Symbol, usize> = BTreeMap::new();
+        let mut ctes = Vec::with_capacity(ordered.len());
+        for name in &ordered {
+            let (sql, arity) = match prog.prog.get(name).ok_or(NoEntryError)? {
+                NormalFormAlgoOrRules::Rules { rules } => {
+                    let arity = rules[0].head.len();
+                    let sql = rules
+                        .iter()
+                        .map(|rule| self.rule_to_sql(rule, &arities, dialect))
+                        .collect::<Result<Vec<_>>>()?
+                        .join(" UNION ");
+                    (sql, arity)
+                }
+                NormalFormAlgoOrRules::Algo { algo } => {
+                    ensure!(
+                        algo.algo.name.name == "Constant",
+                        NotTranspilable(format!("fixed rule {}", algo.algo.name.name), algo.span)
+                    );
+                    let arity = algo.arity()?;
+                    (
+                        constant_to_sql(algo.options.get("data"), arity, dialect, algo.span)?,
+                        arity,
+                    )
+                }
+            };
+            arities.insert(name.clone(), arity);
+            ctes.push(format!(
+                "{} AS ({})",
+                dialect.quote_ident(&rule_cte_name(name)),
+                sql
+            ));
+        }
+
+        let mut ret = String::new();
+        write!(ret, "WITH {} SELECT ", ctes.join(", ")).unwrap();
+        let entry_arity = *arities.get(&entry).ok_or(NoEntryError)?;
+        ensure!(
+            entry_arity == out_head.len(),
+            NotTranspilable("entry arity mismatch".to_string(), SourceSpan(0, 0))
+        );
+        let cols = out_head
+            .iter()
+            .enumerate()
+            .map(|(i, s)| format!("c{} AS {}", i, dialect.quote_ident(&s.name)))
+            .join(", ");
+        write!(ret, "{} FROM {}", cols, dialect.quote_ident("_cozo_entry")).unwrap();
+        if !input_program.out_opts.sorters.is_empty() {
+            let mut sorters = vec![];
+            for (symb, dir) in &input_program.out_opts.sorters {
+                let idx = out_head
+                    .iter()
+                    .position(|h| h.name == symb.name)
+                    .ok_or_else(|| {
+                        NotTranspilable(format!("sort key {} not in output", symb), symb.span)
+                    })?;
+                sorters.push(format!(
+                    "c{}{}",
+                    idx,
+                    if *dir == SortDir::Dsc { " DESC" } else { "" }
+                ));
+            }
+            write!(ret, " ORDER BY {}", sorters.join(", ")).unwrap();
+        }
+        match (input_program.out_opts.limit, input_program.out_opts.offset) {
+            (Some(l), Some(o)) => write!(ret, " LIMIT {} OFFSET {}", l, o).unwrap(),
+            (Some(l), None) => write!(ret, " LIMIT {}", l).unwrap(),
+            (None, Some(o)) => match dialect {
+                SqlDialect::Postgres => write!(ret, " OFFSET {}", o).unwrap(),
+                SqlDialect::Sqlite => write!(ret, " LIMIT -1 OFFSET {}", o).unwrap(),
+                SqlDialect::MySql => {
+                    write!(ret, " LIMIT 18446744073709551615 OFFSET {}", o).unwrap()
+                }
+            },
+            (None, None) => {}
+        }
+        Ok(ret)
+    }
+
+    fn rule_to_sql(
+        &self,
+        rule: &NormalFormInlineRule,
+        arities: &BTreeMap<Symbol, usize>,
+        dialect: SqlDialect,
+    ) -> Result<String> {
+        let mut ctx = RuleBodyCtx {
+            from_items: vec![],
+            conditions: vec![],
+            bindings: Default::default(),
+        };
+        let mut alias_counter = 0;
+        for atom in &rule.body {
+            match atom {
+                NormalFormAtom::Rule(rule_app) | NormalFormAtom::NegatedRule(rule_app) => {
+                    let arity = *arities.get(&rule_app.name).ok_or_else(|| {
+                        NotTranspilable(
+                            format!("rule {} is recursive or undefined", rule_app.name),
+                            rule_app.span,
+                        )
+                    })?;
+                    ensure!(
+                        arity == rule_app.args.len(),
+                        NotTranspilable(
+                            format!("arity mismatch for rule {}", rule_app.name),
+                            rule_app.span
+                        )
+                    );
+                    let cols = (0..arity).map(|i| format!("c{}", i)).collect_vec();
+                    alias_counter += 1;
+                    ctx.add_table(
+                        dialect.quote_ident(&rule_cte_name(&rule_app.name)),
+                        format!("t{}", alias_counter),
+                        &cols,
+                        &rule_app.args,
+                        matches!(atom, NormalFormAtom::NegatedRule(_)),
+                    );
+                }
+                NormalFormAtom::Relation(rel_app) | NormalFormAtom::NegatedRelation(rel_app) => {
+                    ensure!(
+                        rel_app.valid_at.is_none(),
+                        NotTranspilable(
+                            format!("stored relation {} is read as of a time", rel_app.name),
+                            rel_app.span
+                        )
+                    );
+                    let handle = self.get_relation(&rel_app.name, false)?;
+                    if handle.access_level < AccessLevel::ReadOnly {
+                        bail!(InsufficientAccessLevel(
+                            handle.name.to_string(),
+                            "reading rows".to_string(),
+                            handle.access_level
+                        ));
+                    }
+                    let cols = handle
+                        .metadata
+                        .keys
+                        .iter()
+                        .chain(handle.metadata.non_keys.iter())
+                        .map(|col| dialect.quote_ident(&col.name))
+                        .collect_vec();
+                    ensure!(
+                        cols.len() == rel_app.args.len(),
+                        NotTranspilable(
+                            format!("arity mismatch for stored relation {}", rel_app.name),
+                            rel_app.span
+                        )
+                    );
+                    alias_counter += 1;
+                    ctx.add_table(
+                        dialect.quote_ident(&rel_app.name.name),
+                        format!("t{}", alias_counter),
+                        &cols,
+                        &rel_app.args,
+                        matches!(atom, NormalFormAtom::NegatedRelation(_)),
+                    );
+                }
+                NormalFormAtom::Predicate(p) => {
+                    let cond = expr_to_sql(p, &ctx.bindings, dialect)?;
+                    ctx.conditions.push(cond);
+                }
+                NormalFormAtom::Unification(u) => {
+                    ensure!(
+                        !u.one_many_unif,
+                        NotTranspilable("multi-unification with 'in'".to_string(), u.span)
+                    );
+                    let expr = expr_to_sql(&u.expr, &ctx.bindings, dialect)?;
+                    match ctx.bindings.get(&u.binding) {
+                        Some(existing) => {
+                            ctx.conditions.push(format!("{} = {}", existing, expr));
+                        }
+                        None => {
+                            ctx.bindings
+                                .insert(u.binding.clone(), format!("({})", expr));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut select_cols = Vec::with_capacity(rule.head.len());
+        let mut group_by = vec![];
+        let mut has_aggr = false;
+        for (i, (symb, aggr)) in rule.head.iter().zip(rule.aggr.iter()).enumerate() {
+            let bound = ctx.bindings.get(symb).ok_or_else(|| {
+                NotTranspilable(format!("head symbol {} is unbound", symb), symb.span)
+            })?;
+            match aggr {
+                None => {
+                    select_cols.push(format!("{} AS c{}", bound, i));
+                    group_by.push((i + 1).to_string());
+                }
+                Some((aggr, args)) => {
+                    ensure!(
+                        args.is_empty(),
+                        NotTranspilable(
+                            format!("aggregation {} with extra arguments", aggr.name),
+                            symb.span
+                        )
+                    );
+                    has_aggr = true;
+                    let applied = match aggr.name {
+                        "AGGR_COUNT" => format!("COUNT({})", bound),
+                        "AGGR_COUNT_UNIQUE" => format!("COUNT(DISTINCT {})", bound),
+                        "AGGR_SUM" => format!("SUM({})", bound),
+                        "AGGR_MIN" => format!("MIN({})", bound),
+                        "AGGR_MAX" => format!("MAX({})", bound),
+                        "AGGR_MEAN" => format!("AVG({})", bound),
+                        name => bail!(NotTranspilable(
+                            format!(
+                                "aggregation {}",
+                                name.strip_prefix("AGGR_").unwrap().to_ascii_lowercase()
+                            ),
+                            symb.span
+                        )),
+                    };
+                    select_cols.push(format!("{} AS c{}", applied, i));
+                }
+            }
+        }
+        ensure!(
+            !select_cols.is_empty(),
+            NotTranspilable("rules with empty heads".to_string(), SourceSpan(0, 0))
+        );
+
+        let mut ret = String::new();
+        if has_aggr {
+            write!(ret, "SELECT {}", select_cols.join(", ")).unwrap();
+        } else {
+            write!(ret, "SELECT DISTINCT {}", select_cols.join(", ")).unwrap();
+        }
+        if !ctx.from_items.is_empty() {
+            write!(ret, " FROM {}", ctx.from_items.join(", ")).unwrap();
+        }
+        if !ctx.conditions.is_empty() {
+            write!(ret, " WHERE {}", ctx.conditions.join(" AND ")).unwrap();
+        }
+        if has_aggr && !group_by.is_empty() {
+            write!(ret, " GROUP BY {}", group_by.join(", ")).unwrap();
+        }
+        Ok(ret)
+    }
+}
+
+impl RuleBodyCtx {
+    fn add_table(
+        &mut self,
+        table: String,
+        alias: String,
+        cols: &[String],
+        args: &[Symbol],
+        negated: bool,
+    ) {
+        if negated {
+            let conds = args
+                .iter()
+                .zip(cols.iter())
+                .filter_map(|(arg, col)| {
+                    self.bindings
+                        .get(arg)
+                        .map(|bound| format!("{}.{} = {}", alias, col, bound))
+                })
+                .collect_vec();
+            let mut sub = format!("NOT EXISTS (SELECT 1 FROM {} AS {}", table, alias);
+            if !conds.is_empty() {
+                write!(sub, " WHERE {}", conds.join(" AND ")).unwrap();
+            }
+            sub.push(')');
+            self.conditions.push(sub);
+        } else {
+            for (arg, col) in args.iter().zip(cols.iter()) {
+                let col_ref = format!("{}.{}", alias, col);
+                match self.bindings.get(arg) {
+                    Some(bound) => self.conditions.push(format!("{} = {}", col_ref, bound)),
+                    None => {
+                        self.bindings.insert(arg.clone(), col_ref);
+                    }
+                }
+            }
+            self.from_items.push(format!("{} AS {}", table, alias));
+        }
+    }
+}
+
+fn topo_order_rules(
+    prog: &NormalFormProgram,
+    name: &Symbol,
+    visiting: &mut BTreeSet<Symbol>,
+    visited: &mut BTreeSet<Symbol>,
+    ordered: &mut Vec<Symbol>,
+) -> Result<()> {
+    if visited.contains(name) {
+        return Ok(());
+    }
+    if !visiting.insert(name.clone()) {
+        bail!(NotTranspilable(
+            format!("rule {} is recursive", name),
+            name.span
+        ))
+    }
+    let rules_or_algo = prog.prog.get(name).ok_or(NoEntryError)?;
+    if let NormalFormAlgoOrRules::Rules { rules } = rules_or_algo {
+        for rule in rules {
+            for atom in &rule.body {
+                if let NormalFormAtom::Rule(app) | NormalFormAtom::NegatedRule(app) = atom {
+                    topo_order_rules(prog, &app.name, visiting, visited, ordered)?;
+                }
+            }
+        }
+    }
+    visiting.remove(name);
+    visited.insert(name.clone());
+    ordered.push(name.clone());
+    Ok(())
+}
+
+fn constant_to_sql(
+    data: Option<&Expr>,
+    arity: usize,
+    dialect: SqlDialect,
+    span: SourceSpan,
+) -> Result<String> {
+    let rows = data
+        .and_then(|d| d.get_const())
+        .and_then(|d| d.get_list())
+        .ok_or_else(|| NotTranspilable("malformed constant rule".to_string(), span))?;
+    if rows.is_empty() {
+        let nulls = (0..arity).map(|i| format!("NULL AS c{}", i)).join(", ");
+        return Ok(format!("SELECT {} WHERE 1 = 0", nulls));
+    }
+    let mut selects = Vec::with_capacity(rows.len());
+    for row in rows {
+        let row = row
+            .get_list()
+            .ok_or_else(|| NotTranspilable("malformed constant rule".to_string(), span))?;
+        let cols = row
+            .iter()
+            .enumerate()
+            .map(|(i, v)| -> Result<String> {
+                Ok(format!("{} AS c{}", dialect.literal(v, span)?, i))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        selects.push(format!("SELECT {}", cols.join(", ")));
+    }
+    Ok(selects.join(" UNION "))
+}
+
+fn expr_to_sql(
+    expr: &Expr,
+    bindings: &BTreeMap<Symbol, String>,
+    dialect: SqlDialect,
+) -> Result<String> {
+    Ok(match expr {
+        Expr::Binding { var, .. } => bindings
+            .get(var)
+            .ok_or_else(|| NotTranspilable(format!("symbol {} is unbound", var), var.span))?
+            .clone(),
+        Expr::Const { val, span } => dialect.literal(val, *span)?,
+        Expr::Cond { clauses, .. } => {
+            let mut ret = "CASE".to_string();
+            for (cond, val) in clauses {
+                write!(
+                    ret,
+                    " WHEN {} THEN {}",
+                    expr_to_sql(cond, bindings, dialect)?,
+                    expr_to_sql(val, bindings, dialect)?
+                )
+                .unwrap();
+            }
+            ret.push_str(" END");
+            ret
+        }
+        Expr::Try { span, .. } => {
+            bail!(NotTranspilable("'try' expressions".to_string(), *span))
+        }
+        Expr::Apply { op, args, span } => {
+            if op.name == "OP_IS_IN" {
+                let candidates = match &args[1] {
+                    Expr::Const {
+                        val: DataValue::List(l),
+                        span,
+                    } => l
+                        .iter()
+                        .map(|v| dialect.literal(v, *span))
+                        .collect::<Result<Vec<_>>>()?,
+                    _ => bail!(NotTranspilable(
+                        "'is_in' with a non-constant list".to_string(),
+                        *span
+                    )),
+                };
+                if candidates.is_empty() {
+                    return dialect.literal(&DataValue::Bool(false), *span);
+                }
+                let needle = expr_to_sql(&args[0], bindings, dialect)?;
+                return Ok(format!("({} IN ({}))", needle, candidates.join(", ")));
+            }
+            let args_sql = args
+                .iter()
+                .map(|arg| expr_to_sql(arg, bindings, dialect))
+                .collect::<Result<Vec<_>>>()?;
+            match (op.name, dialect) {
+                // division in Cozo always gives a float, whereas Postgres and SQLite
+                // truncate the quotient of integers
+                ("OP_DIV", SqlDialect::Postgres) => {
+                    return Ok(format!(
+                        "(CAST({} AS DOUBLE PRECISION) / {})",
+                        args_sql[0], args_sql[1]
+                    ))
+                }
+                ("OP_DIV", SqlDialect::Sqlite) => {
+                    return Ok(format!("(CAST({} AS REAL) / {})", args_sql[0], args_sql[1]))
+                }
+                // the remainder takes the sign of the dividend and is fractional for
+                // floats, which Postgres' '%' rejects and SQLite's truncates to integers
+                ("OP_MOD", SqlDialect::Postgres) => {
+                    return Ok(format!(
+                        "({a} - {b} * TRUNC({a} / {b}))",
+                        a = args_sql[0],
+                        b = args_sql[1]
+                    ))
+                }
+                ("OP_MOD", SqlDialect::Sqlite) => {
+                    return Ok(format!(
+                        "({a} - {b} * CAST({a} / {b} AS INTEGER))",
+                        a = args_sql[0],
+                        b = args_sql[1]
+                    ))
+                }
+                _ => {}
+            }
+            let infix = match op.name {
+                "OP_ADD" => Some("+"),
+                "OP_SUB" => Some("-"),
+                "OP_MUL" => Some("*"),
+                // MySQL does not truncate the quotient of integers
+                "OP_DIV" => Some("/"),
+                "OP_MOD" => Some("%"),
+                "OP_EQ" => Some("="),
+                "OP_NEQ" => Some("<>"),
+                "OP_GT" => Some(">"),
+                "OP_GE" => Some(">="),
+                "OP_LT" => Some("<"),
+                "OP_LE" => Some("<="),
+                "OP_AND" => Some("AND"),
+                "OP_OR" => Some("OR"),
+                _ => None,
+            };
+            if let Some(infix) = infix {
+                return Ok(format!("({})", args_sql.join(&format!(" {} ", infix))));
+            }
+            let func = match (op.name, dialect) {
+                ("OP_NEGATE", _) => return Ok(format!("(NOT {})", args_sql[0])),
+                ("OP_MINUS", _) => return Ok(format!("(-{})", args_sql[0])),
+                ("OP_IS_NULL", _) => return Ok(format!("({} IS NULL)", args_sql[0])),
+                ("OP_CONCAT", SqlDialect::MySql) => "CONCAT",
+                ("OP_CONCAT", _) => return Ok(format!("({})", args_sql.join(" || "))),
+                ("OP_MAX", SqlDialect::Sqlite) => "MAX",
+                ("OP_MAX", _) => "GREATEST",
+                ("OP_MIN", SqlDialect::Sqlite) => "MIN",
+                ("OP_MIN", _) => "LEAST",
+                ("OP_LENGTH", SqlDialect::MySql) => "CHAR_LENGTH",
+                ("OP_LENGTH", _) => "LENGTH",
+                ("OP_ABS", _) => "ABS",
+                ("OP_FLOOR", _) => "FLOOR",
+                ("OP_CEIL", _) => "CEIL",
+                ("OP_ROUND", _) => "ROUND",
+                ("OP_POW", _) => "POWER",
+                ("OP_EXP", _) => "EXP",
+                ("OP_LN", _) => "LN",
+                ("OP_LOWERCASE", _) => "LOWER",
+                ("OP_UPPERCASE", _) => "UPPER",
+                ("OP_TRIM", _) => "TRIM",
+                (name, _) => bail!(NotTranspilable(
+                    format!(
+                        "function {}",
+                        name.strip_prefix("OP_").unwrap().to_ascii_lowercase()
+                    ),
+                    *span
+                )),
+            };
+            format!("{}({})", func, args_sql.join(", "))
+        }
+    })
+}
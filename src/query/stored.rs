@@ -3,30 +3,30 @@
  */
 
 use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
 
 use itertools::Itertools;
-use miette::{bail, Diagnostic, Result, WrapErr};
+use miette::{bail, ensure, Diagnostic, Result, WrapErr};
 use smartstring::SmartString;
 use thiserror::Error;
 
 use crate::algo::constant::Constant;
 use crate::algo::AlgoHandle;
 use crate::data::expr::Expr;
-use crate::data::program::{AlgoApply, InputInlineRulesOrAlgo, InputProgram, RelationOp};
-use crate::data::relation::{ColumnDef, NullableColType};
-use crate::data::symb::Symbol;
+use crate::data::program::{
+    AlgoApply, InputAtom, InputInlineRulesOrAlgo, InputProgram, RelationOp,
+};
+use crate::data::relation::{ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
 use crate::data::value::DataValue;
-use crate::parse::parse_script;
-use crate::runtime::relation::{AccessLevel, InputRelationHandle, InsufficientAccessLevel};
+use crate::parse::{parse_script, SourceSpan};
+use crate::runtime::relation::{
+    current_validity, AccessLevel, InputRelationHandle, InsufficientAccessLevel,
+};
 use crate::runtime::transact::SessionTx;
 use crate::Db;
 
-#[derive(Debug, Error, Diagnostic)]
-#[error("attempting to write into relation {0} of arity {1} with data of arity {2}")]
-#[diagnostic(code(eval::relation_arity_mismatch))]
-struct RelationArityMismatch(String, usize, usize);
-
 impl SessionTx {
     pub(crate) fn execute_relation<'a>(
         &'a mut self,
@@ -38,6 +38,8 @@ impl SessionTx {
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
+        let mut replaced_maintained = vec![];
+        let mut carried = None;
         if op == RelationOp::Replace {
             if let Ok(old_handle) = self.get_relation(&meta.name, true) {
                 if old_handle.access_level < AccessLevel::Normal {
@@ -47,14 +49,25 @@ impl SessionTx {
                         old_handle.access_level
                     ));
                 }
+                if !old_handle.carried_on_replace().is_empty() {
+                    self.retire_replaced_rows(&old_handle, &meta.metadata, meta.span)?;
+                    carried = Some((
+                        old_handle.history,
+                        old_handle.lww,
+                        old_handle.soft_deleted,
+                        old_handle.vector_index.clone(),
+                    ));
+                }
                 if old_handle.has_triggers() {
                     replaced_old_triggers = Some((old_handle.put_triggers, old_handle.rm_triggers))
                 }
+                replaced_maintained = old_handle.maintained;
                 for trigger in &old_handle.replace_triggers {
                     let program =
-                        parse_script(trigger, &Default::default())?.get_single_program()?;
+                        parse_script(trigger, &Default::default(), &self.services.custom_algos)?
+                            .get_single_program()?;
 
-                    let (_, cleanups) = db.run_query(self, program).map_err(|err| {
+                    let (_, cleanups) = db.run_query(self, program, None).map_err(|err| {
                         if err.source_code().is_some() {
                             err
                         } else {
@@ -65,7 +78,21 @@ impl SessionTx {
                 }
             }
             if let Ok(c) = self.destroy_relation(&meta.name) {
-                to_clear.push(c);
+                // the key ranges of what is carried over are kept
+                let kept = carried
+                    .iter()
+                    .flat_map(|(history, lww, soft_deleted, index)| {
+                        [
+                            *history,
+                            *lww,
+                            *soft_deleted,
+                            index.as_ref().map(|idx| idx.id),
+                        ]
+                    })
+                    .flatten()
+                    .map(|id| Tuple::default().encode_as_key(id))
+                    .collect_vec();
+                to_clear.extend(c.into_iter().filter(|(lower, _)| !kept.contains(lower)));
             }
         }
         let mut relation_store = if op == RelationOp::Replace || op == RelationOp::Create {
@@ -73,10 +100,23 @@ impl SessionTx {
         } else {
             self.get_relation(&meta.name, true)?
         };
+        if let Some((history, lww, soft_deleted, vector_index)) = carried {
+            relation_store.history = history;
+            relation_store.lww = lww;
+            relation_store.soft_deleted = soft_deleted;
+            relation_store.vector_index = vector_index;
+            self.put_relation_handle(&relation_store)?;
+        }
         if let Some((old_put, old_retract)) = replaced_old_triggers {
             relation_store.put_triggers = old_put;
             relation_store.rm_triggers = old_retract;
         }
+        for name in &replaced_maintained {
+            self.set_maintained_by(&relation_store.name, name, true)?;
+        }
+        if !replaced_maintained.is_empty() {
+            relation_store.maintained = replaced_maintained;
+        }
         let InputRelationHandle {
             metadata,
             key_bindings,
@@ -102,8 +142,12 @@ impl SessionTx {
                 )?;
 
                 let has_triggers = !relation_store.rm_triggers.is_empty();
+                let is_maintained = !relation_store.maintained.is_empty();
+                let is_captured = self.services.change_feed.captures(&relation_store.name);
+                let mut n_written = 0;
                 let mut new_tuples: Vec<DataValue> = vec![];
                 let mut old_tuples: Vec<DataValue> = vec![];
+                let at = current_validity();
 
                 for tuple in res_iter {
                     let tuple = tuple?;
@@ -113,8 +157,9 @@ impl SessionTx {
                             .map(|ex| ex.extract_data(&tuple))
                             .try_collect()?,
                     );
+                    relation_store.ensure_hot(&extracted.0, *span)?;
                     let key = relation_store.adhoc_encode_key(&extracted, *span)?;
-                    if has_triggers {
+                    if has_triggers || is_maintained {
                         if let Some(existing) = self.tx.get(&key, false)? {
                             let mut tup = extracted.clone();
                             if !existing.is_empty() {
@@ -129,13 +174,38 @@ impl SessionTx {
                         }
                         new_tuples.push(DataValue::List(extracted.0.clone()));
                     }
+                    if let Some(index) = &relation_store.vector_index {
+                        self.hnsw_remove(&relation_store, index, &extracted.0)?;
+                    }
+                    self.soft_delete_row(&relation_store, &extracted.0, &key)?;
                     self.tx.del(&key)?;
+                    if is_captured {
+                        self.script.changes.push((
+                            relation_store.name.clone(),
+                            false,
+                            extracted.clone(),
+                        ));
+                    }
+                    self.record_history(&relation_store, &extracted, false)?;
+                    self.record_lww(&relation_store, &extracted.0, true, at)?;
+                    self.services
+                        .metrics
+                        .rows_written
+                        .fetch_add(1, Ordering::Relaxed);
+                    n_written += 1;
                 }
+                self.script
+                    .writes
+                    .push((Some(relation_store.name.clone()), Some(n_written)));
 
                 if has_triggers && !new_tuples.is_empty() {
                     for trigger in &relation_store.rm_triggers {
-                        let mut program =
-                            parse_script(trigger, &Default::default())?.get_single_program()?;
+                        let mut program = parse_script(
+                            trigger,
+                            &Default::default(),
+                            &self.services.custom_algos,
+                        )?
+                        .get_single_program()?;
 
                         let mut bindings = relation_store
                             .metadata
@@ -155,7 +225,7 @@ impl SessionTx {
 
                         make_const_rule(&mut program, "_old", bindings, old_tuples.clone());
 
-                        let (_, cleanups) = db.run_query(self, program).map_err(|err| {
+                        let (_, cleanups) = db.run_query(self, program, None).map_err(|err| {
                             if err.source_code().is_some() {
                                 err
                             } else {
@@ -165,6 +235,13 @@ impl SessionTx {
                         to_clear.extend(cleanups);
                     }
                 }
+                if is_maintained {
+                    to_clear.extend(self.update_maintained(
+                        db,
+                        &relation_store,
+                        Some(&old_tuples),
+                    )?);
+                }
             }
             RelationOp::Ensure => {
                 if relation_store.access_level < AccessLevel::ReadOnly {
@@ -200,6 +277,7 @@ impl SessionTx {
                             .try_collect()?,
                     );
 
+                    relation_store.ensure_hot(&extracted.0, *span)?;
                     let key = relation_store.adhoc_encode_key(&extracted, *span)?;
                     let val = relation_store.adhoc_encode_val(&extracted, *span)?;
 
@@ -249,6 +327,7 @@ impl SessionTx {
                             .map(|ex| ex.extract_data(&tuple))
                             .try_collect()?,
                     );
+                    relation_store.ensure_hot(&extracted.0, *span)?;
                     let key = relation_store.adhoc_encode_key(&extracted, *span)?;
                     let existing = self.tx.get(&key, true)?;
                     if existing.is_some() {
@@ -277,6 +356,9 @@ impl SessionTx {
                 )?;
 
                 let has_triggers = !relation_store.put_triggers.is_empty();
+                let is_maintained = !relation_store.maintained.is_empty();
+                let is_captured = self.services.change_feed.captures(&relation_store.name);
+                let mut n_written = 0;
                 let mut new_tuples: Vec<DataValue> = vec![];
                 let mut old_tuples: Vec<DataValue> = vec![];
 
@@ -287,6 +369,7 @@ impl SessionTx {
                     headers,
                 )?;
                 key_extractors.extend(val_extractors);
+                let at = current_validity();
 
                 for tuple in res_iter {
                     let tuple = tuple?;
@@ -298,10 +381,13 @@ impl SessionTx {
                             .try_collect()?,
                     );
 
+                    relation_store.ensure_hot(&extracted.0, *span)?;
                     let key = relation_store.adhoc_encode_key(&extracted, *span)?;
                     let val = relation_store.adhoc_encode_val(&extracted, *span)?;
+                    self.record_history(&relation_store, &extracted, true)?;
+                    self.record_lww(&relation_store, &extracted.0, false, at)?;
 
-                    if has_triggers {
+                    if has_triggers || is_maintained {
                         if let Some(existing) = self.tx.get(&key, false)? {
                             let mut tup = extracted.clone();
                             let mut remaining = &existing[ENCODED_KEY_MIN_LEN..];
@@ -313,16 +399,39 @@ impl SessionTx {
                             old_tuples.push(DataValue::List(tup.0));
                         }
 
-                        new_tuples.push(DataValue::List(extracted.0));
+                        new_tuples.push(DataValue::List(extracted.0.clone()));
                     }
 
                     self.tx.put(&key, &val)?;
+                    self.revive_row(&relation_store, &extracted.0)?;
+                    if is_captured {
+                        self.script.changes.push((
+                            relation_store.name.clone(),
+                            true,
+                            extracted.clone(),
+                        ));
+                    }
+                    if let Some(index) = &relation_store.vector_index {
+                        self.hnsw_put(&relation_store, index, &extracted)?;
+                    }
+                    self.services
+                        .metrics
+                        .rows_written
+                        .fetch_add(1, Ordering::Relaxed);
+                    n_written += 1;
                 }
+                self.script
+                    .writes
+                    .push((Some(relation_store.name.clone()), Some(n_written)));
 
                 if has_triggers && !new_tuples.is_empty() {
                     for trigger in &relation_store.put_triggers {
-                        let mut program =
-                            parse_script(trigger, &Default::default())?.get_single_program()?;
+                        let mut program = parse_script(
+                            trigger,
+                            &Default::default(),
+                            &self.services.custom_algos,
+                        )?
+                        .get_single_program()?;
 
                         let mut bindings = relation_store
                             .metadata
@@ -340,7 +449,7 @@ impl SessionTx {
                         make_const_rule(&mut program, "_new", bindings.clone(), new_tuples.clone());
                         make_const_rule(&mut program, "_old", bindings, old_tuples.clone());
 
-                        let (_, cleanups) = db.run_query(self, program).map_err(|err| {
+                        let (_, cleanups) = db.run_query(self, program, None).map_err(|err| {
                             if err.source_code().is_some() {
                                 err
                             } else {
@@ -350,13 +459,203 @@ impl SessionTx {
                         to_clear.extend(cleanups);
                     }
                 }
+                if is_maintained {
+                    // a replaced relation may have lost any of its rows, so all groups are redone
+                    let changed =
+                        (op != RelationOp::Replace).then(|| [new_tuples, old_tuples].concat());
+                    to_clear.extend(self.update_maintained(
+                        db,
+                        &relation_store,
+                        changed.as_deref(),
+                    )?);
+                }
             }
         };
+        self.check_namespace_storage(&relation_store.name)?;
 
         Ok(to_clear)
     }
+
+    /// Checks the entry rule against the columns of the relation it is stored into before
+    /// anything is evaluated: every column must find its binding in the head or have a
+    /// default, and head variables whose types are known, from constants or from columns
+    /// of stored relations, must be accepted by the types of their columns.
+    pub(crate) fn check_store_head(
+        &self,
+        program: &InputProgram,
+        meta: &InputRelationHandle,
+        op: RelationOp,
+        target: &StoredRelationMetadata,
+    ) -> Result<()> {
+        let head = program.get_entry_out_head_or_default()?;
+        let mut bound = meta
+            .metadata
+            .keys
+            .iter()
+            .zip(meta.key_bindings.iter())
+            .map(|(col, binding)| (col, binding, &target.keys))
+            .collect_vec();
+        if !matches!(op, RelationOp::Rm | RelationOp::EnsureNot) {
+            bound.extend(
+                meta.metadata
+                    .non_keys
+                    .iter()
+                    .zip(meta.dep_bindings.iter())
+                    .map(|(col, binding)| (col, binding, &target.non_keys)),
+            );
+        }
+        let mut targets: BTreeMap<usize, &ColumnDef> = BTreeMap::new();
+        for (col, binding, target_cols) in bound {
+            let target_col = match target_cols.iter().find(|c| c.name == col.name) {
+                Some(c) => c,
+                // reported by the compatibility check
+                None => continue,
+            };
+            match head.iter().position(|h| h == binding) {
+                Some(pos) => {
+                    targets.insert(pos, target_col);
+                }
+                None => ensure!(
+                    target_col.default_gen.is_some(),
+                    StoreBindingNotInHead(
+                        binding.to_string(),
+                        col.name.to_string(),
+                        meta.name.to_string(),
+                        binding.span
+                    )
+                ),
+            }
+        }
+
+        let mismatch = |pos: usize, col: &ColumnDef, reason: String| {
+            StoreHeadTypeMismatch(
+                head[pos].to_string(),
+                col.name.to_string(),
+                col.typing.clone(),
+                reason,
+                head[pos].span,
+            )
+        };
+        match program.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
+            Some(InputInlineRulesOrAlgo::Algo { algo }) => {
+                if algo.algo.name.name.as_str() != "Constant" {
+                    return Ok(());
+                }
+                let rows = algo
+                    .options
+                    .get("data")
+                    .and_then(|data| data.get_const())
+                    .and_then(|data| data.get_list())
+                    .unwrap_or_default();
+                for row in rows.iter().filter_map(|row| row.get_list()) {
+                    for (pos, col) in &targets {
+                        if let Some(val) = row.get(*pos) {
+                            if col.typing.coerce(val.clone()).is_err() {
+                                bail!(mismatch(
+                                    *pos,
+                                    col,
+                                    format!("it is given the value {:?}", val)
+                                ))
+                            }
+                        }
+                    }
+                }
+            }
+            Some(InputInlineRulesOrAlgo::Rules { rules }) => {
+                for rule in rules {
+                    for (pos, col) in &targets {
+                        let var = match (rule.head.get(*pos), rule.aggr.get(*pos)) {
+                            (Some(var), Some(None)) => var,
+                            _ => continue,
+                        };
+                        for atom in &rule.body {
+                            if let Some(reason) = self.incompatible_binding(atom, var, col)? {
+                                bail!(mismatch(*pos, col, reason))
+                            }
+                        }
+                    }
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Describes how the atom binds `var` to values that `col` cannot accept, if it does.
+    fn incompatible_binding(
+        &self,
+        atom: &InputAtom,
+        var: &Symbol,
+        col: &ColumnDef,
+    ) -> Result<Option<String>> {
+        let source_cols = match atom {
+            InputAtom::Unification { inner } if inner.binding == *var && !inner.one_many_unif => {
+                return Ok(match inner.expr.clone().eval_to_const() {
+                    Ok(val) if col.typing.coerce(val.clone()).is_err() => {
+                        Some(format!("it is bound to the value {:?}", val))
+                    }
+                    _ => None,
+                });
+            }
+            InputAtom::Relation { inner } => match self.get_relation(&inner.name, false) {
+                Ok(source) => {
+                    let cols = source
+                        .metadata
+                        .keys
+                        .iter()
+                        .chain(source.metadata.non_keys.iter());
+                    cols.zip(inner.args.iter())
+                        .filter(|(_, arg)| matches!(arg, Expr::Binding { var: v, .. } if v == var))
+                        .map(|(c, _)| (c.clone(), inner.name.to_string()))
+                        .collect_vec()
+                }
+                Err(_) => return Ok(None),
+            },
+            InputAtom::NamedFieldRelation { inner } => {
+                match self.get_relation(&inner.name, false) {
+                    Ok(source) => {
+                        let cols = source
+                            .metadata
+                            .keys
+                            .iter()
+                            .chain(source.metadata.non_keys.iter());
+                        cols.filter(|c| {
+                            matches!(
+                                inner.args.get(&c.name),
+                                Some(Expr::Binding { var: v, .. }) if v == var
+                            )
+                        })
+                        .map(|c| (c.clone(), inner.name.to_string()))
+                        .collect_vec()
+                    }
+                    Err(_) => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(source_cols
+            .into_iter()
+            .find(|(source_col, _)| !col.typing.coltype.accepts(&source_col.typing.coltype))
+            .map(|(source_col, rel)| {
+                format!(
+                    "it is bound to column '{}' of '{}' of type {}",
+                    source_col.name, rel, source_col.typing
+                )
+            }))
+    }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Binding '{0}' for column '{1}' of relation '{2}' is not found in the rule head")]
+#[diagnostic(code(eval::store_binding_not_in_head))]
+#[diagnostic(help("Columns without a default must be bound to a variable of the rule head"))]
+struct StoreBindingNotInHead(String, String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Head variable '{0}' is stored into column '{1}' of type {2}, but {3}")]
+#[diagnostic(code(eval::store_head_type_mismatch))]
+struct StoreHeadTypeMismatch(String, String, NullableColType, String, #[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Assertion failure for {key:?} of {relation}: {notice}")]
 struct TransactAssertionFailure {
@@ -424,7 +723,7 @@ fn make_extractor(
     }
 }
 
-fn make_const_rule(
+pub(crate) fn make_const_rule(
     program: &mut InputProgram,
     rule_name: &str,
     bindings: Vec<Symbol>,
@@ -444,7 +743,10 @@ fn make_const_rule(
         rule_symbol.clone(),
         InputInlineRulesOrAlgo::Algo {
             algo: AlgoApply {
-                algo: AlgoHandle { name: rule_symbol },
+                algo: AlgoHandle {
+                    name: rule_symbol,
+                    custom: None,
+                },
                 rule_args: vec![],
                 options,
                 head: bindings,
@@ -141,7 +141,7 @@ fn convert_normal_form_program_to_graph(
 
 fn reduce_to_graph<'a>(g: &StratifiedGraph<&'a Symbol>) -> Graph<&'a Symbol> {
     g.iter()
-        .map(|(k, s)| (*k, s.iter().map(|(sk, _)| *sk).collect_vec()))
+        .map(|(k, s)| (*k, s.keys().copied().collect_vec()))
         .collect()
 }
 
@@ -174,8 +174,8 @@ fn verify_no_cycle(g: &StratifiedGraph<&'_ Symbol>, sccs: &[BTreeSet<&Symbol>])
     Ok(())
 }
 
-fn make_scc_reduced_graph<'a>(
-    sccs: &[BTreeSet<&'a Symbol>],
+fn make_scc_reduced_graph(
+    sccs: &[BTreeSet<&Symbol>],
     graph: &StratifiedGraph<&Symbol>,
 ) -> (BTreeMap<Symbol, usize>, StratifiedGraph<usize>) {
     let indices = sccs
@@ -0,0 +1,41 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Authorization of queries by the stored relations they read and write, decided by a
+//! policy the embedding application sets with
+//! [`DbOptions::access_policy`](crate::DbOptions::access_policy).
+
+use std::collections::BTreeSet;
+use std::fmt::{Debug, Formatter};
+
+use miette::Result;
+
+/// The stored relations a query or system op is about to read and write.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RelationAccess {
+    /// The label the script was run with, as given to
+    /// [`Db::run_script_labelled`](crate::Db::run_script_labelled)
+    pub label: Option<String>,
+    /// The relations read
+    pub read: BTreeSet<String>,
+    /// The relations created, written, removed or otherwise changed
+    pub written: BTreeSet<String>,
+}
+
+/// Decides which stored relations queries may read and write, for example by the tenant or
+/// role the label of the script stands for.
+///
+/// The policy is asked before each query runs, including those run by triggers, and before
+/// each system op on relations. A denied query fails the transaction it is part of, so none
+/// of the writes made within the transaction are committed.
+pub trait AccessPolicy: Send + Sync {
+    /// Returns an error, which the query then fails with, to deny the access.
+    fn check(&self, access: &RelationAccess) -> Result<()>;
+}
+
+impl Debug for dyn AccessPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "AccessPolicy")
+    }
+}
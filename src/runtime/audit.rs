@@ -0,0 +1,113 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! The audit log: a read-only stored relation with an entry for every change made to stored
+//! relations by scripts and system ops, written in the transaction making the change.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use miette::{ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::relation::{current_validity, AccessLevel, InputRelationHandle};
+use crate::runtime::transact::SessionTx;
+
+/// The stored relation holding the audit log.
+pub(crate) const AUDIT_LOG: &str = "audit_log";
+
+/// A change to be recorded: the relation affected, if known, and the number of rows put
+/// into or removed from it, if the change was made row by row.
+pub(crate) type AuditEntry = (Option<SmartString<LazyCompact>>, Option<usize>);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot keep the audit log in '{0}', as a relation of that name with other columns exists")]
+#[diagnostic(code(db::audit_log_conflict))]
+#[diagnostic(help("Rename the existing relation, or open the database with auditing off"))]
+struct AuditLogConflict(&'static str);
+
+fn audit_log_metadata() -> StoredRelationMetadata {
+    let col = |name: &str, coltype: ColType, nullable: bool| ColumnDef {
+        name: SmartString::from(name),
+        typing: NullableColType { coltype, nullable },
+        default_gen: None,
+    };
+    StoredRelationMetadata {
+        keys: vec![
+            col("at", ColType::Float, false),
+            col("seq", ColType::Int, false),
+        ],
+        non_keys: vec![
+            col("session", ColType::String, true),
+            col("command", ColType::String, false),
+            col("relation", ColType::String, true),
+            col("rows", ColType::Int, true),
+        ],
+    }
+}
+
+impl SessionTx {
+    /// Creates the audit log if it does not exist yet, read-only so that it can only be
+    /// appended to by the database itself.
+    pub(crate) fn ensure_audit_log(&mut self) -> Result<()> {
+        let metadata = audit_log_metadata();
+        if self.relation_exists(AUDIT_LOG)? {
+            let handle = self.get_relation(AUDIT_LOG, false)?;
+            ensure!(handle.metadata == metadata, AuditLogConflict(AUDIT_LOG));
+            return Ok(());
+        }
+        let name = Symbol::new(AUDIT_LOG, Default::default());
+        self.create_relation(InputRelationHandle {
+            name: name.clone(),
+            metadata,
+            key_bindings: vec![],
+            dep_bindings: vec![],
+            span: Default::default(),
+        })?;
+        self.set_access_level(name, AccessLevel::ReadOnly)
+    }
+    /// Appends an entry for each change made by `command`, and discards the entries that
+    /// have been kept longer than `retention`.
+    pub(crate) fn append_audit_entries(
+        &mut self,
+        session: Option<&str>,
+        command: &str,
+        entries: Vec<AuditEntry>,
+        seq: &AtomicU64,
+        retention: Option<Duration>,
+    ) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let handle = self.get_relation(AUDIT_LOG, false)?;
+        let at = current_validity();
+        for (relation, rows) in entries {
+            let entry = Tuple(vec![
+                DataValue::from(at),
+                DataValue::from(seq.fetch_add(1, Ordering::Relaxed) as i64),
+                session.map_or(DataValue::Null, |s| DataValue::Str(SmartString::from(s))),
+                DataValue::Str(SmartString::from(command)),
+                relation.map_or(DataValue::Null, DataValue::Str),
+                rows.map_or(DataValue::Null, |n| DataValue::from(n as i64)),
+            ]);
+            let key = handle.adhoc_encode_key(&entry, Default::default())?;
+            let val = handle.adhoc_encode_val(&entry, Default::default())?;
+            self.tx.put(&key, &val)?;
+        }
+        if let Some(retention) = retention {
+            let cutoff = [DataValue::from(at - retention.as_secs_f64())];
+            let expired: Vec<_> = handle.scan_key_range(self, None, Some(&cutoff)).collect();
+            for entry in expired {
+                let key = handle.adhoc_encode_key(&entry?, Default::default())?;
+                self.tx.del(&key)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! The archive format of [`crate::Db::backup`] and [`crate::Db::restore`]: the raw key-value
+//! pairs of the storage engines, prefixed by a magic number and followed by an end marker, so
+//! that truncated archives are detected. Each pair is a tag telling which storage it comes
+//! from, then the key and the value, each preceded by its length as a big-endian `u32`.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+const BACKUP_MAGIC: &[u8; 8] = b"COZOBAK\x01";
+const TAG_END: u8 = 0xff;
+
+/// The storage engine a pair of the archive belongs to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum BackupStore {
+    Main = 0,
+    /// The cold storage of tiered relations
+    Cold = 1,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("'{0}' is not a complete backup archive")]
+#[diagnostic(code(db::bad_backup))]
+#[diagnostic(help("The archive is either corrupted or was not written by `Db::backup`"))]
+pub(crate) struct BadBackupArchive(pub(crate) String);
+
+/// Writes an archive to a temporary file next to its path, which is only renamed into place
+/// once finished, so that a failed backup never leaves an incomplete archive behind.
+pub(crate) struct BackupWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    out: BufWriter<File>,
+}
+
+impl BackupWriter {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut out = BufWriter::new(File::create(&tmp_path).into_diagnostic()?);
+        out.write_all(BACKUP_MAGIC).into_diagnostic()?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp_path,
+            out,
+        })
+    }
+    pub(crate) fn write(&mut self, store: BackupStore, key: &[u8], val: &[u8]) -> Result<()> {
+        self.out.write_all(&[store as u8]).into_diagnostic()?;
+        for bytes in [key, val] {
+            self.out
+                .write_all(&(bytes.len() as u32).to_be_bytes())
+                .into_diagnostic()?;
+            self.out.write_all(bytes).into_diagnostic()?;
+        }
+        Ok(())
+    }
+    pub(crate) fn finish(mut self) -> Result<()> {
+        self.out.write_all(&[TAG_END]).into_diagnostic()?;
+        let file = self.out.into_inner().map_err(|err| err.into_error());
+        file.into_diagnostic()?.sync_all().into_diagnostic()?;
+        fs::rename(&self.tmp_path, &self.path).into_diagnostic()?;
+        Ok(())
+    }
+}
+
+/// Reads the pairs of an archive in the order they were written. Reading fails on the first
+/// malformed or missing part, including a missing end marker.
+pub(crate) struct BackupReader {
+    name: String,
+    input: BufReader<File>,
+    done: bool,
+}
+
+impl BackupReader {
+    pub(crate) fn open(path: &Path) -> Result<Self> {
+        let mut reader = Self {
+            name: path.display().to_string(),
+            input: BufReader::new(File::open(path).into_diagnostic()?),
+            done: false,
+        };
+        let mut magic = [0; BACKUP_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != *BACKUP_MAGIC {
+            bail!(BadBackupArchive(reader.name))
+        }
+        Ok(reader)
+    }
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        match self.input.read_exact(buf) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+                bail!(BadBackupArchive(self.name.clone()))
+            }
+            Err(err) => Err(err).into_diagnostic(),
+        }
+    }
+    fn read_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.read_exact(&mut len)?;
+        let mut bytes = vec![0; u32::from_be_bytes(len) as usize];
+        self.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+    fn read_pair(&mut self) -> Result<Option<(BackupStore, Vec<u8>, Vec<u8>)>> {
+        let mut tag = [0];
+        self.read_exact(&mut tag)?;
+        let store = match tag[0] {
+            TAG_END => return Ok(None),
+            0 => BackupStore::Main,
+            1 => BackupStore::Cold,
+            _ => bail!(BadBackupArchive(self.name.clone())),
+        };
+        let key = self.read_bytes()?;
+        let val = self.read_bytes()?;
+        Ok(Some((store, key, val)))
+    }
+}
+
+impl Iterator for BackupReader {
+    type Item = Result<(BackupStore, Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.read_pair();
+        self.done = !matches!(res, Ok(Some(_)));
+        res.transpose()
+    }
+}
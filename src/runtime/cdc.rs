@@ -0,0 +1,201 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Change-data capture: the rows put into and removed from stored relations are captured as
+//! they are written or deleted, and delivered to the subscribers of the relations once the transaction
+//! writing them is committed. The changes of the most recent transactions can be kept, so
+//! that a subscriber catching up after a disconnection misses none of them.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+use itertools::Itertools;
+use miette::{ensure, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::tuple::Tuple;
+use crate::runtime::relation::RelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::Db;
+
+/// A row put into or removed from a stored relation.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RowChange {
+    /// The row put, replacing any row with the same keys.
+    Put(Vec<JsonValue>),
+    /// The keys of the row removed.
+    Removed(Vec<JsonValue>),
+}
+
+/// The changes made to a stored relation by a committed transaction, delivered to the
+/// subscribers of [`Db::subscribe`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelationChanges {
+    /// The ID of the transaction, increasing with each transaction whose changes are captured.
+    pub tx_id: u64,
+    /// The name of the relation.
+    pub relation: String,
+    /// The rows changed, in the order the changes were made.
+    pub rows: Vec<RowChange>,
+}
+
+/// A change captured within a transaction: the relation changed, whether the row was put
+/// rather than removed, and the row, or its keys if removed.
+pub(crate) type CapturedChange = (SmartString<LazyCompact>, bool, Tuple);
+
+#[derive(Default)]
+struct FeedState {
+    last_tx_id: u64,
+    /// The ID of the last transaction whose changes are no longer kept
+    evicted_through: u64,
+    buffer: VecDeque<(u64, Vec<RelationChanges>)>,
+    subscribers: Vec<(SmartString<LazyCompact>, Sender<RelationChanges>)>,
+}
+
+/// The subscribers to changes of stored relations, and the changes of recent transactions.
+pub(crate) struct ChangeFeed {
+    /// The number of recent transactions whose changes are kept
+    buffer_size: usize,
+    state: Mutex<FeedState>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The changes made after transaction {0} are no longer kept")]
+#[diagnostic(code(db::changes_not_kept))]
+#[diagnostic(help(
+    "Copy the relation anew before subscribing, or keep the changes of more transactions \
+    with the `change_buffer_size` option"
+))]
+struct ChangesNotKept(u64);
+
+impl ChangeFeed {
+    pub(crate) fn new(buffer_size: usize) -> Self {
+        Self {
+            buffer_size,
+            state: Default::default(),
+        }
+    }
+    /// Whether the changes of `relation` are to be captured.
+    pub(crate) fn captures(&self, relation: &str) -> bool {
+        self.buffer_size > 0
+            || self
+                .state
+                .lock()
+                .unwrap()
+                .subscribers
+                .iter()
+                .any(|(rel, _)| rel == relation)
+    }
+    /// Delivers the changes captured within a transaction that has been committed.
+    pub(crate) fn publish(&self, changes: Vec<CapturedChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.last_tx_id += 1;
+        let tx_id = state.last_tx_id;
+        let by_relation = changes
+            .into_iter()
+            .into_group_map_by(|(relation, _, _)| relation.clone());
+        let changes = by_relation
+            .into_iter()
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(relation, rows)| RelationChanges {
+                tx_id,
+                relation: relation.to_string(),
+                rows: rows
+                    .into_iter()
+                    .map(|(_, is_put, row)| {
+                        let row = row.0.into_iter().map(JsonValue::from).collect();
+                        if is_put {
+                            RowChange::Put(row)
+                        } else {
+                            RowChange::Removed(row)
+                        }
+                    })
+                    .collect(),
+            })
+            .collect_vec();
+        // subscribers whose receivers are dropped are unsubscribed
+        state.subscribers.retain(|(relation, sender)| {
+            changes
+                .iter()
+                .filter(|c| c.relation == *relation)
+                .all(|c| sender.send(c.clone()).is_ok())
+        });
+        if self.buffer_size > 0 {
+            state.buffer.push_back((tx_id, changes));
+            while state.buffer.len() > self.buffer_size {
+                let (evicted, _) = state.buffer.pop_front().unwrap();
+                state.evicted_through = evicted;
+            }
+        }
+    }
+    fn subscribe(&self, relation: &str, since: Option<u64>) -> Result<Receiver<RelationChanges>> {
+        let (sender, receiver) = channel();
+        let mut state = self.state.lock().unwrap();
+        if let Some(since) = since {
+            ensure!(since >= state.evicted_through, ChangesNotKept(since));
+            for (_, changes) in state.buffer.iter().filter(|(id, _)| *id > since) {
+                for c in changes.iter().filter(|c| c.relation == relation) {
+                    // the receiver is still held here
+                    sender.send(c.clone()).unwrap();
+                }
+            }
+        }
+        state
+            .subscribers
+            .push((SmartString::from(relation), sender));
+        Ok(receiver)
+    }
+}
+
+impl SessionTx {
+    /// Deletes the rows of `store` with keys in the range `lower..upper` within the
+    /// transaction. The range is only scanned if the changes of the relation are captured,
+    /// to capture the removal of each row; otherwise it is left to the storage engine.
+    pub(crate) fn del_rows(
+        &mut self,
+        store: &RelationHandle,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<()> {
+        if !self.services.change_feed.captures(&store.name) {
+            return self.tx.range_del(lower, upper);
+        }
+        for pair in self.tx.range_scan(lower, upper) {
+            let (key, _) = pair?;
+            self.tx.del(&key)?;
+            self.script
+                .changes
+                .push((store.name.clone(), false, Tuple::decode_from_key(&key)));
+        }
+        Ok(())
+    }
+}
+
+impl Db {
+    /// Subscribe to the changes made to the stored relation `relation`. Each time a
+    /// transaction putting or removing rows of the relation is committed, the changes it made
+    /// are sent through the returned channel. Dropping the receiver ends the subscription.
+    ///
+    /// Rows are captured as they are written by queries, triggers and imports, and as they
+    /// are removed by deleting ranges of rows, truncating, replacing or removing the
+    /// relation, the keys of each row removed being sent. Purging soft-deleted rows and
+    /// offloading rows to cold storage change no rows as read by queries, and send nothing.
+    pub fn subscribe(&self, relation: &str) -> Result<Receiver<RelationChanges>> {
+        self.transact()?.get_relation(relation, false)?;
+        self.change_feed.subscribe(relation, None)
+    }
+    /// As [`Db::subscribe`], but first sending the changes made to the relation by the
+    /// transactions after the one with ID `tx_id`, which must still be kept as set by
+    /// [`DbOptions::change_buffer_size`](crate::DbOptions::change_buffer_size).
+    pub fn subscribe_since(&self, relation: &str, tx_id: u64) -> Result<Receiver<RelationChanges>> {
+        self.transact()?.get_relation(relation, false)?;
+        self.change_feed.subscribe(relation, Some(tx_id))
+    }
+}
@@ -0,0 +1,188 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Moving stored relations between databases in a binary columnar format, which keeps the
+//! exact types of values and is much more compact and faster to read and write than JSON.
+//!
+//! A stream starts with a magic number and a header holding the name and columns of the
+//! relation. Rows follow in chunks, each starting with its number of rows, followed by the
+//! values of each column in turn. A chunk of no rows ends the stream. The header and the
+//! values of a column in a chunk are MessagePack, preceded by their length as a big-endian
+//! `u32`, as is the number of rows of a chunk.
+
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::data::program::RelationOp;
+use crate::data::relation::StoredRelationMetadata;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::db::column_symbols;
+use crate::runtime::relation::InputRelationHandle;
+use crate::runtime::sync::ensure_readable;
+use crate::Db;
+
+const COLUMNAR_MAGIC: &[u8; 8] = b"COZOCOL\x01";
+/// Number of rows in each chunk written.
+const COLUMNAR_CHUNK_ROWS: usize = 4096;
+
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct ColumnarHeader {
+    relation: String,
+    metadata: StoredRelationMetadata,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The input is not a complete columnar export of a relation")]
+#[diagnostic(code(db::bad_columnar_input))]
+#[diagnostic(help(
+    "The input is either corrupted or was not written by `export_relation_columnar`"
+))]
+struct BadColumnarInput;
+
+fn write_len(out: &mut impl Write, len: usize) -> Result<()> {
+    out.write_all(&(len as u32).to_be_bytes()).into_diagnostic()
+}
+
+fn write_part(out: &mut impl Write, part: &impl Serialize) -> Result<()> {
+    let bytes = rmp_serde::to_vec(part).into_diagnostic()?;
+    write_len(out, bytes.len())?;
+    out.write_all(&bytes).into_diagnostic()
+}
+
+fn read_exact(input: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    match input.read_exact(buf) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => bail!(BadColumnarInput),
+        Err(err) => Err(err).into_diagnostic(),
+    }
+}
+
+fn read_len(input: &mut impl Read) -> Result<usize> {
+    let mut len = [0; 4];
+    read_exact(input, &mut len)?;
+    Ok(u32::from_be_bytes(len) as usize)
+}
+
+fn read_part<T: DeserializeOwned>(input: &mut impl Read) -> Result<T> {
+    let mut bytes = vec![0; read_len(input)?];
+    read_exact(input, &mut bytes)?;
+    rmp_serde::from_slice(&bytes).map_err(|_| BadColumnarInput.into())
+}
+
+/// Writes the rows as one chunk, column by column.
+fn write_chunk(out: &mut impl Write, rows: &[Tuple], arity: usize) -> Result<()> {
+    write_len(out, rows.len())?;
+    for i in 0..arity {
+        let column = rows.iter().map(|row| &row.0[i]).collect_vec();
+        write_part(out, &column)?;
+    }
+    Ok(())
+}
+
+impl Db {
+    /// Write all rows of the stored relation `relation`, together with its columns, to
+    /// `writer` in a binary columnar format keeping the exact types of values, returning the
+    /// number of rows written. The rows are read from a snapshot and written a chunk at a
+    /// time, so that relations of any size can be exported while queries keep running.
+    pub fn export_relation_columnar(&self, relation: &str, writer: impl Write) -> Result<usize> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(relation, false)?;
+        ensure_readable(&handle)?;
+        let mut out = BufWriter::new(writer);
+        out.write_all(COLUMNAR_MAGIC).into_diagnostic()?;
+        write_part(
+            &mut out,
+            &ColumnarHeader {
+                relation: relation.to_string(),
+                metadata: handle.metadata.clone(),
+            },
+        )?;
+        let arity = handle.arity();
+        let mut n_written = 0;
+        let mut chunk = Vec::with_capacity(COLUMNAR_CHUNK_ROWS);
+        for row in handle.scan_all(&tx) {
+            chunk.push(row?);
+            if chunk.len() == COLUMNAR_CHUNK_ROWS {
+                write_chunk(&mut out, &chunk, arity)?;
+                n_written += chunk.len();
+                chunk.clear();
+            }
+        }
+        if !chunk.is_empty() {
+            write_chunk(&mut out, &chunk, arity)?;
+            n_written += chunk.len();
+        }
+        write_len(&mut out, 0)?;
+        out.flush().into_diagnostic()?;
+        Ok(n_written)
+    }
+    /// Import rows written by [`Db::export_relation_columnar`] from `reader` into the stored
+    /// relation `relation`, or the relation they were exported from if `None`, returning the
+    /// number of rows imported. The relation is created with the exported columns if it does
+    /// not exist, and must have the same columns otherwise. Rows are read and written a chunk
+    /// at a time, each chunk in a transaction of its own.
+    pub fn import_relation_columnar(
+        &self,
+        reader: impl Read,
+        relation: Option<&str>,
+    ) -> Result<usize> {
+        let mut input = BufReader::new(reader);
+        let mut magic = [0; COLUMNAR_MAGIC.len()];
+        read_exact(&mut input, &mut magic)?;
+        if magic != *COLUMNAR_MAGIC {
+            bail!(BadColumnarInput)
+        }
+        let header: ColumnarHeader = read_part(&mut input)?;
+        let relation = relation.unwrap_or(&header.relation);
+        let handle = self.import_target(relation, header.metadata)?;
+        let meta = InputRelationHandle {
+            name: Symbol::new(handle.name.clone(), Default::default()),
+            metadata: handle.metadata.clone(),
+            key_bindings: column_symbols(&handle.metadata.keys),
+            dep_bindings: column_symbols(&handle.metadata.non_keys),
+            span: Default::default(),
+        };
+        let headers = meta
+            .key_bindings
+            .iter()
+            .chain(meta.dep_bindings.iter())
+            .cloned()
+            .collect_vec();
+
+        let mut imported = 0;
+        loop {
+            let n_rows = read_len(&mut input)?;
+            if n_rows == 0 {
+                return Ok(imported);
+            }
+            let mut rows = vec![Vec::with_capacity(headers.len()); n_rows];
+            for _ in 0..headers.len() {
+                let column: Vec<DataValue> = read_part(&mut input)?;
+                if column.len() != n_rows {
+                    bail!(BadColumnarInput)
+                }
+                for (row, val) in rows.iter_mut().zip(column) {
+                    row.push(val);
+                }
+            }
+            let mut tx = self.transact_write()?;
+            tx.execute_relation(
+                self,
+                rows.into_iter().map(|row| Ok(Tuple(row))),
+                RelationOp::Put,
+                &meta,
+                &headers,
+            )?;
+            tx.commit_tx()?;
+            imported += n_rows;
+        }
+    }
+}
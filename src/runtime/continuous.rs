@@ -0,0 +1,197 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Continuous queries: read-only queries registered together with a callback. Each time a
+//! commit writes to a stored relation a continuous query reads, the query is run again and
+//! the callback is given the rows that entered and left its result since the previous run.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use itertools::Itertools;
+use log::warn;
+use miette::{ensure, Diagnostic, Result};
+use serde_json::Map;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::parse_script;
+use crate::runtime::transact::SessionTx;
+use crate::Db;
+
+/// The change in the result of a continuous query registered with
+/// [`Db::register_continuous_query`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryDiff {
+    /// The names of the columns of the rows.
+    pub headers: Vec<String>,
+    /// The rows that are in the result now but were not before.
+    pub added: Vec<Vec<JsonValue>>,
+    /// The rows that were in the result before but are not now.
+    pub removed: Vec<Vec<JsonValue>>,
+}
+
+type Relations = BTreeSet<SmartString<LazyCompact>>;
+
+struct ContinuousQuery {
+    payload: String,
+    params: Map<String, JsonValue>,
+    callback: Box<dyn Fn(QueryDiff) + Send + Sync>,
+    /// The stored relations read by the last run, and the rows it returned
+    state: Mutex<(Relations, BTreeSet<Tuple>)>,
+}
+
+/// The continuous queries registered with a database.
+#[derive(Default)]
+pub(crate) struct ContinuousQueries {
+    last_id: AtomicU64,
+    queries: Mutex<BTreeMap<u64, Arc<ContinuousQuery>>>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Continuous queries cannot write to stored relations")]
+#[diagnostic(code(eval::continuous_write))]
+#[diagnostic(help("Use triggers to write to stored relations in response to changes"))]
+struct ContinuousWriteError;
+
+impl Db {
+    /// Register a continuous query, returning its ID. The query is run at once and `callback`
+    /// is called with all its rows as added. Afterwards, whenever a commit writes to a
+    /// stored relation the query read on its last run, the query is run again and `callback`
+    /// is called with the rows added to and removed from the result, if any.
+    ///
+    /// The callback runs on the thread that made the commit, after the commit. It may run
+    /// scripts of its own, including ones triggering further runs of continuous queries.
+    /// System ops and queries writing to stored relations are rejected.
+    pub fn register_continuous_query(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+        callback: impl Fn(QueryDiff) + Send + Sync + 'static,
+    ) -> Result<u64> {
+        let (headers, rows, relations) = self.run_continuous_query(payload, params)?;
+        let diff = QueryDiff {
+            headers,
+            added: rows.iter().map(tuple_to_json).collect(),
+            removed: vec![],
+        };
+        let id = self
+            .continuous_queries
+            .last_id
+            .fetch_add(1, Ordering::SeqCst)
+            + 1;
+        let query = Arc::new(ContinuousQuery {
+            payload: payload.to_string(),
+            params: params.clone(),
+            callback: Box::new(callback),
+            state: Mutex::new((relations, rows)),
+        });
+        self.continuous_queries
+            .queries
+            .lock()
+            .unwrap()
+            .insert(id, query.clone());
+        (query.callback)(diff);
+        Ok(id)
+    }
+    /// Remove the continuous query with the given ID. Returns whether it existed.
+    pub fn unregister_continuous_query(&self, id: u64) -> bool {
+        self.continuous_queries
+            .queries
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .is_some()
+    }
+    /// Runs the continuous queries reading any of the relations `written` again and calls
+    /// their callbacks with the changes. With `None`, all of them are run.
+    pub(crate) fn refresh_continuous_queries(&self, written: Option<&Relations>) {
+        let queries = self
+            .continuous_queries
+            .queries
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect_vec();
+        for query in queries {
+            let diff = {
+                let mut state = query.state.lock().unwrap();
+                if matches!(written, Some(written) if state.0.is_disjoint(written)) {
+                    continue;
+                }
+                let (headers, rows, relations) =
+                    match self.run_continuous_query(&query.payload, &query.params) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            warn!("continuous query failed: {:?}", err);
+                            continue;
+                        }
+                    };
+                let added = rows.difference(&state.1).map(tuple_to_json).collect_vec();
+                let removed = state.1.difference(&rows).map(tuple_to_json).collect_vec();
+                *state = (relations, rows);
+                if added.is_empty() && removed.is_empty() {
+                    continue;
+                }
+                QueryDiff {
+                    headers,
+                    added,
+                    removed,
+                }
+            };
+            (query.callback)(diff);
+        }
+    }
+    /// Runs a continuous query, returning the names of its columns, its rows and the stored
+    /// relations it read.
+    fn run_continuous_query(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+    ) -> Result<(Vec<String>, BTreeSet<Tuple>, Relations)> {
+        let param_pool = params
+            .iter()
+            .map(|(k, v)| (k.clone(), DataValue::from(v)))
+            .collect();
+        let program =
+            parse_script(payload, &param_pool, &self.custom_algos)?.get_single_program()?;
+        ensure!(
+            program.out_opts.store_relation.is_none(),
+            ContinuousWriteError
+        );
+        let headers = match program.get_entry_out_head() {
+            Err(_) => vec![],
+            Ok(headers) => headers.into_iter().map(|v| v.name.to_string()).collect(),
+        };
+        let mut tx = self.transact()?;
+        tx.query.relations_read = Some(Default::default());
+        let (rows, _in_mem_guard) = self.query_rows(&mut tx, program, payload)?;
+        let rows = rows.collect();
+        let relations = tx
+            .query
+            .relations_read
+            .take()
+            .map(|read| read.into_inner().unwrap())
+            .unwrap_or_default();
+        Ok((headers, rows, relations))
+    }
+}
+
+/// The names of the stored relations written within `tx` so far.
+pub(crate) fn written_relations(tx: &SessionTx) -> Relations {
+    tx.script
+        .writes
+        .iter()
+        .filter_map(|(relation, _)| relation.clone())
+        .collect()
+}
+
+fn tuple_to_json(tuple: &Tuple) -> Vec<JsonValue> {
+    tuple.0.iter().cloned().map(JsonValue::from).collect()
+}
@@ -0,0 +1,235 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Bulk loading of CSV files into existing stored relations, with [`Db::import_from_csv`]
+//! or `::import into`. Fields are converted to the types of the columns they go into, and
+//! the rows are written in batches.
+
+use std::io::Read;
+use std::mem;
+
+use csv::StringRecord;
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::algo::csv::csv_field_to_value;
+use crate::algo::jlines::get_file_content_from_url;
+use crate::data::program::RelationOp;
+use crate::data::relation::{ColumnDef, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::db::column_symbols;
+use crate::runtime::relation::InputRelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::Db;
+
+/// Options of [`Db::import_from_csv`].
+#[derive(Clone, Debug)]
+pub struct CsvImportOptions {
+    /// The byte separating the fields of a record. Defaults to `,`.
+    pub delimiter: u8,
+    /// Whether the first record holds the names of the columns the fields go into.
+    /// Otherwise the fields go into the columns of the relation in order, keys first.
+    /// Defaults to `true`.
+    pub has_headers: bool,
+    /// Number of rows written to the relation at a time. Defaults to 10000.
+    pub batch_size: usize,
+}
+
+impl Default for CsvImportOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            batch_size: 10000,
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Column '{0}' of the CSV source is not found in relation '{1}'")]
+#[diagnostic(code(eval::csv_column_not_found))]
+struct CsvColumnNotFound(String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The CSV source has {0} fields per record, more than the {1} columns of relation '{2}'")]
+#[diagnostic(code(eval::csv_too_many_fields))]
+struct CsvTooManyFields(usize, usize, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot import line {line} of the CSV source into column '{column}'")]
+#[diagnostic(code(eval::bad_csv_field))]
+struct BadCsvField {
+    line: u64,
+    column: String,
+    #[related]
+    related: [miette::Report; 1],
+}
+
+/// Opens the CSV source, read from the URL if it starts with `http://` or `https://`, and
+/// from the file at the path otherwise, which may start with `file://`.
+fn open_csv(source: &str, options: &CsvImportOptions) -> Result<csv::Reader<Box<dyn Read>>> {
+    let reader: Box<dyn Read> = if source.starts_with("http://") || source.starts_with("https://") {
+        let content = get_file_content_from_url(source)?;
+        Box::new(std::io::Cursor::new(content.as_bytes().to_vec()))
+    } else {
+        let path = source.strip_prefix("file://").unwrap_or(source);
+        Box::new(std::fs::File::open(path).into_diagnostic()?)
+    };
+    Ok(csv::ReaderBuilder::new()
+        .delimiter(options.delimiter)
+        .has_headers(options.has_headers)
+        .flexible(true)
+        .from_reader(reader))
+}
+
+impl SessionTx {
+    /// Puts the rows of the CSV source into the relation `name`. Columns of the relation
+    /// missing from the source get their default values. Returns the number of rows
+    /// imported and the key ranges to delete after commit.
+    pub(crate) fn import_csv(
+        &mut self,
+        db: &Db,
+        name: &str,
+        source: &str,
+        options: &CsvImportOptions,
+    ) -> Result<(usize, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let relation = self.get_relation(name, false)?;
+        let all_cols = relation
+            .metadata
+            .keys
+            .iter()
+            .chain(relation.metadata.non_keys.iter())
+            .collect_vec();
+        let mut rdr = open_csv(source, options)?;
+
+        // the columns of the relation the fields of each record go into
+        let fields: Vec<&ColumnDef> = if options.has_headers {
+            rdr.headers()
+                .into_diagnostic()?
+                .iter()
+                .map(|h| {
+                    let h = h.trim();
+                    all_cols
+                        .iter()
+                        .find(|col| col.name == h)
+                        .copied()
+                        .ok_or_else(|| CsvColumnNotFound(h.to_string(), name.to_string()).into())
+                })
+                .collect::<Result<_>>()?
+        } else {
+            let mut first = StringRecord::new();
+            let has_first = rdr.read_record(&mut first).into_diagnostic()?;
+            if first.len() > all_cols.len() {
+                bail!(CsvTooManyFields(
+                    first.len(),
+                    all_cols.len(),
+                    name.to_string()
+                ))
+            }
+            let fields = all_cols[..first.len()].to_vec();
+            // the record read to count the fields is imported with the others
+            let mut rows = vec![];
+            if has_first {
+                rows.push(first);
+            }
+            return self.import_csv_records(
+                db,
+                name,
+                &fields,
+                rows.into_iter().map(Ok).chain(rdr.into_records()),
+                options.batch_size,
+            );
+        };
+        self.import_csv_records(db, name, &fields, rdr.into_records(), options.batch_size)
+    }
+    fn import_csv_records(
+        &mut self,
+        db: &Db,
+        name: &str,
+        fields: &[&ColumnDef],
+        records: impl Iterator<Item = csv::Result<StringRecord>>,
+        batch_size: usize,
+    ) -> Result<(usize, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let relation = self.get_relation(name, false)?;
+        let is_given = |col: &ColumnDef| fields.iter().any(|f| f.name == col.name);
+        let metadata = StoredRelationMetadata {
+            keys: relation
+                .metadata
+                .keys
+                .iter()
+                .filter(|col| is_given(col))
+                .cloned()
+                .collect(),
+            non_keys: relation
+                .metadata
+                .non_keys
+                .iter()
+                .filter(|col| is_given(col))
+                .cloned()
+                .collect(),
+        };
+        let handle = InputRelationHandle {
+            name: Symbol::new(name, Default::default()),
+            key_bindings: column_symbols(&metadata.keys),
+            dep_bindings: column_symbols(&metadata.non_keys),
+            metadata,
+            span: Default::default(),
+        };
+        let headers = fields
+            .iter()
+            .map(|col| Symbol::new(col.name.clone(), Default::default()))
+            .collect_vec();
+
+        let mut n_imported = 0;
+        let mut cleanups = vec![];
+        let mut batch = Vec::with_capacity(batch_size);
+        for record in records {
+            let record = record.into_diagnostic()?;
+            let line = record.position().map_or(0, |pos| pos.line());
+            let row = fields
+                .iter()
+                .enumerate()
+                .map(|(i, col)| {
+                    let val = match record.get(i) {
+                        Some(field) => csv_field_to_value(field, &col.typing),
+                        None => col.typing.coerce(DataValue::Null),
+                    };
+                    val.map_err(|err| {
+                        BadCsvField {
+                            line,
+                            column: col.name.to_string(),
+                            related: [err],
+                        }
+                        .into()
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            batch.push(Tuple(row));
+            if batch.len() >= batch_size {
+                n_imported += batch.len();
+                cleanups.extend(self.execute_relation(
+                    db,
+                    mem::take(&mut batch).into_iter().map(Ok),
+                    RelationOp::Put,
+                    &handle,
+                    &headers,
+                )?);
+            }
+        }
+        if !batch.is_empty() {
+            n_imported += batch.len();
+            cleanups.extend(self.execute_relation(
+                db,
+                batch.into_iter().map(Ok),
+                RelationOp::Put,
+                &handle,
+                &headers,
+            )?);
+        }
+        Ok((n_imported, cleanups))
+    }
+}
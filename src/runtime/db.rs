@@ -2,43 +2,84 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
-use std::path::PathBuf;
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use std::{fs, thread};
+use std::{mem, thread};
 
 use either::{Left, Right};
 use itertools::Itertools;
 use lazy_static::lazy_static;
+use log::warn;
 use miette::{
-    bail, ensure, miette, Diagnostic, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic,
+    bail, ensure, Diagnostic, GraphicalReportHandler, GraphicalTheme, IntoDiagnostic,
     JSONReportHandler, Result, WrapErr,
 };
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use serde_json::{json, Map};
-use smartstring::SmartString;
+use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use cozorocks::{DbBuilder, RocksDb};
-
+use crate::algo::call::TypedAlgoCall;
+use crate::algo::custom::{check_custom_algos, CustomAlgo, CustomAlgos};
+use crate::algo::BUILTIN_ALGOS;
+use crate::data::aggr::{list_user_aggrs, AGGRS};
+use crate::data::expr::{Expr, OPS};
+use crate::data::functions::{tuple_hash, TUPLE_HASH_INIT};
 use crate::data::json::JsonValue;
-use crate::data::program::{InputProgram, QueryAssertion, RelationOp};
-use crate::data::symb::Symbol;
-use crate::data::tuple::{Tuple, KEY_PREFIX_LEN};
+use crate::data::program::{
+    InputProgram, MagicAlgoRuleArg, MagicSymbol, QueryAssertion, RelationOp,
+};
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::Tuple;
 use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
 use crate::parse::sys::SysOp;
 use crate::parse::{parse_script, CozoScript, SourceSpan};
-use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::builder::QueryBuilder;
+use crate::query::compile::{AggrKind, CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::eval::QueryProfile;
 use crate::query::relation::{
-    FilteredRA, InMemRelationRA, InnerJoin, NegJoin, RelAlgebra, ReorderRA, StoredRA, UnificationRA,
+    FilteredRA, InMemRelationRA, InnerJoin, NegJoin, RelAlgebra, ReorderRA, UnificationRA,
+};
+use crate::query::sql::SqlDialect;
+use crate::runtime::access::{AccessPolicy, RelationAccess};
+use crate::runtime::audit::AUDIT_LOG;
+use crate::runtime::backup::{BackupReader, BackupStore, BackupWriter};
+use crate::runtime::cdc::ChangeFeed;
+use crate::runtime::continuous::{written_relations, ContinuousQueries};
+use crate::runtime::csv_import::CsvImportOptions;
+use crate::runtime::federation::{Federation, RemoteDb};
+use crate::runtime::in_mem::InMemRelation;
+use crate::runtime::job::{Job, JobRun};
+use crate::runtime::metrics::{GaugeGuard, Metrics};
+use crate::runtime::namespace::{
+    NamespaceNotFound, NamespaceQuota, NamespaceResultQuotaExceeded, StorageUsage, UsageCountingTx,
+};
+use crate::runtime::plan::{script_hash, CapturedPlan, MAX_CAPTURED_PLANS};
+use crate::runtime::relation::{
+    current_validity, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+    RelationId, VersionClock,
 };
-use crate::runtime::relation::{RelationHandle, RelationId};
-use crate::runtime::transact::SessionTx;
+use crate::runtime::replication::{
+    position_key, FollowerReadOnly, ReplicatingStorage, ReplicationLog, ReplicationWithColdStorage,
+};
+use crate::runtime::schedule::ScheduledQuery;
+use crate::runtime::stats::{ColumnStatistics, RelationStatistics, RowSampler};
+use crate::runtime::sync::{post_sync_request, SYNC_CONFLICTS};
+use crate::runtime::transact::{
+    DbServices, EvalOptions, RowGuard, SessionTx, TransactionAccessDenied,
+};
+use crate::storage::{RocksDbStorage, Storage};
+use crate::utils::{enter_span, trace_event};
 
 struct RunningQueryHandle {
     started_at: f64,
+    label: Option<String>,
     poison: Poison,
 }
 
@@ -47,6 +88,14 @@ struct RunningQueryCleanup {
     running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
 }
 
+struct InFlightScript(Arc<AtomicU64>);
+
+impl Drop for InFlightScript {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 impl Drop for RunningQueryCleanup {
     fn drop(&mut self) {
         let mut map = self.running_queries.lock().unwrap();
@@ -56,20 +105,322 @@ impl Drop for RunningQueryCleanup {
     }
 }
 
-#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
-pub(crate) struct DbManifest {
-    storage_version: u64,
+struct EvaluatedQuery {
+    result: InMemRelation,
+    early_return: bool,
+    tolerated: Option<u64>,
+    warnings: Vec<String>,
+    profile: Option<QueryProfile>,
+    in_mem_guard: GaugeGuard,
+    /// The entry rule, when left out of the evaluation for its rows to be pulled lazily
+    lazy_entry: Option<LazyEntry>,
+}
+
+/// The rules of the entry of a query, to be evaluated as its rows are pulled, with what
+/// their evaluation needs. The rows derived are put into the result of the query, which
+/// holds the distinct rows handed out so far.
+struct LazyEntry {
+    rules: CompiledRuleSet,
+    /// The rules read by the entry
+    _stores: BTreeMap<MagicSymbol, InMemRelation>,
+    poison: Poison,
+    /// Keeps the query listed as running, to be killed or timed out
+    _running: RunningQueryCleanup,
+    max_result_rows: Option<usize>,
+}
+
+/// The transaction of a query whose rows are pulled lazily, moved to the thread evaluating
+/// the entry rule.
+struct CursorTx(SessionTx);
+
+// SAFETY: the transaction is moved into the thread producing the rows of a cursor before it
+// is used there, and never used by the thread that created it again. The engine transactions
+// have no affinity to the thread that created them (see `StoreTx`).
+unsafe impl Send for CursorTx {}
+
+impl CursorTx {
+    // Taking `self` makes closures capture the whole wrapper rather than the field.
+    fn into_inner(self) -> SessionTx {
+        self.0
+    }
+}
+
+/// A cursor over the rows returned by [`Db::run_query_cursor`] and [`Db::run_built_query`].
+///
+/// When the query has no sorting and no assertion, and its entry rule neither aggregates nor
+/// reads itself, the rules the entry reads are evaluated before the cursor is returned, and
+/// the entry rule is evaluated as the cursor is advanced: each row is derived only when
+/// pulled, in the order the rules derive it, and `:limit` stops the evaluation once enough
+/// rows have been pulled. Rows are still deduplicated, which is the only reason they are
+/// held in memory: the distinct rows handed out are kept until the cursor is dropped.
+/// The query is running until the cursor is exhausted or dropped, so it holds up
+/// [`Db::close_gracefully`], and it may be killed or time out while rows are pulled, the
+/// cursor then returning the error.
+///
+/// Other queries are evaluated in full, and their rows sorted as a whole, before the cursor
+/// is returned, and the rows are held in memory until handed out.
+///
+/// In both cases, each row is converted to JSON only when the cursor is advanced.
+pub struct QueryCursor {
+    headers: Vec<String>,
+    rows: Box<dyn Iterator<Item = Result<Tuple>> + Send>,
+    /// Stops the evaluation of the rows not pulled yet when the cursor is dropped
+    poison: Option<Poison>,
+    _in_flight: Option<InFlightScript>,
+    _in_mem_guard: Option<GaugeGuard>,
+}
+
+impl QueryCursor {
+    /// The names of the columns of the rows.
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+}
+
+impl Iterator for QueryCursor {
+    type Item = Result<Vec<JsonValue>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows
+            .next()
+            .map(|tuple| Ok(tuple?.0.into_iter().map(JsonValue::from).collect()))
+    }
+}
+
+impl Drop for QueryCursor {
+    fn drop(&mut self) {
+        if let Some(poison) = &self.poison {
+            poison.0.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A transaction spanning several scripts, started with [`Db::multi_transact`].
+/// Each script sees the writes of the scripts run before it, and none of them are seen
+/// outside of the transaction until it is committed.
+/// Dropping the transaction without committing it rolls it back.
+pub struct MultiTransaction {
+    db: Db,
+    tx: SessionTx,
+    cleanups: Vec<(Vec<u8>, Vec<u8>)>,
+    written: BTreeSet<SmartString<LazyCompact>>,
+    /// Keeps closing the database waiting until the transaction is committed, rolled back
+    /// or dropped
+    _in_flight: InFlightScript,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("System ops cannot be run within a multi-statement transaction")]
+#[diagnostic(code(db::sys_op_in_transaction))]
+struct SysOpInTransaction;
+
+impl MultiTransaction {
+    /// Run the CozoScript passed in within the transaction and return the result of its last
+    /// query. The `params` argument is a map of parameters. If the script fails, its own writes
+    /// are undone and the transaction can still be used, unless the access policy denied one
+    /// of its queries, after which the transaction can only be rolled back.
+    pub fn run_script(
+        &mut self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+    ) -> Result<JsonValue> {
+        let _in_flight = self.db.admit()?;
+        let param_pool = params
+            .iter()
+            .map(|(k, v)| (k.clone(), DataValue::from(v)))
+            .collect();
+        ensure!(!self.tx.script.access_denied, TransactionAccessDenied);
+        let ps = match parse_script(payload, &param_pool, &self.db.custom_algos)? {
+            CozoScript::Multi(ps) => ps,
+            CozoScript::Sys(_) => bail!(SysOpInTransaction),
+        };
+        let start = Instant::now();
+        let n_changes = self.tx.script.changes.len();
+        self.tx.tx.save();
+        let res = self
+            .db
+            .run_programs(&mut self.tx, ps, payload)
+            .and_then(|res| {
+                self.written.extend(written_relations(&self.tx));
+                self.db.append_audit(&mut self.tx, payload)?;
+                Ok(res)
+            });
+        match res {
+            Ok((mut json, cleanups)) => {
+                self.tx.tx.pop_save()?;
+                self.cleanups.extend(cleanups);
+                let map = json.as_object_mut().unwrap();
+                map.insert("ok".to_string(), json!(true));
+                map.insert("took".to_string(), json!(start.elapsed().as_secs_f64()));
+                Ok(json)
+            }
+            Err(err) => {
+                self.tx
+                    .services
+                    .metrics
+                    .queries_failed
+                    .fetch_add(1, Ordering::Relaxed);
+                self.tx.script.writes.clear();
+                self.tx.script.changes.truncate(n_changes);
+                self.tx.tx.rollback_to_save()?;
+                Err(err)
+            }
+        }
+    }
+    /// Attach `label` to the scripts run within the transaction from now on,
+    /// as [`Db::run_script_labelled`] does for a single script.
+    pub fn set_label(&mut self, label: Option<&str>) {
+        self.tx.script.label = label.map(|l| l.to_string());
+    }
+    /// Commit the writes of all the scripts run within the transaction.
+    pub fn commit(mut self) -> Result<()> {
+        self.tx.commit_tx()?;
+        for (lower, upper) in mem::take(&mut self.cleanups) {
+            self.db.db.range_del(&lower, &upper)?;
+        }
+        if !self.written.is_empty() {
+            self.db.refresh_continuous_queries(Some(&self.written));
+        }
+        Ok(())
+    }
+    /// Discard the writes of all the scripts run within the transaction.
+    pub fn rollback(mut self) -> Result<()> {
+        self.tx.tx.rollback()?;
+        Ok(())
+    }
+}
+
+/// Options for opening a database.
+#[derive(Debug, Clone)]
+pub struct DbOptions {
+    /// Number of threads used by graph algorithms that run in parallel.
+    /// When `None`, rayon's global thread pool is used.
+    pub algo_threads: Option<usize>,
+    /// Prefix of the names of the algorithm threads.
+    pub algo_thread_name: String,
+    /// Number of background threads the storage engine uses for flushes and compactions.
+    /// When `None`, the storage engine's default is kept.
+    pub storage_threads: Option<usize>,
+    /// Whether predicates in rule bodies are reordered by estimated evaluation cost,
+    /// cheap comparisons running before string and regex operations. Defaults to `true`.
+    pub reorder_predicates: bool,
+    /// Default for the `:max_rows_scanned` option of queries not setting it.
+    /// When `None`, queries may read any number of rows from stored relations.
+    pub max_rows_scanned: Option<usize>,
+    /// Default for the `:max_intermediate_rows` option of queries not setting it.
+    /// When `None`, rules may derive any number of rows.
+    pub max_intermediate_rows: Option<usize>,
+    /// Fixed rules implemented outside of the crate, keyed by the name used to apply them.
+    /// They are only available to the database opened with them, and opening fails if a name
+    /// is taken by a builtin.
+    pub custom_algos: BTreeMap<String, Arc<dyn CustomAlgo>>,
+    /// Whether scripts and system ops changing stored relations are recorded in the audit
+    /// log, the read-only stored relation `audit_log`, created when the database is opened.
+    pub audit: bool,
+    /// How long entries of the audit log are kept. When `None`, they are kept forever.
+    pub audit_retention: Option<Duration>,
+    /// Where tiered relations offload their old rows, usually a slower or cheaper engine
+    /// than the main one. Offloaded rows are read from it transparently by queries.
+    pub cold_storage: Option<Arc<dyn Storage>>,
+    /// Remote databases whose relations queries can read, as `*<name>::<relation>` with
+    /// the name under which the database is configured here.
+    pub remotes: BTreeMap<String, RemoteDb>,
+    /// Whether a background thread runs the queries scheduled with `::schedule create`
+    /// and the jobs created with `::job create` once they are due. The thread keeps the database open until it is closed with
+    /// [`Db::close_gracefully`]. When off, [`Db::run_due_scheduled_queries`] and
+    /// [`Db::run_due_jobs`] can be called instead.
+    pub scheduler: bool,
+    /// Number of rows, as last collected with `::relation stats`, above which scanning a
+    /// stored relation without binding a prefix of its keys is warned about, or rejected in
+    /// queries with the `:strict` option. When `None`, the default, such scans are not
+    /// linted; set it, e.g. to `Some(100_000)`, to enable the lint.
+    pub full_scan_lint_rows: Option<usize>,
+    /// Default for the `:algo_memory_budget` option of queries not setting it, in bytes.
+    /// When `None`, algorithms are run however much memory they are estimated to need.
+    pub algo_memory_budget: Option<usize>,
+    /// Decides which stored relations each query and system op may read and write.
+    /// When `None`, all of them may be.
+    pub access_policy: Option<Arc<dyn AccessPolicy>>,
+    /// Number of recent transactions whose changes to stored relations are kept, for
+    /// subscribers to catch up with [`Db::subscribe_since`]. When non-zero, the changes of
+    /// all relations are captured, even those without subscribers.
+    pub change_buffer_size: usize,
+    /// Number of recent committed write batches kept for followers to fetch with
+    /// [`Db::replicate_from`]. When zero, the database cannot be replicated. Replication
+    /// cannot be combined with `cold_storage`.
+    pub replication_log_size: usize,
+    /// Whether the database is a read-only follower of another, changed only by
+    /// [`Db::restore`] and [`Db::replicate_from`]. Scripts and system ops writing to it fail.
+    pub follower: bool,
+    /// Number of rows of a negated rule above which, if the planner expects the negation to
+    /// be probed by far fewer rows, only the rows matching the keys probed are looked up
+    /// instead of materializing the keys of the whole rule. When `None`, the default,
+    /// negated rules are always materialized.
+    pub negation_materialize_rows: Option<usize>,
+    /// Whether the database is opened strictly for reading. Scripts and system ops writing
+    /// to it are rejected before running. With [`Db::new_with_options`], the storage is also
+    /// opened without its lock, so that several processes can read the same directory.
+    pub read_only: bool,
 }
 
-const CURRENT_STORAGE_VERSION: u64 = 1;
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            algo_threads: None,
+            algo_thread_name: "cozo-algo".to_string(),
+            storage_threads: None,
+            reorder_predicates: true,
+            max_rows_scanned: None,
+            max_intermediate_rows: None,
+            custom_algos: Default::default(),
+            audit: false,
+            audit_retention: None,
+            cold_storage: None,
+            remotes: Default::default(),
+            scheduler: false,
+            full_scan_lint_rows: None,
+            algo_memory_budget: None,
+            access_policy: None,
+            change_buffer_size: 0,
+            replication_log_size: 0,
+            follower: false,
+            negation_materialize_rows: None,
+            read_only: false,
+        }
+    }
+}
 
 /// The database object of Cozo.
 #[derive(Clone)]
 pub struct Db {
-    db: RocksDb,
+    pub(crate) db: Arc<dyn Storage>,
+    cold_storage: Option<Arc<dyn Storage>>,
     relation_store_id: Arc<AtomicU64>,
     queries_count: Arc<AtomicU64>,
     running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
+    algo_pool: Option<Arc<ThreadPool>>,
+    reorder_predicates: bool,
+    max_rows_scanned: Option<usize>,
+    max_intermediate_rows: Option<usize>,
+    full_scan_lint_rows: Option<usize>,
+    algo_memory_budget: Option<usize>,
+    negation_materialize_rows: Option<usize>,
+    access_policy: Option<Arc<dyn AccessPolicy>>,
+    pub(crate) change_feed: Arc<ChangeFeed>,
+    pub(crate) replication: Option<Arc<ReplicationLog>>,
+    pub(crate) follower: bool,
+    pub(crate) read_only: bool,
+    captured_plans: Arc<Mutex<BTreeMap<String, CapturedPlan>>>,
+    in_flight_scripts: Arc<AtomicU64>,
+    closing: Arc<AtomicBool>,
+    audit: bool,
+    audit_retention: Option<Duration>,
+    audit_seq: Arc<AtomicU64>,
+    federation: Arc<Federation>,
+    metrics: Arc<Metrics>,
+    pub(crate) continuous_queries: Arc<ContinuousQueries>,
+    versions: Arc<VersionClock>,
+    pub(crate) custom_algos: Arc<CustomAlgos>,
 }
 
 impl Debug for Db {
@@ -78,10 +429,21 @@ impl Debug for Db {
     }
 }
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("Database is shutting down and no longer accepts queries")]
+#[diagnostic(code(db::closing))]
+struct DbClosing;
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The database is opened read-only")]
+#[diagnostic(code(db::read_only))]
+#[diagnostic(help("Open the database without the `read_only` option to write to it"))]
+pub(crate) struct ReadOnlyDb;
+
 #[derive(Debug, Diagnostic, Error)]
 #[error("Initialization of database failed")]
 #[diagnostic(code(db::init))]
-struct BadDbInit(#[help] String);
+pub(crate) struct BadDbInit(#[help] pub(crate) String);
 
 lazy_static! {
     static ref TEXT_ERR_HANDLER: GraphicalReportHandler =
@@ -92,68 +454,111 @@ lazy_static! {
 impl Db {
     /// Creates a database object.
     pub fn new(path: impl AsRef<str>) -> Result<Self> {
-        let builder = DbBuilder::default().path(path.as_ref());
-        let path = builder.opts.db_path;
-        fs::create_dir_all(path)
-            .map_err(|err| BadDbInit(format!("cannot create directory {}: {}", path, err)))?;
-        let path_buf = PathBuf::from(path);
-
-        let is_new = {
-            let mut manifest_path = path_buf.clone();
-            manifest_path.push("manifest");
-
-            if manifest_path.exists() {
-                let existing: DbManifest = rmp_serde::from_slice(
-                    &fs::read(manifest_path)
-                        .into_diagnostic()
-                        .wrap_err_with(|| "when reading manifest")?,
-                )
-                .into_diagnostic()
-                .wrap_err_with(|| "when reading manifest")?;
-                assert_eq!(
-                    existing.storage_version, CURRENT_STORAGE_VERSION,
-                    "Unknown storage version {}",
-                    existing.storage_version
-                );
-                false
-            } else {
-                fs::write(
-                    manifest_path,
-                    rmp_serde::to_vec_named(&DbManifest {
-                        storage_version: CURRENT_STORAGE_VERSION,
-                    })
-                    .into_diagnostic()
-                    .wrap_err_with(|| "when serializing manifest")?,
-                )
-                .into_diagnostic()
-                .wrap_err_with(|| "when serializing manifest")?;
-                true
+        Self::new_with_options(path, DbOptions::default())
+    }
+    /// Creates a database object with the given options.
+    pub fn new_with_options(path: impl AsRef<str>, options: DbOptions) -> Result<Self> {
+        let storage = if options.read_only {
+            RocksDbStorage::open_read_only(path.as_ref(), options.storage_threads)?
+        } else {
+            RocksDbStorage::open(path.as_ref(), options.storage_threads)?
+        };
+        Self::new_with_storage(Arc::new(storage), options)
+    }
+    /// Creates a database object on the given storage engine.
+    /// The option `storage_threads` is ignored, as the engine is already set up.
+    pub fn new_with_storage(db: Arc<dyn Storage>, options: DbOptions) -> Result<Self> {
+        let scheduler = options.scheduler;
+        check_custom_algos(&options.custom_algos)?;
+        let algo_pool = match options.algo_threads {
+            None => None,
+            Some(n) => {
+                let name = options.algo_thread_name;
+                let pool = ThreadPoolBuilder::new()
+                    .num_threads(n)
+                    .thread_name(move |i| format!("{}-{}", name, i))
+                    .build()
+                    .map_err(|err| BadDbInit(format!("cannot build thread pool: {}", err)))?;
+                Some(Arc::new(pool))
             }
         };
 
-        let mut store_path = path_buf;
-        store_path.push("data");
-        let db_builder = builder
-            .create_if_missing(is_new)
-            .use_capped_prefix_extractor(true, KEY_PREFIX_LEN)
-            .use_bloom_filter(true, 9.9, true)
-            .path(
-                store_path
-                    .to_str()
-                    .ok_or_else(|| miette!("bad path name"))?,
-            );
-
-        let db = db_builder.build()?;
+        let replication = if options.replication_log_size > 0 {
+            ensure!(options.cold_storage.is_none(), ReplicationWithColdStorage);
+            Some(Arc::new(ReplicationLog::new(options.replication_log_size)))
+        } else {
+            None
+        };
+        let db: Arc<dyn Storage> = match &replication {
+            None => db,
+            Some(log) => Arc::new(ReplicatingStorage {
+                inner: db,
+                log: log.clone(),
+            }),
+        };
 
+        let metrics: Arc<Metrics> = Default::default();
+        let versions = Arc::new(VersionClock::new(db.clone()));
         let ret = Self {
             db,
+            cold_storage: options.cold_storage,
             relation_store_id: Arc::new(Default::default()),
             queries_count: Arc::new(Default::default()),
             running_queries: Arc::new(Mutex::new(Default::default())),
+            algo_pool,
+            reorder_predicates: options.reorder_predicates,
+            max_rows_scanned: options.max_rows_scanned,
+            max_intermediate_rows: options.max_intermediate_rows,
+            full_scan_lint_rows: options.full_scan_lint_rows,
+            algo_memory_budget: options.algo_memory_budget,
+            negation_materialize_rows: options.negation_materialize_rows,
+            access_policy: options.access_policy,
+            change_feed: Arc::new(ChangeFeed::new(options.change_buffer_size)),
+            replication,
+            follower: options.follower,
+            read_only: options.read_only,
+            captured_plans: Arc::new(Mutex::new(Default::default())),
+            in_flight_scripts: Arc::new(Default::default()),
+            closing: Arc::new(Default::default()),
+            audit: options.audit,
+            audit_retention: options.audit_retention,
+            audit_seq: Arc::new(Default::default()),
+            federation: Arc::new(Federation::new(options.remotes, metrics.clone())),
+            metrics,
+            continuous_queries: Arc::new(Default::default()),
+            versions,
+            custom_algos: Arc::new(options.custom_algos),
         };
         ret.load_last_ids()?;
+        // the audit log of a follower is replicated from the leader
+        if ret.audit && !ret.follower && !ret.read_only {
+            let mut tx = ret.transact_write()?;
+            tx.ensure_audit_log()?;
+            tx.commit_tx()?;
+        }
+        if scheduler {
+            ret.spawn_scheduler()?;
+        }
         Ok(ret)
     }
+    fn spawn_scheduler(&self) -> Result<()> {
+        let db = self.clone();
+        thread::Builder::new()
+            .name("cozo-scheduler".to_string())
+            .spawn(move || {
+                while !db.closing.load(Ordering::SeqCst) {
+                    if let Err(err) = db.run_due_scheduled_queries() {
+                        warn!("running scheduled queries failed: {:?}", err);
+                    }
+                    if let Err(err) = db.run_due_jobs() {
+                        warn!("running jobs failed: {:?}", err);
+                    }
+                    thread::sleep(SCHEDULER_TICK);
+                }
+            })
+            .map_err(|err| BadDbInit(format!("cannot start scheduler thread: {}", err)))?;
+        Ok(())
+    }
 
     fn compact_relation(&self) -> Result<()> {
         let l = Tuple::default().encode_as_key(RelationId(0));
@@ -162,32 +567,75 @@ impl Db {
         Ok(())
     }
 
-    fn load_last_ids(&self) -> Result<()> {
+    pub(crate) fn load_last_ids(&self) -> Result<()> {
         let tx = self.transact()?;
         self.relation_store_id
             .store(tx.load_last_relation_store_id()?.0, Ordering::Release);
-        Ok(())
+        self.versions.load()
     }
-    fn transact(&self) -> Result<SessionTx> {
-        let ret = SessionTx {
-            tx: self.db.transact().set_snapshot(true).start(),
-            mem_store_id: Default::default(),
-            relation_store_id: self.relation_store_id.clone(),
-        };
-        Ok(ret)
+    pub(crate) fn transact(&self) -> Result<SessionTx> {
+        self.metrics
+            .active_transactions
+            .fetch_add(1, Ordering::Relaxed);
+        self.new_session_tx()
+    }
+    pub(crate) fn transact_write(&self) -> Result<SessionTx> {
+        ensure!(!self.follower, FollowerReadOnly);
+        ensure!(!self.read_only, ReadOnlyDb);
+        self.metrics
+            .active_transactions
+            .fetch_add(1, Ordering::Relaxed);
+        self.new_session_tx()
     }
-    fn transact_write(&self) -> Result<SessionTx> {
-        let ret = SessionTx {
-            tx: self.db.transact().set_snapshot(true).start(),
+    fn new_session_tx(&self) -> Result<SessionTx> {
+        let usage = Arc::new(Mutex::new(StorageUsage::default()));
+        Ok(SessionTx {
+            tx: Box::new(UsageCountingTx {
+                inner: self.db.transact()?,
+                usage: usage.clone(),
+            }),
+            cold: match &self.cold_storage {
+                None => None,
+                Some(cold) => Some(cold.transact()?),
+            },
             mem_store_id: Default::default(),
             relation_store_id: self.relation_store_id.clone(),
-        };
-        Ok(ret)
+            eval: EvalOptions {
+                algo_pool: self.algo_pool.clone(),
+                reorder_predicates: self.reorder_predicates,
+                negation_materialize_rows: self.negation_materialize_rows,
+            },
+            query: Default::default(),
+            script: Default::default(),
+            services: DbServices {
+                federation: self.federation.clone(),
+                metrics: self.metrics.clone(),
+                change_feed: self.change_feed.clone(),
+                versions: self.versions.clone(),
+                custom_algos: self.custom_algos.clone(),
+                cold_storage: self.cold_storage.clone(),
+            },
+            usage,
+        })
     }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
+    /// When the script records versions of rows in the history of relations, the result
+    /// holds the id of its transaction under `tx_id`, which the relations can be read as of
+    /// with `*rel[...] @ tx <id>`.
     pub fn run_script(&self, payload: &str, params: &Map<String, JsonValue>) -> Result<JsonValue> {
+        self.run_script_labelled(payload, params, None)
+    }
+    /// Run the CozoScript passed in, on behalf of the client or session named by `label`.
+    /// The label is shown for the queries of the script in `::running`, attached to its
+    /// tracing spans, and recorded with its entries in the audit log.
+    pub fn run_script_labelled(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+        label: Option<&str>,
+    ) -> Result<JsonValue> {
         let start = Instant::now();
-        match self.do_run_script(payload, params) {
+        match self.do_run_script(payload, params, label) {
             Ok(mut json) => {
                 let took = start.elapsed().as_secs_f64();
                 let map = json.as_object_mut().unwrap();
@@ -195,13 +643,30 @@ impl Db {
                 map.insert("took".to_string(), json!(took));
                 Ok(json)
             }
-            err => err,
+            err => {
+                self.metrics.queries_failed.fetch_add(1, Ordering::Relaxed);
+                if let Some(label) = label {
+                    warn!("script labelled '{}' failed", label);
+                }
+                err
+            }
         }
     }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
     /// Fold any error into the return JSON itself.
     pub fn run_script_fold_err(&self, payload: &str, params: &Map<String, JsonValue>) -> JsonValue {
-        match self.run_script(payload, params) {
+        self.run_script_fold_err_labelled(payload, params, None)
+    }
+    /// As [`Db::run_script_fold_err`], for the client or session named by `label`, which is
+    /// also included in the error report.
+    /// See [`Db::run_script_labelled`] for where else the label shows up.
+    pub fn run_script_fold_err_labelled(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+        label: Option<&str>,
+    ) -> JsonValue {
+        match self.run_script_labelled(payload, params, label) {
             Ok(json) => json,
             Err(mut err) => {
                 if err.source_code().is_none() {
@@ -220,6 +685,9 @@ impl Db {
                 let map = json.as_object_mut().unwrap();
                 map.insert("ok".to_string(), json!(false));
                 map.insert("display".to_string(), json!(text_err));
+                if let Some(label) = label {
+                    map.insert("label".to_string(), json!(label));
+                }
                 json
             }
         }
@@ -243,44 +711,681 @@ impl Db {
         };
         self.run_script_fold_err(payload, &params_json).to_string()
     }
-    fn do_run_script(&self, payload: &str, params: &Map<String, JsonValue>) -> Result<JsonValue> {
+    /// Transpile the CozoScript query passed in into an equivalent SQL statement.
+    /// Only non-recursive queries touching stored relations are supported.
+    /// The `dialect` argument is one of `postgres`, `sqlite` or `mysql`.
+    pub fn transpile_to_sql(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+        dialect: &str,
+    ) -> Result<String> {
+        let dialect = SqlDialect::from_name(dialect)?;
+        let param_pool = params
+            .iter()
+            .map(|(k, v)| (k.clone(), DataValue::from(v)))
+            .collect();
+        let program =
+            parse_script(payload, &param_pool, &self.custom_algos)?.get_single_program()?;
+        let tx = self.transact()?;
+        tx.transpile_to_sql(&program, dialect)
+    }
+    /// Run a single read-only query and return a cursor over its rows, instead of
+    /// converting all of them into one JSON value as [`Db::run_script`] does. Where possible,
+    /// the rows are derived only as they are pulled from the cursor: see [`QueryCursor`] for
+    /// when the query is instead evaluated in full before this returns.
+    /// System ops and queries writing to stored relations are rejected.
+    pub fn run_query_cursor(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+    ) -> Result<QueryCursor> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Queries run with a cursor cannot write to stored relations")]
+        #[diagnostic(code(eval::cursor_write))]
+        #[diagnostic(help("Use `run_script` for queries with `:create`, `:put` and the like"))]
+        struct CursorWriteError;
+
+        let in_flight = self.admit()?;
+        let param_pool = params
+            .iter()
+            .map(|(k, v)| (k.clone(), DataValue::from(v)))
+            .collect();
+        let program =
+            parse_script(payload, &param_pool, &self.custom_algos)?.get_single_program()?;
+        ensure!(program.out_opts.store_relation.is_none(), CursorWriteError);
+        let headers = match program.get_entry_out_head() {
+            Err(_) => vec![],
+            Ok(headers) => headers.into_iter().map(|v| v.name.to_string()).collect(),
+        };
+        self.query_cursor(program, payload, headers, in_flight)
+    }
+    /// Run a read-only query built with [`QueryBuilder`](crate::QueryBuilder) instead of
+    /// written as a script, returning a cursor over its rows as [`Db::run_query_cursor`]
+    /// does. Values in the query are used as they are, never pasted into script text.
+    pub fn run_built_query(&self, query: &QueryBuilder) -> Result<QueryCursor> {
+        let in_flight = self.admit()?;
+        let program = query.to_program()?;
+        let headers = program
+            .get_entry_out_head()?
+            .into_iter()
+            .map(|v| v.name.to_string())
+            .collect();
+        let payload = program.to_string();
+        self.query_cursor(program, &payload, headers, in_flight)
+    }
+    /// Apply a fixed rule to stored relations as built with [`AlgoCall`](crate::AlgoCall) or one
+    /// of its typed builders, without writing a script, and convert the rows it returns.
+    pub fn run_algo<C: TypedAlgoCall>(&self, call: C) -> Result<C::Output> {
+        let _in_flight = self.admit()?;
+        let call = call.into_call();
+        let program = call.to_program(&self.custom_algos)?;
+        let mut tx = self.transact()?;
+        let (rows, _in_mem_guard) = self.query_rows(&mut tx, program, &call.to_string())?;
+        C::convert(
+            rows.map(|tuple| tuple.0.into_iter().map(JsonValue::from).collect())
+                .collect(),
+        )
+    }
+    /// Create the namespace `name`, after which relations named `name.rel` can be created.
+    pub fn create_namespace(&self, name: &str) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        tx.create_namespace(name)?;
+        tx.commit_tx()
+    }
+    /// Remove the namespace `name` together with all of its relations, returning the number
+    /// of relations removed. Nothing is removed if any of them cannot be.
+    pub fn remove_namespace(&self, name: &str) -> Result<usize> {
+        let mut tx = self.transact_write()?;
+        let relations = tx.namespace_relations(name)?;
+        ensure!(
+            tx.remove_namespace(name)?,
+            NamespaceNotFound(name.to_string())
+        );
+        for rel in &relations {
+            self.remove_relation(&Symbol::new(rel.clone(), Default::default()), &mut tx)?;
+        }
+        tx.commit_tx()?;
+        Ok(relations.len())
+    }
+    /// The names of all namespaces.
+    pub fn list_namespaces(&self) -> Result<Vec<String>> {
+        Ok(self
+            .transact()?
+            .list_namespaces()?
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect())
+    }
+    /// Set the limits on the use of the namespace `name`, replacing any set before.
+    pub fn set_namespace_quota(&self, name: &str, quota: NamespaceQuota) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        tx.set_namespace_quota(name, &quota)?;
+        tx.commit_tx()
+    }
+    /// Evaluates a query not writing to stored relations within `tx`, returning its rows with
+    /// the sorting, offset and limit of the query applied.
+    pub(crate) fn query_rows(
+        &self,
+        tx: &mut SessionTx,
+        mut program: InputProgram,
+        payload: &str,
+    ) -> Result<(Box<dyn Iterator<Item = Tuple> + Send>, GaugeGuard)> {
+        tx.expand_graph_views(&mut program)?;
+        self.check_program_access(tx, &program)?;
+        let hash = script_hash(payload);
+        let EvaluatedQuery {
+            result,
+            early_return,
+            in_mem_guard,
+            ..
+        } = self.evaluate_query(tx, &program, Some((&hash, payload)), false)?;
+        let rows = evaluated_rows(tx, &program, result, early_return)?;
+        Ok((rows, in_mem_guard))
+    }
+    /// Evaluates the read-only `program` in a transaction of its own, returning a cursor over
+    /// its rows pulling them lazily where possible, and holding `in_flight` until then.
+    fn query_cursor(
+        &self,
+        mut program: InputProgram,
+        payload: &str,
+        headers: Vec<String>,
+        in_flight: InFlightScript,
+    ) -> Result<QueryCursor> {
+        let mut tx = self.transact()?;
+        tx.expand_graph_views(&mut program)?;
+        self.check_program_access(&mut tx, &program)?;
+        let hash = script_hash(payload);
+        let EvaluatedQuery {
+            result,
+            early_return,
+            in_mem_guard,
+            lazy_entry,
+            ..
+        } = self.evaluate_query(&mut tx, &program, Some((&hash, payload)), true)?;
+        let lazy_entry = match lazy_entry {
+            None => {
+                let rows = evaluated_rows(&mut tx, &program, result, early_return)?;
+                return Ok(QueryCursor {
+                    headers,
+                    rows: Box::new(rows.map(Ok)),
+                    poison: None,
+                    _in_flight: None,
+                    _in_mem_guard: Some(in_mem_guard),
+                });
+            }
+            Some(entry) => entry,
+        };
+        let poison = lazy_entry.poison.clone();
+        let offset = program.out_opts.offset.unwrap_or(0);
+        let limit = program.out_opts.limit.unwrap_or(usize::MAX);
+        // rows are derived only once the previous one has been taken
+        let (sender, receiver) = sync_channel(0);
+        let tx = CursorTx(tx);
+        thread::Builder::new()
+            .name("cozo-cursor".to_string())
+            .spawn(move || {
+                let tx = tx.into_inner();
+                let _in_mem_guard = in_mem_guard;
+                if let Err(err) = send_entry_rows(&tx, lazy_entry, &result, offset, limit, &sender)
+                {
+                    // the cursor may have been dropped already
+                    let _ = sender.send(Err(err));
+                }
+            })
+            .into_diagnostic()?;
+        Ok(QueryCursor {
+            headers,
+            rows: Box::new(receiver.into_iter()),
+            poison: Some(poison),
+            _in_flight: Some(in_flight),
+            _in_mem_guard: None,
+        })
+    }
+    /// Start a transaction on which several scripts can be run, all of whose writes
+    /// are committed or rolled back together.
+    pub fn multi_transact(&self) -> Result<MultiTransaction> {
+        let in_flight = self.admit()?;
+        Ok(MultiTransaction {
+            db: self.clone(),
+            tx: self.transact_write()?,
+            cleanups: vec![],
+            written: Default::default(),
+            _in_flight: in_flight,
+        })
+    }
+    /// Describe everything an editor needs for completing scripts: the stored relations
+    /// with their columns, and the functions, aggregations and fixed rules available.
+    /// The lists are read from the same registries the parser resolves names against.
+    pub fn completion_metadata(&self) -> Result<JsonValue> {
+        let relations = self
+            .relation_handles()?
+            .into_iter()
+            .filter(|handle| handle.access_level != AccessLevel::Hidden)
+            .map(|handle| {
+                let columns = handle
+                    .metadata
+                    .keys
+                    .iter()
+                    .map(|col| (col, true))
+                    .chain(handle.metadata.non_keys.iter().map(|col| (col, false)))
+                    .map(|(col, is_key)| {
+                        json!({
+                            "name": col.name,
+                            "type": col.typing.to_string(),
+                            "is_key": is_key,
+                            "has_default": col.default_gen.is_some(),
+                        })
+                    })
+                    .collect_vec();
+                json!({"name": handle.name, "columns": columns})
+            })
+            .collect_vec();
+        let functions = OPS
+            .iter()
+            .map(|(name, op)| json!({"name": name, "min_arity": op.min_arity, "vararg": op.vararg}))
+            .collect_vec();
+        let aggregations = AGGRS
+            .iter()
+            .map(|(name, aggr)| (name.to_string(), aggr.is_meet))
+            .chain(list_user_aggrs())
+            .map(|(name, is_meet)| json!({"name": name, "is_meet": is_meet}))
+            .collect_vec();
+        let mut fixed_rules = vec![];
+        for algo in BUILTIN_ALGOS {
+            let arity = algo.default_arity();
+            for name in algo.names {
+                fixed_rules.push(json!({
+                    "name": name,
+                    "options": algo.options,
+                    "arity": arity,
+                }));
+            }
+        }
+        for name in self.custom_algos.keys() {
+            fixed_rules.push(json!({"name": name, "options": null, "arity": null}));
+        }
+        Ok(json!({
+            "relations": relations,
+            "functions": functions,
+            "aggregations": aggregations,
+            "fixed_rules": fixed_rules,
+        }))
+    }
+    /// Render engine metrics in the Prometheus text exposition format, including the state of
+    /// the background compactions of storage engines running them, such as RocksDB.
+    pub fn export_metrics(&self) -> String {
+        let running = self.running_queries.lock().unwrap().len();
+        self.metrics
+            .render_prometheus(running, self.db.compaction_stats())
+    }
+    /// Run the queries scheduled with `::schedule create` whose interval has passed since
+    /// they last ran, returning how many succeeded. Each run replaces the rows of the
+    /// result relation of the query, and those of its delta relation with the rows added
+    /// to and removed from the result since the previous run.
+    /// A query that fails is logged and tried again once its interval has passed again.
+    pub fn run_due_scheduled_queries(&self) -> Result<usize> {
+        let _in_flight = self.admit()?;
+        let now = current_validity();
+        let due = self
+            .transact()?
+            .list_schedules()?
+            .into_iter()
+            .filter(|(_, query)| query.is_due(now))
+            .collect_vec();
+        let mut succeeded = 0;
+        for (name, mut query) in due {
+            query.last_run = Some(now);
+            match self.run_scheduled_query(&name, &query) {
+                Ok(()) => succeeded += 1,
+                Err(err) => {
+                    warn!("scheduled query '{}' failed: {:?}", name, err);
+                    let mut tx = self.transact_write()?;
+                    if tx.get_schedule(&name)?.is_some() {
+                        tx.put_schedule(&name, &query)?;
+                    }
+                    tx.commit_tx()?;
+                }
+            }
+        }
+        Ok(succeeded)
+    }
+    fn run_scheduled_query(&self, name: &str, query: &ScheduledQuery) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        // the schedule may have been removed since it was found due
+        if tx.get_schedule(name)?.is_none() {
+            return Ok(());
+        }
+        let cleanups = tx.run_scheduled_query(self, query)?;
+        tx.put_schedule(name, query)?;
+        let written = written_relations(&tx);
+        self.append_audit(&mut tx, &query.script)?;
+        tx.commit_tx()?;
+        for (lower, upper) in cleanups {
+            self.db.range_del(&lower, &upper)?;
+        }
+        self.refresh_continuous_queries(Some(&written));
+        Ok(())
+    }
+    /// Run the jobs created with `::job create` whose next run is due, returning how many
+    /// succeeded. Each run is recorded in the history of the job, shown by `::job history`.
+    /// A job that fails is logged and run again at the next time its cron expression gives.
+    pub fn run_due_jobs(&self) -> Result<usize> {
+        let _in_flight = self.admit()?;
+        let now = current_validity();
+        let due = self
+            .transact()?
+            .list_jobs()?
+            .into_iter()
+            .filter(|(_, job)| matches!(job.next_run, Some(at) if at <= now))
+            .collect_vec();
+        let mut succeeded = 0;
+        for (name, job) in due {
+            match self.run_job(&name, &job, true)?.error {
+                None => succeeded += 1,
+                Some(err) => warn!("job '{}' failed: {}", name, err),
+            }
+        }
+        Ok(succeeded)
+    }
+    /// Runs the script of a job under the label of its creator and records the run. When
+    /// `scheduled`, the next run of the job is moved to the next time its cron expression gives.
+    fn run_job(&self, name: &str, job: &Job, scheduled: bool) -> Result<JobRun> {
+        let started = current_validity();
+        let error = self
+            .run_script_labelled(&job.script, &Default::default(), job.label.as_deref())
+            .err()
+            .map(|err| err.to_string());
+        let run = JobRun {
+            started,
+            finished: current_validity(),
+            error,
+        };
+        let mut tx = self.transact_write()?;
+        // the job may have been removed while it ran
+        if let Some(mut job) = tx.get_job(name)? {
+            job.last_run = Some(started);
+            if run.error.is_none() {
+                job.failures = 0;
+            } else {
+                job.failures += 1;
+            }
+            if scheduled {
+                job.next_run = job.next_run_after(started)?;
+            }
+            tx.put_job(name, &job)?;
+            tx.record_job_run(name, &run)?;
+        }
+        tx.commit_tx()?;
+        Ok(run)
+    }
+    /// Bulk-load a CSV file into the existing stored relation `relation`, returning the
+    /// number of rows imported. `source` is the path of the file, optionally starting with
+    /// `file://`, or an `http(s)://` URL. Fields are converted to the types of the columns
+    /// they go into, and columns missing from the source get their default values.
+    /// Rows are written in batches of `options.batch_size`, all in one transaction: if any
+    /// row fails to convert, nothing is imported.
+    pub fn import_from_csv(
+        &self,
+        relation: &str,
+        source: &str,
+        options: &CsvImportOptions,
+    ) -> Result<usize> {
+        let _in_flight = self.admit()?;
+        let mut tx = self.transact_write()?;
+        let (n_imported, cleanups) = tx.import_csv(self, relation, source, options)?;
+        let written = written_relations(&tx);
+        tx.commit_tx()?;
+        for (lower, upper) in cleanups {
+            self.db.range_del(&lower, &upper)?;
+        }
+        self.refresh_continuous_queries(Some(&written));
+        Ok(n_imported)
+    }
+    /// Write everything stored, that is all stored relations together with their metadata,
+    /// triggers, indices and the rest of the catalog, to a new archive file at `path`.
+    /// The archive is read from a snapshot taken by a single transaction, so it is consistent
+    /// even though queries keep running and writing meanwhile.
+    /// Rows offloaded to the cold storage are included.
+    ///
+    /// If the database keeps a replication log, the archive records the position in the log
+    /// of the snapshot, from which followers restoring the archive start replicating.
+    pub fn backup(&self, path: impl AsRef<Path>) -> Result<()> {
+        let (tx, position) = match &self.replication {
+            None => (self.transact()?, None),
+            Some(log) => {
+                let (tx, position) = log.at_position(|| self.transact())?;
+                (tx, Some(position))
+            }
+        };
+        let position_key = position_key();
+        let last_id = tx.load_last_relation_store_id()?.0;
+        let mut archive = BackupWriter::create(path.as_ref())?;
+        let stores = std::iter::once((BackupStore::Main, &tx.tx))
+            .chain(tx.cold.iter().map(|cold| (BackupStore::Cold, cold)));
+        for (store, store_tx) in stores {
+            // relations are scanned one at a time, as storage engines may only be able to
+            // scan efficiently within the same prefix
+            for id in 0..=last_id {
+                let lower = Tuple::default().encode_as_key(RelationId(id));
+                let upper = Tuple::default().encode_as_key(RelationId(id + 1));
+                for pair in store_tx.range_scan(&lower, &upper) {
+                    let (key, val) = pair?;
+                    if position.is_some() && key == position_key {
+                        continue;
+                    }
+                    archive.write(store, &key, &val)?;
+                }
+            }
+        }
+        if let Some(position) = position {
+            archive.write(BackupStore::Main, &position_key, &position.encode()?)?;
+        }
+        archive.finish()
+    }
+    /// Restore an archive written by [`Db::backup`] into this database, which must not hold
+    /// any stored relation yet, apart from the audit log, which is replaced by the one of the
+    /// archive. The archive is checked in full before anything is written. Rows are then
+    /// written in batches, so that the database should not be used until this returns.
+    pub fn restore(&self, path: impl AsRef<Path>) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot restore into a database holding stored relations: {0:?}")]
+        #[diagnostic(code(db::restore_into_non_empty))]
+        struct RestoreIntoNonEmpty(Vec<String>);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("The backup has offloaded rows but the database has no cold storage")]
+        #[diagnostic(code(db::restore_without_cold_storage))]
+        struct RestoreWithoutColdStorage;
+
+        const RESTORE_BATCH: usize = 10000;
+
+        ensure!(!self.read_only, ReadOnlyDb);
+        let path = path.as_ref();
+        let existing = self
+            .relation_handles()?
+            .into_iter()
+            .filter(|handle| handle.name.as_str() != AUDIT_LOG)
+            .map(|handle| handle.name.to_string())
+            .collect_vec();
+        ensure!(existing.is_empty(), RestoreIntoNonEmpty(existing));
+        for pair in BackupReader::open(path)? {
+            let (store, _, _) = pair?;
+            ensure!(
+                store == BackupStore::Main || self.cold_storage.is_some(),
+                RestoreWithoutColdStorage
+            );
+        }
+
+        let lower = Tuple::default().encode_as_key(RelationId::SYSTEM);
+        let upper = Tuple(vec![DataValue::Bot]).encode_as_key(RelationId(u64::MAX));
+        // the audit log and the catalog are still readable, so they are deleted key by key
+        for storage in std::iter::once(&self.db).chain(&self.cold_storage) {
+            let mut tx = storage.transact()?;
+            tx.range_del(&lower, &upper)?;
+            tx.commit()?;
+        }
+        let mut batches = vec![(self.db.transact()?, 0)];
+        if let Some(cold) = &self.cold_storage {
+            batches.push((cold.transact()?, 0));
+        }
+        for pair in BackupReader::open(path)? {
+            let (store, key, val) = pair?;
+            let (storage, (tx, n)) = match store {
+                BackupStore::Main => (&self.db, &mut batches[0]),
+                BackupStore::Cold => (self.cold_storage.as_ref().unwrap(), &mut batches[1]),
+            };
+            tx.put(&key, &val)?;
+            *n += 1;
+            if *n == RESTORE_BATCH {
+                tx.commit()?;
+                *tx = storage.transact()?;
+                *n = 0;
+            }
+        }
+        for (mut tx, _) in batches {
+            tx.commit()?;
+        }
+        self.load_last_ids()?;
+        if self.audit && !self.follower {
+            let mut tx = self.transact_write()?;
+            tx.ensure_audit_log()?;
+            tx.commit_tx()?;
+        }
+        Ok(())
+    }
+    /// Stop admitting new scripts and wait for those in flight to finish, for at most
+    /// `timeout`. Storage is then flushed to disk. If scripts are still in flight when
+    /// `timeout` has passed, the queries among them are killed and an error naming them is
+    /// returned without waiting any longer, storage being flushed all the same.
+    pub fn close_gracefully(&self, timeout: Duration) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Timed out closing the database with {0} scripts in flight")]
+        #[diagnostic(code(db::close_timeout))]
+        #[diagnostic(help("The running queries {1:?} are killed"))]
+        struct CloseTimedOut(u64, Vec<u64>);
+
+        self.closing.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let in_flight = self.in_flight_scripts.load(Ordering::SeqCst);
+            if in_flight == 0 {
+                break;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                let mut killed = vec![];
+                for (id, handle) in self.running_queries.lock().unwrap().iter() {
+                    if !handle.poison.0.swap(true, Ordering::Relaxed) {
+                        killed.push(*id);
+                    }
+                }
+                self.db.flush()?;
+                bail!(CloseTimedOut(in_flight, killed));
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(10)));
+        }
+        self.db.flush()
+    }
+    /// Admits a script unless the database is closing, returning a guard counting it as in
+    /// flight until dropped.
+    fn admit(&self) -> Result<InFlightScript> {
+        // counted first, so that closing cannot miss a script admitted meanwhile
+        self.in_flight_scripts.fetch_add(1, Ordering::SeqCst);
+        let guard = InFlightScript(self.in_flight_scripts.clone());
+        ensure!(!self.closing.load(Ordering::SeqCst), DbClosing);
+        Ok(guard)
+    }
+    fn do_run_script(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+        label: Option<&str>,
+    ) -> Result<JsonValue> {
+        let _span = enter_span!("script", label);
+        let _in_flight = self.admit()?;
         let param_pool = params
             .iter()
             .map(|(k, v)| (k.clone(), DataValue::from(v)))
             .collect();
-        match parse_script(payload, &param_pool)? {
+        let parsed = {
+            let _span = enter_span!("parse");
+            parse_script(payload, &param_pool, &self.custom_algos)?
+        };
+        match parsed {
             CozoScript::Multi(ps) => {
                 let is_write = ps.iter().any(|p| p.out_opts.store_relation.is_some());
+                ensure!(!is_write || !self.read_only, ReadOnlyDb);
                 let mut tx = if is_write {
                     self.transact_write()?
                 } else {
                     self.transact()?
                 };
-                let mut res = json!(null);
-                let mut cleanups = vec![];
-                for p in ps {
-                    let sleep_opt = p.out_opts.sleep;
-                    let (q_res, q_cleanups) = self.run_query(&mut tx, p)?;
-                    res = q_res;
-                    cleanups.extend(q_cleanups);
-                    if let Some(secs) = sleep_opt {
-                        thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
-                    }
-                }
+                tx.script.label = label.map(|l| l.to_string());
+                let (mut res, cleanups) = self.run_programs(&mut tx, ps, payload)?;
+                let written = written_relations(&tx);
                 if is_write {
+                    self.append_audit(&mut tx, payload)?;
+                    let _span = enter_span!("commit");
                     tx.commit_tx()?;
+                    trace_event!("transaction committed");
+                    if let Some(stamp) = tx.script.version {
+                        res["tx_id"] = json!(stamp.tx);
+                    }
                 } else {
                     assert!(cleanups.is_empty(), "non-empty cleanups on read-only tx");
                 }
                 for (lower, upper) in cleanups {
                     self.db.range_del(&lower, &upper)?;
                 }
+                if !written.is_empty() {
+                    self.refresh_continuous_queries(Some(&written));
+                }
+                Ok(res)
+            }
+            CozoScript::Sys(mut op) => {
+                if let SysOp::CreateJob(_, job) = &mut op {
+                    job.label = label.map(|l| l.to_string());
+                }
+                let audited = audited_relations(&op);
+                ensure!(!self.read_only || !sys_op_writes(&op), ReadOnlyDb);
+                if self.access_policy.is_some() {
+                    let written = audited.iter().flatten().flatten().cloned().collect();
+                    self.check_access(label, sys_op_reads(&op), written)?;
+                }
+                let res = self.run_sys_op(op)?;
+                if let Some(relations) = audited {
+                    if self.audit {
+                        let mut tx = self.transact_write()?;
+                        tx.script.label = label.map(|l| l.to_string());
+                        tx.script.writes =
+                            relations.iter().map(|rel| (rel.clone(), None)).collect();
+                        self.append_audit(&mut tx, payload)?;
+                        tx.commit_tx()?;
+                    }
+                    // graph views are not relations, so changing one may affect any query
+                    match relations.into_iter().collect::<Option<BTreeSet<_>>>() {
+                        Some(written) => self.refresh_continuous_queries(Some(&written)),
+                        None => self.refresh_continuous_queries(None),
+                    }
+                }
                 Ok(res)
             }
-            CozoScript::Sys(op) => self.run_sys_op(op),
         }
     }
-    fn explain_compiled(&self, strata: &[CompiledProgram]) -> Result<JsonValue> {
+    /// Records the writes made within `tx` by `command` in the audit log, if it is kept.
+    fn append_audit(&self, tx: &mut SessionTx, command: &str) -> Result<()> {
+        let writes = mem::take(&mut tx.script.writes);
+        if !self.audit {
+            return Ok(());
+        }
+        let label = tx.script.label.clone();
+        tx.append_audit_entries(
+            label.as_deref(),
+            command,
+            writes,
+            &self.audit_seq,
+            self.audit_retention,
+        )
+    }
+    /// Run the queries of a script in order within `tx`, returning the result of the last one
+    /// and the key ranges to delete once the transaction is committed.
+    fn run_programs(
+        &self,
+        tx: &mut SessionTx,
+        ps: Vec<InputProgram>,
+        payload: &str,
+    ) -> Result<(JsonValue, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let mut res = json!(null);
+        let mut cleanups = vec![];
+        let hash = script_hash(payload);
+        let n_progs = ps.len();
+        for (i, p) in ps.into_iter().enumerate() {
+            let sleep_opt = p.out_opts.sleep;
+            let plan_key = if n_progs == 1 {
+                hash.clone()
+            } else {
+                format!("{}-{}", hash, i)
+            };
+            let (q_res, q_cleanups) = self.run_query(tx, p, Some((&plan_key, payload)))?;
+            res = q_res;
+            cleanups.extend(q_cleanups);
+            if let Some(secs) = sleep_opt {
+                thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
+            }
+        }
+        Ok((res, cleanups))
+    }
+    /// Describe the compiled program row by row. With `mask_consts`, constants in
+    /// expressions are masked, giving a plan signature independent of parameter values.
+    fn explain_compiled(&self, strata: &[CompiledProgram], mask_consts: bool) -> Result<JsonValue> {
+        let render = |e: &Expr| {
+            if mask_consts {
+                e.shape()
+            } else {
+                e.to_string()
+            }
+        };
         let mut ret: Vec<JsonValue> = vec![];
         const STRATUM: &str = "stratum";
         const ATOM_IDX: &str = "atom_idx";
@@ -349,15 +1454,13 @@ impl Db {
                                         "load_mem",
                                         json!(storage.rule_name.to_string()),
                                         json!(null),
-                                        json!(filters.iter().map(|f| f.to_string()).collect_vec()),
+                                        json!(filters.iter().map(&render).collect_vec()),
                                     ),
-                                    RelAlgebra::Stored(StoredRA {
-                                        storage, filters, ..
-                                    }) => (
+                                    RelAlgebra::Stored(stored) => (
                                         "load_stored",
-                                        json!(format!(":{}", storage.name)),
+                                        json!(format!(":{}", stored.storage.name)),
                                         json!(null),
-                                        json!(filters.iter().map(|f| f.to_string()).collect_vec()),
+                                        json!(stored.filters.iter().map(&render).collect_vec()),
                                     ),
                                     RelAlgebra::Join(inner) => {
                                         if inner.left.is_unit() {
@@ -397,7 +1500,7 @@ impl Db {
                                             "filter",
                                             json!(null),
                                             json!(null),
-                                            json!(pred.iter().map(|f| f.to_string()).collect_vec()),
+                                            json!(pred.iter().map(&render).collect_vec()),
                                         )
                                     }
                                     RelAlgebra::Unification(UnificationRA {
@@ -412,7 +1515,7 @@ impl Db {
                                             if *is_multi { "multi-unify" } else { "unify" },
                                             json!(binding.name),
                                             json!(null),
-                                            json!(expr.to_string()),
+                                            json!(render(expr)),
                                         )
                                     }
                                 };
@@ -458,23 +1561,52 @@ impl Db {
     }
     fn run_sys_op(&self, op: SysOp) -> Result<JsonValue> {
         match op {
-            SysOp::Explain(prog) => {
+            SysOp::Explain(mut prog) => {
                 let mut tx = self.transact()?;
+                tx.expand_graph_views(&mut prog)?;
                 let program = prog
                     .to_normalized_program(&tx)?
                     .stratify()?
                     .magic_sets_rewrite(&tx)?;
                 let (compiled, _) = tx.stratified_magic_compile(&program)?;
 
-                self.explain_compiled(&compiled)
-            }
-            SysOp::Compact => {
-                self.compact_relation()?;
-                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+                self.explain_compiled(&compiled, false)
             }
-            SysOp::ListRelations => self.list_relations(),
-            SysOp::RemoveRelation(rel_names) => {
-                let mut tx = self.transact_write()?;
+            SysOp::Estimate(mut prog) => {
+                let mut tx = self.transact()?;
+                tx.expand_graph_views(&mut prog)?;
+                let num_to_take = prog.out_opts.num_to_take();
+                let program = prog
+                    .to_normalized_program(&tx)?
+                    .stratify()?
+                    .magic_sets_rewrite(&tx)?;
+                let (compiled, _) = tx.stratified_magic_compile(&program)?;
+                let rows = tx
+                    .estimate_compiled(&compiled, num_to_take)?
+                    .into_iter()
+                    .map(|est| {
+                        json!([
+                            est.stratum,
+                            est.rule.to_string(),
+                            est.rows.ceil() as u64,
+                            est.cost.ceil() as u64
+                        ])
+                    })
+                    .collect_vec();
+                Ok(json!({"headers": ["stratum", "rule", "rows", "cost"], "rows": rows}))
+            }
+            SysOp::Compact => {
+                self.metrics
+                    .manual_compactions
+                    .fetch_add(1, Ordering::Relaxed);
+                self.compact_relation()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::ListRelations => self.list_relations(),
+            SysOp::ListFunctions => self.list_functions(),
+            SysOp::ListAlgos => self.list_algos(),
+            SysOp::RemoveRelation(rel_names) => {
+                let mut tx = self.transact_write()?;
                 for rs in rel_names {
                     self.remove_relation(&rs, &mut tx)?;
                 }
@@ -482,6 +1614,8 @@ impl Db {
                 Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
             }
             SysOp::ListRelation(rs) => self.list_relation(&rs),
+            SysOp::RelationStats(rs) => self.relation_stats(&rs),
+            SysOp::RelationChecksum(rs) => self.relation_checksum(&rs),
             SysOp::RenameRelation(rename_pairs) => {
                 let mut tx = self.transact_write()?;
                 for (old, new) in rename_pairs {
@@ -490,6 +1624,89 @@ impl Db {
                 tx.commit_tx()?;
                 Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
             }
+            SysOp::CloneRelation(src, dst) => {
+                let mut tx = self.transact_write()?;
+                let copied = tx.clone_relation(src, dst)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status", "rows_copied"], "rows": [["OK", copied]]}))
+            }
+            SysOp::DeleteRange(name, from, to) => {
+                let mut tx = self.transact_write()?;
+                tx.delete_range(&name, from, to)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::TruncateRelation(name) => {
+                let mut tx = self.transact_write()?;
+                tx.truncate_relation(&name)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::SetRelationHistory(name, retain) => {
+                let mut tx = self.transact_write()?;
+                tx.set_relation_history(&name, retain)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::SetRelationLww(name, maintain) => {
+                let mut tx = self.transact_write()?;
+                tx.set_relation_lww(&name, maintain)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::SetRelationSoftDelete(name, soft) => {
+                let mut tx = self.transact_write()?;
+                tx.set_relation_soft_delete(&name, soft)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::PurgeRelation(name) => {
+                let mut tx = self.transact_write()?;
+                tx.purge_relation(&name)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::SetRelationTiering(name, after) => {
+                let mut tx = self.transact_write()?;
+                tx.set_relation_tiering(&name, after)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::OffloadRelation(name) => {
+                let mut tx = self.transact_write()?;
+                let n_offloaded = tx.offload_relation(&name)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["offloaded"], "rows": [[n_offloaded]]}))
+            }
+            SysOp::ImportRemote(url, relations, auth) => {
+                let endpoint = format!("{}/sync", url.trim_end_matches('/'));
+                let peer = |req: &[u8]| post_sync_request(&endpoint, auth.as_deref(), req);
+                let mut rows = vec![];
+                for (rel, from, to) in relations {
+                    let n_imported = self.import_relation(&url, &rel.name, from, to, peer)?;
+                    rows.push(json!([rel.name, n_imported]));
+                }
+                Ok(json!({"headers": ["relation", "imported"], "rows": rows}))
+            }
+            SysOp::ImportInferred(name, import) => {
+                let mut tx = self.transact_write()?;
+                let (report, cleanups) = tx.import_inferred(self, &name, &import)?;
+                tx.commit_tx()?;
+                for (lower, upper) in cleanups {
+                    self.db.range_del(&lower, &upper)?;
+                }
+                Ok(report)
+            }
+            SysOp::ImportCsv(name, source, options) => {
+                let n_imported = self.import_from_csv(&name, &source, &options)?;
+                Ok(json!({"headers": ["imported"], "rows": [[n_imported]]}))
+            }
+            SysOp::SetVectorIndex(name, config) => {
+                let mut tx = self.transact_write()?;
+                tx.set_vector_index(&name, config)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
             SysOp::ListRunning => self.list_running(),
             SysOp::KillRunning(id) => {
                 let queries = self.running_queries.lock().unwrap();
@@ -532,52 +1749,396 @@ impl Db {
                 tx.commit_tx()?;
                 Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
             }
-        }
-    }
-    pub(crate) fn run_query(
-        &self,
-        tx: &mut SessionTx,
-        input_program: InputProgram,
-    ) -> Result<(JsonValue, Vec<(Vec<u8>, Vec<u8>)>)> {
-        let mut clean_ups = vec![];
-        if let Some((meta, op)) = &input_program.out_opts.store_relation {
-            if *op == RelationOp::Create {
-                #[derive(Debug, Error, Diagnostic)]
-                #[error("Stored relation {0} conflicts with an existing one")]
-                #[diagnostic(code(eval::stored_relation_conflict))]
-                struct StoreRelationConflict(String);
+            SysOp::ListPlans => {
+                let tx = self.transact()?;
+                let pinned = tx.list_pinned_plans()?;
+                let mut rows = vec![];
+                for (hash, plan) in &pinned {
+                    rows.push(json!([hash, true, plan.script]));
+                }
+                for (hash, plan) in self.captured_plans.lock().unwrap().iter() {
+                    if !pinned.iter().any(|(h, _)| h == hash) {
+                        rows.push(json!([hash, false, plan.script]));
+                    }
+                }
+                Ok(json!({"headers": ["hash", "pinned", "script"], "rows": rows}))
+            }
+            SysOp::PinPlan(hash) => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("No plan has been captured for script hash {0}")]
+                #[diagnostic(code(db::plan_not_captured))]
+                #[diagnostic(help("Run the script first so that its plan is captured"))]
+                struct PlanNotCaptured(String);
+
+                let plan = self
+                    .captured_plans
+                    .lock()
+                    .unwrap()
+                    .get(&hash)
+                    .cloned()
+                    .ok_or_else(|| PlanNotCaptured(hash.clone()))?;
+                let mut tx = self.transact_write()?;
+                tx.pin_plan(&hash, &plan)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::UnpinPlan(hash) => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("No plan is pinned for script hash {0}")]
+                #[diagnostic(code(db::plan_not_pinned))]
+                struct PlanNotPinned(String);
+
+                let mut tx = self.transact_write()?;
+                ensure!(tx.unpin_plan(&hash)?, PlanNotPinned(hash));
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::SetGraphView(name, view) => {
+                let mut tx = self.transact_write()?;
+                // make sure the parts refer to existing relations before storing them
+                for script in [&view.nodes, &view.edges].into_iter().flatten() {
+                    let program = parse_script(script, &Default::default(), &self.custom_algos)?
+                        .get_single_program()?
+                        .to_normalized_program(&tx)?
+                        .stratify()?
+                        .magic_sets_rewrite(&tx)?;
+                    tx.stratified_magic_compile(&program)?;
+                }
+                tx.put_graph_view(&name, &view)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::RemoveGraphView(name) => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("Graph view '{0}' not found")]
+                #[diagnostic(code(db::graph_view_not_found))]
+                struct GraphViewNotFound(String, #[label] SourceSpan);
 
+                let mut tx = self.transact_write()?;
                 ensure!(
-                    !tx.relation_exists(&meta.name)?,
-                    StoreRelationConflict(meta.name.to_string())
-                )
-            } else if *op != RelationOp::Replace {
-                #[derive(Debug, Error, Diagnostic)]
-                #[error("Stored relation {0} not found")]
-                #[diagnostic(code(eval::stored_relation_not_found))]
-                struct StoreRelationNotFoundError(String);
+                    tx.remove_graph_view(&name)?,
+                    GraphViewNotFound(name.to_string(), name.span)
+                );
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::ListGraphViews => {
+                let tx = self.transact()?;
+                let rows = tx
+                    .list_graph_views()?
+                    .into_iter()
+                    .map(|(name, view)| json!([name, view.nodes, view.edges]))
+                    .collect_vec();
+                Ok(json!({"headers": ["name", "nodes", "edges"], "rows": rows}))
+            }
+            SysOp::CreateNamespace(name) => {
+                self.create_namespace(&name)?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::RemoveNamespace(name) => {
+                let removed = self.remove_namespace(&name)?;
+                Ok(json!({"headers": ["status", "relations_removed"], "rows": [["OK", removed]]}))
+            }
+            SysOp::SetNamespaceQuota(name, quota) => {
+                self.set_namespace_quota(&name, quota)?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::ListNamespaces => {
+                let tx = self.transact()?;
+                let mut rows = vec![];
+                for (name, quota) in tx.list_namespaces()? {
+                    let n_relations = tx.namespace_relations(&name)?.len();
+                    rows.push(json!([
+                        name,
+                        n_relations,
+                        quota.max_storage_bytes,
+                        quota.max_result_rows,
+                        quota.max_query_secs
+                    ]));
+                }
+                Ok(json!({
+                    "headers": [
+                        "name",
+                        "n_relations",
+                        "max_storage_bytes",
+                        "max_result_rows",
+                        "max_query_secs"
+                    ],
+                    "rows": rows
+                }))
+            }
+            SysOp::CreateSchedule(name, query) => {
+                let mut tx = self.transact_write()?;
+                // make sure the query refers to existing relations before storing it
+                let program = parse_script(&query.script, &Default::default(), &self.custom_algos)?
+                    .get_single_program()?
+                    .to_normalized_program(&tx)?
+                    .stratify()?
+                    .magic_sets_rewrite(&tx)?;
+                tx.stratified_magic_compile(&program)?;
+                tx.put_schedule(&name, &query)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::RemoveSchedule(name) => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("Scheduled query '{0}' not found")]
+                #[diagnostic(code(db::schedule_not_found))]
+                struct ScheduleNotFound(String, #[label] SourceSpan);
 
-                let existing = tx.get_relation(&meta.name, true)?;
+                let mut tx = self.transact_write()?;
+                ensure!(
+                    tx.remove_schedule(&name)?,
+                    ScheduleNotFound(name.to_string(), name.span)
+                );
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::ListSchedules => {
+                let tx = self.transact()?;
+                let rows = tx
+                    .list_schedules()?
+                    .into_iter()
+                    .map(|(name, query)| {
+                        json!([
+                            name,
+                            query.every,
+                            query.result,
+                            query.delta,
+                            query.last_run,
+                            query.script
+                        ])
+                    })
+                    .collect_vec();
+                Ok(json!({
+                    "headers": ["name", "every", "into", "delta", "last_run", "script"],
+                    "rows": rows
+                }))
+            }
+            SysOp::CreateJob(name, mut job) => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("The cron expression '{0}' of job '{1}' never fires")]
+                #[diagnostic(code(db::cron_never_fires))]
+                struct CronNeverFires(String, String, #[label] SourceSpan);
 
+                job.next_run = job.next_run_after(current_validity())?;
                 ensure!(
-                    tx.relation_exists(&meta.name)?,
-                    StoreRelationNotFoundError(meta.name.to_string())
+                    job.next_run.is_some(),
+                    CronNeverFires(job.cron.clone(), name.to_string(), name.span)
                 );
+                let mut tx = self.transact_write()?;
+                tx.put_job(&name, &job)?;
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::RemoveJob(name) => {
+                let mut tx = self.transact_write()?;
+                ensure!(
+                    tx.remove_job(&name)?,
+                    JobNotFound(name.to_string(), name.span)
+                );
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::RunJob(name) => {
+                let job = self
+                    .transact()?
+                    .get_job(&name)?
+                    .ok_or_else(|| JobNotFound(name.to_string(), name.span))?;
+                let run = self.run_job(&name, &job, false)?;
+                Ok(json!({
+                    "headers": ["started", "finished", "ok", "error"],
+                    "rows": [[run.started, run.finished, run.error.is_none(), run.error]]
+                }))
+            }
+            SysOp::ShowJobHistory(name) => {
+                let tx = self.transact()?;
+                ensure!(
+                    tx.get_job(&name)?.is_some(),
+                    JobNotFound(name.to_string(), name.span)
+                );
+                let rows = tx
+                    .job_history(&name)?
+                    .into_iter()
+                    .map(|(_, run)| {
+                        json!([run.started, run.finished, run.error.is_none(), run.error])
+                    })
+                    .collect_vec();
+                Ok(json!({"headers": ["started", "finished", "ok", "error"], "rows": rows}))
+            }
+            SysOp::ListJobs => {
+                let tx = self.transact()?;
+                let rows = tx
+                    .list_jobs()?
+                    .into_iter()
+                    .map(|(name, job)| {
+                        json!([
+                            name,
+                            job.cron,
+                            job.next_run,
+                            job.last_run,
+                            job.failures,
+                            job.script
+                        ])
+                    })
+                    .collect_vec();
+                Ok(json!({
+                    "headers": ["name", "cron", "next_run", "last_run", "failures", "script"],
+                    "rows": rows
+                }))
+            }
+            SysOp::CreateMaintained(name, rule) => {
+                let mut tx = self.transact_write()?;
+                let cleanups = tx.create_maintained(self, &name, &rule)?;
+                tx.commit_tx()?;
+                for (lower, upper) in cleanups {
+                    self.db.range_del(&lower, &upper)?;
+                }
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::RemoveMaintained(name) => {
+                #[derive(Debug, Diagnostic, Error)]
+                #[error("Relation '{0}' is not maintained")]
+                #[diagnostic(code(db::not_maintained))]
+                struct NotMaintained(String, #[label] SourceSpan);
 
-                existing.ensure_compatible(meta)?;
+                let mut tx = self.transact_write()?;
+                ensure!(
+                    tx.remove_maintained(&name)?,
+                    NotMaintained(name.to_string(), name.span)
+                );
+                tx.commit_tx()?;
+                Ok(json!({"headers": ["status"], "rows": [["OK"]]}))
+            }
+            SysOp::ListMaintained => {
+                let tx = self.transact()?;
+                let rows = tx
+                    .list_maintained()?
+                    .into_iter()
+                    .map(|(name, rule)| {
+                        let columns = rule
+                            .columns
+                            .iter()
+                            .map(|col| format!("{}: {}", col.name, col.aggr))
+                            .collect_vec();
+                        json!([name, rule.base, rule.by, columns])
+                    })
+                    .collect_vec();
+                Ok(json!({"headers": ["name", "from", "by", "columns"], "rows": rows}))
             }
+        }
+    }
+    /// Capture the plan chosen for a script, and make sure it is the same as the pinned one, if any.
+    fn check_plan(
+        &self,
+        tx: &SessionTx,
+        hash: &str,
+        script: &str,
+        compiled: &[CompiledProgram],
+    ) -> Result<()> {
+        #[derive(Debug, Diagnostic, Error)]
+        #[error("The plan for script hash {0} deviates from the pinned plan")]
+        #[diagnostic(code(eval::plan_deviates_from_pinned))]
+        #[diagnostic(help(
+            "Compare with the output of '::explain', then either rewrite the script, \
+re-pin its current plan with '::plan pin <hash>', or remove the pin with '::plan unpin <hash>'"
+        ))]
+        struct PlanDeviatesFromPinned(String);
+
+        let signature = self.explain_compiled(compiled, true)?;
+        let pinned = tx.get_pinned_plan(hash)?;
+        let deviates = matches!(&pinned, Some(p) if p.signature != signature);
+        {
+            let mut captured = self.captured_plans.lock().unwrap();
+            if captured.len() >= MAX_CAPTURED_PLANS && !captured.contains_key(hash) {
+                if let Some(evicted) = captured.keys().next().cloned() {
+                    captured.remove(&evicted);
+                }
+            }
+            captured.insert(
+                hash.to_string(),
+                CapturedPlan {
+                    script: script.to_string(),
+                    signature,
+                },
+            );
+        }
+        ensure!(!deviates, PlanDeviatesFromPinned(hash.to_string()));
+        Ok(())
+    }
+    /// Evaluate a single program and check its assertions. The result relation of the
+    /// entry rule is returned unsorted, with `:limit` and `:offset` applied only when
+    /// the program has no sorters.
+    ///
+    /// With `lazy`, the entry rule is left out of the evaluation and returned instead when
+    /// its rows can be derived as they are pulled, the result relation being then empty.
+    /// The query options are then left set on `tx`, for the evaluation of the entry.
+    fn evaluate_query(
+        &self,
+        tx: &mut SessionTx,
+        input_program: &InputProgram,
+        plan_key: Option<(&str, &str)>,
+        lazy: bool,
+    ) -> Result<EvaluatedQuery> {
+        let mut warnings = vec![];
+        for deprecation in &input_program.deprecations {
+            warn!("{}", deprecation);
+            warnings.push(deprecation.to_string());
+        }
+        let (mut compiled, stores) = {
+            let _span = enter_span!("compile");
+            let normalized = input_program.to_normalized_program(tx)?;
+            for lint in normalized.cross_product_lints() {
+                if input_program.out_opts.strict {
+                    bail!(lint)
+                }
+                warn!("{}", lint);
+                warnings.push(lint.to_string());
+            }
+            if let Some(threshold) = self.full_scan_lint_rows {
+                for lint in normalized.full_scan_lints(tx, threshold)? {
+                    if input_program.out_opts.strict {
+                        bail!(lint)
+                    }
+                    warn!("{}", lint);
+                    warnings.push(lint.to_string());
+                }
+            }
+            let program = normalized.stratify()?.magic_sets_rewrite(tx)?;
+            tx.stratified_magic_compile(&program)?
+        };
+        if let Some((hash, script)) = plan_key {
+            self.check_plan(tx, hash, script, &compiled)?;
+        }
+        tx.plan_adaptive_joins(&mut compiled)?;
+        let entry = MagicSymbol::Muggle {
+            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        };
+        let lazy_rules = if lazy && can_pull_lazily(input_program, &compiled, &entry) {
+            compiled
+                .last_mut()
+                .and_then(|stratum| stratum.remove(&entry))
+        } else {
+            None
         };
-        let program = input_program
-            .to_normalized_program(tx)?
-            .stratify()?
-            .magic_sets_rewrite(tx)?;
-        let (compiled, stores) = tx.stratified_magic_compile(&program)?;
 
+        let quota = {
+            let mut used = input_program.stored_relations_read();
+            if let Some((meta, _)) = &input_program.out_opts.store_relation {
+                used.insert(meta.name.name.clone());
+            }
+            tx.quota_of_relations(used.iter().map(|name| name.as_str()))?
+        };
         let poison = Poison::default();
-        if let Some(secs) = input_program.out_opts.timeout {
+        let timeout = match (input_program.out_opts.timeout, quota.max_query_secs) {
+            (Some(secs), Some(max)) => Some(secs.min(max)),
+            (secs, max) => secs.or(max),
+        };
+        if let Some(secs) = timeout {
             poison.set_timeout(secs);
         }
         let id = self.queries_count.fetch_add(1, Ordering::AcqRel);
+        self.metrics.queries_run.fetch_add(1, Ordering::Relaxed);
 
         let now = SystemTime::now();
         let since_the_epoch = now
@@ -587,15 +2148,69 @@ impl Db {
 
         let handle = RunningQueryHandle {
             started_at: since_the_epoch,
+            label: tx.script.label.clone(),
             poison: poison.clone(),
         };
         self.running_queries.lock().unwrap().insert(id, handle);
-        let _guard = RunningQueryCleanup {
+        let _span = enter_span!("query", id);
+        let guard = RunningQueryCleanup {
             id,
             running_queries: self.running_queries.clone(),
         };
 
-        let (result, early_return) = tx.stratified_magic_evaluate(
+        let prev_tolerated = if input_program.out_opts.null_on_error {
+            tx.query.tolerated_errors.replace(AtomicU64::new(0))
+        } else {
+            tx.query.tolerated_errors.take()
+        };
+        let max_rows_scanned = input_program
+            .out_opts
+            .max_rows_scanned
+            .or(self.max_rows_scanned);
+        let max_intermediate_rows = input_program
+            .out_opts
+            .max_intermediate_rows
+            .or(self.max_intermediate_rows);
+        let prev_row_guard = if max_rows_scanned.is_some() || max_intermediate_rows.is_some() {
+            tx.query.row_guard.replace(Arc::new(RowGuard::new(
+                max_rows_scanned,
+                max_intermediate_rows,
+            )))
+        } else {
+            tx.query.row_guard.take()
+        };
+        let prev_include_deleted = mem::replace(
+            &mut tx.query.include_deleted,
+            input_program.out_opts.include_deleted,
+        );
+        let prev_algo_memory_budget = mem::replace(
+            &mut tx.query.algo_memory_budget,
+            input_program
+                .out_opts
+                .algo_memory_budget
+                .or(self.algo_memory_budget),
+        );
+        // a limit within the quota bounds the rows returned however many are derived
+        // `Option::is_none_or` would raise the minimum supported Rust version
+        #[allow(clippy::unnecessary_map_or)]
+        let prev_max_result_rows = mem::replace(
+            &mut tx.query.max_result_rows,
+            quota
+                .max_result_rows
+                .filter(|max| {
+                    input_program
+                        .out_opts
+                        .limit
+                        .map_or(true, |limit| limit > *max)
+                })
+                .map(|max| (max, input_program.out_opts.offset.unwrap_or(0) + max)),
+        );
+        let mut profile = input_program.out_opts.profile.then(QueryProfile::default);
+        if profile.is_some() {
+            tx.query.join_switches = Some(Default::default());
+        }
+        let mut in_mem_guard = GaugeGuard::new(self.metrics.clone(), |m| &m.in_mem_tuples, 0);
+        let evaluated = tx.stratified_magic_evaluate(
             &compiled,
             &stores,
             if input_program.out_opts.sorters.is_empty() {
@@ -608,8 +2223,23 @@ impl Db {
             } else {
                 None
             },
-            poison,
-        )?;
+            poison.clone(),
+            profile.as_mut(),
+            &mut in_mem_guard,
+        );
+        let mut tolerated = None;
+        if lazy_rules.is_none() {
+            tolerated = mem::replace(&mut tx.query.tolerated_errors, prev_tolerated)
+                .map(|counter| counter.into_inner());
+            tx.query.row_guard = prev_row_guard;
+            tx.query.include_deleted = prev_include_deleted;
+            tx.query.algo_memory_budget = prev_algo_memory_budget;
+            tx.query.max_result_rows = prev_max_result_rows;
+        }
+        if let (Some(profile), Some(switches)) = (&mut profile, tx.query.join_switches.take()) {
+            profile.join_switches = switches.into_inner().unwrap();
+        }
+        let (result, early_return) = evaluated?;
         if let Some(assertion) = &input_program.out_opts.assertion {
             match assertion {
                 QueryAssertion::AssertNone(span) => {
@@ -638,6 +2268,127 @@ impl Db {
                 }
             }
         }
+        let lazy_entry = lazy_rules.map(|rules| LazyEntry {
+            rules,
+            _stores: stores,
+            poison,
+            _running: guard,
+            max_result_rows: quota.max_result_rows,
+        });
+        Ok(EvaluatedQuery {
+            result,
+            early_return,
+            tolerated,
+            warnings,
+            profile,
+            in_mem_guard,
+            lazy_entry,
+        })
+    }
+    /// Asks the access policy, if any, whether the relations may be read and written.
+    fn check_access(
+        &self,
+        label: Option<&str>,
+        read: BTreeSet<SmartString<LazyCompact>>,
+        written: BTreeSet<SmartString<LazyCompact>>,
+    ) -> Result<()> {
+        if let Some(policy) = &self.access_policy {
+            policy.check(&RelationAccess {
+                label: label.map(|l| l.to_string()),
+                read: read.into_iter().map(|name| name.to_string()).collect(),
+                written: written.into_iter().map(|name| name.to_string()).collect(),
+            })?;
+        }
+        Ok(())
+    }
+    /// Asks the access policy, if any, whether the program may run within `tx`, which can no
+    /// longer be committed if not.
+    fn check_program_access(&self, tx: &mut SessionTx, program: &InputProgram) -> Result<()> {
+        if self.access_policy.is_none() {
+            return Ok(());
+        }
+        let mut read = program.stored_relations_read();
+        let mut written = BTreeSet::new();
+        if let Some((meta, op)) = &program.out_opts.store_relation {
+            match op {
+                RelationOp::Ensure | RelationOp::EnsureNot => read.insert(meta.name.name.clone()),
+                _ => written.insert(meta.name.name.clone()),
+            };
+        }
+        let res = self.check_access(tx.script.label.as_deref(), read, written);
+        tx.script.access_denied |= res.is_err();
+        res
+    }
+    /// Run a single program. When `plan_key` is given as the hash and text of the script,
+    /// the chosen plan is captured for pinning and checked against any pinned one.
+    pub(crate) fn run_query(
+        &self,
+        tx: &mut SessionTx,
+        mut input_program: InputProgram,
+        plan_key: Option<(&str, &str)>,
+    ) -> Result<(JsonValue, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let mut clean_ups = vec![];
+        tx.expand_graph_views(&mut input_program)?;
+        self.check_program_access(tx, &input_program)?;
+        if let Some((meta, op)) = &mut input_program.out_opts.store_relation {
+            if !matches!(op, RelationOp::Create | RelationOp::Replace) {
+                if let Ok(existing) = tx.get_relation(&meta.name, false) {
+                    existing.split_input_cols(meta);
+                }
+            }
+        }
+        if let Some((meta, op)) = &input_program.out_opts.store_relation {
+            if *op == RelationOp::Create {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Stored relation {0} conflicts with an existing one")]
+                #[diagnostic(code(eval::stored_relation_conflict))]
+                struct StoreRelationConflict(String);
+
+                ensure!(
+                    !tx.relation_exists(&meta.name)?,
+                    StoreRelationConflict(meta.name.to_string())
+                );
+                tx.check_store_head(&input_program, meta, *op, &meta.metadata)?;
+            } else if *op == RelationOp::Replace {
+                tx.check_store_head(&input_program, meta, *op, &meta.metadata)?;
+            } else {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Stored relation {0} not found")]
+                #[diagnostic(code(eval::stored_relation_not_found))]
+                struct StoreRelationNotFoundError(String);
+
+                let existing = tx.get_relation(&meta.name, true)?;
+
+                ensure!(
+                    tx.relation_exists(&meta.name)?,
+                    StoreRelationNotFoundError(meta.name.to_string())
+                );
+
+                existing.ensure_compatible(meta)?;
+                tx.check_store_head(&input_program, meta, *op, &existing.metadata)?;
+            }
+        };
+        let EvaluatedQuery {
+            result,
+            early_return,
+            tolerated,
+            warnings,
+            profile,
+            in_mem_guard: _in_mem_guard,
+            ..
+        } = self.evaluate_query(tx, &input_program, plan_key, false)?;
+        let with_tolerated = |mut ret: JsonValue| {
+            if let Some(n) = tolerated {
+                ret["tolerated_errors"] = json!(n);
+            }
+            if !warnings.is_empty() {
+                ret["warnings"] = json!(warnings);
+            }
+            if let Some(profile) = &profile {
+                ret["profile"] = profile.to_json();
+            }
+            ret
+        };
         let json_headers = match input_program.get_entry_out_head() {
             Err(_) => JsonValue::Null,
             Ok(headers) => headers.into_iter().map(|v| json!(v.name)).collect(),
@@ -668,7 +2419,10 @@ impl Db {
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                Ok((json!({"headers": ["status"], "rows": [["OK"]]}), clean_ups))
+                Ok((
+                    with_tolerated(json!({"headers": ["status"], "rows": [["OK"]]})),
+                    clean_ups,
+                ))
             } else {
                 let ret: Vec<Vec<JsonValue>> = sorted_iter
                     .map_ok(|tuple| -> Vec<JsonValue> {
@@ -676,7 +2430,10 @@ impl Db {
                     })
                     .try_collect()?;
 
-                Ok((json!({ "rows": ret, "headers": json_headers }), clean_ups))
+                Ok((
+                    with_tolerated(json!({ "rows": ret, "headers": json_headers })),
+                    clean_ups,
+                ))
             }
         } else {
             let scan = if early_return {
@@ -702,7 +2459,10 @@ impl Db {
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
-                Ok((json!({"headers": ["status"], "rows": [["OK"]]}), clean_ups))
+                Ok((
+                    with_tolerated(json!({"headers": ["status"], "rows": [["OK"]]})),
+                    clean_ups,
+                ))
             } else {
                 let ret: Vec<Vec<JsonValue>> = scan
                     .map_ok(|tuple| -> Vec<JsonValue> {
@@ -710,13 +2470,17 @@ impl Db {
                     })
                     .try_collect()?;
 
-                Ok((json!({ "rows": ret, "headers": json_headers }), clean_ups))
+                Ok((
+                    with_tolerated(json!({ "rows": ret, "headers": json_headers })),
+                    clean_ups,
+                ))
             }
         }
     }
     pub(crate) fn remove_relation(&self, name: &Symbol, tx: &mut SessionTx) -> Result<()> {
-        let (lower, upper) = tx.destroy_relation(name)?;
-        self.db.range_del(&lower, &upper)?;
+        for (lower, upper) in tx.destroy_relation(name)? {
+            self.db.range_del(&lower, &upper)?;
+        }
         Ok(())
     }
     pub(crate) fn list_running(&self) -> Result<JsonValue> {
@@ -725,9 +2489,34 @@ impl Db {
             .lock()
             .unwrap()
             .iter()
-            .map(|(k, v)| json!([k, format!("{:?}", v.started_at)]))
+            .map(|(k, v)| json!([k, format!("{:?}", v.started_at), v.label]))
             .collect_vec();
-        Ok(json!({"rows": res, "headers": ["id", "started_at"]}))
+        Ok(json!({"rows": res, "headers": ["id", "started_at", "label"]}))
+    }
+    fn list_functions(&self) -> Result<JsonValue> {
+        let functions = OPS
+            .iter()
+            .map(|(name, op)| json!([name, "function", op.min_arity, op.vararg, null]));
+        let aggregations = AGGRS
+            .iter()
+            .map(|(name, aggr)| (name.to_string(), aggr.is_meet))
+            .chain(list_user_aggrs())
+            .map(|(name, is_meet)| json!([name, "aggregation", null, null, is_meet]));
+        let rows = functions.chain(aggregations).collect_vec();
+        Ok(json!({"rows": rows, "headers": ["name", "kind", "min_arity", "vararg", "is_meet"]}))
+    }
+    fn list_algos(&self) -> Result<JsonValue> {
+        let mut rows = vec![];
+        for algo in BUILTIN_ALGOS {
+            let arity = algo.default_arity();
+            for name in algo.names {
+                rows.push(json!([name, arity, algo.options, false]));
+            }
+        }
+        for name in self.custom_algos.keys() {
+            rows.push(json!([name, null, null, true]));
+        }
+        Ok(json!({"rows": rows, "headers": ["name", "arity", "options", "custom"]}))
     }
     fn list_relation(&self, name: &str) -> Result<JsonValue> {
         let tx = self.transact()?;
@@ -756,52 +2545,499 @@ impl Db {
         }
         Ok(json!({"rows": ret, "headers": ["column", "is_key", "index", "type", "has_default"]}))
     }
-    fn list_relations(&self) -> Result<JsonValue> {
+    /// Collects the statistics of a relation by scanning it, recording them in the catalog
+    /// for use as estimates by later queries.
+    fn relation_stats(&self, name: &str) -> Result<JsonValue> {
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(name, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "reading rows".to_string(),
+                handle.access_level
+            ));
+        }
+        let cols = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .collect_vec();
+        let mut n_rows = 0usize;
+        let mut nulls = vec![0usize; cols.len()];
+        let mut trues = vec![0usize; cols.len()];
+        let mut falses = vec![0usize; cols.len()];
+        let mut sampler = RowSampler::new();
+        for tuple in handle.scan_all(&tx) {
+            let tuple = tuple?;
+            n_rows += 1;
+            for (i, val) in tuple.0.iter().enumerate() {
+                match val {
+                    DataValue::Null => nulls[i] += 1,
+                    DataValue::Bool(true) => trues[i] += 1,
+                    DataValue::Bool(false) => falses[i] += 1,
+                    _ => {}
+                }
+            }
+            sampler.feed(tuple);
+        }
+        let columns = (0..cols.len())
+            .map(|i| {
+                let values = sampler.sample.iter().map(|row| row.0[i].clone()).collect();
+                ColumnStatistics::from_sample(values, n_rows)
+            })
+            .collect_vec();
+        let rows = cols
+            .iter()
+            .zip(columns.iter())
+            .enumerate()
+            .map(|(i, (col, stats))| {
+                let histogram = if col.typing.coltype == ColType::Bool {
+                    json!({"true": trues[i], "false": falses[i]})
+                } else {
+                    json!(stats
+                        .bounds
+                        .iter()
+                        .cloned()
+                        .map(JsonValue::from)
+                        .collect_vec())
+                };
+                let most_common = stats
+                    .most_common
+                    .iter()
+                    .map(|(v, f)| json!([JsonValue::from(v.clone()), f]))
+                    .collect_vec();
+                json!([
+                    col.name,
+                    col.typing.to_string(),
+                    n_rows,
+                    nulls[i],
+                    stats.distinct,
+                    most_common,
+                    histogram
+                ])
+            })
+            .collect_vec();
+        tx.put_statistics(
+            &handle.name,
+            &RelationStatistics {
+                rows: n_rows,
+                collected: current_validity(),
+                columns,
+            },
+        )?;
+        tx.commit_tx()?;
+        Ok(json!({
+            "rows": rows,
+            "headers": ["column", "type", "rows", "nulls", "distinct", "most_common", "histogram"]
+        }))
+    }
+    /// Hashes all rows of the relation in key order, so that two instances holding the same
+    /// rows report the same checksum regardless of how the rows got there.
+    fn relation_checksum(&self, name: &str) -> Result<JsonValue> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "reading rows".to_string(),
+                handle.access_level
+            ));
+        }
+        let mut n_rows = 0usize;
+        let mut hash = TUPLE_HASH_INIT;
+        for tuple in handle.scan_all(&tx) {
+            let tuple = tuple?;
+            n_rows += 1;
+            hash = tuple_hash(hash, &tuple.0);
+        }
+        Ok(json!({"rows": [[n_rows, format!("{:016x}", hash)]], "headers": ["rows", "checksum"]}))
+    }
+    /// Writes rows received during a sync into the relation, going through the same path as
+    /// `:put` so that history, indices and triggers are maintained. For relations keeping
+    /// last-writer-wins metadata, the rows are records as produced by the sync, carrying the
+    /// time of the write and whether it was a deletion after the keys; deletions go through
+    /// `:rm`, and the metadata of the other side is kept.
+    pub(crate) fn put_synced_rows(
+        &self,
+        relation: &str,
+        rows: Vec<Vec<DataValue>>,
+    ) -> Result<usize> {
+        if rows.is_empty() {
+            return Ok(0);
+        }
+        let n_rows = rows.len();
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(relation, false)?;
+        let meta = InputRelationHandle {
+            name: Symbol::new(handle.name.clone(), Default::default()),
+            metadata: handle.metadata.clone(),
+            key_bindings: column_symbols(&handle.metadata.keys),
+            dep_bindings: column_symbols(&handle.metadata.non_keys),
+            span: Default::default(),
+        };
+        let headers = meta
+            .key_bindings
+            .iter()
+            .chain(meta.dep_bindings.iter())
+            .cloned()
+            .collect_vec();
+        let mut cleanups = vec![];
+        if handle.lww.is_none() {
+            cleanups.extend(tx.execute_relation(
+                self,
+                rows.into_iter().map(|row| Ok(Tuple(row))),
+                RelationOp::Put,
+                &meta,
+                &headers,
+            )?);
+        } else {
+            let n_keys = meta.key_bindings.len();
+            let mut puts = vec![];
+            let mut rms = vec![];
+            let mut stamps = vec![];
+            for mut row in rows {
+                let mut stamp = row.split_off(n_keys);
+                let vals = stamp.split_off(2);
+                let at = stamp[0].get_float().unwrap_or_default();
+                let deleted = stamp[1] == DataValue::Bool(true);
+                stamps.push((row.clone(), deleted, at));
+                if deleted {
+                    rms.push(row);
+                } else {
+                    row.extend(vals);
+                    puts.push(row);
+                }
+            }
+            if !puts.is_empty() {
+                cleanups.extend(tx.execute_relation(
+                    self,
+                    puts.into_iter().map(|row| Ok(Tuple(row))),
+                    RelationOp::Put,
+                    &meta,
+                    &headers,
+                )?);
+            }
+            if !rms.is_empty() {
+                cleanups.extend(tx.execute_relation(
+                    self,
+                    rms.into_iter().map(|row| Ok(Tuple(row))),
+                    RelationOp::Rm,
+                    &meta,
+                    &meta.key_bindings,
+                )?);
+            }
+            for (keys, deleted, at) in stamps {
+                tx.record_lww(&handle, &keys, deleted, at)?;
+            }
+        }
+        self.append_audit(&mut tx, SYNC_COMMAND)?;
+        tx.commit_tx()?;
+        for (lower, upper) in cleanups {
+            self.db.range_del(&lower, &upper)?;
+        }
+        Ok(n_rows)
+    }
+    /// Writes conflicting edits found during a sync into the relation [SYNC_CONFLICTS],
+    /// creating it if necessary.
+    pub(crate) fn record_sync_conflicts(&self, rows: Vec<Vec<DataValue>>) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let col = |name: &str, coltype: ColType| ColumnDef {
+            name: SmartString::from(name),
+            typing: NullableColType {
+                coltype,
+                nullable: true,
+            },
+            default_gen: None,
+        };
+        let metadata = StoredRelationMetadata {
+            keys: vec![
+                col("relation", ColType::String),
+                col("key", ColType::Any),
+                col("detected_at", ColType::Float),
+            ],
+            non_keys: vec![
+                col("local", ColType::Any),
+                col("local_at", ColType::Float),
+                col("peer", ColType::Any),
+                col("peer_at", ColType::Float),
+                col("kept", ColType::String),
+            ],
+        };
+        let meta = InputRelationHandle {
+            name: Symbol::new(SYNC_CONFLICTS, Default::default()),
+            key_bindings: column_symbols(&metadata.keys),
+            dep_bindings: column_symbols(&metadata.non_keys),
+            metadata,
+            span: Default::default(),
+        };
+        let headers = meta
+            .key_bindings
+            .iter()
+            .chain(meta.dep_bindings.iter())
+            .cloned()
+            .collect_vec();
+        let mut tx = self.transact_write()?;
+        let op = if tx.relation_exists(SYNC_CONFLICTS)? {
+            RelationOp::Put
+        } else {
+            RelationOp::Create
+        };
+        let cleanups = tx.execute_relation(
+            self,
+            rows.into_iter().map(|row| Ok(Tuple(row))),
+            op,
+            &meta,
+            &headers,
+        )?;
+        self.append_audit(&mut tx, SYNC_COMMAND)?;
+        tx.commit_tx()?;
+        for (lower, upper) in cleanups {
+            self.db.range_del(&lower, &upper)?;
+        }
+        Ok(())
+    }
+    fn relation_handles(&self) -> Result<Vec<RelationHandle>> {
         let lower =
             Tuple(vec![DataValue::Str(SmartString::from(""))]).encode_as_key(RelationId::SYSTEM);
         let upper = Tuple(vec![DataValue::Str(SmartString::from(String::from(
             LARGEST_UTF_CHAR,
         )))])
         .encode_as_key(RelationId::SYSTEM);
-        let mut it = self
-            .db
-            .transact()
-            .start()
-            .iterator()
-            .upper_bound(&upper)
-            .start();
-        it.seek(&lower);
+        let tx = self.db.transact()?;
         let mut collected = vec![];
-        while let Some((k_slice, v_slice)) = it.pair()? {
-            if upper.as_slice() <= k_slice {
-                break;
-            }
-            // if compare_tuple_keys(&upper, k_slice) != Greater {
-            //     break;
-            // }
-            let meta = RelationHandle::decode(v_slice)?;
-            let n_keys = meta.metadata.keys.len();
-            let n_dependents = meta.metadata.non_keys.len();
-            let arity = n_keys + n_dependents;
-            let name = meta.name;
-            let access_level = meta.access_level.to_string();
-            collected.push(json!([
-                name,
-                arity,
-                access_level,
-                n_keys,
-                n_dependents,
-                meta.put_triggers.len(),
-                meta.rm_triggers.len(),
-                meta.replace_triggers.len(),
-            ]));
-            it.next();
+        for pair in tx.range_scan(&lower, &upper) {
+            let (_, v_slice) = pair?;
+            collected.push(RelationHandle::decode(&v_slice)?);
         }
+        Ok(collected)
+    }
+    fn list_relations(&self) -> Result<JsonValue> {
+        let collected = self
+            .relation_handles()?
+            .into_iter()
+            .map(|meta| {
+                let n_keys = meta.metadata.keys.len();
+                let n_dependents = meta.metadata.non_keys.len();
+                let arity = n_keys + n_dependents;
+                let name = meta.name;
+                let access_level = meta.access_level.to_string();
+                json!([
+                    name,
+                    arity,
+                    access_level,
+                    n_keys,
+                    n_dependents,
+                    meta.put_triggers.len(),
+                    meta.rm_triggers.len(),
+                    meta.replace_triggers.len(),
+                ])
+            })
+            .collect_vec();
         Ok(json!({"rows": collected, "headers":
                 ["name", "arity", "access_level", "n_keys", "n_non_keys", "n_put_triggers", "n_rm_triggers", "n_replace_triggers"]}))
     }
 }
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("Job '{0}' not found")]
+#[diagnostic(code(db::job_not_found))]
+struct JobNotFound(String, #[label] SourceSpan);
+
+/// The command recorded in the audit log for writes made by [Db::sync_relation].
+const SYNC_COMMAND: &str = "<sync>";
+
+/// How often the scheduler thread looks for scheduled queries that are due.
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+
+/// The relations changed by a system op, or `None` if it changes none.
+fn audited_relations(op: &SysOp) -> Option<Vec<Option<SmartString<LazyCompact>>>> {
+    let names =
+        |rels: &[&Symbol]| Some(rels.iter().map(|rel| Some(rel.name.clone())).collect_vec());
+    match op {
+        SysOp::RemoveRelation(rels) | SysOp::SetAccessLevel(rels, _) => {
+            names(&rels.iter().collect_vec())
+        }
+        SysOp::ImportRemote(_, relations, _) => {
+            names(&relations.iter().map(|(rel, _, _)| rel).collect_vec())
+        }
+        SysOp::RenameRelation(pairs) => {
+            names(&pairs.iter().flat_map(|(old, new)| [old, new]).collect_vec())
+        }
+        SysOp::CloneRelation(_, rel)
+        | SysOp::DeleteRange(rel, _, _)
+        | SysOp::TruncateRelation(rel)
+        | SysOp::SetRelationHistory(rel, _)
+        | SysOp::SetRelationLww(rel, _)
+        | SysOp::SetRelationSoftDelete(rel, _)
+        | SysOp::PurgeRelation(rel)
+        | SysOp::SetRelationTiering(rel, _)
+        | SysOp::OffloadRelation(rel)
+        | SysOp::SetVectorIndex(rel, _)
+        | SysOp::SetTriggers(rel, _, _, _)
+        | SysOp::ImportInferred(rel, _)
+        | SysOp::ImportCsv(rel, _, _)
+        | SysOp::CreateMaintained(rel, _)
+        | SysOp::RemoveMaintained(rel) => names(&[rel]),
+        SysOp::SetGraphView(_, _)
+        | SysOp::RemoveGraphView(_)
+        | SysOp::CreateNamespace(_)
+        | SysOp::RemoveNamespace(_)
+        | SysOp::SetNamespaceQuota(_, _)
+        | SysOp::CreateSchedule(_, _)
+        | SysOp::RemoveSchedule(_)
+        | SysOp::CreateJob(_, _)
+        | SysOp::RemoveJob(_) => Some(vec![None]),
+        _ => None,
+    }
+}
+
+/// Whether a system op writes to the storage, be it to relations or to the catalog.
+fn sys_op_writes(op: &SysOp) -> bool {
+    audited_relations(op).is_some()
+        || matches!(
+            op,
+            SysOp::Compact
+                | SysOp::RelationStats(_)
+                | SysOp::PinPlan(_)
+                | SysOp::UnpinPlan(_)
+                | SysOp::RunJob(_)
+        )
+}
+
+/// The relations read by a system op, besides those it changes.
+fn sys_op_reads(op: &SysOp) -> BTreeSet<SmartString<LazyCompact>> {
+    match op {
+        SysOp::ListRelation(rel)
+        | SysOp::RelationStats(rel)
+        | SysOp::RelationChecksum(rel)
+        | SysOp::ShowTrigger(rel)
+        | SysOp::CloneRelation(rel, _) => BTreeSet::from([rel.name.clone()]),
+        SysOp::Explain(prog) | SysOp::Estimate(prog) => prog.stored_relations_read(),
+        _ => BTreeSet::new(),
+    }
+}
+
+/// The rows of a query evaluated in full into `result`, sorted and limited as it asks.
+fn evaluated_rows(
+    tx: &mut SessionTx,
+    program: &InputProgram,
+    result: InMemRelation,
+    early_return: bool,
+) -> Result<Box<dyn Iterator<Item = Tuple> + Send>> {
+    let out_opts = &program.out_opts;
+    Ok(if !out_opts.sorters.is_empty() {
+        let entry_head = program.get_entry_out_head()?;
+        let sorted = tx.sort_and_collect(result, &out_opts.sorters, &entry_head)?;
+        Box::new(
+            sorted
+                .into_iter()
+                .skip(out_opts.offset.unwrap_or(0))
+                .take(out_opts.limit.unwrap_or(usize::MAX)),
+        )
+    } else if early_return {
+        Box::new(result.drain_all(true))
+    } else {
+        Box::new(
+            result
+                .drain_all(false)
+                .skip(out_opts.offset.unwrap_or(0))
+                .take(out_opts.limit.unwrap_or(usize::MAX)),
+        )
+    })
+}
+
+/// Whether the rows of the entry rule of `program`, compiled into `strata`, can be derived
+/// as they are pulled: the entry must be made of rules without aggregation in the last
+/// stratum, which no rule reads, the entry included, and its rows must be neither sorted nor
+/// asserted over nor stored.
+fn can_pull_lazily(
+    program: &InputProgram,
+    strata: &[CompiledProgram],
+    entry: &MagicSymbol,
+) -> bool {
+    let out_opts = &program.out_opts;
+    if !out_opts.sorters.is_empty()
+        || out_opts.assertion.is_some()
+        || out_opts.store_relation.is_some()
+    {
+        return false;
+    }
+    let plain_rules = match strata.last().and_then(|stratum| stratum.get(entry)) {
+        Some(rule_set @ CompiledRuleSet::Rules(_)) => rule_set.aggr_kind() == AggrKind::None,
+        _ => false,
+    };
+    plain_rules
+        && strata
+            .iter()
+            .flat_map(|stratum| stratum.values())
+            .all(|rule_set| match rule_set {
+                CompiledRuleSet::Rules(rules) => rules
+                    .iter()
+                    .all(|rule| !rule.contained_rules.contains(entry)),
+                CompiledRuleSet::Algo(apply) => apply.rule_args.iter().all(
+                    |arg| !matches!(arg, MagicAlgoRuleArg::InMem { name, .. } if name == entry),
+                ),
+            })
+}
+
+/// Evaluates the rules of `entry`, sending the distinct rows derived, after skipping `offset`
+/// of them and up to `limit` of them, through `sender` as they are taken. Stops early once the
+/// receiver is dropped.
+fn send_entry_rows(
+    tx: &SessionTx,
+    entry: LazyEntry,
+    result: &InMemRelation,
+    offset: usize,
+    limit: usize,
+    sender: &SyncSender<Result<Tuple>>,
+) -> Result<()> {
+    let rules = match &entry.rules {
+        CompiledRuleSet::Rules(rules) => rules,
+        CompiledRuleSet::Algo(_) => unreachable!(),
+    };
+    let use_delta = BTreeSet::default();
+    let mut skipped = 0;
+    let mut sent = 0;
+    if limit == 0 {
+        return Ok(());
+    }
+    for rule in rules {
+        for item in rule.relation.iter(tx, Some(0), &use_delta)? {
+            let item = item?;
+            tx.count_derived_row()?;
+            entry.poison.check()?;
+            if result.exists(&item, 0) {
+                continue;
+            }
+            result.put(item.clone(), 0);
+            if skipped < offset {
+                skipped += 1;
+                continue;
+            }
+            if let Some(max) = entry.max_result_rows {
+                ensure!(sent < max, NamespaceResultQuotaExceeded(max));
+            }
+            if sender.send(Ok(item)).is_err() {
+                return Ok(());
+            }
+            sent += 1;
+            if sent == limit {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn column_symbols(cols: &[ColumnDef]) -> Vec<Symbol> {
+    cols.iter()
+        .map(|col| Symbol::new(col.name.clone(), Default::default()))
+        .collect_vec()
+}
+
 #[derive(Clone, Default)]
 pub(crate) struct Poison(pub(crate) Arc<AtomicBool>);
 
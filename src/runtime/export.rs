@@ -0,0 +1,79 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Exporting the rows of a query to a writer as CSV or JSON lines. The rows are written one at
+//! a time as they are pulled from a [`QueryCursor`](crate::QueryCursor), so that where the
+//! cursor derives them lazily, dumping a large relation needs neither all of it evaluated
+//! first nor all of it converted to JSON in memory.
+
+use std::io::{BufWriter, Write};
+
+use miette::{IntoDiagnostic, Result};
+use serde_json::Map;
+
+use crate::data::json::JsonValue;
+use crate::Db;
+
+/// The format of the rows written by [`Db::export_query`].
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// CSV with a header record naming the columns. Nulls are written as empty fields,
+    /// strings as they are, and lists and other values as JSON.
+    Csv,
+    /// One JSON object per line, keyed by the names of the columns.
+    JsonLines,
+}
+
+/// The text of a CSV field holding `val`.
+fn csv_field(val: &JsonValue) -> String {
+    match val {
+        JsonValue::Null => String::new(),
+        JsonValue::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+impl Db {
+    /// Run a single read-only query and write its rows to `writer` in `format`, returning
+    /// the number of rows written. The rows are pulled from [`Db::run_query_cursor`] and
+    /// written one at a time, so whether the query is evaluated in full first is as described
+    /// for [`QueryCursor`](crate::QueryCursor). System ops and queries writing to stored
+    /// relations are rejected. If deriving a row fails, the rows before it have been written.
+    pub fn export_query(
+        &self,
+        payload: &str,
+        params: &Map<String, JsonValue>,
+        format: ExportFormat,
+        writer: impl Write,
+    ) -> Result<usize> {
+        let mut cursor = self.run_query_cursor(payload, params)?;
+        let mut n_written = 0;
+        match format {
+            ExportFormat::Csv => {
+                let mut wtr = csv::Writer::from_writer(writer);
+                wtr.write_record(cursor.headers()).into_diagnostic()?;
+                for row in &mut cursor {
+                    let row = row?;
+                    wtr.write_record(row.iter().map(csv_field))
+                        .into_diagnostic()?;
+                    n_written += 1;
+                }
+                wtr.flush().into_diagnostic()?;
+            }
+            ExportFormat::JsonLines => {
+                let mut wtr = BufWriter::new(writer);
+                let headers = cursor.headers().to_vec();
+                for row in &mut cursor {
+                    let row = row?;
+                    let obj: Map<String, JsonValue> = headers.iter().cloned().zip(row).collect();
+                    serde_json::to_writer(&mut wtr, &obj).into_diagnostic()?;
+                    wtr.write_all(b"\n").into_diagnostic()?;
+                    n_written += 1;
+                }
+                wtr.flush().into_diagnostic()?;
+            }
+        }
+        Ok(n_written)
+    }
+}
@@ -0,0 +1,234 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Query federation: atoms like `*central::users{...}` read the relation `users` of the
+//! remote database configured under the name `central`, over the sync protocol of its server.
+//!
+//! Nothing is replicated: each scan a query makes of a remote relation fetches the rows in
+//! the range of keys bound by the atom, a page at a time and only as far as the query reads.
+//! Responses are cached for a while, so that repeated small lookups do not go over the
+//! network every time. Remote relations are read-only.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::metrics::Metrics;
+use crate::runtime::relation::{AccessLevel, RelationHandle, RelationId};
+use crate::runtime::sync::{
+    key_successor, post_sync_request, BadSyncResponse, KeyRange, SyncRequest, SyncResponse,
+};
+use crate::runtime::transact::{RowGuard, SessionTx};
+use crate::utils::swap_option_result;
+
+/// Number of rows fetched in each request when scanning a remote relation.
+const REMOTE_PAGE_ROWS: usize = 256;
+/// Number of cached responses above which expired ones are dropped.
+const REMOTE_CACHE_ENTRIES: usize = 4096;
+
+/// A remote database whose relations queries can read, as `*<name>::<relation>` with the
+/// name under which it is configured in [`DbOptions::remotes`](crate::DbOptions::remotes).
+#[derive(Debug, Clone)]
+pub struct RemoteDb {
+    /// The URL of the server of the remote database.
+    pub url: String,
+    /// The auth string sent to the server, if it requires one.
+    pub auth: Option<String>,
+    /// How long fetched rows and schemas are reused before being fetched again.
+    /// Zero disables caching. Defaults to one minute.
+    pub cache_ttl: Duration,
+}
+
+impl RemoteDb {
+    /// A remote database served at `url`, without auth and with the default cache duration.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            auth: None,
+            cache_ttl: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("No remote database named '{0}' is configured")]
+#[diagnostic(code(eval::unknown_remote))]
+#[diagnostic(help("Remote databases are configured with the option 'remotes' of the database"))]
+struct UnknownRemote(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot write to '{0}' as relations of remote databases are read-only")]
+#[diagnostic(code(eval::remote_relation_write))]
+pub(crate) struct RemoteRelationWrite(pub(crate) String);
+
+/// The configured remote databases and the responses recently fetched from them.
+#[derive(Default)]
+pub(crate) struct Federation {
+    remotes: BTreeMap<String, RemoteDb>,
+    cache: Mutex<BTreeMap<(String, Vec<u8>), (Instant, Vec<u8>)>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Federation {
+    pub(crate) fn new(remotes: BTreeMap<String, RemoteDb>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            remotes,
+            cache: Default::default(),
+            metrics,
+        }
+    }
+    /// Sends `request` to the remote database `remote`, or answers it from the cache.
+    fn ask(&self, remote: &str, request: SyncRequest) -> Result<SyncResponse> {
+        let config = self
+            .remotes
+            .get(remote)
+            .ok_or_else(|| UnknownRemote(remote.to_string()))?;
+        let request = rmp_serde::to_vec(&request).into_diagnostic()?;
+        let cache_key = (remote.to_string(), request);
+        if let Some((at, response)) = self.cache.lock().unwrap().get(&cache_key) {
+            if at.elapsed() < config.cache_ttl {
+                self.metrics
+                    .remote_cache_hits
+                    .fetch_add(1, Ordering::Relaxed);
+                return rmp_serde::from_slice(response).into_diagnostic();
+            }
+        }
+        self.metrics
+            .remote_cache_misses
+            .fetch_add(1, Ordering::Relaxed);
+        let endpoint = format!("{}/sync", config.url.trim_end_matches('/'));
+        let response = post_sync_request(&endpoint, config.auth.as_deref(), &cache_key.1)?;
+        let decoded = rmp_serde::from_slice(&response).into_diagnostic()?;
+        if !config.cache_ttl.is_zero() {
+            let mut cache = self.cache.lock().unwrap();
+            if cache.len() >= REMOTE_CACHE_ENTRIES {
+                cache.retain(|(remote, _), (at, _)| {
+                    matches!(self.remotes.get(remote), Some(c) if at.elapsed() < c.cache_ttl)
+                });
+            }
+            if cache.len() < REMOTE_CACHE_ENTRIES {
+                cache.insert(cache_key, (Instant::now(), response));
+            }
+        }
+        Ok(decoded)
+    }
+    /// The handle of the relation named `<remote>::<relation>`, with the columns it has in
+    /// the remote database.
+    pub(crate) fn remote_relation(&self, name: &str) -> Result<RelationHandle> {
+        let (remote, relation) = split_remote_name(name);
+        let metadata = match self.ask(
+            remote,
+            SyncRequest::Columns {
+                relation: relation.to_string(),
+            },
+        )? {
+            SyncResponse::Columns(metadata) => metadata,
+            _ => bail!(BadSyncResponse),
+        };
+        Ok(RelationHandle {
+            name: name.into(),
+            id: RelationId::SYSTEM,
+            metadata,
+            put_triggers: vec![],
+            rm_triggers: vec![],
+            replace_triggers: vec![],
+            access_level: AccessLevel::ReadOnly,
+            history: None,
+            vector_index: None,
+            lww: None,
+            soft_deleted: None,
+            tiering: None,
+            maintained: vec![],
+            remote: true,
+        })
+    }
+}
+
+fn split_remote_name(name: &str) -> (&str, &str) {
+    name.split_once("::").unwrap_or(("", name))
+}
+
+impl RelationHandle {
+    /// Scans the rows of a remote relation with keys between `lower` and `upper`, fetching
+    /// them page by page as they are read.
+    pub(crate) fn scan_remote(&self, tx: &SessionTx, lower: &Tuple, upper: &Tuple) -> RemoteScan {
+        let (remote, relation) = split_remote_name(&self.name);
+        RemoteScan {
+            federation: tx.services.federation.clone(),
+            remote: remote.to_string(),
+            relation: relation.to_string(),
+            range: KeyRange {
+                lower: Some(lower.0.clone()),
+                upper: Some(upper.0.clone()),
+            },
+            n_keys: self.metadata.keys.len(),
+            arity: self.arity(),
+            page: vec![].into_iter(),
+            exhausted: false,
+            row_guard: tx.query.row_guard.clone(),
+        }
+    }
+}
+
+pub(crate) struct RemoteScan {
+    federation: Arc<Federation>,
+    remote: String,
+    relation: String,
+    /// The keys not fetched yet
+    range: KeyRange,
+    n_keys: usize,
+    arity: usize,
+    page: std::vec::IntoIter<Vec<DataValue>>,
+    exhausted: bool,
+    row_guard: Option<Arc<RowGuard>>,
+}
+
+impl RemoteScan {
+    fn next_inner(&mut self) -> Result<Option<Tuple>> {
+        loop {
+            if let Some(row) = self.page.next() {
+                self.federation
+                    .metrics
+                    .rows_scanned
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Some(guard) = &self.row_guard {
+                    guard.scanned()?;
+                }
+                return Ok(Some(Tuple(row)));
+            }
+            if self.exhausted {
+                return Ok(None);
+            }
+            let rows = match self.federation.ask(
+                &self.remote,
+                SyncRequest::Page {
+                    relation: self.relation.clone(),
+                    range: self.range.clone(),
+                    limit: REMOTE_PAGE_ROWS,
+                },
+            )? {
+                SyncResponse::Rows(rows) if rows.iter().all(|row| row.len() == self.arity) => rows,
+                _ => bail!(BadSyncResponse),
+            };
+            self.exhausted = rows.len() < REMOTE_PAGE_ROWS;
+            if let Some(last) = rows.last() {
+                self.range.lower = Some(key_successor(last[..self.n_keys].to_vec()));
+            }
+            self.page = rows.into_iter();
+        }
+    }
+}
+
+impl Iterator for RemoteScan {
+    type Item = Result<Tuple>;
+    fn next(&mut self) -> Option<Self::Item> {
+        swap_option_result(self.next_inner())
+    }
+}
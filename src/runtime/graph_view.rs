@@ -0,0 +1,127 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeSet;
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::program::{AlgoRuleArg, InputInlineRulesOrAlgo, InputProgram};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::{parse_script, SourceSpan};
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+
+/// A named pair of queries giving the nodes and the edges of a graph. Fixed rules take
+/// them as `g.nodes[]` and `g.edges[]` in place of rules defined in the query.
+#[derive(Clone, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct GraphView {
+    pub(crate) nodes: Option<String>,
+    pub(crate) edges: Option<String>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Graph view '{0}' not found")]
+#[diagnostic(code(eval::graph_view_not_found))]
+struct GraphViewNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Graph view '{0}' does not define its {1}")]
+#[diagnostic(code(eval::graph_view_part_not_found))]
+struct GraphViewPartNotFound(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Graph view '{0}' has no part '{1}'")]
+#[diagnostic(code(eval::graph_view_unknown_part))]
+#[diagnostic(help("The parts of a graph view are 'nodes' and 'edges'"))]
+struct GraphViewUnknownPart(String, String, #[label] SourceSpan);
+
+fn graph_view_key(name: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("graph_view")),
+        DataValue::Str(SmartString::from(name)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl SessionTx {
+    pub(crate) fn get_graph_view(&self, name: &str) -> Result<Option<GraphView>> {
+        match self.tx.get(&graph_view_key(name), false)? {
+            None => Ok(None),
+            Some(slice) => Ok(Some(serde_json::from_slice(&slice).into_diagnostic()?)),
+        }
+    }
+    pub(crate) fn put_graph_view(&mut self, name: &str, view: &GraphView) -> Result<()> {
+        let val = serde_json::to_vec(view).into_diagnostic()?;
+        self.tx.put(&graph_view_key(name), &val)?;
+        Ok(())
+    }
+    pub(crate) fn remove_graph_view(&mut self, name: &str) -> Result<bool> {
+        let key = graph_view_key(name);
+        let existed = self.tx.exists(&key, true)?;
+        if existed {
+            self.tx.del(&key)?;
+        }
+        Ok(existed)
+    }
+    pub(crate) fn list_graph_views(&self) -> Result<Vec<(String, GraphView)>> {
+        let lower = graph_view_key("");
+        let upper = graph_view_key(&String::from(LARGEST_UTF_CHAR));
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            let name = key.0[2].get_string().unwrap_or_default().to_string();
+            collected.push((name, serde_json::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(collected)
+    }
+    /// Adds the rules of the graph views referred to by fixed rules of the program,
+    /// under the names they are referred to, unless the program defines them itself.
+    pub(crate) fn expand_graph_views(&self, program: &mut InputProgram) -> Result<()> {
+        let mut refs = BTreeSet::new();
+        for rules_or_algo in program.prog.values() {
+            if let InputInlineRulesOrAlgo::Algo { algo } = rules_or_algo {
+                for arg in &algo.rule_args {
+                    if let AlgoRuleArg::InMem { name, .. } = arg {
+                        if name.name.contains('.') && !program.prog.contains_key(name) {
+                            refs.insert(name.clone());
+                        }
+                    }
+                }
+            }
+        }
+        for name in refs {
+            let (view_name, part) = name.name.split_once('.').unwrap();
+            let view = self
+                .get_graph_view(view_name)?
+                .ok_or_else(|| GraphViewNotFound(view_name.to_string(), name.span))?;
+            let script = match part {
+                "nodes" => view.nodes,
+                "edges" => view.edges,
+                _ => bail!(GraphViewUnknownPart(
+                    view_name.to_string(),
+                    part.to_string(),
+                    name.span
+                )),
+            }
+            .ok_or_else(|| {
+                GraphViewPartNotFound(view_name.to_string(), part.to_string(), name.span)
+            })?;
+            let mut view_program =
+                parse_script(&script, &Default::default(), &self.services.custom_algos)?
+                    .get_single_program()?;
+            let rules = view_program
+                .prog
+                .remove(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0)))
+                .unwrap();
+            program.prog.insert(name, rules);
+        }
+        Ok(())
+    }
+}
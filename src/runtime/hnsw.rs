@@ -0,0 +1,520 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Approximate nearest neighbour search over a vector column of a stored relation,
+//! using a hierarchical navigable small world (HNSW) graph kept in the store next to the rows.
+//!
+//! The graph lives under its own relation id, with the nodes identified by the keys of
+//! the rows of the indexed relation:
+//!
+//! * `[-1]` holds the top layer and the keys of the entry point,
+//! * `[-2, keys]` holds the top layer of each indexed row,
+//! * `[layer, keys, neighbour keys]` are the directed links of each layer, with empty values.
+//!
+//! The vectors themselves are always read from the rows of the indexed relation.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, BinaryHeap};
+
+use miette::{bail, Diagnostic, Result};
+use ordered_float::OrderedFloat;
+use rand::Rng;
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::functions::{cos_dist, ip_dist, l2_dist};
+use crate::data::relation::ColType;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+
+const ENTRY_TAG: i64 = -1;
+const LEVEL_TAG: i64 = -2;
+const MAX_LEVEL: usize = 16;
+
+/// How the distance between two vectors is measured.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum VectorDistance {
+    /// Squared euclidean distance
+    L2,
+    /// One minus the cosine of the angle between the vectors
+    Cosine,
+    /// One minus the inner product
+    InnerProduct,
+}
+
+impl VectorDistance {
+    pub(crate) fn compute(&self, a: &[f32], b: &[f32]) -> f64 {
+        match self {
+            VectorDistance::L2 => l2_dist(a, b),
+            VectorDistance::Cosine => cos_dist(a, b),
+            VectorDistance::InnerProduct => ip_dist(a, b),
+        }
+    }
+}
+
+/// A vector index as stored in the handle of the indexed relation.
+#[derive(Clone, Debug, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct VectorIndex {
+    pub(crate) id: RelationId,
+    pub(crate) column: SmartString<LazyCompact>,
+    /// Position of the indexed column among the non-key columns
+    pub(crate) val_idx: usize,
+    pub(crate) dim: usize,
+    /// Number of links made for each new node, per layer
+    pub(crate) m: usize,
+    /// Number of candidates considered when linking a new node
+    pub(crate) ef_construction: usize,
+    pub(crate) distance: VectorDistance,
+}
+
+/// A vector index as requested by `::relation vector_index`.
+#[derive(Clone, Debug)]
+pub(crate) struct VectorIndexConfig {
+    pub(crate) column: Symbol,
+    pub(crate) m: usize,
+    pub(crate) ef_construction: usize,
+    pub(crate) distance: VectorDistance,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Column '{0}' of '{1}' cannot be indexed for vector search")]
+#[diagnostic(code(eval::bad_vector_index_column))]
+#[diagnostic(help("Only non-key columns of a vector type such as <F32; 128> can be indexed"))]
+struct BadVectorIndexColumn(String, String, #[label] SourceSpan);
+
+type Node = Vec<DataValue>;
+
+impl VectorIndex {
+    fn entry_key(&self) -> Vec<u8> {
+        Tuple(vec![DataValue::from(ENTRY_TAG)]).encode_as_key(self.id)
+    }
+    fn level_key(&self, node: &[DataValue]) -> Vec<u8> {
+        Tuple(vec![
+            DataValue::from(LEVEL_TAG),
+            DataValue::List(node.to_vec()),
+        ])
+        .encode_as_key(self.id)
+    }
+    fn link_key(&self, layer: usize, from: &[DataValue], to: &[DataValue]) -> Vec<u8> {
+        Tuple(vec![
+            DataValue::from(layer as i64),
+            DataValue::List(from.to_vec()),
+            DataValue::List(to.to_vec()),
+        ])
+        .encode_as_key(self.id)
+    }
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            2 * self.m
+        } else {
+            self.m
+        }
+    }
+    fn random_level(&self) -> usize {
+        let u: f64 = rand::thread_rng().gen();
+        let level = (-(1. - u).ln() / (self.m as f64).ln()).floor();
+        (level as usize).min(MAX_LEVEL)
+    }
+}
+
+impl SessionTx {
+    /// Builds, replaces or drops the vector index of a relation, removing the discarded
+    /// index, if any.
+    pub(crate) fn set_vector_index(
+        &mut self,
+        name: &Symbol,
+        config: Option<VectorIndexConfig>,
+    ) -> Result<()> {
+        let mut handle = self.get_relation(name, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "setting vector index".to_string(),
+                handle.access_level
+            ))
+        }
+        if let Some(old) = handle.vector_index.take() {
+            self.del_relation_keys(old.id)?;
+        }
+        if let Some(config) = config {
+            let found = handle
+                .metadata
+                .non_keys
+                .iter()
+                .enumerate()
+                .find(|(_, col)| col.name == config.column.name);
+            let (val_idx, dim) = match found {
+                Some((i, col)) => match col.typing.coltype {
+                    ColType::Vec { dim } => (i, dim),
+                    _ => bail!(BadVectorIndexColumn(
+                        config.column.name.to_string(),
+                        handle.name.to_string(),
+                        config.column.span
+                    )),
+                },
+                None => bail!(BadVectorIndexColumn(
+                    config.column.name.to_string(),
+                    handle.name.to_string(),
+                    config.column.span
+                )),
+            };
+            let index = VectorIndex {
+                id: self.next_relation_id()?,
+                column: config.column.name,
+                val_idx,
+                dim,
+                m: config.m,
+                ef_construction: config.ef_construction,
+                distance: config.distance,
+            };
+            let rows: Vec<_> = handle.scan_all(self).collect::<Result<_>>()?;
+            for row in &rows {
+                self.hnsw_put(&handle, &index, row)?;
+            }
+            handle.vector_index = Some(index);
+        }
+
+        let name_key =
+            Tuple(vec![DataValue::Str(handle.name.clone())]).encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.tx.put(&name_key, &meta_val)?;
+        Ok(())
+    }
+    /// Indexes a row that has just been written. A previous version of the row is unlinked
+    /// first, and rows whose indexed column is null are not indexed.
+    pub(crate) fn hnsw_put(
+        &mut self,
+        handle: &RelationHandle,
+        index: &VectorIndex,
+        tuple: &Tuple,
+    ) -> Result<()> {
+        let n_keys = handle.metadata.keys.len();
+        let node = tuple.0[..n_keys].to_vec();
+        self.hnsw_remove(handle, index, &node)?;
+        let q = match &tuple.0[n_keys + index.val_idx] {
+            DataValue::Vec(v) => v.0.clone(),
+            _ => return Ok(()),
+        };
+        let level = index.random_level();
+        self.hnsw_set(
+            &index.level_key(&node),
+            index.id,
+            &[DataValue::from(level as i64)],
+        )?;
+        let (top, entry) = match self.hnsw_entry(index)? {
+            None => {
+                return self.hnsw_set(
+                    &index.entry_key(),
+                    index.id,
+                    &[DataValue::from(level as i64), DataValue::List(node)],
+                )
+            }
+            Some(found) => found,
+        };
+        let entry_dist = self.hnsw_distance(handle, index, &q, &entry)?;
+        let mut nearest = vec![(entry_dist, entry)];
+        for layer in (level + 1..=top).rev() {
+            nearest = self.hnsw_search_layer(handle, index, &q, nearest, 1, layer)?;
+        }
+        for layer in (0..=level.min(top)).rev() {
+            nearest =
+                self.hnsw_search_layer(handle, index, &q, nearest, index.ef_construction, layer)?;
+            let linked: Vec<_> = nearest
+                .iter()
+                .filter(|(_, n)| *n != node)
+                .take(index.m)
+                .map(|(_, n)| n.clone())
+                .collect();
+            for other in &linked {
+                self.tx.put(&index.link_key(layer, &node, other), &[])?;
+                self.tx.put(&index.link_key(layer, other, &node), &[])?;
+                self.hnsw_prune(handle, index, layer, other)?;
+            }
+        }
+        if level > top {
+            self.hnsw_set(
+                &index.entry_key(),
+                index.id,
+                &[DataValue::from(level as i64), DataValue::List(node)],
+            )?;
+        }
+        Ok(())
+    }
+    /// Unlinks a row about to be removed or replaced, reconnecting its former neighbours
+    /// among themselves. Links from other rows to the removed one may remain: they are
+    /// skipped when followed, and dropped when the linking rows are pruned.
+    pub(crate) fn hnsw_remove(
+        &mut self,
+        handle: &RelationHandle,
+        index: &VectorIndex,
+        node: &[DataValue],
+    ) -> Result<()> {
+        let level_key = index.level_key(node);
+        let level = match self.hnsw_get(&level_key)? {
+            None => return Ok(()),
+            Some(vals) => vals[0].get_int().unwrap() as usize,
+        };
+        self.tx.del(&level_key)?;
+        for layer in 0..=level {
+            let links = self.hnsw_links(index, layer, node)?;
+            for other in &links {
+                self.tx.del(&index.link_key(layer, node, other))?;
+                self.tx.del(&index.link_key(layer, other, node))?;
+            }
+            for a in &links {
+                let va = match self.hnsw_vector(handle, index, a)? {
+                    None => continue,
+                    Some(v) => v,
+                };
+                let mut candidates = vec![];
+                for b in &links {
+                    if a == b {
+                        continue;
+                    }
+                    if let Some(vb) = self.hnsw_vector(handle, index, b)? {
+                        candidates.push((OrderedFloat(index.distance.compute(&va, &vb)), b));
+                    }
+                }
+                candidates.sort();
+                for (_, b) in candidates.into_iter().take(index.m) {
+                    self.tx.put(&index.link_key(layer, a, b), &[])?;
+                }
+                self.hnsw_prune(handle, index, layer, a)?;
+            }
+        }
+        if let Some((_, entry)) = self.hnsw_entry(index)? {
+            if entry == node {
+                self.hnsw_elect_entry(index)?;
+            }
+        }
+        Ok(())
+    }
+    /// Finds the `k` indexed rows closest to `q`, considering `ef` candidates on the bottom
+    /// layer. Returns the distances and the keys of the rows, nearest first.
+    pub(crate) fn hnsw_search(
+        &self,
+        handle: &RelationHandle,
+        index: &VectorIndex,
+        q: &[f32],
+        k: usize,
+        ef: usize,
+    ) -> Result<Vec<(f64, Node)>> {
+        let (top, entry) = match self.hnsw_entry(index)? {
+            None => return Ok(vec![]),
+            Some(found) => found,
+        };
+        let entry_dist = self.hnsw_distance(handle, index, q, &entry)?;
+        let mut nearest = vec![(entry_dist, entry)];
+        for layer in (1..=top).rev() {
+            nearest = self.hnsw_search_layer(handle, index, q, nearest, 1, layer)?;
+        }
+        nearest = self.hnsw_search_layer(handle, index, q, nearest, ef.max(k), 0)?;
+        Ok(nearest
+            .into_iter()
+            .filter(|(d, _)| d.is_finite())
+            .take(k)
+            .collect())
+    }
+    fn hnsw_search_layer(
+        &self,
+        handle: &RelationHandle,
+        index: &VectorIndex,
+        q: &[f32],
+        entries: Vec<(f64, Node)>,
+        ef: usize,
+        layer: usize,
+    ) -> Result<Vec<(f64, Node)>> {
+        let mut visited: BTreeSet<Node> = entries.iter().map(|(_, n)| n.clone()).collect();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+        for (d, n) in entries {
+            candidates.push(Reverse((OrderedFloat(d), n.clone())));
+            found.push((OrderedFloat(d), n));
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+        while let Some(Reverse((OrderedFloat(d), node))) = candidates.pop() {
+            if let Some((OrderedFloat(worst), _)) = found.peek() {
+                if d > *worst && found.len() >= ef {
+                    break;
+                }
+            }
+            for other in self.hnsw_links(index, layer, &node)? {
+                if !visited.insert(other.clone()) {
+                    continue;
+                }
+                let v = match self.hnsw_vector(handle, index, &other)? {
+                    None => continue,
+                    Some(v) => v,
+                };
+                let od = index.distance.compute(q, &v);
+                let closer = match found.peek() {
+                    Some((OrderedFloat(worst), _)) => found.len() < ef || od < *worst,
+                    None => true,
+                };
+                if closer {
+                    candidates.push(Reverse((OrderedFloat(od), other.clone())));
+                    found.push((OrderedFloat(od), other));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        Ok(found
+            .into_sorted_vec()
+            .into_iter()
+            .map(|(OrderedFloat(d), n)| (d, n))
+            .collect())
+    }
+    /// Keeps only the closest links of a node if it has too many.
+    fn hnsw_prune(
+        &mut self,
+        handle: &RelationHandle,
+        index: &VectorIndex,
+        layer: usize,
+        node: &[DataValue],
+    ) -> Result<()> {
+        let max_links = index.max_links(layer);
+        let links = self.hnsw_links(index, layer, node)?;
+        if links.len() <= max_links {
+            return Ok(());
+        }
+        let base = match self.hnsw_vector(handle, index, node)? {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        let mut scored = vec![];
+        for other in links {
+            let d = match self.hnsw_vector(handle, index, &other)? {
+                None => f64::INFINITY,
+                Some(v) => index.distance.compute(&base, &v),
+            };
+            scored.push((OrderedFloat(d), other));
+        }
+        scored.sort();
+        for (_, other) in scored.into_iter().skip(max_links) {
+            self.tx.del(&index.link_key(layer, node, &other))?;
+        }
+        Ok(())
+    }
+    /// Makes the node on the highest layer the new entry point.
+    fn hnsw_elect_entry(&mut self, index: &VectorIndex) -> Result<()> {
+        let lower = Tuple(vec![DataValue::from(LEVEL_TAG)]).encode_as_key(index.id);
+        let upper = Tuple(vec![DataValue::from(LEVEL_TAG), DataValue::Bot]).encode_as_key(index.id);
+        let mut best: Option<(i64, Node)> = None;
+        {
+            for pair in self.tx.range_scan(&lower, &upper) {
+                let (k_slice, v_slice) = pair?;
+                let vals: Vec<DataValue> =
+                    rmp_serde::from_slice(&v_slice[ENCODED_KEY_MIN_LEN..]).unwrap();
+                let level = vals[0].get_int().unwrap();
+                if best.as_ref().map(|(l, _)| level > *l).unwrap_or(true) {
+                    if let Some(DataValue::List(node)) = Tuple::decode_from_key(&k_slice).0.pop() {
+                        best = Some((level, node));
+                    }
+                }
+            }
+        }
+        match best {
+            None => self.tx.del(&index.entry_key())?,
+            Some((level, node)) => self.hnsw_set(
+                &index.entry_key(),
+                index.id,
+                &[DataValue::from(level), DataValue::List(node)],
+            )?,
+        }
+        Ok(())
+    }
+    fn hnsw_entry(&self, index: &VectorIndex) -> Result<Option<(usize, Node)>> {
+        Ok(match self.hnsw_get(&index.entry_key())? {
+            None => None,
+            Some(mut vals) => match vals.pop() {
+                Some(DataValue::List(node)) => Some((vals[0].get_int().unwrap() as usize, node)),
+                _ => None,
+            },
+        })
+    }
+    fn hnsw_links(
+        &self,
+        index: &VectorIndex,
+        layer: usize,
+        node: &[DataValue],
+    ) -> Result<Vec<Node>> {
+        let prefix = vec![
+            DataValue::from(layer as i64),
+            DataValue::List(node.to_vec()),
+        ];
+        let lower = Tuple(prefix.clone()).encode_as_key(index.id);
+        let mut upper = prefix;
+        upper.push(DataValue::Bot);
+        let upper = Tuple(upper).encode_as_key(index.id);
+        let mut ret = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, _) = pair?;
+            if let Some(DataValue::List(other)) = Tuple::decode_from_key(&k_slice).0.pop() {
+                ret.push(other);
+            }
+        }
+        Ok(ret)
+    }
+    fn hnsw_distance(
+        &self,
+        handle: &RelationHandle,
+        index: &VectorIndex,
+        q: &[f32],
+        node: &[DataValue],
+    ) -> Result<f64> {
+        Ok(match self.hnsw_vector(handle, index, node)? {
+            None => f64::INFINITY,
+            Some(v) => index.distance.compute(q, &v),
+        })
+    }
+    /// Reads the indexed vector from the row with the given keys.
+    fn hnsw_vector(
+        &self,
+        handle: &RelationHandle,
+        index: &VectorIndex,
+        node: &[DataValue],
+    ) -> Result<Option<Vec<f32>>> {
+        let key = Tuple(node.to_vec()).encode_as_key(handle.id);
+        Ok(match self.tx.get(&key, false)? {
+            None => None,
+            Some(found) => {
+                let mut vals: Vec<DataValue> =
+                    rmp_serde::from_slice(&found[ENCODED_KEY_MIN_LEN..]).unwrap();
+                if index.val_idx < vals.len() {
+                    match vals.swap_remove(index.val_idx) {
+                        DataValue::Vec(v) => Some(v.0),
+                        _ => None,
+                    }
+                } else {
+                    None
+                }
+            }
+        })
+    }
+    fn hnsw_get(&self, key: &[u8]) -> Result<Option<Vec<DataValue>>> {
+        Ok(self
+            .tx
+            .get(key, false)?
+            .map(|found| rmp_serde::from_slice(&found[ENCODED_KEY_MIN_LEN..]).unwrap()))
+    }
+    fn hnsw_set(&mut self, key: &[u8], id: RelationId, vals: &[DataValue]) -> Result<()> {
+        let mut val = id.raw_encode().to_vec();
+        vals.serialize(&mut Serializer::new(&mut val)).unwrap();
+        self.tx.put(key, &val)?;
+        Ok(())
+    }
+}
@@ -5,10 +5,10 @@
 use std::borrow::BorrowMut;
 use std::collections::BTreeMap;
 use std::fmt::{Debug, Formatter};
-use std::iter;
 use std::ops::Bound::Included;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, RwLock};
+use std::{iter, mem};
 
 use either::{Left, Right};
 use itertools::Itertools;
@@ -78,7 +78,7 @@ impl InMemRelation {
     ) -> Result<bool> {
         self.ensure_mem_db_for_epoch(epoch);
         let db_target = self.mem_db.try_read().unwrap();
-        let mut zero_target = db_target.get(0).unwrap().try_write().unwrap();
+        let mut zero_target = db_target.first().unwrap().try_write().unwrap();
         let key = Tuple(
             aggrs
                 .iter()
@@ -129,6 +129,16 @@ impl InMemRelation {
             Ok(true)
         }
     }
+    pub(crate) fn num_tuples(&self) -> usize {
+        let db = self.mem_db.try_read().unwrap();
+        db.iter().map(|epoch| epoch.try_read().unwrap().len()).sum()
+    }
+    pub(crate) fn num_tuples_at_epoch(&self, epoch: u32) -> usize {
+        let db = self.mem_db.try_read().unwrap();
+        db.get(epoch as usize)
+            .map(|tuples| tuples.try_read().unwrap().len())
+            .unwrap_or(0)
+    }
     pub(crate) fn put(&self, tuple: Tuple, epoch: u32) {
         self.ensure_mem_db_for_epoch(epoch);
         let db = self.mem_db.try_read().unwrap();
@@ -138,7 +148,7 @@ impl InMemRelation {
     pub(crate) fn put_with_skip(&self, tuple: Tuple, should_skip: bool) {
         self.ensure_mem_db_for_epoch(0);
         let db = self.mem_db.try_read().unwrap();
-        let mut target = db.get(0).unwrap().try_write().unwrap();
+        let mut target = db.first().unwrap().try_write().unwrap();
         if should_skip {
             target.insert(tuple, Tuple(vec![DataValue::Guard]));
         } else {
@@ -166,7 +176,7 @@ impl InMemRelation {
         vals.push(DataValue::from(serial as i64));
 
         let target = self.mem_db.try_read().unwrap();
-        let mut target = target.get(0).unwrap().try_write().unwrap();
+        let mut target = target.first().unwrap().try_write().unwrap();
         target.insert(Tuple(vals), Tuple::default());
     }
     pub(crate) fn exists(&self, tuple: &Tuple, epoch: u32) -> bool {
@@ -184,7 +194,7 @@ impl InMemRelation {
         poison: Poison,
     ) -> Result<bool> {
         let db_target = self.mem_db.try_read().unwrap();
-        let target = db_target.get(0);
+        let target = db_target.first();
         let it = match target {
             None => Left(iter::empty()),
             Some(target) => {
@@ -195,7 +205,7 @@ impl InMemRelation {
                     } else {
                         let combined =
                             k.0.into_iter()
-                                .zip(v.0.into_iter())
+                                .zip(v.0)
                                 .map(|(kel, vel)| {
                                     if matches!(kel, DataValue::Guard) {
                                         vel
@@ -294,7 +304,7 @@ impl InMemRelation {
             } else {
                 let combined =
                     k.0.into_iter()
-                        .zip(v.0.into_iter())
+                        .zip(v.0)
                         .map(|(kel, vel)| {
                             if matches!(kel, DataValue::Guard) {
                                 vel
@@ -316,7 +326,7 @@ impl InMemRelation {
             .mem_db
             .try_read()
             .unwrap()
-            .get(0)
+            .first()
             .unwrap()
             .clone()
             .try_read()
@@ -343,6 +353,42 @@ impl InMemRelation {
             }
         })
     }
+    /// Moves all tuples out of the relation instead of copying them, leaving it empty.
+    /// With `early_returned`, tuples guarded by an early return are skipped as in
+    /// [`InMemRelation::scan_early_returned`].
+    pub(crate) fn drain_all(&self, early_returned: bool) -> impl Iterator<Item = Tuple> {
+        self.ensure_mem_db_for_epoch(0);
+        let db = mem::take(
+            &mut *self
+                .mem_db
+                .try_read()
+                .unwrap()
+                .first()
+                .unwrap()
+                .try_write()
+                .unwrap(),
+        );
+        db.into_iter().filter_map(move |(k, v)| {
+            if v.0.is_empty() {
+                Some(k)
+            } else if early_returned && v.0.last() == Some(&DataValue::Guard) {
+                None
+            } else {
+                let combined =
+                    k.0.into_iter()
+                        .zip(v.0)
+                        .map(|(kel, vel)| {
+                            if matches!(kel, DataValue::Guard) {
+                                vel
+                            } else {
+                                kel
+                            }
+                        })
+                        .collect_vec();
+                Some(Tuple(combined))
+            }
+        })
+    }
     pub(crate) fn scan_prefix(&self, prefix: &Tuple) -> impl Iterator<Item = Result<Tuple>> {
         self.scan_prefix_for_epoch(prefix, 0)
     }
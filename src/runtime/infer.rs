@@ -0,0 +1,368 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Importing CSV and JSON lines sources into new relations whose schemas are inferred from a
+//! sample of the rows, for exploring data without writing the schema first.
+
+use std::collections::BTreeSet;
+use std::fs;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use serde_json::json;
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::algo::jlines::get_file_content_from_url;
+use crate::data::functions::op_to_uuid;
+use crate::data::json::JsonValue;
+use crate::data::program::RelationOp;
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num};
+use crate::runtime::db::column_symbols;
+use crate::runtime::relation::InputRelationHandle;
+use crate::runtime::transact::SessionTx;
+use crate::Db;
+
+/// Number of rows the types of the columns are inferred from, unless given.
+pub(crate) const DEFAULT_INFER_SAMPLE: usize = 1000;
+/// Name of the key column holding the position of each row, added when no column of the
+/// source can serve as the key.
+const ROW_COLUMN: &str = "_row";
+
+/// The types tried for a column, in order of preference: the first one accepting all
+/// values in the sample wins.
+const CANDIDATE_TYPES: [ColType; 7] = [
+    ColType::Int,
+    ColType::Float,
+    ColType::Bool,
+    ColType::Timestamp,
+    ColType::Uuid,
+    ColType::String,
+    ColType::Any,
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum ImportFormat {
+    Csv,
+    JsonLines,
+}
+
+/// The source imported into a new relation by `::import`.
+#[derive(Clone, Debug)]
+pub(crate) struct InferredImport {
+    pub(crate) url: String,
+    /// The format of the source, guessed from the extension of the URL if not given
+    pub(crate) format: Option<ImportFormat>,
+    pub(crate) sample: usize,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot tell the format of '{0}'")]
+#[diagnostic(code(eval::unknown_import_format))]
+#[diagnostic(help("Give the format with 'as csv' or 'as jsonl'"))]
+struct UnknownImportFormat(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Line {0} of the JSON lines source is not an object")]
+#[diagnostic(code(eval::import_line_not_object))]
+struct ImportLineNotObject(usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The source '{0}' has no columns")]
+#[diagnostic(code(eval::import_no_columns))]
+struct ImportNoColumns(String);
+
+impl InferredImport {
+    fn format(&self) -> Result<ImportFormat> {
+        if let Some(format) = self.format {
+            return Ok(format);
+        }
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        let ext = path.rsplit('.').next().unwrap_or_default();
+        Ok(match ext.to_ascii_lowercase().as_str() {
+            "csv" => ImportFormat::Csv,
+            "jsonl" | "ndjson" => ImportFormat::JsonLines,
+            _ => bail!(UnknownImportFormat(self.url.clone())),
+        })
+    }
+    fn read_content(&self) -> Result<String> {
+        match self.url.strip_prefix("file://") {
+            Some(path) => fs::read_to_string(path).into_diagnostic(),
+            None => Ok(get_file_content_from_url(&self.url)?
+                .as_str()
+                .into_diagnostic()?
+                .to_string()),
+        }
+    }
+    /// Reads the source as column names and rows of raw values. Values read from CSV are
+    /// strings, converted to the types of their columns later; absent values are null.
+    fn read(&self) -> Result<(Vec<String>, Vec<Vec<DataValue>>)> {
+        let content = self.read_content()?;
+        let (columns, rows) = match self.format()? {
+            ImportFormat::Csv => {
+                let mut rdr = csv::ReaderBuilder::new()
+                    .has_headers(true)
+                    .flexible(true)
+                    .from_reader(content.as_bytes());
+                let columns = rdr
+                    .headers()
+                    .into_diagnostic()?
+                    .iter()
+                    .map(|h| h.trim().to_string())
+                    .collect_vec();
+                let mut rows = vec![];
+                for record in rdr.records() {
+                    let record = record.into_diagnostic()?;
+                    let row = (0..columns.len())
+                        .map(|i| match record.get(i) {
+                            None | Some("") => DataValue::Null,
+                            Some(s) => DataValue::Str(SmartString::from(s)),
+                        })
+                        .collect_vec();
+                    rows.push(row);
+                }
+                (columns, rows)
+            }
+            ImportFormat::JsonLines => {
+                let mut objects = vec![];
+                for (i, line) in content.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(line).into_diagnostic()? {
+                        JsonValue::Object(obj) => objects.push(obj),
+                        _ => bail!(ImportLineNotObject(i + 1)),
+                    }
+                }
+                let mut columns: Vec<String> = vec![];
+                for obj in &objects {
+                    for k in obj.keys() {
+                        if !columns.contains(k) {
+                            columns.push(k.clone());
+                        }
+                    }
+                }
+                let rows = objects
+                    .iter()
+                    .map(|obj| {
+                        columns
+                            .iter()
+                            .map(|col| obj.get(col).map_or(DataValue::Null, DataValue::from))
+                            .collect_vec()
+                    })
+                    .collect_vec();
+                (columns, rows)
+            }
+        };
+        if columns.is_empty() {
+            bail!(ImportNoColumns(self.url.clone()))
+        }
+        Ok((columns, rows))
+    }
+}
+
+/// Converts a raw value to `coltype`, if it can be. Strings read from CSV (`text`) are parsed
+/// as numbers and booleans, whereas those from JSON only as timestamps and UUIDs.
+fn convert(val: &DataValue, coltype: &ColType, text: bool) -> Option<DataValue> {
+    match (coltype, val) {
+        (ColType::Any, v) => Some(v.clone()),
+        (ColType::Int, DataValue::Str(s)) if text => {
+            s.trim().parse::<i64>().ok().map(DataValue::from)
+        }
+        (ColType::Int, v @ DataValue::Num(Num::Int(_))) => Some(v.clone()),
+        (ColType::Float, DataValue::Str(s)) if text => {
+            s.trim().parse::<f64>().ok().map(DataValue::from)
+        }
+        (ColType::Float, DataValue::Num(n)) => Some(DataValue::from(n.get_float())),
+        (ColType::Bool, DataValue::Str(s)) if text => {
+            match s.trim().to_ascii_lowercase().as_str() {
+                "true" => Some(DataValue::Bool(true)),
+                "false" => Some(DataValue::Bool(false)),
+                _ => None,
+            }
+        }
+        (ColType::Bool, v @ DataValue::Bool(_)) => Some(v.clone()),
+        (ColType::Timestamp, v @ DataValue::Str(_)) => NullableColType {
+            coltype: ColType::Timestamp,
+            nullable: false,
+        }
+        .coerce(v.clone())
+        .ok(),
+        (ColType::Uuid, v @ DataValue::Str(_)) => op_to_uuid(std::slice::from_ref(v)).ok(),
+        (ColType::String, v @ DataValue::Str(_)) => Some(v.clone()),
+        _ => None,
+    }
+}
+
+/// Infers the type of a column from the values it has in the sample: the first of the
+/// candidate types accepting all of them, or `Any` if all are null.
+fn infer_type<'a>(values: impl Iterator<Item = &'a DataValue>, text: bool) -> ColType {
+    let mut possible = [true; CANDIDATE_TYPES.len()];
+    let mut seen = false;
+    for val in values.filter(|v| **v != DataValue::Null) {
+        seen = true;
+        for (ok, coltype) in possible.iter_mut().zip(CANDIDATE_TYPES.iter()) {
+            *ok = *ok && convert(val, coltype, text).is_some();
+        }
+    }
+    if !seen {
+        return ColType::Any;
+    }
+    CANDIDATE_TYPES
+        .iter()
+        .zip(possible)
+        .find(|(_, ok)| *ok)
+        .map(|(coltype, _)| coltype.clone())
+        .unwrap_or(ColType::Any)
+}
+
+/// What became of a column of the source.
+struct ImportedColumn {
+    name: String,
+    coltype: ColType,
+    /// Number of values that could not be converted to the inferred type and were
+    /// stored as null
+    failures: usize,
+    /// The first value that could not be converted
+    failed_example: Option<DataValue>,
+}
+
+impl SessionTx {
+    /// Creates the relation `name` with the rows of the source, the types of its columns
+    /// inferred from the first rows. Values failing to convert to the type of their column
+    /// are stored as null. The key is the column `id` if it has no nulls nor duplicates,
+    /// otherwise the first column of a type other than `Float` or `Any` that has none, or
+    /// failing that a new column `_row` numbering the rows. Returns the report of the columns
+    /// and the key ranges to delete after commit.
+    pub(crate) fn import_inferred(
+        &mut self,
+        db: &Db,
+        name: &Symbol,
+        import: &InferredImport,
+    ) -> Result<(JsonValue, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let text = import.format()? == ImportFormat::Csv;
+        let (names, raw_rows) = import.read()?;
+        let mut columns = names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let coltype =
+                    infer_type(raw_rows.iter().take(import.sample).map(|row| &row[i]), text);
+                ImportedColumn {
+                    name,
+                    coltype,
+                    failures: 0,
+                    failed_example: None,
+                }
+            })
+            .collect_vec();
+
+        let mut rows = Vec::with_capacity(raw_rows.len());
+        for raw_row in raw_rows {
+            let mut row = Vec::with_capacity(raw_row.len());
+            for (col, val) in columns.iter_mut().zip(raw_row) {
+                if val == DataValue::Null {
+                    row.push(val);
+                    continue;
+                }
+                match convert(&val, &col.coltype, text) {
+                    Some(converted) => row.push(converted),
+                    None => {
+                        col.failures += 1;
+                        col.failed_example.get_or_insert(val);
+                        row.push(DataValue::Null);
+                    }
+                }
+            }
+            rows.push(row);
+        }
+
+        let is_key = |i: usize| {
+            let mut seen = BTreeSet::new();
+            rows.iter()
+                .all(|row| row[i] != DataValue::Null && seen.insert(&row[i]))
+        };
+        let key_idx = columns
+            .iter()
+            .position(|col| col.name == "id")
+            .filter(|i| is_key(*i))
+            .or_else(|| {
+                (0..columns.len()).find(|i| {
+                    !matches!(columns[*i].coltype, ColType::Float | ColType::Any) && is_key(*i)
+                })
+            });
+        let key_col = match key_idx {
+            Some(i) => {
+                for row in rows.iter_mut() {
+                    let key = row.remove(i);
+                    row.insert(0, key);
+                }
+                columns.remove(i)
+            }
+            None => {
+                for (i, row) in rows.iter_mut().enumerate() {
+                    row.insert(0, DataValue::from(i as i64));
+                }
+                ImportedColumn {
+                    name: ROW_COLUMN.to_string(),
+                    coltype: ColType::Int,
+                    failures: 0,
+                    failed_example: None,
+                }
+            }
+        };
+
+        let column_def = |col: &ImportedColumn, nullable: bool| ColumnDef {
+            name: SmartString::from(col.name.as_str()),
+            typing: NullableColType {
+                coltype: col.coltype.clone(),
+                nullable,
+            },
+            default_gen: None,
+        };
+        let metadata = StoredRelationMetadata {
+            keys: vec![column_def(&key_col, false)],
+            non_keys: columns.iter().map(|col| column_def(col, true)).collect(),
+        };
+        let report = std::iter::once((&key_col, true))
+            .chain(columns.iter().map(|col| (col, false)))
+            .map(|(col, key)| {
+                json!([
+                    col.name,
+                    column_def(col, !key).typing.to_string(),
+                    key,
+                    col.failures,
+                    col.failed_example.clone().map(JsonValue::from)
+                ])
+            })
+            .collect_vec();
+        let handle = InputRelationHandle {
+            name: name.clone(),
+            key_bindings: column_symbols(&metadata.keys),
+            dep_bindings: column_symbols(&metadata.non_keys),
+            metadata,
+            span: name.span,
+        };
+        let mut headers = handle.key_bindings.clone();
+        headers.extend(handle.dep_bindings.iter().cloned());
+        let cleanups = self.execute_relation(
+            db,
+            rows.into_iter().map(|row| Ok(Tuple(row))),
+            RelationOp::Create,
+            &handle,
+            &headers,
+        )?;
+        Ok((
+            json!({
+                "headers": ["column", "type", "key", "failures", "failed_example"],
+                "rows": report
+            }),
+            cleanups,
+        ))
+    }
+}
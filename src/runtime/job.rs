@@ -0,0 +1,286 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Jobs: named scripts run at the times given by cron expressions, by the scheduler thread of
+//! the database or on demand with `::job run`. Jobs are kept in the catalog together with the
+//! history of their runs, in which failures are kept longer than successes.
+
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc};
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+
+/// Number of successful runs of a job kept in its history.
+const JOB_HISTORY_SUCCEEDED: usize = 16;
+/// Number of failed runs of a job kept in its history.
+const JOB_HISTORY_FAILED: usize = 64;
+/// How far ahead the next time a cron expression fires is looked for.
+const CRON_SEARCH_DAYS: i64 = 366 * 5;
+
+/// The times matched by a cron expression of five fields: minutes, hours, days of the month,
+/// months and days of the week, each a set of values held as bits. Times are in UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the days of the month are restricted, in which case a day matches if either
+    /// its day of the month or its day of the week does, as in cron
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+/// Parses a field of a cron expression: a comma-separated list of `*`, values and ranges of
+/// values, each optionally followed by `/step`.
+fn parse_cron_field(field: &str, name: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            None => (part, None),
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("bad step '{}' for {}", step, name)),
+            },
+        };
+        let value = |s: &str| match s.parse::<u32>() {
+            Ok(v) if (min..=max).contains(&v) => Ok(v),
+            _ => Err(format!(
+                "bad value '{}' for {}, expected {} to {}",
+                s, name, min, max
+            )),
+        };
+        let (lo, hi) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((lo, hi)) => (value(lo)?, value(hi)?),
+                None if step.is_some() => (value(range)?, max),
+                None => {
+                    let v = value(range)?;
+                    (v, v)
+                }
+            },
+        };
+        if lo > hi {
+            return Err(format!("empty range '{}' for {}", range, name));
+        }
+        for v in (lo..=hi).step_by(step.unwrap_or(1) as usize) {
+            bits |= 1 << v;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expanded = match s.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            s => s,
+        };
+        let fields: Vec<_> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!(
+                "expected 5 fields (minute, hour, day of month, month, day of week), got {}",
+                fields.len()
+            ));
+        }
+        let mut weekdays = parse_cron_field(fields[4], "day of week", 0, 7)?;
+        // both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], "minute", 0, 59)?,
+            hours: parse_cron_field(fields[1], "hour", 0, 23)?,
+            days: parse_cron_field(fields[2], "day of month", 1, 31)?,
+            months: parse_cron_field(fields[3], "month", 1, 12)?,
+            weekdays,
+            // as in cron, a field starting with `*`, such as `*/2`, is not a restriction
+            days_restricted: !fields[2].starts_with('*'),
+            weekdays_restricted: !fields[4].starts_with('*'),
+        })
+    }
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+    /// The first time strictly after `t` matched by the expression, in seconds since the
+    /// epoch, or `None` if it does not fire within the next few years.
+    pub(crate) fn next_after(&self, t: f64) -> Option<f64> {
+        let start = (t.floor() as i64).div_euclid(60) * 60 + 60;
+        let mut at = Utc.timestamp_opt(start, 0).single()?.naive_utc();
+        let limit = at + Duration::days(CRON_SEARCH_DAYS);
+        while at < limit {
+            let date = at.date();
+            if self.months & (1 << date.month()) == 0 {
+                let (year, month) = if date.month() == 12 {
+                    (date.year() + 1, 1)
+                } else {
+                    (date.year(), date.month() + 1)
+                };
+                at = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                at = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << at.hour()) == 0 {
+                at = date.and_hms_opt(at.hour(), 0, 0)? + Duration::hours(1);
+            } else if self.minutes & (1 << at.minute()) == 0 {
+                at += Duration::minutes(1);
+            } else {
+                return Some(Utc.from_utc_datetime(&at).timestamp() as f64);
+            }
+        }
+        None
+    }
+}
+
+/// A script run at the times given by the cron expression `cron`.
+#[derive(Clone, Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct Job {
+    pub(crate) cron: String,
+    pub(crate) script: String,
+    /// When the job is next run by the scheduler, in seconds since the epoch
+    pub(crate) next_run: Option<f64>,
+    pub(crate) last_run: Option<f64>,
+    /// Number of runs that failed since the last one that succeeded
+    #[serde(default)]
+    pub(crate) failures: u64,
+    /// The label of the script that created the job, under which the job runs
+    #[serde(default)]
+    pub(crate) label: Option<String>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad cron expression '{0}': {1}")]
+#[diagnostic(code(eval::bad_cron_expr))]
+struct BadCronExpr(String, String);
+
+impl Job {
+    /// The first time after `t` at which the job is to be run, if any.
+    pub(crate) fn next_run_after(&self, t: f64) -> Result<Option<f64>> {
+        let schedule = CronSchedule::from_str(&self.cron)
+            .map_err(|msg| BadCronExpr(self.cron.clone(), msg))?;
+        Ok(schedule.next_after(t))
+    }
+}
+
+/// A run of a job. Times are in seconds since the epoch.
+#[derive(Clone, Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct JobRun {
+    pub(crate) started: f64,
+    pub(crate) finished: f64,
+    /// The error the script failed with, if it did
+    pub(crate) error: Option<String>,
+}
+
+fn job_key(name: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("job")),
+        DataValue::Str(SmartString::from(name)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+fn job_run_key(name: &str, started: DataValue) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("job_run")),
+        DataValue::Str(SmartString::from(name)),
+        started,
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl SessionTx {
+    pub(crate) fn get_job(&self, name: &str) -> Result<Option<Job>> {
+        match self.tx.get(&job_key(name), false)? {
+            None => Ok(None),
+            Some(slice) => Ok(Some(serde_json::from_slice(&slice).into_diagnostic()?)),
+        }
+    }
+    pub(crate) fn put_job(&mut self, name: &str, job: &Job) -> Result<()> {
+        let val = serde_json::to_vec(job).into_diagnostic()?;
+        self.tx.put(&job_key(name), &val)?;
+        Ok(())
+    }
+    /// Removes a job together with its history. Returns whether it existed.
+    pub(crate) fn remove_job(&mut self, name: &str) -> Result<bool> {
+        let key = job_key(name);
+        let existed = self.tx.exists(&key, true)?;
+        if existed {
+            self.tx.del(&key)?;
+            for (started, _) in self.job_history(name)? {
+                self.tx.del(&job_run_key(name, DataValue::from(started)))?;
+            }
+        }
+        Ok(existed)
+    }
+    pub(crate) fn list_jobs(&self) -> Result<Vec<(String, Job)>> {
+        let lower = job_key("");
+        let upper = job_key(&String::from(LARGEST_UTF_CHAR));
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            let name = key.0[2].get_string().unwrap_or_default().to_string();
+            collected.push((name, serde_json::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(collected)
+    }
+    /// The runs of a job kept in its history, oldest first.
+    pub(crate) fn job_history(&self, name: &str) -> Result<Vec<(f64, JobRun)>> {
+        let lower = job_run_key(name, DataValue::Null);
+        let upper = job_run_key(name, DataValue::Bot);
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            let started = key.0[3].get_float().unwrap_or_default();
+            collected.push((started, serde_json::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(collected)
+    }
+    /// Records a run of a job in its history, dropping the oldest runs beyond the number
+    /// of successful and of failed runs kept.
+    pub(crate) fn record_job_run(&mut self, name: &str, run: &JobRun) -> Result<()> {
+        let val = serde_json::to_vec(run).into_diagnostic()?;
+        self.tx
+            .put(&job_run_key(name, DataValue::from(run.started)), &val)?;
+        let (mut succeeded, mut failed) = (0, 0);
+        for (started, run) in self.job_history(name)?.into_iter().rev() {
+            let kept = if run.error.is_none() {
+                succeeded += 1;
+                succeeded <= JOB_HISTORY_SUCCEEDED
+            } else {
+                failed += 1;
+                failed <= JOB_HISTORY_FAILED
+            };
+            if !kept {
+                self.tx.del(&job_run_key(name, DataValue::from(started)))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Maintained relations: relations holding a projection of a base relation on some of its
+//! columns, optionally with aggregations over the rows of each group, kept up to date on every
+//! write to the base relation. Only the groups touched by a write are recomputed, from the rows
+//! of the base relation, so any aggregation can be used.
+
+use std::collections::BTreeSet;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::program::RelationOp;
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::parse_script;
+use crate::query::stored::make_const_rule;
+use crate::runtime::db::column_symbols;
+use crate::runtime::relation::{
+    AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle, RelationId,
+};
+use crate::runtime::transact::SessionTx;
+use crate::Db;
+
+/// A relation keyed by the columns `by` of the relation `base`, with the results of `columns`
+/// over the rows of each group as its other columns.
+#[derive(Clone, Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct MaintainedRelation {
+    pub(crate) base: String,
+    pub(crate) by: Vec<String>,
+    pub(crate) columns: Vec<MaintainedColumn>,
+}
+
+/// A column `name` holding the aggregation `aggr` applied to the column `arg`, as written in
+/// the definition, e.g. `count(x)`.
+#[derive(Clone, Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct MaintainedColumn {
+    pub(crate) name: String,
+    pub(crate) aggr: String,
+    pub(crate) arg: String,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Column '{0}' not found in relation '{1}'")]
+#[diagnostic(code(eval::maintained_column_not_found))]
+struct MaintainedColumnNotFound(String, String);
+
+impl MaintainedRelation {
+    /// The query computing the rows of the relation from those of the base relation, only
+    /// for the groups in the rule `_groups` if `restricted`.
+    fn script(&self, base: &str, restricted: bool) -> String {
+        let head = self
+            .by
+            .iter()
+            .cloned()
+            .chain(self.columns.iter().map(|col| col.aggr.clone()))
+            .join(", ");
+        let bound = self
+            .by
+            .iter()
+            .chain(self.columns.iter().map(|col| &col.arg))
+            .unique()
+            .join(", ");
+        if restricted {
+            format!(
+                "?[{}] := _groups[{}], *{}{{{}}}",
+                head,
+                self.by.join(", "),
+                base,
+                bound
+            )
+        } else {
+            format!("?[{}] := *{}{{{}}}", head, base, bound)
+        }
+    }
+    /// The positions of the grouping columns in the rows of the base relation.
+    fn group_positions(&self, base: &RelationHandle) -> Result<Vec<usize>> {
+        let all_cols = base
+            .metadata
+            .keys
+            .iter()
+            .chain(base.metadata.non_keys.iter())
+            .collect_vec();
+        self.by
+            .iter()
+            .map(|name| {
+                all_cols
+                    .iter()
+                    .position(|col| col.name.as_str() == name.as_str())
+                    .ok_or_else(|| {
+                        MaintainedColumnNotFound(name.clone(), base.name.to_string()).into()
+                    })
+            })
+            .collect()
+    }
+}
+
+fn maintained_key(name: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("maintained")),
+        DataValue::Str(SmartString::from(name)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl SessionTx {
+    pub(crate) fn get_maintained(&self, name: &str) -> Result<Option<MaintainedRelation>> {
+        match self.tx.get(&maintained_key(name), false)? {
+            None => Ok(None),
+            Some(slice) => Ok(Some(serde_json::from_slice(&slice).into_diagnostic()?)),
+        }
+    }
+    pub(crate) fn list_maintained(&self) -> Result<Vec<(String, MaintainedRelation)>> {
+        let lower = maintained_key("");
+        let upper = maintained_key(&String::from(LARGEST_UTF_CHAR));
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            let name = key.0[2].get_string().unwrap_or_default().to_string();
+            collected.push((name, serde_json::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(collected)
+    }
+    /// Adds or removes `target` from the relations maintained from `base`.
+    pub(crate) fn set_maintained_by(
+        &mut self,
+        base: &str,
+        target: &str,
+        maintained: bool,
+    ) -> Result<()> {
+        let mut handle = self.get_relation(base, true)?;
+        handle.maintained.retain(|name| name != target);
+        if maintained {
+            handle.maintained.push(target.to_string());
+        }
+        let name_key =
+            Tuple(vec![DataValue::Str(handle.name.clone())]).encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.tx.put(&name_key, &meta_val)?;
+        Ok(())
+    }
+    /// Creates the maintained relation `name` and fills it from the base relation. Returns
+    /// the key ranges to delete after commit.
+    pub(crate) fn create_maintained(
+        &mut self,
+        db: &Db,
+        name: &Symbol,
+        rule: &MaintainedRelation,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let base = self.get_relation(&rule.base, true)?;
+        if base.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                base.name.to_string(),
+                "maintaining relations".to_string(),
+                base.access_level
+            ))
+        }
+        let all_cols = base
+            .metadata
+            .keys
+            .iter()
+            .chain(base.metadata.non_keys.iter())
+            .collect_vec();
+        for col in rule
+            .by
+            .iter()
+            .chain(rule.columns.iter().map(|col| &col.arg))
+        {
+            if !all_cols.iter().any(|c| c.name.as_str() == col.as_str()) {
+                bail!(MaintainedColumnNotFound(col.clone(), base.name.to_string()))
+            }
+        }
+        let keys = rule
+            .by
+            .iter()
+            .map(|col| {
+                let mut def = (*all_cols
+                    .iter()
+                    .find(|c| c.name.as_str() == col.as_str())
+                    .unwrap())
+                .clone();
+                def.default_gen = None;
+                def
+            })
+            .collect_vec();
+        let non_keys = rule
+            .columns
+            .iter()
+            .map(|col| ColumnDef {
+                name: SmartString::from(col.name.as_str()),
+                typing: NullableColType {
+                    coltype: ColType::Any,
+                    nullable: true,
+                },
+                default_gen: None,
+            })
+            .collect_vec();
+        let metadata = StoredRelationMetadata { keys, non_keys };
+        self.create_relation(InputRelationHandle {
+            name: name.clone(),
+            key_bindings: column_symbols(&metadata.keys),
+            dep_bindings: column_symbols(&metadata.non_keys),
+            metadata,
+            span: name.span,
+        })?;
+        let val = serde_json::to_vec(rule).into_diagnostic()?;
+        self.tx.put(&maintained_key(name), &val)?;
+        self.set_maintained_by(&rule.base, name, true)?;
+        self.refresh_maintained(db, name, rule, &base.name, None)
+    }
+    /// Stops maintaining the relation `name`, which is kept with its current rows. Returns
+    /// whether it was maintained.
+    pub(crate) fn remove_maintained(&mut self, name: &str) -> Result<bool> {
+        let rule = match self.get_maintained(name)? {
+            None => return Ok(false),
+            Some(rule) => rule,
+        };
+        self.tx.del(&maintained_key(name))?;
+        if self.relation_exists(&rule.base)? {
+            self.set_maintained_by(&rule.base, name, false)?;
+        }
+        Ok(true)
+    }
+    /// Brings the relations maintained from `base` up to date after a write to it. `changed`
+    /// are the rows of the base relation written or removed, whose groups are recomputed, or
+    /// `None` if all groups are to be recomputed. Returns the key ranges to delete after commit.
+    pub(crate) fn update_maintained(
+        &mut self,
+        db: &Db,
+        base: &RelationHandle,
+        changed: Option<&[DataValue]>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut cleanups = vec![];
+        for name in &base.maintained {
+            let rule = match self.get_maintained(name)? {
+                None => continue,
+                Some(rule) => rule,
+            };
+            let groups = match changed {
+                None => None,
+                Some(rows) => {
+                    let positions = rule.group_positions(base)?;
+                    let groups: BTreeSet<Tuple> = rows
+                        .iter()
+                        .filter_map(|row| match row {
+                            DataValue::List(row) => {
+                                Some(Tuple(positions.iter().map(|i| row[*i].clone()).collect()))
+                            }
+                            _ => None,
+                        })
+                        .collect();
+                    Some(groups)
+                }
+            };
+            cleanups.extend(self.refresh_maintained(db, name, &rule, &base.name, groups)?);
+        }
+        Ok(cleanups)
+    }
+    /// Recomputes the rows of the maintained relation `name` for `groups`, or for all groups
+    /// if `None`, removing those groups no longer found in the base relation.
+    fn refresh_maintained(
+        &mut self,
+        db: &Db,
+        name: &str,
+        rule: &MaintainedRelation,
+        base: &str,
+        groups: Option<BTreeSet<Tuple>>,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let script = rule.script(base, groups.is_some());
+        let mut program = parse_script(&script, &Default::default(), &self.services.custom_algos)?
+            .get_single_program()?;
+        if let Some(groups) = &groups {
+            if groups.is_empty() {
+                return Ok(vec![]);
+            }
+            let bindings = rule
+                .by
+                .iter()
+                .map(|col| Symbol::new(col.as_str(), Default::default()))
+                .collect_vec();
+            let data = groups
+                .iter()
+                .map(|group| DataValue::List(group.0.clone()))
+                .collect_vec();
+            make_const_rule(&mut program, "_groups", bindings, data);
+        }
+        let (rows, _in_mem_guard) = db.query_rows(self, program, &script)?;
+        let rows = rows.collect_vec();
+
+        let target = self.get_relation(name, false)?;
+        let n_keys = target.metadata.keys.len();
+        let found: BTreeSet<Tuple> = rows
+            .iter()
+            .map(|row| Tuple(row.0[..n_keys].to_vec()))
+            .collect();
+        let previous: BTreeSet<Tuple> = match groups {
+            Some(groups) => groups,
+            None => target
+                .scan_all(self)
+                .map_ok(|row| Tuple(row.0[..n_keys].to_vec()))
+                .collect::<Result<_>>()?,
+        };
+        let stale = previous.difference(&found).cloned().map(Ok).collect_vec();
+
+        let metadata = target.metadata.clone();
+        let handle = InputRelationHandle {
+            name: Symbol::new(name, Default::default()),
+            key_bindings: column_symbols(&metadata.keys),
+            dep_bindings: column_symbols(&metadata.non_keys),
+            metadata,
+            span: Default::default(),
+        };
+        let mut headers = handle.key_bindings.clone();
+        headers.extend(handle.dep_bindings.iter().cloned());
+        let mut cleanups = vec![];
+        if !stale.is_empty() {
+            cleanups.extend(self.execute_relation(
+                db,
+                stale.into_iter(),
+                RelationOp::Rm,
+                &handle,
+                &handle.key_bindings,
+            )?);
+        }
+        cleanups.extend(self.execute_relation(
+            db,
+            rows.into_iter().map(Ok),
+            RelationOp::Put,
+            &handle,
+            &headers,
+        )?);
+        Ok(cleanups)
+    }
+}
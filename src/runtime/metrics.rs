@@ -0,0 +1,154 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::storage::CompactionStats;
+
+/// Counters and gauges describing the engine internals of one database.
+#[derive(Default)]
+pub(crate) struct Metrics {
+    pub(crate) queries_run: AtomicU64,
+    pub(crate) queries_failed: AtomicU64,
+    pub(crate) rows_scanned: AtomicU64,
+    pub(crate) rows_written: AtomicU64,
+    pub(crate) manual_compactions: AtomicU64,
+    pub(crate) remote_cache_hits: AtomicU64,
+    pub(crate) remote_cache_misses: AtomicU64,
+    pub(crate) active_transactions: AtomicI64,
+    pub(crate) in_mem_tuples: AtomicI64,
+}
+
+/// Adds to a gauge of `metrics` on creation and subtracts whatever it last added on drop.
+pub(crate) struct GaugeGuard {
+    metrics: Arc<Metrics>,
+    gauge: fn(&Metrics) -> &AtomicI64,
+    amount: i64,
+}
+
+impl GaugeGuard {
+    pub(crate) fn new(
+        metrics: Arc<Metrics>,
+        gauge: fn(&Metrics) -> &AtomicI64,
+        amount: i64,
+    ) -> Self {
+        gauge(&metrics).fetch_add(amount, Ordering::Relaxed);
+        Self {
+            metrics,
+            gauge,
+            amount,
+        }
+    }
+    /// Changes the amount added to the gauge to `amount`.
+    pub(crate) fn set(&mut self, amount: i64) {
+        (self.gauge)(&self.metrics).fetch_add(amount - self.amount, Ordering::Relaxed);
+        self.amount = amount;
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        (self.gauge)(&self.metrics).fetch_sub(self.amount, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Render in the Prometheus text exposition format, together with the state of the
+    /// compactions of the storage engine if it runs them.
+    pub(crate) fn render_prometheus(
+        &self,
+        running_queries: usize,
+        compactions: Option<CompactionStats>,
+    ) -> String {
+        let mut ret = String::new();
+        let mut put = |name: &str, kind: &str, help: &str, val: i64| {
+            writeln!(ret, "# HELP {} {}", name, help).unwrap();
+            writeln!(ret, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(ret, "{} {}", name, val).unwrap();
+        };
+        put(
+            "cozo_queries_total",
+            "counter",
+            "Number of queries run",
+            self.queries_run.load(Ordering::Relaxed) as i64,
+        );
+        put(
+            "cozo_queries_failed_total",
+            "counter",
+            "Number of scripts that returned an error",
+            self.queries_failed.load(Ordering::Relaxed) as i64,
+        );
+        put(
+            "cozo_rows_scanned_total",
+            "counter",
+            "Number of rows read from stored relations",
+            self.rows_scanned.load(Ordering::Relaxed) as i64,
+        );
+        put(
+            "cozo_rows_written_total",
+            "counter",
+            "Number of rows put into or removed from stored relations",
+            self.rows_written.load(Ordering::Relaxed) as i64,
+        );
+        put(
+            "cozo_manual_compactions_total",
+            "counter",
+            "Number of manual compactions requested",
+            self.manual_compactions.load(Ordering::Relaxed) as i64,
+        );
+        put(
+            "cozo_remote_cache_hits_total",
+            "counter",
+            "Number of requests to remote databases answered from the cache",
+            self.remote_cache_hits.load(Ordering::Relaxed) as i64,
+        );
+        put(
+            "cozo_remote_cache_misses_total",
+            "counter",
+            "Number of requests to remote databases not answered from the cache",
+            self.remote_cache_misses.load(Ordering::Relaxed) as i64,
+        );
+        put(
+            "cozo_active_transactions",
+            "gauge",
+            "Number of open transactions",
+            self.active_transactions.load(Ordering::Relaxed),
+        );
+        put(
+            "cozo_running_queries",
+            "gauge",
+            "Number of queries currently running",
+            running_queries as i64,
+        );
+        put(
+            "cozo_in_mem_tuples",
+            "gauge",
+            "Number of tuples held in in-memory relations of running queries",
+            self.in_mem_tuples.load(Ordering::Relaxed),
+        );
+        if let Some(stats) = compactions {
+            put(
+                "cozo_compaction_pending",
+                "gauge",
+                "Whether a compaction of the storage engine is waiting to run",
+                stats.pending as i64,
+            );
+            put(
+                "cozo_running_compactions",
+                "gauge",
+                "Number of compactions the storage engine is running",
+                stats.running as i64,
+            );
+            put(
+                "cozo_compaction_pending_bytes",
+                "gauge",
+                "Estimated number of bytes compactions of the storage engine have to rewrite",
+                stats.pending_bytes as i64,
+            );
+        }
+        ret
+    }
+}
@@ -2,7 +2,29 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
+pub(crate) mod access;
+pub(crate) mod audit;
+pub(crate) mod backup;
+pub(crate) mod cdc;
+pub(crate) mod columnar;
+pub(crate) mod continuous;
+pub(crate) mod csv_import;
 pub(crate) mod db;
-pub(crate) mod transact;
+pub(crate) mod export;
+pub(crate) mod federation;
+pub(crate) mod graph_view;
+pub(crate) mod hnsw;
 pub(crate) mod in_mem;
+pub(crate) mod infer;
+pub(crate) mod job;
+pub(crate) mod maintain;
+pub(crate) mod metrics;
+pub(crate) mod namespace;
+pub(crate) mod plan;
 pub(crate) mod relation;
+pub(crate) mod replication;
+pub(crate) mod schedule;
+pub(crate) mod stats;
+pub(crate) mod sync;
+pub(crate) mod tiering;
+pub(crate) mod transact;
@@ -0,0 +1,510 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Namespaces, letting one database hold the relations of several logical databases.
+//! A relation named `ns.rel` belongs to the namespace `ns` once it is created, and removing
+//! the namespace removes all of them. Without a namespace `ns`, `ns.rel` is a plain relation
+//! name as before namespaces existed.
+//! A namespace may be given a quota bounding the storage taken by its relations, the rows
+//! returned by queries reading or writing them and the time such queries may run.
+//!
+//! The storage taken by each relation of a namespace with a storage quota is kept in a
+//! counter, which the transactions writing to the relation move by the bytes they write.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::storage::{KvIter, StoreTx};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Namespace '{0}' not found")]
+#[diagnostic(code(eval::namespace_not_found))]
+#[diagnostic(help("Namespaces are created with `::namespace create`"))]
+pub(crate) struct NamespaceNotFound(pub(crate) String);
+
+/// Limits on the use of a namespace, as set by `::namespace quota` or
+/// [`Db::set_namespace_quota`](crate::Db::set_namespace_quota). Every limit defaults to none.
+#[derive(Clone, Debug, Default, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub struct NamespaceQuota {
+    /// Bytes the keys and values of all relations of the namespace may take together.
+    /// Checked after each write to one of its relations against counters moved by the
+    /// writes. A relation is scanned to set its counter when the quota is set, and again
+    /// after a range of its rows is deleted.
+    pub max_storage_bytes: Option<u64>,
+    /// Rows a query reading or writing relations of the namespace may return or write.
+    pub max_result_rows: Option<usize>,
+    /// Seconds a query reading or writing relations of the namespace may run before it
+    /// is killed.
+    pub max_query_secs: Option<f64>,
+}
+
+impl NamespaceQuota {
+    /// The quota with the tighter of each limit of `self` and `other`.
+    fn tighten(self, other: NamespaceQuota) -> NamespaceQuota {
+        fn tighter<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+                (a, b) => a.or(b),
+            }
+        }
+        NamespaceQuota {
+            max_storage_bytes: tighter(self.max_storage_bytes, other.max_storage_bytes),
+            max_result_rows: tighter(self.max_result_rows, other.max_result_rows),
+            max_query_secs: tighter(self.max_query_secs, other.max_query_secs),
+        }
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relations of namespace '{0}' take {1} bytes, over its quota of {2} bytes")]
+#[diagnostic(code(eval::namespace_storage_quota))]
+#[diagnostic(help("Remove rows from the namespace or raise its quota with `::namespace quota`"))]
+struct NamespaceStorageQuotaExceeded(String, u64, u64);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query returns more than {0} rows, over the quota of a namespace it uses")]
+#[diagnostic(code(eval::namespace_result_quota))]
+#[diagnostic(help("Narrow the query down, or use ':limit'"))]
+pub(crate) struct NamespaceResultQuotaExceeded(pub(crate) usize);
+
+/// The storage counted for the relations of namespaces with a storage quota, as a
+/// transaction writes to them.
+#[derive(Default)]
+pub(crate) struct StorageUsage {
+    /// Whether each namespace looked up has a storage quota
+    namespaces: BTreeMap<SmartString<LazyCompact>, bool>,
+    /// The relation the keys under each id prefix count towards
+    prefixes: BTreeMap<[u8; 8], RelationId>,
+    /// The relations counted
+    relations: BTreeMap<RelationId, CountedRelation>,
+    /// The relations counted at each savepoint
+    saves: Vec<BTreeMap<RelationId, CountedRelation>>,
+}
+
+#[derive(Clone)]
+struct CountedRelation {
+    name: SmartString<LazyCompact>,
+    /// The ids of the relation and of its history, tombstones, soft-deleted rows and vector
+    /// index when counting started
+    ids: Vec<RelationId>,
+    /// The bytes the transaction added, negative when it removed more
+    delta: i64,
+    written: bool,
+    /// Whether a range of keys was deleted, so that the bytes must be found by a scan
+    rescan: bool,
+}
+
+impl CountedRelation {
+    fn reset(&mut self) {
+        self.delta = 0;
+        self.written = false;
+        self.rescan = false;
+    }
+}
+
+impl StorageUsage {
+    fn counted(&mut self, key: &[u8]) -> Option<&mut CountedRelation> {
+        let id = self.prefixes.get(&key_prefix(key)?)?;
+        self.relations.get_mut(id)
+    }
+}
+
+fn key_prefix(key: &[u8]) -> Option<[u8; 8]> {
+    key.get(..8)?.try_into().ok()
+}
+
+fn stored_bytes(tx: &dyn StoreTx, key: &[u8]) -> Result<i64> {
+    Ok(match tx.get(key, false)? {
+        None => 0,
+        Some(val) => (key.len() + val.len()) as i64,
+    })
+}
+
+/// A transaction counting the bytes written to the relations of namespaces with a storage
+/// quota, so that the quota is checked without scanning the namespace.
+pub(crate) struct UsageCountingTx {
+    pub(crate) inner: Box<dyn StoreTx>,
+    pub(crate) usage: Arc<Mutex<StorageUsage>>,
+}
+
+impl StoreTx for UsageCountingTx {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(rel) = usage.counted(key) {
+            rel.delta += (key.len() + val.len()) as i64 - stored_bytes(&*self.inner, key)?;
+            rel.written = true;
+        }
+        self.inner.put(key, val)
+    }
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(rel) = usage.counted(key) {
+            rel.delta -= stored_bytes(&*self.inner, key)?;
+            rel.written = true;
+        }
+        self.inner.del(key)
+    }
+    fn range_scan(&self, lower: &[u8], upper: &[u8]) -> KvIter {
+        self.inner.range_scan(lower, upper)
+    }
+    fn range_del(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        // counting the bytes here would read the values the engine deletes without reading
+        if let (Some(from), Some(to)) = (key_prefix(lower), key_prefix(upper)) {
+            let mut usage = self.usage.lock().unwrap();
+            let usage = &mut *usage;
+            if from <= to {
+                // keys under the prefix of `upper` are deleted only if it goes past the prefix
+                let in_range = if upper.len() > 8 {
+                    usage.prefixes.range(from..=to)
+                } else {
+                    usage.prefixes.range(from..to)
+                };
+                for (_, id) in in_range {
+                    if let Some(rel) = usage.relations.get_mut(id) {
+                        rel.written = true;
+                        rel.rescan = true;
+                    }
+                }
+            }
+        }
+        self.inner.range_del(lower, upper)
+    }
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+    fn rollback(&mut self) -> Result<()> {
+        let mut usage = self.usage.lock().unwrap();
+        usage
+            .relations
+            .values_mut()
+            .for_each(CountedRelation::reset);
+        usage.saves.clear();
+        drop(usage);
+        self.inner.rollback()
+    }
+    fn save(&mut self) {
+        let mut usage = self.usage.lock().unwrap();
+        let relations = usage.relations.clone();
+        usage.saves.push(relations);
+        drop(usage);
+        self.inner.save();
+    }
+    fn pop_save(&mut self) -> Result<()> {
+        self.inner.pop_save()?;
+        self.usage.lock().unwrap().saves.pop();
+        Ok(())
+    }
+    fn rollback_to_save(&mut self) -> Result<()> {
+        self.inner.rollback_to_save()?;
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(mut saved) = usage.saves.pop() {
+            // relations first counted after the savepoint had nothing written before it
+            for (id, rel) in &usage.relations {
+                saved.entry(*id).or_insert_with(|| {
+                    let mut rel = rel.clone();
+                    rel.reset();
+                    rel
+                });
+            }
+            usage.relations = saved;
+        }
+        Ok(())
+    }
+}
+
+/// The ids the keys of the relation, of its history, tombstones, soft-deleted rows and
+/// vector index are stored under, all counting towards its storage.
+fn storage_ids(handle: &RelationHandle) -> Vec<RelationId> {
+    [
+        Some(handle.id),
+        handle.history,
+        handle.lww,
+        handle.soft_deleted,
+        handle.vector_index.as_ref().map(|idx| idx.id),
+    ]
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+fn usage_key(id: RelationId) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("storage_usage")),
+        DataValue::from(id.0 as i64),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+fn decode_usage(bytes: &[u8]) -> u64 {
+    bytes.try_into().map(u64::from_be_bytes).unwrap_or_default()
+}
+
+fn namespace_key(name: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("namespace")),
+        DataValue::Str(SmartString::from(name)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+/// The namespace the relation `name` belongs to, if any.
+pub(crate) fn relation_namespace(name: &str) -> Option<&str> {
+    match name.split_once('.') {
+        Some((ns, _)) if !name.contains("::") => Some(ns),
+        _ => None,
+    }
+}
+
+impl SessionTx {
+    pub(crate) fn create_namespace(&mut self, name: &str) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("'{0}' is not a valid name for a namespace")]
+        #[diagnostic(code(eval::bad_namespace_name))]
+        #[diagnostic(help("Names of namespaces must be non-empty and contain no '.' or '::'"))]
+        struct BadNamespaceName(String);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Namespace '{0}' already exists")]
+        #[diagnostic(code(eval::namespace_exists))]
+        struct NamespaceExists(String);
+
+        if name.is_empty() || name.contains('.') || name.contains("::") {
+            bail!(BadNamespaceName(name.to_string()))
+        }
+        let key = namespace_key(name);
+        if self.tx.exists(&key, true)? {
+            bail!(NamespaceExists(name.to_string()))
+        }
+        self.tx.put(&key, &[])?;
+        Ok(())
+    }
+    pub(crate) fn set_namespace_quota(&mut self, name: &str, quota: &NamespaceQuota) -> Result<()> {
+        let key = namespace_key(name);
+        if !self.tx.exists(&key, true)? {
+            bail!(NamespaceNotFound(name.to_string()))
+        }
+        let val = serde_json::to_vec(quota).into_diagnostic()?;
+        self.tx.put(&key, &val)?;
+        // the counters are only moved while the namespace has a storage quota
+        self.usage.lock().unwrap().namespaces.remove(name);
+        for rel in self.namespace_relations(name)? {
+            let handle = self.get_relation(&rel, false)?;
+            self.forget_storage_usage(handle.id)?;
+        }
+        Ok(())
+    }
+    /// The tightest limits of the quotas of the namespaces of the relations `names`.
+    pub(crate) fn quota_of_relations<'a>(
+        &self,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Result<NamespaceQuota> {
+        let mut quota = NamespaceQuota::default();
+        for ns in names.into_iter().filter_map(relation_namespace) {
+            if let Some(slice) = self.tx.get(&namespace_key(ns), false)? {
+                if !slice.is_empty() {
+                    quota = quota.tighten(serde_json::from_slice(&slice).into_diagnostic()?);
+                }
+            }
+        }
+        Ok(quota)
+    }
+    /// Fails if the relations of the namespace of the relation `name` take more storage than
+    /// the quota of the namespace allows, counting the keys and values of their rows and of
+    /// their history, tombstones, soft-deleted rows and vector indices.
+    pub(crate) fn check_namespace_storage(&mut self, name: &str) -> Result<()> {
+        let ns = match relation_namespace(name) {
+            Some(ns) => ns,
+            None => return Ok(()),
+        };
+        // relations named with a dot outside any namespace have no quota
+        let max = match self.quota_of_relations([name])?.max_storage_bytes {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        self.flush_storage_usage()?;
+        let mut used = 0u64;
+        for rel in self.namespace_relations(ns)? {
+            let handle = self.get_relation(&rel, false)?;
+            let key = usage_key(handle.id);
+            used += match self.tx.get(&key, false)? {
+                Some(bytes) => decode_usage(&bytes),
+                None => {
+                    let bytes = self.scan_storage(&storage_ids(&handle))?;
+                    self.tx.put(&key, &bytes.to_be_bytes())?;
+                    bytes
+                }
+            };
+            if used > max {
+                bail!(NamespaceStorageQuotaExceeded(ns.to_string(), used, max))
+            }
+        }
+        Ok(())
+    }
+    /// Starts counting the bytes written to the relation, if its namespace has a storage quota.
+    pub(crate) fn count_storage_of(&self, handle: &RelationHandle) -> Result<()> {
+        let ns = match relation_namespace(&handle.name) {
+            Some(ns) => ns,
+            None => return Ok(()),
+        };
+        let mut usage = self.usage.lock().unwrap();
+        if usage.relations.contains_key(&handle.id) {
+            return Ok(());
+        }
+        let limited = match usage.namespaces.get(ns) {
+            Some(limited) => *limited,
+            None => {
+                let quota = self.quota_of_relations([&handle.name as &str])?;
+                let limited = quota.max_storage_bytes.is_some();
+                usage.namespaces.insert(SmartString::from(ns), limited);
+                limited
+            }
+        };
+        if limited {
+            let ids = storage_ids(handle);
+            for id in &ids {
+                usage.prefixes.insert(id.raw_encode(), handle.id);
+            }
+            usage.relations.insert(
+                handle.id,
+                CountedRelation {
+                    name: handle.name.clone(),
+                    ids,
+                    delta: 0,
+                    written: false,
+                    rescan: false,
+                },
+            );
+        }
+        Ok(())
+    }
+    /// Moves the stored counters of the relations written to by the bytes the transaction
+    /// wrote. Relations without a counter yet, those a range of keys was deleted from and
+    /// those given or stripped of history, tombstones, soft-deleted rows or a vector index
+    /// since counting started are scanned instead.
+    pub(crate) fn flush_storage_usage(&mut self) -> Result<()> {
+        let written = {
+            let mut usage = self.usage.lock().unwrap();
+            let mut written = vec![];
+            for (id, rel) in usage.relations.iter_mut() {
+                if rel.written {
+                    written.push((*id, rel.clone()));
+                    rel.reset();
+                }
+            }
+            written
+        };
+        for (id, rel) in written {
+            let key = usage_key(id);
+            let name_key =
+                Tuple(vec![DataValue::Str(rel.name.clone())]).encode_as_key(RelationId::SYSTEM);
+            let handle = match self.tx.get(&name_key, false)? {
+                Some(found) => RelationHandle::decode(&found)?,
+                None => {
+                    self.tx.del(&key)?;
+                    continue;
+                }
+            };
+            if handle.id != id {
+                // renamed or replaced within the transaction
+                self.tx.del(&key)?;
+                continue;
+            }
+            let ids = storage_ids(&handle);
+            let used = match self.tx.get(&key, true)? {
+                Some(bytes) if !rel.rescan && ids == rel.ids => {
+                    decode_usage(&bytes).saturating_add_signed(rel.delta)
+                }
+                _ => self.scan_storage(&ids)?,
+            };
+            self.tx.put(&key, &used.to_be_bytes())?;
+            if ids != rel.ids {
+                let mut usage = self.usage.lock().unwrap();
+                for id in &ids {
+                    usage.prefixes.insert(id.raw_encode(), handle.id);
+                }
+                if let Some(counted) = usage.relations.get_mut(&handle.id) {
+                    counted.ids = ids;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Drops the stored counter of the relation, to be set by a scan when next needed.
+    pub(crate) fn forget_storage_usage(&mut self, id: RelationId) -> Result<()> {
+        self.tx.del(&usage_key(id))
+    }
+    fn scan_storage(&self, ids: &[RelationId]) -> Result<u64> {
+        let mut used = 0u64;
+        for id in ids {
+            let lower = Tuple::default().encode_as_key(*id);
+            let upper = Tuple::default().encode_as_key(id.next());
+            for pair in self.tx.range_scan(&lower, &upper) {
+                let (k, v) = pair?;
+                used += (k.len() + v.len()) as u64;
+            }
+        }
+        Ok(used)
+    }
+    pub(crate) fn remove_namespace(&mut self, name: &str) -> Result<bool> {
+        let key = namespace_key(name);
+        let existed = self.tx.exists(&key, true)?;
+        if existed {
+            self.tx.del(&key)?;
+        }
+        Ok(existed)
+    }
+    pub(crate) fn list_namespaces(&self) -> Result<Vec<(String, NamespaceQuota)>> {
+        let lower = namespace_key("");
+        let upper = namespace_key(&String::from(LARGEST_UTF_CHAR));
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            let name = key.0[2].get_string().unwrap_or_default().to_string();
+            let quota = if v_slice.is_empty() {
+                NamespaceQuota::default()
+            } else {
+                serde_json::from_slice(&v_slice).into_diagnostic()?
+            };
+            collected.push((name, quota));
+        }
+        Ok(collected)
+    }
+    /// The names of the relations in the namespace `name`.
+    pub(crate) fn namespace_relations(&self, name: &str) -> Result<Vec<SmartString<LazyCompact>>> {
+        let prefix = format!("{}.", name);
+        let lower = Tuple(vec![DataValue::Str(SmartString::from(prefix.as_str()))])
+            .encode_as_key(RelationId::SYSTEM);
+        let upper = Tuple(vec![DataValue::Str(SmartString::from(format!(
+            "{}{}",
+            prefix, LARGEST_UTF_CHAR
+        )))])
+        .encode_as_key(RelationId::SYSTEM);
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, _) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            if let Some(rel) = key.0[0].get_string() {
+                collected.push(SmartString::from(rel));
+            }
+        }
+        Ok(collected)
+    }
+}
@@ -0,0 +1,75 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use miette::{IntoDiagnostic, Result};
+use serde_json::Value as JsonValue;
+use smartstring::SmartString;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+
+/// Maximal number of recently run scripts whose plans are kept for pinning.
+pub(crate) const MAX_CAPTURED_PLANS: usize = 1024;
+
+/// The plan most recently chosen for a script, identified by its hash.
+#[derive(Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct CapturedPlan {
+    pub(crate) script: String,
+    pub(crate) signature: JsonValue,
+}
+
+/// Stable 64-bit FNV-1a hash of the script text, rendered in hex.
+pub(crate) fn script_hash(script: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in script.trim().bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+fn pinned_plan_key(hash: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("plan")),
+        DataValue::Str(SmartString::from(hash)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl SessionTx {
+    pub(crate) fn get_pinned_plan(&self, hash: &str) -> Result<Option<CapturedPlan>> {
+        match self.tx.get(&pinned_plan_key(hash), false)? {
+            None => Ok(None),
+            Some(slice) => Ok(Some(serde_json::from_slice(&slice).into_diagnostic()?)),
+        }
+    }
+    pub(crate) fn pin_plan(&mut self, hash: &str, plan: &CapturedPlan) -> Result<()> {
+        let val = serde_json::to_vec(plan).into_diagnostic()?;
+        self.tx.put(&pinned_plan_key(hash), &val)?;
+        Ok(())
+    }
+    pub(crate) fn unpin_plan(&mut self, hash: &str) -> Result<bool> {
+        let key = pinned_plan_key(hash);
+        let existed = self.tx.exists(&key, true)?;
+        if existed {
+            self.tx.del(&key)?;
+        }
+        Ok(existed)
+    }
+    pub(crate) fn list_pinned_plans(&self) -> Result<Vec<(String, CapturedPlan)>> {
+        let lower = pinned_plan_key("");
+        let upper = pinned_plan_key(&String::from(LARGEST_UTF_CHAR));
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            let hash = key.0[2].get_string().unwrap_or_default().to_string();
+            collected.push((hash, serde_json::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(collected)
+    }
+}
@@ -3,8 +3,14 @@
  */
 
 use std::fmt::{Debug, Display, Formatter};
+use std::iter::Peekable;
+use std::mem;
 use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use either::{Left, Right};
+use itertools::Itertools;
 use log::error;
 use miette::{bail, ensure, Diagnostic, Result};
 use rmp_serde::Serializer;
@@ -12,15 +18,19 @@ use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
-use cozorocks::DbIter;
-
 use crate::data::memcmp::MemCmpEncoder;
+use crate::data::program::AsOf;
 use crate::data::relation::StoredRelationMetadata;
 use crate::data::symb::Symbol;
-use crate::data::tuple::{ENCODED_KEY_MIN_LEN, Tuple};
+use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN};
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
-use crate::runtime::transact::SessionTx;
+use crate::runtime::federation::RemoteRelationWrite;
+use crate::runtime::hnsw::VectorIndex;
+use crate::runtime::metrics::Metrics;
+use crate::runtime::tiering::Tiering;
+use crate::runtime::transact::{RowGuard, SessionTx};
+use crate::storage::{KvIter, Storage};
 use crate::utils::swap_option_result;
 
 #[derive(
@@ -68,6 +78,28 @@ pub(crate) struct RelationHandle {
     pub(crate) rm_triggers: Vec<String>,
     pub(crate) replace_triggers: Vec<String>,
     pub(crate) access_level: AccessLevel,
+    /// Where past versions of the rows are kept, if history is retained for the relation.
+    #[serde(default)]
+    pub(crate) history: Option<RelationId>,
+    /// The nearest-neighbour index over a vector column, if any.
+    #[serde(default)]
+    pub(crate) vector_index: Option<VectorIndex>,
+    /// Where the time of the last write to each key is kept, together with tombstones of
+    /// deleted keys, if last-writer-wins metadata is maintained for the relation.
+    #[serde(default)]
+    pub(crate) lww: Option<RelationId>,
+    /// Where removed rows are kept until purged, if deletions from the relation are soft.
+    #[serde(default)]
+    pub(crate) soft_deleted: Option<RelationId>,
+    /// The retention policy offloading old rows to the cold storage, if the relation is tiered.
+    #[serde(default)]
+    pub(crate) tiering: Option<Tiering>,
+    /// The relations maintained from this one with `::maintain create`.
+    #[serde(default)]
+    pub(crate) maintained: Vec<String>,
+    /// Whether this is a relation of a remote database, named `<remote>::<relation>`.
+    #[serde(skip)]
+    pub(crate) remote: bool,
 }
 
 #[derive(
@@ -116,6 +148,19 @@ impl RelationHandle {
     pub(crate) fn has_triggers(&self) -> bool {
         !self.put_triggers.is_empty() || !self.rm_triggers.is_empty()
     }
+    /// What a replacement of the relation takes over besides its triggers.
+    pub(crate) fn carried_on_replace(&self) -> Vec<&'static str> {
+        [
+            (self.history.is_some(), "history"),
+            (self.lww.is_some(), "last-writer-wins metadata"),
+            (self.soft_deleted.is_some(), "soft-deleted rows"),
+            (self.vector_index.is_some(), "vector index"),
+        ]
+        .into_iter()
+        .filter(|(kept, _)| *kept)
+        .map(|(_, name)| name)
+        .collect()
+    }
     fn encode_key_prefix(&self, len: usize) -> Vec<u8> {
         let mut ret = Vec::with_capacity(4 + 4 * len + 10 * len);
         let prefix_bytes = self.id.0.to_be_bytes();
@@ -151,6 +196,25 @@ impl RelationHandle {
         // }
         Ok(ret)
     }
+    /// Encodes a prefix of the keys, coerced to the types of the key columns.
+    fn encode_key_bound(&self, prefix: Vec<DataValue>, span: SourceSpan) -> Result<Vec<u8>> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Key prefix of length {0} is too long for '{1}' with {2} key columns")]
+        #[diagnostic(code(eval::key_prefix_too_long))]
+        struct KeyPrefixTooLong(usize, String, usize, #[label] SourceSpan);
+
+        let n_keys = self.metadata.keys.len();
+        ensure!(
+            prefix.len() <= n_keys,
+            KeyPrefixTooLong(prefix.len(), self.name.to_string(), n_keys, span)
+        );
+        let coerced = prefix
+            .into_iter()
+            .zip(self.metadata.keys.iter())
+            .map(|(v, col)| col.typing.coerce(v))
+            .collect::<Result<_>>()?;
+        Ok(Tuple(coerced).encode_as_key(self.id))
+    }
     pub(crate) fn adhoc_encode_val(&self, tuple: &Tuple, _span: SourceSpan) -> Result<Vec<u8>> {
         let start = self.metadata.keys.len();
         let len = self.metadata.non_keys.len();
@@ -181,6 +245,25 @@ impl RelationHandle {
         }
         Ok(())
     }
+    /// Moves the columns of a specification without `=>` to the dependent part if that is
+    /// where they are in the stored relation, so that writes can name their columns as in
+    /// `:put rel {a: x, b: y}` without repeating how the relation splits them.
+    pub(crate) fn split_input_cols(&self, inp: &mut InputRelationHandle) {
+        if !inp.metadata.non_keys.is_empty() {
+            return;
+        }
+        let keys = mem::take(&mut inp.metadata.keys);
+        let key_bindings = mem::take(&mut inp.key_bindings);
+        for (col, binding) in keys.into_iter().zip(key_bindings) {
+            if self.metadata.non_keys.iter().any(|c| c.name == col.name) {
+                inp.metadata.non_keys.push(col);
+                inp.dep_bindings.push(binding);
+            } else {
+                inp.metadata.keys.push(col);
+                inp.key_bindings.push(binding);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
@@ -218,9 +301,66 @@ impl RelationHandle {
         })?)
     }
     pub(crate) fn scan_all(&self, tx: &SessionTx) -> impl Iterator<Item = Result<Tuple>> {
-        let lower = Tuple::default().encode_as_key(self.id);
-        let upper = Tuple::default().encode_as_key(self.id.next());
-        RelationIterator::new(tx, &lower, &upper)
+        self.scan_between(tx, &Tuple::default(), &Tuple(vec![DataValue::Bot]))
+    }
+    /// Scans the rows with keys between `lower` and `upper`, including offloaded ones,
+    /// merged in key order with the soft-deleted rows in the same range if the running query asked for those.
+    /// The rows of remote relations are fetched from their database.
+    fn scan_between(
+        &self,
+        tx: &SessionTx,
+        lower: &Tuple,
+        upper: &Tuple,
+    ) -> impl Iterator<Item = Result<Tuple>> {
+        if self.remote {
+            return Left(self.scan_remote(tx, lower, upper));
+        }
+        let live = RelationIterator::wrap(
+            tx,
+            self.raw_scan(
+                tx,
+                &lower.encode_as_key(self.id),
+                &upper.encode_as_key(self.id),
+            ),
+        );
+        match self.soft_deleted {
+            Some(deleted) if tx.query.include_deleted => {
+                let deleted = RelationIterator::new(
+                    tx,
+                    &lower.encode_as_key(deleted),
+                    &upper.encode_as_key(deleted),
+                );
+                Right(Right(live.merge_by(deleted, |a, b| match (a, b) {
+                    (Ok(a), Ok(b)) => a <= b,
+                    _ => true,
+                })))
+            }
+            _ => Right(Left(live)),
+        }
+    }
+
+    /// Scans the rows whose keys lie between the full keys `lower` (inclusive) and
+    /// `upper` (exclusive). A missing bound leaves that end of the range open.
+    pub(crate) fn scan_key_range(
+        &self,
+        tx: &SessionTx,
+        lower: Option<&[DataValue]>,
+        upper: Option<&[DataValue]>,
+    ) -> impl Iterator<Item = Result<Tuple>> {
+        let (lower, upper) = key_range_of(self.id, lower, upper);
+        RelationIterator::wrap(tx, self.raw_scan(tx, &lower, &upper))
+    }
+    /// Like [RelationHandle::scan_key_range], but over the last-writer-wins metadata `lww`
+    /// of the relation, producing the keys followed by the time of the last write to them
+    /// and whether it was a deletion.
+    pub(crate) fn scan_lww_key_range(
+        &self,
+        lww: RelationId,
+        tx: &SessionTx,
+        lower: Option<&[DataValue]>,
+        upper: Option<&[DataValue]>,
+    ) -> impl Iterator<Item = Result<Tuple>> {
+        scan_key_range_of(lww, tx, lower, upper)
     }
 
     pub(crate) fn scan_prefix(
@@ -232,9 +372,7 @@ impl RelationHandle {
         lower.truncate(self.metadata.keys.len());
         let mut upper = lower.clone();
         upper.push(DataValue::Bot);
-        let prefix_encoded = Tuple(lower).encode_as_key(self.id);
-        let upper_encoded = Tuple(upper).encode_as_key(self.id);
-        RelationIterator::new(tx, &prefix_encoded, &upper_encoded)
+        self.scan_between(tx, &Tuple(lower), &Tuple(upper))
     }
     pub(crate) fn scan_bounded_prefix(
         &self,
@@ -248,56 +386,155 @@ impl RelationHandle {
         let mut upper_t = prefix.clone();
         upper_t.0.extend_from_slice(upper);
         upper_t.0.push(DataValue::Bot);
-        let lower_encoded = lower_t.encode_as_key(self.id);
-        let upper_encoded = upper_t.encode_as_key(self.id);
-        RelationIterator::new(tx, &lower_encoded, &upper_encoded)
+        self.scan_between(tx, &lower_t, &upper_t)
+    }
+    /// Scans the rows as they were at `as_of`, from the retained history.
+    pub(crate) fn scan_all_as_of(
+        &self,
+        tx: &SessionTx,
+        as_of: AsOf,
+    ) -> impl Iterator<Item = Result<Tuple>> {
+        self.scan_prefix_as_of(tx, &Tuple::default(), as_of)
+    }
+    /// Scans the rows starting with `prefix` as they were at `as_of`.
+    pub(crate) fn scan_prefix_as_of(
+        &self,
+        tx: &SessionTx,
+        prefix: &Tuple,
+        as_of: AsOf,
+    ) -> impl Iterator<Item = Result<Tuple>> {
+        let history = self.history.unwrap_or(self.id);
+        let mut lower = prefix.0.clone();
+        lower.truncate(self.metadata.keys.len());
+        let mut upper = lower.clone();
+        upper.push(DataValue::Bot);
+        let lower_encoded = Tuple(lower).encode_as_key(history);
+        let upper_encoded = Tuple(upper).encode_as_key(history);
+        AsOfIterator {
+            inner: RelationIterator::new(tx, &lower_encoded, &upper_encoded).peekable(),
+            n_keys: self.metadata.keys.len(),
+            as_of,
+        }
     }
 }
 
+/// Picks, for each key of the history of a relation, the version that was current at
+/// `as_of`. The history rows are the keys followed by the id of the transaction that recorded
+/// the version, the time from which it is current, whether the row was asserted or
+/// retracted, and the non-key values.
+struct AsOfIterator<I: Iterator<Item = Result<Tuple>>> {
+    inner: Peekable<I>,
+    n_keys: usize,
+    as_of: AsOf,
+}
+
+impl<I: Iterator<Item = Result<Tuple>>> AsOfIterator<I> {
+    fn is_visible(&self, version: &Tuple) -> bool {
+        match self.as_of {
+            AsOf::Time(t) => {
+                matches!(version.0[self.n_keys + 1].get_float(), Some(since) if since <= t)
+            }
+            AsOf::Tx(id) => {
+                matches!(version.0[self.n_keys].get_int(), Some(tx) if tx as u64 <= id)
+            }
+        }
+    }
+    fn next_inner(&mut self) -> Result<Option<Tuple>> {
+        loop {
+            let first = match self.inner.next() {
+                None => return Ok(None),
+                Some(first) => first?,
+            };
+            let key = first.0[..self.n_keys].to_vec();
+            let mut current = None;
+            let mut version = Some(first);
+            // versions of the same key are ordered by the transactions that recorded them
+            while let Some(tuple) = version {
+                if self.is_visible(&tuple) {
+                    current = Some(tuple);
+                }
+                version = match self.inner.peek() {
+                    Some(Ok(next)) if next.0[..self.n_keys] == key[..] => {
+                        self.inner.next().transpose()?
+                    }
+                    _ => None,
+                };
+            }
+            if let Some(mut tuple) = current {
+                if tuple.0[self.n_keys + 2] == DataValue::Bool(true) {
+                    tuple.0.drain(self.n_keys..self.n_keys + 3);
+                    return Ok(Some(tuple));
+                }
+            }
+        }
+    }
+}
+
+impl<I: Iterator<Item = Result<Tuple>>> Iterator for AsOfIterator<I> {
+    type Item = Result<Tuple>;
+    fn next(&mut self) -> Option<Self::Item> {
+        swap_option_result(self.next_inner())
+    }
+}
+
+fn scan_key_range_of(
+    id: RelationId,
+    tx: &SessionTx,
+    lower: Option<&[DataValue]>,
+    upper: Option<&[DataValue]>,
+) -> RelationIterator {
+    let (lower, upper) = key_range_of(id, lower, upper);
+    RelationIterator::new(tx, &lower, &upper)
+}
+
+fn key_range_of(
+    id: RelationId,
+    lower: Option<&[DataValue]>,
+    upper: Option<&[DataValue]>,
+) -> (Vec<u8>, Vec<u8>) {
+    let lower = match lower {
+        None => Tuple::default().encode_as_key(id),
+        Some(key) => Tuple(key.to_vec()).encode_as_key(id),
+    };
+    let upper = match upper {
+        None => Tuple::default().encode_as_key(id.next()),
+        Some(key) => Tuple(key.to_vec()).encode_as_key(id),
+    };
+    (lower, upper)
+}
+
 struct RelationIterator {
-    inner: DbIter,
-    started: bool,
-    upper_bound: Vec<u8>,
+    inner: KvIter,
+    row_guard: Option<Arc<RowGuard>>,
+    metrics: Arc<Metrics>,
 }
 
 impl RelationIterator {
     fn new(sess: &SessionTx, lower: &[u8], upper: &[u8]) -> Self {
-        let mut inner = sess.tx.iterator().upper_bound(upper).start();
-        inner.seek(lower);
+        Self::wrap(sess, sess.tx.range_scan(lower, upper))
+    }
+    fn wrap(sess: &SessionTx, inner: KvIter) -> Self {
         Self {
             inner,
-            started: false,
-            upper_bound: upper.to_vec(),
+            row_guard: sess.query.row_guard.clone(),
+            metrics: sess.services.metrics.clone(),
         }
     }
     fn next_inner(&mut self) -> Result<Option<Tuple>> {
-        if self.started {
-            self.inner.next()
-        } else {
-            self.started = true;
-        }
-        Ok(match self.inner.pair()? {
+        Ok(match self.inner.next().transpose()? {
             None => None,
             Some((k_slice, v_slice)) => {
-                if self.upper_bound.as_slice() <= k_slice {
-                    //
-                    // }
-                    // if compare_tuple_keys(&self.upper_bound, k_slice) != Greater {
-                    None
-                } else {
-                    let mut tup = Tuple::decode_from_key(k_slice);
-                    if !v_slice.is_empty() {
-                        let vals: Vec<DataValue> = rmp_serde::from_slice(&v_slice[ENCODED_KEY_MIN_LEN..]).unwrap();
-                        tup.0.extend(vals);
-                    }
-                    // if !v_slice.is_empty() {
-                    //     let v_tup = EncodedTuple(v_slice);
-                    //     if v_tup.arity() > 0 {
-                    //         tup.0.extend(v_tup.decode().0);
-                    //     }
-                    // }
-                    Some(tup)
+                let mut tup = Tuple::decode_from_key(&k_slice);
+                if !v_slice.is_empty() {
+                    let vals: Vec<DataValue> =
+                        rmp_serde::from_slice(&v_slice[ENCODED_KEY_MIN_LEN..]).unwrap();
+                    tup.0.extend(vals);
                 }
+                self.metrics.rows_scanned.fetch_add(1, Ordering::Relaxed);
+                if let Some(guard) = &self.row_guard {
+                    guard.scanned()?;
+                }
+                Some(tup)
             }
         })
     }
@@ -319,7 +556,7 @@ impl SessionTx {
     pub(crate) fn relation_exists(&self, name: &str) -> Result<bool> {
         let key = DataValue::Str(SmartString::from(name));
         let encoded = Tuple(vec![key]).encode_as_key(RelationId::SYSTEM);
-        Ok(self.tx.exists(&encoded, false)?)
+        self.tx.exists(&encoded, false)
     }
     pub(crate) fn set_relation_triggers(
         &mut self,
@@ -363,15 +600,21 @@ impl SessionTx {
         };
 
         let metadata = input_meta.metadata.clone();
-        let last_id = self.relation_store_id.fetch_add(1, Ordering::SeqCst);
         let meta = RelationHandle {
             name: input_meta.name.name,
-            id: RelationId::new(last_id + 1),
+            id: self.next_relation_id()?,
             metadata,
             put_triggers: vec![],
             rm_triggers: vec![],
             replace_triggers: vec![],
             access_level: AccessLevel::Normal,
+            history: None,
+            vector_index: None,
+            lww: None,
+            soft_deleted: None,
+            tiering: None,
+            maintained: vec![],
+            remote: false,
         };
 
         self.tx.put(&encoded, &meta.id.raw_encode())?;
@@ -382,11 +625,215 @@ impl SessionTx {
         meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
             .unwrap();
         self.tx.put(&name_key, &meta_val)?;
-
+        self.count_storage_of(&meta)?;
+        Ok(meta)
+    }
+    pub(crate) fn next_relation_id(&mut self) -> Result<RelationId> {
+        let last_id = self.relation_store_id.fetch_add(1, Ordering::SeqCst);
+        let id = RelationId::new(last_id + 1);
         let tuple = Tuple(vec![DataValue::Null]);
         let t_encoded = tuple.encode_as_key(RelationId::SYSTEM);
-        self.tx.put(&t_encoded, &meta.id.raw_encode())?;
-        Ok(meta)
+        self.tx.put(&t_encoded, &id.raw_encode())?;
+        Ok(id)
+    }
+    /// Records a new version of a row in the history of the relation, if it is retained,
+    /// stamped with the transaction. For retractions only the keys of `tuple` are used.
+    pub(crate) fn record_history(
+        &mut self,
+        handle: &RelationHandle,
+        tuple: &Tuple,
+        asserted: bool,
+    ) -> Result<()> {
+        let history = match handle.history {
+            None => return Ok(()),
+            Some(history) => history,
+        };
+        let stamp = self.version_stamp()?;
+        let n_keys = handle.metadata.keys.len();
+        let mut key = tuple.0[..n_keys].to_vec();
+        key.push(DataValue::from(stamp.tx as i64));
+        let key = Tuple(key).encode_as_key(history);
+        let mut vals = vec![DataValue::from(stamp.since), DataValue::Bool(asserted)];
+        if asserted {
+            vals.extend_from_slice(&tuple.0[n_keys..]);
+        }
+        let mut val = history.raw_encode().to_vec();
+        vals.serialize(&mut Serializer::new(&mut val)).unwrap();
+        self.tx.put(&key, &val)?;
+        Ok(())
+    }
+    /// Records the time of a write to the key of `tuple`, and whether it was a deletion,
+    /// if last-writer-wins metadata is maintained for the relation.
+    pub(crate) fn record_lww(
+        &mut self,
+        handle: &RelationHandle,
+        tuple: &[DataValue],
+        deleted: bool,
+        at: f64,
+    ) -> Result<()> {
+        let lww = match handle.lww {
+            None => return Ok(()),
+            Some(lww) => lww,
+        };
+        let key = Tuple(tuple[..handle.metadata.keys.len()].to_vec()).encode_as_key(lww);
+        let mut val = lww.raw_encode().to_vec();
+        vec![DataValue::from(at), DataValue::Bool(deleted)]
+            .serialize(&mut Serializer::new(&mut val))
+            .unwrap();
+        self.tx.put(&key, &val)?;
+        Ok(())
+    }
+    /// Keeps the row stored under `key`, about to be removed, among the soft-deleted rows if
+    /// deletions from the relation are soft. `keys` are the key values of the row.
+    pub(crate) fn soft_delete_row(
+        &mut self,
+        handle: &RelationHandle,
+        keys: &[DataValue],
+        key: &[u8],
+    ) -> Result<()> {
+        let deleted = match handle.soft_deleted {
+            None => return Ok(()),
+            Some(deleted) => deleted,
+        };
+        if let Some(existing) = self.tx.get(key, false)? {
+            let deleted_key =
+                Tuple(keys[..handle.metadata.keys.len()].to_vec()).encode_as_key(deleted);
+            self.tx.put(&deleted_key, &existing)?;
+        }
+        Ok(())
+    }
+    /// Forgets the soft-deleted row with the keys of `tuple`, as a new row takes its place.
+    pub(crate) fn revive_row(
+        &mut self,
+        handle: &RelationHandle,
+        tuple: &[DataValue],
+    ) -> Result<()> {
+        if let Some(deleted) = handle.soft_deleted {
+            let deleted_key =
+                Tuple(tuple[..handle.metadata.keys.len()].to_vec()).encode_as_key(deleted);
+            self.tx.del(&deleted_key)?;
+        }
+        Ok(())
+    }
+    /// Turns soft deletion on or off for the relation. When turning it off, the soft-deleted
+    /// rows are removed.
+    pub(crate) fn set_relation_soft_delete(&mut self, name: &Symbol, soft: bool) -> Result<()> {
+        let mut handle = self.get_relation(name, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "setting soft deletion".to_string(),
+                handle.access_level
+            ))
+        }
+        match (handle.soft_deleted, soft) {
+            (None, true) => {
+                handle.soft_deleted = Some(self.next_relation_id()?);
+            }
+            (Some(deleted), false) => {
+                handle.soft_deleted = None;
+                self.del_relation_keys(deleted)?;
+            }
+            _ => return Ok(()),
+        }
+
+        let name_key =
+            Tuple(vec![DataValue::Str(handle.name.clone())]).encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.tx.put(&name_key, &meta_val)?;
+        Ok(())
+    }
+    /// Removes the soft-deleted rows of the relation for good.
+    pub(crate) fn purge_relation(&mut self, name: &Symbol) -> Result<()> {
+        let store = self.get_relation(name, true)?;
+        if store.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                store.name.to_string(),
+                "purging deleted rows".to_string(),
+                store.access_level
+            ))
+        }
+        let deleted = match store.soft_deleted {
+            Some(deleted) => deleted,
+            None => bail!(NoSoftDelete(store.name.to_string(), name.span)),
+        };
+        self.del_relation_keys(deleted)?;
+        Ok(())
+    }
+    /// Starts or stops maintaining last-writer-wins metadata for the relation. When starting,
+    /// the current rows are stamped with the current time. When stopping, the metadata is
+    /// removed.
+    pub(crate) fn set_relation_lww(&mut self, name: &Symbol, maintain: bool) -> Result<()> {
+        let mut handle = self.get_relation(name, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "setting last-writer-wins metadata".to_string(),
+                handle.access_level
+            ))
+        }
+        match (handle.lww, maintain) {
+            (None, true) => {
+                handle.lww = Some(self.next_relation_id()?);
+                let at = current_validity();
+                let rows: Vec<_> = handle.scan_all(self).try_collect()?;
+                for row in &rows {
+                    self.record_lww(&handle, &row.0, false, at)?;
+                }
+            }
+            (Some(lww), false) => {
+                handle.lww = None;
+                self.del_relation_keys(lww)?;
+            }
+            _ => return Ok(()),
+        }
+
+        let name_key =
+            Tuple(vec![DataValue::Str(handle.name.clone())]).encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.tx.put(&name_key, &meta_val)?;
+        Ok(())
+    }
+    /// Starts or stops retaining the history of the relation. When starting, the current rows
+    /// are recorded as their first versions. When stopping, the history is removed.
+    pub(crate) fn set_relation_history(&mut self, name: &Symbol, retain: bool) -> Result<()> {
+        let mut handle = self.get_relation(name, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "setting history retention".to_string(),
+                handle.access_level
+            ))
+        }
+        match (handle.history, retain) {
+            (None, true) => {
+                handle.history = Some(self.next_relation_id()?);
+                let rows: Vec<_> = handle.scan_all(self).try_collect()?;
+                for row in &rows {
+                    self.record_history(&handle, row, true)?;
+                }
+            }
+            (Some(history), false) => {
+                handle.history = None;
+                self.del_relation_keys(history)?;
+            }
+            _ => return Ok(()),
+        }
+
+        let name_key =
+            Tuple(vec![DataValue::Str(handle.name.clone())]).encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.tx.put(&name_key, &meta_val)?;
+        Ok(())
     }
     pub(crate) fn get_relation(&self, name: &str, lock: bool) -> Result<RelationHandle> {
         #[derive(Error, Diagnostic, Debug)]
@@ -394,6 +841,13 @@ impl SessionTx {
         #[diagnostic(code(query::relation_not_found))]
         struct StoredRelationNotFoundError(String);
 
+        if let Some(read) = &self.query.relations_read {
+            read.lock().unwrap().insert(SmartString::from(name));
+        }
+        if name.contains("::") {
+            ensure!(!lock, RemoteRelationWrite(name.to_string()));
+            return self.services.federation.remote_relation(name);
+        }
         let key = DataValue::Str(SmartString::from(name as &str));
         let encoded = Tuple(vec![key]).encode_as_key(RelationId::SYSTEM);
 
@@ -402,9 +856,12 @@ impl SessionTx {
             .get(&encoded, lock)?
             .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?;
         let metadata = RelationHandle::decode(&found)?;
+        self.count_storage_of(&metadata)?;
         Ok(metadata)
     }
-    pub(crate) fn destroy_relation(&mut self, name: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    /// Removes the relation, returning the key ranges of its rows, of its history, of
+    /// its vector index, of its last-writer-wins metadata and of its soft-deleted rows.
+    pub(crate) fn destroy_relation(&mut self, name: &str) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
         let store = self.get_relation(name, true)?;
         if store.access_level < AccessLevel::Normal {
             bail!(InsufficientAccessLevel(
@@ -416,9 +873,112 @@ impl SessionTx {
         let key = DataValue::Str(SmartString::from(name as &str));
         let encoded = Tuple(vec![key]).encode_as_key(RelationId::SYSTEM);
         self.tx.del(&encoded)?;
-        let lower_bound = Tuple::default().encode_as_key(store.id);
-        let upper_bound = Tuple::default().encode_as_key(store.id.next());
-        Ok((lower_bound, upper_bound))
+        self.remove_statistics(name)?;
+        self.forget_storage_usage(store.id)?;
+        self.destroy_offloaded(&store)?;
+        if self.services.change_feed.captures(&store.name) {
+            let lower = Tuple::default().encode_as_key(store.id);
+            let upper = Tuple::default().encode_as_key(store.id.next());
+            self.del_rows(&store, &lower, &upper)?;
+        }
+        let index = store.vector_index.as_ref().map(|idx| idx.id);
+        Ok([
+            Some(store.id),
+            store.history,
+            index,
+            store.lww,
+            store.soft_deleted,
+        ]
+        .into_iter()
+        .flatten()
+        .map(|id| {
+            (
+                Tuple::default().encode_as_key(id),
+                Tuple::default().encode_as_key(id.next()),
+            )
+        })
+        .collect())
+    }
+    /// Removes all rows of the relation with a range deletion of the storage engine, which
+    /// RocksDB makes without rewriting the rows, unless their removal is captured. The stored
+    /// handle is untouched, so the schema, triggers and access level survive, but removal
+    /// triggers are not run.
+    pub(crate) fn truncate_relation(&mut self, name: &Symbol) -> Result<()> {
+        let store = self.get_relation(name, true)?;
+        if store.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                store.name.to_string(),
+                "truncating relation".to_string(),
+                store.access_level
+            ))
+        }
+        ensure!(
+            store.history.is_none(),
+            RangeDeleteWithHistory(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.vector_index.is_none(),
+            RangeDeleteWithVectorIndex(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.lww.is_none(),
+            RangeDeleteWithLww(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.soft_deleted.is_none(),
+            RangeDeleteWithSoftDelete(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.tiering.is_none(),
+            RangeDeleteWithTiering(store.name.to_string(), name.span)
+        );
+        let lower = Tuple::default().encode_as_key(store.id);
+        let upper = Tuple::default().encode_as_key(store.id.next());
+        self.del_rows(&store, &lower, &upper)?;
+        Ok(())
+    }
+    /// Records the removal of the rows of a relation about to be replaced in its history,
+    /// last-writer-wins metadata and soft-deleted rows as `:rm` would, and empties its vector
+    /// index, so that these can be carried over to the replacement. Fails unless the
+    /// replacement has the same columns, which the records and the index are laid out for.
+    pub(crate) fn retire_replaced_rows(
+        &mut self,
+        old: &RelationHandle,
+        metadata: &StoredRelationMetadata,
+        span: SourceSpan,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot replace '{0}' with different columns, as its {1} would be lost")]
+        #[diagnostic(code(eval::replace_changing_columns))]
+        #[diagnostic(help("Replace it with the same columns, or turn off its {1} first"))]
+        struct ReplaceChangingColumns(String, String, #[label] SourceSpan);
+
+        let carried = old.carried_on_replace();
+        if carried.is_empty() {
+            return Ok(());
+        }
+        ensure!(
+            old.metadata == *metadata,
+            ReplaceChangingColumns(old.name.to_string(), carried.join(", "), span)
+        );
+        if let Some(index) = &old.vector_index {
+            self.del_relation_keys(index.id)?;
+        }
+        let at = current_validity();
+        let rows: Vec<_> = old.scan_all(self).try_collect()?;
+        for row in &rows {
+            let key = old.adhoc_encode_key(row, span)?;
+            self.soft_delete_row(old, &row.0, &key)?;
+            self.record_history(old, row, false)?;
+            self.record_lww(old, &row.0, true, at)?;
+        }
+        Ok(())
+    }
+    /// Deletes all keys stored under `id` within the transaction.
+    pub(crate) fn del_relation_keys(&mut self, id: RelationId) -> Result<()> {
+        let lower = Tuple::default().encode_as_key(id);
+        let upper = Tuple::default().encode_as_key(id.next());
+        self.tx.range_del(&lower, &upper)
     }
     pub(crate) fn set_access_level(&mut self, rel: Symbol, level: AccessLevel) -> Result<()> {
         let mut meta = self.get_relation(&rel, true)?;
@@ -459,9 +1019,254 @@ impl SessionTx {
         rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
         self.tx.del(&old_encoded)?;
         self.tx.put(&new_encoded, &meta_val)?;
+        if let Some(stats) = self.get_statistics(&old.name)? {
+            self.remove_statistics(&old.name)?;
+            self.put_statistics(&rel.name, &stats)?;
+        }
+        // the counter is only moved while the namespace has a storage quota
+        self.forget_storage_usage(rel.id)?;
 
         Ok(())
     }
+    /// Deletes the rows whose keys start at the `from` prefix (inclusive) up to the `to`
+    /// prefix (exclusive) within the transaction, without decoding them unless their removal
+    /// is captured. Missing bounds extend to the ends of the relation.
+    pub(crate) fn delete_range(
+        &mut self,
+        name: &Symbol,
+        from: Option<Vec<DataValue>>,
+        to: Option<Vec<DataValue>>,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot delete a range of rows from '{0}' as it has removal triggers")]
+        #[diagnostic(code(eval::range_delete_with_triggers))]
+        #[diagnostic(help("Range deletion does not produce the rows, use ':rm' instead"))]
+        struct RangeDeleteWithTriggers(String, #[label] SourceSpan);
+
+        let store = self.get_relation(name, true)?;
+        if store.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                store.name.to_string(),
+                "row removal".to_string(),
+                store.access_level
+            ));
+        }
+        ensure!(
+            store.rm_triggers.is_empty(),
+            RangeDeleteWithTriggers(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.history.is_none(),
+            RangeDeleteWithHistory(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.vector_index.is_none(),
+            RangeDeleteWithVectorIndex(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.lww.is_none(),
+            RangeDeleteWithLww(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.soft_deleted.is_none(),
+            RangeDeleteWithSoftDelete(store.name.to_string(), name.span)
+        );
+        ensure!(
+            store.tiering.is_none(),
+            RangeDeleteWithTiering(store.name.to_string(), name.span)
+        );
+        let lower = match from {
+            None => Tuple::default().encode_as_key(store.id),
+            Some(prefix) => store.encode_key_bound(prefix, name.span)?,
+        };
+        let upper = match to {
+            None => Tuple::default().encode_as_key(store.id.next()),
+            Some(prefix) => store.encode_key_bound(prefix, name.span)?,
+        };
+        if lower >= upper {
+            return Ok(());
+        }
+        self.del_rows(&store, &lower, &upper)
+    }
+    /// Copies the schema and all rows of a stored relation into a new one, returning the
+    /// number of rows copied. Triggers and access level are not copied.
+    ///
+    /// The storage engine has no cheap range copy inside a transaction, so the rows are
+    /// rewritten under the new relation id, in batches to bound the memory used.
+    pub(crate) fn clone_relation(&mut self, src: Symbol, dst: Symbol) -> Result<usize> {
+        const CLONE_BATCH_SIZE: usize = 4096;
+
+        let original = self.get_relation(&src, false)?;
+        if original.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                original.name.to_string(),
+                "cloning relation".to_string(),
+                original.access_level
+            ));
+        }
+        let span = dst.span;
+        let cloned = self.create_relation(InputRelationHandle {
+            name: dst,
+            metadata: original.metadata.clone(),
+            key_bindings: vec![],
+            dep_bindings: vec![],
+            span,
+        })?;
+
+        let upper = Tuple::default().encode_as_key(original.id.next());
+        let mut cursor = Tuple::default().encode_as_key(original.id);
+        let mut copied = 0;
+        loop {
+            let mut batch = Vec::with_capacity(CLONE_BATCH_SIZE);
+            {
+                for pair in original.raw_scan(self, &cursor, &upper) {
+                    if batch.len() >= CLONE_BATCH_SIZE {
+                        break;
+                    }
+                    batch.push(pair?);
+                }
+            }
+            let exhausted = batch.len() < CLONE_BATCH_SIZE;
+            for (key, val) in &batch {
+                let mut new_key = key.clone();
+                new_key[..ENCODED_KEY_MIN_LEN].copy_from_slice(&cloned.id.raw_encode());
+                self.tx.put(&new_key, val)?;
+            }
+            copied += batch.len();
+            match batch.pop() {
+                Some((mut last_key, _)) if !exhausted => {
+                    // the smallest key greater than the last one copied
+                    last_key.push(0);
+                    cursor = last_key;
+                }
+                _ => break,
+            }
+        }
+        Ok(copied)
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot delete a range of rows from '{0}' as its history is retained")]
+#[diagnostic(code(eval::range_delete_with_history))]
+#[diagnostic(help("Range deletion does not produce the rows, use ':rm' instead"))]
+struct RangeDeleteWithHistory(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot delete a range of rows from '{0}' as it has a vector index")]
+#[diagnostic(code(eval::range_delete_with_vector_index))]
+#[diagnostic(help("Range deletion does not produce the rows, use ':rm' instead"))]
+struct RangeDeleteWithVectorIndex(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot delete a range of rows from '{0}' as it keeps last-writer-wins metadata")]
+#[diagnostic(code(eval::range_delete_with_lww))]
+#[diagnostic(help("Range deletion leaves no tombstones, use ':rm' instead"))]
+struct RangeDeleteWithLww(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot delete a range of rows from '{0}' as its deletions are soft")]
+#[diagnostic(code(eval::range_delete_with_soft_delete))]
+#[diagnostic(help("Range deletion cannot be undone, use ':rm' instead"))]
+struct RangeDeleteWithSoftDelete(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot delete a range of rows from '{0}' as it is tiered")]
+#[diagnostic(code(eval::range_delete_with_tiering))]
+#[diagnostic(help("Turn tiering off with '::relation tier <relation> off' first"))]
+struct RangeDeleteWithTiering(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot purge '{0}' as its deletions are not soft")]
+#[diagnostic(code(eval::no_soft_delete))]
+#[diagnostic(help("Turn soft deletion on with '::relation soft_delete <relation> on'"))]
+struct NoSoftDelete(String, #[label] SourceSpan);
+
+/// The current time in seconds since the epoch, as recorded in last-writer-wins metadata
+/// and audit entries.
+pub(crate) fn current_validity() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+/// The stamp of the versions of rows a transaction records in histories.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct VersionStamp {
+    /// The id of the transaction, increasing with each transaction recording versions
+    pub(crate) tx: u64,
+    /// The time from which the versions are current, in seconds since the epoch, increasing
+    /// with `tx` even if the clock goes back
+    pub(crate) since: f64,
+}
+
+/// Hands out the stamps of the transactions recording versions of rows in histories.
+/// Transaction ids are reserved in blocks, each persisted by a transaction of its own before
+/// its ids are handed out, so that ids are not reused after a restart while the transactions
+/// using them do not conflict over a counter.
+pub(crate) struct VersionClock {
+    storage: Arc<dyn Storage>,
+    state: Mutex<ClockState>,
+}
+
+#[derive(Default)]
+struct ClockState {
+    last_tx: u64,
+    last_since: f64,
+    /// Ids up to this one are reserved
+    reserved: u64,
+}
+
+const TX_IDS_RESERVED_AT_ONCE: u64 = 1024;
+
+fn version_clock_key() -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("version_clock")),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl VersionClock {
+    pub(crate) fn new(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            storage,
+            state: Default::default(),
+        }
+    }
+    /// Continues after the ids reserved when the database was last open, and after the time
+    /// of the last reservation.
+    pub(crate) fn load(&self) -> Result<()> {
+        let tx = self.storage.transact()?;
+        if let Some(bytes) = tx.get(&version_clock_key(), false)? {
+            if let Ok(bytes) = <[u8; 16]>::try_from(bytes.as_slice()) {
+                let mut state = self.state.lock().unwrap();
+                state.reserved = u64::from_be_bytes(bytes[..8].try_into().unwrap());
+                state.last_tx = state.reserved;
+                state.last_since = f64::from_be_bytes(bytes[8..].try_into().unwrap());
+            }
+        }
+        Ok(())
+    }
+    pub(crate) fn next(&self) -> Result<VersionStamp> {
+        let mut state = self.state.lock().unwrap();
+        let tx = state.last_tx + 1;
+        // the smallest time after the last one, as times are never negative
+        let since = current_validity().max(f64::from_bits(state.last_since.to_bits() + 1));
+        if tx > state.reserved {
+            let reserved = tx - 1 + TX_IDS_RESERVED_AT_ONCE;
+            let mut val = reserved.to_be_bytes().to_vec();
+            val.extend_from_slice(&since.to_be_bytes());
+            let mut store_tx = self.storage.transact()?;
+            store_tx.put(&version_clock_key(), &val)?;
+            store_tx.commit()?;
+            state.reserved = reserved;
+        }
+        state.last_tx = tx;
+        state.last_since = since;
+        Ok(VersionStamp { tx, since })
+    }
 }
 
 #[derive(Debug, Error, Diagnostic)]
@@ -0,0 +1,392 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Replication of the writes committed to a database, the leader, to read-only followers.
+//!
+//! The storage of the leader is wrapped so that the key-value writes of each committed
+//! transaction, and each range deleted outside of transactions, are appended to a log as a
+//! batch numbered in commit order. The most recent batches are kept in memory, and followers
+//! ask for those after the last one they applied, over any transport.
+//!
+//! A follower starts from a backup of the leader, which records the position in the log of
+//! the snapshot it was taken from. The log is identified by a random ID chosen each time the
+//! leader is opened, so that followers of a leader that was restarted, or that fell behind
+//! further than the batches kept, are told to start over from a newer backup instead of
+//! silently missing writes.
+
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use miette::{bail, ensure, Diagnostic, IntoDiagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::db::ReadOnlyDb;
+use crate::runtime::relation::RelationId;
+use crate::storage::{CompactionStats, KvIter, Storage, StoreTx};
+use crate::Db;
+
+/// Maximum number of batches sent in answer to a single request of a follower.
+const REPLICATION_PAGE_BATCHES: usize = 256;
+
+/// A write made to the storage of the leader.
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum ReplicatedWrite {
+    Put { key: Vec<u8>, val: Vec<u8> },
+    Del { key: Vec<u8> },
+    RangeDel { lower: Vec<u8>, upper: Vec<u8> },
+}
+
+/// The writes of a committed transaction, or a range deleted outside of transactions.
+#[derive(Debug, Clone, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct WriteBatch {
+    seq: u64,
+    writes: Vec<ReplicatedWrite>,
+}
+
+/// Where a database stands in the log of a leader: the ID of the log and the number of the
+/// last batch applied.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ReplicationPosition {
+    log_id: u64,
+    seq: u64,
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum ReplicationRequest {
+    Batches {
+        from: ReplicationPosition,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum ReplicationResponse {
+    Batches(Vec<WriteBatch>),
+    /// The batches following the position are no longer kept, or were never in this log
+    NotKept,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database does not keep a replication log")]
+#[diagnostic(code(replication::disabled))]
+#[diagnostic(help("Open the leader with a non-zero `replication_log_size` option"))]
+struct ReplicationDisabled;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Replication cannot be combined with a cold storage")]
+#[diagnostic(code(replication::cold_storage))]
+pub(crate) struct ReplicationWithColdStorage;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database has no position in the replication log of a leader")]
+#[diagnostic(code(replication::no_position))]
+#[diagnostic(help("Restore a backup of the leader, taken with `Db::backup`, first"))]
+struct NoReplicationPosition;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The leader no longer keeps the writes following those applied to this database")]
+#[diagnostic(code(replication::gap))]
+#[diagnostic(help(
+    "The leader was restarted or kept too few batches for the follower to catch up: \
+    restore a newer backup of the leader, or keep more batches with `replication_log_size`"
+))]
+struct ReplicationGap;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unexpected response from the leader during replication")]
+#[diagnostic(code(replication::bad_response))]
+struct BadReplicationResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database is not a follower")]
+#[diagnostic(code(replication::not_a_follower))]
+#[diagnostic(help("Open the database with the `follower` option to replicate into it"))]
+struct NotAFollower;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database is a read-only follower, changed only by replication")]
+#[diagnostic(code(replication::follower_read_only))]
+pub(crate) struct FollowerReadOnly;
+
+/// The key under which a follower stores its position, and under which the position of the
+/// snapshot is recorded in backups of the leader.
+pub(crate) fn position_key() -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("replication")),
+        DataValue::Str(SmartString::from("position")),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl ReplicationPosition {
+    pub(crate) fn encode(&self) -> Result<Vec<u8>> {
+        rmp_serde::to_vec(self).into_diagnostic()
+    }
+}
+
+struct LogState {
+    last_seq: u64,
+    batches: VecDeque<WriteBatch>,
+}
+
+/// The most recent batches written to the storage of the leader.
+pub(crate) struct ReplicationLog {
+    id: u64,
+    /// The number of recent batches kept
+    capacity: usize,
+    state: Mutex<LogState>,
+}
+
+impl ReplicationLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            id: rand::random(),
+            capacity,
+            state: Mutex::new(LogState {
+                last_seq: 0,
+                batches: Default::default(),
+            }),
+        }
+    }
+    /// Calls `f`, which must not write, with no batch appended meanwhile, and returns its
+    /// result together with the position of the log at that point.
+    pub(crate) fn at_position<T>(
+        &self,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<(T, ReplicationPosition)> {
+        let state = self.state.lock().unwrap();
+        let ret = f()?;
+        Ok((
+            ret,
+            ReplicationPosition {
+                log_id: self.id,
+                seq: state.last_seq,
+            },
+        ))
+    }
+    /// Makes the writes with `write`, then appends them as a batch, so that batches are
+    /// numbered in the order the writes took effect.
+    fn append(
+        &self,
+        writes: Vec<ReplicatedWrite>,
+        write: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        write()?;
+        state.last_seq += 1;
+        let seq = state.last_seq;
+        state.batches.push_back(WriteBatch { seq, writes });
+        while state.batches.len() > self.capacity {
+            state.batches.pop_front();
+        }
+        Ok(())
+    }
+    fn batches_after(&self, from: ReplicationPosition, limit: usize) -> ReplicationResponse {
+        let state = self.state.lock().unwrap();
+        let first_kept = state
+            .batches
+            .front()
+            .map(|b| b.seq)
+            .unwrap_or(state.last_seq + 1);
+        if from.log_id != self.id || from.seq > state.last_seq || from.seq + 1 < first_kept {
+            return ReplicationResponse::NotKept;
+        }
+        ReplicationResponse::Batches(
+            state
+                .batches
+                .iter()
+                .filter(|b| b.seq > from.seq)
+                .take(limit)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// A storage appending everything written to it to a [`ReplicationLog`].
+pub(crate) struct ReplicatingStorage {
+    pub(crate) inner: Arc<dyn Storage>,
+    pub(crate) log: Arc<ReplicationLog>,
+}
+
+impl Storage for ReplicatingStorage {
+    fn transact(&self) -> Result<Box<dyn StoreTx>> {
+        Ok(Box::new(ReplicatingTx {
+            inner: self.inner.transact()?,
+            log: self.log.clone(),
+            writes: vec![],
+            saves: vec![],
+        }))
+    }
+    fn range_del(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        let write = ReplicatedWrite::RangeDel {
+            lower: lower.to_vec(),
+            upper: upper.to_vec(),
+        };
+        self.log
+            .append(vec![write], || self.inner.range_del(lower, upper))
+    }
+    fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.range_compact(lower, upper)
+    }
+    fn flush(&self) -> Result<()> {
+        self.inner.flush()
+    }
+    fn compaction_stats(&self) -> Option<CompactionStats> {
+        self.inner.compaction_stats()
+    }
+}
+
+struct ReplicatingTx {
+    inner: Box<dyn StoreTx>,
+    log: Arc<ReplicationLog>,
+    writes: Vec<ReplicatedWrite>,
+    /// The number of writes made at each savepoint
+    saves: Vec<usize>,
+}
+
+impl StoreTx for ReplicatingTx {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        self.inner.get(key, for_update)
+    }
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        self.inner.exists(key, for_update)
+    }
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.put(key, val)?;
+        self.writes.push(ReplicatedWrite::Put {
+            key: key.to_vec(),
+            val: val.to_vec(),
+        });
+        Ok(())
+    }
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)?;
+        self.writes.push(ReplicatedWrite::Del { key: key.to_vec() });
+        Ok(())
+    }
+    fn range_scan(&self, lower: &[u8], upper: &[u8]) -> KvIter {
+        self.inner.range_scan(lower, upper)
+    }
+    fn range_del(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        self.inner.range_del(lower, upper)?;
+        self.writes.push(ReplicatedWrite::RangeDel {
+            lower: lower.to_vec(),
+            upper: upper.to_vec(),
+        });
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<()> {
+        if self.writes.is_empty() {
+            return self.inner.commit();
+        }
+        let writes = mem::take(&mut self.writes);
+        let inner = &mut self.inner;
+        self.log.append(writes, || inner.commit())
+    }
+    fn rollback(&mut self) -> Result<()> {
+        self.writes.clear();
+        self.saves.clear();
+        self.inner.rollback()
+    }
+    fn save(&mut self) {
+        self.saves.push(self.writes.len());
+        self.inner.save();
+    }
+    fn pop_save(&mut self) -> Result<()> {
+        self.inner.pop_save()?;
+        self.saves.pop();
+        Ok(())
+    }
+    fn rollback_to_save(&mut self) -> Result<()> {
+        self.inner.rollback_to_save()?;
+        if let Some(n) = self.saves.pop() {
+            self.writes.truncate(n);
+        }
+        Ok(())
+    }
+}
+
+impl Db {
+    /// Answer a request of a follower calling [`Db::replicate_from`], returning the response
+    /// to send back. The database must keep a replication log, as set by
+    /// [`DbOptions::replication_log_size`](crate::DbOptions::replication_log_size).
+    pub fn handle_replication_request(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let log = match &self.replication {
+            Some(log) => log,
+            None => bail!(ReplicationDisabled),
+        };
+        let response = match rmp_serde::from_slice(request).into_diagnostic()? {
+            ReplicationRequest::Batches { from, limit } => {
+                log.batches_after(from, limit.min(REPLICATION_PAGE_BATCHES))
+            }
+        };
+        rmp_serde::to_vec(&response).into_diagnostic()
+    }
+    /// Apply the writes committed to the leader since those last applied to this database,
+    /// which must have been opened as a follower with
+    /// [`DbOptions::follower`](crate::DbOptions::follower). `leader` carries a request to the
+    /// leader, where it is answered by [`Db::handle_replication_request`], and returns the
+    /// response; any transport will do. Returns the number of batches applied, each being
+    /// the writes of a transaction committed to the leader, applied in a single transaction.
+    ///
+    /// A new follower is first initialized by restoring a backup of the leader with
+    /// [`Db::restore`]. Afterwards this is called repeatedly, for example on a timer, to catch
+    /// up. It fails if the leader no longer keeps some of the batches to apply, in which
+    /// case the follower must be initialized anew from a newer backup.
+    pub fn replicate_from(
+        &self,
+        mut leader: impl FnMut(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<usize> {
+        ensure!(self.follower, NotAFollower);
+        ensure!(!self.read_only, ReadOnlyDb);
+        let mut position: ReplicationPosition =
+            match self.db.transact()?.get(&position_key(), false)? {
+                None => bail!(NoReplicationPosition),
+                Some(slice) => rmp_serde::from_slice(&slice).into_diagnostic()?,
+            };
+        let mut applied = 0;
+        loop {
+            let req = rmp_serde::to_vec(&ReplicationRequest::Batches {
+                from: position,
+                limit: REPLICATION_PAGE_BATCHES,
+            })
+            .into_diagnostic()?;
+            let batches = match rmp_serde::from_slice(&leader(&req)?).into_diagnostic()? {
+                ReplicationResponse::Batches(batches) => batches,
+                ReplicationResponse::NotKept => bail!(ReplicationGap),
+            };
+            if batches.is_empty() {
+                break;
+            }
+            for batch in batches {
+                ensure!(batch.seq == position.seq + 1, BadReplicationResponse);
+                position.seq = batch.seq;
+                let mut tx = self.db.transact()?;
+                for write in batch.writes {
+                    match write {
+                        ReplicatedWrite::Put { key, val } => tx.put(&key, &val)?,
+                        ReplicatedWrite::Del { key } => tx.del(&key)?,
+                        ReplicatedWrite::RangeDel { lower, upper } => {
+                            tx.range_del(&lower, &upper)?
+                        }
+                    }
+                }
+                tx.put(&position_key(), &position.encode()?)?;
+                tx.commit()?;
+                applied += 1;
+            }
+        }
+        if applied > 0 {
+            self.load_last_ids()?;
+            self.refresh_continuous_queries(None);
+        }
+        Ok(applied)
+    }
+}
@@ -0,0 +1,173 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Scheduled queries: named read-only queries run periodically, each run storing the result
+//! in one relation and the rows that entered or left the result since the previous run in
+//! another. The schedules are kept in the catalog and run by [`Db::run_due_scheduled_queries`],
+//! which a background thread calls when the database is opened with `scheduler` on.
+
+use std::collections::BTreeSet;
+
+use miette::{IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::program::RelationOp;
+use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::parse_script;
+use crate::runtime::db::column_symbols;
+use crate::runtime::relation::{InputRelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::Db;
+
+/// A query run every `every` seconds, its rows replacing those of `result` and the changes
+/// to them since the previous run replacing those of `delta`.
+#[derive(Clone, Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ScheduledQuery {
+    pub(crate) every: f64,
+    pub(crate) script: String,
+    pub(crate) result: String,
+    pub(crate) delta: String,
+    /// When the query last ran, in seconds since the epoch
+    #[serde(default)]
+    pub(crate) last_run: Option<f64>,
+}
+
+impl ScheduledQuery {
+    // `Option::is_none_or` would raise the minimum supported Rust version
+    #[allow(clippy::unnecessary_map_or)]
+    pub(crate) fn is_due(&self, now: f64) -> bool {
+        self.last_run.map_or(true, |last| now >= last + self.every)
+    }
+}
+
+fn schedule_key(name: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("schedule")),
+        DataValue::Str(SmartString::from(name)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+/// The input handle of a relation whose columns are all keys of any type.
+fn output_relation(name: &str, columns: Vec<SmartString<LazyCompact>>) -> InputRelationHandle {
+    let metadata = StoredRelationMetadata {
+        keys: columns
+            .into_iter()
+            .map(|name| ColumnDef {
+                name,
+                typing: NullableColType {
+                    coltype: ColType::Any,
+                    nullable: true,
+                },
+                default_gen: None,
+            })
+            .collect(),
+        non_keys: vec![],
+    };
+    InputRelationHandle {
+        name: Symbol::new(name, Default::default()),
+        key_bindings: column_symbols(&metadata.keys),
+        dep_bindings: vec![],
+        metadata,
+        span: Default::default(),
+    }
+}
+
+impl SessionTx {
+    pub(crate) fn get_schedule(&self, name: &str) -> Result<Option<ScheduledQuery>> {
+        match self.tx.get(&schedule_key(name), false)? {
+            None => Ok(None),
+            Some(slice) => Ok(Some(serde_json::from_slice(&slice).into_diagnostic()?)),
+        }
+    }
+    pub(crate) fn put_schedule(&mut self, name: &str, query: &ScheduledQuery) -> Result<()> {
+        let val = serde_json::to_vec(query).into_diagnostic()?;
+        self.tx.put(&schedule_key(name), &val)?;
+        Ok(())
+    }
+    pub(crate) fn remove_schedule(&mut self, name: &str) -> Result<bool> {
+        let key = schedule_key(name);
+        let existed = self.tx.exists(&key, true)?;
+        if existed {
+            self.tx.del(&key)?;
+        }
+        Ok(existed)
+    }
+    pub(crate) fn list_schedules(&self) -> Result<Vec<(String, ScheduledQuery)>> {
+        let lower = schedule_key("");
+        let upper = schedule_key(&String::from(LARGEST_UTF_CHAR));
+        let mut collected = vec![];
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = pair?;
+            let key = Tuple::decode_from_key(&k_slice);
+            let name = key.0[2].get_string().unwrap_or_default().to_string();
+            collected.push((name, serde_json::from_slice(&v_slice).into_diagnostic()?));
+        }
+        Ok(collected)
+    }
+    /// Runs a scheduled query, replacing the rows of its result relation with the new result
+    /// and those of its delta relation with the rows added to and removed from the result,
+    /// marked by the leading column `added`. Returns the key ranges to delete after commit.
+    pub(crate) fn run_scheduled_query(
+        &mut self,
+        db: &Db,
+        query: &ScheduledQuery,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let program = parse_script(
+            &query.script,
+            &Default::default(),
+            &self.services.custom_algos,
+        )?
+        .get_single_program()?;
+        let headers = program
+            .get_entry_out_head()?
+            .into_iter()
+            .map(|symb| symb.name)
+            .collect::<Vec<_>>();
+        let (rows, _in_mem_guard) = db.query_rows(self, program, &query.script)?;
+        let rows: BTreeSet<Tuple> = rows.collect();
+        let previous: BTreeSet<Tuple> = if self.relation_exists(&query.result)? {
+            self.get_relation(&query.result, false)?
+                .scan_all(self)
+                .collect::<Result<_>>()?
+        } else {
+            Default::default()
+        };
+        let changes = rows
+            .difference(&previous)
+            .map(|row| (true, row))
+            .chain(previous.difference(&rows).map(|row| (false, row)))
+            .map(|(added, row)| {
+                let mut changed = vec![DataValue::Bool(added)];
+                changed.extend(row.0.iter().cloned());
+                Ok(Tuple(changed))
+            })
+            .collect::<Vec<_>>();
+
+        let mut cleanups = vec![];
+        let result = output_relation(&query.result, headers.clone());
+        cleanups.extend(self.execute_relation(
+            db,
+            rows.into_iter().map(Ok),
+            RelationOp::Replace,
+            &result,
+            &result.key_bindings,
+        )?);
+        let mut delta_columns = vec![SmartString::from("added")];
+        delta_columns.extend(headers);
+        let delta = output_relation(&query.delta, delta_columns);
+        cleanups.extend(self.execute_relation(
+            db,
+            changes.into_iter(),
+            RelationOp::Replace,
+            &delta,
+            &delta.key_bindings,
+        )?);
+        Ok(cleanups)
+    }
+}
@@ -0,0 +1,221 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Statistics of stored relations, recorded in the catalog each time they are collected with
+//! `::relation stats`. They are not kept up to date by writes, and are only used as estimates,
+//! e.g. by the lint warning about full scans of large relations and by `::estimate`.
+//!
+//! Besides the number of rows, the statistics of each column hold its most common values and
+//! an equi-depth histogram of its other values, computed from a sample of the rows of large
+//! relations.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{IntoDiagnostic, Result};
+use rand::Rng;
+use smartstring::SmartString;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+
+/// Number of rows sampled from larger relations for the statistics of columns
+const STATS_SAMPLE_ROWS: usize = 10_000;
+/// Maximum number of most common values kept for each column
+const MOST_COMMON_VALUES: usize = 10;
+/// Number of buckets of the histograms of columns
+const HISTOGRAM_BUCKETS: usize = 20;
+
+/// Statistics of a stored relation as of the time they were collected.
+#[derive(Clone, Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct RelationStatistics {
+    pub(crate) rows: usize,
+    /// When the statistics were collected, in seconds since the epoch
+    pub(crate) collected: f64,
+    /// The statistics of the keys and then the non-keys of the relation, empty if collected
+    /// by versions not computing them
+    #[serde(default)]
+    pub(crate) columns: Vec<ColumnStatistics>,
+}
+
+/// Statistics of the values of a column.
+#[derive(Clone, Debug, Default, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ColumnStatistics {
+    /// Fraction of rows holding null
+    pub(crate) null_fraction: f64,
+    /// Estimated number of distinct non-null values
+    pub(crate) distinct: usize,
+    /// The most common values, most common first, with the fraction of rows holding each
+    pub(crate) most_common: Vec<(DataValue, f64)>,
+    /// Bounds of the buckets of an equi-depth histogram of the non-null values other than
+    /// the most common ones, each bucket holding about as many rows
+    pub(crate) bounds: Vec<DataValue>,
+}
+
+impl ColumnStatistics {
+    /// Computes the statistics from the values of the column in a sample of the `total` rows
+    /// of the relation.
+    pub(crate) fn from_sample(values: Vec<DataValue>, total: usize) -> Self {
+        let sampled = values.len();
+        if sampled == 0 {
+            return Self::default();
+        }
+        let mut counts: BTreeMap<DataValue, usize> = BTreeMap::new();
+        let mut nulls = 0;
+        for val in values {
+            if val == DataValue::Null {
+                nulls += 1;
+            } else {
+                *counts.entry(val).or_default() += 1;
+            }
+        }
+        let non_null = sampled - nulls;
+        let seen = counts.len();
+        let distinct = if sampled == total || seen == 0 {
+            seen
+        } else {
+            // the Duj1 estimator of Haas and Stokes, scaling up by the values seen only once
+            let singles = counts.values().filter(|c| **c == 1).count() as f64;
+            let (n, total) = (sampled as f64, total as f64);
+            let estimate = n * seen as f64 / (n - singles + singles * n / total);
+            (estimate.round() as usize).clamp(seen, total as usize)
+        };
+        // values are only common if seen more than once and more often than the average
+        let average = non_null as f64 / seen.max(1) as f64;
+        let most_common = counts
+            .iter()
+            .filter(|(_, c)| **c > 1 && **c as f64 > average)
+            .sorted_by(|(_, a), (_, b)| b.cmp(a))
+            .take(MOST_COMMON_VALUES)
+            .map(|(v, c)| (v.clone(), *c as f64 / sampled as f64))
+            .collect_vec();
+        for (v, _) in &most_common {
+            counts.remove(v);
+        }
+        // `iter::repeat_n` would raise the minimum supported Rust version
+        #[allow(clippy::manual_repeat_n)]
+        let rest = counts
+            .into_iter()
+            .flat_map(|(v, c)| std::iter::repeat(v).take(c))
+            .collect_vec();
+        let bounds = if rest.is_empty() {
+            vec![]
+        } else {
+            let buckets = HISTOGRAM_BUCKETS.min(rest.len());
+            (0..=buckets)
+                .map(|i| rest[i * (rest.len() - 1) / buckets].clone())
+                .collect_vec()
+        };
+        Self {
+            null_fraction: nulls as f64 / sampled as f64,
+            distinct,
+            most_common,
+            bounds,
+        }
+    }
+    fn histogram_fraction(&self) -> f64 {
+        let common: f64 = self.most_common.iter().map(|(_, f)| f).sum();
+        (1. - self.null_fraction - common).max(0.)
+    }
+    /// Estimated fraction of rows holding `val`.
+    pub(crate) fn eq_selectivity(&self, val: &DataValue) -> f64 {
+        if *val == DataValue::Null {
+            return self.null_fraction;
+        }
+        if let Some((_, f)) = self.most_common.iter().find(|(v, _)| v == val) {
+            return *f;
+        }
+        let others = self.distinct.saturating_sub(self.most_common.len());
+        if others == 0 {
+            0.
+        } else {
+            self.histogram_fraction() / others as f64
+        }
+    }
+    /// Estimated fraction of rows holding a non-null value less than `val`.
+    pub(crate) fn lt_selectivity(&self, val: &DataValue) -> f64 {
+        let common: f64 = self
+            .most_common
+            .iter()
+            .filter(|(v, _)| v < val)
+            .map(|(_, f)| f)
+            .sum();
+        let in_histogram = match self.bounds.len() {
+            0 => 0.,
+            n => {
+                let buckets = (n - 1).max(1) as f64;
+                let below = self.bounds.iter().filter(|b| *b < val).count();
+                let position = if below == 0 {
+                    0.
+                } else if below == n {
+                    1.
+                } else {
+                    // the value is assumed to be halfway through its bucket
+                    ((below - 1) as f64 + 0.5) / buckets
+                };
+                position * self.histogram_fraction()
+            }
+        };
+        common + in_histogram
+    }
+}
+
+/// Collects a uniform sample of at most [STATS_SAMPLE_ROWS] of the rows fed to it.
+pub(crate) struct RowSampler {
+    seen: usize,
+    pub(crate) sample: Vec<Tuple>,
+}
+
+impl RowSampler {
+    pub(crate) fn new() -> Self {
+        Self {
+            seen: 0,
+            sample: vec![],
+        }
+    }
+    pub(crate) fn feed(&mut self, row: Tuple) {
+        self.seen += 1;
+        if self.sample.len() < STATS_SAMPLE_ROWS {
+            self.sample.push(row);
+        } else {
+            let i = rand::thread_rng().gen_range(0..self.seen);
+            if i < STATS_SAMPLE_ROWS {
+                self.sample[i] = row;
+            }
+        }
+    }
+}
+
+fn stats_key(name: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("stats")),
+        DataValue::Str(SmartString::from(name)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl SessionTx {
+    pub(crate) fn get_statistics(&self, name: &str) -> Result<Option<RelationStatistics>> {
+        match self.tx.get(&stats_key(name), false)? {
+            None => Ok(None),
+            // statistics collected by earlier versions are JSON
+            Some(slice) if slice.first() == Some(&b'{') => {
+                Ok(Some(serde_json::from_slice(&slice).into_diagnostic()?))
+            }
+            Some(slice) => Ok(Some(rmp_serde::from_slice(&slice).into_diagnostic()?)),
+        }
+    }
+    pub(crate) fn put_statistics(&mut self, name: &str, stats: &RelationStatistics) -> Result<()> {
+        let val = rmp_serde::to_vec(stats).into_diagnostic()?;
+        self.tx.put(&stats_key(name), &val)?;
+        Ok(())
+    }
+    pub(crate) fn remove_statistics(&mut self, name: &str) -> Result<()> {
+        self.tx.del(&stats_key(name))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,697 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Synchronisation of stored relations between two databases by exchanging digests of key
+//! ranges, Merkle-style: ranges whose digests agree are skipped, ranges that differ are split
+//! and compared again, and only the rows of small differing ranges are transferred.
+//!
+//! For relations keeping last-writer-wins metadata (`::relation lww <rel> on`), what is compared
+//! are records carrying the time of the last write to each key and whether it was a deletion,
+//! so that deletions propagate and the newer write wins regardless of which side runs the sync.
+//!
+//! The same requests serve to import relations from another database, page by page.
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::{bail, ensure, miette, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::functions::{tuple_hash, TUPLE_HASH_INIT};
+use crate::data::program::RelationOp;
+use crate::data::relation::StoredRelationMetadata;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::runtime::db::column_symbols;
+use crate::runtime::relation::{
+    current_validity, AccessLevel, InputRelationHandle, InsufficientAccessLevel, RelationHandle,
+    RelationId,
+};
+use crate::runtime::transact::SessionTx;
+use crate::Db;
+
+/// Ranges holding at most this many rows on both sides together are compared row by row.
+const SYNC_LEAF_ROWS: usize = 256;
+/// Number of sub-ranges a differing range is split into.
+const SYNC_FANOUT: usize = 16;
+/// The stored relation into which conflicting edits found while syncing relations keeping
+/// last-writer-wins metadata are written for review.
+pub(crate) const SYNC_CONFLICTS: &str = "sync_conflicts";
+/// Number of rows fetched in each request when importing a relation.
+const IMPORT_PAGE_ROWS: usize = 1024;
+
+/// Keys from `lower` (inclusive) to `upper` (exclusive); missing bounds are open.
+#[derive(Debug, Clone, Default, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct KeyRange {
+    pub(crate) lower: Option<Vec<DataValue>>,
+    pub(crate) upper: Option<Vec<DataValue>>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct RangeDigest {
+    rows: usize,
+    hash: u64,
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum SyncRequest {
+    Schema {
+        relation: String,
+    },
+    Digests {
+        relation: String,
+        ranges: Vec<KeyRange>,
+    },
+    Split {
+        relation: String,
+        range: KeyRange,
+        fanout: usize,
+    },
+    Fetch {
+        relation: String,
+        range: KeyRange,
+    },
+    Put {
+        relation: String,
+        rows: Vec<Vec<DataValue>>,
+    },
+    Finish {
+        relation: String,
+        at: f64,
+    },
+    Columns {
+        relation: String,
+    },
+    Page {
+        relation: String,
+        range: KeyRange,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) enum SyncResponse {
+    Schema(String),
+    Digests(Vec<RangeDigest>),
+    SplitPoints(Vec<Vec<DataValue>>),
+    Rows(Vec<Vec<DataValue>>),
+    Written(usize),
+    Finished,
+    Columns(StoredRelationMetadata),
+}
+
+/// Summary of a run of [`Db::sync_relation`].
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SyncReport {
+    /// Number of key ranges whose digests were compared.
+    pub ranges_compared: usize,
+    /// Number of rows written into the local relation.
+    pub rows_received: usize,
+    /// Number of rows written into the relation of the peer.
+    pub rows_sent: usize,
+    /// Number of keys edited on both sides since the last sync, recorded in `sync_conflicts`.
+    pub conflicts: usize,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot sync relation '{0}': the columns differ between the two databases")]
+#[diagnostic(code(sync::schema_mismatch))]
+#[diagnostic(help("Local columns: {1}; columns of the peer: {2}"))]
+struct SyncSchemaMismatch(String, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unexpected response from the peer during sync")]
+#[diagnostic(code(sync::bad_response))]
+pub(crate) struct BadSyncResponse;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot import relation '{0}': the columns differ from those of the existing relation")]
+#[diagnostic(code(sync::import_schema_mismatch))]
+#[diagnostic(help("Local columns: {1}; columns of the peer: {2}"))]
+struct ImportSchemaMismatch(String, String, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Request to '{0}' failed with status {1}")]
+#[diagnostic(code(sync::remote_error))]
+#[diagnostic(help("{2}"))]
+struct RemoteRequestFailed(String, i32, String);
+
+impl Db {
+    /// Bring the stored relation `relation` in line with the relation of the same name in
+    /// another database, in both directions. `peer` carries a request to the other database,
+    /// where it is answered by [`Db::handle_sync_request`], and returns the response; any
+    /// transport will do, e.g. the `/sync` endpoint of the server.
+    ///
+    /// Afterwards both relations contain the union of their rows. Where the two sides hold
+    /// different rows for the same key, the row of this database wins. Deletions are not
+    /// propagated.
+    ///
+    /// If the relation keeps last-writer-wins metadata on both sides, the later of the two
+    /// writes to a key wins instead, whether it put or removed the row. Keys written on both
+    /// sides since the last sync are also recorded in the relation `sync_conflicts` of this
+    /// database, together with the losing version, for review.
+    pub fn sync_relation(
+        &self,
+        relation: &str,
+        mut peer: impl FnMut(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<SyncReport> {
+        let mut ask = |req: SyncRequest| -> Result<SyncResponse> {
+            let req = rmp_serde::to_vec(&req).into_diagnostic()?;
+            rmp_serde::from_slice(&peer(&req)?).into_diagnostic()
+        };
+        let relation = relation.to_string();
+        let started = current_validity();
+        let tx = self.transact()?;
+        let handle = tx.get_relation(&relation, false)?;
+        ensure_readable(&handle)?;
+        let local_schema = sync_schema(&handle);
+        let last_synced = tx.last_synced(&relation)?;
+        match ask(SyncRequest::Schema {
+            relation: relation.clone(),
+        })? {
+            SyncResponse::Schema(peer_schema) => ensure!(
+                peer_schema == local_schema,
+                SyncSchemaMismatch(relation, local_schema, peer_schema)
+            ),
+            _ => bail!(BadSyncResponse),
+        }
+
+        let n_keys = handle.metadata.keys.len();
+        let mut report = SyncReport::default();
+        let mut to_receive = vec![];
+        let mut conflicts = vec![];
+        let mut pending = vec![KeyRange::default()];
+        while !pending.is_empty() {
+            let ranges = pending.split_off(pending.len().saturating_sub(SYNC_FANOUT));
+            let peer_digests = match ask(SyncRequest::Digests {
+                relation: relation.clone(),
+                ranges: ranges.clone(),
+            })? {
+                SyncResponse::Digests(digests) if digests.len() == ranges.len() => digests,
+                _ => bail!(BadSyncResponse),
+            };
+            for (range, peer_digest) in ranges.into_iter().zip(peer_digests) {
+                report.ranges_compared += 1;
+                let local_digest = tx.range_digest(&handle, &range)?;
+                if local_digest == peer_digest {
+                    continue;
+                }
+                if local_digest.rows + peer_digest.rows > SYNC_LEAF_ROWS {
+                    let points = if local_digest.rows >= peer_digest.rows {
+                        tx.range_split_points(&handle, &range, SYNC_FANOUT)?
+                    } else {
+                        match ask(SyncRequest::Split {
+                            relation: relation.clone(),
+                            range: range.clone(),
+                            fanout: SYNC_FANOUT,
+                        })? {
+                            SyncResponse::SplitPoints(points) => points,
+                            _ => bail!(BadSyncResponse),
+                        }
+                    };
+                    if !points.is_empty() {
+                        let mut lower = range.lower;
+                        for point in points {
+                            pending.push(KeyRange {
+                                lower,
+                                upper: Some(point.clone()),
+                            });
+                            lower = Some(point);
+                        }
+                        pending.push(KeyRange {
+                            lower,
+                            upper: range.upper,
+                        });
+                        continue;
+                    }
+                }
+
+                let peer_rows = match ask(SyncRequest::Fetch {
+                    relation: relation.clone(),
+                    range: range.clone(),
+                })? {
+                    SyncResponse::Rows(rows) => rows,
+                    _ => bail!(BadSyncResponse),
+                };
+                let mut local_rows: BTreeMap<_, _> = tx
+                    .range_rows(&handle, &range)?
+                    .into_iter()
+                    .map(|row| (row[..n_keys].to_vec(), row))
+                    .collect();
+                for row in peer_rows {
+                    match local_rows.remove(&row[..n_keys]) {
+                        None => to_receive.push(row),
+                        Some(local) => {
+                            if local == row {
+                                continue;
+                            }
+                            if handle.lww.is_none() {
+                                local_rows.insert(local[..n_keys].to_vec(), local);
+                                continue;
+                            }
+                            // Records compare by the time of the write first, with the rest
+                            // of the record breaking ties the same way on both sides.
+                            let keep_local = local[n_keys..] > row[n_keys..];
+                            let since = last_synced.unwrap_or(f64::NEG_INFINITY);
+                            let written_at =
+                                |rec: &[DataValue]| rec[n_keys].get_float().unwrap_or_default();
+                            if written_at(&local) > since && written_at(&row) > since {
+                                conflicts.push(conflict_row(
+                                    &relation, n_keys, &local, &row, keep_local, started,
+                                ));
+                            }
+                            if keep_local {
+                                local_rows.insert(local[..n_keys].to_vec(), local);
+                            } else {
+                                to_receive.push(row);
+                            }
+                        }
+                    }
+                }
+                if !local_rows.is_empty() {
+                    let rows = local_rows.into_values().collect_vec();
+                    match ask(SyncRequest::Put {
+                        relation: relation.clone(),
+                        rows,
+                    })? {
+                        SyncResponse::Written(n) => report.rows_sent += n,
+                        _ => bail!(BadSyncResponse),
+                    }
+                }
+            }
+        }
+        drop(tx);
+        report.rows_received = self.put_synced_rows(&relation, to_receive)?;
+        if handle.lww.is_some() {
+            report.conflicts = conflicts.len();
+            self.record_sync_conflicts(conflicts)?;
+            match ask(SyncRequest::Finish {
+                relation: relation.clone(),
+                at: started,
+            })? {
+                SyncResponse::Finished => {}
+                _ => bail!(BadSyncResponse),
+            }
+            self.set_last_synced(&relation, started)?;
+        }
+        Ok(report)
+    }
+    /// Answer a request sent by [`Db::sync_relation`] running on another database,
+    /// returning the response to be handed back to it.
+    pub fn handle_sync_request(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let request: SyncRequest = rmp_serde::from_slice(request)
+            .map_err(|err| miette!("Malformed sync request: {}", err))?;
+        let response = match request {
+            SyncRequest::Put { relation, rows } => {
+                SyncResponse::Written(self.put_synced_rows(&relation, rows)?)
+            }
+            SyncRequest::Schema { relation } => {
+                let tx = self.transact()?;
+                let handle = tx.get_relation(&relation, false)?;
+                ensure_readable(&handle)?;
+                SyncResponse::Schema(sync_schema(&handle))
+            }
+            SyncRequest::Digests { relation, ranges } => {
+                let tx = self.transact()?;
+                let handle = tx.get_relation(&relation, false)?;
+                ensure_readable(&handle)?;
+                SyncResponse::Digests(
+                    ranges
+                        .iter()
+                        .map(|range| tx.range_digest(&handle, range))
+                        .try_collect()?,
+                )
+            }
+            SyncRequest::Split {
+                relation,
+                range,
+                fanout,
+            } => {
+                let tx = self.transact()?;
+                let handle = tx.get_relation(&relation, false)?;
+                ensure_readable(&handle)?;
+                SyncResponse::SplitPoints(tx.range_split_points(&handle, &range, fanout)?)
+            }
+            SyncRequest::Fetch { relation, range } => {
+                let tx = self.transact()?;
+                let handle = tx.get_relation(&relation, false)?;
+                ensure_readable(&handle)?;
+                SyncResponse::Rows(tx.range_rows(&handle, &range)?)
+            }
+            SyncRequest::Finish { relation, at } => {
+                self.set_last_synced(&relation, at)?;
+                SyncResponse::Finished
+            }
+            SyncRequest::Columns { relation } => {
+                let tx = self.transact()?;
+                let handle = tx.get_relation(&relation, false)?;
+                ensure_readable(&handle)?;
+                SyncResponse::Columns(handle.metadata)
+            }
+            SyncRequest::Page {
+                relation,
+                range,
+                limit,
+            } => {
+                let tx = self.transact()?;
+                let handle = tx.get_relation(&relation, false)?;
+                ensure_readable(&handle)?;
+                SyncResponse::Rows(
+                    handle
+                        .scan_key_range(&tx, range.lower.as_deref(), range.upper.as_deref())
+                        .take(limit)
+                        .map_ok(|tuple| tuple.0)
+                        .try_collect()?,
+                )
+            }
+        };
+        rmp_serde::to_vec(&response).into_diagnostic()
+    }
+    /// Copy the rows of `relation` with keys from the prefix `lower` (inclusive) up to the
+    /// prefix `upper` (exclusive) from another database, reached through `peer` as for
+    /// [`Db::sync_relation`], and return the number of rows copied. The relation is created
+    /// with the columns it has there if it does not exist here.
+    ///
+    /// Rows are fetched and written a page at a time, each page in a transaction of its own
+    /// recording the last key written, so that an import interrupted midway resumes after
+    /// that key when run again from the same `source` over the same range.
+    pub(crate) fn import_relation(
+        &self,
+        source: &str,
+        relation: &str,
+        lower: Option<Vec<DataValue>>,
+        upper: Option<Vec<DataValue>>,
+        mut peer: impl FnMut(&[u8]) -> Result<Vec<u8>>,
+    ) -> Result<usize> {
+        let mut ask = |req: SyncRequest| -> Result<SyncResponse> {
+            let req = rmp_serde::to_vec(&req).into_diagnostic()?;
+            rmp_serde::from_slice(&peer(&req)?).into_diagnostic()
+        };
+        let relation = relation.to_string();
+        let metadata = match ask(SyncRequest::Columns {
+            relation: relation.clone(),
+        })? {
+            SyncResponse::Columns(metadata) => metadata,
+            _ => bail!(BadSyncResponse),
+        };
+        let handle = self.import_target(&relation, metadata)?;
+        let meta = InputRelationHandle {
+            name: Symbol::new(handle.name.clone(), Default::default()),
+            metadata: handle.metadata.clone(),
+            key_bindings: column_symbols(&handle.metadata.keys),
+            dep_bindings: column_symbols(&handle.metadata.non_keys),
+            span: Default::default(),
+        };
+        let headers = meta
+            .key_bindings
+            .iter()
+            .chain(meta.dep_bindings.iter())
+            .cloned()
+            .collect_vec();
+        let n_keys = meta.key_bindings.len();
+
+        let progress_key = import_progress_key(source, &relation);
+        let filter = KeyRange { lower, upper };
+        let mut range = filter.clone();
+        if let Some(last) = self.transact()?.import_progress(&progress_key, &filter)? {
+            range.lower = Some(key_successor(last));
+        }
+        let mut imported = 0;
+        loop {
+            let rows = match ask(SyncRequest::Page {
+                relation: relation.clone(),
+                range: range.clone(),
+                limit: IMPORT_PAGE_ROWS,
+            })? {
+                SyncResponse::Rows(rows) if rows.iter().all(|row| row.len() == headers.len()) => {
+                    rows
+                }
+                _ => bail!(BadSyncResponse),
+            };
+            let finished = rows.len() < IMPORT_PAGE_ROWS;
+            let last_key = rows.last().map(|row| row[..n_keys].to_vec());
+            imported += rows.len();
+            let mut tx = self.transact_write()?;
+            tx.execute_relation(
+                self,
+                rows.into_iter().map(|row| Ok(Tuple(row))),
+                RelationOp::Put,
+                &meta,
+                &headers,
+            )?;
+            match last_key {
+                Some(last) if !finished => {
+                    let val = rmp_serde::to_vec(&(&filter, &last)).into_diagnostic()?;
+                    tx.tx.put(&progress_key, &val)?;
+                    range.lower = Some(key_successor(last));
+                }
+                _ => tx.tx.del(&progress_key)?,
+            }
+            tx.commit_tx()?;
+            if finished {
+                return Ok(imported);
+            }
+        }
+    }
+    /// The relation imported rows go into, created with `metadata` if it does not exist.
+    pub(crate) fn import_target(
+        &self,
+        relation: &str,
+        metadata: StoredRelationMetadata,
+    ) -> Result<RelationHandle> {
+        let mut tx = self.transact_write()?;
+        if tx.relation_exists(relation)? {
+            let handle = tx.get_relation(relation, false)?;
+            let local = column_schema(&handle.metadata);
+            let peer = column_schema(&metadata);
+            ensure!(
+                local == peer,
+                ImportSchemaMismatch(relation.to_string(), local, peer)
+            );
+            return Ok(handle);
+        }
+        let handle = tx.create_relation(InputRelationHandle {
+            name: Symbol::new(relation, Default::default()),
+            metadata,
+            key_bindings: vec![],
+            dep_bindings: vec![],
+            span: Default::default(),
+        })?;
+        tx.commit_tx()?;
+        Ok(handle)
+    }
+    fn set_last_synced(&self, relation: &str, at: f64) -> Result<()> {
+        let mut tx = self.transact_write()?;
+        let val = rmp_serde::to_vec(&at).into_diagnostic()?;
+        tx.tx.put(&last_synced_key(relation), &val)?;
+        tx.commit_tx()
+    }
+}
+
+fn last_synced_key(relation: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("last_synced")),
+        DataValue::Str(SmartString::from(relation)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+fn import_progress_key(source: &str, relation: &str) -> Vec<u8> {
+    Tuple(vec![
+        DataValue::Null,
+        DataValue::Str(SmartString::from("import_progress")),
+        DataValue::Str(SmartString::from(source)),
+        DataValue::Str(SmartString::from(relation)),
+    ])
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+/// A bound greater than `key` and less than any other key following it.
+pub(crate) fn key_successor(mut key: Vec<DataValue>) -> Vec<DataValue> {
+    key.push(DataValue::Bot);
+    key
+}
+
+/// Sends a sync request to the `/sync` endpoint of a running server and returns the response,
+/// authenticating with `auth` if given.
+pub(crate) fn post_sync_request(
+    endpoint: &str,
+    auth: Option<&str>,
+    request: &[u8],
+) -> Result<Vec<u8>> {
+    let mut req = minreq::post(endpoint).with_body(request.to_vec());
+    if let Some(auth) = auth {
+        req = req.with_header("x-cozo-auth", auth);
+    }
+    let resp = req
+        .send()
+        .map_err(|e| miette!(e))
+        .wrap_err_with(|| format!("when requesting URL {}", endpoint))?;
+    ensure!(
+        resp.status_code == 200,
+        RemoteRequestFailed(
+            endpoint.to_string(),
+            resp.status_code,
+            resp.as_str().unwrap_or_default().to_string()
+        )
+    );
+    Ok(resp.into_bytes())
+}
+
+/// A row of `sync_conflicts` for two records of the same key. Deleted rows are shown as null.
+fn conflict_row(
+    relation: &str,
+    n_keys: usize,
+    local: &[DataValue],
+    peer: &[DataValue],
+    keep_local: bool,
+    detected_at: f64,
+) -> Vec<DataValue> {
+    let version = |rec: &[DataValue]| {
+        if rec[n_keys + 1] == DataValue::Bool(true) {
+            DataValue::Null
+        } else {
+            DataValue::List(rec[n_keys + 2..].to_vec())
+        }
+    };
+    vec![
+        DataValue::Str(SmartString::from(relation)),
+        DataValue::List(local[..n_keys].to_vec()),
+        DataValue::from(detected_at),
+        version(local),
+        local[n_keys].clone(),
+        version(peer),
+        peer[n_keys].clone(),
+        DataValue::Str(SmartString::from(if keep_local { "local" } else { "peer" })),
+    ]
+}
+
+pub(crate) fn ensure_readable(handle: &RelationHandle) -> Result<()> {
+    if handle.access_level < AccessLevel::ReadOnly {
+        bail!(InsufficientAccessLevel(
+            handle.name.to_string(),
+            "reading rows".to_string(),
+            handle.access_level
+        ));
+    }
+    Ok(())
+}
+
+/// The columns of the relation as compared between the two sides of a sync.
+fn sync_schema(handle: &RelationHandle) -> String {
+    let columns = column_schema(&handle.metadata);
+    if handle.lww.is_some() {
+        format!("{} with lww", columns)
+    } else {
+        columns
+    }
+}
+
+fn column_schema(metadata: &StoredRelationMetadata) -> String {
+    let keys = metadata
+        .keys
+        .iter()
+        .map(|col| format!("{}: {}", col.name, col.typing))
+        .join(", ");
+    let non_keys = metadata
+        .non_keys
+        .iter()
+        .map(|col| format!("{}: {}", col.name, col.typing))
+        .join(", ");
+    format!("{{{} => {}}}", keys, non_keys)
+}
+
+impl SessionTx {
+    /// The rows of the range in key order, or for relations keeping last-writer-wins
+    /// metadata, records of the keys followed by the time of the last write, whether it was
+    /// a deletion, and the non-keys of the row (nulls for deleted rows).
+    fn range_records<'a>(
+        &'a self,
+        handle: &'a RelationHandle,
+        range: &'a KeyRange,
+    ) -> Box<dyn Iterator<Item = Result<Vec<DataValue>>> + 'a> {
+        let (lower, upper) = (range.lower.as_deref(), range.upper.as_deref());
+        let lww = match handle.lww {
+            None => {
+                return Box::new(
+                    handle
+                        .scan_key_range(self, lower, upper)
+                        .map_ok(|tuple| tuple.0),
+                )
+            }
+            Some(lww) => lww,
+        };
+        let n_keys = handle.metadata.keys.len();
+        let n_non_keys = handle.metadata.non_keys.len();
+        Box::new(handle.scan_lww_key_range(lww, self, lower, upper).map(
+            move |stamp| -> Result<Vec<DataValue>> {
+                let mut record = stamp?.0;
+                if record[n_keys + 1] == DataValue::Bool(true) {
+                    record.extend((0..n_non_keys).map(|_| DataValue::Null));
+                } else {
+                    let prefix = Tuple(record[..n_keys].to_vec());
+                    let row = handle
+                        .scan_prefix(self, &prefix)
+                        .next()
+                        .transpose()?
+                        .ok_or_else(|| miette!("Missing row in '{}'", handle.name))?;
+                    record.extend(row.0.into_iter().skip(n_keys));
+                }
+                Ok(record)
+            },
+        ))
+    }
+    fn range_digest(&self, handle: &RelationHandle, range: &KeyRange) -> Result<RangeDigest> {
+        let mut rows = 0;
+        let mut hash = TUPLE_HASH_INIT;
+        for record in self.range_records(handle, range) {
+            rows += 1;
+            hash = tuple_hash(hash, &record?);
+        }
+        Ok(RangeDigest { rows, hash })
+    }
+    /// Keys dividing the rows of the range into `fanout` parts of about equal size.
+    fn range_split_points(
+        &self,
+        handle: &RelationHandle,
+        range: &KeyRange,
+        fanout: usize,
+    ) -> Result<Vec<Vec<DataValue>>> {
+        let n_keys = handle.metadata.keys.len();
+        let total = self.range_digest(handle, range)?.rows;
+        let step = (total / fanout.max(2)).max(1);
+        let mut points = vec![];
+        for (i, record) in self.range_records(handle, range).enumerate() {
+            let mut record = record?;
+            if i > 0 && i % step == 0 {
+                record.truncate(n_keys);
+                points.push(record);
+            }
+        }
+        Ok(points)
+    }
+    fn range_rows(&self, handle: &RelationHandle, range: &KeyRange) -> Result<Vec<Vec<DataValue>>> {
+        self.range_records(handle, range).try_collect()
+    }
+    /// The last key written by an interrupted import over the same range, if any.
+    fn import_progress(&self, key: &[u8], range: &KeyRange) -> Result<Option<Vec<DataValue>>> {
+        match self.tx.get(key, false)? {
+            None => Ok(None),
+            Some(slice) => {
+                let (imported, last): (KeyRange, Vec<DataValue>) =
+                    rmp_serde::from_slice(&slice).into_diagnostic()?;
+                Ok(if imported == *range { Some(last) } else { None })
+            }
+        }
+    }
+    fn last_synced(&self, relation: &str) -> Result<Option<f64>> {
+        match self.tx.get(&last_synced_key(relation), false)? {
+            None => Ok(None),
+            Some(slice) => Ok(Some(rmp_serde::from_slice(&slice).into_diagnostic()?)),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! Tiered storage: the old rows of relations keyed by time are offloaded to a second,
+//! slower or cheaper storage engine, and read back from it transparently by queries.
+//!
+//! A tiered relation has a watermark: the rows whose first key is below it live in the
+//! cold storage only, the others in the main storage only. Offloading copies the rows
+//! between the old and the new watermark to the cold storage and moves the watermark in
+//! the same transaction, after which the copied rows are dropped from the main storage.
+//! Rows below the watermark cannot be written. Rows are only dropped from the cold storage
+//! after the transaction no longer reading them there is committed.
+
+use miette::{bail, ensure, Diagnostic, Result};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::data::relation::ColType;
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{
+    current_validity, AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId,
+};
+use crate::runtime::transact::SessionTx;
+use crate::storage::KvIter;
+
+/// The retention policy of a tiered relation, as stored in its handle.
+#[derive(Clone, Debug, PartialEq, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct Tiering {
+    /// Rows whose first key is older than this many seconds are offloaded.
+    pub(crate) after: f64,
+    /// The watermark: rows whose first key is less than this are in the cold storage.
+    pub(crate) offloaded_before: Option<f64>,
+}
+
+// the times are never NaN
+impl Eq for Tiering {}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database has no cold storage for the offloaded rows of '{0}'")]
+#[diagnostic(code(eval::no_cold_storage))]
+#[diagnostic(help("Open the database with the option 'cold_storage' set"))]
+struct NoColdStorage(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot offload rows of '{0}' as it is not tiered")]
+#[diagnostic(code(eval::not_tiered))]
+#[diagnostic(help("Set a retention policy with '::relation tier <relation> after <seconds>'"))]
+struct NotTiered(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot tier '{0}' as its first key column is not a time")]
+#[diagnostic(code(eval::tiering_key_not_time))]
+#[diagnostic(help("The first key column must be of type 'Int' or 'Float', in seconds"))]
+struct TieringKeyNotTime(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot tier '{0}' as it has a vector index")]
+#[diagnostic(code(eval::tiering_with_vector_index))]
+struct TieringWithVectorIndex(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Row with key {1:?} of '{0}' has been offloaded to cold storage and cannot be written")]
+#[diagnostic(code(eval::write_to_offloaded_row))]
+#[diagnostic(help(
+    "Rows older than the retention policy are read-only, turn tiering off to write them"
+))]
+struct OffloadedRowWrite(String, Vec<DataValue>, #[label] SourceSpan);
+
+impl RelationHandle {
+    fn offload_watermark(&self) -> Option<f64> {
+        self.tiering.as_ref().and_then(|t| t.offloaded_before)
+    }
+    /// Checks that the row with the given keys is not below the watermark.
+    pub(crate) fn ensure_hot(&self, keys: &[DataValue], span: SourceSpan) -> Result<()> {
+        if let (Some(watermark), Some(first)) = (self.offload_watermark(), keys.first()) {
+            ensure!(
+                *first >= DataValue::from(watermark),
+                OffloadedRowWrite(self.name.to_string(), keys.to_vec(), span)
+            );
+        }
+        Ok(())
+    }
+    /// Scans the raw rows of the relation between the encoded keys `lower` and `upper`,
+    /// those below the watermark coming from the cold storage.
+    pub(crate) fn raw_scan(&self, tx: &SessionTx, lower: &[u8], upper: &[u8]) -> KvIter {
+        let watermark = match self.offload_watermark() {
+            None => return tx.tx.range_scan(lower, upper),
+            Some(w) => Tuple(vec![DataValue::from(w)]).encode_as_key(self.id),
+        };
+        let cold = match &tx.cold {
+            None => {
+                return Box::new(std::iter::once(Err(
+                    NoColdStorage(self.name.to_string()).into()
+                )))
+            }
+            Some(cold) => cold,
+        };
+        let split: &[u8] = if watermark.as_slice() < lower {
+            lower
+        } else if watermark.as_slice() > upper {
+            upper
+        } else {
+            &watermark
+        };
+        // rows below the watermark may linger in the main storage until dropped, so it is
+        // only read from the watermark on
+        Box::new(
+            cold.range_scan(lower, split)
+                .chain(tx.tx.range_scan(split, upper)),
+        )
+    }
+}
+
+impl SessionTx {
+    /// Sets or removes the retention policy of a relation. Removing it brings the offloaded
+    /// rows back into the main storage.
+    pub(crate) fn set_relation_tiering(&mut self, name: &Symbol, after: Option<f64>) -> Result<()> {
+        let mut handle = self.get_relation(name, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "setting tiering".to_string(),
+                handle.access_level
+            ))
+        }
+        match after {
+            Some(after) => {
+                ensure!(self.cold.is_some(), NoColdStorage(handle.name.to_string()));
+                ensure!(
+                    matches!(
+                        handle.metadata.keys.first().map(|col| &col.typing.coltype),
+                        Some(ColType::Int | ColType::Float)
+                    ),
+                    TieringKeyNotTime(handle.name.to_string(), name.span)
+                );
+                ensure!(
+                    handle.vector_index.is_none(),
+                    TieringWithVectorIndex(handle.name.to_string(), name.span)
+                );
+                let offloaded_before = handle.offload_watermark();
+                handle.tiering = Some(Tiering {
+                    after,
+                    offloaded_before,
+                });
+            }
+            None => {
+                if let Some(watermark) = handle.offload_watermark() {
+                    let lower = Tuple::default().encode_as_key(handle.id);
+                    let upper = Tuple(vec![DataValue::from(watermark)]).encode_as_key(handle.id);
+                    let cold = self
+                        .cold
+                        .as_ref()
+                        .ok_or_else(|| NoColdStorage(handle.name.to_string()))?;
+                    for pair in cold.range_scan(&lower, &upper) {
+                        let (key, val) = pair?;
+                        self.tx.put(&key, &val)?;
+                    }
+                    // the cold copies are kept should the transaction fail to commit
+                    self.script.cold_garbage.push((lower, upper));
+                }
+                handle.tiering = None;
+            }
+        }
+        self.put_relation_handle(&handle)
+    }
+    /// Moves the rows of a tiered relation older than its retention policy to the cold
+    /// storage and moves the watermark past them. Returns the number of rows moved.
+    pub(crate) fn offload_relation(&mut self, name: &Symbol) -> Result<usize> {
+        let mut handle = self.get_relation(name, true)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "offloading rows".to_string(),
+                handle.access_level
+            ))
+        }
+        let tiering = match &mut handle.tiering {
+            None => bail!(NotTiered(handle.name.to_string(), name.span)),
+            Some(tiering) => tiering,
+        };
+        let cutoff = current_validity() - tiering.after;
+        if matches!(tiering.offloaded_before, Some(w) if w >= cutoff) {
+            return Ok(0);
+        }
+        let cold = self
+            .cold
+            .as_mut()
+            .ok_or_else(|| NoColdStorage(handle.name.to_string()))?;
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple(vec![DataValue::from(cutoff)]).encode_as_key(handle.id);
+        if tiering.offloaded_before.is_none() {
+            // copies left behind when tiering was turned off are stale
+            cold.range_del(&lower, &Tuple::default().encode_as_key(handle.id.next()))?;
+        }
+        let mut copied = 0;
+        for pair in self.tx.range_scan(&lower, &upper) {
+            let (key, val) = pair?;
+            cold.put(&key, &val)?;
+            self.tx.del(&key)?;
+            copied += 1;
+        }
+        tiering.offloaded_before = Some(cutoff);
+        self.put_relation_handle(&handle)?;
+        Ok(copied)
+    }
+    /// Deletes the offloaded rows of a relation being destroyed from the cold storage, once
+    /// the transaction is committed.
+    pub(crate) fn destroy_offloaded(&mut self, handle: &RelationHandle) -> Result<()> {
+        if handle.offload_watermark().is_none() {
+            return Ok(());
+        }
+        ensure!(self.cold.is_some(), NoColdStorage(handle.name.to_string()));
+        let lower = Tuple::default().encode_as_key(handle.id);
+        let upper = Tuple::default().encode_as_key(handle.id.next());
+        self.script.cold_garbage.push((lower, upper));
+        Ok(())
+    }
+    pub(crate) fn put_relation_handle(&mut self, handle: &RelationHandle) -> Result<()> {
+        let name_key =
+            Tuple(vec![DataValue::Str(handle.name.clone())]).encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        handle
+            .serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.tx.put(&name_key, &meta_val)
+    }
+}
@@ -2,25 +2,184 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
  */
 
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::collections::BTreeSet;
+use std::mem;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
-use miette::Result;
-
-use cozorocks::Tx;
+use log::debug;
+use miette::{bail, Diagnostic, Result};
+use rayon::ThreadPool;
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
 
+use crate::algo::custom::CustomAlgos;
+use crate::data::expr::Expr;
 use crate::data::program::MagicSymbol;
 use crate::data::symb::Symbol;
 use crate::data::tuple::Tuple;
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
+use crate::query::eval::JoinSwitch;
+use crate::runtime::audit::AuditEntry;
+use crate::runtime::cdc::{CapturedChange, ChangeFeed};
+use crate::runtime::federation::Federation;
 use crate::runtime::in_mem::{InMemRelation, StoredRelationId};
-use crate::runtime::relation::RelationId;
+use crate::runtime::metrics::Metrics;
+use crate::runtime::namespace::{NamespaceResultQuotaExceeded, StorageUsage};
+use crate::runtime::relation::{RelationId, VersionClock, VersionStamp};
+use crate::storage::{Storage, StoreTx};
 
 pub struct SessionTx {
-    pub(crate) tx: Tx,
+    pub(crate) tx: Box<dyn StoreTx>,
+    /// The transaction over the cold storage holding the offloaded rows of tiered relations
+    pub(crate) cold: Option<Box<dyn StoreTx>>,
     pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) mem_store_id: Arc<AtomicU32>,
+    pub(crate) eval: EvalOptions,
+    pub(crate) query: QueryState,
+    pub(crate) script: ScriptRecord,
+    pub(crate) services: DbServices,
+    /// The bytes written to relations of namespaces with a storage quota, shared with the
+    /// transaction `tx` counting them
+    pub(crate) usage: Arc<Mutex<StorageUsage>>,
+}
+
+/// How the queries run within a transaction are evaluated, as set for the database.
+pub(crate) struct EvalOptions {
+    pub(crate) algo_pool: Option<Arc<ThreadPool>>,
+    pub(crate) reorder_predicates: bool,
+    /// Number of rows above which negated rules probed by few rows are not materialized
+    pub(crate) negation_materialize_rows: Option<usize>,
+}
+
+/// The options and records of the query being run, set before and reset after each query.
+#[derive(Default)]
+pub(crate) struct QueryState {
+    /// When set, row-level expression errors are counted here instead of failing the query
+    pub(crate) tolerated_errors: Option<AtomicU64>,
+    /// When set, the rows read and derived by the running query are counted against limits
+    pub(crate) row_guard: Option<Arc<RowGuard>>,
+    /// Whether scans of stored relations with soft deletion also produce the deleted rows
+    pub(crate) include_deleted: bool,
+    /// When set, algorithms estimated to need more memory than this many bytes are not run
+    pub(crate) algo_memory_budget: Option<usize>,
+    /// When set, the names of the stored relations the running query reads are collected here
+    pub(crate) relations_read: Option<Mutex<BTreeSet<SmartString<LazyCompact>>>>,
+    /// When set, the joins of the running query that reached the point of switching strategy
+    /// are collected here for the profile
+    pub(crate) join_switches: Option<Mutex<Vec<JoinSwitch>>>,
+    /// When set, the rows a namespace quota lets the running query return, and the number of
+    /// distinct rows of the entry rule, counting those skipped by `:offset`, past which the
+    /// query fails
+    pub(crate) max_result_rows: Option<(usize, usize)>,
+}
+
+/// What the scripts run within a transaction did, kept until it is committed.
+#[derive(Default)]
+pub(crate) struct ScriptRecord {
+    /// Rows put into or removed from stored relations by the running script, for the audit log
+    pub(crate) writes: Vec<AuditEntry>,
+    /// The label the host attached to the running script, if any
+    pub(crate) label: Option<String>,
+    /// Whether the access policy denied a query run within the transaction, which must then
+    /// not be committed
+    pub(crate) access_denied: bool,
+    /// The rows put into or removed from stored relations whose changes are captured
+    pub(crate) changes: Vec<CapturedChange>,
+    /// The stamp of the versions of rows recorded in histories within the transaction, once
+    /// one is recorded
+    pub(crate) version: Option<VersionStamp>,
+    /// The ranges of the cold storage holding rows no longer read from it, dropped once the
+    /// transaction is committed
+    pub(crate) cold_garbage: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// The parts of the database a transaction reports to or reads through.
+pub(crate) struct DbServices {
+    /// The remote databases whose relations queries may read
+    pub(crate) federation: Arc<Federation>,
+    /// The metrics of the database the transaction belongs to
+    pub(crate) metrics: Arc<Metrics>,
+    /// Where the changes captured within the transaction are delivered once it is committed
+    pub(crate) change_feed: Arc<ChangeFeed>,
+    /// What stamps the versions of rows recorded in histories
+    pub(crate) versions: Arc<VersionClock>,
+    /// The fixed rules implemented outside of the crate that queries may apply
+    pub(crate) custom_algos: Arc<CustomAlgos>,
+    /// The storage offloaded rows are kept in, if any
+    pub(crate) cold_storage: Option<Arc<dyn Storage>>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The transaction is aborted, as a query within it was denied access")]
+#[diagnostic(code(tx::access_denied))]
+pub(crate) struct TransactionAccessDenied;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query scanned more than {0} rows from stored relations")]
+#[diagnostic(code(eval::max_rows_scanned))]
+#[diagnostic(help(
+    "Bind the leading key columns of stored relations so that only a part of them is read, \
+    or raise the limit with ':max_rows_scanned'"
+))]
+struct MaxRowsScannedExceeded(usize);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The query derived more than {0} intermediate rows")]
+#[diagnostic(code(eval::max_intermediate_rows))]
+#[diagnostic(help(
+    "Check for rule bodies whose atoms share no variables, which join as a cartesian product, \
+    or raise the limit with ':max_intermediate_rows'"
+))]
+struct MaxIntermediateRowsExceeded(usize);
+
+/// Limits on the work a single query may do, shared with the iterators it opens.
+#[derive(Default)]
+pub(crate) struct RowGuard {
+    max_scanned: Option<usize>,
+    max_intermediate: Option<usize>,
+    scanned: AtomicUsize,
+    intermediate: AtomicUsize,
+}
+
+impl RowGuard {
+    pub(crate) fn new(max_scanned: Option<usize>, max_intermediate: Option<usize>) -> Self {
+        Self {
+            max_scanned,
+            max_intermediate,
+            ..Default::default()
+        }
+    }
+    /// Counts a row read from a stored relation.
+    #[inline(always)]
+    pub(crate) fn scanned(&self) -> Result<()> {
+        if let Some(max) = self.max_scanned {
+            if self.scanned.fetch_add(1, Ordering::Relaxed) >= max {
+                bail!(MaxRowsScannedExceeded(max))
+            }
+        }
+        Ok(())
+    }
+    /// Counts a row produced by the body of a rule, before deduplication.
+    #[inline(always)]
+    pub(crate) fn derived(&self) -> Result<()> {
+        if let Some(max) = self.max_intermediate {
+            if self.intermediate.fetch_add(1, Ordering::Relaxed) >= max {
+                bail!(MaxIntermediateRowsExceeded(max))
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SessionTx {
+    fn drop(&mut self) {
+        self.services
+            .metrics
+            .active_transactions
+            .fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
 impl SessionTx {
@@ -42,6 +201,76 @@ impl SessionTx {
         )
     }
 
+    /// Evaluates a row-level expression, turning errors into nulls if the query tolerates them.
+    pub(crate) fn eval_expr(&self, expr: &Expr, bindings: &Tuple) -> Result<DataValue> {
+        match expr.eval(bindings) {
+            Err(err) => match &self.query.tolerated_errors {
+                None => Err(err),
+                Some(counter) => {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    Ok(DataValue::Null)
+                }
+            },
+            ok => ok,
+        }
+    }
+
+    /// Evaluates a row-level predicate, rejecting the row on error if the query tolerates errors.
+    pub(crate) fn eval_pred(&self, expr: &Expr, bindings: &Tuple) -> Result<bool> {
+        match expr.eval_pred(bindings) {
+            Err(err) => match &self.query.tolerated_errors {
+                None => Err(err),
+                Some(counter) => {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    Ok(false)
+                }
+            },
+            ok => ok,
+        }
+    }
+
+    /// Records that a join probed far more rows than expected.
+    pub(crate) fn record_join_switch(&self, switch: JoinSwitch) {
+        debug!("{:?}", switch);
+        if let Some(switches) = &self.query.join_switches {
+            switches.lock().unwrap().push(switch);
+        }
+    }
+
+    /// Fails if `store`, holding the rows of the rule `rule_symb`, is the entry and has derived
+    /// more rows than the query may return.
+    pub(crate) fn check_result_rows(
+        &self,
+        rule_symb: &MagicSymbol,
+        store: &InMemRelation,
+    ) -> Result<()> {
+        if let Some((max, max_derived)) = self.query.max_result_rows {
+            if rule_symb.is_prog_entry() && store.num_tuples_at_epoch(0) > max_derived {
+                bail!(NamespaceResultQuotaExceeded(max))
+            }
+        }
+        Ok(())
+    }
+
+    /// The stamp of the versions of rows recorded in histories within the transaction,
+    /// the same for all of them.
+    pub(crate) fn version_stamp(&mut self) -> Result<VersionStamp> {
+        if let Some(stamp) = self.script.version {
+            return Ok(stamp);
+        }
+        let stamp = self.services.versions.next()?;
+        self.script.version = Some(stamp);
+        Ok(stamp)
+    }
+
+    /// Counts a row produced by the body of a rule against the limit of the query.
+    pub(crate) fn count_derived_row(&self) -> Result<()> {
+        match &self.query.row_guard {
+            None => Ok(()),
+            Some(guard) => guard.derived(),
+        }
+    }
+
     pub(crate) fn load_last_relation_store_id(&self) -> Result<RelationId> {
         let tuple = Tuple(vec![DataValue::Null]);
         let t_encoded = tuple.encode_as_key(RelationId::SYSTEM);
@@ -53,7 +282,24 @@ impl SessionTx {
     }
 
     pub fn commit_tx(&mut self) -> Result<()> {
+        if self.script.access_denied {
+            bail!(TransactionAccessDenied)
+        }
+        self.flush_storage_usage()?;
+        // rows are offloaded before the watermark is moved past them
+        if let Some(cold) = &mut self.cold {
+            cold.commit()?;
+        }
         self.tx.commit()?;
+        // and only dropped from it once the main storage no longer reads them there
+        if let Some(cold) = &self.services.cold_storage {
+            for (lower, upper) in mem::take(&mut self.script.cold_garbage) {
+                cold.range_del(&lower, &upper)?;
+            }
+        }
+        self.services
+            .change_feed
+            .publish(mem::take(&mut self.script.changes));
         Ok(())
     }
 }
@@ -0,0 +1,332 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use miette::{ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::storage::{Storage, StoreTx};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Storage compliance check '{0}' failed: {1}")]
+#[diagnostic(code(storage::compliance))]
+struct ComplianceFailure(&'static str, &'static str);
+
+/// Checks that `storage` has the transactional semantics Cozo relies on, returning an error
+/// naming the first check failing. The storage should be empty, and is left with some keys
+/// written by the checks.
+pub fn check_storage_compliance(storage: &dyn Storage) -> Result<()> {
+    read_own_writes(storage)?;
+    commit_and_rollback(storage)?;
+    isolation(storage)?;
+    savepoints(storage)?;
+    range_scans(storage)?;
+    range_deletion(storage)?;
+    Ok(())
+}
+
+/// A key of the group `group`, with a prefix like the keys Cozo writes.
+fn key(group: u8, suffix: &[u8]) -> Vec<u8> {
+    let mut key = vec![0, 0, 0, 0, 0, 0, 0, group];
+    key.extend_from_slice(suffix);
+    key
+}
+
+fn scanned(tx: &dyn StoreTx, lower: &[u8], upper: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    tx.range_scan(lower, upper).collect()
+}
+
+fn read_own_writes(storage: &dyn Storage) -> Result<()> {
+    const CHECK: &str = "read own writes";
+    let k = key(1, b"a");
+    let mut tx = storage.transact()?;
+    ensure!(
+        tx.get(&k, false)?.is_none(),
+        ComplianceFailure(CHECK, "a key never written has a value")
+    );
+    tx.put(&k, b"1")?;
+    ensure!(
+        tx.get(&k, false)? == Some(b"1".to_vec()),
+        ComplianceFailure(CHECK, "a put is not seen by the transaction making it")
+    );
+    ensure!(
+        tx.exists(&k, true)?,
+        ComplianceFailure(CHECK, "'exists' disagrees with 'get'")
+    );
+    tx.put(&k, b"2")?;
+    ensure!(
+        tx.get(&k, true)? == Some(b"2".to_vec()),
+        ComplianceFailure(CHECK, "a put does not overwrite the previous value")
+    );
+    tx.del(&k)?;
+    ensure!(
+        tx.get(&k, false)?.is_none() && !tx.exists(&k, false)?,
+        ComplianceFailure(CHECK, "a deletion is not seen by the transaction making it")
+    );
+    tx.rollback()
+}
+
+fn commit_and_rollback(storage: &dyn Storage) -> Result<()> {
+    const CHECK: &str = "commit and rollback";
+    let committed = key(2, b"committed");
+    let rolled_back = key(2, b"rolled back");
+    let dropped = key(2, b"dropped");
+
+    let mut tx = storage.transact()?;
+    tx.put(&committed, b"1")?;
+    tx.commit()?;
+    let mut tx = storage.transact()?;
+    tx.put(&rolled_back, b"1")?;
+    tx.rollback()?;
+    {
+        let mut tx = storage.transact()?;
+        tx.put(&dropped, b"1")?;
+    }
+
+    let tx = storage.transact()?;
+    ensure!(
+        tx.get(&committed, false)? == Some(b"1".to_vec()),
+        ComplianceFailure(CHECK, "a committed write is not seen by later transactions")
+    );
+    ensure!(
+        tx.get(&rolled_back, false)?.is_none(),
+        ComplianceFailure(CHECK, "a rolled back write is seen by later transactions")
+    );
+    ensure!(
+        tx.get(&dropped, false)?.is_none(),
+        ComplianceFailure(
+            CHECK,
+            "a write of a dropped transaction is seen by later ones"
+        )
+    );
+
+    let mut tx = storage.transact()?;
+    tx.del(&committed)?;
+    tx.commit()?;
+    let tx = storage.transact()?;
+    ensure!(
+        tx.get(&committed, false)?.is_none(),
+        ComplianceFailure(
+            CHECK,
+            "a committed deletion is not seen by later transactions"
+        )
+    );
+    Ok(())
+}
+
+fn isolation(storage: &dyn Storage) -> Result<()> {
+    const CHECK: &str = "isolation";
+    let existing = key(3, b"existing");
+    let added = key(3, b"added");
+    let mut tx = storage.transact()?;
+    tx.put(&existing, b"old")?;
+    tx.commit()?;
+
+    let mut writer = storage.transact()?;
+    let reader = storage.transact()?;
+    writer.put(&existing, b"new")?;
+    writer.put(&added, b"1")?;
+    let uncommitted = storage.transact()?;
+    ensure!(
+        uncommitted.get(&existing, false)? == Some(b"old".to_vec())
+            && uncommitted.get(&added, false)?.is_none(),
+        ComplianceFailure(CHECK, "an uncommitted write is seen by another transaction")
+    );
+    writer.commit()?;
+    ensure!(
+        reader.get(&existing, false)? == Some(b"old".to_vec())
+            && reader.get(&added, false)?.is_none(),
+        ComplianceFailure(
+            CHECK,
+            "a transaction sees a write committed after it started, so it does not read a snapshot"
+        )
+    );
+    ensure!(
+        scanned(reader.as_ref(), &key(3, b""), &key(4, b""))?
+            == vec![(existing.clone(), b"old".to_vec())],
+        ComplianceFailure(
+            CHECK,
+            "a range scan does not read the snapshot of its transaction"
+        )
+    );
+    Ok(())
+}
+
+fn savepoints(storage: &dyn Storage) -> Result<()> {
+    const CHECK: &str = "savepoints";
+    let a = key(4, b"a");
+    let b = key(4, b"b");
+    let c = key(4, b"c");
+    let mut tx = storage.transact()?;
+    tx.put(&a, b"1")?;
+    tx.save();
+    tx.put(&b, b"1")?;
+    tx.del(&a)?;
+    tx.rollback_to_save()?;
+    ensure!(
+        tx.get(&a, false)? == Some(b"1".to_vec()) && tx.get(&b, false)?.is_none(),
+        ComplianceFailure(
+            CHECK,
+            "rolling back to a savepoint does not undo the writes since"
+        )
+    );
+    tx.save();
+    tx.put(&c, b"1")?;
+    tx.pop_save()?;
+    ensure!(
+        tx.get(&c, false)? == Some(b"1".to_vec()),
+        ComplianceFailure(CHECK, "popping a savepoint undoes the writes since")
+    );
+    tx.save();
+    tx.save();
+    tx.put(&b, b"2")?;
+    tx.pop_save()?;
+    tx.rollback_to_save()?;
+    ensure!(
+        tx.get(&b, false)?.is_none(),
+        ComplianceFailure(CHECK, "nested savepoints are not rolled back to in order")
+    );
+    tx.commit()?;
+    let tx = storage.transact()?;
+    ensure!(
+        tx.get(&a, false)?.is_some()
+            && tx.get(&b, false)?.is_none()
+            && tx.get(&c, false)?.is_some(),
+        ComplianceFailure(
+            CHECK,
+            "the writes kept past savepoints are not all committed"
+        )
+    );
+    Ok(())
+}
+
+fn range_scans(storage: &dyn Storage) -> Result<()> {
+    const CHECK: &str = "range scans";
+    let keys = [
+        key(5, &[]),
+        key(5, &[0]),
+        key(5, &[0, 0]),
+        key(5, &[1]),
+        key(5, &[1, 255]),
+        key(5, &[128]),
+        key(5, &[255, 255]),
+    ];
+    let mut tx = storage.transact()?;
+    for k in keys.iter().rev() {
+        tx.put(k, k)?;
+    }
+    tx.put(&key(6, &[]), b"outside")?;
+    tx.commit()?;
+
+    let mut tx = storage.transact()?;
+    let expected = keys
+        .iter()
+        .map(|k| (k.clone(), k.clone()))
+        .collect::<Vec<_>>();
+    ensure!(
+        scanned(tx.as_ref(), &key(5, &[]), &key(6, &[]))? == expected,
+        ComplianceFailure(CHECK, "a scan does not produce the keys in bytewise order")
+    );
+    ensure!(
+        scanned(tx.as_ref(), &key(5, &[0, 0]), &key(5, &[128]))? == expected[2..5],
+        ComplianceFailure(
+            CHECK,
+            "a scan does not include its lower bound but exclude its upper one"
+        )
+    );
+    ensure!(
+        scanned(tx.as_ref(), &key(5, &[2]), &key(5, &[3]))?.is_empty()
+            && scanned(tx.as_ref(), &key(5, &[128]), &key(5, &[1]))?.is_empty(),
+        ComplianceFailure(CHECK, "a scan of an empty range produces keys")
+    );
+
+    let added = key(5, &[1, 0]);
+    tx.put(&added, b"added")?;
+    tx.del(&keys[1])?;
+    tx.put(&keys[3], b"changed")?;
+    let mut expected = vec![
+        (keys[0].clone(), keys[0].clone()),
+        (keys[2].clone(), keys[2].clone()),
+        (keys[3].clone(), b"changed".to_vec()),
+        (added, b"added".to_vec()),
+    ];
+    expected.extend(keys[4..].iter().map(|k| (k.clone(), k.clone())));
+    ensure!(
+        scanned(tx.as_ref(), &key(5, &[]), &key(6, &[]))? == expected,
+        ComplianceFailure(
+            CHECK,
+            "a scan does not see the writes of its own transaction"
+        )
+    );
+    tx.rollback()
+}
+
+fn range_deletion(storage: &dyn Storage) -> Result<()> {
+    const CHECK: &str = "range deletion";
+    let keys = |tx: &dyn StoreTx| -> Result<Vec<Vec<u8>>> {
+        Ok(scanned(tx, &key(7, &[]), &key(8, &[]))?
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect())
+    };
+    let mut tx = storage.transact()?;
+    for i in 0..10u8 {
+        tx.put(&key(7, &[i]), b"")?;
+    }
+    tx.commit()?;
+
+    let mut tx = storage.transact()?;
+    tx.range_del(&key(7, &[2]), &key(7, &[8]))?;
+    let expected = vec![key(7, &[0]), key(7, &[1]), key(7, &[8]), key(7, &[9])];
+    ensure!(
+        keys(tx.as_ref())? == expected,
+        ComplianceFailure(CHECK, "not exactly the keys in the range are deleted")
+    );
+    ensure!(
+        tx.get(&key(7, &[3]), false)?.is_none() && !tx.exists(&key(7, &[4]), true)?,
+        ComplianceFailure(CHECK, "a key in a deleted range can still be read")
+    );
+    tx.rollback()?;
+    ensure!(
+        keys(storage.transact()?.as_ref())?.len() == 10,
+        ComplianceFailure(CHECK, "a range deletion rolled back is not undone")
+    );
+
+    let mut tx = storage.transact()?;
+    tx.save();
+    tx.range_del(&key(7, &[0]), &key(7, &[10]))?;
+    tx.rollback_to_save()?;
+    ensure!(
+        keys(tx.as_ref())?.len() == 10,
+        ComplianceFailure(
+            CHECK,
+            "a range deletion rolled back to a savepoint is not undone"
+        )
+    );
+    tx.range_del(&key(7, &[2]), &key(7, &[8]))?;
+    tx.put(&key(7, &[5]), b"kept")?;
+    let mut with_kept = expected.clone();
+    with_kept.insert(2, key(7, &[5]));
+    ensure!(
+        keys(tx.as_ref())? == with_kept
+            && tx.get(&key(7, &[5]), false)?.as_deref() == Some(b"kept"),
+        ComplianceFailure(CHECK, "a key put after deleting its range is not seen")
+    );
+    tx.commit()?;
+    ensure!(
+        keys(storage.transact()?.as_ref())? == with_kept,
+        ComplianceFailure(
+            CHECK,
+            "a committed range deletion does not keep the keys put after it"
+        )
+    );
+
+    // keys dropped outside of transactions may stay readable, but no others may go
+    storage.range_del(&key(7, &[2]), &key(7, &[8]))?;
+    let remaining = keys(storage.transact()?.as_ref())?;
+    ensure!(
+        expected.iter().all(|k| remaining.contains(k)),
+        ComplianceFailure(CHECK, "keys outside of a dropped range are deleted")
+    );
+    Ok(())
+}
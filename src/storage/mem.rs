@@ -0,0 +1,132 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+
+use miette::{bail, Report, Result};
+
+use crate::storage::{KvIter, Storage, StoreTx};
+
+type KvMap = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A storage engine keeping everything in memory, lost when the last handle to it is dropped.
+///
+/// Transactions read from a snapshot taken when they start and never conflict: of two
+/// transactions writing to the same key, the one committing last wins.
+/// Meant for tests and short-lived databases, and as a reference for implementing engines.
+#[derive(Clone, Default)]
+pub struct MemStorage {
+    data: Arc<RwLock<Arc<KvMap>>>,
+}
+
+impl MemStorage {
+    /// Creates an empty storage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Storage for MemStorage {
+    fn transact(&self) -> Result<Box<dyn StoreTx>> {
+        Ok(Box::new(MemTx {
+            data: self.data.clone(),
+            snapshot: self.data.read().unwrap().clone(),
+            writes: Default::default(),
+            saves: vec![],
+        }))
+    }
+    fn range_del(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        if lower >= upper {
+            return Ok(());
+        }
+        let mut data = self.data.write().unwrap();
+        let data = Arc::make_mut(&mut *data);
+        let keys = data
+            .range(lower.to_vec()..upper.to_vec())
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for key in keys {
+            data.remove(&key);
+        }
+        Ok(())
+    }
+}
+
+/// The writes of a transaction, with `None` for a deleted key.
+type WriteSet = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+struct MemTx {
+    data: Arc<RwLock<Arc<KvMap>>>,
+    snapshot: Arc<KvMap>,
+    writes: WriteSet,
+    saves: Vec<WriteSet>,
+}
+
+impl StoreTx for MemTx {
+    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
+        Ok(match self.writes.get(key) {
+            Some(written) => written.clone(),
+            None => self.snapshot.get(key).cloned(),
+        })
+    }
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.writes.insert(key.to_vec(), Some(val.to_vec()));
+        Ok(())
+    }
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.writes.insert(key.to_vec(), None);
+        Ok(())
+    }
+    fn range_scan(&self, lower: &[u8], upper: &[u8]) -> KvIter {
+        if lower >= upper {
+            return Box::new(std::iter::empty());
+        }
+        let range = lower.to_vec()..upper.to_vec();
+        let mut merged: BTreeMap<Vec<u8>, Vec<u8>> = self
+            .snapshot
+            .range(range.clone())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        for (k, v) in self.writes.range(range) {
+            match v {
+                Some(v) => merged.insert(k.clone(), v.clone()),
+                None => merged.remove(k),
+            };
+        }
+        Box::new(merged.into_iter().map(Ok::<_, Report>))
+    }
+    fn commit(&mut self) -> Result<()> {
+        let mut data = self.data.write().unwrap();
+        let data = Arc::make_mut(&mut *data);
+        for (k, v) in std::mem::take(&mut self.writes) {
+            match v {
+                Some(v) => data.insert(k, v),
+                None => data.remove(&k),
+            };
+        }
+        Ok(())
+    }
+    fn rollback(&mut self) -> Result<()> {
+        self.writes.clear();
+        self.saves.clear();
+        Ok(())
+    }
+    fn save(&mut self) {
+        self.saves.push(self.writes.clone());
+    }
+    fn pop_save(&mut self) -> Result<()> {
+        if self.saves.pop().is_none() {
+            bail!("no savepoint to pop")
+        }
+        Ok(())
+    }
+    fn rollback_to_save(&mut self) -> Result<()> {
+        match self.saves.pop() {
+            None => bail!("no savepoint to roll back to"),
+            Some(writes) => self.writes = writes,
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+//! The storage engines Cozo can run on.
+//!
+//! Cozo keeps everything it stores, relations and metadata alike, in a single ordered
+//! key-value space with transactions over it. An engine is anything implementing [`Storage`]
+//! and [`StoreTx`], and a database is opened on it with [`crate::Db::new_with_storage`].
+//! Two engines come with the crate: [`RocksDbStorage`], used by [`crate::Db::new`],
+//! and [`MemStorage`], a simple in-memory one.
+//!
+//! # Stability
+//!
+//! The traits of this module follow semantic versioning like the rest of the public API:
+//! methods will not be removed or changed in a minor release, and methods added in one will
+//! come with default implementations. What is stored under which keys is private to Cozo
+//! and engines must treat keys and values as opaque bytes.
+//!
+//! Implementations out of tree should be checked with [`check_storage_compliance`],
+//! which exercises the transactional semantics Cozo relies on.
+
+use std::fmt::{Debug, Formatter};
+
+use miette::Result;
+
+pub use compliance::check_storage_compliance;
+pub use mem::MemStorage;
+pub use rocks::RocksDbStorage;
+
+pub(crate) mod compliance;
+pub(crate) mod mem;
+pub(crate) mod rocks;
+
+/// An iterator over key-value pairs in ascending order of keys.
+pub type KvIter = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>>;
+
+/// A storage engine: an ordered map from byte strings to byte strings with transactions over it.
+pub trait Storage: Send + Sync {
+    /// Starts a transaction. Reads within the transaction see the data as of its start,
+    /// together with the writes made within the transaction itself.
+    fn transact(&self) -> Result<Box<dyn StoreTx>>;
+    /// Drops all keys in the range `lower..upper` outside of any transaction.
+    /// Cozo only calls this on ranges no longer reachable, such as the rows of removed
+    /// relations, so engines may keep the keys readable until they are compacted away.
+    /// Keys that must disappear are deleted with [`StoreTx::range_del`] instead.
+    fn range_del(&self, lower: &[u8], upper: &[u8]) -> Result<()>;
+    /// Hints that the range `lower..upper` should be compacted.
+    fn range_compact(&self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+        Ok(())
+    }
+    /// Persists all committed writes, for engines buffering them.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+    /// The state of the background compactions, for engines running them.
+    fn compaction_stats(&self) -> Option<CompactionStats> {
+        None
+    }
+}
+
+/// The state of the background compactions of a storage engine.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Whether a compaction is waiting to run
+    pub pending: bool,
+    /// The number of compactions running
+    pub running: u64,
+    /// The estimated number of bytes compactions have to rewrite to settle
+    pub pending_bytes: u64,
+}
+
+impl Debug for dyn Storage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Storage")
+    }
+}
+
+/// A transaction of a [`Storage`]. Dropping it without committing rolls it back.
+///
+/// A transaction is never used by two threads at once, but it may be used from a thread other
+/// than the one that created it, while a fixed rule runs on the algorithm thread pool.
+pub trait StoreTx {
+    /// Gets the value of `key`. With `for_update`, the engine should detect writes to the key
+    /// by other transactions committing before this one, if it detects conflicts at all.
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>>;
+    /// Whether `key` has a value, with `for_update` as for [`StoreTx::get`].
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        Ok(self.get(key, for_update)?.is_some())
+    }
+    /// Sets the value of `key`.
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()>;
+    /// Removes `key`, which need not exist.
+    fn del(&mut self, key: &[u8]) -> Result<()>;
+    /// Iterates over the keys in the range `lower..upper` in ascending bytewise order.
+    /// The iterator may be kept while the transaction is written to, but whether it sees
+    /// writes made after its creation is up to the engine.
+    fn range_scan(&self, lower: &[u8], upper: &[u8]) -> KvIter;
+    /// Deletes all keys in the range `lower..upper` within the transaction. Keys put in the
+    /// range afterwards within the transaction are kept. The default implementation scans
+    /// the range and deletes the keys one by one; engines with range tombstones should
+    /// override it so that large ranges are deleted without visiting their keys.
+    fn range_del(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        for pair in self.range_scan(lower, upper) {
+            self.del(&pair?.0)?;
+        }
+        Ok(())
+    }
+    /// Makes the writes of the transaction visible to transactions started afterwards,
+    /// all of them or none.
+    fn commit(&mut self) -> Result<()>;
+    /// Discards the writes of the transaction.
+    fn rollback(&mut self) -> Result<()>;
+    /// Pushes a savepoint recording the writes made so far.
+    fn save(&mut self);
+    /// Pops the last savepoint, keeping the writes made since.
+    fn pop_save(&mut self) -> Result<()>;
+    /// Discards the writes made since the last savepoint, and pops it.
+    fn rollback_to_save(&mut self) -> Result<()>;
+}
@@ -0,0 +1,373 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under MPL-2.0.
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use miette::{miette, IntoDiagnostic, Result, WrapErr};
+
+use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+
+use crate::data::tuple::{Tuple, ENCODED_KEY_MIN_LEN, KEY_PREFIX_LEN};
+use crate::runtime::db::BadDbInit;
+use crate::storage::{CompactionStats, KvIter, Storage, StoreTx};
+
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct DbManifest {
+    storage_version: u64,
+}
+
+const CURRENT_STORAGE_VERSION: u64 = 2;
+
+fn write_manifest(manifest_path: &Path) -> Result<()> {
+    fs::write(
+        manifest_path,
+        rmp_serde::to_vec_named(&DbManifest {
+            storage_version: CURRENT_STORAGE_VERSION,
+        })
+        .into_diagnostic()
+        .wrap_err_with(|| "when serializing manifest")?,
+    )
+    .into_diagnostic()
+    .wrap_err_with(|| "when serializing manifest")
+}
+
+/// The storage engine backed by RocksDB, persisting data in a directory.
+#[derive(Clone)]
+pub struct RocksDbStorage {
+    db: RocksDb,
+}
+
+impl RocksDbStorage {
+    /// Opens the storage in the directory `path`, creating it if it does not exist.
+    /// With `threads`, RocksDB uses that many background threads.
+    pub fn open(path: &str, threads: Option<usize>) -> Result<Self> {
+        Self::open_with(path, threads, false)
+    }
+    /// Opens the existing storage in the directory `path` for reading only. No lock is taken,
+    /// so any number of processes may open the same directory this way, along with at most
+    /// one process opening it with [`RocksDbStorage::open`]. The data is seen as of the
+    /// opening, and writing it fails.
+    pub fn open_read_only(path: &str, threads: Option<usize>) -> Result<Self> {
+        Self::open_with(path, threads, true)
+    }
+    fn open_with(path: &str, threads: Option<usize>, read_only: bool) -> Result<Self> {
+        let mut builder = DbBuilder::default().path(path).read_only(read_only);
+        if let Some(n) = threads {
+            builder = builder.increase_parallelism(n);
+        }
+        if !read_only {
+            fs::create_dir_all(path)
+                .map_err(|err| BadDbInit(format!("cannot create directory {}: {}", path, err)))?;
+        }
+        let path_buf = PathBuf::from(path);
+
+        let mut manifest_path = path_buf.clone();
+        manifest_path.push("manifest");
+        let existing_version = if manifest_path.exists() {
+            let existing: DbManifest = rmp_serde::from_slice(
+                &fs::read(&manifest_path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| "when reading manifest")?,
+            )
+            .into_diagnostic()
+            .wrap_err_with(|| "when reading manifest")?;
+            assert!(
+                existing.storage_version <= CURRENT_STORAGE_VERSION,
+                "Unknown storage version {}",
+                existing.storage_version
+            );
+            if read_only && existing.storage_version < CURRENT_STORAGE_VERSION {
+                return Err(BadDbInit(format!(
+                    "the database at {} has to be opened for writing once to upgrade its storage",
+                    path
+                ))
+                .into());
+            }
+            Some(existing.storage_version)
+        } else if read_only {
+            return Err(BadDbInit(format!("no database to open read-only at {}", path)).into());
+        } else {
+            write_manifest(&manifest_path)?;
+            None
+        };
+
+        let mut store_path = path_buf;
+        store_path.push("data");
+        let db_builder = builder
+            .create_if_missing(existing_version.is_none())
+            .use_capped_prefix_extractor(true, KEY_PREFIX_LEN)
+            .use_bloom_filter(true, 9.9, true)
+            .path(
+                store_path
+                    .to_str()
+                    .ok_or_else(|| miette!("bad path name"))?,
+            );
+
+        let storage = Self {
+            db: db_builder.build()?,
+        };
+        if matches!(existing_version, Some(v) if v < CURRENT_STORAGE_VERSION) {
+            storage.upgrade_keys()?;
+            write_manifest(&manifest_path)?;
+        }
+        Ok(storage)
+    }
+    /// Storage version 1 encoded integers of magnitude 2^53 or more in keys after their
+    /// nearest float, which did not sort them among floats and exact numbers. Keys holding
+    /// such integers are re-encoded.
+    fn upgrade_keys(&self) -> Result<()> {
+        let mut tx = self.transact()?;
+        let mut upgraded = vec![];
+        for pair in tx.range_scan(&[], &[u8::MAX; ENCODED_KEY_MIN_LEN + 1]) {
+            let (key, val) = pair?;
+            if let Some(new_key) = Tuple::upgrade_key(&key) {
+                upgraded.push((key, new_key, val));
+            }
+        }
+        for (key, new_key, val) in upgraded {
+            tx.del(&key)?;
+            tx.put(&new_key, &val)?;
+        }
+        tx.commit()
+    }
+}
+
+impl Storage for RocksDbStorage {
+    fn transact(&self) -> Result<Box<dyn StoreTx>> {
+        Ok(Box::new(RocksDbTx {
+            tx: self.db.transact().set_snapshot(true).start(),
+            db: self.db.clone(),
+        }))
+    }
+    fn range_del(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        Ok(self.db.range_del(lower, upper)?)
+    }
+    fn range_compact(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        Ok(self.db.range_compact(lower, upper)?)
+    }
+    fn flush(&self) -> Result<()> {
+        Ok(self.db.flush()?)
+    }
+    fn compaction_stats(&self) -> Option<CompactionStats> {
+        Some(CompactionStats {
+            pending: self.db.int_property("rocksdb.compaction-pending")? != 0,
+            running: self.db.int_property("rocksdb.num-running-compactions")?,
+            pending_bytes: self
+                .db
+                .int_property("rocksdb.estimate-pending-compaction-bytes")?,
+        })
+    }
+}
+
+struct RocksDbTx {
+    tx: Tx,
+    db: RocksDb,
+}
+
+impl RocksDbTx {
+    /// Whether `key` is in a range deleted within the transaction, whose keys are then only
+    /// read from the writes of the transaction
+    fn in_deleted_range(&self, key: &[u8]) -> bool {
+        self.tx
+            .del_ranges()
+            .iter()
+            .any(|(lower, upper)| lower.as_slice() <= key && key < upper.as_slice())
+    }
+    fn get_written(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        if for_update {
+            // for the lock only
+            self.tx.exists(key, true)?;
+        }
+        let mut upper = key.to_vec();
+        upper.push(0);
+        Ok(self.tx.batch_next(key, &upper)?.map(|(_, val)| val))
+    }
+    /// Locks the keys now in the range `lower..upper`, failing with a conflict if any was
+    /// written by another transaction since this one started.
+    fn lock_range(&self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        let latest = self.db.transact().start();
+        let mut it = latest.iterator().upper_bound(upper).start();
+        it.seek(lower);
+        while let Some(key) = it.key()? {
+            self.tx.exists(key, true)?;
+            it.next();
+        }
+        Ok(())
+    }
+}
+
+impl StoreTx for RocksDbTx {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        if self.in_deleted_range(key) {
+            return self.get_written(key, for_update);
+        }
+        Ok(self.tx.get(key, for_update)?.map(|slice| slice.to_vec()))
+    }
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        if self.in_deleted_range(key) {
+            return Ok(self.get_written(key, for_update)?.is_some());
+        }
+        Ok(self.tx.exists(key, for_update)?)
+    }
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        Ok(self.tx.put(key, val)?)
+    }
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        Ok(self.tx.del(key)?)
+    }
+    fn range_scan(&self, lower: &[u8], upper: &[u8]) -> KvIter {
+        let mut inner = self.tx.iterator().upper_bound(upper).start();
+        inner.seek(lower);
+        let del_ranges = self
+            .tx
+            .del_ranges()
+            .iter()
+            .filter(|(l, u)| l.as_slice() < upper && lower < u.as_slice())
+            .cloned()
+            .collect();
+        Box::new(RocksDbIter {
+            inner,
+            started: false,
+            upper_bound: upper.to_vec(),
+            del_ranges,
+        })
+    }
+    /// The range is deleted by RocksDB when the transaction commits, with a range tombstone
+    /// written past the conflict detection of RocksDB. Until then, reads within the
+    /// transaction skip the range except for the keys written to it afterwards.
+    ///
+    /// So that no row written by another transaction is deleted unseen, the keys of the range
+    /// are locked first, and the deletion fails with a conflict if another transaction wrote
+    /// any since this one started. Keys are then only added to the range by transactions
+    /// writing without holding the lock on the entry of the relation in the catalog, which
+    /// Cozo takes before writing rows.
+    fn range_del(&mut self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        if lower < upper {
+            self.lock_range(lower, upper)?;
+            self.tx.del_range_on_commit(lower, upper)?;
+        }
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<()> {
+        Ok(self.tx.commit()?)
+    }
+    fn rollback(&mut self) -> Result<()> {
+        Ok(self.tx.rollback()?)
+    }
+    fn save(&mut self) {
+        self.tx.save()
+    }
+    fn pop_save(&mut self) -> Result<()> {
+        Ok(self.tx.pop_save()?)
+    }
+    fn rollback_to_save(&mut self) -> Result<()> {
+        Ok(self.tx.rollback_to_save()?)
+    }
+}
+
+struct RocksDbIter {
+    inner: DbIter,
+    started: bool,
+    upper_bound: Vec<u8>,
+    /// The ranges deleted within the transaction as the iterator was created
+    del_ranges: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+#[cfg(test)]
+thread_local! {
+    static KEYS_VISITED: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+impl RocksDbIter {
+    fn next_inner(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            if self.started {
+                self.inner.next()
+            } else {
+                self.started = true;
+            }
+            #[cfg(test)]
+            KEYS_VISITED.with(|n| n.set(n.get() + 1));
+            let (key, val) = match self.inner.pair()? {
+                Some((k_slice, v_slice)) if k_slice < self.upper_bound.as_slice() => {
+                    (k_slice.to_vec(), v_slice.to_vec())
+                }
+                _ => return Ok(None),
+            };
+            let deleted_until = self
+                .del_ranges
+                .iter()
+                .filter(|(l, u)| l.as_slice() <= key.as_slice() && key.as_slice() < u.as_slice())
+                .map(|(_, u)| u.as_slice().min(self.upper_bound.as_slice()))
+                .max();
+            let deleted_until = match deleted_until {
+                None => return Ok(Some((key, val))),
+                Some(until) => until.to_vec(),
+            };
+            // within a deleted range, only the keys written afterwards are seen
+            match self.inner.batch_next(&key, &deleted_until)? {
+                Some((written_key, written_val)) => {
+                    self.inner.seek(&written_key);
+                    return Ok(Some((written_key, written_val)));
+                }
+                None => {
+                    self.inner.seek(&deleted_until);
+                    self.started = false;
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for RocksDbIter {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_inner().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::rocks::{RocksDbStorage, KEYS_VISITED};
+    use crate::storage::Storage;
+
+    #[test]
+    fn range_del_visits_no_keys() {
+        let mut path = std::env::temp_dir();
+        path.push("cozo_rocks_range_del");
+        let path = path.to_str().unwrap().to_string();
+        _ = std::fs::remove_dir_all(&path);
+        let storage = RocksDbStorage::open(&path, None).unwrap();
+        let key = |i: u32| {
+            let mut key = vec![0, 0, 0, 0, 0, 0, 0, 1];
+            key.extend_from_slice(&i.to_be_bytes());
+            key
+        };
+
+        let mut tx = storage.transact().unwrap();
+        for i in 0..10_000 {
+            tx.put(&key(i), b"").unwrap();
+        }
+        tx.commit().unwrap();
+
+        KEYS_VISITED.with(|n| n.set(0));
+        let mut tx = storage.transact().unwrap();
+        tx.range_del(&key(1), &key(9_999)).unwrap();
+        tx.put(&key(5_000), b"kept").unwrap();
+        tx.commit().unwrap();
+        assert_eq!(KEYS_VISITED.with(|n| n.get()), 0);
+
+        let tx = storage.transact().unwrap();
+        let remaining = tx
+            .range_scan(&key(0), &key(10_000))
+            .map(|pair| pair.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, vec![key(0), key(5_000), key(9_999)]);
+        assert_eq!(tx.get(&key(5_000), false).unwrap().unwrap(), b"kept");
+        drop(tx);
+        drop(storage);
+        _ = std::fs::remove_dir_all(&path);
+    }
+}
@@ -10,3 +10,40 @@ pub(crate) fn swap_option_result<T, E>(d: Result<Option<T>, E>) -> Option<Result
         Err(e) => Some(Err(e)),
     }
 }
+
+/// Placeholder returned by [`enter_span!`] when the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoopSpan;
+
+/// Enters a `tracing` span that lasts until the returned guard is dropped.
+#[cfg(feature = "tracing")]
+macro_rules! enter_span {
+    ($($arg:tt)+) => {
+        tracing::info_span!($($arg)+).entered()
+    };
+}
+
+/// Does nothing, since the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+macro_rules! enter_span {
+    ($($arg:tt)+) => {
+        $crate::utils::NoopSpan
+    };
+}
+
+/// Emits a `tracing` event at the debug level.
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+    ($($arg:tt)+) => {
+        tracing::debug!($($arg)+)
+    };
+}
+
+/// Does nothing, since the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+    ($($arg:tt)+) => {};
+}
+
+pub(crate) use enter_span;
+pub(crate) use trace_event;
@@ -2,15 +2,23 @@
  * Copyright 2022, The Cozo Project Authors. Licensed under AGPL-3 or later.
  */
 
+use std::collections::BTreeMap;
 use std::str::FromStr;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use approx::AbsDiffEq;
 use env_logger::Env;
 use lazy_static::lazy_static;
 use serde_json::json;
 
-use cozo::Db;
+use cozo::storage::{check_storage_compliance, MemStorage, RocksDbStorage, Storage};
+use cozo::{
+    quote_identifier, quote_string, register_aggregation, AccessPolicy, AlgoCall, CsvImportOptions,
+    CustomAlgo, CustomAlgoInput, CustomAlgoOutput, Db, DbOptions, ExportFormat, NamespaceQuota,
+    QueryAtom, QueryBuilder, QueryDiff, QueryExpr, QueryRule, RelationAccess, RemoteDb, RowChange,
+    UserAggregation, UserNormalAggregation,
+};
 
 lazy_static! {
     static ref TEST_DB: Db = {
@@ -150,7 +158,7 @@ fn dfs() {
         .unwrap();
     let rows = res.get("rows").unwrap().as_array().unwrap();
     assert_eq!(rows.len(), 1);
-    let row = rows.get(0).unwrap();
+    let row = rows.first().unwrap();
     assert_eq!(row.get(0).unwrap().as_str().unwrap(), "PEK");
     assert_eq!(row.get(1).unwrap().as_str().unwrap(), "LHR");
     let path = row.get(2).unwrap().as_array().unwrap();
@@ -186,7 +194,7 @@ fn bfs() {
         .unwrap();
     let rows = res.get("rows").unwrap().as_array().unwrap();
     assert_eq!(rows.len(), 1);
-    let row = rows.get(0).unwrap();
+    let row = rows.first().unwrap();
     assert_eq!(row.get(0).unwrap().as_str().unwrap(), "PEK");
     assert_eq!(row.get(1).unwrap().as_str().unwrap(), "LHR");
     let path = row.get(2).unwrap().as_array().unwrap();
@@ -1929,3 +1937,4616 @@ fn skip_limit() {
     let rows = res.get("rows").unwrap();
     assert_eq!(*rows, json!([[3], [4], [5], [6], [7], [8]]));
 }
+
+#[test]
+fn transpile_to_sql() {
+    check_db();
+    let sql = TEST_DB
+        .transpile_to_sql(
+            r#"
+        ?[code, city] := *airport{code, city, country: 'UK'}
+        :order city
+        :limit 5
+    "#,
+            &Default::default(),
+            "postgres",
+        )
+        .unwrap();
+    assert!(sql.starts_with("WITH "));
+    assert!(sql.contains("FROM \"airport\" AS t1"));
+    assert!(sql.contains("'UK'"));
+    assert!(sql.ends_with("ORDER BY c1 LIMIT 5"));
+
+    let res = TEST_DB.transpile_to_sql(
+        r#"
+        reachable[a, b] := *route{fr: a, to: b}
+        reachable[a, b] := reachable[a, c], *route{fr: c, to: b}
+        ?[b] := reachable['LHR', b]
+    "#,
+        &Default::default(),
+        "sqlite",
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn transpile_integer_division() {
+    check_db();
+    let script = r#"
+        ?[code, half, rem] := *airport{code, runways}, half = runways / 2, rem = runways % 3
+    "#;
+    // 'runways' is an integer column, whose quotient Cozo does not truncate
+    let res = TEST_DB
+        .run_script(
+            &format!("{} :order code :limit 1", script),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["AAA", 0.5, 1]]));
+    let transpile = |dialect: &str| {
+        TEST_DB
+            .transpile_to_sql(script, &Default::default(), dialect)
+            .unwrap()
+    };
+    let sql = transpile("postgres");
+    assert!(sql.contains("(CAST(t1.\"runways\" AS DOUBLE PRECISION) / 2)"));
+    assert!(sql.contains("(t1.\"runways\" - 3 * TRUNC(t1.\"runways\" / 3))"));
+    let sql = transpile("sqlite");
+    assert!(sql.contains("(CAST(t1.\"runways\" AS REAL) / 2)"));
+    assert!(sql.contains("(t1.\"runways\" - 3 * CAST(t1.\"runways\" / 3 AS INTEGER))"));
+    let sql = transpile("mysql");
+    assert!(sql.contains("(t1.`runways` / 2)"));
+    assert!(sql.contains("(t1.`runways` % 3)"));
+}
+
+#[test]
+fn quoted_identifiers() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[code, name] := *airport{code, city: name}, code == 'LHR'
+        :replace `airport names` { `iata code`: String = code => `city name`: String = name }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script(
+            "?[c] := *`airport names`{`city name`: c}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["London"]]));
+    let res = TEST_DB
+        .run_script("::columns `airport names`", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"][0][0], json!("iata code"));
+    TEST_DB
+        .run_script("::remove `airport names`", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn script_consts() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        ?[x, y] <- [[$a, $b]]
+        const a = 1 + 2
+        const b = $a * 2
+        :limit $b
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[3, 6]]));
+
+    let res = TEST_DB.run_script(
+        r#"
+        ?[x] <- [[$a]]
+        const a = 1
+        const a = 2
+    "#,
+        &Default::default(),
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn constant_folding() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            "?[code] := *airport{code}, code == 'LHR', 1 + 1 == 2, true || code == 'x'",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["LHR"]]));
+
+    let res = TEST_DB
+        .run_script(
+            "?[code] := *airport{code}, code == 'LHR', 1 > 2",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([]));
+
+    let res = TEST_DB
+        .run_script(
+            r#"
+        r[a] := a = 1, false
+        r[a] := a = if(1 > 2, 3, 2)
+        ?[a] := r[a]
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[2]]));
+}
+
+#[test]
+fn common_subexpressions() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        ?[code, n] := *airport{code, city},
+                      code == 'LHR',
+                      length(lowercase(city)) > 5,
+                      length(lowercase(city)) < 7,
+                      n = length(lowercase(city)) + 1
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["LHR", 7]]));
+
+    // the shared expression is not evaluated before the predicate guarding it
+    let db = Db::new_with_storage(
+        Arc::new(MemStorage::new()),
+        DbOptions {
+            reorder_predicates: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            r#"
+        nums[x, y] <- [[7, 0], [7, 3], [9, 5]]
+        ?[x, y] := nums[x, y], y != 0, mod(x, y) > 1, mod(x, y) < 5
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[9, 5]]));
+}
+
+#[test]
+fn predicate_reordering() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            "?[code] := *airport{code, city}, regex_matches(city, '^Lon'), is_string(city), code == 'LHR'",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["LHR"]]));
+
+    // the cheaper 'mod' is not moved ahead of the 'is_in' guarding it
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let res = db
+        .run_script(
+            r#"
+        nums[x, y] <- [[4, 0], [4, 2], [3, 1], [5, 2]]
+        ?[x, y] := nums[x, y], is_in(y, [1, 2]), mod(x, y) == 0
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[3, 1], [4, 2]]));
+}
+
+#[test]
+fn plan_pinning() {
+    check_db();
+    let script = "?[code] := *airport{code, city}, city == 'London', code == 'LHR'";
+    TEST_DB.run_script(script, &Default::default()).unwrap();
+    let listed = TEST_DB
+        .run_script("::plan list", &Default::default())
+        .unwrap();
+    let hash = listed["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|row| row[2] == json!(script))
+        .unwrap()[0]
+        .as_str()
+        .unwrap()
+        .to_string();
+    TEST_DB
+        .run_script(&format!("::plan pin {}", hash), &Default::default())
+        .unwrap();
+    let res = TEST_DB.run_script(script, &Default::default()).unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["LHR"]]));
+    TEST_DB
+        .run_script(&format!("::plan unpin {}", hash), &Default::default())
+        .unwrap();
+    assert!(TEST_DB
+        .run_script(&format!("::plan unpin {}", hash), &Default::default())
+        .is_err());
+}
+
+#[test]
+fn named_inline_rows() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        rows[a, b] <- [{a: 1, b: 'x'}, {b: 'y', a: 2}, {a: 3}]
+        ?[a, b] := rows[a, b]
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, "x"], [2, "y"], [3, null]])
+    );
+    assert!(TEST_DB
+        .run_script("?[a] <- [{a: 1, c: 2}]", &Default::default())
+        .is_err());
+}
+
+#[test]
+fn null_tests() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        data[a, b] <- [[1, null], [2, 'x'], [null, 'y']]
+        ?[a, n, nn, eq] := data[a, b], n = b is null, nn = a is not null, eq = a <=> null
+        :order a
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            [null, false, false, true],
+            [1, true, true, false],
+            [2, false, true, false]
+        ])
+    );
+    let res = TEST_DB
+        .run_script(
+            r#"
+        data[a, b] <- [[1, null], [2, 'x']]
+        ?[a] := data[a, b], b is not null
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[2]]));
+}
+
+#[test]
+fn error_tolerant_evaluation() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        data[a] <- [[1], ['x'], [3]]
+        ?[a, b] := data[a], b = a + 1
+        :on_error null
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, 2], [3, 4], ["x", null]])
+    );
+    assert_eq!(*res.get("tolerated_errors").unwrap(), json!(1));
+    let res = TEST_DB
+        .run_script(
+            r#"
+        data[a] <- [[1], ['x'], [3]]
+        ?[a] := data[a], a + 1 > 2
+        :on_error null
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[3]]));
+    assert_eq!(*res.get("tolerated_errors").unwrap(), json!(1));
+    assert!(TEST_DB
+        .run_script(
+            r#"
+        data[a] <- [[1], ['x'], [3]]
+        ?[a] := data[a], a + 1 > 2
+    "#,
+            &Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn overflow_policy() {
+    check_db();
+    let script = r#"
+        data[a] <- [[9223372036854775807], [1]]
+        ?[a, b] := data[a], b = a + 1
+    "#;
+    assert!(TEST_DB.run_script(script, &Default::default()).is_err());
+    let res = TEST_DB
+        .run_script(&format!("{}\n:overflow null", script), &Default::default())
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, 2], [9223372036854775807i64, null]])
+    );
+    let res = TEST_DB
+        .run_script(
+            &format!("{}\n:overflow saturate", script),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, 2], [9223372036854775807i64, 9223372036854775807i64]])
+    );
+}
+
+#[test]
+fn exact_numbers() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[k, amount] <- [['a', '0.10'], ['b', '0.20'], ['c', 1.05]]
+        :replace ledger { k: String => amount: Decimal }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script("?[sum(amount)] := *ledger{amount}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["1.35"]]));
+    TEST_DB
+        .run_script(
+            r#"
+        ?[p] <- [['10'], ['2.5'], [-1]]
+        :replace decimal_keys { p: Decimal }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script("?[p] := *decimal_keys{p}, p > 0", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["2.5"], ["10"]]));
+    let res = TEST_DB
+        .run_script(
+            "?[x] := x = to_bigint('9223372036854775807') + 1",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["9223372036854775808"]]));
+    TEST_DB
+        .run_script("::remove ledger, decimal_keys", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn bellman_ford() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        edges[] <- [['a', 'b', 4], ['a', 'c', 2], ['c', 'b', -1], ['b', 'd', 1]]
+        start[] <- [['a']]
+        end[] <- [['d']]
+        ?[] <~ BellmanFord(edges[], start[], end[])
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", "d", 2.0, ["a", "c", "b", "d"]]])
+    );
+    let cyclic = r#"
+        edges[] <- [['a', 'b', 4], ['a', 'c', 2], ['c', 'b', -1], ['b', 'd', 1], ['d', 'b', -3]]
+        start[] <- [['a']]
+    "#;
+    assert!(TEST_DB
+        .run_script(
+            &format!("{}?[] <~ BellmanFord(edges[], start[])", cyclic),
+            &Default::default()
+        )
+        .is_err());
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}res[] <~ BellmanFord(edges[], start[], negative_cycle: 'flag')
+                ?[t, f] := res[_, t, _, _, f]",
+                cyclic
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", false], ["b", true], ["c", false], ["d", true]])
+    );
+}
+
+#[test]
+fn floyd_warshall() {
+    check_db();
+    let edges = "edges[] <- [['a', 'b', 1], ['b', 'c', 1], ['a', 'c', 2], ['c', 'a', -1]]";
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}
+                res[] <~ FloydWarshall(edges[])
+                ?[from, to, cost, path] := res[from, to, cost, path], from = 'a'",
+                edges
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            ["a", "a", 0.0, ["a"]],
+            ["a", "b", 1.0, ["a", "b"]],
+            ["a", "c", 2.0, ["a", "c"]]
+        ])
+    );
+    assert!(TEST_DB
+        .run_script(
+            &format!("{}\n?[] <~ FloydWarshall(edges[], max_nodes: 2)", edges),
+            &Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn eccentricity() {
+    check_db();
+    let edges = "edges[] <- [['a', 'b', 1], ['b', 'c', 2], ['c', 'd', 1]]";
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}
+                ?[node, ecc] <~ Eccentricity(edges[], undirected: true)",
+                edges
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 4.0], ["b", 3.0], ["c", 3.0], ["d", 4.0]])
+    );
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}
+                ?[diameter, radius] <~ Eccentricity(edges[], summary: true)",
+                edges
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[4.0, 0.0]]));
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}
+                ?[node, ecc] <~ Eccentricity(edges[], undirected: true, samples: 2, seed: 1)",
+                edges
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    for row in res.get("rows").unwrap().as_array().unwrap() {
+        let ecc = row[1].as_f64().unwrap();
+        assert!((0.0..=4.0).contains(&ecc));
+    }
+}
+
+#[test]
+fn maximum_matching() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+            edges[] <- [['a', 'x'], ['a', 'y'], ['b', 'x'], ['c', 'x'], ['d', 'z']]
+            ?[worker, task] <~ MaximumMatching(edges[])
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let rows = res.get("rows").unwrap().as_array().unwrap();
+    assert_eq!(rows.len(), 3);
+    assert!(rows.contains(&json!(["a", "y"])));
+    assert!(rows.contains(&json!(["d", "z"])));
+}
+
+#[test]
+fn triangle_count() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+            edges[] <- [['a', 'b'], ['b', 'c'], ['c', 'd'], ['d', 'a'], ['a', 'c']]
+            ?[triangles, transitivity, average] <~ TriangleCount(edges[])
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let row = res.get("rows").unwrap().get(0).unwrap();
+    assert_eq!(row[0], json!(2));
+    assert_eq!(row[1], json!(0.75));
+    assert!(row[2].as_f64().unwrap().abs_diff_eq(&(5. / 6.), 1e-9));
+}
+
+#[test]
+fn node2vec_walks() {
+    check_db();
+    let script = r#"
+        edges[] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['c', 'd']]
+        ?[walk, pos, node] <~ Node2Vec(edges[], walk_length: 5, walks_per_node: 3,
+                                       p: 0.5, q: 2, undirected: true, seed: 42)
+    "#;
+    let res = TEST_DB.run_script(script, &Default::default()).unwrap();
+    let rows = res.get("rows").unwrap().as_array().unwrap();
+    assert_eq!(rows.len(), 4 * 3 * 5);
+    for row in rows {
+        let pos = row[1].as_i64().unwrap();
+        assert!((0..5).contains(&pos));
+        assert!(["a", "b", "c", "d"].contains(&row[2].as_str().unwrap()));
+    }
+    // consecutive nodes of a walk are adjacent
+    for pair in rows.windows(2) {
+        if pair[0][0] == pair[1][0] {
+            let step = [pair[0][2].as_str().unwrap(), pair[1][2].as_str().unwrap()];
+            assert!(!matches!(
+                step,
+                ["a", "d"] | ["d", "a"] | ["b", "d"] | ["d", "b"]
+            ));
+            assert_ne!(step[0], step[1]);
+        }
+    }
+    let again = TEST_DB.run_script(script, &Default::default()).unwrap();
+    assert_eq!(res.get("rows"), again.get("rows"));
+}
+
+#[test]
+fn astar_builtin_heuristics() {
+    check_db();
+    let data = r#"
+        edges[] <- [['a', 'b', 1], ['b', 'c', 1], ['a', 'c', 3]]
+        nodes[] <- [['a', 0, 0], ['b', 1, 0], ['c', 1, 1]]
+        start[] <- [['a', 0, 0]]
+        goal[] <- [['c', 1, 1]]
+    "#;
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ ShortestPathAStar(edges[], nodes[n, x, y], start[], goal[g, gx, gy], \
+                 heuristic: 'euclidean', validate_heuristic: true)",
+                data
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", "c", 2.0, ["a", "b", "c"]]])
+    );
+    assert!(TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ ShortestPathAStar(edges[], nodes[n, x, y], start[], goal[g, gx, gy], \
+                 heuristic: 10, validate_heuristic: true)",
+                data
+            ),
+            &Default::default(),
+        )
+        .is_err());
+
+    // on a sphere of radius one, the heuristic never exceeds the cost of the edges
+    let data = r#"
+        edges[] <- [['a', 'b', 1], ['b', 'c', 1], ['a', 'c', 3]]
+        nodes[] <- [['a', 0, 0], ['b', 0, 1], ['c', 0, 2]]
+        start[] <- [['a', 0, 0]]
+        goal[] <- [['c', 0, 2]]
+    "#;
+    let haversine = |radius: &str| {
+        TEST_DB.run_script(
+            &format!(
+                "{}?[] <~ ShortestPathAStar(edges[], nodes[n, lat, lon], start[], \
+                 goal[g, glat, glon], heuristic: 'haversine', {}validate_heuristic: true)",
+                data, radius
+            ),
+            &Default::default(),
+        )
+    };
+    let res = haversine("radius: 1, ").unwrap();
+    assert_eq!(res["rows"], json!([["a", "c", 2.0, ["a", "b", "c"]]]));
+    // on the earth, it does
+    assert!(haversine("").is_err());
+    assert!(haversine("radius: -1, ").is_err());
+}
+
+#[test]
+fn astar_packed_keys() {
+    check_db();
+    let data = r#"
+        edges[] <- [[[0, 0], [1, 0], 1], [[1, 0], [1, 1], 1], [[0, 0], [1, 1], 3]]
+        start[] <- [[[0, 0]]]
+        goal[] <- [[[1, 1]]]
+    "#;
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ ShortestPathAStar(edges[], start[], goal[], \
+                 heuristic: 'euclidean', validate_heuristic: true)",
+                data
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[[0, 0], [1, 1], 2.0, [[0, 0], [1, 0], [1, 1]]]])
+    );
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ ShortestPathAStar(edges[n, m, c], start[], goal[g], \
+                 heuristic: abs(get(n, 0) - get(g, 0)) + abs(get(n, 1) - get(g, 1)), \
+                 validate_heuristic: true)",
+                data
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[[0, 0], [1, 1], 2.0, [[0, 0], [1, 0], [1, 1]]]])
+    );
+}
+
+#[test]
+fn yen_small_graph() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        edges[] <- [['a', 'b', 1], ['b', 'd', 1], ['a', 'c', 1], ['c', 'd', 2], ['a', 'd', 5]]
+        start[] <- [['a']]
+        end[] <- [['d']]
+        res[] <~ KShortestPathYen(edges[], start[], end[], k: 5)
+        ?[cost, path] := res[_, _, cost, path]
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            [2.0, ["a", "b", "d"]],
+            [3.0, ["a", "c", "d"]],
+            [5.0, ["a", "d"]]
+        ])
+    );
+}
+
+#[test]
+fn yen_cost_cutoff() {
+    check_db();
+    let data = r#"
+        edges[] <- [['a', 'b', 1], ['b', 'd', 1], ['a', 'c', 1], ['c', 'd', 2], ['a', 'd', 5]]
+        start[] <- [['a']]
+        end[] <- [['d']]
+    "#;
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}res[] <~ KShortestPathYen(edges[], start[], end[], tolerance: 0.5)
+                ?[cost] := res[_, _, cost, _]",
+                data
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[2.0], [3.0]]));
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}res[] <~ KShortestPathYen(edges[], start[], end[], k: 1, max_cost: 10)
+                ?[cost] := res[_, _, cost, _]",
+                data
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[2.0]]));
+}
+
+#[test]
+fn cascade_simulation() {
+    check_db();
+    let data = r#"
+        edges[] <- [['a', 'b', 1], ['b', 'c', 1], ['a', 'd', 1], ['e', 'c', 0.3]]
+        seeds[] <- [['a']]
+    "#;
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ CascadeSimulation(edges[], seeds[], seed: 42)",
+                data
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 0], ["b", 1], ["c", 2], ["d", 1]])
+    );
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ CascadeSimulation(edges[], seeds[], rounds: 1, \
+                 model: 'linear_threshold', threshold: 0.5)",
+                data
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 0], ["b", 1], ["d", 1]])
+    );
+}
+
+#[test]
+fn graph_diff() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        old[] <- [['a', 'b', 1], ['b', 'c', 2], ['c', 'd', 3]]
+        new[] <- [['a', 'b', 1], ['b', 'c', 5], ['a', 'e', 1]]
+        ?[] <~ GraphDiff(old[], new[])
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            ["edge_added", "a", "e", null, 1],
+            ["edge_removed", "c", "d", 3, null],
+            ["node_added", "e", null, null, null],
+            ["node_removed", "d", null, null, null],
+            ["weight_changed", "b", "c", 2, 5]
+        ])
+    );
+}
+
+#[test]
+fn clone_relation() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[k, v] <- [[1, 'a'], [2, 'b']]
+        :replace clone_src { k: Int => v: String }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script(
+            "::relation clone clone_src -> clone_dst",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["OK", 2]]));
+    TEST_DB
+        .run_script(
+            "?[k, v] <- [[3, 'c']] :put clone_dst { k => v }",
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script("?[k, v] := *clone_dst{k, v}", &Default::default())
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, "a"], [2, "b"], [3, "c"]])
+    );
+    let res = TEST_DB
+        .run_script("?[count(k)] := *clone_src{k}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[2]]));
+    assert!(TEST_DB
+        .run_script(
+            "::relation clone clone_src -> clone_dst",
+            &Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn delete_range() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[t, id, v] <- [[1, 1, 'a'], [2, 1, 'b'], [2, 2, 'c'], [3, 1, 'd'], [4, 1, 'e']]
+        :replace range_del_test { t: Int, id: Int => v: String }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    TEST_DB
+        .run_script(
+            "::relation delete_range range_del_test from [2] to [4]",
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script("?[v] := *range_del_test{v}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["a"], ["e"]]));
+    TEST_DB
+        .run_script(
+            "::relation delete_range range_del_test from 4",
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script("?[v] := *range_del_test{v}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["a"]]));
+    assert!(TEST_DB
+        .run_script(
+            "::relation delete_range range_del_test from [1, 1, 1]",
+            &Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn query_cursor() {
+    check_db();
+    let cursor = TEST_DB
+        .run_query_cursor(
+            "?[code] := *airport{code}, starts_with(code, 'AA') :order -code :limit 2",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(cursor.headers(), ["code"]);
+    let rows: Vec<_> = cursor.map(Result::unwrap).collect();
+    assert_eq!(json!(rows), json!([["AAY"], ["AAX"]]));
+
+    let mut cursor = TEST_DB
+        .run_query_cursor("?[code] := *airport{code}", &Default::default())
+        .unwrap();
+    assert_eq!(cursor.next().unwrap().unwrap(), vec![json!("AAA")]);
+    assert_eq!(cursor.count(), 3503);
+
+    assert!(TEST_DB
+        .run_query_cursor("?[a] <- [[1]] :replace cursor_out {a}", &Default::default())
+        .is_err());
+
+    // rows are derived as they are pulled, so an error in a later row comes after the
+    // earlier rows, and a limit stops the evaluation before it is reached
+    let script = "?[b] := a in [1, 2, 2, 'x'], b = a + 1";
+    let mut cursor = TEST_DB
+        .run_query_cursor(script, &Default::default())
+        .unwrap();
+    assert_eq!(cursor.next().unwrap().unwrap(), vec![json!(2)]);
+    assert_eq!(cursor.next().unwrap().unwrap(), vec![json!(3)]);
+    assert!(cursor.next().unwrap().is_err());
+    assert!(TEST_DB.run_script(script, &Default::default()).is_err());
+    let rows: Vec<_> = TEST_DB
+        .run_query_cursor(&format!("{} :limit 2", script), &Default::default())
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(json!(rows), json!([[2], [3]]));
+
+    // the query is running until the cursor is exhausted, so closing waits for it, and
+    // kills it once timed out
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let mut cursor = db
+        .run_query_cursor("?[a] := a in int_range(10)", &Default::default())
+        .unwrap();
+    assert_eq!(cursor.next().unwrap().unwrap(), vec![json!(0)]);
+    assert!(db.close_gracefully(Duration::from_millis(50)).is_err());
+    assert!(cursor.any(|row| row.is_err()));
+}
+
+#[test]
+fn close_gracefully() {
+    let start_sleeping = |db: &Db, secs: f64| {
+        let db = db.clone();
+        let script = format!("?[a] <- [[1]] :sleep {}", secs);
+        let handle = std::thread::spawn(move || db.run_script(&script, &Default::default()));
+        // let the script be admitted before closing
+        std::thread::sleep(Duration::from_millis(50));
+        handle
+    };
+
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let in_flight = start_sleeping(&db, 0.2);
+    db.close_gracefully(Duration::from_secs(10)).unwrap();
+    let res = in_flight.join().unwrap().unwrap();
+    assert_eq!(res["rows"], json!([[1]]));
+    let err = db
+        .run_script("?[a] <- [[1]]", &Default::default())
+        .unwrap_err();
+    assert!(err.to_string().contains("shutting down"));
+
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let in_flight = start_sleeping(&db, 2.);
+    let started = Instant::now();
+    let err = db.close_gracefully(Duration::from_millis(100)).unwrap_err();
+    assert!(err.to_string().contains("Timed out"), "{}", err);
+    assert!(started.elapsed() < Duration::from_secs(1));
+    in_flight.join().unwrap().unwrap();
+
+    // an open transaction is in flight until it is committed
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let mut tx = db.multi_transact().unwrap();
+    tx.run_script("?[a] <- [[1]] :create t {a}", &Default::default())
+        .unwrap();
+    assert!(db.close_gracefully(Duration::from_millis(50)).is_err());
+    tx.commit().unwrap();
+    db.close_gracefully(Duration::from_millis(50)).unwrap();
+}
+
+#[test]
+fn truncate_relation() {
+    fn check(db: &Db) {
+        let run = |script: &str| db.run_script(script, &Default::default());
+        run(r#"
+            ?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']]
+            :replace truncate_test { k: Int => v: String }
+        "#)
+        .unwrap();
+        run(r#"
+            ?[k, v] <- [[1, 'x']]
+            :replace truncate_next { k: Int => v: String }
+        "#)
+        .unwrap();
+        run("::set_triggers truncate_test on put { ?[k] := _new[k, v] }").unwrap();
+        run("::access_level protected truncate_test").unwrap();
+        run("::relation truncate truncate_test").unwrap();
+        let res = run("?[k, v] := *truncate_test{k, v}").unwrap();
+        assert_eq!(*res.get("rows").unwrap(), json!([]));
+        let res = run("::show_triggers truncate_test").unwrap();
+        assert_eq!(res.get("rows").unwrap().as_array().unwrap().len(), 1);
+        // still protected
+        assert!(run("::remove truncate_test").is_err());
+        // the rows of the relation created next are untouched
+        let res = run("?[k, v] := *truncate_next{k, v}").unwrap();
+        assert_eq!(*res.get("rows").unwrap(), json!([[1, "x"]]));
+        run("?[k, v] <- [[4, 'd']] :put truncate_test { k => v }").unwrap();
+        let res = run("?[k, v] := *truncate_test{k, v}").unwrap();
+        assert_eq!(*res.get("rows").unwrap(), json!([[4, "d"]]));
+    }
+
+    check(&Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap());
+
+    let path = "_test_truncate_relation";
+    _ = std::fs::remove_dir_all(path);
+    let db = Db::new(path).unwrap();
+    check(&db);
+    drop(db);
+    _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn row_guardrails() {
+    check_db();
+    let res = TEST_DB.run_script(
+        "?[count(code)] := *airport{code} :max_rows_scanned 100",
+        &Default::default(),
+    );
+    assert!(res.is_err());
+    let res = TEST_DB
+        .run_script(
+            "?[count(code)] := *airport{code} :max_rows_scanned 10000",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[3504]]));
+    let res = TEST_DB.run_script(
+        "?[a, b] := *airport{code: a}, *airport{code: b} :max_intermediate_rows 100000",
+        &Default::default(),
+    );
+    assert!(res.is_err());
+}
+
+#[test]
+fn user_aggregations() {
+    struct Concat {
+        sep: String,
+        parts: Vec<String>,
+    }
+
+    impl UserNormalAggregation for Concat {
+        fn set(&mut self, value: &serde_json::Value) -> miette::Result<()> {
+            self.parts
+                .push(value.as_str().unwrap_or_default().to_string());
+            Ok(())
+        }
+        fn get(&self) -> miette::Result<serde_json::Value> {
+            Ok(json!(self.parts.join(&self.sep)))
+        }
+    }
+
+    check_db();
+    register_aggregation(
+        "test_concat",
+        UserAggregation::Normal(Arc::new(
+            |args: &[serde_json::Value]| -> miette::Result<Box<dyn UserNormalAggregation>> {
+                let sep = args.first().and_then(|v| v.as_str()).unwrap_or(",");
+                Ok(Box::new(Concat {
+                    sep: sep.to_string(),
+                    parts: vec![],
+                }))
+            },
+        )),
+    )
+    .unwrap();
+    register_aggregation(
+        "test_min_int",
+        UserAggregation::Meet(Arc::new(
+            |left: &mut serde_json::Value, right: &serde_json::Value| -> miette::Result<bool> {
+                Ok(if right.as_i64() < left.as_i64() {
+                    *left = right.clone();
+                    true
+                } else {
+                    false
+                })
+            },
+        )),
+    )
+    .unwrap();
+    let noop =
+        |_: &mut serde_json::Value, _: &serde_json::Value| -> miette::Result<bool> { Ok(false) };
+    assert!(register_aggregation("count", UserAggregation::Meet(Arc::new(noop))).is_err());
+
+    let res = TEST_DB
+        .run_script(
+            r#"
+        ?[test_concat(code, '|')] := *airport{code}, starts_with(code, 'AA'), code < 'AAN'
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["AAA|AAE|AAL"]]));
+
+    let res = TEST_DB
+        .run_script(
+            r#"
+        hops[code, test_min_int(n)] := code = 'AUS', n = 0
+        hops[code, test_min_int(n)] := hops[prev, m], *route{fr: prev, to: code}, m < 2, n = m + 1
+        ?[n] := hops['LHR', n]
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[1]]));
+}
+
+#[test]
+fn custom_algo() {
+    struct RowCounts;
+
+    impl CustomAlgo for RowCounts {
+        fn arity(
+            &self,
+            _options: &BTreeMap<String, serde_json::Value>,
+            _rule_head: &[String],
+        ) -> miette::Result<usize> {
+            Ok(2)
+        }
+        fn run(
+            &self,
+            input: &CustomAlgoInput<'_>,
+            out: &mut CustomAlgoOutput<'_>,
+        ) -> miette::Result<()> {
+            let scale = input
+                .options()
+                .get("scale")
+                .and_then(|v| v.as_i64())
+                .unwrap_or(1);
+            for i in 0..input.relations_count() {
+                let mut count = 0;
+                for row in input.rows(i)? {
+                    assert_eq!(row?.len(), input.arity(i)?);
+                    count += 1;
+                }
+                input.check_killed()?;
+                out.put(vec![json!(i), json!(count * scale)])?;
+            }
+            Ok(())
+        }
+    }
+
+    let path = "_test_custom_algo";
+    _ = std::fs::remove_dir_all(path);
+    let algo: Arc<dyn CustomAlgo> = Arc::new(RowCounts);
+    let options = DbOptions {
+        custom_algos: BTreeMap::from([("TestRowCounts".to_string(), algo)]),
+        ..Default::default()
+    };
+    let db = Db::new_with_options(path, options.clone()).unwrap();
+    let script = r#"
+        a[x] <- [[1], [2], [3]]
+        b[x] <- [[1]]
+        ?[i, n] <~ TestRowCounts(a[], b[], scale: 10)
+    "#;
+    let res = db.run_script(script, &Default::default()).unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[0, 30], [1, 10]]));
+    db.run_script(
+        "?[x, y] <- [[1, 2], [3, 4]] :create pairs {x, y}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[i, n] <~ TestRowCounts(pairs[])", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[0, 2]]));
+    let other = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    assert!(other.run_script(script, &Default::default()).is_err());
+    drop(db);
+    let db = Db::new_with_options(path, options).unwrap();
+    drop(db);
+    _ = std::fs::remove_dir_all(path);
+
+    let conflicting: Arc<dyn CustomAlgo> = Arc::new(RowCounts);
+    let options = DbOptions {
+        custom_algos: BTreeMap::from([("PageRank".to_string(), conflicting)]),
+        ..Default::default()
+    };
+    assert!(Db::new_with_options("_test_custom_algo_conflict", options).is_err());
+}
+
+#[test]
+fn cross_product_warnings() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        a[x] <- [[1], [2]]
+        b[y] <- [[3]]
+        ?[x, y] := a[x], b[y]
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[1, 3], [2, 3]]));
+    assert_eq!(res["warnings"].as_array().unwrap().len(), 1);
+
+    let res = TEST_DB
+        .run_script(
+            r#"
+        a[x] <- [[1], [2]]
+        b[y] <- [[2]]
+        ?[x, y] := a[x], y = x, b[y]
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[2, 2]]));
+    assert!(res.get("warnings").is_none());
+
+    assert!(TEST_DB
+        .run_script(
+            r#"
+        a[x] <- [[1], [2]]
+        b[y] <- [[3]]
+        ?[x, y] := a[x], b[y]
+        :strict
+    "#,
+            &Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn personalized_pagerank() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+        edges[] <- [['a', 'b'], ['b', 'a'], ['c', 'd'], ['d', 'c']]
+        sources[] <- [['a']]
+        ?[] <~ PersonalizedPageRank(edges[], sources[], iterations: 200, epsilon: 0.000001)
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let rows = res.get("rows").unwrap().as_array().unwrap();
+    let scores: Vec<f64> = rows.iter().map(|r| r[1].as_f64().unwrap()).collect();
+    assert_eq!(rows[0][0], json!("a"));
+    assert!((scores[0] - 0.2 / 0.36).abs() < 0.001);
+    assert!((scores[1] - 0.16 / 0.36).abs() < 0.001);
+    assert_eq!(scores[2], 0.0);
+    assert_eq!(scores[3], 0.0);
+}
+
+#[test]
+fn store_head_checks() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[k, v] <- [['a', 'x']]
+        :replace head_check { k: String => v: String }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    for script in [
+        "?[k, v] <- [['b', 'y'], ['c', 1]] :put head_check { k => v }",
+        "?[k, v] := *airport{code: k, runways: v} :put head_check { k => v }",
+        "?[k, w] <- [['b', 'y']] :put head_check { k => v }",
+    ] {
+        assert!(TEST_DB.run_script(script, &Default::default()).is_err());
+    }
+    let res = TEST_DB
+        .run_script("?[k, v] := *head_check[k, v]", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["a", "x"]]));
+}
+
+#[test]
+fn named_put_columns() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[a, b] <- [[1, 'one']]
+        :replace named_put { a: Int => b: String }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    TEST_DB
+        .run_script(
+            "?[x, y] <- [[2, 'two']] :put named_put { b: y, a: x }",
+            &Default::default(),
+        )
+        .unwrap();
+    TEST_DB
+        .run_script(
+            "?[x, y] <- [[3, 'three']] :put named_put { a: x => b: y }",
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script("?[a, b] := *named_put[a, b]", &Default::default())
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, "one"], [2, "two"], [3, "three"]])
+    );
+}
+
+#[test]
+fn random_walk_restart() {
+    check_db();
+    let graph = r#"
+        edges[] <- [['a', 'b'], ['b', 'c'], ['c', 'a']]
+        nodes[] <- [['a'], ['b'], ['c']]
+        start[] <- [['a']]
+    "#;
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ RandomWalk(edges[], nodes[], start[], steps: 3, seed: 7)",
+                graph
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, "a", ["a", "b", "c", "a"]]])
+    );
+    let res = TEST_DB
+        .run_script(
+            &format!(
+                "{}?[] <~ RandomWalk(edges[], nodes[], start[], steps: 3, iterations: 2, restart: 1)",
+                graph
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            [1, "a", ["a", "a", "a", "a"]],
+            [2, "a", ["a", "a", "a", "a"]]
+        ])
+    );
+}
+
+#[test]
+fn set_columns() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[user, tags] <- [['alice', ['admin', 'dev', 'admin']], ['bob', ['dev']]]
+        :replace user_tags { user: String => tags: Set<String> }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script(
+            "?[user, tag] := *user_tags{user, tags}, tag in tags",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["alice", "admin"], ["alice", "dev"], ["bob", "dev"]])
+    );
+    let res = TEST_DB
+        .run_script(
+            r#"
+        ?[user, extra] := *user_tags{user, tags}, contains(tags, 'admin'),
+                          extra = difference(tags, ['dev'])
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["alice", ["admin"]]]));
+    assert!(TEST_DB
+        .run_script(
+            "?[user, tags] <- [['carol', 'admin']] :put user_tags { user => tags }",
+            &Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn graph_views() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+        ?[fr, to] <- [['a', 'b'], ['b', 'c']]
+        :replace gv_links { fr: String, to: String }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    TEST_DB
+        .run_script(
+            r#"
+        ::graph view gv {
+            edges: { ?[a, b] := *gv_links{fr: a, to: b} },
+            nodes: { ?[n] <- [['a'], ['b'], ['c'], ['d']] }
+        }
+    "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let res = TEST_DB
+        .run_script(
+            "?[] <~ DegreeCentrality(gv.edges[], gv.nodes[])",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            ["a", 1, 1, 0],
+            ["b", 2, 1, 1],
+            ["c", 1, 0, 1],
+            ["d", 0, 0, 0]
+        ])
+    );
+    let res = TEST_DB
+        .run_script("::graph views", &Default::default())
+        .unwrap();
+    assert_eq!(res.get("rows").unwrap().as_array().unwrap().len(), 1);
+    assert!(TEST_DB
+        .run_script("?[] <~ DegreeCentrality(gv.edgez[])", &Default::default())
+        .is_err());
+    TEST_DB
+        .run_script("::graph remove gv", &Default::default())
+        .unwrap();
+    assert!(TEST_DB
+        .run_script("?[] <~ DegreeCentrality(gv.edges[])", &Default::default())
+        .is_err());
+}
+
+#[test]
+fn multi_transaction() {
+    check_db();
+    let mut tx = TEST_DB.multi_transact().unwrap();
+    tx.run_script("?[a] <- [[1]] :create mt_rel { a }", &Default::default())
+        .unwrap();
+    tx.run_script("?[a] <- [[2]] :put mt_rel { a }", &Default::default())
+        .unwrap();
+    assert!(tx
+        .run_script("?[a] <- [[3]] :put mt_rel { a, b }", &Default::default())
+        .is_err());
+    let res = tx
+        .run_script("?[a] := *mt_rel{a}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[1], [2]]));
+    assert!(TEST_DB
+        .run_script("?[a] := *mt_rel{a}", &Default::default())
+        .is_err());
+    tx.rollback().unwrap();
+    assert!(TEST_DB
+        .run_script("?[a] := *mt_rel{a}", &Default::default())
+        .is_err());
+
+    let mut tx = TEST_DB.multi_transact().unwrap();
+    tx.run_script("?[a] <- [[1]] :create mt_rel { a }", &Default::default())
+        .unwrap();
+    tx.run_script("?[a] <- [[2]] :put mt_rel { a }", &Default::default())
+        .unwrap();
+    tx.commit().unwrap();
+    let res = TEST_DB
+        .run_script("?[a] := *mt_rel{a}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[1], [2]]));
+}
+
+#[test]
+fn completion_metadata() {
+    check_db();
+    let meta = TEST_DB.completion_metadata().unwrap();
+    let airport = meta["relations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["name"] == json!("airport"))
+        .unwrap();
+    assert_eq!(airport["columns"][0]["name"], json!("code"));
+    assert_eq!(airport["columns"][0]["is_key"], json!(true));
+    assert!(meta["functions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|f| f["name"] == json!("haversine") && f["min_arity"] == json!(4)));
+    assert!(meta["aggregations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|a| a["name"] == json!("min") && a["is_meet"] == json!(true)));
+    let yen = meta["fixed_rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r["name"] == json!("KShortestPathYen"))
+        .unwrap();
+    assert!(yen["options"].as_array().unwrap().contains(&json!("k")));
+    assert_eq!(yen["arity"], json!(4));
+}
+
+#[test]
+fn time_travel() {
+    check_db();
+    fn now() -> f64 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let t = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        t
+    }
+    TEST_DB
+        .run_script(
+            "?[k, v] <- [[1, 'a']] :create tt_rel { k => v }",
+            &Default::default(),
+        )
+        .unwrap();
+    assert!(TEST_DB
+        .run_script("?[k, v] := *tt_rel[k, v] @ 0", &Default::default())
+        .is_err());
+    TEST_DB
+        .run_script("::relation history tt_rel on", &Default::default())
+        .unwrap();
+    let t1 = now();
+    let tx_of = |script: &str| {
+        TEST_DB.run_script(script, &Default::default()).unwrap()["tx_id"]
+            .as_u64()
+            .unwrap()
+    };
+    let put_tx = tx_of("?[k, v] <- [[1, 'b'], [2, 'c']] :put tt_rel { k => v }");
+    let t2 = now();
+    let rm_tx = tx_of("?[k] <- [[1]] :rm tt_rel { k }");
+    assert!(rm_tx > put_tx);
+
+    let as_of = |t: f64| {
+        let params = serde_json::Map::from_iter([("t".to_string(), json!(t))]);
+        TEST_DB
+            .run_script("?[k, v] := *tt_rel{k, v} @ $t", &params)
+            .unwrap()
+            .get("rows")
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(as_of(t1), json!([[1, "a"]]));
+    assert_eq!(as_of(t2), json!([[1, "b"], [2, "c"]]));
+    assert_eq!(as_of(now()), json!([[2, "c"]]));
+    assert_eq!(as_of(0.), json!([]));
+    let rows = |script: &str, params: &serde_json::Map<String, serde_json::Value>| {
+        TEST_DB.run_script(script, params).unwrap()["rows"].clone()
+    };
+    assert_eq!(
+        rows("?[k, v] := *tt_rel{k, v} @ now()", &Default::default()),
+        json!([[2, "c"]])
+    );
+    assert_eq!(
+        rows(
+            "?[k, v] := *tt_rel{k, v} @ to_timestamp(now() - 3600)",
+            &Default::default()
+        ),
+        json!([])
+    );
+    assert_eq!(
+        rows(
+            "?[k, v] := *tt_rel{k, v} @ to_timestamp($t)",
+            &serde_json::Map::from_iter([("t".to_string(), json!(t2))])
+        ),
+        json!([[1, "b"], [2, "c"]])
+    );
+
+    let as_of_tx = |tx: u64| {
+        let params = serde_json::Map::from_iter([("tx".to_string(), json!(tx))]);
+        TEST_DB
+            .run_script("?[k, v] := *tt_rel{k, v} @ tx $tx", &params)
+            .unwrap()
+            .get("rows")
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(as_of_tx(put_tx - 1), json!([[1, "a"]]));
+    assert_eq!(as_of_tx(put_tx), json!([[1, "b"], [2, "c"]]));
+    assert_eq!(as_of_tx(rm_tx), json!([[2, "c"]]));
+    assert!(TEST_DB
+        .run_script("?[k, v] := *tt_rel{k, v} @ tx -1", &Default::default())
+        .is_err());
+
+    TEST_DB
+        .run_script(
+            "?[k, v] <- [[3, 'd']] :replace tt_rel { k => v }",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(as_of(now()), json!([[3, "d"]]));
+    assert_eq!(as_of(t2), json!([[1, "b"], [2, "c"]]));
+    assert_eq!(as_of_tx(rm_tx), json!([[2, "c"]]));
+    assert!(TEST_DB
+        .run_script(
+            "?[k, w] <- [[3, 'd']] :replace tt_rel { k => w }",
+            &Default::default(),
+        )
+        .is_err());
+
+    assert!(TEST_DB
+        .run_script("::relation truncate tt_rel", &Default::default())
+        .is_err());
+    TEST_DB
+        .run_script("::relation history tt_rel off", &Default::default())
+        .unwrap();
+    TEST_DB
+        .run_script("::remove tt_rel", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn list_functions_and_algos() {
+    check_db();
+    let res = TEST_DB
+        .run_script("::functions", &Default::default())
+        .unwrap();
+    let rows = res["rows"].as_array().unwrap();
+    assert!(rows.contains(&json!(["haversine", "function", 4, false, null])));
+    assert!(rows.contains(&json!(["min", "aggregation", null, null, true])));
+
+    let res = TEST_DB.run_script("::algos", &Default::default()).unwrap();
+    let yen = res["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|r| r[0] == json!("KShortestPathYen"))
+        .unwrap();
+    assert_eq!(yen[1], json!(4));
+    assert!(yen[2].as_array().unwrap().contains(&json!("k")));
+    assert_eq!(yen[3], json!(false));
+}
+
+#[test]
+fn script_version() {
+    check_db();
+    let res = TEST_DB
+        .run_script("?[a] <- [[2], [1]] :order a", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[1], [2]]));
+    assert_eq!(res["warnings"].as_array().unwrap().len(), 1);
+    assert!(TEST_DB
+        .run_script(
+            "%version 2 ?[a] <- [[2], [1]] :order a",
+            &Default::default()
+        )
+        .is_err());
+    let res = TEST_DB
+        .run_script("%version 2 ?[a] <- [[2], [1]] :sort a", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[1], [2]]));
+    assert!(res.get("warnings").is_none());
+    assert!(TEST_DB
+        .run_script("%version 3 ?[a] <- [[1]]", &Default::default())
+        .is_err());
+    TEST_DB
+        .run_script("%version 2 ::relations", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn vector_search() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+            ?[k, v] <- [[1, [0, 0]], [2, [1, 0]], [3, [0, 1]], [4, [5, 5]], [5, [10, 10]]]
+            :create vs_rel { k: Int => v: <F32; 2>? }
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert!(TEST_DB
+        .run_script(
+            "?[k, v] <- [[6, [1, 2, 3]]] :put vs_rel { k => v }",
+            &Default::default()
+        )
+        .is_err());
+    let res = TEST_DB
+        .run_script(
+            "?[v, d] := *vs_rel{k: 4, v}, d = l2_dist(v, [2, 1])",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[[5.0, 5.0], 25.0]]));
+
+    let search = r#"
+        r[k, d] <~ VectorSearch(*vs_rel[], query: vec([0.9, 0.1]), k: 2)
+        ?[k] := r[k, d]
+    "#;
+    assert!(TEST_DB.run_script(search, &Default::default()).is_err());
+    TEST_DB
+        .run_script(
+            "::relation vector_index vs_rel on v {m: 4}",
+            &Default::default(),
+        )
+        .unwrap();
+    let nearest = || {
+        TEST_DB
+            .run_script(search, &Default::default())
+            .unwrap()
+            .get("rows")
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(nearest(), json!([[1], [2]]));
+    TEST_DB
+        .run_script(
+            "?[k, v] <- [[6, [0.8, 0.2]], [7, null]] :put vs_rel { k => v }",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(nearest(), json!([[2], [6]]));
+    TEST_DB
+        .run_script("?[k] <- [[2], [6]] :rm vs_rel { k }", &Default::default())
+        .unwrap();
+    assert_eq!(nearest(), json!([[1], [3]]));
+
+    assert!(TEST_DB
+        .run_script("::relation truncate vs_rel", &Default::default())
+        .is_err());
+    TEST_DB
+        .run_script("::relation vector_index vs_rel off", &Default::default())
+        .unwrap();
+    assert!(TEST_DB.run_script(search, &Default::default()).is_err());
+    TEST_DB
+        .run_script("::remove vs_rel", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn fixed_rule_time_travel() {
+    check_db();
+    TEST_DB
+        .run_script(
+            "?[fr, to] <- [['a', 'b']] :create tt_edges { fr, to }",
+            &Default::default(),
+        )
+        .unwrap();
+    let degrees = "?[n, d, o, i] <~ DegreeCentrality(*tt_edges[] @ $t)";
+    let params = serde_json::Map::from_iter([("t".to_string(), json!(0.))]);
+    assert!(TEST_DB.run_script(degrees, &params).is_err());
+    TEST_DB
+        .run_script("::relation history tt_edges on", &Default::default())
+        .unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    let t = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    TEST_DB
+        .run_script(
+            "?[fr, to] <- [['a', 'c'], ['b', 'c']] :put tt_edges { fr, to }",
+            &Default::default(),
+        )
+        .unwrap();
+
+    let params = serde_json::Map::from_iter([("t".to_string(), json!(t))]);
+    let res = TEST_DB.run_script(degrees, &params).unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 1, 1, 0], ["b", 1, 0, 1]])
+    );
+    let res = TEST_DB
+        .run_script(
+            "?[n, d, o, i] <~ DegreeCentrality(*tt_edges{fr, to} @ $t)",
+            &params,
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 1, 1, 0], ["b", 1, 0, 1]])
+    );
+    let res = TEST_DB
+        .run_script("?[n, d, o, i] <~ DegreeCentrality(*tt_edges[])", &params)
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 2, 2, 0], ["b", 2, 1, 1], ["c", 2, 0, 2]])
+    );
+
+    TEST_DB
+        .run_script("::relation history tt_edges off", &Default::default())
+        .unwrap();
+    TEST_DB
+        .run_script("::remove tt_edges", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn regex_match_operator() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+            data[s, p] <- [['abcdef', 'c.e'], ['abcdef', '^c'], ['xyz', 'y+']]
+            ?[s, p, m, e] := data[s, p], m = s =~ p, e = regex_extract_first(s, p)
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            ["abcdef", "^c", false, null],
+            ["abcdef", "c.e", true, "cde"],
+            ["xyz", "y+", true, "y"]
+        ])
+    );
+    let res = TEST_DB
+        .run_script(
+            "?[a] := a in ['cat', 'dog', 'cow'], a =~ '^c'",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["cat"], ["cow"]]));
+}
+
+#[test]
+fn identical_rules_across_strata() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+            a[x, y] := x in [1, 2, 3], y = x * 2
+            b[x, y] := x in [1, 2, 3], y = x * 2
+            c[x, y] := x in [1, 2, 3], y = x * 3
+            n[count(x)] := b[x, y]
+            m[max(y)] := c[x, y]
+            ?[x, y, cnt, mx] := a[x, y], n[cnt], m[mx]
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, 2, 3, 9], [2, 4, 3, 9], [3, 6, 3, 9]])
+    );
+}
+
+#[test]
+fn relation_checksum() {
+    check_db();
+    TEST_DB
+        .run_script(
+            r#"
+            {?[k, v] <- [[1, 'x'], [2, 'y']] :replace checksum_a {k: Int => v: String}}
+            {?[k, v] <- [[2, 'y']] :replace checksum_b {k: Int => v: String}}
+            {?[k, v] <- [[1, 'x']] :put checksum_b {k => v}}
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let a = TEST_DB
+        .run_script("::relation checksum checksum_a", &Default::default())
+        .unwrap();
+    let b = TEST_DB
+        .run_script("::relation checksum checksum_b", &Default::default())
+        .unwrap();
+    assert_eq!(a.get("rows").unwrap()[0][0], json!(2));
+    assert_eq!(a.get("rows"), b.get("rows"));
+
+    TEST_DB
+        .run_script(
+            "?[k, v] <- [[2, 'z']] :put checksum_b {k => v}",
+            &Default::default(),
+        )
+        .unwrap();
+    let b = TEST_DB
+        .run_script("::relation checksum checksum_b", &Default::default())
+        .unwrap();
+    assert_ne!(a.get("rows"), b.get("rows"));
+
+    let res = TEST_DB
+        .run_script(
+            "?[h1, h2] := h1 = tuple_hash(1, 'x'), h2 = tuple_hash(1, 'x')",
+            &Default::default(),
+        )
+        .unwrap();
+    let row = &res.get("rows").unwrap()[0];
+    assert_eq!(row[0], row[1]);
+    TEST_DB
+        .run_script("::remove checksum_a, checksum_b", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn sync_relation() {
+    let path_a = "_test_sync_a";
+    let path_b = "_test_sync_b";
+    _ = std::fs::remove_dir_all(path_a);
+    _ = std::fs::remove_dir_all(path_b);
+    let db_a = Db::new(path_a).unwrap();
+    let db_b = Db::new(path_b).unwrap();
+    db_a.run_script(
+        r#"
+        nums[k] := k = 0
+        nums[k] := nums[j], k = j + 1, k < 1000
+        ?[k, v] := nums[k], v = to_string(k)
+        :create data {k: Int => v: String}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db_b.run_script(
+        r#"
+        nums[k] := k = 500
+        nums[k] := nums[j], k = j + 1, k < 1500
+        ?[k, v] := nums[k], k != 700, v = to_string(k)
+        ?[k, v] := k = 700, v = 'changed'
+        :create data {k: Int => v: String}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+
+    let report = db_a
+        .sync_relation("data", |req| db_b.handle_sync_request(req))
+        .unwrap();
+    assert_eq!(report.rows_received, 500);
+    assert_eq!(report.rows_sent, 501);
+
+    let checksum = "::relation checksum data";
+    let a = db_a.run_script(checksum, &Default::default()).unwrap();
+    let b = db_b.run_script(checksum, &Default::default()).unwrap();
+    assert_eq!(a.get("rows").unwrap()[0][0], json!(1500));
+    assert_eq!(a.get("rows"), b.get("rows"));
+    let res = db_b
+        .run_script("?[v] := *data[700, v]", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["700"]]));
+
+    let report = db_b
+        .sync_relation("data", |req| db_a.handle_sync_request(req))
+        .unwrap();
+    assert_eq!(report.ranges_compared, 1);
+    assert_eq!(report.rows_received + report.rows_sent, 0);
+
+    db_b.run_script(
+        "?[k, v] <- [[1, 1]] :create other {k: Int => v: Int}",
+        &Default::default(),
+    )
+    .unwrap();
+    db_a.run_script(
+        "?[k, v] <- [[1, 'a']] :create other {k: Int => v: String}",
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(db_a
+        .sync_relation("other", |req| db_b.handle_sync_request(req))
+        .is_err());
+
+    drop(db_a);
+    drop(db_b);
+    _ = std::fs::remove_dir_all(path_a);
+    _ = std::fs::remove_dir_all(path_b);
+}
+
+#[test]
+fn sync_relation_lww() {
+    let path_a = "_test_sync_lww_a";
+    let path_b = "_test_sync_lww_b";
+    _ = std::fs::remove_dir_all(path_a);
+    _ = std::fs::remove_dir_all(path_b);
+    let db_a = Db::new(path_a).unwrap();
+    let db_b = Db::new(path_b).unwrap();
+    let pause = || std::thread::sleep(std::time::Duration::from_millis(10));
+    db_a.run_script(
+        r#"
+        ?[k, v] <- [[1, 'a1'], [2, 'a2'], [3, 'a3']]
+        :create data {k: Int => v: String}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db_a.run_script("::relation lww data on", &Default::default())
+        .unwrap();
+    pause();
+    db_b.run_script(
+        r#"
+        ?[k, v] <- [[1, 'b1'], [4, 'b4']]
+        :create data {k: Int => v: String}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db_b.run_script("::relation lww data on", &Default::default())
+        .unwrap();
+
+    let report = db_a
+        .sync_relation("data", |req| db_b.handle_sync_request(req))
+        .unwrap();
+    assert_eq!(report.conflicts, 1);
+    let conflicts = db_a
+        .run_script(
+            "?[relation, key, local, peer, kept] := *sync_conflicts{relation, key, local, peer, kept}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *conflicts.get("rows").unwrap(),
+        json!([["data", [1], ["a1"], ["b1"], "peer"]])
+    );
+
+    pause();
+    db_a.run_script("?[k] <- [[2]] :rm data {k}", &Default::default())
+        .unwrap();
+    pause();
+    db_b.run_script(
+        "?[k, v] <- [[3, 'b3']] :put data {k => v}",
+        &Default::default(),
+    )
+    .unwrap();
+    let report = db_b
+        .sync_relation("data", |req| db_a.handle_sync_request(req))
+        .unwrap();
+    assert_eq!(report.conflicts, 0);
+
+    let query = "?[k, v] := *data[k, v]";
+    let a = db_a.run_script(query, &Default::default()).unwrap();
+    let b = db_b.run_script(query, &Default::default()).unwrap();
+    assert_eq!(
+        *a.get("rows").unwrap(),
+        json!([[1, "b1"], [3, "b3"], [4, "b4"]])
+    );
+    assert_eq!(a.get("rows"), b.get("rows"));
+
+    drop(db_a);
+    drop(db_b);
+    _ = std::fs::remove_dir_all(path_a);
+    _ = std::fs::remove_dir_all(path_b);
+}
+
+#[test]
+fn profile_query() {
+    check_db();
+    let res = TEST_DB
+        .run_script(
+            r#"
+            reachable[to] := *route{fr: 'FRA', to}
+            reachable[to] := reachable[stop], *route{fr: stop, to}
+            n[count(to)] := reachable[to]
+            ?[c] := n[c]
+            :profile
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let profile = res.get("profile").unwrap();
+    let strata = profile["strata"]["rows"].as_array().unwrap();
+    assert_eq!(strata.len(), 2);
+    let rules = profile["rules"]["rows"].as_array().unwrap();
+    let reachable = rules
+        .iter()
+        .find(|row| row[1] == json!("reachable"))
+        .unwrap();
+    assert!(reachable[3].as_u64().unwrap() > 1);
+    let n_reachable = reachable[4].as_u64().unwrap();
+    assert_eq!(res["rows"][0][0], json!(n_reachable));
+    assert!(rules.iter().all(|row| row[2].as_f64().unwrap() >= 0.));
+
+    let res = TEST_DB
+        .run_script("?[a] <- [[1]]", &Default::default())
+        .unwrap();
+    assert!(res.get("profile").is_none());
+}
+
+#[test]
+fn soft_delete() {
+    check_db();
+    TEST_DB
+        .run_script(
+            "?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :create soft_rel { k => v }",
+            &Default::default(),
+        )
+        .unwrap();
+    TEST_DB
+        .run_script("::relation soft_delete soft_rel on", &Default::default())
+        .unwrap();
+    TEST_DB
+        .run_script("?[k] <- [[2]] :rm soft_rel { k }", &Default::default())
+        .unwrap();
+
+    let rows = |script: &str| {
+        TEST_DB
+            .run_script(script, &Default::default())
+            .unwrap()
+            .get("rows")
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(
+        rows("?[k, v] := *soft_rel[k, v]"),
+        json!([[1, "a"], [3, "c"]])
+    );
+    assert_eq!(
+        rows("?[k, v] := *soft_rel[k, v] :include_deleted true"),
+        json!([[1, "a"], [2, "b"], [3, "c"]])
+    );
+    assert_eq!(
+        rows("?[v] := *soft_rel[2, v] :include_deleted true"),
+        json!([["b"]])
+    );
+
+    TEST_DB
+        .run_script(
+            "?[k, v] <- [[2, 'B']] :put soft_rel { k => v }",
+            &Default::default(),
+        )
+        .unwrap();
+    TEST_DB
+        .run_script("?[k] <- [[3]] :rm soft_rel { k }", &Default::default())
+        .unwrap();
+    assert_eq!(
+        rows("?[k, v] := *soft_rel[k, v] :include_deleted true"),
+        json!([[1, "a"], [2, "B"], [3, "c"]])
+    );
+    TEST_DB
+        .run_script("::relation purge soft_rel", &Default::default())
+        .unwrap();
+    assert_eq!(
+        rows("?[k, v] := *soft_rel[k, v] :include_deleted true"),
+        json!([[1, "a"], [2, "B"]])
+    );
+
+    assert!(TEST_DB
+        .run_script("::relation truncate soft_rel", &Default::default())
+        .is_err());
+    TEST_DB
+        .run_script("::relation soft_delete soft_rel off", &Default::default())
+        .unwrap();
+    assert!(TEST_DB
+        .run_script("::relation purge soft_rel", &Default::default())
+        .is_err());
+    TEST_DB
+        .run_script("::remove soft_rel", &Default::default())
+        .unwrap();
+}
+
+#[test]
+fn audit_log() {
+    let path = "_test_audit";
+    _ = std::fs::remove_dir_all(path);
+    let db = Db::new_with_options(
+        path,
+        DbOptions {
+            audit: true,
+            audit_retention: Some(std::time::Duration::from_secs(3600)),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    db.run_script(
+        "?[k, v] <- [[1, 'a'], [2, 'b']] :create audited { k => v }",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("?[k] <- [[1]] :rm audited { k }", &Default::default())
+        .unwrap();
+    db.run_script("::relation history audited on", &Default::default())
+        .unwrap();
+    db.run_script("?[k, v] := *audited[k, v]", &Default::default())
+        .unwrap();
+
+    let res = db
+        .run_script(
+            "?[command, relation, rows] := *audit_log{command, relation, rows}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            ["::relation history audited on", "audited", null],
+            [
+                "?[k, v] <- [[1, 'a'], [2, 'b']] :create audited { k => v }",
+                "audited",
+                2
+            ],
+            ["?[k] <- [[1]] :rm audited { k }", "audited", 1]
+        ])
+    );
+    assert!(db
+        .run_script(
+            "?[at, seq, command] <- [[0., 0, 'forged']] :put audit_log { at, seq => command }",
+            &Default::default()
+        )
+        .is_err());
+
+    drop(db);
+    _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn session_labels() {
+    let path = "_test_session_labels";
+    _ = std::fs::remove_dir_all(path);
+    let db = Db::new_with_options(
+        path,
+        DbOptions {
+            audit: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    db.run_script_labelled(
+        "?[k] <- [[1]] :create labelled { k }",
+        &Default::default(),
+        Some("tenant-a"),
+    )
+    .unwrap();
+    db.run_script("?[k] <- [[2]] :put labelled { k }", &Default::default())
+        .unwrap();
+    let res = db
+        .run_script(
+            "?[session, rows] := *audit_log{session, rows}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[null, 1], ["tenant-a", 1]])
+    );
+
+    let err = db.run_script_fold_err_labelled(
+        "?[k] := *not_there[k]",
+        &Default::default(),
+        Some("tenant-b"),
+    );
+    assert_eq!(*err.get("ok").unwrap(), json!(false));
+    assert_eq!(*err.get("label").unwrap(), json!("tenant-b"));
+
+    let running = db.run_script("::running", &Default::default()).unwrap();
+    assert_eq!(
+        *running.get("headers").unwrap(),
+        json!(["id", "started_at", "label"])
+    );
+
+    drop(db);
+    _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn storage_compliance() {
+    check_storage_compliance(&MemStorage::new()).unwrap();
+
+    let path = "_test_storage_compliance";
+    _ = std::fs::remove_dir_all(path);
+    let storage = RocksDbStorage::open(path, None).unwrap();
+    check_storage_compliance(&storage).unwrap();
+    drop(storage);
+    _ = std::fs::remove_dir_all(path);
+
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        "?[k, v] <- [[1, 'a'], [2, 'b']] :create in_mem_storage { k => v }",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[k] <- [[1]] :rm in_mem_storage { k }",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[k, v] := *in_mem_storage[k, v]", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[2, "b"]]));
+}
+
+#[test]
+fn rocksdb_range_del_conflict() {
+    let path = "_test_range_del_conflict";
+    _ = std::fs::remove_dir_all(path);
+    let storage = RocksDbStorage::open(path, None).unwrap();
+    let mut setup = storage.transact().unwrap();
+    setup.put(b"k1", b"a").unwrap();
+    setup.commit().unwrap();
+
+    let mut deleting = storage.transact().unwrap();
+    let mut writing = storage.transact().unwrap();
+    writing.put(b"k2", b"b").unwrap();
+    writing.commit().unwrap();
+    // the deletion would remove a row it never saw
+    assert!(deleting.range_del(b"k", b"l").is_err());
+    deleting.rollback().unwrap();
+
+    let mut deleting = storage.transact().unwrap();
+    deleting.range_del(b"k", b"l").unwrap();
+    deleting.commit().unwrap();
+    assert_eq!(
+        storage.transact().unwrap().range_scan(b"k", b"l").count(),
+        0
+    );
+    drop(storage);
+    _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn tiered_storage() {
+    let cold = Arc::new(MemStorage::new());
+    let db = Db::new_with_storage(
+        Arc::new(MemStorage::new()),
+        DbOptions {
+            cold_storage: Some(cold.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let cold_rows = || {
+        cold.transact()
+            .unwrap()
+            .range_scan(&[], &[u8::MAX; 16])
+            .count()
+    };
+    let rows = |script: &str| {
+        db.run_script(script, &Default::default())
+            .unwrap()
+            .get("rows")
+            .unwrap()
+            .clone()
+    };
+    db.run_script(
+        "?[t, id, v] <- [[100, 1, 'a'], [200, 2, 'b'], [9999999999, 3, 'c']]
+         :create events { t: Float, id: Int => v }",
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(db
+        .run_script("::relation tier events after 0", &Default::default())
+        .is_err());
+    db.run_script("::relation tier events after 3600", &Default::default())
+        .unwrap();
+    assert_eq!(rows("::relation offload events"), json!([[2]]));
+    assert_eq!(cold_rows(), 2);
+    assert_eq!(rows("::relation offload events"), json!([[0]]));
+
+    assert_eq!(
+        rows("?[t, id, v] := *events[t, id, v]"),
+        json!([[100.0, 1, "a"], [200.0, 2, "b"], [9999999999.0, 3, "c"]])
+    );
+    assert_eq!(rows("?[v] := *events[200.0, id, v]"), json!([["b"]]));
+    assert!(db
+        .run_script(
+            "?[t, id, v] <- [[100, 1, 'A']] :put events { t, id => v }",
+            &Default::default()
+        )
+        .is_err());
+    assert!(db
+        .run_script("::relation truncate events", &Default::default())
+        .is_err());
+
+    db.run_script("::relation tier events off", &Default::default())
+        .unwrap();
+    assert_eq!(cold_rows(), 0);
+    assert_eq!(
+        rows("?[t, id, v] := *events[t, id, v]"),
+        json!([[100.0, 1, "a"], [200.0, 2, "b"], [9999999999.0, 3, "c"]])
+    );
+
+    let plain = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    plain
+        .run_script(
+            "?[t] <- [[1]] :create plain_events { t }",
+            &Default::default(),
+        )
+        .unwrap();
+    assert!(plain
+        .run_script(
+            "::relation tier plain_events after 3600",
+            &Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn tiered_storage_failed_commit() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use cozo::storage::{KvIter, StoreTx};
+
+    struct FlakyStorage {
+        inner: MemStorage,
+        fail: Arc<AtomicBool>,
+    }
+    struct FlakyTx {
+        inner: Box<dyn StoreTx>,
+        fail: Arc<AtomicBool>,
+    }
+    impl Storage for FlakyStorage {
+        fn transact(&self) -> miette::Result<Box<dyn StoreTx>> {
+            Ok(Box::new(FlakyTx {
+                inner: self.inner.transact()?,
+                fail: self.fail.clone(),
+            }))
+        }
+        fn range_del(&self, lower: &[u8], upper: &[u8]) -> miette::Result<()> {
+            self.inner.range_del(lower, upper)
+        }
+    }
+    impl StoreTx for FlakyTx {
+        fn get(&self, key: &[u8], for_update: bool) -> miette::Result<Option<Vec<u8>>> {
+            self.inner.get(key, for_update)
+        }
+        fn put(&mut self, key: &[u8], val: &[u8]) -> miette::Result<()> {
+            self.inner.put(key, val)
+        }
+        fn del(&mut self, key: &[u8]) -> miette::Result<()> {
+            self.inner.del(key)
+        }
+        fn range_scan(&self, lower: &[u8], upper: &[u8]) -> KvIter {
+            self.inner.range_scan(lower, upper)
+        }
+        fn commit(&mut self) -> miette::Result<()> {
+            if self.fail.load(Ordering::SeqCst) {
+                miette::bail!("commit failed")
+            }
+            self.inner.commit()
+        }
+        fn rollback(&mut self) -> miette::Result<()> {
+            self.inner.rollback()
+        }
+        fn save(&mut self) {
+            self.inner.save()
+        }
+        fn pop_save(&mut self) -> miette::Result<()> {
+            self.inner.pop_save()
+        }
+        fn rollback_to_save(&mut self) -> miette::Result<()> {
+            self.inner.rollback_to_save()
+        }
+    }
+
+    let fail = Arc::new(AtomicBool::new(false));
+    let cold = Arc::new(MemStorage::new());
+    let db = Db::new_with_storage(
+        Arc::new(FlakyStorage {
+            inner: MemStorage::new(),
+            fail: fail.clone(),
+        }),
+        DbOptions {
+            cold_storage: Some(cold.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let cold_rows = || {
+        cold.transact()
+            .unwrap()
+            .range_scan(&[], &[u8::MAX; 16])
+            .count()
+    };
+    db.run_script(
+        "?[t, id, v] <- [[100, 1, 'a'], [200, 2, 'b'], [9999999999, 3, 'c']]
+         :create events { t: Float, id: Int => v }",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("::relation tier events after 3600", &Default::default())
+        .unwrap();
+    db.run_script("::relation offload events", &Default::default())
+        .unwrap();
+    assert_eq!(cold_rows(), 2);
+
+    fail.store(true, Ordering::SeqCst);
+    assert!(db
+        .run_script("::relation tier events off", &Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::remove events", &Default::default())
+        .is_err());
+    fail.store(false, Ordering::SeqCst);
+
+    assert_eq!(cold_rows(), 2);
+    let res = db
+        .run_script("?[t, id, v] := *events[t, id, v]", &Default::default())
+        .unwrap();
+    assert_eq!(
+        res["rows"],
+        json!([[100.0, 1, "a"], [200.0, 2, "b"], [9999999999.0, 3, "c"]])
+    );
+    db.run_script("::relation tier events off", &Default::default())
+        .unwrap();
+    assert_eq!(cold_rows(), 0);
+}
+
+#[test]
+fn import_remote() {
+    use std::io::Read;
+
+    let source = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    source
+        .run_script(
+            r#"
+            nums[k] := k = 0
+            nums[k] := nums[j], k = j + 1, k < 2500
+            ?[k, v] := nums[k], v = to_string(k)
+            :create data {k: Int => v: String}
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let served = source.clone();
+    let server = rouille::Server::new("127.0.0.1:0", move |request| {
+        let mut body = vec![];
+        request.data().unwrap().read_to_end(&mut body).unwrap();
+        match served.handle_sync_request(&body) {
+            Ok(response) => rouille::Response::from_data("application/octet-stream", response),
+            Err(err) => rouille::Response::text(format!("{:?}", err)).with_status_code(400),
+        }
+    })
+    .unwrap();
+    let url = format!("http://{}", server.server_addr());
+    let (handle, stop) = server.stoppable();
+
+    let rows = |db: &Db, script: &str| {
+        db.run_script(script, &Default::default())
+            .unwrap()
+            .get("rows")
+            .unwrap()
+            .clone()
+    };
+    let target = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    assert_eq!(
+        rows(&target, &format!("::import remote '{}' data", url)),
+        json!([["data", 2500]])
+    );
+    assert_eq!(rows(&target, "?[count(k)] := *data[k, v]"), json!([[2500]]));
+    assert_eq!(rows(&target, "?[v] := *data[1234, v]"), json!([["1234"]]));
+
+    let partial = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    partial
+        .run_script(
+            "?[k, v] <- [[0, 'local']] :create data {k: Int => v: String}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        rows(
+            &partial,
+            &format!("::import remote '{}/' data from 10 to 20", url)
+        ),
+        json!([["data", 10]])
+    );
+    assert_eq!(
+        rows(&partial, "?[min(k), max(k), count(k)] := *data[k, v]"),
+        json!([[0, 19, 11]])
+    );
+    assert!(partial
+        .run_script(
+            &format!("::import remote '{}' missing", url),
+            &Default::default()
+        )
+        .is_err());
+
+    stop.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn federated_query() {
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let source = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    source
+        .run_script(
+            r#"
+            nums[k] := k = 0
+            nums[k] := nums[j], k = j + 1, k < 1000
+            ?[k, v] := nums[k], v = to_string(k)
+            :create data {k: Int => v: String}
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let served = source.clone();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counted = requests.clone();
+    let server = rouille::Server::new("127.0.0.1:0", move |request| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        let mut body = vec![];
+        request.data().unwrap().read_to_end(&mut body).unwrap();
+        match served.handle_sync_request(&body) {
+            Ok(response) => rouille::Response::from_data("application/octet-stream", response),
+            Err(err) => rouille::Response::text(format!("{:?}", err)).with_status_code(400),
+        }
+    })
+    .unwrap();
+    let url = format!("http://{}", server.server_addr());
+    let (handle, stop) = server.stoppable();
+
+    let options = DbOptions {
+        remotes: BTreeMap::from([("central".to_string(), RemoteDb::new(url))]),
+        ..Default::default()
+    };
+    let local = Db::new_with_storage(Arc::new(MemStorage::new()), options).unwrap();
+    let rows = |script: &str| {
+        local
+            .run_script(script, &Default::default())
+            .unwrap()
+            .get("rows")
+            .unwrap()
+            .clone()
+    };
+    rows("?[k] <- [[3], [500], [999], [1000]] :create wanted {k: Int}");
+
+    let lookup = "?[k, v] := *wanted[k], *central::data{k, v}";
+    assert_eq!(rows(lookup), json!([[3, "3"], [500, "500"], [999, "999"]]));
+    let fetched = requests.load(Ordering::SeqCst);
+    assert!(fetched <= 5);
+    assert_eq!(rows(lookup), json!([[3, "3"], [500, "500"], [999, "999"]]));
+    assert_eq!(requests.load(Ordering::SeqCst), fetched);
+
+    assert_eq!(
+        rows("?[count(k), max(k)] := *central::data[k, v]"),
+        json!([[1000, 999]])
+    );
+    assert!(local
+        .run_script("?[k] := *elsewhere::data[k, _]", &Default::default())
+        .is_err());
+    assert!(local
+        .run_script("?[k] := *central::missing[k]", &Default::default())
+        .is_err());
+
+    stop.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn continuous_queries() {
+    use std::sync::Mutex;
+
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        {:create readings {sensor: String => value: Float}}
+        {:create unrelated {k: Int}}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let diffs = Arc::new(Mutex::new(vec![]));
+    let received = diffs.clone();
+    let id = db
+        .register_continuous_query(
+            "?[sensor, value] := *readings[sensor, value], value > $threshold",
+            &serde_json::from_value(json!({"threshold": 10})).unwrap(),
+            move |diff| received.lock().unwrap().push(diff),
+        )
+        .unwrap();
+    let take = || std::mem::take(&mut *diffs.lock().unwrap());
+    let diff = |added: serde_json::Value, removed: serde_json::Value| QueryDiff {
+        headers: vec!["sensor".to_string(), "value".to_string()],
+        added: serde_json::from_value(added).unwrap(),
+        removed: serde_json::from_value(removed).unwrap(),
+    };
+    assert_eq!(take(), vec![diff(json!([]), json!([]))]);
+
+    let run = |script: &str| {
+        db.run_script(script, &Default::default()).unwrap();
+    };
+    run("?[sensor, value] <- [['a', 5.0], ['b', 15.0]] :put readings {sensor => value}");
+    assert_eq!(take(), vec![diff(json!([["b", 15.0]]), json!([]))]);
+    run("?[k] <- [[1]] :put unrelated {k}");
+    run("?[sensor, value] <- [['a', 6.0]] :put readings {sensor => value}");
+    assert_eq!(take(), vec![]);
+    run("?[sensor, value] <- [['a', 20.0], ['b', 1.0]] :put readings {sensor => value}");
+    assert_eq!(
+        take(),
+        vec![diff(json!([["a", 20.0]]), json!([["b", 15.0]]))]
+    );
+
+    let mut tx = db.multi_transact().unwrap();
+    tx.run_script(
+        "?[sensor, value] <- [['c', 30.0]] :put readings {sensor => value}",
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(take(), vec![]);
+    tx.commit().unwrap();
+    assert_eq!(take(), vec![diff(json!([["c", 30.0]]), json!([]))]);
+
+    assert!(db.unregister_continuous_query(id));
+    assert!(!db.unregister_continuous_query(id));
+    run("?[sensor] <- [['c']] :rm readings {sensor}");
+    assert_eq!(take(), vec![]);
+    assert!(db
+        .register_continuous_query(
+            "?[k] <- [[1]] :put unrelated {k}",
+            &Default::default(),
+            |_| {}
+        )
+        .is_err());
+}
+
+#[test]
+fn scheduled_queries() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[item, qty] <- [['bolt', 2], ['nut', 10], ['screw', 3]]
+        :create stock {item: String => qty: Int}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let schedule = r#"
+        ::schedule create low_stock every 3600 into low delta low_changes {
+            ?[item] := *stock{item, qty}, qty < 5
+        }
+    "#;
+    db.run_script(schedule, &Default::default()).unwrap();
+    assert_eq!(db.run_due_scheduled_queries().unwrap(), 1);
+    let res = db
+        .run_script("?[item] := *low{item}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["bolt"], ["screw"]]));
+    let res = db
+        .run_script(
+            "?[added, item] := *low_changes{added, item}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[true, "bolt"], [true, "screw"]])
+    );
+    // not due again for an hour
+    assert_eq!(db.run_due_scheduled_queries().unwrap(), 0);
+
+    db.run_script(
+        "?[item, qty] <- [['bolt', 20], ['nut', 1]] :put stock {item => qty}",
+        &Default::default(),
+    )
+    .unwrap();
+    // creating the schedule again makes it due at once
+    db.run_script(schedule, &Default::default()).unwrap();
+    assert_eq!(db.run_due_scheduled_queries().unwrap(), 1);
+    let res = db
+        .run_script("?[item] := *low{item}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["nut"], ["screw"]]));
+    let res = db
+        .run_script(
+            "?[added, item] := *low_changes{added, item}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[false, "bolt"], [true, "nut"]])
+    );
+
+    let res = db
+        .run_script("::schedule list", &Default::default())
+        .unwrap();
+    assert_eq!(res.get("rows").unwrap().as_array().unwrap().len(), 1);
+    assert!(db
+        .run_script(
+            "::schedule create bad every 0 into a delta b { ?[x] <- [[1]] }",
+            &Default::default()
+        )
+        .is_err());
+    assert!(db
+        .run_script(
+            "::schedule create bad every 1 into a delta b { ?[x] <- [[1]] :put stock {x} }",
+            &Default::default()
+        )
+        .is_err());
+    db.run_script("::schedule remove low_stock", &Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("::schedule remove low_stock", &Default::default())
+        .is_err());
+}
+
+#[test]
+fn jobs() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script("?[n] <- [[0]] :create ticks {n: Int}", &Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+        ::job create tick '*/5 * * * *' {
+            ?[n] := *ticks{n}
+        } {
+            ?[n] := *ticks{n: m}, n = m + 1
+            :put ticks {n}
+        }
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    // the next run is at the next multiple of five minutes
+    let res = db.run_script("::job list", &Default::default()).unwrap();
+    let next_run = res["rows"][0][2].as_f64().unwrap();
+    assert_eq!(next_run % 300., 0.);
+    assert_eq!(db.run_due_jobs().unwrap(), 0);
+
+    let res = db
+        .run_script("::job run tick", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"][0][2], json!(true));
+    db.run_script("::job run tick", &Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[n] := *ticks{n}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[0], [1], [2]]));
+
+    db.run_script(
+        "::job create broken '@daily' { ?[n] <- [['x']] :put ticks {n} }",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("::job run broken", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"][0][2], json!(false));
+    assert!(res["rows"][0][3].is_string());
+    let res = db.run_script("::job list", &Default::default()).unwrap();
+    assert_eq!(res["rows"][0][0], json!("broken"));
+    assert_eq!(res["rows"][0][4], json!(1));
+    let res = db
+        .run_script("::job history tick", &Default::default())
+        .unwrap();
+    assert_eq!(res.get("rows").unwrap().as_array().unwrap().len(), 2);
+
+    assert!(db
+        .run_script(
+            "::job create bad '61 * * * *' { ?[] <- [[]] }",
+            &Default::default()
+        )
+        .is_err());
+    assert!(db
+        .run_script(
+            "::job create bad '0 0 31 2 *' { ?[] <- [[]] }",
+            &Default::default()
+        )
+        .is_err());
+    // `*/1` restricts the days of the month no more than `*`, so only Mondays match
+    db.run_script(
+        "::job create weekly '0 0 */1 * 1' { ?[] <- [[]] }",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db.run_script("::job list", &Default::default()).unwrap();
+    let weekly = res["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|row| row[0] == json!("weekly"))
+        .unwrap();
+    let days = (weekly[2].as_f64().unwrap() / 86400.) as i64;
+    // the epoch is a Thursday
+    assert_eq!((days + 4) % 7, 1);
+    db.run_script("::job remove tick", &Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("::job history tick", &Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::job run tick", &Default::default())
+        .is_err());
+}
+
+#[test]
+fn timestamps() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[at, what] <- [['2024-03-01T12:00:00Z', 'a'],
+                        ['2024-02-29T23:59:59.25+00:00', 'b'],
+                        [1709337600, 'c']]
+        :create events {at: Timestamp => what: String}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[at, what] := *events{at, what},
+                           at >= parse_timestamp('2024-03-01', '%Y-%m-%d'),
+                           at < parse_timestamp('2024-03-01', '%Y-%m-%d') + 86400
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["2024-03-01T12:00:00Z", "a"]])
+    );
+    let res = db
+        .run_script("?[at, what] := *events{at, what}", &Default::default())
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            ["2024-02-29T23:59:59.250Z", "b"],
+            ["2024-03-01T12:00:00Z", "a"],
+            ["2024-03-02T00:00:00Z", "c"]
+        ])
+    );
+    let res = db
+        .run_script(
+            r#"
+            ?[gap, recent] := *events{at: a, what: 'a'}, *events{at: b, what: 'b'},
+                              gap = a - b, recent = to_timestamp(now()) - 86400 < a
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[43200.75, false]]));
+    assert!(db
+        .run_script(
+            "?[at, what] <- [['yesterday', 'd']] :put events {at => what}",
+            &Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn maintained_relations() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[id, dept, salary] <- [[1, 'a', 10], [2, 'a', 20], [3, 'b', 5]]
+        :create staff {id: Int => dept: String, salary: Int}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::maintain create dept_pay from staff by dept { n: count(id), total: sum(salary) }",
+        &Default::default(),
+    )
+    .unwrap();
+    let query = "?[dept, n, total] := *dept_pay{dept, n, total}";
+    let res = db.run_script(query, &Default::default()).unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 2, 30.0], ["b", 1, 5.0]])
+    );
+
+    // moving a row between groups updates both of them
+    db.run_script(
+        "?[id, dept, salary] <- [[2, 'b', 20], [4, 'c', 1]] :put staff {id => dept, salary}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db.run_script(query, &Default::default()).unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([["a", 1, 10.0], ["b", 2, 25.0], ["c", 1, 1.0]])
+    );
+
+    // groups left empty are removed
+    db.run_script("?[id] <- [[1], [4]] :rm staff {id}", &Default::default())
+        .unwrap();
+    let res = db.run_script(query, &Default::default()).unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["b", 2, 25.0]]));
+
+    // replacing the base relation recomputes everything
+    db.run_script(
+        "?[id, dept, salary] <- [[7, 'd', 3]] :replace staff {id => dept, salary}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db.run_script(query, &Default::default()).unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["d", 1, 3.0]]));
+
+    let res = db
+        .run_script("::maintain list", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"][0][0], json!("dept_pay"));
+    assert_eq!(res["rows"][0][1], json!("staff"));
+
+    db.run_script("::maintain remove dept_pay", &Default::default())
+        .unwrap();
+    db.run_script(
+        "?[id, dept, salary] <- [[8, 'e', 1]] :put staff {id => dept, salary}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db.run_script(query, &Default::default()).unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["d", 1, 3.0]]));
+
+    assert!(db
+        .run_script(
+            "::maintain create bad from staff by nothing",
+            &Default::default()
+        )
+        .is_err());
+    assert!(db
+        .run_script("::maintain remove dept_pay", &Default::default())
+        .is_err());
+}
+
+#[test]
+fn import_with_inferred_schema() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let dir = std::env::temp_dir();
+    let csv_path = dir.join(format!("cozo-infer-{}.csv", std::process::id()));
+    std::fs::write(
+        &csv_path,
+        "name,id,score,active,joined\n\
+         alice,1,2.5,true,2022-01-01T00:00:00Z\n\
+         bob,2,3,false,\n\
+         carol,3,n/a,TRUE,2022-03-01T12:00:00Z\n",
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            &format!(
+                "::import people from 'file://{}' sample 2",
+                csv_path.display()
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([
+            ["id", "Int", true, 0, null],
+            ["name", "String?", false, 0, null],
+            ["score", "Float?", false, 1, "n/a"],
+            ["active", "Bool?", false, 0, null],
+            ["joined", "Timestamp?", false, 0, null]
+        ])
+    );
+    let res = db
+        .run_script(
+            "?[id, name, score] := *people{id, name, score}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[1, "alice", 2.5], [2, "bob", 3.0], [3, "carol", null]])
+    );
+
+    // without a column fit for the key, rows are numbered
+    let jsonl_path = dir.join(format!("cozo-infer-{}.jsonl", std::process::id()));
+    std::fs::write(
+        &jsonl_path,
+        "{\"tag\": \"x\", \"n\": 1}\n{\"tag\": \"x\", \"n\": 1, \"extra\": [1, 2]}\n",
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            &format!("::import tags from 'file://{}'", jsonl_path.display()),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"][0], json!(["_row", "Int", true, 0, null]));
+    assert_eq!(res["rows"][3], json!(["extra", "Any?", false, 0, null]));
+    let res = db
+        .run_script(
+            "?[_row, tag, extra] := *tags{_row, tag, extra}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[0, "x", null], [1, "x", [1, 2]]])
+    );
+
+    // the relation must not exist yet
+    assert!(db
+        .run_script(
+            &format!("::import tags from 'file://{}'", jsonl_path.display()),
+            &Default::default()
+        )
+        .is_err());
+    std::fs::remove_file(csv_path).unwrap();
+    std::fs::remove_file(jsonl_path).unwrap();
+}
+
+#[test]
+fn json_values() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[id, payload] <- [[1, '{"user": {"name": "ann", "tags": ["a", "b"]}, "n": 3}'],
+                           [2, '{"user": {"name": "bo"}, "n": 10}']]
+        :create events {id: Int => payload: Json}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            "?[id, name, tag] := *events{id, payload}, name = json_get(payload, 'user.name'), \
+             tag = json_get(payload, ['user', 'tags', 0]), json_get(payload, 'n') < 5",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([[1, "ann", "a"]]));
+
+    db.run_script(
+        r#"
+        ?[id, payload] := *events{id, payload: old},
+                          payload = json_merge(json_set(old, 'user.seen', true),
+                                               parse_json('{"n": null}'))
+        :put events {id => payload}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[payload] := *events{id: 2, payload}", &Default::default())
+        .unwrap();
+    assert_eq!(
+        *res.get("rows").unwrap(),
+        json!([[{"user": {"name": "bo", "seen": true}}]])
+    );
+
+    assert!(db
+        .run_script(
+            "?[id, payload] <- [[3, '{oops']] :put events {id => payload}",
+            &Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn full_scan_warnings() {
+    let options = DbOptions {
+        full_scan_lint_rows: Some(2),
+        ..Default::default()
+    };
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), options).unwrap();
+    db.run_script(
+        r#"
+        ?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']]
+        :create big {k => v}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ?[k] <- [[1]]
+        :create small {k}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+
+    // without collected statistics nothing is known of the size of the relation
+    let res = db
+        .run_script("?[v] := *big{v}", &Default::default())
+        .unwrap();
+    assert!(res.get("warnings").is_none());
+
+    db.run_script("::relation stats big", &Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[v] := *big{v}", &Default::default())
+        .unwrap();
+    assert_eq!(res["warnings"].as_array().unwrap().len(), 1);
+    assert!(db
+        .run_script("?[v] := *big{v} :strict", &Default::default())
+        .is_err());
+
+    let res = db
+        .run_script("?[v] := *big{k: 2, v}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["b"]]));
+    assert!(res.get("warnings").is_none());
+    let res = db
+        .run_script("?[v] := *small{k}, *big{k, v}", &Default::default())
+        .unwrap();
+    assert_eq!(*res.get("rows").unwrap(), json!([["a"]]));
+    assert!(res.get("warnings").is_none());
+
+    db.run_script("::rename big -> large", &Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[v] := *large{v}", &Default::default())
+        .unwrap();
+    assert_eq!(res["warnings"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn algo_memory_budget() {
+    let options = DbOptions {
+        algo_memory_budget: Some(10_000),
+        ..Default::default()
+    };
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), options).unwrap();
+    let edges = (0..100)
+        .map(|i| format!("[{}, {}]", i, (i + 1) % 100))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let script = format!("edges[] <- [{}] ?[] <~ PageRank(edges[])", edges);
+    assert!(db.run_script(&script, &Default::default()).is_err());
+    let res = db
+        .run_script(
+            &format!("{} :algo_memory_budget 1000000", script),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().len(), 100);
+    // the estimate grows with the edges, not with the square of the nodes
+    let edges = (0..2000)
+        .map(|i| format!("[{}, {}]", i, (i + 1) % 2000))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let res = db
+        .run_script(
+            &format!(
+                "edges[] <- [{}] ?[] <~ PageRank(edges[]) :algo_memory_budget 1000000",
+                edges
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().len(), 2000);
+
+    // a small input fits
+    let res = db
+        .run_script(
+            r#"
+        edges[] <- [['a', 'b'], ['b', 'c']]
+        ?[] <~ PageRank(edges[])
+        "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().len(), 3);
+}
+
+#[test]
+fn algo_checkpoints() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let edges = "edges[] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['c', 'd'], ['d', 'a']]";
+    let plain = db
+        .run_script(
+            &format!("{} ?[] <~ PageRank(edges[], iterations: 10)", edges),
+            &Default::default(),
+        )
+        .unwrap();
+    for _ in 0..2 {
+        let res = db
+            .run_script(
+                &format!(
+                    "{} ?[] <~ PageRank(edges[], iterations: 10, {})",
+                    edges, "checkpoint_every: 2, resume: true"
+                ),
+                &Default::default(),
+            )
+            .unwrap();
+        assert_eq!(res["rows"], plain["rows"]);
+    }
+    let res = db
+        .run_script(
+            &format!(
+                "{} ?[] <~ CommunityDetectionLouvain(edges[], checkpoint_every: 1)",
+                edges
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().len(), 4);
+}
+
+#[test]
+fn import_csv_into_relation() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        ":create people {id: Int => name: String, score: Float?, active: Bool default false}",
+        &Default::default(),
+    )
+    .unwrap();
+    let dir = std::env::temp_dir();
+    let csv_path = dir.join(format!("cozo-import-{}.csv", std::process::id()));
+    std::fs::write(
+        &csv_path,
+        "name,id,score\n\
+         alice,1,2.5\n\
+         bob,2,\n\
+         carol,3,4\n",
+    )
+    .unwrap();
+    let n = db
+        .import_from_csv(
+            "people",
+            csv_path.to_str().unwrap(),
+            &CsvImportOptions {
+                batch_size: 2,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    assert_eq!(n, 3);
+    let res = db
+        .run_script(
+            "?[id, name, score, active] := *people{id, name, score, active}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res["rows"],
+        json!([
+            [1, "alice", 2.5, false],
+            [2, "bob", null, false],
+            [3, "carol", 4.0, false]
+        ])
+    );
+
+    // without headers, fields go into the columns in order, keys first
+    let tsv_path = dir.join(format!("cozo-import-{}.tsv", std::process::id()));
+    std::fs::write(&tsv_path, "4\tdave\t1.5\ttrue\n5\teve\t\tfalse\n").unwrap();
+    let res = db
+        .run_script(
+            &format!(
+                "::import into people from 'file://{}' {{delimiter: '\t', headers: false}}",
+                tsv_path.display()
+            ),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[2]]));
+    let res = db
+        .run_script(
+            "?[name, active] := *people{id, name, active}, id > 3",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([["dave", true], ["eve", false]]));
+
+    // a bad value aborts the whole import
+    std::fs::write(&csv_path, "id,name\n6,frank\nseven,grace\n").unwrap();
+    let err = db
+        .import_from_csv("people", csv_path.to_str().unwrap(), &Default::default())
+        .unwrap_err();
+    assert!(err.to_string().contains("line 3"));
+    let res = db
+        .run_script("?[count(id)] := *people{id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[5]]));
+
+    // headers must name columns of the relation
+    std::fs::write(&csv_path, "id,nickname\n6,frank\n").unwrap();
+    assert!(db
+        .import_from_csv("people", csv_path.to_str().unwrap(), &Default::default())
+        .is_err());
+    std::fs::remove_file(csv_path).unwrap();
+    std::fs::remove_file(tsv_path).unwrap();
+}
+
+#[test]
+fn export_query_rows() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[id, name, tags] <- [[1, 'alice, "al"', ['a', 'b']], [2, 'bob', null]]
+        :create people {id => name, tags}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let query = "?[id, name, tags] := *people{id, name, tags}";
+
+    let mut out = vec![];
+    let n = db
+        .export_query(query, &Default::default(), ExportFormat::Csv, &mut out)
+        .unwrap();
+    assert_eq!(n, 2);
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "id,name,tags\n1,\"alice, \"\"al\"\"\",\"[\"\"a\"\",\"\"b\"\"]\"\n2,bob,\n"
+    );
+
+    let mut out = vec![];
+    let n = db
+        .export_query(
+            query,
+            &Default::default(),
+            ExportFormat::JsonLines,
+            &mut out,
+        )
+        .unwrap();
+    assert_eq!(n, 2);
+    let lines = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            json!({"id": 1, "name": "alice, \"al\"", "tags": ["a", "b"]}),
+            json!({"id": 2, "name": "bob", "tags": null})
+        ]
+    );
+
+    assert!(db
+        .export_query(
+            "?[id] <- [[3]] :put people {id}",
+            &Default::default(),
+            ExportFormat::Csv,
+            std::io::sink()
+        )
+        .is_err());
+
+    // rows are written as they are derived, up to a row that fails
+    let mut out = vec![];
+    assert!(db
+        .export_query(
+            "?[b] := a in [1, 2, 'x'], b = a + 1",
+            &Default::default(),
+            ExportFormat::Csv,
+            &mut out,
+        )
+        .is_err());
+    assert_eq!(String::from_utf8(out).unwrap(), "b\n2\n3\n");
+}
+
+#[test]
+fn incremental_pagerank() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[fr, to] <- [['a', 'b'], ['b', 'c'], ['c', 'a'], ['c', 'd'], ['d', 'a'], ['e', 'f']]
+        :create edges {fr, to}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(":create ranks {node => score: Float}", &Default::default())
+        .unwrap();
+    let update = r#"
+        added[fr, to] <- []
+        ?[node, score] <~ IncrementalPageRank(*edges[], *ranks[], added[], epsilon: 0.000001,
+                                              iterations: 100)
+        :put ranks {node => score}
+    "#;
+    let scores = |db: &Db| {
+        db.run_script("?[node, score] := *ranks{node, score}", &Default::default())
+            .unwrap()["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| (row[0].to_string(), row[1].as_f64().unwrap()))
+            .collect::<Vec<_>>()
+    };
+
+    // without previous scores, all nodes are computed
+    db.run_script(update, &Default::default()).unwrap();
+    let full = scores(&db);
+    assert_eq!(full.len(), 6);
+    // 'e' has no incoming edges, 'a' the most
+    assert!((full[4].1 - 0.2).abs() < 1e-6);
+    assert!(full.iter().all(|(_, score)| *score <= full[0].1));
+
+    // without changes, nothing is recomputed
+    db.run_script(update, &Default::default()).unwrap();
+    assert_eq!(scores(&db), full);
+
+    // after adding an edge, the scores are those of a full run on the new graph
+    db.run_script(
+        r#"
+        ?[fr, to] <- [['f', 'a']]
+        :put edges {fr, to}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        &update.replace("<- []", "<- [['f', 'a']]"),
+        &Default::default(),
+    )
+    .unwrap();
+    let incremental = scores(&db);
+    db.run_script("::relation truncate ranks", &Default::default())
+        .unwrap();
+    db.run_script(update, &Default::default()).unwrap();
+    let full = scores(&db);
+    for ((node, a), (_, b)) in incremental.iter().zip(full.iter()) {
+        assert!((a - b).abs() < 1e-4, "{}: {} vs {}", node, a, b);
+    }
+}
+
+#[test]
+fn backup_and_restore() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        {
+            ?[id, name] <- [[1, 'alice'], [2, 'bob']]
+            :create people {id => name}
+        }
+        {
+            ?[a, b] <- [[1, 2]]
+            :create pairs {a, b}
+        }
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let path = std::env::temp_dir().join(format!("cozo-backup-{}.bin", std::process::id()));
+    db.backup(&path).unwrap();
+
+    let restored = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    restored.restore(&path).unwrap();
+    // new relations do not reuse the ids of the restored ones
+    restored
+        .run_script(
+            r#"
+            ?[x] <- [[3]]
+            :create fresh {x}
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    for query in [
+        "?[id, name] := *people{id, name}",
+        "?[a, b] := *pairs{a, b}",
+    ] {
+        assert_eq!(
+            restored.run_script(query, &Default::default()).unwrap()["rows"],
+            db.run_script(query, &Default::default()).unwrap()["rows"]
+        );
+    }
+
+    // only empty databases can be restored into
+    assert!(restored.restore(&path).is_err());
+
+    // truncated archives are rejected before anything is written
+    let content = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &content[..content.len() - 1]).unwrap();
+    let empty = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    assert!(empty.restore(&path).is_err());
+    let res = empty
+        .run_script("::relations", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([]));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn run_algo_from_rust() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        {
+            ?[fr, to, cost] <- [['a', 'b', 1.], ['b', 'c', 1.], ['a', 'c', 3.], ['d', 'e', 1.]]
+            :create edges {fr, to => cost}
+        }
+        {
+            ?[node] <- [['a']]
+            :create starts {node}
+        }
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+
+    let ranks = db
+        .run_algo(AlgoCall::pagerank().edges("edges").damping(0.85))
+        .unwrap();
+    let res = db
+        .run_script(
+            "?[] <~ PageRank(*edges[], theta: 0.85)",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(ranks.len(), 5);
+    for ((node, score), row) in ranks.iter().zip(res["rows"].as_array().unwrap()) {
+        assert_eq!(*node, row[0]);
+        assert_eq!(*score, row[1].as_f64().unwrap());
+    }
+
+    let components = db
+        .run_algo(AlgoCall::connected_components().edges("edges"))
+        .unwrap();
+    assert_eq!(components.len(), 5);
+    assert_eq!(components[0].1, components[2].1);
+    assert_ne!(components[0].1, components[3].1);
+
+    let paths = db
+        .run_algo(
+            AlgoCall::shortest_path_dijkstra()
+                .edges("edges")
+                .starting("starts"),
+        )
+        .unwrap();
+    let to_c = paths.iter().find(|p| p.goal == json!("c")).unwrap();
+    assert_eq!(to_c.cost, 2.);
+    assert_eq!(to_c.path, vec![json!("a"), json!("b"), json!("c")]);
+
+    // any fixed rule can be applied, returning rows as JSON
+    let rows = db
+        .run_algo(AlgoCall::new("DegreeCentrality").input("edges"))
+        .unwrap();
+    assert_eq!(rows[0], vec![json!("a"), json!(2), json!(2), json!(0)]);
+
+    // inputs must be given in order, and options are checked
+    assert!(db
+        .run_algo(
+            AlgoCall::shortest_path_dijkstra()
+                .edges("edges")
+                .goals("starts")
+        )
+        .is_err());
+    assert!(db
+        .run_algo(AlgoCall::pagerank().edges("edges").damping(2.))
+        .is_err());
+}
+
+#[test]
+fn columnar_export_and_import() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[k, v] <- [[1, 1.0], [2, 1], [3, 'x'], [4, [1, 2.5, null]], [5, decode_base64('AQI=')]]
+        :create mixed {k: Int => v: Any}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let mut out = vec![];
+    assert_eq!(db.export_relation_columnar("mixed", &mut out).unwrap(), 5);
+
+    let other = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    assert_eq!(
+        other
+            .import_relation_columnar(out.as_slice(), None)
+            .unwrap(),
+        5
+    );
+    // values keep their exact types, floats that are whole numbers included
+    let query = "?[k, v, is_float(v), is_bytes(v)] := *mixed{k, v}";
+    assert_eq!(
+        other.run_script(query, &Default::default()).unwrap()["rows"],
+        db.run_script(query, &Default::default()).unwrap()["rows"]
+    );
+    let res = other
+        .run_script("?[k] := *mixed{k, v}, is_float(v)", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[1]]));
+
+    // into another relation, which must have the same columns if it exists
+    other
+        .import_relation_columnar(out.as_slice(), Some("copy"))
+        .unwrap();
+    let res = other
+        .run_script("?[count(k)] := *copy{k}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[5]]));
+    other
+        .run_script(":create narrow {k: Int}", &Default::default())
+        .unwrap();
+    assert!(other
+        .import_relation_columnar(out.as_slice(), Some("narrow"))
+        .is_err());
+
+    // truncated input is rejected
+    assert!(other
+        .import_relation_columnar(&out[..out.len() - 1], Some("copy2"))
+        .is_err());
+}
+
+#[test]
+fn built_query() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[name, age] <- [['alice', 30], ['bob', 25], ["o'brien\"]", 40], ['carol', 15]]
+        :create person {name: String => age: Int}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+
+    // values that would need escaping in a script are passed as they are
+    let query =
+        QueryBuilder::new().rule(QueryRule::entry().var("age").atom(QueryAtom::stored_named(
+            "person",
+            [
+                ("name", QueryExpr::val("o'brien\"]")),
+                ("age", QueryExpr::var("age")),
+            ],
+        )));
+    let rows: Vec<_> = db
+        .run_built_query(&query)
+        .unwrap()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(rows, vec![vec![json!(40)]]);
+
+    // rules, functions, negation, aggregation, sorting and limits
+    let query = QueryBuilder::new()
+        .rule(
+            QueryRule::new("adult")
+                .var("name")
+                .atom(QueryAtom::stored(
+                    "person",
+                    [QueryExpr::var("name"), QueryExpr::var("age")],
+                ))
+                .atom(QueryAtom::predicate(QueryExpr::call(
+                    "ge",
+                    [QueryExpr::var("age"), QueryExpr::val(18)],
+                ))),
+        )
+        .rule(
+            QueryRule::entry()
+                .var("name")
+                .var("upper")
+                .atom(QueryAtom::rule("adult", [QueryExpr::var("name")]))
+                .atom(QueryAtom::not(QueryAtom::stored(
+                    "person",
+                    [QueryExpr::var("name"), QueryExpr::val(30)],
+                )))
+                .atom(QueryAtom::unify(
+                    "upper",
+                    QueryExpr::call("uppercase", [QueryExpr::var("name")]),
+                )),
+        )
+        .order_by_desc("name")
+        .limit(1);
+    let cursor = db.run_built_query(&query).unwrap();
+    assert_eq!(cursor.headers(), ["name", "upper"]);
+    assert_eq!(
+        cursor.map(Result::unwrap).collect::<Vec<_>>(),
+        vec![vec![json!("o'brien\"]"), json!("O'BRIEN\"]")]]
+    );
+
+    let query = QueryBuilder::new().rule(QueryRule::entry().aggr("count", "name").atom(
+        QueryAtom::stored_named("person", [("name", QueryExpr::var("name"))]),
+    ));
+    let mut cursor = db.run_built_query(&query).unwrap();
+    assert_eq!(cursor.headers(), ["count(name)"]);
+    assert_eq!(cursor.next().unwrap().unwrap(), vec![json!(4)]);
+
+    // names and functions are checked when the query is run
+    let bad_var = QueryBuilder::new().rule(
+        QueryRule::entry()
+            .var("x] := *person[x]; ?[y")
+            .atom(QueryAtom::unify("x", QueryExpr::val(1))),
+    );
+    assert!(db.run_built_query(&bad_var).is_err());
+    let bad_func = QueryBuilder::new().rule(QueryRule::entry().var("x").atom(QueryAtom::unify(
+        "x",
+        QueryExpr::call("no_such_function", []),
+    )));
+    assert!(db.run_built_query(&bad_func).is_err());
+}
+
+#[test]
+fn quoting_and_relation_params() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let name = "tenant a.`notes`";
+    let text = "it's \"quoted\" \\ with\nnew lines\t\u{1}";
+    db.run_script(
+        &format!(
+            "?[k, v] <- [[1, {}]] :create {} {{k: Int => v: String}}",
+            quote_string(text),
+            quote_identifier(name)
+        ),
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            &format!("?[v] := *{}[_, v]", quote_identifier(name)),
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[text]]));
+
+    // relation names given as parameters
+    let params = |rel: serde_json::Value| json!({ "rel": rel }).as_object().unwrap().clone();
+    let res = db
+        .run_script("?[v] := *$rel[_, v]", &params(json!(name)))
+        .unwrap();
+    assert_eq!(res["rows"], json!([[text]]));
+    let res = db
+        .run_script("?[k] := *$rel{k}", &params(json!(name)))
+        .unwrap();
+    assert_eq!(res["rows"], json!([[1]]));
+    assert!(db
+        .run_script("?[k] := *$rel{k}", &params(json!("no_such")))
+        .is_err());
+    assert!(db
+        .run_script("?[k] := *$rel{k}", &params(json!(1)))
+        .is_err());
+    assert!(db
+        .run_script("?[k] := *$rel{k}", &params(json!("remote::rel")))
+        .is_err());
+    assert!(db
+        .run_script("?[k] := *$rel{k}", &Default::default())
+        .is_err());
+
+    // names following the Unicode identifier rules need no quoting, and listings give
+    // names as they are
+    assert_eq!(quote_identifier("größe"), "größe");
+    assert_eq!(quote_identifier("名前.列"), "名前.列");
+    assert_eq!(quote_identifier("a b"), "`a b`");
+    assert_eq!(quote_identifier("_x"), "`_x`");
+    assert_eq!(quote_identifier("a.b.c"), "`a.b.c`");
+    db.run_script(
+        "?[größe] <- [[1]] :create 名前 {größe: Int}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("::columns 名前", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"][0][0], json!("größe"));
+    let res = db.run_script("::relations", &Default::default()).unwrap();
+    let names = res["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row[0].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![json!(name), json!("名前")]);
+}
+
+#[test]
+fn namespaces() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    // without a namespace, dotted names are plain relation names
+    db.run_script("?[x] <- [[1]] :create c.plain {x}", &Default::default())
+        .unwrap();
+    db.run_script("::rename c.plain -> d.plain", &Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[x] := *d.plain{x}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[1]]));
+    db.run_script("::namespace create a", &Default::default())
+        .unwrap();
+    db.create_namespace("b").unwrap();
+    assert!(db.create_namespace("b").is_err());
+    assert!(db.create_namespace("c.d").is_err());
+    for ns in ["a", "b"] {
+        db.run_script(
+            &format!("?[id] <- [[1], [2]] :create {}.users {{id: Int}}", ns),
+            &Default::default(),
+        )
+        .unwrap();
+    }
+    db.run_script("?[id] <- [[3]] :put a.users {id}", &Default::default())
+        .unwrap();
+    db.run_script("?[x] <- [[1]] :create a.other {x}", &Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[count(id)] := *a.users{id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[3]]));
+    let res = db
+        .run_script("?[count(id)] := *b.users{id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[2]]));
+    db.run_script("::rename a.other -> c.other", &Default::default())
+        .unwrap();
+
+    let res = db
+        .run_script("::namespace list", &Default::default())
+        .unwrap();
+    assert_eq!(
+        res["rows"],
+        json!([["a", 1, null, null, null], ["b", 1, null, null, null]])
+    );
+    assert_eq!(db.list_namespaces().unwrap(), vec!["a", "b"]);
+
+    // removing a namespace removes its relations and leaves the others alone
+    let res = db
+        .run_script("::namespace remove a", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([["OK", 1]]));
+    assert!(db
+        .run_script("?[id] := *a.users{id}", &Default::default())
+        .is_err());
+    let res = db
+        .run_script("?[count(id)] := *b.users{id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[2]]));
+    let res = db
+        .run_script("?[x] := *c.other{x}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[1]]));
+    assert!(db.remove_namespace("a").is_err());
+    assert_eq!(db.list_namespaces().unwrap(), vec!["b"]);
+}
+
+#[test]
+fn namespace_quotas() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.create_namespace("a").unwrap();
+    db.create_namespace("b").unwrap();
+    assert!(db
+        .run_script("::namespace quota c {}", &Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::namespace quota a {max_rows: 1}", &Default::default())
+        .is_err());
+    db.run_script(
+        "::namespace quota a {max_storage_bytes: 2000}",
+        &Default::default(),
+    )
+    .unwrap();
+    db.set_namespace_quota(
+        "b",
+        NamespaceQuota {
+            max_query_secs: Some(0.1),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let res = db
+        .run_script("::namespace list", &Default::default())
+        .unwrap();
+    assert_eq!(
+        res["rows"],
+        json!([["a", 0, 2000, null, null], ["b", 0, null, null, 0.1]])
+    );
+
+    // relations with dotted names outside any namespace have no quota
+    db.run_script(
+        "?[id] := id in int_range(1000) :create c.users {id}",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[id] := id in int_range(1000, 2000) :put c.users {id}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[count(id)] := *c.users{id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[2000]]));
+
+    // writes taking the namespace over its storage quota are rolled back
+    db.run_script(
+        "?[id] := id in int_range(20) :create a.users {id}",
+        &Default::default(),
+    )
+    .unwrap();
+    let err = db
+        .run_script(
+            "?[id] := id in int_range(1000) :put a.users {id}",
+            &Default::default(),
+        )
+        .unwrap_err();
+    assert!(err.to_string().contains("over its quota"));
+    let res = db
+        .run_script("?[count(id)] := *a.users{id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[20]]));
+    // the storage counted follows removals and ignores the writes rolled back
+    let run = |script: &str| db.run_script(script, &Default::default());
+    run("?[id] := id in int_range(10) :rm a.users {id}").unwrap();
+    run("?[id] := id in int_range(100, 115) :put a.users {id}").unwrap();
+    assert!(run("?[id] := id in int_range(1000) :put a.users {id}").is_err());
+    run("::relation truncate a.users").unwrap();
+    run("?[id] := id in int_range(30) :put a.users {id}").unwrap();
+    run("?[id] := id in int_range(20, 30) :rm a.users {id}").unwrap();
+    let res = run("?[count(id)] := *a.users{id}").unwrap();
+    assert_eq!(res["rows"], json!([[20]]));
+
+    // queries reading the namespace may not return too many rows
+    db.run_script(
+        "::namespace quota a {max_result_rows: 10}",
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(db
+        .run_script("?[id] := *a.users{id}", &Default::default())
+        .is_err());
+    // the query fails as soon as it derives too many rows, without deriving them all
+    let started = Instant::now();
+    assert!(run("?[id, x] := *a.users{id}, x in int_range(100000000) :order x").is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+    let res = db
+        .run_script("?[id] := *a.users{id} :limit 5", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().len(), 5);
+    let res = db
+        .run_script("?[id] := id in int_range(1000)", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().len(), 1000);
+
+    // queries reading the namespace are killed when they run too long
+    db.run_script("?[x] <- [[1]] :create b.r {x}", &Default::default())
+        .unwrap();
+    let started = Instant::now();
+    assert!(db
+        .run_script(
+            r#"
+            n[x] := *b.r{x}
+            n[y] := n[x], y = x + 1, y < 100000000
+            ?[x] := n[x], x < 0
+            "#,
+            &Default::default()
+        )
+        .is_err());
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    db.set_namespace_quota("a", Default::default()).unwrap();
+    let res = db
+        .run_script("?[id] := *a.users{id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().len(), 20);
+}
+
+#[test]
+fn access_policy() {
+    struct TenantPolicy;
+
+    impl AccessPolicy for TenantPolicy {
+        fn check(&self, access: &RelationAccess) -> miette::Result<()> {
+            if access.label.as_deref() == Some("admin") {
+                return Ok(());
+            }
+            if access.read.contains("secret") || access.written.contains("secret") {
+                miette::bail!("access to 'secret' denied")
+            }
+            Ok(())
+        }
+    }
+
+    let options = DbOptions {
+        access_policy: Some(Arc::new(TenantPolicy)),
+        ..Default::default()
+    };
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), options).unwrap();
+    db.run_script_labelled(
+        "?[k, v] <- [[1, 'a']] :create secret {k => v}",
+        &Default::default(),
+        Some("admin"),
+    )
+    .unwrap();
+    db.run_script("?[k] <- [[1]] :create public {k}", &Default::default())
+        .unwrap();
+
+    assert!(db
+        .run_script("?[k, v] := *secret{k, v}", &Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::relation stats secret", &Default::default())
+        .is_err());
+    assert!(db
+        .run_script("::remove secret", &Default::default())
+        .is_err());
+    let res = db
+        .run_script_labelled(
+            "?[k, v] := *secret{k, v}",
+            &Default::default(),
+            Some("admin"),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[1, "a"]]));
+
+    // jobs run under the label of the script creating them
+    let job = "{ ?[k] := *secret{k}, k > 1 :put public {k} }";
+    db.run_script_labelled(
+        &format!("::job create admin_job '@daily' {}", job),
+        &Default::default(),
+        Some("admin"),
+    )
+    .unwrap();
+    db.run_script(
+        &format!("::job create user_job '@daily' {}", job),
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("::job run admin_job", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"][0][2], json!(true));
+    let res = db
+        .run_script("::job run user_job", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"][0][2], json!(false));
+
+    let mut tx = db.multi_transact().unwrap();
+    tx.run_script("?[k] <- [[2]] :put public {k}", &Default::default())
+        .unwrap();
+    assert!(tx
+        .run_script(
+            "?[k, v] <- [[2, 'b']] :put secret {k => v}",
+            &Default::default()
+        )
+        .is_err());
+    assert!(tx
+        .run_script("?[k] := *public{k}", &Default::default())
+        .is_err());
+    assert!(tx.commit().is_err());
+    let res = db
+        .run_script("?[k] := *public{k}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[1]]));
+}
+
+#[test]
+fn estimate_without_running() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[k, v] := k in int_range(100), v = k % 10
+        :create nums {k => v}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("::relation stats nums", &Default::default())
+        .unwrap();
+
+    let res = db
+        .run_script("::estimate { ?[k, v] := *nums{k, v} }", &Default::default())
+        .unwrap();
+    assert_eq!(res["headers"], json!(["stratum", "rule", "rows", "cost"]));
+    let rows = res["rows"].as_array().unwrap();
+    let entry = rows.last().unwrap();
+    assert_eq!(entry[1], json!("?"));
+    assert_eq!(entry[2], json!(100));
+
+    let res = db
+        .run_script("::estimate { ?[v] := *nums{k: 5, v} }", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().last().unwrap()[2], json!(1));
+
+    let res = db
+        .run_script(
+            "::estimate { ?[a, b] := *nums{k: a}, *nums{k: b} }",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res["rows"].as_array().unwrap().last().unwrap()[2],
+        json!(10000)
+    );
+
+    let res = db
+        .run_script(
+            "::estimate { ?[a, b] := *nums{k: a}, *nums{k: b} :limit 5 }",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"].as_array().unwrap().last().unwrap()[2], json!(5));
+}
+
+#[test]
+fn change_subscriptions() {
+    let options = DbOptions {
+        change_buffer_size: 2,
+        ..Default::default()
+    };
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), options).unwrap();
+    db.run_script(
+        "?[k, v] <- [[1, 'a']] :create src {k => v}",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("?[k] <- [[1]] :create other {k}", &Default::default())
+        .unwrap();
+    assert!(db.subscribe("nonexistent").is_err());
+    let live = db.subscribe("src").unwrap();
+
+    db.run_script(
+        "{?[k, v] <- [[2, 'b'], [3, 'c']] :put src {k => v}} {?[k] <- [[1]] :rm src {k}}",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("?[k] <- [[2]] :put other {k}", &Default::default())
+        .unwrap();
+    assert!(db
+        .run_script(
+            "?[k, v] <- [[2, 'x']] :insert src {k => v}",
+            &Default::default()
+        )
+        .is_err());
+
+    let changes = live.try_recv().unwrap();
+    assert_eq!(changes.relation, "src");
+    assert_eq!(
+        changes.rows,
+        vec![
+            RowChange::Put(vec![json!(2), json!("b")]),
+            RowChange::Put(vec![json!(3), json!("c")]),
+            RowChange::Removed(vec![json!(1)]),
+        ]
+    );
+    assert!(live.try_recv().is_err());
+
+    let mut tx = db.multi_transact().unwrap();
+    tx.run_script(
+        "?[k, v] <- [[5, 'e']] :put src {k => v}",
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(live.try_recv().is_err());
+    tx.commit().unwrap();
+    let last = live.try_recv().unwrap();
+    assert!(last.tx_id > changes.tx_id);
+
+    let replayed = db.subscribe_since("src", changes.tx_id).unwrap();
+    assert!(db.subscribe_since("src", 0).is_err());
+    let ids = replayed.try_iter().map(|c| c.tx_id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![last.tx_id]);
+
+    db.run_script(
+        "::relation delete_range src from [2] to [4]",
+        &Default::default(),
+    )
+    .unwrap();
+    let changes = live.try_recv().unwrap();
+    assert_eq!(
+        changes.rows,
+        vec![
+            RowChange::Removed(vec![json!(2)]),
+            RowChange::Removed(vec![json!(3)]),
+        ]
+    );
+    db.run_script("::relation truncate src", &Default::default())
+        .unwrap();
+    let changes = live.try_recv().unwrap();
+    assert_eq!(changes.rows, vec![RowChange::Removed(vec![json!(5)])]);
+    db.run_script(
+        "?[k, v] <- [[6, 'f']] :replace src {k => v}",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[k, v] <- [[7, 'g']] :replace src {k => v}",
+        &Default::default(),
+    )
+    .unwrap();
+    let changes = live.try_iter().flat_map(|c| c.rows).collect::<Vec<_>>();
+    assert_eq!(
+        changes,
+        vec![
+            RowChange::Put(vec![json!(6), json!("f")]),
+            RowChange::Removed(vec![json!(6)]),
+            RowChange::Put(vec![json!(7), json!("g")]),
+        ]
+    );
+}
+
+#[test]
+fn column_statistics() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        ?[k, v] := k in int_range(100), v = if(k < 90, 0, k)
+        :create skewed {k => v}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("::relation stats skewed", &Default::default())
+        .unwrap();
+    assert_eq!(
+        res["headers"],
+        json!([
+            "column",
+            "type",
+            "rows",
+            "nulls",
+            "distinct",
+            "most_common",
+            "histogram"
+        ])
+    );
+    let v = &res["rows"][1];
+    assert_eq!(v[4], json!(11));
+    assert_eq!(v[5], json!([[0, 0.9]]));
+    assert_eq!(v[6][0], json!(90));
+    assert_eq!(v[6].as_array().unwrap().last().unwrap(), &json!(99));
+    assert_eq!(res["rows"][0][4], json!(100));
+
+    let entry_rows = |script: &str| {
+        let res = db.run_script(script, &Default::default()).unwrap();
+        res["rows"].as_array().unwrap().last().unwrap()[2].clone()
+    };
+    assert_eq!(
+        entry_rows("::estimate { ?[k] := *skewed{k, v}, v == 0 }"),
+        json!(90)
+    );
+    assert_eq!(
+        entry_rows("::estimate { ?[k] := *skewed{k, v}, v == 95 }"),
+        json!(1)
+    );
+    assert_eq!(
+        entry_rows("::estimate { ?[k] := *skewed{k, v}, v > 95 }"),
+        json!(4)
+    );
+}
+
+#[test]
+fn adaptive_join_switch() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    db.run_script(
+        r#"
+        {
+            ?[k, v] := k in int_range(10), v = k * k
+            :create small {k => v}
+        }
+        {
+            ?[x, k] <- [[0, 0]]
+            :create lookups {x => k}
+        }
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("::relation stats small", &Default::default())
+        .unwrap();
+    db.run_script("::relation stats lookups", &Default::default())
+        .unwrap();
+    // the statistics of `lookups` now underestimate its rows by far
+    db.run_script(
+        r#"
+        ?[x, k] := x in int_range(5000), k = x % 10
+        :put lookups {x => k}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[count(x), max(v)] := *lookups{x, k}, *small{k, v}
+            :profile
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[5000, 81]]));
+    assert_eq!(
+        res["profile"]["join_switches"]["rows"],
+        json!([["small", 1, 1000, "hash"]])
+    );
+
+    let res = db
+        .run_script(
+            "?[count(x)] := *lookups{x, k}, *small{k, v}",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[5000]]));
+    assert!(res.get("profile").is_none());
+
+    // integers and floats of the same value are distinct keys, for hash joins as for scans
+    db.run_script(
+        r#"
+        {
+            ?[k, v] := k in int_range(10), v = 'int'
+            ?[k, v] := n in int_range(10), k = to_float(n), v = 'float'
+            :create mixed {k => v}
+        }
+        {
+            ?[x, k] <- [[0, 0]]
+            :create mixed_lookups {x => k}
+        }
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("::relation stats mixed", &Default::default())
+        .unwrap();
+    db.run_script("::relation stats mixed_lookups", &Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+        ?[x, k] := x in int_range(5000), n = x % 10, k = if(x < 1000, to_float(n), n)
+        :put mixed_lookups {x => k}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[v, count(x)] := *mixed_lookups{x, k}, *mixed{k, v}
+            :profile
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([["float", 1000], ["int", 4000]]));
+    assert_eq!(res["profile"]["join_switches"]["rows"][0][3], json!("hash"));
+}
+
+#[test]
+fn replication() {
+    let leader = Db::new_with_storage(
+        Arc::new(MemStorage::new()),
+        DbOptions {
+            replication_log_size: 4,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    leader
+        .run_script(
+            r#"
+            {
+                ?[k, v] <- [[1, 'a'], [2, 'b']]
+                :create kv {k => v}
+            }
+            {
+                ?[x] <- [[1]]
+                :create doomed {x}
+            }
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    let path = std::env::temp_dir().join(format!("cozo-replication-{}.bin", std::process::id()));
+    leader.backup(&path).unwrap();
+
+    let follower = Db::new_with_storage(
+        Arc::new(MemStorage::new()),
+        DbOptions {
+            follower: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    follower.restore(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let ship = |req: &[u8]| leader.handle_replication_request(req);
+    assert_eq!(follower.replicate_from(ship).unwrap(), 0);
+
+    leader
+        .run_script(
+            r#"
+            {
+                ?[k, v] <- [[2, 'c'], [3, 'd']]
+                :put kv {k => v}
+            }
+            {
+                ?[k] <- [[1]]
+                :rm kv {k}
+            }
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    leader
+        .run_script("::remove doomed", &Default::default())
+        .unwrap();
+    assert!(follower.replicate_from(ship).unwrap() > 0);
+    for query in ["?[k, v] := *kv{k, v}", "::relations"] {
+        assert_eq!(
+            follower.run_script(query, &Default::default()).unwrap()["rows"],
+            leader.run_script(query, &Default::default()).unwrap()["rows"]
+        );
+    }
+    assert_eq!(follower.replicate_from(ship).unwrap(), 0);
+
+    // followers only change by replication
+    assert!(follower
+        .run_script(
+            "?[k, v] <- [[4, 'e']] :put kv {k => v}",
+            &Default::default()
+        )
+        .is_err());
+
+    // a follower falling behind more batches than kept must start over
+    for i in 0..5 {
+        leader
+            .run_script(
+                "?[k, v] <- [[$k, 'f']] :put kv {k => v}",
+                json!({ "k": 10 + i }).as_object().unwrap(),
+            )
+            .unwrap();
+    }
+    assert!(follower.replicate_from(ship).is_err());
+    // the leader only answers followers if it keeps a log
+    assert!(follower.handle_replication_request(&[]).is_err());
+}
+
+#[test]
+fn negation_lookups() {
+    let script = r#"
+        big[a, b, c] := a in int_range(20000), b = 0, c = a % 7
+        probe[a, c] <- [[1, 1], [2, 5], [30000, 1]]
+        prefixed[a, c] := probe[a, c], not big[a, _, c]
+        unprefixed[c] := probe[_, c], not big[_, _, c]
+        ?[kind, a, c] := prefixed[a, c], kind = 'prefixed'
+        ?[kind, a, c] := unprefixed[c], kind = 'unprefixed', a = null
+        :order kind, a, c
+    "#;
+    let expected = json!([["prefixed", 2, 5], ["prefixed", 30000, 1]]);
+    for threshold in [Some(10_000), None] {
+        let db = Db::new_with_storage(
+            Arc::new(MemStorage::new()),
+            DbOptions {
+                negation_materialize_rows: threshold,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let res = db.run_script(script, &Default::default()).unwrap();
+        assert_eq!(res["rows"], expected);
+    }
+
+    // more probes than expected from stale statistics are still answered in full
+    let db = Db::new_with_storage(
+        Arc::new(MemStorage::new()),
+        DbOptions {
+            negation_materialize_rows: Some(10_000),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ?[c] <- [[1]]
+        :create probes {c}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("::relation stats probes", &Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+        ?[c] := c in int_range(100)
+        :put probes {c}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            r#"
+            big[a, c] := a in int_range(20000), c = a % 7
+            ?[count(c)] := *probes{c}, not big[_, c]
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([[93]]));
+}
+
+#[test]
+fn read_only_mode() {
+    let path = "_test_read_only_mode";
+    _ = std::fs::remove_dir_all(path);
+    let read_only = DbOptions {
+        read_only: true,
+        ..Default::default()
+    };
+    assert!(Db::new_with_options(path, read_only.clone()).is_err());
+
+    let writer = Db::new(path).unwrap();
+    writer
+        .run_script(
+            r#"
+            ?[k, v] <- [[1, 'a'], [2, 'b']]
+            :create kv {k => v}
+            "#,
+            &Default::default(),
+        )
+        .unwrap();
+    writer.run_script("::compact", &Default::default()).unwrap();
+
+    // readers share the directory with the writer and with each other
+    let readers = [
+        Db::new_with_options(path, read_only.clone()).unwrap(),
+        Db::new_with_options(path, read_only).unwrap(),
+    ];
+    for db in &readers {
+        let res = db
+            .run_script("?[k, v] := *kv{k, v}", &Default::default())
+            .unwrap();
+        assert_eq!(res["rows"], json!([[1, "a"], [2, "b"]]));
+        for script in [
+            "?[k, v] <- [[3, 'c']] :put kv {k => v}",
+            "?[k] <- [[4]] :create other {k}",
+            "::remove kv",
+            "::relation stats kv",
+            "::compact",
+        ] {
+            let err = db.run_script(script, &Default::default()).unwrap_err();
+            assert!(err.to_string().contains("read-only"), "{}: {}", script, err);
+        }
+    }
+    let res = writer
+        .run_script("?[count(k)] := *kv{k}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[2]]));
+    drop(readers);
+    drop(writer);
+    _ = std::fs::remove_dir_all(path);
+}
@@ -0,0 +1,257 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under AGPL-3 or later.
+ */
+
+use std::sync::Arc;
+
+use serde_json::json;
+
+use cozo::storage::MemStorage;
+use cozo::{quote_string, Db, DbOptions};
+
+fn mem_db() -> Db {
+    Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap()
+}
+
+fn rows(db: &Db, script: &str) -> serde_json::Value {
+    db.run_script(script, &Default::default()).unwrap()["rows"].clone()
+}
+
+#[test]
+fn named_relation_args() {
+    let db = mem_db();
+    rows(
+        &db,
+        "?[fr, to, w] <- [['a', 'b', 1], ['a', 'c', 2]] :create `my edges` {fr, to => w}",
+    );
+    let expected = json!([["a", 2, 2, 0], ["b", 1, 0, 1], ["c", 1, 0, 1]]);
+    assert_eq!(
+        rows(&db, "?[] <~ DegreeCentrality(*`my edges`{fr, to})"),
+        expected
+    );
+    assert_eq!(
+        rows(&db, "?[] <~ DegreeCentrality(*`my edges`{to: b, fr: a})"),
+        expected
+    );
+    assert_eq!(
+        rows(&db, "?[] <~ DegreeCentrality(*`my edges`[a, b, w])"),
+        expected
+    );
+}
+
+/// Rounds the floats in `v`, as sums may be taken in a different order on different threads.
+fn rounded(v: serde_json::Value) -> serde_json::Value {
+    match v {
+        serde_json::Value::Number(n) if n.is_f64() => {
+            json!((n.as_f64().unwrap() * 1e6).round() / 1e6)
+        }
+        serde_json::Value::Array(items) => items.into_iter().map(rounded).collect(),
+        v => v,
+    }
+}
+
+#[test]
+fn parallel_evaluation_matches_sequential() {
+    let sequential = mem_db();
+    let parallel = Db::new_with_storage(
+        Arc::new(MemStorage::new()),
+        DbOptions {
+            algo_threads: Some(3),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let rules = "
+        edges[a, b, w] := a in int_range(40), b = (a * 7 + 3) % 40, w = a % 5 + 1
+        edges[a, b, w] := a in int_range(40), b = (a + 1) % 40, w = 2
+        starts[s] <- [[0], [5], [17]]
+    ";
+    for applied in [
+        "ShortestPathDijkstra(edges[], starts[])",
+        "BetweennessCentrality(edges[])",
+        "ClosenessCentrality(edges[])",
+        "PageRank(edges[a, b])",
+        "KShortestPathYen(edges[], starts[], starts[], k: 3)",
+    ] {
+        let script = format!("{rules} ?[] <~ {applied}");
+        assert_eq!(
+            rounded(rows(&parallel, &script)),
+            rounded(rows(&sequential, &script)),
+            "{applied}"
+        );
+    }
+}
+
+/// Writes `content` to a file in the temporary directory and returns its `file://` URL.
+fn temp_url(name: &str, content: &str) -> String {
+    let path = std::env::temp_dir().join(format!("cozo-{}-{}", std::process::id(), name));
+    std::fs::write(&path, content).unwrap();
+    format!("file://{}", path.display())
+}
+
+fn read_graph(db: &Db, url: &str, options: &str) -> serde_json::Value {
+    rows(
+        db,
+        &format!(
+            "?[] <~ GraphReader(url: {}, {})",
+            quote_string(url),
+            options
+        ),
+    )
+}
+
+#[test]
+fn graph_reader_edge_list() {
+    let db = mem_db();
+    let url = temp_url("edges.txt", "# a comment\na b 2.5\nb c\n\nc a 1\n");
+    assert_eq!(
+        read_graph(&db, &url, "weighted: true"),
+        json!([["a", "b", 2.5], ["b", "c", 1.0], ["c", "a", 1.0]])
+    );
+    assert_eq!(
+        read_graph(&db, &url, "output: 'nodes'"),
+        json!([["a"], ["b"], ["c"]])
+    );
+    let url = temp_url("edges.csv", "1,2\n2,3\n");
+    assert_eq!(
+        read_graph(&db, &url, "delimiter: ',', id_type: 'Int'"),
+        json!([[1, 2], [2, 3]])
+    );
+}
+
+const GRAPHML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="d0" for="node" attr.name="weight" attr.type="double"/>
+  <key id="d1" for="edge" attr.name="weight" attr.type="double"/>
+  <graph id="G" edgedefault="directed">
+    <node id="a"><data key="d0">100</data></node>
+    <node id="b&amp;c"/>
+    <node id="d"/>
+    <edge source="a" target="b&amp;c"><data key="d1"> 0.5 </data></edge>
+    <edge source="b&amp;c" target="d"/>
+    <!-- <edge source="d" target="a"/> -->
+    <edge source="d" target="a"><data key="d1"><![CDATA[3]]></data></edge>
+  </graph>
+</graphml>
+"#;
+
+#[test]
+fn graph_reader_graphml() {
+    let db = mem_db();
+    let url = temp_url("graph.graphml", GRAPHML);
+    assert_eq!(
+        read_graph(&db, &url, "format: 'graphml', weighted: true"),
+        json!([["a", "b&c", 0.5], ["b&c", "d", 1.0], ["d", "a", 3.0]])
+    );
+    assert_eq!(
+        read_graph(&db, &url, "format: 'graphml', output: 'nodes'"),
+        json!([["a"], ["b&c"], ["d"]])
+    );
+    let url = temp_url(
+        "truncated.graphml",
+        "<graphml><graph><edge source='a'/></graph>",
+    );
+    assert!(db
+        .run_script(
+            &format!(
+                "?[] <~ GraphReader(url: {}, format: 'graphml')",
+                quote_string(&url)
+            ),
+            &Default::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn graph_reader_graphml_over_http() {
+    let server = rouille::Server::new("127.0.0.1:0", |_request| {
+        rouille::Response::from_data("application/xml", GRAPHML)
+    })
+    .unwrap();
+    let url = format!("http://{}/graph.graphml", server.server_addr());
+    let (handle, stop) = server.stoppable();
+    let db = mem_db();
+    assert_eq!(
+        read_graph(&db, &url, "format: 'graphml'"),
+        json!([["a", "b&c"], ["b&c", "d"], ["d", "a"]])
+    );
+    stop.send(()).unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn graph_reader_matrix_market() {
+    let db = mem_db();
+    let url = temp_url(
+        "matrix.mtx",
+        "%%MatrixMarket matrix coordinate real symmetric\n% a comment\n4 4 3\n2 1 1.5\n3 3 2\n4 2 0.5\n",
+    );
+    assert_eq!(
+        read_graph(&db, &url, "format: 'matrix_market', weighted: true"),
+        json!([
+            [1, 2, 1.5],
+            [2, 1, 1.5],
+            [2, 4, 0.5],
+            [3, 3, 2.0],
+            [4, 2, 0.5]
+        ])
+    );
+    assert_eq!(
+        read_graph(&db, &url, "format: 'matrix_market', output: 'nodes'"),
+        json!([[1], [2], [3], [4]])
+    );
+}
+
+#[test]
+fn dijkstra_keeps_ties_through_zero_weight_edges() {
+    let db = mem_db();
+    let rules = "
+        edges[] <- [['a', 'b', 1], ['a', 'x', 1], ['x', 'b', 0], ['a', 'e', 0.5], ['b', 'f', 1]]
+        starts[] <- [['a']]
+    ";
+    let run = |goals: &str| {
+        rows(
+            &db,
+            &format!(
+                "{rules} goals[] <- {goals}
+                ?[] <~ ShortestPathDijkstra(edges[], starts[], goals[], keep_ties: true)"
+            ),
+        )
+    };
+    let to_b = json!([
+        ["a", "b", 1.0, ["a", "b"]],
+        ["a", "b", 1.0, ["a", "x", "b"]]
+    ]);
+    assert_eq!(run("[['b']]"), to_b);
+    assert_eq!(
+        run("[['b'], ['e']]"),
+        json!([
+            ["a", "b", 1.0, ["a", "b"]],
+            ["a", "b", 1.0, ["a", "x", "b"]],
+            ["a", "e", 0.5, ["a", "e"]]
+        ])
+    );
+}
+
+#[test]
+fn yen_k_shortest_paths() {
+    let script = "
+        edges[] <- [['c', 'd', 3], ['c', 'e', 2], ['d', 'f', 4], ['e', 'd', 1], ['e', 'f', 2],
+                    ['e', 'g', 3], ['f', 'g', 2], ['f', 'h', 1], ['g', 'h', 2]]
+        res[] <~ KShortestPathYen(edges[], start[], goal[], k: 2)
+        start[] <- [['c']]
+        goal[] <- [['h']]
+        ?[cost, path] := res[_, _, cost, path]
+    ";
+    let expected = json!([[5.0, ["c", "e", "f", "h"]], [7.0, ["c", "e", "g", "h"]]]);
+    assert_eq!(rows(&mem_db(), script), expected);
+    let pooled = Db::new_with_storage(
+        Arc::new(MemStorage::new()),
+        DbOptions {
+            algo_threads: Some(2),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(rows(&pooled, script), expected);
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under AGPL-3 or later.
+ */
+
+use std::sync::Arc;
+
+use serde_json::json;
+
+use cozo::storage::MemStorage;
+use cozo::Db;
+
+fn mem_db() -> Db {
+    Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap()
+}
+
+#[test]
+fn bool_keys() {
+    let db = mem_db();
+    db.run_script(
+        r#"
+        ?[flag, id] <- [[true, 1], [false, 2], [true, 0]]
+        :create flags {flag: Bool, id: Int}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[flag, id] := *flags{flag, id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[false, 2], [true, 0], [true, 1]]));
+    let res = db
+        .run_script("?[id] := *flags{flag: false, id}", &Default::default())
+        .unwrap();
+    assert_eq!(res["rows"], json!([[2]]));
+}
+
+#[test]
+fn bool_coercion() {
+    let db = mem_db();
+    db.run_script(
+        ":create settings {name: String => on: Bool}",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ?[name, on] <- [['a', 1], ['b', 0], ['c', 'TRUE'], ['d', ' false '], ['e', true]]
+        :put settings {name => on}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[name, on] := *settings{name, on}", &Default::default())
+        .unwrap();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["a", true],
+            ["b", false],
+            ["c", true],
+            ["d", false],
+            ["e", true]
+        ])
+    );
+    for bad in ["2", "0.5", "'yes'", "[true]"] {
+        assert!(db
+            .run_script(
+                &format!(
+                    "?[name, on] <- [['x', {}]] :put settings {{name => on}}",
+                    bad
+                ),
+                &Default::default(),
+            )
+            .is_err());
+    }
+
+    // columns of 0 and 1 can be stored into boolean columns, but not those of other types
+    db.run_script(
+        r#"
+        ?[name, n] <- [['f', 0], ['g', 1]]
+        :create legacy {name: String => n: Int}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[name, on] := *legacy{name, n: on} :put settings {name => on}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            "?[name, on] := *settings{name, on}, name in ['f', 'g']",
+            &Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res["rows"], json!([["f", false], ["g", true]]));
+    db.run_script(
+        ":create blobs {name: String => b: Bytes}",
+        &Default::default(),
+    )
+    .unwrap();
+    assert!(db
+        .run_script(
+            "?[name, on] := *blobs{name, b: on} :put settings {name => on}",
+            &Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn bool_stats() {
+    let db = mem_db();
+    db.run_script(
+        r#"
+        ?[id, on] := id in int_range(10), on = id % 3 == 0
+        :create toggles {id: Int => on: Bool?}
+        "#,
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[id, on] <- [[10, null]] :put toggles {id => on}",
+        &Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("::relation stats toggles", &Default::default())
+        .unwrap();
+    let on = &res["rows"][1];
+    assert_eq!(on[0], json!("on"));
+    assert_eq!(on[1], json!("Bool?"));
+    assert_eq!(on[3], json!(1));
+    assert_eq!(on[6], json!({"true": 4, "false": 6}));
+}
@@ -0,0 +1,144 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under AGPL-3 or later.
+ */
+
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use cozo::storage::MemStorage;
+use cozo::{Db, DbOptions, RemoteDb};
+
+fn mem_db(options: DbOptions) -> Db {
+    Db::new_with_storage(Arc::new(MemStorage::new()), options).unwrap()
+}
+
+/// The value of the metric `name` in the exported metrics of `db`.
+fn metric(db: &Db, name: &str) -> i64 {
+    let exported = db.export_metrics();
+    let line = exported
+        .lines()
+        .find(|line| line.split(' ').next() == Some(name))
+        .unwrap_or_else(|| panic!("metric {} not exported", name));
+    line.rsplit(' ').next().unwrap().parse().unwrap()
+}
+
+#[test]
+fn metrics_are_per_db() {
+    let a = mem_db(Default::default());
+    let b = mem_db(Default::default());
+    a.run_script(
+        "?[k] := k in int_range(10) :create nums {k: Int}",
+        &Default::default(),
+    )
+    .unwrap();
+    a.run_script("?[k] := *nums{k}", &Default::default())
+        .unwrap();
+    assert!(a
+        .run_script("?[k] := *missing{k}", &Default::default())
+        .is_err());
+
+    assert_eq!(metric(&a, "cozo_queries_total"), 2);
+    assert_eq!(metric(&a, "cozo_queries_failed_total"), 1);
+    assert_eq!(metric(&a, "cozo_rows_written_total"), 10);
+    assert!(metric(&a, "cozo_rows_scanned_total") >= 10);
+    assert_eq!(metric(&a, "cozo_active_transactions"), 0);
+    assert_eq!(metric(&a, "cozo_in_mem_tuples"), 0);
+    for name in [
+        "cozo_queries_total",
+        "cozo_queries_failed_total",
+        "cozo_rows_written_total",
+        "cozo_rows_scanned_total",
+    ] {
+        assert_eq!(metric(&b, name), 0);
+    }
+}
+
+#[test]
+fn rocksdb_compactions() {
+    let path = "_test_metrics_compactions";
+    _ = std::fs::remove_dir_all(path);
+    let db = Db::new(path).unwrap();
+    db.run_script(
+        "?[k] := k in int_range(1000) :create nums {k: Int}",
+        &Default::default(),
+    )
+    .unwrap();
+    db.run_script("::compact", &Default::default()).unwrap();
+    assert_eq!(metric(&db, "cozo_manual_compactions_total"), 1);
+    assert!(metric(&db, "cozo_compaction_pending") >= 0);
+    assert!(metric(&db, "cozo_running_compactions") >= 0);
+    assert!(metric(&db, "cozo_compaction_pending_bytes") >= 0);
+    assert!(!mem_db(Default::default())
+        .export_metrics()
+        .contains("cozo_running_compactions"));
+    drop(db);
+    _ = std::fs::remove_dir_all(path);
+}
+
+#[test]
+fn in_mem_tuples_during_evaluation() {
+    let db = mem_db(Default::default());
+    let running = db.clone();
+    let handle = thread::spawn(move || {
+        running.run_script(
+            r#"
+            n[x] := x = 0
+            n[y] := n[x], y = x + 1, y < 100000000
+            ?[x] := n[x], x < 0
+            :timeout 1
+            "#,
+            &Default::default(),
+        )
+    });
+    let mut seen = 0;
+    while !handle.is_finished() {
+        seen = seen.max(metric(&db, "cozo_in_mem_tuples"));
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(handle.join().unwrap().is_err());
+    assert!(seen > 0);
+    assert_eq!(metric(&db, "cozo_in_mem_tuples"), 0);
+}
+
+#[test]
+fn remote_cache_hits() {
+    let source = mem_db(Default::default());
+    source
+        .run_script(
+            "?[k, v] := k in int_range(100), v = to_string(k) :create data {k: Int => v: String}",
+            &Default::default(),
+        )
+        .unwrap();
+    let served = source.clone();
+    let server = rouille::Server::new("127.0.0.1:0", move |request| {
+        let mut body = vec![];
+        request.data().unwrap().read_to_end(&mut body).unwrap();
+        match served.handle_sync_request(&body) {
+            Ok(response) => rouille::Response::from_data("application/octet-stream", response),
+            Err(err) => rouille::Response::text(format!("{:?}", err)).with_status_code(400),
+        }
+    })
+    .unwrap();
+    let url = format!("http://{}", server.server_addr());
+    let (handle, stop) = server.stoppable();
+
+    let local = mem_db(DbOptions {
+        remotes: BTreeMap::from([("central".to_string(), RemoteDb::new(url))]),
+        ..Default::default()
+    });
+    let lookup = "?[v] := *central::data{k: 42, v}";
+    local.run_script(lookup, &Default::default()).unwrap();
+    let misses = metric(&local, "cozo_remote_cache_misses_total");
+    assert!(misses > 0);
+    assert_eq!(metric(&local, "cozo_remote_cache_hits_total"), 0);
+    local.run_script(lookup, &Default::default()).unwrap();
+    assert_eq!(metric(&local, "cozo_remote_cache_misses_total"), misses);
+    assert!(metric(&local, "cozo_remote_cache_hits_total") > 0);
+    assert_eq!(metric(&source, "cozo_remote_cache_hits_total"), 0);
+
+    stop.send(()).unwrap();
+    handle.join().unwrap();
+}
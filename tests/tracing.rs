@@ -0,0 +1,135 @@
+/*
+ * Copyright 2022, The Cozo Project Authors. Licensed under AGPL-3 or later.
+ */
+
+#![cfg(feature = "tracing")]
+
+use std::fmt::{Debug, Write};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use cozo::storage::MemStorage;
+use cozo::Db;
+
+/// The spans opened and the events emitted, each written as its name or message followed by
+/// its fields, with the events also listing the spans entered when they were emitted.
+#[derive(Default)]
+struct Captured {
+    spans: Vec<String>,
+    events: Vec<(String, Vec<String>)>,
+}
+
+#[derive(Default)]
+struct Capture {
+    captured: Arc<Mutex<Captured>>,
+    entered: Mutex<Vec<usize>>,
+}
+
+struct Fields(String);
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        if field.name() == "message" {
+            write!(self.0, "{:?}", value).unwrap();
+        } else {
+            write!(self.0, "{}={:?}", field.name(), value).unwrap();
+        }
+    }
+}
+
+impl Subscriber for Capture {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = Fields(span.metadata().name().to_string());
+        span.record(&mut fields);
+        let mut captured = self.captured.lock().unwrap();
+        captured.spans.push(fields.0);
+        Id::from_u64(captured.spans.len() as u64)
+    }
+
+    fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields(String::new());
+        event.record(&mut fields);
+        let mut captured = self.captured.lock().unwrap();
+        let entered = self
+            .entered
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|i| captured.spans[*i].clone())
+            .collect();
+        captured.events.push((fields.0, entered));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered
+            .lock()
+            .unwrap()
+            .push(span.into_u64() as usize - 1);
+    }
+
+    fn exit(&self, _span: &Id) {
+        self.entered.lock().unwrap().pop();
+    }
+}
+
+#[test]
+fn spans_and_events() {
+    let db = Db::new_with_storage(Arc::new(MemStorage::new()), Default::default()).unwrap();
+    let capture = Capture::default();
+    let captured = capture.captured.clone();
+    tracing::subscriber::with_default(capture, || {
+        db.run_script_labelled(
+            "?[a] <- [[1], [2]] :create t {a}",
+            &Default::default(),
+            Some("importer"),
+        )
+        .unwrap();
+    });
+    let captured = captured.lock().unwrap();
+    for span in [
+        "script label=\"importer\"",
+        "parse",
+        "compile",
+        "query id=",
+        "stratum idx=0",
+        "commit",
+    ] {
+        assert!(
+            captured.spans.iter().any(|s| s.starts_with(span)),
+            "span {} not in {:?}",
+            span,
+            captured.spans
+        );
+    }
+    let in_spans = |message: &str| {
+        captured
+            .events
+            .iter()
+            .find(|(m, _)| m.starts_with(message))
+            .map(|(_, spans)| spans.clone())
+    };
+    assert_eq!(
+        in_spans("transaction committed"),
+        Some(vec![
+            "script label=\"importer\"".to_string(),
+            "commit".to_string()
+        ])
+    );
+    let stratum = in_spans("stratum evaluated").unwrap();
+    assert_eq!(stratum.last().unwrap(), "stratum idx=0");
+    assert!(stratum.iter().any(|s| s.starts_with("query id=")));
+}